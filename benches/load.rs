@@ -0,0 +1,57 @@
+#![allow(clippy::needless_return)]
+
+// Compares parsing a large machine-made grammar with loading it compiled,
+// which has to be at least ten times faster. Run with
+// `cargo bench --bench load`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use blabber::compiled::{from_bytes, to_bytes};
+use blabber::error_handling::SourceName;
+use blabber::parser::parse_str;
+
+const RULES: usize = 100_000;
+const RUNS: u32 = 5;
+
+// Rules which each refer to a few later ones, with a handful of terminals
+fn grammar_text() -> String {
+    let mut text = String::new();
+    for rule in 0..RULES {
+        let next = |offset: usize| (rule + offset) % RULES;
+        text.push_str(&format!(
+            "rule{} = \"word{} \" rule{} | rule{}:title \", \" rule{} | \"end of {}\"\n",
+            rule, rule % 1000, next(1), next(7), next(31), rule
+        ));
+    }
+    return text;
+}
+
+// The fastest of several runs. Dropping the grammar isn't counted, since
+// it's the same work either way.
+fn time<T>(mut body: impl FnMut() -> T) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let result = black_box(body());
+            let elapsed = start.elapsed();
+            drop(result);
+            elapsed
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let text = grammar_text();
+    let grammar = parse_str(&text, SourceName::Builtin).unwrap();
+    let bytes = to_bytes(&grammar);
+    assert_eq!(from_bytes(&bytes).unwrap(), grammar);
+
+    let parsing = time(|| parse_str(&text, SourceName::Builtin).unwrap());
+    let loading = time(|| from_bytes(&bytes).unwrap());
+    println!("{} rules, {} bytes of text, {} bytes compiled", RULES, text.len(), bytes.len());
+    println!("parsing  {:?}", parsing);
+    println!("loading  {:?} ({:.1}x faster)", loading, parsing.as_secs_f64() / loading.as_secs_f64());
+    assert!(loading * 10 <= parsing, "loading is less than ten times faster than parsing");
+}
@@ -0,0 +1,92 @@
+#![allow(clippy::needless_return)]
+
+// Measures how fast a very large machine-made grammar file parses, and the
+// most memory parsing it holds at once. Run with `cargo bench --bench parse`.
+// Parsing may hold at most twice the file's size at once, and the bench
+// fails if it holds more.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use blabber::parser::parse_file;
+
+const RULES: usize = 1_000_000;
+const RUNS: u32 = 3;
+const PEAK_BUDGET: u64 = 2;
+
+// The system allocator, keeping count of how many bytes are allocated and
+// the most there have been since the peak was last reset
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let allocated = ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+        return unsafe { System.alloc(layout) };
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) };
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > layout.size() {
+            let allocated = ALLOCATED.fetch_add(new_size - layout.size(), Ordering::Relaxed) + new_size - layout.size();
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        } else {
+            ALLOCATED.fetch_sub(layout.size() - new_size, Ordering::Relaxed);
+        }
+        return unsafe { System.realloc(ptr, layout, new_size) };
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+// Rules which each refer to a few others, with a handful of terminals, and
+// names repeated the way generated grammars repeat them
+fn write_grammar(file: &mut impl Write) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(file);
+    for rule in 0..RULES {
+        let next = |offset: usize| (rule + offset) % RULES;
+        writeln!(file, "rule{} = \"word{} \" rule{} | rule{}:title \", \" rule{} | \"end of {}\"",
+            rule, rule % 1000, next(1), next(7), next(31), rule)?;
+    }
+    return file.flush();
+}
+
+fn main() -> std::io::Result<()> {
+    let path = std::env::temp_dir().join(format!("blabber-parse-bench-{}.bnf", std::process::id()));
+    write_grammar(&mut std::fs::File::create(&path)?)?;
+    let size = std::fs::metadata(&path)?.len();
+
+    let mut fastest = Duration::MAX;
+    let mut peak = 0;
+    let mut kept = 0;
+    for _ in 0..RUNS {
+        let before = ALLOCATED.load(Ordering::Relaxed);
+        PEAK.store(before, Ordering::Relaxed);
+        let start = Instant::now();
+        let grammar = black_box(parse_file(&path).unwrap());
+        fastest = fastest.min(start.elapsed());
+        peak = peak.max(PEAK.load(Ordering::Relaxed) - before);
+        kept = ALLOCATED.load(Ordering::Relaxed) - before;
+        drop(grammar);
+    }
+    std::fs::remove_file(&path)?;
+
+    println!("{} rules, {} bytes", RULES, size);
+    println!("parsing      {:?}", fastest);
+    println!("peak memory  {} bytes ({:.1}x the file)", peak, peak as f64 / size as f64);
+    println!("grammar      {} bytes ({:.1}x the file)", kept, kept as f64 / size as f64);
+    assert!(peak as u64 <= PEAK_BUDGET * size,
+        "parsing peaked at {:.1}x the file, over the {}x budget", peak as f64 / size as f64, PEAK_BUDGET);
+    return Ok(());
+}
@@ -0,0 +1,79 @@
+#ifndef BLABBER_H
+#define BLABBER_H
+
+/* Generated by cbindgen from src/ffi.rs; don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ A parsed grammar. Free it with blabber_grammar_free.
+ */
+typedef struct BlabberGrammar BlabberGrammar;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Parses and verifies the grammar file at path. Returns null if it has
+ errors, which blabber_last_error describes.
+
+ # Safety
+ path must be null or a valid nul terminated string.
+ */
+struct BlabberGrammar *blabber_parse_file(const char *path);
+
+/*
+ Parses and verifies a grammar held in a string. Returns null if it has
+ errors, which blabber_last_error describes.
+
+ # Safety
+ text must be null or a valid nul terminated string.
+ */
+struct BlabberGrammar *blabber_parse_string(const char *text);
+
+/*
+ Generates a sentence from start, or from the grammar's start symbol if
+ start is null. The same seed always gives the same sentence, like `--seed`
+ on the command line. Returns null on failure, which blabber_last_error
+ describes. Free the sentence with blabber_string_free.
+
+ # Safety
+ grammar must come from blabber_parse_file or blabber_parse_string and not
+ have been freed, and start must be null or a valid nul terminated string.
+ */
+char *blabber_generate(const struct BlabberGrammar *grammar, const char *start, uint64_t seed);
+
+/*
+ Describes why the last call to a blabber function on this thread failed,
+ or returns null if it succeeded. The message belongs to blabber and lasts
+ until the next call on this thread.
+ */
+const char *blabber_last_error(void);
+
+/*
+ Frees a grammar. Does nothing if grammar is null.
+
+ # Safety
+ grammar must be null or come from blabber_parse_file or
+ blabber_parse_string, and not have been freed already.
+ */
+void blabber_grammar_free(struct BlabberGrammar *grammar);
+
+/*
+ Frees a sentence. Does nothing if sentence is null.
+
+ # Safety
+ sentence must be null or come from blabber_generate, and not have been
+ freed already.
+ */
+void blabber_string_free(char *sentence);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* BLABBER_H */
@@ -0,0 +1,55 @@
+# Exercises the Python module, after `maturin develop`, with
+# `pytest python/tests`
+
+import threading
+
+import pytest
+
+import blabber
+
+GRAMMAR = 'sentence = "the " noun\nnoun = "cat" | "dog"'
+
+
+def test_generate_from_seeds():
+    grammar = blabber.Grammar.parse_str(GRAMMAR)
+    sentence = grammar.generate(seed=7)
+    assert sentence in ("the cat", "the dog")
+    assert grammar.generate(seed=7) == sentence
+    assert grammar.generate(start="noun") in ("cat", "dog")
+    assert grammar.symbols == ["sentence", "noun"]
+
+
+def test_generate_many_like_the_command_line():
+    grammar = blabber.Grammar.parse_str(GRAMMAR)
+    sentences = grammar.generate_many(20, seed=3)
+    assert len(sentences) == 20
+    assert sentences[0] == grammar.generate(seed=3)
+    assert grammar.generate_many(20, seed=3) == sentences
+    assert set(grammar.generate_many(5, start="noun")) <= {"cat", "dog"}
+
+
+def test_generate_from_threads():
+    grammar = blabber.Grammar.parse_str(GRAMMAR)
+    results = []
+    threads = [threading.Thread(target=lambda: results.append(grammar.generate_many(1000, seed=1))) for _ in range(4)]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join()
+    assert len(results) == 4 and all(result == results[0] for result in results)
+
+
+def test_raise_located_errors(tmp_path):
+    path = tmp_path / "broken.bnf"
+    path.write_text('sentence = "the " noun\nnoun = "cat')
+    with pytest.raises(blabber.BlabberError) as raised:
+        blabber.Grammar.parse_file(str(path))
+    assert raised.value.errors == [(str(path), 2, "Unmatched quote: the quote opened at column 8 was never closed; consumed 3 characters to end of line")]
+
+    with pytest.raises(blabber.BlabberError) as raised:
+        blabber.Grammar.parse_str("; nothing here")
+    assert raised.value.errors[0][:2] == ("<builtin>", None)
+
+    with pytest.raises(blabber.BlabberError) as raised:
+        blabber.Grammar.parse_str(GRAMMAR).generate(start="verb")
+    assert raised.value.errors == []
@@ -0,0 +1,144 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Display;
+
+use crate::error_handling::ErrorType;
+use crate::grammar::*;
+use super::referenced_symbols;
+use super::scc::{is_cycle, strongly_connected_components};
+
+// A group of rules which can reach themselves through each other
+#[derive(Debug, PartialEq)]
+pub struct Cycle<'a> {
+    // A shortest path from the first rule in the group back to itself
+    pub path: Vec<&'a str>,
+    // Every rule in the group, which may be more than the path visits
+    pub members: Vec<&'a str>,
+}
+
+impl Display for Cycle<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.path.join(" -> "))?;
+        let extra: Vec<&str> = self.members.iter()
+            .filter(|member| !self.path.contains(member))
+            .copied()
+            .collect();
+        if !extra.is_empty() {
+            write!(f, " (also cycling through {})", extra.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// A cycle reported as an error, for grammars that are required to be finite
+#[derive(Debug, PartialEq)]
+pub struct RecursionError(pub String);
+
+impl ErrorType for RecursionError {}
+
+impl Display for RecursionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Recursive rules: {}", self.0)
+    }
+}
+
+// Whether a reference from rule to referenced is the rule made for braces
+// expanding itself again, which repeats it rather than recursing
+fn repeats(rule: &str, referenced: &str) -> bool {
+    return rule == referenced && matches!(Bracket::of_rule(rule), Some((_, Bracket::Repeat)));
+}
+
+// Finds every group of rules which can reach themselves, including single
+// rules referencing themselves. Braces repeating aren't cycles, though
+// what they hold can still reach the rule they're in.
+pub fn cycles(grammar: &Grammar) -> Vec<Cycle<'_>> {
+    let mut cycles: Vec<Cycle> = strongly_connected_components(grammar)
+        .into_iter()
+        .filter(|component| is_cycle(grammar, component) && !matches!(component[..], [rule] if repeats(rule, rule)))
+        .map(|component| Cycle {
+            path: shortest_cycle(grammar, &component),
+            members: component,
+        })
+        .collect();
+    cycles.sort_by(|a, b| a.members.cmp(&b.members));
+    return cycles;
+}
+
+// Breadth first search from the first member of the component back to
+// itself, staying inside the component
+fn shortest_cycle<'a>(grammar: &'a Grammar, component: &[&'a str]) -> Vec<&'a str> {
+    let start = component[0];
+    let members: HashSet<&str> = component.iter().copied().collect();
+    let mut previous: HashMap<&str, &str> = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+
+    while let Some(symbol) = queue.pop_front() {
+        for referenced in referenced_symbols(grammar.rules.rewrite(symbol)) {
+            if repeats(symbol, referenced) {
+                continue;
+            }
+            if referenced == start {
+                // Walk back to the start, then flip the path around
+                let mut path = vec![start];
+                let mut current = symbol;
+                while current != start {
+                    path.push(current);
+                    current = previous[current];
+                }
+                path.push(start);
+                path.reverse();
+                return path;
+            }
+            if members.contains(referenced) && !previous.contains_key(referenced) {
+                previous.insert(referenced, symbol);
+                queue.push_back(referenced);
+            }
+        }
+    }
+
+    unreachable!("every member of a recursive component reaches itself");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn find_cycles() {
+        let grammar = parse_str(concat!(
+            "a = b \"x\"\n",
+            "b = c | \"y\"\n",
+            "c = a | d\n",
+            "d = d \"z\" | \"\"\n",
+            "e = \"w\"\n",
+        ), SourceName::Builtin).unwrap();
+        let found = cycles(&grammar);
+
+        assert_eq!(found, vec![
+            Cycle { path: vec!["a", "b", "c", "a"], members: vec!["a", "b", "c"] },
+            Cycle { path: vec!["d", "d"], members: vec!["d"] },
+        ]);
+        assert_eq!(found[0].to_string(), "a -> b -> c -> a");
+        assert_eq!(found[1].to_string(), "d -> d");
+    }
+
+    #[test]
+    fn mention_members_off_the_path() {
+        let grammar = parse_str(concat!(
+            "a = b | c\n",
+            "b = a\n",
+            "c = b\n",
+        ), SourceName::Builtin).unwrap();
+
+        assert_eq!(cycles(&grammar)[0].to_string(), "a -> b -> a (also cycling through c)");
+    }
+
+    #[test]
+    fn leave_out_repetition() {
+        let grammar = parse_str("a = \"x\" {\"y\" [\"z\"]}\nb = \"(\" {b} \")\"", SourceName::Builtin).unwrap();
+        let found = cycles(&grammar);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].to_string(), "b -> b{1} -> b");
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use num_bigint::BigUint;
+use num_traits::{One, Zero};
+
+use crate::grammar::*;
+use super::scc::{is_cycle, strongly_connected_components};
+
+// The number of distinct derivation trees of every rule, for every depth up to
+// a bound. A derivation's depth is the number of nested rule expansions along
+// its deepest path, so a rule made of only terminals has depth 1.
+pub struct DerivationCounts<'a> {
+    indices: HashMap<&'a str, usize>,
+    // The nonterminals of each alternative of each rule, as indices, with
+    // None for undefined symbols
+    alternatives: Vec<Vec<Vec<Option<usize>>>>,
+    // counts[depth][rule] is the number of derivations no deeper than depth
+    counts: Vec<Vec<BigUint>>,
+}
+
+impl<'a> DerivationCounts<'a> {
+    pub fn new(grammar: &'a Grammar, max_depth: usize) -> Self {
+        let mut symbols: Vec<&str> = grammar.rules.keys().collect();
+        symbols.sort_unstable();
+        let indices: HashMap<&str, usize> = symbols.iter()
+            .enumerate()
+            .map(|(index, symbol)| (*symbol, index))
+            .collect();
+
+        let alternatives: Vec<Vec<Vec<Option<usize>>>> = symbols.iter()
+            .map(|symbol| grammar.rules.rewrite(symbol).iter()
+                .map(|alternative| alternative.iter()
+                    .filter_map(|symbol| match symbol {
+                        SymbolRef::Nonterminal(name, _) => Some(indices.get(name).copied()),
+                        _ => None
+                    })
+                    .collect())
+                .collect())
+            .collect();
+
+        let mut counts = vec![vec![BigUint::zero(); symbols.len()]];
+        for depth in 1..=max_depth {
+            let shallower = &counts[depth - 1];
+            let row = alternatives.iter()
+                .map(|rule| rule.iter()
+                    .map(|alternative| product(alternative, shallower))
+                    .sum())
+                .collect();
+            counts.push(row);
+        }
+
+        DerivationCounts {
+            indices,
+            alternatives,
+            counts
+        }
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.counts.len() - 1
+    }
+
+    // The number of derivations of a rule no deeper than depth, which is zero
+    // for undefined rules
+    pub fn count(&self, symbol: &str, depth: usize) -> BigUint {
+        match self.indices.get(symbol) {
+            Some(&index) => self.counts[depth][index].clone(),
+            None => BigUint::zero(),
+        }
+    }
+
+    // The number of derivations of one of a rule's alternatives, when the rule
+    // is expanded with depth left
+    pub fn alternative_count(&self, symbol: &str, alternative: usize, depth: usize) -> BigUint {
+        match (self.indices.get(symbol), depth) {
+            (Some(&index), 1..) => product(&self.alternatives[index][alternative], &self.counts[depth - 1]),
+            _ => BigUint::zero(),
+        }
+    }
+}
+
+// The number of ways to derive every nonterminal of an alternative, using
+// the counts of one depth less
+fn product(nonterminals: &[Option<usize>], shallower: &[BigUint]) -> BigUint {
+    let mut result = BigUint::one();
+    for nonterminal in nonterminals {
+        match nonterminal {
+            Some(index) => result *= &shallower[*index],
+            None => return BigUint::zero(),
+        }
+    }
+    return result;
+}
+
+// A depth which every derivation of a grammar without recursion fits in, or
+// None if the grammar is recursive and so has derivations of every depth
+pub fn finite_depth(grammar: &Grammar) -> Option<usize> {
+    let recursive = strongly_connected_components(grammar)
+        .iter()
+        .any(|component| is_cycle(grammar, component));
+    if recursive {
+        None
+    } else {
+        // Without recursion, no path can expand a rule twice
+        Some(grammar.rules.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn count_finite_derivations() {
+        let grammar = parse_str(concat!(
+            "sentence = subject \" \" verb | verb\n",
+            "subject = \"I\" | \"you\" | \"we\"\n",
+            "verb = \"run\" | \"walk\"\n",
+        ), SourceName::Builtin).unwrap();
+        let depth = finite_depth(&grammar).unwrap();
+        let counts = DerivationCounts::new(&grammar, depth);
+
+        assert_eq!(counts.count("sentence", depth), BigUint::from(8u32));
+        assert_eq!(counts.alternative_count("sentence", 0, depth), BigUint::from(6u32));
+        assert_eq!(counts.alternative_count("sentence", 1, depth), BigUint::from(2u32));
+        assert_eq!(counts.count("sentence", 1), BigUint::zero());
+        assert_eq!(counts.count("verb", 1), BigUint::from(2u32));
+    }
+
+    #[test]
+    fn count_recursive_derivations() {
+        let grammar = parse_str("list = \"x\" | \"x\" list | list list\n", SourceName::Builtin).unwrap();
+        assert_eq!(finite_depth(&grammar), None);
+
+        // c(1) = 1, c(d) = 1 + c(d - 1) + c(d - 1)^2
+        let counts = DerivationCounts::new(&grammar, 4);
+        let answers = [0u32, 1, 3, 13, 183];
+        for (depth, answer) in answers.iter().enumerate() {
+            assert_eq!(counts.count("list", depth), BigUint::from(*answer));
+        }
+    }
+
+    #[test]
+    fn count_huge_numbers() {
+        let grammar = parse_str("pair = \"a\" | \"b\" | pair pair\n", SourceName::Builtin).unwrap();
+        let counts = DerivationCounts::new(&grammar, 12);
+        assert!(counts.count("pair", 12).bits() > 1000);
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::error_handling::ErrorType;
+use crate::grammar::*;
+
+// A group of rules with the same definition, as reported by the error
+#[derive(Debug, PartialEq)]
+pub struct DuplicateError(pub String);
+
+impl ErrorType for DuplicateError {}
+
+impl Display for DuplicateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Rules with the same definition: {}", self.0)
+    }
+}
+
+// A rule's alternatives with their weights, if it has any, for comparing
+// definitions. Weights are compared bit for bit.
+type Definition<'a> = Vec<(AlternativeRef<'a>, Option<u64>)>;
+
+fn definition<'a>(grammar: &'a Grammar, rule: &str, ignore_order: bool) -> Definition<'a> {
+    let weights = grammar.weights.get(rule);
+    let mut definition: Definition = grammar.rules.rewrite(rule).iter()
+        .enumerate()
+        .map(|(index, alternative)| (alternative, weights.map(|weights| weights[index].to_bits())))
+        .collect();
+    if ignore_order {
+        definition.sort();
+    }
+    return definition;
+}
+
+// Finds every group of rules whose alternatives, and weights, are exactly
+// the same, optionally in any order. Rules are compared as they're written,
+// so rules which only differ in referencing each other aren't found. Each
+// group is in definition order, starting with the start symbol if it's in
+// one, and the groups are in the order of their first rules. Rules made for
+// brackets are left out, since they're written inside their rules and
+// can't be referenced instead of each other.
+pub fn duplicate_rules(grammar: &Grammar, ignore_order: bool) -> Vec<Vec<&str>> {
+    let order: Vec<&str> = definition_order(grammar).iter()
+        .map(|rule| grammar.rules.get_key_value(rule.as_str()).expect("the order has only defined rules").0)
+        .filter(|rule| Bracket::of_rule(rule).is_none())
+        .collect();
+    let mut groups: HashMap<Definition, Vec<&str>> = HashMap::new();
+    for rule in &order {
+        groups.entry(definition(grammar, rule, ignore_order)).or_default().push(rule);
+    }
+
+    let position: HashMap<&str, usize> = order.iter().enumerate().map(|(index, rule)| (*rule, index)).collect();
+    let mut duplicates: Vec<Vec<&str>> = groups.into_values().filter(|group| group.len() > 1).collect();
+    duplicates.sort_by_key(|group| position[group[0]]);
+    return duplicates;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn find_identical_rules() {
+        let grammar = parse_str(concat!(
+            "sentence = colour \" \" color \" \" digits \" \" digit\n",
+            "digit = \"1\" | \"2\"\n",
+            "colour = \"red\" | \"blue\"\n",
+            "digits = \"2\" | \"1\"\n",
+            "color = \"red\" | \"blue\"\n",
+            "number = \"1\" | \"2\"\n",
+        ), SourceName::Builtin).unwrap();
+        assert_eq!(duplicate_rules(&grammar, false), [vec!["digit", "number"], vec!["colour", "color"]]);
+        assert_eq!(duplicate_rules(&grammar, true), [vec!["digit", "digits", "number"], vec!["colour", "color"]]);
+    }
+
+    #[test]
+    fn compare_weights_and_cases() {
+        let mut grammar = parse_str("s = a b c d\na = \"x\" | \"y\"\nb = \"x\" | \"y\"\nc = a:upper\nd = a", SourceName::Builtin).unwrap();
+        assert_eq!(duplicate_rules(&grammar, false), [vec!["a", "b"]]);
+        grammar.weights.insert("a".into(), vec![1.0, 2.0].into());
+        assert_eq!(duplicate_rules(&grammar, false), Vec::<Vec<&str>>::new());
+        grammar.weights.insert("b".into(), vec![1.0, 2.0].into());
+        assert_eq!(duplicate_rules(&grammar, false), [vec!["a", "b"]]);
+    }
+
+    #[test]
+    fn leave_out_brackets() {
+        let grammar = parse_str("s = a b\na = \"x\" [\"y\"] {\"z\"}\nb = \"w\" [\"y\"] {\"z\"}", SourceName::Builtin).unwrap();
+        assert_eq!(duplicate_rules(&grammar, false), Vec::<Vec<&str>>::new());
+    }
+}
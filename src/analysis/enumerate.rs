@@ -0,0 +1,98 @@
+use std::collections::{BTreeSet, HashMap};
+
+use itertools::Itertools;
+
+use crate::grammar::*;
+use super::referenced_symbols;
+
+// Every string symbol can generate in derivations at most depth deep, with
+// the derivation depth counted as in derivations::DerivationCounts. Undefined
+// symbols generate nothing, and so do dynamic symbols, which have too many
+// values to list.
+pub fn enumerate(grammar: &Grammar, symbol: &str, depth: usize) -> BTreeSet<String> {
+    return enumerate_memoized(grammar, symbol, depth, &mut HashMap::new());
+}
+
+// Rules referenced in several places are only enumerated once for each depth
+fn enumerate_memoized<'a>(grammar: &'a Grammar, symbol: &'a str, depth: usize, known: &mut HashMap<(&'a str, usize), BTreeSet<String>>) -> BTreeSet<String> {
+    if let Some(strings) = known.get(&(symbol, depth)) {
+        return strings.clone();
+    }
+    let Some(rewrite) = grammar.rules.get(symbol).filter(|_| depth > 0) else {
+        return BTreeSet::new();
+    };
+
+    let mut strings = BTreeSet::new();
+    for alternative in rewrite {
+        let mut partials = BTreeSet::from([String::new()]);
+        for symbol in alternative {
+            let expansions = match symbol {
+                SymbolRef::Terminal(text) => BTreeSet::from([text.to_string()]),
+                SymbolRef::Dynamic(_) => BTreeSet::new(),
+                SymbolRef::Nonterminal(name, case) => enumerate_memoized(grammar, name, depth - 1, known)
+                    .into_iter()
+                    .map(|expansion| apply_case(case, expansion))
+                    .collect(),
+            };
+            partials = partials.iter()
+                .cartesian_product(expansions.iter())
+                .map(|(partial, expansion)| partial.clone() + expansion)
+                .collect();
+        }
+        strings.extend(partials);
+    }
+    known.insert((symbol, depth), strings.clone());
+    return strings;
+}
+
+// The depth of the deepest derivation from start, or None if a recursive
+// rule can be reached from it, which gives derivations of every depth
+pub fn depth_bound(grammar: &Grammar, start: &str) -> Option<usize> {
+    return rule_depth(grammar, start, &mut HashMap::new());
+}
+
+// None in depths marks a rule still being measured, so reaching it again
+// means it's recursive
+fn rule_depth<'a>(grammar: &'a Grammar, symbol: &'a str, depths: &mut HashMap<&'a str, Option<usize>>) -> Option<usize> {
+    match depths.get(symbol) {
+        Some(Some(depth)) => return Some(*depth),
+        Some(None) => return None,
+        None => {}
+    }
+    let Some(rewrite) = grammar.rules.get(symbol) else {
+        return Some(0);
+    };
+
+    depths.insert(symbol, None);
+    let mut deepest = 0;
+    for referenced in referenced_symbols(rewrite) {
+        deepest = deepest.max(rule_depth(grammar, referenced, depths)?);
+    }
+    depths.insert(symbol, Some(deepest + 1));
+    return Some(deepest + 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn enumerate_sentences() {
+        let grammar = parse_str(concat!(
+            "sentence = subject \" \" verb | verb:title\n",
+            "subject = \"I\" | \"you\"\n",
+            "verb = \"run\" | \"walk\"\n",
+            "list = \"x\" | \"x\" list\n",
+        ), SourceName::Builtin).unwrap();
+        assert_eq!(depth_bound(&grammar, "sentence"), Some(2));
+        assert_eq!(enumerate(&grammar, "sentence", 2), BTreeSet::from(
+            ["I run", "I walk", "Run", "Walk", "you run", "you walk"].map(String::from)
+        ));
+        assert_eq!(enumerate(&grammar, "sentence", 1), BTreeSet::new());
+
+        assert_eq!(depth_bound(&grammar, "list"), None);
+        assert_eq!(enumerate(&grammar, "list", 3), BTreeSet::from(["x", "xx", "xxx"].map(String::from)));
+    }
+}
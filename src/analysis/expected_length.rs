@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::grammar::*;
+use super::scc::strongly_connected_components;
+
+// The expected length of a symbol's expansions, in characters
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Expectation {
+    Finite(f64),
+    // The expansion is expected to never end, so its length has no mean
+    Diverges,
+}
+
+impl Display for Expectation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expectation::Finite(length) => write!(f, "{:.2}", length),
+            Expectation::Diverges => write!(f, "diverges"),
+        }
+    }
+}
+
+// Pivots smaller than this are treated as zero when solving
+const SINGULAR_EPSILON: f64 = 1e-12;
+
+// Computes the expected expansion length of every rule when alternatives are
+// chosen uniformly, or by their weights in weighted rules.
+//
+// For the rules in a recursive component, the expected lengths x satisfy
+// x = b + Mx, where M[i][j] is the expected number of times rule i's chosen
+// alternative references rule j, and b holds the expected length of
+// everything else in the alternative. The rules are solved one component at a
+// time, with everything they reference outside the component already known.
+// The expectation is finite exactly when the expected number of expansions
+// is, which is when (I - M)x = 1 has a positive solution.
+pub fn expected_lengths(grammar: &Grammar) -> HashMap<&str, Expectation> {
+    let mut expectations = HashMap::with_capacity(grammar.rules.len());
+
+    for component in strongly_connected_components(grammar) {
+        let local: HashMap<&str, usize> = component.iter()
+            .enumerate()
+            .map(|(index, symbol)| (*symbol, index))
+            .collect();
+
+        let size = component.len();
+        let mut references = vec![vec![0.0; size]; size];
+        let mut lengths = vec![0.0; size];
+        let mut reaches_divergence = false;
+
+        for (row, symbol) in component.iter().enumerate() {
+            let rewrite = grammar.rules.rewrite(symbol);
+            let weights = grammar.weights.get(*symbol);
+            let total: f64 = weights.map_or(rewrite.len() as f64, |weights| weights.iter().sum());
+            for (index, alternative) in rewrite.iter().enumerate() {
+                let probability = weights.map_or(1.0, |weights| weights[index]) / total;
+                // Alternatives which are never picked can't diverge
+                if probability == 0.0 {
+                    continue;
+                }
+                for symbol in alternative {
+                    match symbol {
+                        SymbolRef::Terminal(text) => lengths[row] += probability * text.chars().count() as f64,
+                        // Halfway between the shortest and longest value,
+                        // which is close enough for lengths of numbers and
+                        // names
+                        SymbolRef::Dynamic(dynamic) => {
+                            let (shortest, longest) = dynamic.lengths();
+                            lengths[row] += probability * (shortest + longest) as f64 / 2.0;
+                        }
+                        SymbolRef::Nonterminal(name, _) => match local.get(name) {
+                            Some(&column) => references[row][column] += probability,
+                            None => match expectations.get(name) {
+                                Some(Expectation::Finite(length)) => lengths[row] += probability * length,
+                                Some(Expectation::Diverges) => reaches_divergence = true,
+                                // Undefined symbols never produce anything
+                                None => {}
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let system: Vec<Vec<f64>> = references.iter()
+            .enumerate()
+            .map(|(row, coefficients)| coefficients.iter()
+                .enumerate()
+                .map(|(column, coefficient)| if row == column { 1.0 - coefficient } else { -coefficient })
+                .collect())
+            .collect();
+
+        let finite = !reaches_divergence && solve(system.clone(), vec![1.0; size])
+            .is_some_and(|counts| counts.iter().all(|count| count.is_finite() && *count > 0.0));
+        let solution = if finite { solve(system, lengths) } else { None };
+
+        for (row, symbol) in component.iter().enumerate() {
+            let expectation = match &solution {
+                Some(lengths) => Expectation::Finite(lengths[row].max(0.0)),
+                None => Expectation::Diverges,
+            };
+            expectations.insert(*symbol, expectation);
+        }
+    }
+
+    return expectations;
+}
+
+// Solves the linear system Ax = b with Gaussian elimination and partial
+// pivoting, returning None if A is singular
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let size = b.len();
+
+    for column in 0..size {
+        let pivot = (column..size)
+            .max_by(|&x, &y| a[x][column].abs().total_cmp(&a[y][column].abs()))?;
+        if a[pivot][column].abs() < SINGULAR_EPSILON {
+            return None;
+        }
+        a.swap(column, pivot);
+        b.swap(column, pivot);
+
+        for row in column + 1..size {
+            let factor = a[row][column] / a[column][column];
+            if factor == 0.0 {
+                continue;
+            }
+            let (pivot_rows, rest) = a.split_at_mut(row);
+            for (target, source) in rest[0][column..].iter_mut().zip(&pivot_rows[column][column..]) {
+                *target -= factor * source;
+            }
+            b[row] -= factor * b[column];
+        }
+    }
+
+    let mut x = vec![0.0; size];
+    for row in (0..size).rev() {
+        let rest: f64 = (row + 1..size).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - rest) / a[row][row];
+    }
+
+    return Some(x);
+}
+
+// Sorts the expectations from longest to shortest, with diverging rules first
+// and ties broken by name
+pub fn sorted_descending<'a>(expectations: &HashMap<&'a str, Expectation>) -> Vec<(&'a str, Expectation)> {
+    let mut sorted: Vec<(&str, Expectation)> = expectations.iter()
+        .map(|(symbol, expectation)| (*symbol, *expectation))
+        .collect();
+    sorted.sort_by(|(a_symbol, a), (b_symbol, b)| {
+        let key = |expectation: &Expectation| match expectation {
+            Expectation::Finite(length) => *length,
+            Expectation::Diverges => f64::INFINITY,
+        };
+        key(b).total_cmp(&key(a)).then_with(|| a_symbol.cmp(b_symbol))
+    });
+    return sorted;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::generator::generate_from;
+    use crate::error_handling::SourceName;
+    use crate::parser::{parse_file, parse_str};
+
+    fn assert_finite(expectation: Expectation, answer: f64) {
+        match expectation {
+            Expectation::Finite(length) => assert!((length - answer).abs() < 1e-9, "expected {}, got {}", answer, length),
+            Expectation::Diverges => panic!("expected {}, got divergence", answer),
+        }
+    }
+
+    #[test]
+    fn exact_lengths() {
+        let grammar = parse_str(concat!(
+            "a = \"xy\" | b\n",
+            "b = \"z\" | \"zzz\" b\n",
+            "c =\n",
+        ), SourceName::Builtin).unwrap();
+        let expectations = expected_lengths(&grammar);
+
+        // b = 1/2 * 1 + 1/2 * (3 + b), so b = 4
+        assert_finite(expectations["b"], 4.0);
+        assert_finite(expectations["a"], 3.0);
+        assert_finite(expectations["c"], 0.0);
+    }
+
+    #[test]
+    fn detect_divergence() {
+        let grammar = parse_str(concat!(
+            "a = a a | \"x\"\n",
+            "b = \"y\" a\n",
+            "c = c c c | \"x\" | \"\"\n",
+            "d = d d d | \"x\" | \"\" | \"\"\n",
+        ), SourceName::Builtin).unwrap();
+        let expectations = expected_lengths(&grammar);
+
+        assert_eq!(expectations["a"], Expectation::Diverges);
+        assert_eq!(expectations["b"], Expectation::Diverges);
+        assert_eq!(expectations["c"], Expectation::Diverges);
+        // d = 3/4 d + 1/4, so d = 1
+        assert_finite(expectations["d"], 1.0);
+    }
+
+    #[test]
+    fn sort_descending() {
+        let grammar = parse_str(concat!(
+            "a = \"xy\"\n",
+            "b = b b | \"x\"\n",
+            "c = \"xyz\"\n",
+            "d = \"zy\"\n",
+        ), SourceName::Builtin).unwrap();
+        let expectations = expected_lengths(&grammar);
+        let order: Vec<&str> = sorted_descending(&expectations).into_iter().map(|(symbol, _)| symbol).collect();
+
+        assert_eq!(order, vec!["b", "c", "a", "d"]);
+    }
+
+    #[test]
+    fn agree_with_generation() {
+        let grammar = parse_file(&PathBuf::from("example_data/english.bnf")).unwrap();
+        let expectations = expected_lengths(&grammar);
+        let mut rng = StdRng::seed_from_u64(405);
+
+        for symbol in ["sentence", "noun.phrase", "adjective.phrase", "verb.phrase"] {
+            let samples = 20_000;
+            let total: usize = (0..samples)
+                .map(|_| generate_from(&grammar, symbol, &mut rng).unwrap().chars().count())
+                .sum();
+            let mean = total as f64 / samples as f64;
+
+            match expectations[symbol] {
+                Expectation::Finite(length) => assert!((mean - length).abs() / length < 0.03, "{}: expected {}, sampled {}", symbol, length, mean),
+                Expectation::Diverges => panic!("{} should not diverge", symbol),
+            }
+        }
+    }
+}
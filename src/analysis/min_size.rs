@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use crate::grammar::*;
+
+// The size of the smallest derivation tree of every rule, counting each rule
+// expansion and each terminal in it, or None for rules which never finish.
+// Undefined symbols count as 1, like the placeholder generated for them.
+//
+// Sizes only ever shrink as shorter derivations are found, so this repeats
+// passes over the rules until one changes nothing.
+pub fn min_sizes(grammar: &Grammar) -> HashMap<&str, Option<u64>> {
+    return min_costs(grammar, alternative_min_size);
+}
+
+// The length of the shortest expansion of every rule in characters, or None
+// for rules which never finish. Undefined symbols count as their
+// placeholders, and case suffixes are assumed to keep the length the same.
+pub fn min_lengths(grammar: &Grammar) -> HashMap<&str, Option<u64>> {
+    return min_costs(grammar, alternative_min_length);
+}
+
+// Whether the rule can expand to nothing at all
+pub fn can_be_empty(grammar: &Grammar, rule: &str) -> bool {
+    return min_lengths(grammar).get(rule) == Some(&Some(0));
+}
+
+// The smallest cost of every rule, given how to find the cost of an
+// alternative from the costs of the rules it references
+fn min_costs(grammar: &Grammar, alternative_cost: impl Fn(AlternativeRef<'_>, &HashMap<&str, Option<u64>>) -> Option<u64>) -> HashMap<&str, Option<u64>> {
+    let mut sizes: HashMap<&str, Option<u64>> = grammar.rules.keys().map(|rule| (rule, None)).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (rule, rewrite) in &grammar.rules {
+            let smallest = rewrite.iter()
+                .filter_map(|alternative| alternative_cost(alternative, &sizes))
+                .min();
+            let smaller = match (smallest, sizes[rule]) {
+                (Some(new), Some(old)) => new < old,
+                (new, old) => new.is_some() && old.is_none(),
+            };
+            if smaller {
+                sizes.insert(rule, smallest);
+                changed = true;
+            }
+        }
+    }
+    return sizes;
+}
+
+// The size of the smallest derivation tree through one alternative, given the
+// sizes of the rules it references
+pub fn alternative_min_size(alternative: AlternativeRef<'_>, sizes: &HashMap<&str, Option<u64>>) -> Option<u64> {
+    alternative.iter().try_fold(1u64, |size, symbol| {
+        let symbol_size = match symbol {
+            SymbolRef::Terminal(_) | SymbolRef::Dynamic(_) => 1,
+            SymbolRef::Nonterminal(name, _) => sizes.get(name).copied().unwrap_or(Some(1))?,
+        };
+        Some(size.saturating_add(symbol_size))
+    })
+}
+
+// The length of the shortest expansion of one alternative, given the lengths
+// of the rules it references
+pub fn alternative_min_length(alternative: AlternativeRef<'_>, lengths: &HashMap<&str, Option<u64>>) -> Option<u64> {
+    alternative.iter().try_fold(0u64, |length, symbol| {
+        let symbol_length = match symbol {
+            SymbolRef::Terminal(text) => text.chars().count() as u64,
+            SymbolRef::Nonterminal(name, _) => match lengths.get(name) {
+                Some(length) => (*length)?,
+                None => name.chars().count() as u64 + 2,
+            },
+            SymbolRef::Dynamic(dynamic) => dynamic.lengths().0 as u64,
+        };
+        Some(length.saturating_add(symbol_length))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_str, parse_str_with_options, ParseOptions};
+    use crate::error_handling::SourceName;
+
+    #[test]
+    fn smallest_derivations() {
+        let grammar = parse_str(concat!(
+            "list = item \", \" list | item\n",
+            "item = \"a\" | \"b\" \"c\"\n",
+            "loop = loop \"x\"\n",
+            "maybe = loop | item\n",
+        ), SourceName::Builtin).unwrap();
+        let sizes = min_sizes(&grammar);
+        assert_eq!(sizes["item"], Some(2));
+        assert_eq!(sizes["list"], Some(3));
+        assert_eq!(sizes["loop"], None);
+        assert_eq!(sizes["maybe"], Some(3));
+        assert_eq!(alternative_min_size(grammar.rules.rewrite("list").get(0).unwrap(), &sizes), Some(7));
+        assert_eq!(alternative_min_size(grammar.rules.rewrite("maybe").get(0).unwrap(), &sizes), None);
+    }
+
+    #[test]
+    fn shortest_expansions() {
+        let options = ParseOptions { verify: false, ..Default::default() };
+        let (grammar, _) = parse_str_with_options(concat!(
+            "list = item \", \" list | item\n",
+            "item = \"ab\" | \"é\"\n",
+            "loop = loop \"x\"\n",
+            "maybe = loop | list | nothing\n",
+        ), SourceName::Builtin, &options).unwrap();
+        let lengths = min_lengths(&grammar);
+        assert_eq!(lengths["item"], Some(1));
+        assert_eq!(lengths["list"], Some(1));
+        assert_eq!(lengths["loop"], None);
+        assert_eq!(alternative_min_length(grammar.rules.rewrite("list").get(0).unwrap(), &lengths), Some(4));
+        // The placeholder ⟨nothing⟩ is 9 characters
+        assert_eq!(alternative_min_length(grammar.rules.rewrite("maybe").get(2).unwrap(), &lengths), Some(9));
+    }
+
+    #[test]
+    fn find_rules_that_can_be_empty() {
+        let grammar = parse_str("s = a b | \"x\"\na = \"\" | \"y\"\nb = a a:upper\nc = a \"z\"", SourceName::Builtin).unwrap();
+        assert!(can_be_empty(&grammar, "s"));
+        assert!(can_be_empty(&grammar, "b"));
+        assert!(!can_be_empty(&grammar, "c"));
+        assert!(!can_be_empty(&grammar, "missing"));
+    }
+}
@@ -0,0 +1,92 @@
+/*
+    This module computes static properties of grammars
+*/
+
+pub mod cycles;
+pub mod derivations;
+pub mod duplicates;
+pub mod enumerate;
+pub mod expected_length;
+pub mod min_size;
+pub mod recognize;
+pub mod scc;
+pub mod start;
+pub mod terminals;
+
+use std::collections::{HashMap, HashSet};
+
+use crate::grammar::*;
+
+// The nonterminals referenced anywhere in a rewrite, in order of appearance
+pub fn referenced_symbols(rewrite: RewriteRef<'_>) -> impl Iterator<Item = &str> {
+    rewrite.iter()
+        .flatten()
+        .filter_map(|symbol| match symbol {
+            SymbolRef::Nonterminal(name, _) => Some(name),
+            _ => None
+        })
+}
+
+// The defined rules that can be expanded when generating from start,
+// including start itself
+pub fn reachable_rules<'a>(grammar: &'a Grammar, start: &str) -> HashSet<&'a str> {
+    return grammar.rules.keys()
+        .zip(grammar.rules.reachable(start))
+        .filter_map(|(rule, reached)| reached.then_some(rule))
+        .collect();
+}
+
+// The rules which can expand target somewhere inside them, which only
+// includes target if it's recursive. It takes the rules alone so the
+// generator can use it.
+pub fn rules_reaching<'a>(rules: &'a Rules, target: &str) -> HashSet<&'a str> {
+    let mut referrers: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (rule, rewrite) in rules {
+        for referenced in referenced_symbols(rewrite) {
+            referrers.entry(referenced).or_default().push(rule);
+        }
+    }
+    let mut reaching = HashSet::new();
+    let mut pending = referrers.get(target).cloned().unwrap_or_default();
+    while let Some(rule) = pending.pop() {
+        if reaching.insert(rule) {
+            pending.extend(referrers.get(rule).into_iter().flatten());
+        }
+    }
+    return reaching;
+}
+
+// Sizes of the parts of a grammar
+#[derive(Debug, PartialEq)]
+pub struct Counts {
+    pub rules: usize,
+    pub alternatives: usize,
+    pub terminals: usize,
+    pub nonterminals: usize,
+}
+
+// The sizes of the grammar as it's written, so what brackets hold counts
+// towards the rule they're in, and the rules made for them, their empty
+// alternatives and references to them aren't counted
+pub fn counts(grammar: &Grammar) -> Counts {
+    let mut counts = Counts { rules: 0, alternatives: 0, terminals: 0, nonterminals: 0 };
+    for (rule, rewrite) in &grammar.rules {
+        let added = match Bracket::of_rule(rule) {
+            None => {
+                counts.rules += 1;
+                0
+            }
+            Some((_, Bracket::Group)) => 0,
+            Some(_) => 1,
+        };
+        counts.alternatives += rewrite.len().saturating_sub(added);
+        for symbol in rewrite.iter().flatten() {
+            match symbol {
+                SymbolRef::Terminal(_) => counts.terminals += 1,
+                SymbolRef::Nonterminal(name, _) if Bracket::of_rule(name).is_none() => counts.nonterminals += 1,
+                _ => {}
+            }
+        }
+    }
+    return counts;
+}
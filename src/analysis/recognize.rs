@@ -0,0 +1,262 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::grammar::*;
+
+// Where each rule's expansions can end when started at one position of the
+// text, with case suffixes matched ignoring case or not
+type Ends<'a> = HashMap<(&'a str, bool), BTreeSet<usize>>;
+
+// Whether start can generate exactly text. Nonterminals with case suffixes
+// are matched ignoring case rather than by applying the case, and undefined
+// symbols never match.
+pub fn derives(grammar: &Grammar, start: &str, text: &str) -> bool {
+    let ends = chart(grammar, text, false);
+    return ends[0].get(&(start, false)).is_some_and(|ends| ends.contains(&text.len()));
+}
+
+// The alternatives used by one way start can generate exactly text, as each
+// rule with the index of its alternative, in the order they're expanded, or
+// None if it can't. Text start can generate more than one way gets the way
+// using the earliest alternatives.
+pub fn derivation(grammar: &Grammar, start: &str, text: &str) -> Option<Vec<(String, usize)>> {
+    let ends = chart(grammar, text, false);
+    let derivable = ends[0].get(&(start, false)).is_some_and(|ends| ends.contains(&text.len()));
+    if !derivable {
+        return None;
+    }
+    let mut deriver = Deriver { grammar, text, ends: &ends, expanding: HashSet::new(), used: Vec::new() };
+    assert!(deriver.derive_rule(start, false, 0, text.len()), "a derivable text has a derivation");
+    return Some(deriver.used.into_iter().map(|(rule, index)| (rule.to_string(), index)).collect());
+}
+
+// The length in bytes of the longest start of text that some sentence start
+// can generate starts with. Dynamic symbols cut off by the end of the start
+// are matched by the characters their values can have, so this can be a
+// little longer than it should be with them.
+//
+// Every start of one that a sentence starts with is one too, so the longest
+// is found by binary search among the places characters start.
+pub fn viable_prefix(grammar: &Grammar, start: &str, text: &str) -> usize {
+    let viable = |length: usize| chart(grammar, &text[..length], true)[0].get(&(start, false)).is_some_and(|ends| ends.contains(&length));
+    let lengths: Vec<usize> = text.char_indices().map(|(offset, _)| offset).chain([text.len()]).collect();
+    if !viable(0) {
+        return 0;
+    }
+    // lengths[low] is viable, and lengths past high aren't
+    let (mut low, mut high) = (0, lengths.len() - 1);
+    while low < high {
+        let middle = (low + high).div_ceil(2);
+        if viable(lengths[middle]) {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    return lengths[low];
+}
+
+// The ends of every rule are found for each position of the text, from the
+// last position back to the first, so everything after a position is known
+// before it's needed. Rules matching the empty string can make rules at the
+// same position depend on each other, so each position repeats passes until
+// one changes nothing.
+//
+// With cut_off, the text is taken to be cut off at its end, so a symbol
+// matches if it could continue past the end, and every symbol matches at
+// the end.
+fn chart<'a>(grammar: &'a Grammar, text: &str, cut_off: bool) -> Vec<Ends<'a>> {
+    let mut ends: Vec<Ends> = vec![HashMap::new(); text.len() + 1];
+    for position in (0..=text.len()).rev().filter(|&position| text.is_char_boundary(position)) {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (rule, rewrite) in &grammar.rules {
+                for ignore_case in [false, true] {
+                    let found: BTreeSet<usize> = rewrite.iter()
+                        .flat_map(|alternative| alternative_ends(alternative, position, ignore_case, text, cut_off, &ends))
+                        .collect();
+                    let known = ends[position].entry((rule, ignore_case)).or_default();
+                    if found.len() > known.len() {
+                        *known = found;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    return ends;
+}
+
+// Where an alternative can end when started at position
+fn alternative_ends(alternative: AlternativeRef<'_>, position: usize, ignore_case: bool, text: &str, cut_off: bool, ends: &[Ends]) -> BTreeSet<usize> {
+    let mut positions = BTreeSet::from([position]);
+    for symbol in alternative {
+        positions = positions.iter()
+            .flat_map(|&position| symbol_ends(symbol, position, ignore_case, text, cut_off, ends))
+            .collect();
+        if positions.is_empty() {
+            break;
+        }
+    }
+    return positions;
+}
+
+// Where a symbol can end when started at position
+fn symbol_ends(symbol: SymbolRef<'_>, position: usize, ignore_case: bool, text: &str, cut_off: bool, ends: &[Ends]) -> BTreeSet<usize> {
+    let cut = cut_off && position == text.len();
+    match symbol {
+        _ if cut => BTreeSet::from([position]),
+        SymbolRef::Terminal(terminal) => match_terminal(text, position, terminal, ignore_case)
+            .or_else(|| (cut_off && match_terminal(terminal, 0, &text[position..], ignore_case).is_some()).then_some(text.len()))
+            .into_iter()
+            .collect(),
+        SymbolRef::Dynamic(dynamic) => {
+            let mut found = match_dynamic(text, position, dynamic, ignore_case);
+            let rest = &text[position..];
+            if cut_off && rest.chars().count() < dynamic.lengths().1 && rest.chars().all(|c| dynamic.chars().iter().any(|made| same_char(*made, c, ignore_case))) {
+                found.insert(text.len());
+            }
+            found
+        }
+        SymbolRef::Nonterminal(name, case) => ends[position]
+            .get(&(name, ignore_case || case.is_some()))
+            .cloned()
+            .unwrap_or_default(),
+    }
+}
+
+fn same_char(a: char, b: char, ignore_case: bool) -> bool {
+    a == b || (ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+}
+
+// Finds a derivation from a chart of where rules end, going back down
+// through the alternatives that can end in the right places
+struct Deriver<'a> {
+    grammar: &'a Grammar,
+    text: &'a str,
+    ends: &'a [Ends<'a>],
+    // The rules being expanded, with where they start and end, so a rule
+    // matching the empty string through itself isn't followed forever
+    expanding: HashSet<(&'a str, bool, usize, usize)>,
+    used: Vec<(&'a str, usize)>,
+}
+
+impl<'a> Deriver<'a> {
+    fn derive_rule(&mut self, rule: &'a str, ignore_case: bool, start: usize, end: usize) -> bool {
+        let Some((rule, rewrite)) = self.grammar.rules.get_key_value(rule) else {
+            return false;
+        };
+        if !self.expanding.insert((rule, ignore_case, start, end)) {
+            return false;
+        }
+        let mut derived = false;
+        for (index, alternative) in rewrite.iter().enumerate() {
+            let used = self.used.len();
+            self.used.push((rule, index));
+            if self.derive_symbols(alternative, ignore_case, start, end) {
+                derived = true;
+                break;
+            }
+            self.used.truncate(used);
+        }
+        self.expanding.remove(&(rule, ignore_case, start, end));
+        return derived;
+    }
+
+    fn derive_symbols(&mut self, symbols: AlternativeRef<'a>, ignore_case: bool, start: usize, end: usize) -> bool {
+        let Some((symbol, rest)) = symbols.split_first() else {
+            return start == end;
+        };
+        for middle in symbol_ends(symbol, start, ignore_case, self.text, false, self.ends) {
+            if middle > end || !alternative_ends(rest, middle, ignore_case, self.text, false, self.ends).contains(&end) {
+                continue;
+            }
+            let used = self.used.len();
+            let derived = match symbol {
+                SymbolRef::Nonterminal(name, case) => self.derive_rule(name, ignore_case || case.is_some(), start, middle),
+                _ => true,
+            };
+            if derived && self.derive_symbols(rest, ignore_case, middle, end) {
+                return true;
+            }
+            self.used.truncate(used);
+        }
+        return false;
+    }
+}
+
+// Where terminal ends if it's at position in text
+fn match_terminal(text: &str, position: usize, terminal: &str, ignore_case: bool) -> Option<usize> {
+    if !ignore_case {
+        return text[position..].starts_with(terminal).then_some(position + terminal.len());
+    }
+
+    let mut rest = text[position..].char_indices();
+    for expected in terminal.chars() {
+        let (_, found) = rest.next()?;
+        if !found.to_lowercase().eq(expected.to_lowercase()) {
+            return None;
+        }
+    }
+    return Some(rest.next().map_or(text.len(), |(offset, _)| position + offset));
+}
+
+// Where each value of a dynamic symbol that's at position in text ends,
+// trying every length its values can have
+fn match_dynamic(text: &str, position: usize, dynamic: &Dynamic, ignore_case: bool) -> BTreeSet<usize> {
+    let (shortest, longest) = dynamic.lengths();
+    let ends = text[position..].char_indices().map(|(offset, _)| position + offset).chain([text.len()]);
+    return ends.skip(shortest)
+        .take(longest + 1 - shortest)
+        .filter(|&end| dynamic.makes(&text[position..end], ignore_case))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_str;
+    use crate::error_handling::SourceName;
+
+    #[test]
+    fn recognize_sentences() {
+        let grammar = parse_str(concat!(
+            "list = list \", \" item | item\n",
+            "item = \"a\" | \"b\" maybe | word:title\n",
+            "maybe = \"\" | \"!\"\n",
+            "word = \"élan\" | \"ab\"\n",
+        ), SourceName::Builtin).unwrap();
+        assert!(derives(&grammar, "list", "a"));
+        assert!(derives(&grammar, "list", "a, b!, b"));
+        assert!(derives(&grammar, "list", "Élan, ab"));
+        // The terminals can be split differently than they're written
+        assert!(derives(&grammar, "item", "ab"));
+        assert!(!derives(&grammar, "list", "a,b"));
+        assert!(!derives(&grammar, "list", ""));
+        // Case suffixes are matched ignoring case, not applied
+        assert!(derives(&grammar, "item", "éLAN"));
+        assert!(!derives(&grammar, "nothing", "a"));
+    }
+
+    #[test]
+    fn find_derivations() {
+        let grammar = parse_str(concat!(
+            "list = list \", \" item | item\n",
+            "item = \"a\" | \"b\" maybe | word:title\n",
+            "maybe = \"\" | \"!\" | maybe maybe\n",
+            "word = \"élan\" | \"ab\"\n",
+        ), SourceName::Builtin).unwrap();
+        let derivation = |text| derivation(&grammar, "list", text)
+            .map(|used| used.iter().map(|(rule, index)| format!("{}:{}", rule, index)).collect::<Vec<_>>());
+        assert_eq!(derivation("a"), Some(vec!["list:1".to_string(), "item:0".to_string()]));
+        assert_eq!(derivation("b!, Élan").unwrap(), ["list:0", "list:1", "item:1", "maybe:1", "item:2", "word:0"]);
+        assert_eq!(derivation("b"), Some(vec!["list:1".to_string(), "item:1".to_string(), "maybe:0".to_string()]));
+        assert_eq!(derivation("a,b"), None);
+
+        assert_eq!(viable_prefix(&grammar, "list", "a, b! and"), "a, b!".len());
+        assert_eq!(viable_prefix(&grammar, "list", "a, éL"), "a, éL".len());
+        assert_eq!(viable_prefix(&grammar, "list", "a,"), "a,".len());
+        assert_eq!(viable_prefix(&grammar, "list", "x"), 0);
+        assert_eq!(viable_prefix(&grammar, "nothing", "a"), 0);
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::grammar::*;
+use super::referenced_symbols;
+
+// Finds the strongly connected components of the graph of rules referencing
+// each other, using Tarjan's algorithm. The components come in reverse
+// topological order, so every component comes after all the components it
+// references. References to undefined symbols are ignored.
+pub fn strongly_connected_components(grammar: &Grammar) -> Vec<Vec<&str>> {
+    // Sorting the symbols keeps the output the same between runs, since
+    // hashmap iteration order is random
+    let mut symbols: Vec<&str> = grammar.rules.keys().collect();
+    symbols.sort_unstable();
+    let indices: HashMap<&str, usize> = symbols.iter()
+        .enumerate()
+        .map(|(index, symbol)| (*symbol, index))
+        .collect();
+
+    // The successors of each symbol, by index
+    let edges: Vec<Vec<usize>> = symbols.iter()
+        .map(|symbol| {
+            let mut successors: Vec<usize> = referenced_symbols(grammar.rules.rewrite(symbol))
+                .filter_map(|referenced| indices.get(referenced).copied())
+                .collect();
+            successors.sort_unstable();
+            successors.dedup();
+            successors
+        })
+        .collect();
+
+    let mut tarjan = Tarjan {
+        edges: &edges,
+        order: vec![None; symbols.len()],
+        low_link: vec![0; symbols.len()],
+        on_stack: vec![false; symbols.len()],
+        stack: Vec::new(),
+        next_order: 0,
+        components: Vec::new(),
+    };
+    for root in 0..symbols.len() {
+        if tarjan.order[root].is_none() {
+            tarjan.visit(root);
+        }
+    }
+
+    return tarjan.components
+        .into_iter()
+        .map(|component| component.into_iter().map(|index| symbols[index]).collect())
+        .collect();
+}
+
+struct Tarjan<'a> {
+    edges: &'a [Vec<usize>],
+    order: Vec<Option<usize>>,
+    low_link: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next_order: usize,
+    components: Vec<Vec<usize>>,
+}
+
+impl Tarjan<'_> {
+    // Runs the depth first search from the given node with an explicit call
+    // stack, so deeply nested grammars can't overflow the real one
+    fn visit(&mut self, root: usize) {
+        // (node, index of the next successor to look at)
+        let mut calls = vec![(root, 0)];
+        self.enter(root);
+
+        while let Some((node, next_edge)) = calls.pop() {
+            if let Some(&successor) = self.edges[node].get(next_edge) {
+                calls.push((node, next_edge + 1));
+                match self.order[successor] {
+                    None => {
+                        self.enter(successor);
+                        calls.push((successor, 0));
+                    }
+                    Some(successor_order) if self.on_stack[successor] => {
+                        self.low_link[node] = self.low_link[node].min(successor_order);
+                    }
+                    Some(_) => {}
+                }
+                continue;
+            }
+
+            // All the successors are done, so the node is finished
+            if Some(self.low_link[node]) == self.order[node] {
+                let mut component = Vec::new();
+                while let Some(member) = self.stack.pop() {
+                    self.on_stack[member] = false;
+                    component.push(member);
+                    if member == node {
+                        break;
+                    }
+                }
+                component.reverse();
+                self.components.push(component);
+            }
+            if let Some(&(caller, _)) = calls.last() {
+                self.low_link[caller] = self.low_link[caller].min(self.low_link[node]);
+            }
+        }
+    }
+
+    fn enter(&mut self, node: usize) {
+        self.order[node] = Some(self.next_order);
+        self.low_link[node] = self.next_order;
+        self.next_order += 1;
+        self.stack.push(node);
+        self.on_stack[node] = true;
+    }
+}
+
+// Whether a component is recursive, either through several rules or a rule
+// referencing itself
+pub fn is_cycle(grammar: &Grammar, component: &[&str]) -> bool {
+    match component {
+        [symbol] => referenced_symbols(grammar.rules.rewrite(symbol)).any(|referenced| referenced == *symbol),
+        _ => true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn components_in_reverse_topological_order() {
+        let grammar = parse_str(concat!(
+            "a = b \"x\" | c\n",
+            "b = c d\n",
+            "c = b | \"y\"\n",
+            "d = \"z\" | d\n",
+        ), SourceName::Builtin).unwrap();
+
+        assert_eq!(strongly_connected_components(&grammar), vec![
+            vec!["d"],
+            vec!["b", "c"],
+            vec!["a"],
+        ]);
+    }
+
+    #[test]
+    fn detect_cycles() {
+        let grammar = parse_str(concat!(
+            "a = b\n",
+            "b = \"x\" | b\n",
+            "c = \"y\"\n",
+        ), SourceName::Builtin).unwrap();
+
+        assert!(!is_cycle(&grammar, &["a"]));
+        assert!(is_cycle(&grammar, &["b"]));
+        assert!(!is_cycle(&grammar, &["c"]));
+    }
+
+    #[test]
+    fn deep_chain() {
+        let text: String = (0..100_000)
+            .map(|i| format!("s{} = s{}\n", i, i + 1))
+            .chain(std::iter::once("s100000 = \"end\"\n".to_string()))
+            .collect();
+        let grammar = parse_str(&text, SourceName::Builtin).unwrap();
+
+        assert_eq!(strongly_connected_components(&grammar).len(), 100_001);
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt::Display;
+
+use crate::error_handling::ErrorType;
+use crate::grammar::*;
+use super::referenced_symbols;
+
+// What was found while looking for the real start symbol, when the first rule
+// is referenced by other rules and so probably isn't the start
+#[derive(Debug, PartialEq)]
+pub enum StartWarningType {
+    // Exactly one rule isn't referenced by any other, so it's likely the start
+    Inferred { first: String, inferred: String },
+    // Every rule is referenced by another
+    NoCandidates { first: String },
+    // Several rules aren't referenced by any other
+    SeveralCandidates { first: String, candidates: Vec<String> },
+}
+
+impl ErrorType for StartWarningType {}
+
+impl Display for StartWarningType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartWarningType::Inferred { first, inferred } => write!(f, "First rule `{}` is referenced elsewhere; did you mean start symbol `{}`? (use `--start auto` to start there)", first, inferred),
+            StartWarningType::NoCandidates { first } => write!(f, "First rule `{}` is referenced elsewhere, but so is every other rule; starting from `{}` anyway", first, first),
+            StartWarningType::SeveralCandidates { first, candidates } => write!(f, "First rule `{}` is referenced elsewhere, but several rules aren't ({}); starting from `{}` anyway", first, candidates.join(", "), first),
+        }
+    }
+}
+
+// The rules no other rule references, sorted. References from a rule to
+// itself don't count.
+pub fn unreferenced_symbols(grammar: &Grammar) -> Vec<&str> {
+    let mut unreferenced: Vec<&str> = grammar.rules.keys()
+        .filter(|symbol| !grammar.rules.iter().any(|(other, rewrite)| {
+            *other != **symbol && referenced_symbols(rewrite).any(|referenced| referenced == *symbol)
+        }))
+        .collect();
+    unreferenced.sort_unstable();
+    return unreferenced;
+}
+
+// Checks whether the first rule looks like the start symbol, returning a
+// warning if it doesn't. The warning says which rule to use instead when
+// there's exactly one rule that nothing else references.
+pub fn check_start(grammar: &Grammar) -> Option<StartWarningType> {
+    let first = &grammar.start_symbol;
+    let unreferenced = unreferenced_symbols(grammar);
+    if unreferenced.contains(&first.as_str()) || !grammar.rules.contains_key(first.as_str()) {
+        return None;
+    }
+
+    let first = first.clone();
+    return Some(match unreferenced.as_slice() {
+        [] => StartWarningType::NoCandidates { first },
+        [inferred] => StartWarningType::Inferred { first, inferred: inferred.to_string() },
+        candidates => StartWarningType::SeveralCandidates {
+            first,
+            candidates: candidates.iter().map(|candidate| candidate.to_string()).collect()
+        },
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    fn check(text: &str) -> Option<StartWarningType> {
+        check_start(&parse_str(text, SourceName::Builtin).unwrap())
+    }
+
+    #[test]
+    fn accept_unreferenced_first_rule() {
+        assert_eq!(check("sentence = adjective \"ideas\"\nadjective = \"green\""), None);
+        // Only referencing itself still counts as unreferenced
+        assert_eq!(check("list = \"x\" | \"x\" list"), None);
+    }
+
+    #[test]
+    fn infer_start_symbol() {
+        assert_eq!(check("adjective = \"green\"\nsentence = adjective \"ideas\""), Some(StartWarningType::Inferred {
+            first: "adjective".to_string(),
+            inferred: "sentence".to_string()
+        }));
+    }
+
+    #[test]
+    fn report_ambiguous_start_symbols() {
+        assert_eq!(check("adjective = \"green\"\nsentence = adjective\nother = adjective"), Some(StartWarningType::SeveralCandidates {
+            first: "adjective".to_string(),
+            candidates: vec!["other".to_string(), "sentence".to_string()]
+        }));
+        assert_eq!(check("a = b | \"x\"\nb = a"), Some(StartWarningType::NoCandidates {
+            first: "a".to_string()
+        }));
+    }
+}
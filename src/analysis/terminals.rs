@@ -0,0 +1,168 @@
+/*
+    This module lists the text a grammar can output, for spell checking and
+    reviewing translations
+*/
+
+use std::collections::{BTreeMap, HashSet};
+
+use crate::grammar::*;
+
+// Every distinct nonempty terminal, with how many alternatives use it,
+// sorted. If only is given, just those rules are looked at.
+pub fn terminals<'a>(grammar: &'a Grammar, only: Option<&HashSet<&str>>) -> BTreeMap<&'a str, usize> {
+    let mut terminals = BTreeMap::new();
+
+    for (rule, rewrite) in &grammar.rules {
+        if only.is_some_and(|only| !only.contains(rule)) {
+            continue;
+        }
+        for alternative in rewrite {
+            let mut used: Vec<&str> = alternative.iter()
+                .filter_map(|symbol| match symbol {
+                    SymbolRef::Terminal(text) if !text.is_empty() => Some(text),
+                    _ => None
+                })
+                .collect();
+            // An alternative using a terminal twice still counts once
+            used.sort_unstable();
+            used.dedup();
+            for terminal in used {
+                *terminals.entry(terminal).or_insert(0) += 1;
+            }
+        }
+    }
+
+    return terminals;
+}
+
+// Every character a sentence generated from start could have. Characters
+// of the terminals and dynamic symbols in every case are included if a case
+// suffix is used, so a character missing from this can't be in any sentence.
+pub fn producible_chars(grammar: &Grammar, start: &str) -> HashSet<char> {
+    let reachable = super::reachable_rules(grammar, start);
+    let symbols = || reachable.iter().flat_map(|rule| grammar.rules.rewrite(rule).iter().flatten());
+    let cased = symbols().any(|symbol| matches!(symbol, SymbolRef::Nonterminal(_, Some(_))));
+
+    let mut chars = HashSet::new();
+    for symbol in symbols() {
+        let symbol_chars = match symbol {
+            SymbolRef::Terminal(text) => text.chars().collect(),
+            SymbolRef::Dynamic(dynamic) => dynamic.chars(),
+            SymbolRef::Nonterminal(..) => Vec::new(),
+        };
+        for c in symbol_chars {
+            chars.insert(c);
+            if cased {
+                chars.extend(c.to_lowercase().chain(c.to_uppercase()));
+            }
+        }
+    }
+    return chars;
+}
+
+// The distinct words in the terminals, split on whitespace, with how many
+// alternatives use each
+pub fn words<'a>(terminals: &BTreeMap<&'a str, usize>) -> BTreeMap<&'a str, usize> {
+    let mut words = BTreeMap::new();
+    for (terminal, count) in terminals {
+        let mut split: Vec<&str> = terminal.split_whitespace().collect();
+        split.sort_unstable();
+        split.dedup();
+        for word in split {
+            *words.entry(word).or_insert(0) += count;
+        }
+    }
+    return words;
+}
+
+// Escapes backslashes and control characters so every terminal fits on one
+// line
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    return escaped;
+}
+
+// Reverses escape, or returns None if text has an escape escape can't make
+pub fn unescape(text: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '\\' => unescaped.push('\\'),
+            'n' => unescaped.push('\n'),
+            'r' => unescaped.push('\r'),
+            't' => unescaped.push('\t'),
+            'u' => {
+                if chars.next()? != '{' {
+                    return None;
+                }
+                let code: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                unescaped.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+            }
+            _ => return None,
+        }
+    }
+    return Some(unescaped);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::reachable_rules;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn list_terminals_and_words() {
+        let grammar = parse_str(concat!(
+            "greeting = \"good morning\" name | \"good morning\" \"\" | \"hi\" \" \" \" \"\n",
+            "name = \"Jo\" | \"good\"\n",
+            "unused = \"spare words\"\n",
+        ), SourceName::Builtin).unwrap();
+
+        let all = terminals(&grammar, None);
+        assert_eq!(all, BTreeMap::from([
+            (" ", 1), ("Jo", 1), ("good", 1), ("good morning", 2), ("hi", 1), ("spare words", 1)
+        ]));
+        let reachable = terminals(&grammar, Some(&reachable_rules(&grammar, "greeting")));
+        assert!(!reachable.contains_key("spare words"));
+        assert_eq!(reachable.len(), all.len() - 1);
+
+        assert_eq!(words(&reachable), BTreeMap::from([
+            ("Jo", 1), ("good", 3), ("hi", 1), ("morning", 2)
+        ]));
+    }
+
+    #[test]
+    fn find_producible_chars() {
+        let grammar = parse_str("s = \"ab\" t\nt = \"c\"\nu = \"d\"\ncased = s:upper", SourceName::Builtin).unwrap();
+        assert_eq!(producible_chars(&grammar, "s"), HashSet::from(['a', 'b', 'c']));
+        assert_eq!(producible_chars(&grammar, "cased"), HashSet::from(['a', 'b', 'c', 'A', 'B', 'C']));
+        assert!(producible_chars(&grammar, "undefined").is_empty());
+    }
+
+    #[test]
+    fn escape_terminals() {
+        assert_eq!(escape("two\nlines\tand a \\"), "two\\nlines\\tand a \\\\");
+        assert_eq!(escape("bell\u{7}"), "bell\\u{7}");
+        for text in ["two\nlines\tand a \\", "bell\u{7}\r", "plain ⟨text⟩"] {
+            assert_eq!(unescape(&escape(text)).as_deref(), Some(text));
+        }
+        assert_eq!(unescape("bad \\q"), None);
+        assert_eq!(unescape("cut off \\"), None);
+    }
+}
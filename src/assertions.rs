@@ -0,0 +1,214 @@
+/*
+    This module reads and checks assertions a grammar makes about itself,
+    written in directive comments starting with `;!`:
+
+        ;! derives "colorless green ideas hug furiously"
+        ;! min-length 10
+        ;! terminates
+        ;! defines noun.phrase
+
+    Each is checked from the grammar's start symbol, so grammar changes can be
+    tested like code.
+*/
+
+use std::fmt::Display;
+
+use itertools::Itertools;
+
+use crate::analysis::min_size::{min_lengths, min_sizes};
+use crate::analysis::reachable_rules;
+use crate::analysis::recognize::derives;
+use crate::error_handling::{Error, ErrorType, Location, SourceName};
+use crate::grammar::{Grammar, Symbol};
+use crate::parser::lexer::{lex_terminal, Token};
+
+// Something a grammar asserts about itself
+#[derive(Debug, PartialEq)]
+pub enum Check {
+    // The start symbol can generate exactly this sentence
+    Derives(String),
+    // Every sentence is at least this many characters long
+    MinLength(u64),
+    // Every rule reachable from the start symbol can finish
+    Terminates,
+    // The rule is defined
+    Defines(String),
+}
+
+// Checks are shown the way they're written after `;!`
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Check::Derives(sentence) => write!(f, "derives {}", Symbol::Terminal(sentence.as_str().into())),
+            Check::MinLength(length) => write!(f, "min-length {}", length),
+            Check::Terminates => write!(f, "terminates"),
+            Check::Defines(rule) => write!(f, "defines {}", rule),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Assertion {
+    pub check: Check,
+    // The line of the grammar it's written on
+    pub location: Location,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AssertionErrorType {
+    // The word after `;!` isn't a known check
+    UnknownCheck(String),
+    // A check's argument is missing or malformed, with what was expected
+    InvalidArgument(&'static str, &'static str),
+    // The grammar doesn't do what the assertion says, and why
+    Failed(String),
+}
+
+impl ErrorType for AssertionErrorType {}
+
+impl Display for AssertionErrorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssertionErrorType::UnknownCheck(check) => write!(f, "Unknown assertion `{}` (expected `derives`, `min-length`, `terminates`, or `defines`)", check),
+            AssertionErrorType::InvalidArgument(check, expected) => write!(f, "`{}` expects {}", check, expected),
+            AssertionErrorType::Failed(reason) => write!(f, "Assertion failed: {}", reason),
+        }
+    }
+}
+
+pub type AssertionError = Error<AssertionErrorType>;
+
+fn parse_check(directive: &str) -> Result<Check, AssertionErrorType> {
+    let (name, argument) = directive.trim().split_once(char::is_whitespace).unwrap_or((directive.trim(), ""));
+    let argument = argument.trim();
+    match name {
+        "derives" => {
+            let invalid = AssertionErrorType::InvalidArgument("derives", "a quoted sentence");
+            if !argument.starts_with('"') {
+                return Err(invalid);
+            }
+            match lex_terminal(argument, 1) {
+                Ok((Token::Terminal(sentence), taken)) if argument[taken..].chars().all(char::is_whitespace) => Ok(Check::Derives(sentence.into_owned())),
+                _ => Err(invalid),
+            }
+        }
+        "min-length" => argument.parse()
+            .map(Check::MinLength)
+            .map_err(|_| AssertionErrorType::InvalidArgument("min-length", "a whole number of characters")),
+        "terminates" if argument.is_empty() => Ok(Check::Terminates),
+        "terminates" => Err(AssertionErrorType::InvalidArgument("terminates", "nothing after it")),
+        "defines" if !argument.is_empty() && !argument.contains(char::is_whitespace) => Ok(Check::Defines(argument.to_string())),
+        "defines" => Err(AssertionErrorType::InvalidArgument("defines", "one rule name")),
+        _ => Err(AssertionErrorType::UnknownCheck(name.to_string())),
+    }
+}
+
+// Reads every `;!` assertion in a grammar's text, using source for the
+// locations, along with the errors in the ones that can't be read
+pub fn parse_assertions(text: &str, source: SourceName) -> (Vec<Assertion>, Vec<AssertionError>) {
+    let mut assertions = Vec::new();
+    let mut errors = Vec::new();
+    for (num, line) in text.lines().enumerate() {
+        let Some(directive) = line.strip_prefix(";!") else {
+            continue;
+        };
+        let location = Location::line(source.clone(), num + 1);
+        match parse_check(directive) {
+            Ok(check) => assertions.push(Assertion { check, location }),
+            Err(error) => errors.push(Error { location, error }),
+        }
+    }
+    return (assertions, errors);
+}
+
+impl Assertion {
+    // Checks the assertion against the grammar from its start symbol,
+    // failing with why it doesn't hold
+    pub fn check(&self, grammar: &Grammar) -> Result<(), AssertionError> {
+        let start = grammar.start_symbol.as_str();
+        let reason = match &self.check {
+            Check::Derives(sentence) => (!derives(grammar, start, sentence))
+                .then(|| format!("`{}` can't generate {}", start, Symbol::Terminal(sentence.as_str().into()))),
+            Check::MinLength(length) => match min_lengths(grammar).get(start).copied().flatten() {
+                Some(shortest) if shortest < *length => Some(format!("the shortest sentence is {} characters", shortest)),
+                Some(_) => None,
+                None => Some(format!("`{}` never finishes", start)),
+            },
+            Check::Terminates => {
+                let sizes = min_sizes(grammar);
+                let endless = reachable_rules(grammar, start).into_iter()
+                    .filter(|rule| sizes[rule].is_none())
+                    .sorted()
+                    .map(|rule| format!("`{}`", rule))
+                    .collect_vec();
+                (!endless.is_empty()).then(|| format!("these rules can never finish: {}", endless.join(", ")))
+            }
+            Check::Defines(rule) => (!grammar.rules.contains_key(rule.as_str())).then(|| format!("`{}` isn't defined", rule)),
+        };
+        return match reason {
+            Some(reason) => Err(Error { location: self.location.clone(), error: AssertionErrorType::Failed(reason) }),
+            None => Ok(()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_str;
+
+    const GRAMMAR: &str = concat!(
+        ";! derives \"ab ab\"\n",
+        "s = word \" \" word\n",
+        ";! min-length 5\n",
+        ";!   terminates\n",
+        "word = \"ab\" | \"cde\" | loop\n",
+        ";! defines loop\n",
+        ";! defines nothing\n",
+        "loop = loop \"!\"\n",
+        "; an ordinary comment\n",
+    );
+
+    #[test]
+    fn read_assertions() {
+        let (assertions, errors) = parse_assertions(GRAMMAR, SourceName::Builtin);
+        let checks = assertions.iter().map(|assertion| (assertion.location.line.unwrap(), &assertion.check)).collect_vec();
+        assert_eq!(checks, vec![
+            (1, &Check::Derives("ab ab".to_string())),
+            (3, &Check::MinLength(5)),
+            (4, &Check::Terminates),
+            (6, &Check::Defines("loop".to_string())),
+            (7, &Check::Defines("nothing".to_string())),
+        ]);
+        assert_eq!(errors, vec![]);
+
+        let (_, errors) = parse_assertions(";! derives ab\n;! derives \"ab\" x\n;! min-length ten\n;! terminate\n;! defines\n", SourceName::Builtin);
+        let errors = errors.into_iter().map(|error| (error.location.line.unwrap(), error.error)).collect_vec();
+        assert_eq!(errors, vec![
+            (1, AssertionErrorType::InvalidArgument("derives", "a quoted sentence")),
+            (2, AssertionErrorType::InvalidArgument("derives", "a quoted sentence")),
+            (3, AssertionErrorType::InvalidArgument("min-length", "a whole number of characters")),
+            (4, AssertionErrorType::UnknownCheck("terminate".to_string())),
+            (5, AssertionErrorType::InvalidArgument("defines", "one rule name")),
+        ]);
+    }
+
+    #[test]
+    fn check_assertions() {
+        let grammar = parse_str(GRAMMAR, SourceName::Builtin).unwrap();
+        let (assertions, _) = parse_assertions(GRAMMAR, SourceName::Builtin);
+        let failures = assertions.iter()
+            .filter_map(|assertion| assertion.check(&grammar).err())
+            .map(|error| (error.location.line.unwrap(), error.error.to_string()))
+            .collect_vec();
+        assert_eq!(failures, vec![
+            (4, "Assertion failed: these rules can never finish: `loop`".to_string()),
+            (7, "Assertion failed: `nothing` isn't defined".to_string()),
+        ]);
+
+        let too_long = Assertion { check: Check::MinLength(6), location: Location::whole(SourceName::Builtin) };
+        assert_eq!(too_long.check(&grammar).unwrap_err().error, AssertionErrorType::Failed("the shortest sentence is 5 characters".to_string()));
+        let impossible = Assertion { check: Check::Derives("ab cd".to_string()), location: Location::whole(SourceName::Builtin) };
+        assert!(impossible.check(&grammar).is_err());
+    }
+}
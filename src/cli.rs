@@ -1,18 +1,881 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use blabber::filter::DEFAULT_TRIES;
+use blabber::limit::parse_size;
+use blabber::parser::{parse_exclusion, warning_name, Exclusion, Level, WarningLevels, DEFAULT_MAX_INCLUDE_DEPTH};
+use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser)]
-#[command(version, about)]
+#[command(version, about, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub generate: Option<GenerateArgs>,
+
+    /// Read default options from FILE instead of the blabber.toml next to the grammar or above it
+    #[arg(long, value_name = "FILE", global = true)]
+    pub config: Option<PathBuf>
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Generate many expansions of a symbol and print how often each occurred
+    Histogram(HistogramArgs),
+
+    /// Print statistics about a grammar
+    Stats(StatsArgs),
+
+    /// Check a grammar for errors, exiting with failure if there are any
+    Check(CheckArgs),
+
+    /// Check the `;!` assertions written in a grammar, exiting with failure if any don't hold
+    Test(TestArgs),
+
+    /// Record seeded output to a file, or check a grammar still generates what was recorded
+    Snapshot(SnapshotArgs),
+
+    /// Print the tokens each line of a grammar is split into
+    Lex(LexArgs),
+
+    /// Generate a sentence choosing each alternative yourself, then print it and its choice trace
+    Pick(PickArgs),
+
+    /// Save a grammar in a binary form which loads much faster, for giving to other commands as a `.blb` file
+    Compile(CompileArgs),
+
+    /// Write a Rust module that generates from a grammar without depending on blabber
+    Codegen(CodegenArgs),
+
+    /// Generate a sentence again from its seed and show which rule produced each part
+    Explain(ExplainArgs),
+
+    /// Check two grammars generate the same sentences, exiting with failure and printing some that differ if not
+    Equiv(EquivArgs),
+
+    /// Check which lines of a corpus a grammar can generate, and which alternatives those lines use
+    Cover(CoverArgs),
+
+    /// Check every word in a grammar's terminals is in a dictionary, exiting with failure if any aren't
+    Lint(LintArgs),
+
+    /// Combine several grammar files into one, noting above each rule where it came from
+    Merge(MergeArgs),
+
+    /// Write a grammar back out in canonical form, or as small as it can be with --minify
+    Fmt(FmtArgs),
+
+    /// Write reference documentation for a grammar, with each rule, what uses it and example sentences
+    Export(ExportArgs),
+
+    /// Create a directory for a new grammar, with a commented starter grammar, a blabber.toml and a snapshot
+    New(NewArgs),
+
+    /// Serve generated sentences over HTTP
+    #[cfg(feature = "serve")]
+    Serve(ServeArgs),
+
+    /// Run a language server for grammar files, speaking LSP over stdin and stdout
+    #[cfg(feature = "lsp")]
+    Lsp,
+
+    /// Browse a grammar's rules in a terminal UI, with sentences from the selected rule and the file's problems
+    #[cfg(feature = "tui")]
+    Tui(TuiArgs)
+}
+
+// What --steps and --steps-trace can't be given with, since they print
+// sentences partway through rather than generating whole ones
+const STEPS_CONFLICTS: [&str; 22] = ["uniform_sentences", "choices_in", "choices_out", "jobs_file", "stdin_starts", "seeds_file", "out_dir",
+    "list_terminals", "words", "list_tags", "variety", "temperature", "stable_noise", "deterministic", "profile", "count_symbols", "require", "forbid", "no_repeat_window",
+    "sort", "dedup", "keep"];
+
+#[derive(Args)]
+pub struct GenerateArgs {
     /// File containing the grammar
-    pub file: PathBuf,
+    #[arg(required_unless_present = "grammar")]
+    pub file: Option<PathBuf>,
 
-    /// Start symbol (default: first in the file)
+    /// Use TEXT as the grammar instead of a file, with rules separated by `;` or `\n`
+    #[arg(long, value_name = "TEXT", conflicts_with = "file")]
+    pub grammar: Option<String>,
+
+    /// Syntax the grammar is written in (default: YAML for `.yaml` and `.yml` files, BNF otherwise)
+    #[arg(long, value_name = "SYNTAX", value_parser = ["bnf", "yaml"])]
+    pub syntax: Option<String>,
+
+    /// Start symbol, `random` for a random rule per sentence, or `auto` for the one rule nothing else references (default: first in the file)
     #[arg(short, long, value_name = "SYMBOL")]
     pub start: Option<String>,
 
+    /// Start each sentence from a random one of these symbols
+    #[arg(long, value_name = "SYMBOLS", value_delimiter = ',', conflicts_with = "start")]
+    pub start_any: Option<Vec<String>>,
+
+    /// Prefix each sentence with the symbol it started from and a tab
+    #[arg(long)]
+    pub label: bool,
+
     /// Amount to generate (default: 1)
     #[arg(short = 'n', long, value_name = "AMOUNT")]
-    pub amount: Option<u32>
-}
\ No newline at end of file
+    pub amount: Option<u32>,
+
+    /// Seed for the random choices, giving the same output on every platform and release
+    #[arg(long, value_name = "SEED")]
+    pub seed: Option<u64>,
+
+    /// Seed from a hash of KEY, like a record's ID, so the same key always gives the same sentences
+    #[arg(long, value_name = "KEY", conflicts_with = "seed")]
+    pub seed_from: Option<String>,
+
+    /// Seed each line of --stdin-starts or --jobs-file from the key in its tab separated field N, counting from 1
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..), requires = "keyed_input")]
+    pub key_field: Option<u64>,
+
+    /// Generate one sentence for each seed listed in FILE, one per line, in order
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["seed", "seed_from", "stdin_starts", "jobs_file", "choices_in", "list_terminals", "words", "list_tags"])]
+    pub seeds_file: Option<PathBuf>,
+
+    /// Pick uniformly among every derivation instead of among each rule's alternatives
+    #[arg(long, conflicts_with = "lenient")]
+    pub uniform_sentences: bool,
+
+    /// Favour alternatives used less often so far in this run, from 0 (no effect) to 1 (no repeats until every alternative is used)
+    #[arg(long, value_name = "STRENGTH", value_parser = parse_strength, conflicts_with_all = ["uniform_sentences", "choices_in"])]
+    pub variety: Option<f64>,
+
+    /// Favour alternatives with smaller derivations below 1 and larger ones above 1 (default: 1, which favours neither)
+    #[arg(long, value_name = "T", value_parser = parse_temperature, conflicts_with_all = ["uniform_sentences", "choices_in"])]
+    pub temperature: Option<f64>,
+
+    /// Seed each choice from the rule making it instead of from one shared stream, so editing a rule leaves other rules' seeded output alone
+    #[arg(long, conflicts_with_all = ["uniform_sentences", "choices_in", "jobs_file", "variety", "temperature"])]
+    pub stable_noise: bool,
+
+    /// Always choose the first alternative, with no randomness, generating one sentence per start symbol
+    #[arg(long, conflicts_with_all = ["uniform_sentences", "choices_in", "jobs_file", "seed", "seed_from", "seeds_file", "variety", "temperature", "stable_noise",
+        "require", "forbid", "no_repeat_window", "list_terminals", "words", "list_tags"])]
+    pub deterministic: bool,
+
+    /// Fail a sentence whose rules nest more than DEPTH deep, as a grammar recursing without end does [default: 512]; with --uniform-sentences, also the deepest derivation to sample from (required for recursive grammars), so no sentence fails
+    #[arg(long, value_name = "DEPTH")]
+    pub max_depth: Option<usize>,
+
+    /// Read start symbols from stdin, generating one sentence per line
+    #[arg(long, group = "keyed_input", conflicts_with_all = ["start", "start_any", "label"])]
+    pub stdin_starts: bool,
+
+    /// Output placeholders like ⟨noun⟩ for undefined symbols instead of failing (skips verification)
+    #[arg(long)]
+    pub lenient: bool,
+
+    /// Skip checking every used symbol is defined, for large trusted grammars; an undefined symbol fails when it's reached instead
+    #[arg(long, conflicts_with = "lenient")]
+    pub no_verify: bool,
+
+    /// Treat a kind of warning, or `warnings` for all of them, as an error
+    #[arg(short = 'D', long, value_name = "NAME", value_parser = warning_name)]
+    pub deny: Vec<String>,
+
+    /// Warn about a kind of warning even when `warnings` are denied
+    #[arg(short = 'W', long, value_name = "NAME", value_parser = warning_name)]
+    pub warn: Vec<String>,
+
+    /// Leave out a kind of warning, or `warnings` for all of them
+    #[arg(short = 'A', long, value_name = "NAME", value_parser = warning_name)]
+    pub allow: Vec<String>,
+
+    /// Treat warnings about the grammar as errors, like `--deny warnings`
+    #[arg(long)]
+    pub deny_warnings: bool,
+
+    /// Fetch `%include`s of http and https URLs, keeping them in a cache (needs the `remote` feature)
+    #[arg(long)]
+    pub allow_remote_includes: bool,
+
+    /// Only use included URLs fetched before, from the cache, instead of fetching them
+    #[arg(long, requires = "allow_remote_includes")]
+    pub offline: bool,
+
+    /// Fail if `%include`s are nested more than N deep
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_INCLUDE_DEPTH)]
+    pub max_include_depth: usize,
+
+    /// Substitute VALUE for `{NAME}` in terminals (enables parameters)
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_param)]
+    pub param: Vec<(String, String)>,
+
+    /// Write the choices made for each sentence to FILE, one JSON line per sentence
+    #[arg(long, value_name = "FILE", conflicts_with = "uniform_sentences")]
+    pub choices_out: Option<PathBuf>,
+
+    /// Replay the choices from a file written by --choices-out instead of choosing randomly
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["start", "start_any", "stdin_starts", "uniform_sentences", "choices_out", "amount", "seed", "seed_from"])]
+    pub choices_in: Option<PathBuf>,
+
+    /// Leave out alternative INDEX of RULE, counting from 0
+    #[arg(long, value_name = "RULE:INDEX", value_parser = parse_exclusion)]
+    pub exclude: Vec<Exclusion>,
+
+    /// Leave out every alternative with a terminal containing TEXT
+    #[arg(long, value_name = "TEXT")]
+    pub exclude_containing: Vec<String>,
+
+    /// Leave out every tagged alternative without one of TAGS, keeping untagged ones
+    #[arg(long, value_name = "TAGS", value_delimiter = ',')]
+    pub only_tags: Vec<String>,
+
+    /// Leave out every alternative with one of TAGS
+    #[arg(long, value_name = "TAGS", value_delimiter = ',')]
+    pub skip_tags: Vec<String>,
+
+    /// Only write sentences containing TEXT, generating again until one does
+    #[arg(long, value_name = "TEXT", conflicts_with_all = ["jobs_file", "choices_in", "list_terminals", "words", "list_tags"])]
+    pub require: Vec<String>,
+
+    /// Only write sentences not containing TEXT, generating again until one doesn't
+    #[arg(long, value_name = "TEXT", conflicts_with_all = ["jobs_file", "choices_in", "list_terminals", "words", "list_tags"])]
+    pub forbid: Vec<String>,
+
+    /// Only write sentences without a word twice in any N words in a row, generating again until one doesn't
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(2..), conflicts_with_all = ["jobs_file", "choices_in", "list_terminals", "words", "list_tags"])]
+    pub no_repeat_window: Option<u64>,
+
+    /// Words --no-repeat-window lets repeat, instead of short common words like `the` and `a`
+    #[arg(long, value_name = "WORDS", value_delimiter = ',', requires = "no_repeat_window")]
+    pub repeat_allow: Option<Vec<String>>,
+
+    /// How many times to generate each sentence before giving up on meeting --require, --forbid and --no-repeat-window
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_TRIES, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_tries: u32,
+
+    /// Treat an empty sentence as a failure to generate
+    #[arg(long, conflicts_with_all = ["jobs_file", "choices_in", "list_terminals", "words", "list_tags"])]
+    pub fail_on_empty: bool,
+
+    /// Treat a sentence of only whitespace, or nothing, as a failure to generate
+    #[arg(long, conflicts_with_all = ["jobs_file", "choices_in", "list_terminals", "words", "list_tags"])]
+    pub fail_on_blank: bool,
+
+    /// Rewrite the grammar before generating, keeping the same sentences (left-factor changes their probabilities)
+    #[arg(long, value_name = "PASS", value_parser = ["left-factor", "merge-duplicates"])]
+    pub optimize: Vec<String>,
+
+    /// Count rules as the same with their alternatives in any order for --optimize merge-duplicates
+    #[arg(long, requires = "optimize")]
+    pub ignore_alternative_order: bool,
+
+    /// Escape each sentence for a POSIX shell or as a C string literal
+    #[arg(long, value_name = "STYLE", value_parser = ["none", "shell", "c"], default_value = "none", conflicts_with_all = ["out_dir", "list_terminals", "words", "list_tags"])]
+    pub escape: String,
+
+    /// Put each sentence in Unicode normalization form NFC or NFD before checking, deduplicating, or writing it
+    #[arg(long, value_name = "FORM", value_parser = ["none", "nfc", "nfd"], default_value = "none", conflicts_with_all = ["list_terminals", "words", "list_tags", "steps", "steps_trace"])]
+    pub normalize: String,
+
+    /// Follow each sentence with a `# counts {...}` line giving how many times each rule was expanded for it, as JSON
+    #[arg(long, conflicts_with_all = ["uniform_sentences", "choices_in", "jobs_file", "out_dir", "list_terminals", "words", "list_tags"])]
+    pub count_symbols: bool,
+
+    /// Describe what was done to the grammar, how often --variety used each alternative, and what was generated before an error, on stderr
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Most characters of partial output to show after an error with --verbose
+    #[arg(long, value_name = "CHARS", default_value_t = 1000)]
+    pub partial_limit: usize,
+
+    /// List every distinct terminal, one per line, instead of generating
+    #[arg(long)]
+    pub list_terminals: bool,
+
+    /// List every distinct word in the terminals, one per line, instead of generating
+    #[arg(long, conflicts_with = "list_terminals")]
+    pub words: bool,
+
+    /// List every tag alternatives have, one per line, instead of generating
+    #[arg(long, conflicts_with_all = ["list_terminals", "words", "reachable"])]
+    pub list_tags: bool,
+
+    /// Only list terminals reachable from the start symbols
+    #[arg(long)]
+    pub reachable: bool,
+
+    /// Prefix each listed terminal, word or tag with how many alternatives use it and a tab
+    #[arg(long)]
+    pub counts: bool,
+
+    /// Write each sentence to its own file in DIR instead of stdout, as a fuzzing corpus
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["stdin_starts", "label"])]
+    pub out_dir: Option<PathBuf>,
+
+    /// How to name the files in --out-dir
+    #[arg(long, value_name = "SCHEME", value_parser = ["index", "hash"], default_value = "index", requires = "out_dir")]
+    pub name_by: String,
+
+    /// Leave files that already exist in --out-dir alone
+    #[arg(long, requires = "out_dir")]
+    pub no_clobber: bool,
+
+    /// Stop at the first file in --out-dir that can't be written
+    #[arg(long, requires = "out_dir")]
+    pub fail_fast: bool,
+
+    /// Generate each batch listed in FILE, one `START<TAB>COUNT[<TAB>OUTPUT]` per line, from one parse of the grammar
+    #[arg(long, value_name = "FILE", group = "keyed_input", conflicts_with_all = ["start", "start_any", "amount", "stdin_starts", "choices_in", "choices_out", "out_dir", "variety", "temperature"])]
+    pub jobs_file: Option<PathBuf>,
+
+    /// How many jobs from --jobs-file to run at once
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), requires = "jobs_file")]
+    pub jobs: u32,
+
+    /// Print how long reading, parsing, verifying, optimizing, and generating took on stderr, as text or json
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "text", value_parser = ["text", "json"])]
+    pub time: Option<String>,
+
+    /// Color the text each rule made when writing to a terminal, printing which color is which rule on stderr
+    #[arg(long, conflicts_with_all = ["uniform_sentences", "choices_in", "jobs_file", "out_dir", "escape", "count_symbols", "sort", "keep", "dedup", "list_terminals", "words", "list_tags", "steps", "steps_trace"])]
+    pub color_output: bool,
+
+    /// Expand at most N nonterminals, leftmost first, and print the sentence with the rest left like ⟨noun⟩
+    #[arg(long, value_name = "N", conflicts_with_all = STEPS_CONFLICTS)]
+    pub steps: Option<usize>,
+
+    /// Print the sentence after every step, one per line, up to --steps or until it's finished
+    #[arg(long, conflicts_with_all = STEPS_CONFLICTS)]
+    pub steps_trace: bool,
+
+    /// Print how many times each rule was expanded, how long it took, and how many bytes its terminals wrote on stderr, the slowest first
+    #[arg(long, conflicts_with_all = ["uniform_sentences", "choices_in", "jobs_file", "list_terminals", "words", "list_tags"])]
+    pub profile: bool,
+
+    /// Write the sentences sorted when done, by bytes or in natural order (case insensitive, with numbers by value)
+    #[arg(long, value_name = "ORDER", num_args = 0..=1, require_equals = true, default_missing_value = "bytes", value_parser = ["bytes", "natural"], conflicts_with_all = ["stdin_starts", "jobs_file", "out_dir", "list_terminals", "words", "list_tags"])]
+    pub sort: Option<String>,
+
+    /// Leave out sentences already written, remembering them whole or as 128-bit hashes to save memory
+    #[arg(long, value_name = "BY", num_args = 0..=1, require_equals = true, default_missing_value = "text", value_parser = ["text", "hash"], conflicts_with_all = ["stdin_starts", "jobs_file", "choices_in", "list_terminals", "words", "list_tags"])]
+    pub dedup: Option<String>,
+
+    /// Only write the N best sentences by --by when done, holding no more than N at once
+    #[arg(long, value_name = "N", requires = "by", conflicts_with_all = ["uniform_sentences", "stdin_starts", "jobs_file", "choices_in", "out_dir", "list_terminals", "words", "list_tags"])]
+    pub keep: Option<usize>,
+
+    /// What makes a sentence better for --keep, with ties going to the one generated first
+    #[arg(long, value_name = "MEASURE", value_parser = ["longest", "shortest", "most-words", "most-expansions"], requires = "keep")]
+    pub by: Option<String>,
+
+    /// Print the amount of sentences and statistics about their lengths on stderr when done
+    #[arg(long, conflicts_with_all = ["jobs_file", "choices_in", "list_terminals", "words", "list_tags"])]
+    pub summary: bool,
+
+    /// How to print the --summary
+    #[arg(long, value_name = "FORMAT", value_parser = ["text", "json"], default_value = "text", requires = "summary")]
+    pub summary_format: String,
+
+    /// Keep generating after an error, summarizing the failures and exiting with failure at the end
+    #[arg(long)]
+    pub keep_going: bool,
+
+    /// Exit successfully with --keep-going if no more than N sentences failed
+    #[arg(long, value_name = "N", requires = "keep_going", conflicts_with = "stdin_starts")]
+    pub allow_failures: Option<u64>,
+
+    /// Stop once SIZE bytes of sentences are written, like 512M or 2G, finishing the sentence that reaches it
+    #[arg(long, value_name = "SIZE", value_parser = parse_size, conflicts_with_all = ["out_dir", "jobs_file", "choices_in", "list_terminals", "words", "list_tags", "sort", "keep", "steps", "steps_trace"])]
+    pub limit_bytes: Option<u64>,
+
+    /// Exit with code 3 when --limit-bytes stops the output early
+    #[arg(long, requires = "limit_bytes")]
+    pub limit_is_error: bool
+}
+
+impl GenerateArgs {
+    pub fn warning_levels(&self) -> WarningLevels {
+        warning_levels(&self.deny, &self.warn, &self.allow, self.deny_warnings)
+    }
+}
+
+impl CheckArgs {
+    pub fn warning_levels(&self) -> WarningLevels {
+        warning_levels(&self.deny, &self.warn, &self.allow, self.deny_warnings)
+    }
+}
+
+impl CompileArgs {
+    pub fn warning_levels(&self) -> WarningLevels {
+        warning_levels(&self.deny, &self.warn, &self.allow, self.deny_warnings)
+    }
+}
+
+// The levels -D, -W, -A and --deny-warnings give warnings, leaving out the
+// findings `blabber check --deny` can be given
+fn warning_levels(deny: &[String], warn: &[String], allow: &[String], deny_warnings: bool) -> WarningLevels {
+    let mut levels = if deny_warnings { WarningLevels::deny_all() } else { WarningLevels::default() };
+    for (names, level) in [(deny, Level::Deny), (warn, Level::Warn), (allow, Level::Allow)] {
+        for name in names.iter().filter(|name| !CHECK_FINDINGS.contains(&name.as_str())) {
+            levels.set(name, level);
+        }
+    }
+    return levels;
+}
+
+// What `blabber check` finds besides warnings, which --deny can make errors
+const CHECK_FINDINGS: [&str; 2] = ["recursion", "duplicates"];
+
+fn parse_check_denial(text: &str) -> Result<String, String> {
+    if CHECK_FINDINGS.contains(&text) {
+        return Ok(text.to_string());
+    }
+    return warning_name(text).map_err(|error| format!("{}, or a finding, `recursion` or `duplicates`", error));
+}
+
+fn parse_param(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((name, value)) => Ok((name.to_string(), value.to_string())),
+        None => Err(format!("expected NAME=VALUE, found `{}`", text)),
+    }
+}
+
+fn parse_strength(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(strength) if (0.0..=1.0).contains(&strength) => Ok(strength),
+        _ => Err(format!("expected a number from 0 to 1, found `{}`", text)),
+    }
+}
+
+fn parse_temperature(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(temperature) if temperature > 0.0 && temperature.is_finite() => Ok(temperature),
+        _ => Err(format!("expected a number above 0, found `{}`", text)),
+    }
+}
+
+#[derive(Args)]
+pub struct HistogramArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// Symbol to expand (default: first in the file)
+    #[arg(short, long, value_name = "SYMBOL")]
+    pub start: Option<String>,
+
+    /// Amount to generate (default: 1000)
+    #[arg(short = 'n', long, value_name = "AMOUNT")]
+    pub amount: Option<u32>,
+
+    /// Amount of the most frequent strings to show (default: 20)
+    #[arg(short = 'k', long, value_name = "K")]
+    pub top: Option<usize>,
+
+    /// Most distinct strings to keep count of (default: 100000)
+    #[arg(long, value_name = "CAP")]
+    pub max_distinct: Option<usize>,
+
+    /// Favour alternatives used less often so far, as with generating
+    #[arg(long, value_name = "STRENGTH", value_parser = parse_strength)]
+    pub variety: Option<f64>,
+
+    /// Favour smaller or larger derivations, as with generating
+    #[arg(long, value_name = "T", value_parser = parse_temperature)]
+    pub temperature: Option<f64>,
+
+    /// Print the table as JSON
+    #[arg(long)]
+    pub json: bool
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// Leave out alternative INDEX of RULE, counting from 0
+    #[arg(long, value_name = "RULE:INDEX", value_parser = parse_exclusion)]
+    pub exclude: Vec<Exclusion>,
+
+    /// Leave out every alternative with a terminal containing TEXT
+    #[arg(long, value_name = "TEXT")]
+    pub exclude_containing: Vec<String>,
+
+    /// Leave out every tagged alternative without one of TAGS, keeping untagged ones
+    #[arg(long, value_name = "TAGS", value_delimiter = ',')]
+    pub only_tags: Vec<String>,
+
+    /// Leave out every alternative with one of TAGS
+    #[arg(long, value_name = "TAGS", value_delimiter = ',')]
+    pub skip_tags: Vec<String>,
+
+    /// Print the expected length of each rule's expansions, longest first
+    #[arg(long)]
+    pub expected_lengths: bool
+}
+
+#[derive(Args)]
+pub struct CheckArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// List every group of rules that can expand to itself
+    #[arg(long)]
+    pub cycles: bool,
+
+    /// List every group of rules with the same definition, which could be one rule
+    #[arg(long)]
+    pub duplicates: bool,
+
+    /// Count rules as the same with their alternatives in any order
+    #[arg(long)]
+    pub ignore_alternative_order: bool,
+
+    /// Treat a finding, `recursion` or `duplicates`, or a kind of warning, or `warnings` for all of them, as an error
+    #[arg(short = 'D', long, value_name = "NAME", value_parser = parse_check_denial)]
+    pub deny: Vec<String>,
+
+    /// Warn about a kind of warning even when `warnings` are denied
+    #[arg(short = 'W', long, value_name = "NAME", value_parser = warning_name)]
+    pub warn: Vec<String>,
+
+    /// Leave out a kind of warning, or `warnings` for all of them
+    #[arg(short = 'A', long, value_name = "NAME", value_parser = warning_name)]
+    pub allow: Vec<String>,
+
+    /// Treat warnings about the grammar as errors, like `--deny warnings`
+    #[arg(long)]
+    pub deny_warnings: bool,
+
+    /// Fetch `%include`s of http and https URLs, keeping them in a cache (needs the `remote` feature)
+    #[arg(long)]
+    pub allow_remote_includes: bool,
+
+    /// Only use included URLs fetched before, from the cache, instead of fetching them
+    #[arg(long, requires = "allow_remote_includes")]
+    pub offline: bool,
+
+    /// Fail if `%include`s are nested more than N deep
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_INCLUDE_DEPTH)]
+    pub max_include_depth: usize
+}
+
+#[derive(Args)]
+pub struct TestArgs {
+    /// File containing the grammar
+    pub file: PathBuf
+}
+
+#[derive(Args)]
+pub struct SnapshotArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// Seed to generate with (default with --check: the snapshot's)
+    #[arg(long, value_name = "SEED", required_unless_present = "check")]
+    pub seed: Option<u64>,
+
+    /// Amount to generate (default: 1, or with --check the snapshot's)
+    #[arg(short = 'n', long, value_name = "AMOUNT")]
+    pub amount: Option<u32>,
+
+    /// Start symbol, or `auto` for the one rule nothing else references (default: first in the file, or with --check the snapshot's)
+    #[arg(short, long, value_name = "SYMBOL")]
+    pub start: Option<String>,
+
+    /// Seed each choice from the rule making it, as when generating (default with --check: the snapshot's)
+    #[arg(long)]
+    pub stable_noise: bool,
+
+    /// Write the sentences to FILE as a new snapshot
+    #[arg(long, value_name = "FILE", required_unless_present = "check", conflicts_with = "check")]
+    pub write: Option<PathBuf>,
+
+    /// Generate again with the snapshot in FILE's settings and show how the sentences differ from it, failing if they do
+    #[arg(long, value_name = "FILE")]
+    pub check: Option<PathBuf>,
+
+    /// Rewrite the snapshot checked with --check to accept the differences
+    #[arg(long, requires = "check")]
+    pub update: bool
+}
+
+#[derive(Args)]
+pub struct LexArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// How to print the tokens
+    #[arg(long, value_name = "FORMAT", value_parser = ["text", "json"], default_value = "text")]
+    pub format: String
+}
+
+#[derive(Args)]
+pub struct PickArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// Start symbol (default: first in the file)
+    #[arg(short, long, value_name = "SYMBOL")]
+    pub start: Option<String>,
+
+    /// Seed for the choices left to chance
+    #[arg(long, value_name = "SEED")]
+    pub seed: Option<u64>
+}
+
+#[derive(Args)]
+pub struct CompileArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// File to save the compiled grammar to, conventionally ending in `.blb`
+    #[arg(short, long, value_name = "FILE")]
+    pub output: PathBuf,
+
+    /// Treat a kind of warning, or `warnings` for all of them, as an error
+    #[arg(short = 'D', long, value_name = "NAME", value_parser = warning_name)]
+    pub deny: Vec<String>,
+
+    /// Warn about a kind of warning even when `warnings` are denied
+    #[arg(short = 'W', long, value_name = "NAME", value_parser = warning_name)]
+    pub warn: Vec<String>,
+
+    /// Leave out a kind of warning, or `warnings` for all of them
+    #[arg(short = 'A', long, value_name = "NAME", value_parser = warning_name)]
+    pub allow: Vec<String>,
+
+    /// Treat warnings about the grammar as errors, like `--deny warnings`
+    #[arg(long)]
+    pub deny_warnings: bool,
+
+    /// Fetch `%include`s of http and https URLs, keeping them in a cache (needs the `remote` feature)
+    #[arg(long)]
+    pub allow_remote_includes: bool,
+
+    /// Only use included URLs fetched before, from the cache, instead of fetching them
+    #[arg(long, requires = "allow_remote_includes")]
+    pub offline: bool,
+
+    /// Fail if `%include`s are nested more than N deep
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_INCLUDE_DEPTH)]
+    pub max_include_depth: usize
+}
+
+#[derive(Args)]
+pub struct CodegenArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// Write the module to FILE instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>
+}
+
+#[derive(Args)]
+pub struct ExplainArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// Seed the sentence was generated with
+    #[arg(long, value_name = "SEED")]
+    pub seed: u64,
+
+    /// Start symbol, or `auto` for the one rule nothing else references (default: first in the file)
+    #[arg(short, long, value_name = "SYMBOL")]
+    pub start: Option<String>,
+
+    /// Which sentence of a run with this seed to explain, counting from 1
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub sentence: u32,
+
+    /// Explain a sentence generated with --stable-noise
+    #[arg(long)]
+    pub stable_noise: bool,
+
+    /// How to print the explanation
+    #[arg(long, value_name = "FORMAT", value_parser = ["text", "json"], default_value = "text")]
+    pub format: String
+}
+
+#[derive(Args)]
+pub struct EquivArgs {
+    /// File containing the first grammar
+    pub first: PathBuf,
+
+    /// File containing the second grammar
+    pub second: PathBuf,
+
+    /// Start symbol for both grammars (default: first in each file)
+    #[arg(short, long, value_name = "SYMBOL")]
+    pub start: Option<String>,
+
+    /// Sentences to sample from each grammar when they can't be enumerated
+    #[arg(long, value_name = "AMOUNT", default_value_t = 10000)]
+    pub samples: u32,
+
+    /// Deepest derivation to enumerate, sampling grammars with deeper ones instead
+    #[arg(long, value_name = "DEPTH", default_value_t = 20)]
+    pub max_depth: usize,
+
+    /// Most sentences to print that only one grammar generates, for each grammar
+    #[arg(short = 'k', long, value_name = "K", default_value_t = 10)]
+    pub counterexamples: usize,
+
+    /// Seed to sample with
+    #[arg(long, value_name = "SEED")]
+    pub seed: Option<u64>
+}
+
+#[derive(Args)]
+pub struct CoverArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// File of sentences, one per line, or `-` to read them from stdin
+    pub corpus: PathBuf,
+
+    /// Start symbol, or `auto` for the one rule nothing else references (default: first in the file)
+    #[arg(short, long, value_name = "SYMBOL")]
+    pub start: Option<String>,
+
+    /// List the lines the grammar can't generate, with the longest start of each that a sentence starts with
+    #[arg(long)]
+    pub unmatched: bool
+}
+
+#[derive(Args)]
+pub struct LintArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// File of the words terminals can use, one per line
+    #[arg(long, value_name = "FILE")]
+    pub dictionary: PathBuf,
+
+    /// Only allow words in the case the dictionary has them, so `Paris` needs `Paris` rather than `paris`
+    #[arg(long)]
+    pub case_sensitive: bool,
+
+    /// Check runs of punctuation, like `!` or `...`, against the dictionary too
+    #[arg(long)]
+    pub check_punctuation: bool,
+
+    /// Check words made only of digits, like `42`, against the dictionary too
+    #[arg(long)]
+    pub check_numbers: bool,
+
+    /// Also write each word that isn't in the dictionary to FILE, as a line of the word, rule and location separated by tabs
+    #[arg(long, value_name = "FILE")]
+    pub unknown_words_out: Option<PathBuf>
+}
+
+#[derive(Args)]
+pub struct MergeArgs {
+    /// Files containing the grammars, the first of which gives the start symbol
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Write the merged grammar to FILE instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// What to do with a rule more than one file defines
+    #[arg(long, value_name = "POLICY", value_parser = ["error", "first", "last", "combine"], default_value = "error")]
+    pub on_conflict: String,
+
+    /// Leave out rules the start symbol can't reach
+    #[arg(long)]
+    pub prune: bool
+}
+
+#[derive(Args)]
+pub struct FmtArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// Write the formatted grammar to FILE instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Rename every rule to a short name and leave out comments and any space the grammar doesn't need
+    #[arg(long)]
+    pub minify: bool,
+
+    /// Write a JSON object from each rule's name to its --minify name to FILE
+    #[arg(long, value_name = "FILE", requires = "minify")]
+    pub map: Option<PathBuf>,
+
+    /// Syntax to write the grammar in
+    #[arg(long, value_name = "SYNTAX", default_value = "bnf", value_parser = ["bnf", "yaml"], conflicts_with = "minify")]
+    pub to: String,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// Format to write the documentation in
+    #[arg(long, value_name = "FORMAT", default_value = "markdown", value_parser = ["markdown"])]
+    pub format: String,
+
+    /// Write the documentation to FILE instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// How many example sentences to generate from each rule
+    #[arg(short = 'k', long, value_name = "K", default_value_t = 3)]
+    pub examples: usize,
+
+    /// Seed for the example sentences, which every rule's start from
+    #[arg(long, value_name = "SEED", default_value_t = 0)]
+    pub seed: u64,
+}
+
+#[derive(Args)]
+pub struct NewArgs {
+    /// Directory to create, whose name the grammar is named after
+    pub dir: PathBuf,
+
+    /// Starter grammar to begin with
+    #[arg(long, value_name = "TEMPLATE", default_value = "story", value_parser = ["story", "data", "minimal"])]
+    pub template: String,
+}
+
+#[cfg(feature = "serve")]
+#[derive(Args)]
+pub struct ServeArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// Port to listen on, on localhost
+    #[arg(long, default_value_t = 8080)]
+    pub port: u16,
+
+    /// How many requests to handle at once
+    #[arg(long, default_value_t = 4)]
+    pub threads: usize,
+
+    /// Parse the grammar again whenever the file changes
+    #[arg(long)]
+    pub reload: bool
+}
+
+#[cfg(feature = "tui")]
+#[derive(Args)]
+pub struct TuiArgs {
+    /// File containing the grammar
+    pub file: PathBuf,
+
+    /// Amount of sentences to show
+    #[arg(short = 'n', long, value_name = "AMOUNT", default_value_t = 5)]
+    pub amount: usize,
+
+    /// Start with the sentences fixed to this seed
+    #[arg(long, value_name = "SEED")]
+    pub seed: Option<u64>,
+
+    /// Generate new sentences every this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    pub every: Option<u64>,
+}
+
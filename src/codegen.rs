@@ -0,0 +1,323 @@
+/*
+    This module turns a grammar into Rust source for a self-contained module
+    that generates from it, for embedding in programs that shouldn't depend
+    on blabber.
+
+    The module only needs the rand crate. It keeps the grammar in static
+    tables and makes every choice the way the interpreter does, so given the
+    same random number generator it generates the same sentences.
+*/
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use itertools::Itertools;
+
+use crate::generator::GenerateErrorType;
+use crate::grammar::{Case, Grammar, SymbolRef};
+
+// Names the module defines itself, which rules can't be named after
+const RESERVED: [&str; 6] = ["generate", "sentence", "expand", "choose_index", "choose_cumulative", "change_case"];
+
+const KEYWORDS: [&str; 52] = [
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for", "if", "impl", "in",
+    "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "static", "struct", "super",
+    "trait", "true", "type", "unsafe", "use", "where", "while", "async", "await", "dyn", "abstract", "become", "box",
+    "do", "final", "macro", "override", "priv", "typeof", "unsized", "virtual", "yield", "try", "gen", "union",
+];
+
+// Whether a rule gets its own function. Rules starting with `_` are only
+// used by other rules.
+pub fn is_public(rule: &str) -> bool {
+    !rule.starts_with('_')
+}
+
+// The snake case function name for a rule, before making it unique. Every
+// character that can't be in one becomes `_`.
+fn function_name(rule: &str) -> String {
+    let name = rule.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .dedup_by(|a, b| *a == '_' && *b == '_')
+        .collect::<String>();
+    let name = name.trim_matches('_');
+    return match name {
+        "" => "rule".to_string(),
+        name if name.starts_with(|c: char| c.is_ascii_digit()) => format!("rule_{}", name),
+        name if KEYWORDS.contains(&name) || RESERVED.contains(&name) => format!("{}_", name),
+        name => name.to_string(),
+    };
+}
+
+// A function name for each public rule, numbering rules whose names would
+// otherwise be the same
+fn function_names<'a>(rules: &[&'a str]) -> BTreeMap<&'a str, String> {
+    let mut used = BTreeSet::new();
+    let mut names = BTreeMap::new();
+    for rule in rules.iter().filter(|rule| is_public(rule)) {
+        let base = function_name(rule);
+        let name = (1..)
+            .map(|number| if number == 1 { base.clone() } else { format!("{}_{}", base, number) })
+            .find(|name| !used.contains(name))
+            .unwrap();
+        used.insert(name.clone());
+        names.insert(*rule, name);
+    }
+    return names;
+}
+
+fn case_name(case: Case) -> &'static str {
+    match case {
+        Case::Upper => "Upper",
+        Case::Lower => "Lower",
+        Case::Title => "Title",
+    }
+}
+
+// Writes a Rust module generating from the grammar. Fails if a rule uses an
+// undefined nonterminal, since the module would have nothing to expand.
+pub fn codegen(grammar: &Grammar) -> Result<String, GenerateErrorType> {
+    let rules = grammar.rules.keys().sorted().collect_vec();
+    let indices: BTreeMap<&str, usize> = rules.iter().enumerate().map(|(index, rule)| (*rule, index)).collect();
+    let symbols = || rules.iter().flat_map(|rule| grammar.rules.rewrite(rule).iter().flatten());
+
+    // Empty terminals are left out, like the interpreter leaves them out of
+    // its tokens
+    let terminals: BTreeSet<&str> = symbols()
+        .filter_map(|symbol| match symbol {
+            SymbolRef::Terminal(text) if !text.is_empty() => Some(text),
+            _ => None
+        })
+        .collect();
+    let terminal_indices: BTreeMap<&str, usize> = terminals.iter().enumerate().map(|(index, text)| (*text, index)).collect();
+    let mut cases = BTreeSet::new();
+    let mut uses_rules = false;
+    for symbol in symbols() {
+        if let SymbolRef::Dynamic(dynamic) = symbol {
+            return Err(GenerateErrorType::UnsupportedDynamic(dynamic.name().to_string()));
+        }
+        if let SymbolRef::Nonterminal(name, case) = symbol {
+            if !indices.contains_key(name) {
+                return Err(GenerateErrorType::UndefinedNonterminal(name.to_string()));
+            }
+            uses_rules = true;
+            cases.extend(case.map(case_name));
+        }
+    }
+    let start = *indices.get(grammar.start_symbol.as_str())
+        .ok_or_else(|| GenerateErrorType::UndefinedNonterminal(grammar.start_symbol.clone()))?;
+
+    // Only what the grammar uses is written, so the module has no dead code
+    // to warn about
+    let mut out = String::new();
+    out.push_str("// Generated by `blabber codegen`. Change the grammar and generate this again\n");
+    out.push_str("// rather than editing it.\n\n");
+    if cases.is_empty() {
+        out.push_str("use rand::{Rng, RngCore};\n\n");
+    } else {
+        out.push_str("use std::borrow::Cow;\n\nuse rand::{Rng, RngCore};\n\n");
+        out.push_str("#[derive(Clone, Copy)]\nenum Case {\n");
+        for case in &cases {
+            writeln!(out, "    {},", case).unwrap();
+        }
+        out.push_str("}\n\n");
+    }
+
+    out.push_str("#[derive(Clone, Copy)]\nenum Symbol {\n");
+    if !terminals.is_empty() {
+        out.push_str("    // An index into TERMINALS\n    Terminal(usize),\n");
+    }
+    if uses_rules {
+        out.push_str("    // An index into RULES");
+        if cases.is_empty() {
+            out.push_str("\n    Rule(usize),\n");
+        } else {
+            out.push_str(", and the case to put its expansion in\n    Rule(usize, Option<Case>),\n");
+        }
+    }
+    out.push_str("}\n\n");
+
+    if !terminals.is_empty() {
+        out.push_str("// Every distinct terminal, sorted\n");
+        writeln!(out, "static TERMINALS: [&str; {}] = [", terminals.len()).unwrap();
+        for text in &terminals {
+            writeln!(out, "    {:?},", text).unwrap();
+        }
+        out.push_str("];\n\n");
+    }
+    if cases.contains("Title") {
+        out.push_str("// Each terminal in title case, which blabber works out a grapheme at a time\n");
+        writeln!(out, "static TITLED: [&str; {}] = [", terminals.len()).unwrap();
+        for text in &terminals {
+            writeln!(out, "    {:?},", Case::Title.apply(text)).unwrap();
+        }
+        out.push_str("];\n\n");
+    }
+
+    out.push_str("// The alternatives of each rule\n");
+    writeln!(out, "static RULES: [&[&[Symbol]]; {}] = [", rules.len()).unwrap();
+    for rule in &rules {
+        let alternatives = grammar.rules.rewrite(rule).iter()
+            .map(|alternative| {
+                let symbols = alternative.iter()
+                    .filter_map(|symbol| match symbol {
+                        SymbolRef::Terminal("") => None,
+                        SymbolRef::Terminal(text) => Some(format!("Symbol::Terminal({})", terminal_indices[text])),
+                        SymbolRef::Nonterminal(name, _) if cases.is_empty() => Some(format!("Symbol::Rule({})", indices[name])),
+                        SymbolRef::Nonterminal(name, None) => Some(format!("Symbol::Rule({}, None)", indices[name])),
+                        SymbolRef::Nonterminal(name, Some(case)) => Some(format!("Symbol::Rule({}, Some(Case::{}))", indices[name], case_name(case))),
+                        SymbolRef::Dynamic(_) => unreachable!("grammars with dynamic symbols were turned down"),
+                    })
+                    .join(", ");
+                format!("&[{}]", symbols)
+            })
+            .join(", ");
+        writeln!(out, "    // {}\n    &[{}],", rule, alternatives).unwrap();
+    }
+    out.push_str("];\n\n");
+
+    let weighted = !grammar.weights.is_empty();
+    if weighted {
+        out.push_str("// The running totals of each rule's weights, or nothing if its alternatives\n// are equally likely\n");
+        writeln!(out, "static WEIGHTS: [&[f64]; {}] = [", rules.len()).unwrap();
+        for rule in &rules {
+            let weights = grammar.weights.get(&**rule).map_or_else(String::new, |weights| weights.cumulative().iter().map(|sum| format!("{:?}", sum)).join(", "));
+            writeln!(out, "    &[{}],", weights).unwrap();
+        }
+        out.push_str("];\n\n");
+    }
+
+    out.push_str("/// Generates a sentence from the start symbol\n");
+    out.push_str("pub fn generate(rng: &mut impl Rng) -> String {\n");
+    writeln!(out, "    sentence({}, rng)\n}}\n", start).unwrap();
+    for (rule, name) in function_names(&rules) {
+        writeln!(out, "/// Generates a sentence from `{}`", rule).unwrap();
+        writeln!(out, "pub fn {}(rng: &mut impl Rng) -> String {{", name).unwrap();
+        writeln!(out, "    sentence({}, rng)\n}}\n", indices[rule]).unwrap();
+    }
+
+    let token = if cases.is_empty() { "&'static str" } else { "Cow<'static, str>" };
+    out.push_str("fn sentence<R: RngCore + ?Sized>(rule: usize, rng: &mut R) -> String {\n");
+    out.push_str("    let mut tokens = Vec::new();\n");
+    out.push_str("    expand(rule, rng, &mut tokens);\n");
+    out.push_str("    tokens.concat()\n}\n\n");
+
+    writeln!(out, "fn expand<R: RngCore + ?Sized>(rule: usize, rng: &mut R, tokens: &mut Vec<{}>) {{", token).unwrap();
+    out.push_str("    let alternatives = RULES[rule];\n");
+    out.push_str("    if alternatives.is_empty() {\n        return;\n    }\n");
+    if weighted {
+        out.push_str("    let index = match WEIGHTS[rule] {\n");
+        out.push_str("        [] => choose_index(rng, alternatives.len()),\n");
+        out.push_str("        cumulative => choose_cumulative(rng, cumulative),\n");
+        out.push_str("    };\n");
+        out.push_str("    for symbol in alternatives[index] {\n");
+    } else {
+        out.push_str("    for symbol in alternatives[choose_index(rng, alternatives.len())] {\n");
+    }
+    out.push_str("        match *symbol {\n");
+    if !terminals.is_empty() {
+        let text = if cases.is_empty() { "TERMINALS[terminal]" } else { "Cow::Borrowed(TERMINALS[terminal])" };
+        writeln!(out, "            Symbol::Terminal(terminal) => tokens.push({}),", text).unwrap();
+    }
+    if uses_rules && cases.is_empty() {
+        out.push_str("            Symbol::Rule(rule) => expand(rule, rng, tokens),\n");
+    } else if uses_rules {
+        out.push_str("            Symbol::Rule(rule, case) => {\n");
+        out.push_str("                let first = tokens.len();\n");
+        out.push_str("                expand(rule, rng, tokens);\n");
+        out.push_str("                if let Some(case) = case {\n");
+        out.push_str("                    change_case(case, &mut tokens[first..]);\n");
+        out.push_str("                }\n            }\n");
+    }
+    out.push_str("        }\n    }\n}\n\n");
+
+    if !cases.is_empty() {
+        out.push_str("// Title case only changes the first token, since it only changes the first\n");
+        out.push_str("// character. Tokens another case already changed are title cased a character\n");
+        out.push_str("// at a time instead of a grapheme at a time.\n");
+        out.push_str("fn change_case(case: Case, tokens: &mut [Cow<'static, str>]) {\n");
+        out.push_str("    match case {\n");
+        for (case, method) in [("Upper", "to_uppercase"), ("Lower", "to_lowercase")] {
+            if cases.contains(case) {
+                writeln!(out, "        Case::{} => {{", case).unwrap();
+                out.push_str("            for token in tokens {\n");
+                writeln!(out, "                *token = Cow::Owned(token.{}());", method).unwrap();
+                out.push_str("            }\n        }\n");
+            }
+        }
+        if cases.contains("Title") {
+            out.push_str("        Case::Title => match tokens.first_mut() {\n");
+            out.push_str("            Some(Cow::Borrowed(text)) => *text = TITLED[TERMINALS.binary_search(text).unwrap()],\n");
+            out.push_str("            Some(Cow::Owned(text)) => {\n");
+            out.push_str("                if let Some(first) = text.chars().next() {\n");
+            out.push_str("                    *text = first.to_uppercase().chain(text[first.len_utf8()..].chars()).collect();\n");
+            out.push_str("                }\n            }\n");
+            out.push_str("            None => {}\n        },\n");
+        }
+        out.push_str("    }\n}\n\n");
+    }
+
+    out.push_str("// Picks an index below len like blabber does, from whole 64 bit outputs,\n");
+    out.push_str("// redrawing those at or above the largest multiple of len\n");
+    out.push_str("fn choose_index<R: RngCore + ?Sized>(rng: &mut R, len: usize) -> usize {\n");
+    out.push_str("    let len = len as u64;\n");
+    out.push_str("    let rejected = len.wrapping_neg() % len;\n");
+    out.push_str("    loop {\n");
+    out.push_str("        let value = rng.next_u64();\n");
+    out.push_str("        if value <= u64::MAX - rejected {\n");
+    out.push_str("            return (value % len) as usize;\n");
+    out.push_str("        }\n    }\n}\n");
+    if weighted {
+        out.push_str("\n// Picks an index with probability proportional to its weight like blabber\n");
+        out.push_str("// does, from the top 53 bits of one output and the running totals\n");
+        out.push_str("fn choose_cumulative<R: RngCore + ?Sized>(rng: &mut R, cumulative: &[f64]) -> usize {\n");
+        out.push_str("    let total = cumulative[cumulative.len() - 1];\n");
+        out.push_str("    let target = (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * total;\n");
+        out.push_str("    match cumulative.partition_point(|sum| *sum <= target) {\n");
+        out.push_str("        index if index < cumulative.len() => index,\n");
+        out.push_str("        _ => cumulative.partition_point(|sum| *sum < total),\n");
+        out.push_str("    }\n");
+        out.push_str("}\n");
+    }
+    return Ok(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::{parse_str, parse_str_with_options, ParseOptions};
+
+    #[test]
+    fn name_functions() {
+        let rules = ["noun.phrase", "Noun-Phrase", "loop", "2nd", "_hidden", "---", "generate", "noun_phrase_2"];
+        let names = function_names(&rules);
+        assert_eq!(names.into_iter().collect_vec(), vec![
+            ("---", "rule".to_string()),
+            ("2nd", "rule_2nd".to_string()),
+            ("Noun-Phrase", "noun_phrase_2".to_string()),
+            ("generate", "generate_".to_string()),
+            ("loop", "loop_".to_string()),
+            ("noun.phrase", "noun_phrase".to_string()),
+            ("noun_phrase_2", "noun_phrase_2_2".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn only_write_what_is_used() {
+        let plain = codegen(&parse_str("s = \"a\" | \"b\"", SourceName::Builtin).unwrap()).unwrap();
+        assert!(plain.contains("static TERMINALS: [&str; 2] = [\n    \"a\",\n    \"b\",\n];"));
+        assert!(!plain.contains("Cow") && !plain.contains("Rule(") && !plain.contains("TITLED"));
+
+        let cased = codegen(&parse_str("s = t:title\nt = \"élan\"", SourceName::Builtin).unwrap()).unwrap();
+        assert!(cased.contains("enum Case {\n    Title,\n}"));
+        assert!(cased.contains("static TITLED: [&str; 1] = [\n    \"Élan\",\n];"));
+        assert!(!cased.contains("Case::Upper"));
+    }
+
+    #[test]
+    fn refuse_undefined_nonterminals() {
+        let options = ParseOptions { verify: false, ..Default::default() };
+        let (grammar, _) = parse_str_with_options("s = \"the \" noun", SourceName::Builtin, &options).unwrap();
+        assert_eq!(codegen(&grammar), Err(GenerateErrorType::UndefinedNonterminal("noun".to_string())));
+    }
+}
@@ -0,0 +1,77 @@
+/*
+    Colors each part of a sentence by the rule which produced it, for showing
+    in a terminal how a grammar put a sentence together.
+
+    A rule's color comes from a hash of its name, so it's the same on every
+    run and in every grammar. Text is colored by the innermost rule it came
+    from, so a rule made only of other rules never shows up itself.
+*/
+
+use std::collections::BTreeSet;
+
+use itertools::Itertools;
+
+use crate::dedup::fnv1a_128;
+use crate::generator::GeneratedToken;
+
+// The ANSI foreground colors rules are given, leaving out black and white,
+// which disappear on some backgrounds
+const PALETTE: [u8; 12] = [31, 32, 33, 34, 35, 36, 91, 92, 93, 94, 95, 96];
+
+fn color_of(rule: &str) -> u8 {
+    PALETTE[(fnv1a_128(rule.as_bytes()) % PALETTE.len() as u128) as usize]
+}
+
+fn paint_text(rule: &str, text: &str) -> String {
+    format!("\x1b[{}m{}\x1b[0m", color_of(rule), text)
+}
+
+// Colors sentences, remembering which rules it has colored for the legend
+#[derive(Default)]
+pub struct Painter {
+    used: BTreeSet<String>,
+}
+
+impl Painter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // The text of the tokens, with each run of tokens from the same rule in
+    // its color
+    pub fn paint(&mut self, tokens: &[GeneratedToken]) -> String {
+        let mut painted = String::new();
+        for (rule, run) in &tokens.iter().chunk_by(|token| token.rule.as_str()) {
+            let text: String = run.map(|token| token.text.as_str()).collect();
+            painted += &paint_text(rule, &text);
+            if !self.used.contains(rule) {
+                self.used.insert(rule.to_string());
+            }
+        }
+        return painted;
+    }
+
+    // Each rule that colored some text, in its color, in order of name
+    pub fn legend(&self) -> String {
+        return self.used.iter().map(|rule| paint_text(rule, rule)).join("  ");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(text: &str, rule: &str) -> GeneratedToken {
+        GeneratedToken { text: text.to_string(), rule: rule.to_string() }
+    }
+
+    #[test]
+    fn color_by_innermost_rule() {
+        let mut painter = Painter::new();
+        let tokens = [token("the ", "s"), token("green", "adjective"), token(" ", "adjective"), token("ideas", "noun"), token("!", "s")];
+        let painted = painter.paint(&tokens);
+        let (s, adjective, noun) = (color_of("s"), color_of("adjective"), color_of("noun"));
+        assert_eq!(painted, format!("\x1b[{s}mthe \x1b[0m\x1b[{adjective}mgreen \x1b[0m\x1b[{noun}mideas\x1b[0m\x1b[{s}m!\x1b[0m"));
+        assert_eq!(painter.legend(), format!("\x1b[{adjective}madjective\x1b[0m  \x1b[{noun}mnoun\x1b[0m  \x1b[{s}ms\x1b[0m"));
+    }
+}
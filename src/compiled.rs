@@ -0,0 +1,504 @@
+/*
+    This module writes and reads compiled grammars, a binary form of a parsed
+    and verified grammar which loads much faster than the text it came from.
+
+    A compiled grammar is, with every number little endian:
+
+        magic        8 bytes, MAGIC
+        format       u32, FORMAT
+        version      the blabber version which compiled it, as a u32 length
+                     and UTF-8
+        strings      u32 count, then the u32 offset each string ends at, then
+                     all of them as one run of UTF-8
+        names        u32 count, then the index of each string naming a rule
+                     or referred to by a nonterminal, in order, and its u32
+                     hash
+        sources      u32 count, then each source as a u32 kind and its name,
+                     which is empty for kinds without one
+        dynamics     u32 count, then each dynamic symbol as a u8 tag and its
+                     parts
+        start        the start symbol
+        rules        u32 count, then each rule as u32s for its name's string,
+                     where its numbers start, its source, and its line
+        numbers      u32 count, then each rule's numbers as u32s: how many
+                     alternatives it has, where each ends among its symbols,
+                     and each symbol, with its kind in the top bits and its
+                     string or dynamic symbol in the rest
+        annotations  u32 count, then each annotated rule's index, and a u32
+                     count of its keys, each with a u32 count of values
+        weights      u32 count, then each weighted rule's index and a u32
+                     count of f64 weights
+        deprecated   u32 count, then each deprecated rule's index, message,
+                     source, and u32 line
+        checksum     u64 hash of everything before it
+
+    The start symbol, the names of sources, date formats, annotations and
+    deprecation messages are written as a u32 length and UTF-8.
+
+    The strings, names, rules and numbers are the tables the grammar keeps
+    its rules in, so loading checks them and takes them as they are, rather
+    than adding the rules one at a time, and finds names by the hashes
+    written with them rather than hashing them again. Nothing else is
+    checked again, since the grammar was verified before it was compiled
+    and the checksum catches damage. Rules are in the grammar's order, with
+    their numbers moved together, so compiling the same grammar always
+    gives the same bytes.
+*/
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::error_handling::{Location, SourceName};
+use crate::grammar::{Annotations, Deprecation, Dynamic, Grammar, Name, Rules, Tables};
+
+pub const MAGIC: [u8; 8] = *b"BLABBER\x1a";
+
+// The version of the format, which changes whenever the layout does
+pub const FORMAT: u32 = 5;
+
+// The extension compiled grammars are recognized by
+pub const EXTENSION: &str = "blb";
+
+#[derive(Debug, PartialEq)]
+pub enum CompiledErrorType {
+    // The file doesn't start with MAGIC
+    NotCompiled,
+    // The file was written in another version of the format, by the given
+    // version of blabber
+    UnsupportedFormat { format: u32, compiled_by: String },
+    // The file was damaged or cut short, and why it can't be read
+    Corrupted(&'static str),
+}
+
+impl Display for CompiledErrorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompiledErrorType::NotCompiled => write!(f, "Not a compiled grammar (compile one with `blabber compile`)"),
+            CompiledErrorType::UnsupportedFormat { format, compiled_by } => write!(f,
+                "Compiled by blabber {} in format {}, but this blabber reads format {} (compile the grammar again)", compiled_by, format, FORMAT),
+            CompiledErrorType::Corrupted(reason) => write!(f, "Corrupted compiled grammar: {} (compile the grammar again)", reason),
+        }
+    }
+}
+
+// Whether a path is for a compiled grammar, by its extension
+pub fn is_compiled(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == EXTENSION)
+}
+
+// FNV-1a taken 8 bytes at a time, with the last few bytes zero padded, in
+// four lanes of every fourth word so their multiplications overlap, which
+// are then mixed into one. Each step mixes in a word in a way that can be
+// undone, so any one damaged word always changes it, but it doesn't protect
+// against tampering.
+fn checksum(bytes: &[u8]) -> u64 {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    let mix = |hash: u64, word: u64| (hash ^ word).wrapping_mul(0x100000001b3);
+    let mut lanes = [OFFSET; 4];
+    let blocks = bytes.chunks_exact(32);
+    let rest = blocks.remainder();
+    for block in blocks {
+        for (lane, word) in lanes.iter_mut().zip(block.chunks_exact(8)) {
+            *lane = mix(*lane, u64::from_le_bytes(word.try_into().unwrap()));
+        }
+    }
+    for (lane, chunk) in lanes.iter_mut().zip(rest.chunks(8)) {
+        let mut word = [0; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        *lane = mix(*lane, u64::from_le_bytes(word));
+    }
+    return lanes.into_iter().fold(OFFSET, mix);
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: usize) {
+        let value = u32::try_from(value).expect("compiled grammars have fewer than 2^32 of everything");
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn text(&mut self, text: &str) {
+        self.u32(text.len());
+        self.bytes.extend_from_slice(text.as_bytes());
+    }
+
+    // A source as its kind and its name, which is empty if it has none
+    fn source(&mut self, source: &SourceName) {
+        match source {
+            SourceName::File(path) => {
+                self.u32(0);
+                self.text(&path.to_string_lossy());
+            }
+            SourceName::Cli(option) => {
+                self.u32(1);
+                self.text(option);
+            }
+            SourceName::Stdin => {
+                self.u32(2);
+                self.text("");
+            }
+            SourceName::Builtin => {
+                self.u32(3);
+                self.text("");
+            }
+        }
+    }
+
+    // A counted table of u32s
+    fn u32s(&mut self, values: &[u32]) {
+        self.u32(values.len());
+        for value in values {
+            self.bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    // A counted table of rows of N u32s each
+    fn rows<const N: usize>(&mut self, rows: &[[u32; N]]) {
+        self.u32(rows.len());
+        for value in rows.as_flattened() {
+            self.bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+pub fn to_bytes(grammar: &Grammar) -> Vec<u8> {
+    let Tables { text, ends, names, rules, codes, dynamics, sources } = grammar.rules.tables();
+    let mut writer = Writer { bytes: Vec::new() };
+    writer.bytes.extend_from_slice(&MAGIC);
+    writer.bytes.extend_from_slice(&FORMAT.to_le_bytes());
+    writer.text(crate::snapshot::VERSION);
+
+    writer.u32s(&ends);
+    writer.bytes.extend_from_slice(text.as_bytes());
+    writer.rows(&names);
+
+    writer.u32(sources.len());
+    for source in &sources {
+        writer.source(source);
+    }
+
+    writer.u32(dynamics.len());
+    for dynamic in &dynamics {
+        match dynamic {
+            Dynamic::Date { format, first, last } => {
+                writer.u8(0);
+                writer.text(format);
+                writer.i64(*first);
+                writer.i64(*last);
+            }
+            Dynamic::Float { min, max, places } => {
+                writer.u8(1);
+                writer.i64(*min);
+                writer.i64(*max);
+                writer.u32(*places as usize);
+            }
+            Dynamic::Uuid => writer.u8(2),
+        }
+    }
+
+    writer.text(&grammar.start_symbol);
+    writer.rows(&rules);
+    writer.u32s(&codes);
+
+    let annotated = grammar.rules.keys()
+        .enumerate()
+        .filter_map(|(index, rule)| Some((index, grammar.annotations.get(rule)?)))
+        .collect::<Vec<_>>();
+    writer.u32(annotated.len());
+    for (index, annotations) in annotated {
+        writer.u32(index);
+        writer.u32(annotations.len());
+        for (key, values) in annotations {
+            writer.text(key);
+            writer.u32(values.len());
+            for value in values {
+                writer.text(value);
+            }
+        }
+    }
+
+    let weighted = grammar.rules.keys()
+        .enumerate()
+        .filter_map(|(index, rule)| Some((index, grammar.weights.get(rule)?)))
+        .collect::<Vec<_>>();
+    writer.u32(weighted.len());
+    for (index, weights) in weighted {
+        writer.u32(index);
+        writer.u32(weights.len());
+        for weight in weights.iter() {
+            writer.f64(*weight);
+        }
+    }
+
+    let deprecated = grammar.rules.keys()
+        .enumerate()
+        .filter_map(|(index, rule)| Some((index, grammar.deprecations.get(rule)?)))
+        .collect::<Vec<_>>();
+    writer.u32(deprecated.len());
+    for (index, deprecation) in deprecated {
+        writer.u32(index);
+        writer.text(&deprecation.message);
+        writer.source(&deprecation.location.source);
+        writer.u32(deprecation.location.line.unwrap_or(0));
+    }
+
+    let sum = checksum(&writer.bytes);
+    writer.bytes.extend_from_slice(&sum.to_le_bytes());
+    return writer.bytes;
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+type ReadResult<T> = Result<T, CompiledErrorType>;
+
+const TRUNCATED: CompiledErrorType = CompiledErrorType::Corrupted("it ends too early");
+const OUT_OF_RANGE: CompiledErrorType = CompiledErrorType::Corrupted("an index is out of range");
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> ReadResult<&'a [u8]> {
+        let end = self.position.checked_add(len).filter(|end| *end <= self.bytes.len()).ok_or(TRUNCATED)?;
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        return Ok(taken);
+    }
+
+    fn u8(&mut self) -> ReadResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> ReadResult<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> ReadResult<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> ReadResult<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // A count of things which each take at least min_size bytes, checked
+    // against what's left so a damaged count can't allocate too much
+    fn count(&mut self, min_size: usize) -> ReadResult<usize> {
+        let count = self.u32()? as usize;
+        if count.saturating_mul(min_size) > self.bytes.len() - self.position {
+            return Err(TRUNCATED);
+        }
+        return Ok(count);
+    }
+
+    // A counted table of u32s
+    fn u32s(&mut self) -> ReadResult<Vec<u32>> {
+        let count = self.count(4)?;
+        return Ok(self.take(4 * count)?
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect());
+    }
+
+    // A counted table of rows of N u32s each
+    fn rows<const N: usize>(&mut self) -> ReadResult<Vec<[u32; N]>> {
+        let count = self.count(4 * N)?;
+        return Ok(self.take(4 * N * count)?
+            .chunks_exact(4 * N)
+            .map(|row| std::array::from_fn(|index| u32::from_le_bytes(row[4 * index..4 * index + 4].try_into().unwrap())))
+            .collect());
+    }
+
+    fn text(&mut self) -> ReadResult<&'a str> {
+        let len = self.u32()? as usize;
+        std::str::from_utf8(self.take(len)?).map_err(|_| CompiledErrorType::Corrupted("a string isn't UTF-8"))
+    }
+
+    fn source(&mut self) -> ReadResult<SourceName> {
+        let kind = self.u32()?;
+        let name = self.text()?;
+        return match kind {
+            0 => Ok(SourceName::file(name)),
+            1 => Ok(SourceName::Cli(name.to_string())),
+            2 => Ok(SourceName::Stdin),
+            3 => Ok(SourceName::Builtin),
+            _ => Err(CompiledErrorType::Corrupted("a location has an unknown kind of source")),
+        };
+    }
+}
+
+// Reads a grammar written by to_bytes. It isn't verified again, since it was
+// verified before it was compiled.
+pub fn from_bytes(bytes: &[u8]) -> Result<Grammar, CompiledErrorType> {
+    if !bytes.starts_with(&MAGIC) {
+        return Err(CompiledErrorType::NotCompiled);
+    }
+    // The header is read before the checksum is checked, so grammars from
+    // other versions get a helpful error even if the checksum changed too
+    let mut reader = Reader { bytes, position: MAGIC.len() };
+    let format = reader.u32()?;
+    let compiled_by = reader.text()?;
+    if format != FORMAT {
+        return Err(CompiledErrorType::UnsupportedFormat { format, compiled_by: compiled_by.to_string() });
+    }
+    let Some(body_len) = bytes.len().checked_sub(8) else {
+        return Err(TRUNCATED);
+    };
+    let (body, sum) = bytes.split_at(body_len);
+    if checksum(body) != u64::from_le_bytes(sum.try_into().unwrap()) {
+        return Err(CompiledErrorType::Corrupted("its checksum doesn't match"));
+    }
+    reader.bytes = body;
+
+    // The strings are checked to be UTF-8 all at once
+    let ends = reader.u32s()?;
+    let text_len = ends.last().map_or(0, |end| *end as usize);
+    let text = std::str::from_utf8(reader.take(text_len)?).map_err(|_| CompiledErrorType::Corrupted("a string isn't UTF-8"))?;
+    let names = reader.rows()?;
+
+    let sources = (0..reader.count(8)?).map(|_| reader.source()).collect::<ReadResult<Vec<_>>>()?;
+
+    let dynamic_count = reader.count(1)?;
+    let mut dynamics = Vec::with_capacity(dynamic_count);
+    for _ in 0..dynamic_count {
+        let dynamic = match reader.u8()? {
+            0 => Dynamic::Date { format: reader.text()?.to_string(), first: reader.i64()?, last: reader.i64()? },
+            1 => Dynamic::Float { min: reader.i64()?, max: reader.i64()?, places: reader.u32()? },
+            2 => Dynamic::Uuid,
+            _ => return Err(CompiledErrorType::Corrupted("a dynamic symbol has an unknown kind")),
+        };
+        if !dynamic.is_valid() {
+            return Err(CompiledErrorType::Corrupted("a dynamic symbol can't make any values"));
+        }
+        dynamics.push(dynamic);
+    }
+
+    let start_symbol = reader.text()?.to_string();
+    let rules = reader.rows()?;
+    let codes = reader.u32s()?;
+    let rules = Rules::from_tables(Tables { text: text.to_string(), ends, names, rules, codes, dynamics, sources })
+        .map_err(CompiledErrorType::Corrupted)?;
+    let name = |index: u32| rules.get_index(index as usize).map(|(name, _)| Name::from(name)).ok_or(OUT_OF_RANGE);
+
+    let annotated_count = reader.count(8)?;
+    let mut annotations = HashMap::with_capacity(annotated_count);
+    for _ in 0..annotated_count {
+        let rule = name(reader.u32()?)?;
+        let mut rule_annotations = Annotations::new();
+        for _ in 0..reader.count(8)? {
+            let key = reader.text()?.to_string();
+            let values = (0..reader.count(4)?)
+                .map(|_| Ok(reader.text()?.to_string()))
+                .collect::<ReadResult<Vec<String>>>()?;
+            rule_annotations.insert(key, values);
+        }
+        annotations.insert(rule, rule_annotations);
+    }
+
+    let weighted_count = reader.count(8)?;
+    let mut weights = HashMap::with_capacity(weighted_count);
+    for _ in 0..weighted_count {
+        let rule = name(reader.u32()?)?;
+        let rule_weights = (0..reader.count(8)?)
+            .map(|_| reader.f64())
+            .collect::<ReadResult<Vec<f64>>>()?;
+        let valid = rule_weights.iter().all(|weight| weight.is_finite() && *weight >= 0.0)
+            && rule_weights.iter().any(|weight| *weight > 0.0);
+        if rules.get(&rule).map(|rewrite| rewrite.len()) != Some(rule_weights.len()) || !valid {
+            return Err(CompiledErrorType::Corrupted("a rule's weights don't fit it"));
+        }
+        weights.insert(rule, rule_weights.into());
+    }
+
+    let deprecated_count = reader.count(16)?;
+    let mut deprecations = HashMap::with_capacity(deprecated_count);
+    for _ in 0..deprecated_count {
+        let rule = name(reader.u32()?)?;
+        let message = reader.text()?.to_string();
+        let source = reader.source()?;
+        let location = match reader.u32()? {
+            0 => Location::whole(source),
+            line => Location::line(source, line as usize),
+        };
+        deprecations.insert(rule, Deprecation { message, location });
+    }
+
+    if reader.position != body.len() {
+        return Err(CompiledErrorType::Corrupted("there's more after the grammar"));
+    }
+    return Ok(Grammar {
+        start_symbol,
+        rules,
+        annotations,
+        weights,
+        deprecations
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::parser::{parse_file, parse_str};
+
+    #[test]
+    fn compile_and_load() {
+        let grammar = parse_file(&PathBuf::from("example_data/english.bnf")).unwrap();
+        let bytes = to_bytes(&grammar);
+        assert_eq!(from_bytes(&bytes).unwrap(), grammar);
+        assert_eq!(to_bytes(&grammar), bytes);
+
+        let text = ";@ tag greeting formal\n;@ weight 2\ns = t:upper \"\" t:title | t:lower\nt = \"hé\\n\"\n%deprecated t \"use s\"";
+        let grammar = parse_str(text, SourceName::Cli("arg".to_string())).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&grammar)).unwrap(), grammar);
+
+        let text = "%use dynamics\ns = @date(\"%a %e %b\", 1969-12-31, 2038-01-19) | @float(-0.5, 2, 1) t:upper\nt = @uuid";
+        let grammar = parse_str(text, SourceName::Builtin).unwrap();
+        assert_eq!(from_bytes(&to_bytes(&grammar)).unwrap(), grammar);
+
+        let mut grammar = parse_str("s = \"a\" | \"b\"\nt = s", SourceName::Builtin).unwrap();
+        grammar.weights.insert("s".into(), vec![0.25, 0.0].into());
+        assert_eq!(from_bytes(&to_bytes(&grammar)).unwrap(), grammar);
+    }
+
+    #[test]
+    fn refuse_corrupted_files() {
+        let grammar = parse_file(&PathBuf::from("example_data/english.bnf")).unwrap();
+        let bytes = to_bytes(&grammar);
+
+        assert_eq!(from_bytes(b"sentence = \"hi\"\n"), Err(CompiledErrorType::NotCompiled));
+        assert_eq!(from_bytes(&MAGIC), Err(TRUNCATED));
+        assert_eq!(from_bytes(&bytes[..bytes.len() - 1]), Err(CompiledErrorType::Corrupted("its checksum doesn't match")));
+        // Every single byte changed after the header is noticed
+        let header = MAGIC.len() + 8 + crate::snapshot::VERSION.len();
+        for position in header..bytes.len() {
+            let mut damaged = bytes.clone();
+            damaged[position] ^= 0x20;
+            assert!(matches!(from_bytes(&damaged), Err(CompiledErrorType::Corrupted(_))), "byte {}", position);
+        }
+
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&(FORMAT + 1).to_le_bytes());
+        assert_eq!(from_bytes(&newer), Err(CompiledErrorType::UnsupportedFormat {
+            format: FORMAT + 1,
+            compiled_by: crate::snapshot::VERSION.to_string()
+        }));
+    }
+}
@@ -0,0 +1,279 @@
+/*
+    Reads defaults for command line options from blabber.toml.
+
+    The config file is found next to the grammar file or in a directory above
+    it, or given with --config. Its keys are the long names of options, like
+    `seed = 42` or `max-depth = 10`, and tables named after subcommands, like
+    `[histogram]`, hold defaults for those. Options with NAME=VALUE values,
+    like --param, take a table. `include = "team.toml"` reads another config
+    first, so the including file's settings win. Relative paths are relative
+    to the file they're written in.
+
+    Settings are turned back into arguments and parsed after the command
+    line's, skipping any option the command line gave explicitly or that
+    conflicts with one it gave, so explicit flags always win.
+*/
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use blabber::error_handling;
+use clap::builder::ValueHint;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command, CommandFactory, FromArgMatches};
+
+use crate::cli::Cli;
+
+const FILE_NAME: &str = "blabber.toml";
+// How deep includes can go, which stops include cycles
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// A value from a config file, and the file it came from
+#[derive(Debug, Clone)]
+struct Setting {
+    value: toml::Value,
+    file: PathBuf,
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    // Every setting, with tables flattened into dotted keys like
+    // `histogram.amount` or `param.company`
+    settings: BTreeMap<String, Setting>,
+}
+
+fn invalid(path: &Path, message: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message))
+}
+
+impl Config {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut config = Config::default();
+        config.load_into(path, 0)?;
+        return Ok(config);
+    }
+
+    fn load_into(&mut self, path: &Path, depth: usize) -> io::Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(invalid(path, "includes are nested too deeply, which usually means they form a cycle"));
+        }
+        let text = std::fs::read_to_string(path).map_err(|error| invalid(path, error))?;
+        let table: toml::Table = text.parse().map_err(|error| invalid(path, error))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        // Included files are read first, so this file's settings replace theirs
+        let includes = match table.get("include") {
+            None => Vec::new(),
+            Some(toml::Value::String(include)) => vec![include.as_str()],
+            Some(toml::Value::Array(includes)) => includes.iter()
+                .map(|include| include.as_str().ok_or_else(|| invalid(path, "`include` must be a path or a list of paths")))
+                .collect::<io::Result<_>>()?,
+            Some(_) => return Err(invalid(path, "`include` must be a path or a list of paths")),
+        };
+        for include in includes {
+            self.load_into(&dir.join(include), depth + 1)?;
+        }
+
+        self.insert_table("", table.into_iter().filter(|(key, _)| key != "include"), path);
+        return Ok(());
+    }
+
+    fn insert_table(&mut self, prefix: &str, table: impl Iterator<Item = (String, toml::Value)>, file: &Path) {
+        for (key, value) in table {
+            let key = format!("{}{}", prefix, key);
+            match value {
+                toml::Value::Table(table) => self.insert_table(&format!("{}.", key), table.into_iter(), file),
+                value => {
+                    self.settings.insert(key, Setting { value, file: file.to_path_buf() });
+                }
+            }
+        }
+    }
+
+    // Parses argv with the settings as defaults, warning about any setting
+    // that isn't an option
+    pub fn apply(&self, argv: &[OsString]) -> Result<Cli, clap::Error> {
+        let root = Cli::command();
+        let matches = root.clone().try_get_matches_from(argv)?;
+        let active = matches.subcommand().map(|(name, _)| name);
+
+        let mut extra = Vec::new();
+        for (key, setting) in &self.settings {
+            // Keys starting with a subcommand's name are for that subcommand
+            let (section, key) = match key.split_once('.') {
+                Some((name, rest)) if root.find_subcommand(name).is_some() => (Some(name), rest),
+                _ => (None, key.as_str()),
+            };
+            let (long, field) = match key.split_once('.') {
+                Some((long, field)) => (long, Some(field)),
+                None => (key, None),
+            };
+            let mut command = match section {
+                Some(name) => root.find_subcommand(name).expect("it was just found").clone(),
+                None => root.clone(),
+            };
+            command.build();
+            let Some(arg) = command.get_arguments().find(|arg| arg.get_long() == Some(long) && !arg.is_global_set()) else {
+                error_handling::warn(format!("{}: unknown option `{}`", setting.file.display(), key));
+                continue;
+            };
+            // Only the command being run gets its settings
+            let command_matches = match (section, active) {
+                (None, None) => &matches,
+                (Some(name), Some(active)) if name == active => matches.subcommand_matches(name).expect("it's the active subcommand"),
+                _ => continue,
+            };
+            if given(&command, command_matches, arg) {
+                continue;
+            }
+            match setting_args(arg, field, setting) {
+                Ok(args) => extra.extend(args),
+                Err(problem) => error_handling::warn(format!("{}: `{}` {}", setting.file.display(), key, problem)),
+            }
+        }
+
+        let argv: Vec<OsString> = argv.iter().cloned().chain(extra).collect();
+        return Cli::from_arg_matches(&root.try_get_matches_from(argv)?);
+    }
+}
+
+// Whether the command line gave the option, or one that conflicts with it
+fn given(command: &Command, matches: &ArgMatches, arg: &Arg) -> bool {
+    let explicit = |arg: &Arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine);
+    explicit(arg) || command.get_arg_conflicts_with(arg).into_iter().any(explicit)
+}
+
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(text) => Some(text.clone()),
+        toml::Value::Integer(number) => Some(number.to_string()),
+        toml::Value::Float(number) => Some(number.to_string()),
+        toml::Value::Boolean(flag) => Some(flag.to_string()),
+        _ => None
+    }
+}
+
+// The arguments a setting stands for, like `--seed=42`, or why it can't be
+// used. Field is the name in a table of NAME=VALUE values.
+fn setting_args(arg: &Arg, field: Option<&str>, setting: &Setting) -> Result<Vec<OsString>, &'static str> {
+    let long = format!("--{}", arg.get_long().expect("settings are only matched to long options"));
+    if !arg.get_action().takes_values() {
+        return match (&setting.value, field, arg.get_action()) {
+            (toml::Value::Boolean(true), None, ArgAction::SetTrue) => Ok(vec![long.into()]),
+            (toml::Value::Boolean(false), None, ArgAction::SetTrue) => Ok(Vec::new()),
+            _ => Err("should be true or false"),
+        };
+    }
+
+    let values = match &setting.value {
+        toml::Value::Array(values) => values.iter().map(scalar).collect::<Option<Vec<String>>>(),
+        value => scalar(value).map(|value| vec![value]),
+    }.ok_or("should be a string, number, or list of them")?;
+    let is_path = matches!(arg.get_value_hint(), ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath);
+    let dir = setting.file.parent().unwrap_or(Path::new(""));
+
+    return Ok(values.into_iter()
+        .map(|value| {
+            let value = if is_path { dir.join(value).into_os_string() } else { value.into() };
+            let mut argument = OsString::from(format!("{}=", long));
+            if let Some(field) = field {
+                argument.push(format!("{}=", field));
+            }
+            argument.push(value);
+            argument
+        })
+        .collect());
+}
+
+// Looks for blabber.toml in the grammar file's directory, then in each
+// directory above it. The grammar file `-` is stdin, so the search starts
+// from the current directory.
+pub fn discover(grammar_file: &Path) -> Option<PathBuf> {
+    let dir = match grammar_file.parent() {
+        Some(dir) if grammar_file.as_os_str() != "-" && !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let dir = std::fs::canonicalize(&dir).unwrap_or(dir);
+    dir.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+// Parses the command line, with defaults from the config file given by
+// --config or found next to the grammar
+pub fn parse_args() -> io::Result<Cli> {
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let matches = Cli::command().get_matches_from(&argv);
+
+    let explicit = matches.get_one::<PathBuf>("config").cloned();
+    let grammar_file = match matches.subcommand() {
+        Some((_, sub_matches)) => sub_matches.try_get_one::<PathBuf>("file").ok().flatten(),
+        None => matches.try_get_one::<PathBuf>("file").ok().flatten(),
+    };
+    // A grammar given with --grammar has no file, so the config is looked for
+    // from the current directory
+    let path = explicit.or_else(|| discover(grammar_file.map_or(Path::new("-"), |file| file.as_path())));
+
+    let config = match path {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+    return Ok(config.apply(&argv).unwrap_or_else(|error| error.exit()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("blabber-config-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        return dir;
+    }
+
+    fn argv(args: &[&str]) -> Vec<OsString> {
+        std::iter::once("blabber").chain(args.iter().copied()).map(OsString::from).collect()
+    }
+
+    #[test]
+    fn command_line_wins() {
+        let dir = temp_dir("precedence");
+        std::fs::write(dir.join(FILE_NAME), "seed = 5\namount = 3\nstart-any = [\"a\", \"b\"]\nparam = { company = \"Acme\" }\n\n[histogram]\ntop = 4\n").unwrap();
+        let config = Config::load(&dir.join(FILE_NAME)).unwrap();
+
+        let args = config.apply(&argv(&["g.bnf", "--seed", "7", "--start", "s"])).unwrap().generate.unwrap();
+        // The command line wins, then the config, then the built in default
+        assert_eq!(args.seed, Some(7));
+        assert_eq!(args.amount, Some(3));
+        assert_eq!(args.max_depth, None);
+        // --start conflicts with --start-any, so the config's is left out
+        assert_eq!(args.start_any, None);
+        assert_eq!(args.param, vec![("company".to_string(), "Acme".to_string())]);
+
+        match config.apply(&argv(&["histogram", "g.bnf"])).unwrap().command {
+            Some(crate::cli::Command::Histogram(args)) => assert_eq!(args.top, Some(4)),
+            _ => panic!("expected the histogram subcommand"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn relative_includes() {
+        let dir = temp_dir("include");
+        std::fs::create_dir(dir.join("shared")).unwrap();
+        std::fs::write(dir.join(FILE_NAME), "include = \"shared/team.toml\"\namount = 2\n").unwrap();
+        std::fs::write(dir.join("shared/team.toml"), "amount = 9\nseed = 1\nchoices-out = \"traces.jsonl\"\n").unwrap();
+        std::fs::write(dir.join("g.bnf"), "s = \"x\"\n").unwrap();
+
+        let found = discover(&dir.join("g.bnf")).unwrap();
+        let args = Config::load(&found).unwrap().apply(&argv(&["g.bnf"])).unwrap().generate.unwrap();
+        // The including file wins, and paths are relative to the file they're in
+        assert_eq!(args.amount, Some(2));
+        assert_eq!(args.seed, Some(1));
+        assert_eq!(args.choices_out, Some(found.parent().unwrap().join("shared/traces.jsonl")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+/*
+    This module writes generated sentences to a directory, one file each, as
+    a seed corpus for fuzzers
+*/
+
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::generator::fnv1a;
+
+// How the files in a corpus are named
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameBy {
+    // The sentence's position in the output, padded to the same width
+    Index,
+    // A hash of the sentence, so identical sentences share a file. It's
+    // FNV-1a, which unlike std's hashers gives the same name in every release
+    Hash,
+}
+
+pub struct Corpus {
+    dir: PathBuf,
+    name_by: NameBy,
+    // Digits in the largest index, so names sort in order
+    index_width: usize,
+    no_clobber: bool,
+    // Hashes of the sentences already written this run, with NameBy::Hash
+    hashes: HashSet<u64>,
+    pub written: u64,
+    pub bytes: u64,
+    // Files which already existed with no_clobber
+    pub skipped: u64,
+    // Sentences which repeated one already written this run, with
+    // NameBy::Hash, so went to the same file
+    pub merged: u64,
+    pub failed: u64,
+}
+
+// A write that failed, with the file it was for
+#[derive(Debug)]
+pub struct CorpusError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl Display for CorpusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Could not write {}: {}", self.path.display(), self.error)
+    }
+}
+
+impl Corpus {
+    // Creates the directory, if needed, for a corpus of up to amount files
+    pub fn create(dir: &Path, name_by: NameBy, amount: u32, no_clobber: bool) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Corpus {
+            dir: dir.to_path_buf(),
+            name_by,
+            index_width: amount.saturating_sub(1).to_string().len(),
+            no_clobber,
+            hashes: HashSet::new(),
+            written: 0,
+            bytes: 0,
+            skipped: 0,
+            merged: 0,
+            failed: 0,
+        })
+    }
+
+    pub fn path(&self, index: u32, sentence: &str) -> PathBuf {
+        let name = match self.name_by {
+            NameBy::Index => format!("{:0width$}", index, width = self.index_width),
+            NameBy::Hash => format!("{:016x}", fnv1a(sentence.bytes())),
+        };
+        self.dir.join(name)
+    }
+
+    // Writes the sentence at index to its own file, counting the result
+    pub fn write(&mut self, index: u32, sentence: &str) -> Result<(), CorpusError> {
+        if self.name_by == NameBy::Hash && !self.hashes.insert(fnv1a(sentence.bytes())) {
+            self.merged += 1;
+            return Ok(());
+        }
+        let path = self.path(index, sentence);
+        let result = if self.no_clobber {
+            fs::OpenOptions::new().write(true).create_new(true).open(&path)
+        } else {
+            fs::File::create(&path)
+        }.and_then(|mut file| file.write_all(sentence.as_bytes()));
+
+        match result {
+            Ok(()) => {
+                self.written += 1;
+                self.bytes += sentence.len() as u64;
+                Ok(())
+            }
+            Err(error) if self.no_clobber && error.kind() == io::ErrorKind::AlreadyExists => {
+                self.skipped += 1;
+                Ok(())
+            }
+            Err(error) => {
+                self.failed += 1;
+                Err(CorpusError { path, error })
+            }
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let files = if self.written == 1 { "file" } else { "files" };
+        let mut summary = format!("Wrote {} {} ({} bytes) to {}", self.written, files, self.bytes, self.dir.display());
+        if self.skipped > 0 {
+            summary += &format!(", skipped {} that already existed", self.skipped);
+        }
+        if self.merged > 0 {
+            let sentences = if self.merged == 1 { "sentence" } else { "sentences" };
+            summary += &format!(", merged {} duplicate {}", self.merged, sentences);
+        }
+        if self.failed > 0 {
+            summary += &format!(", failed to write {}", self.failed);
+        }
+        return summary;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::generator::generate_from;
+    use crate::parser::parse_str;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("blabber-corpus-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        return dir;
+    }
+
+    #[test]
+    fn write_files_by_index() {
+        let dir = temp_dir("index");
+        let mut corpus = Corpus::create(&dir.join("nested"), NameBy::Index, 100, false).unwrap();
+        corpus.write(7, "seven").unwrap();
+        corpus.write(42, "forty two").unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("nested/07")).unwrap(), "seven");
+        assert_eq!(fs::read_to_string(dir.join("nested/42")).unwrap(), "forty two");
+        assert_eq!((corpus.written, corpus.bytes), (2, 14));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn skip_existing_files() {
+        let dir = temp_dir("clobber");
+        Corpus::create(&dir, NameBy::Hash, 10, true).unwrap().write(0, "same").unwrap();
+        let mut corpus = Corpus::create(&dir, NameBy::Hash, 10, true).unwrap();
+        corpus.write(0, "same").unwrap();
+        corpus.write(1, "different").unwrap();
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        assert_eq!(corpus.path(0, "same"), dir.join(format!("{:016x}", fnv1a("same".bytes()))));
+        assert_eq!(corpus.summary(), format!("Wrote 1 file (9 bytes) to {}, skipped 1 that already existed", dir.display()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn merge_repeated_sentences() {
+        let dir = temp_dir("merge");
+        let grammar = parse_str(r#"s = "a" | "b""#, SourceName::Builtin).unwrap();
+        let mut corpus = Corpus::create(&dir, NameBy::Hash, 6, false).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        for index in 0..6 {
+            corpus.write(index, &generate_from(&grammar, "s", &mut rng).unwrap()).unwrap();
+        }
+
+        assert_eq!(corpus.written, fs::read_dir(&dir).unwrap().count() as u64);
+        assert_eq!(corpus.written + corpus.merged, 6);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn report_failed_writes() {
+        let dir = temp_dir("fail");
+        let mut corpus = Corpus::create(&dir, NameBy::Index, 1, false).unwrap();
+        // A directory where the file should go can't be written over
+        fs::create_dir(dir.join("0")).unwrap();
+
+        let error = corpus.write(0, "blocked").unwrap_err();
+        assert_eq!(error.path, dir.join("0"));
+        assert_eq!(corpus.failed, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+/*
+    This module measures how much of a corpus of real sentences a grammar
+    accounts for, for finding where a grammar and the text it imitates
+    differ.
+
+    Each line of the corpus is run through the recognizer. The lines the
+    grammar can generate are counted by which alternatives one derivation of
+    them uses, so alternatives nothing in the corpus needs stand out, and the
+    lines it can't generate can be listed with how far into them it gets.
+*/
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use itertools::Itertools;
+
+use crate::analysis::reachable_rules;
+use crate::analysis::recognize::{derivation, viable_prefix};
+use crate::grammar::{definition_order, Grammar, Symbol};
+
+// A line of the corpus the grammar can't generate
+#[derive(Debug, PartialEq)]
+pub struct Underivable {
+    // Counting from 1, including blank lines
+    pub line: usize,
+    pub text: String,
+    // The length in bytes of the longest start of the line some sentence
+    // starts with, if it was looked for
+    pub prefix: Option<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Coverage {
+    // The lines checked, which are every line that isn't blank
+    pub lines: usize,
+    pub derivable: usize,
+    // How many derivable lines used each alternative of each rule start can
+    // reach, with the rules in definition order. A line using an
+    // alternative more than once counts once.
+    pub uses: Vec<(String, Vec<u64>)>,
+    pub underivable: Vec<Underivable>,
+}
+
+impl Coverage {
+    // The rules and indices of the alternatives no derivable line used
+    pub fn unused(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.uses.iter().flat_map(|(rule, uses)| uses.iter()
+            .positions(|&count| count == 0)
+            .map(move |index| (rule.as_str(), index)))
+    }
+}
+
+// Checks every line of corpus that isn't blank against start. With
+// prefixes, the longest start of each underivable line a sentence starts
+// with is found too, which takes a few more passes over the line.
+pub fn cover(grammar: &Grammar, start: &str, corpus: &str, prefixes: bool) -> Coverage {
+    let reachable = reachable_rules(grammar, start);
+    let mut counts: HashMap<&str, Vec<u64>> = reachable.iter()
+        .map(|rule| (*rule, vec![0; grammar.rules.rewrite(rule).len()]))
+        .collect();
+    let mut coverage = Coverage { lines: 0, derivable: 0, uses: Vec::new(), underivable: Vec::new() };
+
+    for (index, line) in corpus.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        coverage.lines += 1;
+        match derivation(grammar, start, line) {
+            Some(used) => {
+                coverage.derivable += 1;
+                for (rule, alternative) in used.iter().unique() {
+                    counts.get_mut(rule.as_str()).expect("derivations only use reachable rules")[*alternative] += 1;
+                }
+            }
+            None => coverage.underivable.push(Underivable {
+                line: index + 1,
+                text: line.to_string(),
+                prefix: prefixes.then(|| viable_prefix(grammar, start, line)),
+            }),
+        }
+    }
+
+    coverage.uses = definition_order(grammar).into_iter()
+        .filter_map(|rule| counts.remove(rule.as_str()).map(|uses| (rule, uses)))
+        .collect();
+    return coverage;
+}
+
+// How much of the corpus was derivable, then each alternative with how many
+// lines used it, like `      12  noun:0  "ideas"`
+pub struct Report<'a> {
+    pub coverage: &'a Coverage,
+    pub grammar: &'a Grammar,
+}
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let coverage = self.coverage;
+        write!(f, "derivable: {} of {} lines", coverage.derivable, coverage.lines)?;
+        if coverage.lines > 0 {
+            write!(f, " ({:.1}%)", 100.0 * coverage.derivable as f64 / coverage.lines as f64)?;
+        }
+        writeln!(f)?;
+        for (rule, uses) in &coverage.uses {
+            for (index, (count, alternative)) in uses.iter().zip(self.grammar.rules.rewrite(rule.as_str())).enumerate() {
+                let written = if alternative.is_empty() { "\"\"".to_string() } else { alternative.iter().join(" ") };
+                writeln!(f, "{:>8}  {}:{}  {}", count, rule, index, written)?;
+            }
+        }
+        let alternatives: usize = coverage.uses.iter().map(|(_, uses)| uses.len()).sum();
+        let unused = coverage.unused().map(|(rule, index)| format!("{}:{}", rule, index)).collect_vec();
+        write!(f, "never used: {} of {} alternatives", unused.len(), alternatives)?;
+        if !unused.is_empty() {
+            write!(f, " ({})", unused.join(", "))?;
+        }
+        return writeln!(f);
+    }
+}
+
+// A line the grammar can't generate, like `line 3: "hello there"`, with how
+// much of it a sentence could start with if that was looked for
+impl Display for Underivable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, Symbol::Terminal(self.text.as_str().into()))?;
+        if let Some(prefix) = self.prefix {
+            write!(f, " (derivable up to {})", Symbol::Terminal(self.text[..prefix].to_string().into()))?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn cover_corpus() {
+        let grammar = parse_str(concat!(
+            "sentence = greeting \", \" name | greeting\n",
+            "greeting = \"hello\" | \"hi\" | \"good \" time\n",
+            "time = \"morning\" | \"evening\"\n",
+            "name = \"Jo\" | \"Sam\"\n",
+            "unused = \"never\"\n",
+        ), SourceName::Builtin).unwrap();
+        let corpus = "hello, Jo\nhi\n\ngood morning, Jo\nhey, Jo\ngood night\n";
+        let coverage = cover(&grammar, "sentence", corpus, true);
+        assert_eq!((coverage.lines, coverage.derivable), (5, 3));
+        assert_eq!(coverage.uses, vec![
+            ("sentence".to_string(), vec![2, 1]),
+            ("greeting".to_string(), vec![1, 1, 1]),
+            ("time".to_string(), vec![1, 0]),
+            ("name".to_string(), vec![2, 0]),
+        ]);
+        assert_eq!(coverage.unused().collect_vec(), [("time", 1), ("name", 1)]);
+        assert_eq!(coverage.underivable.iter().map(ToString::to_string).collect_vec(), [
+            "line 5: \"hey, Jo\" (derivable up to \"he\")",
+            "line 6: \"good night\" (derivable up to \"good \")",
+        ]);
+
+        assert_eq!(Report { coverage: &coverage, grammar: &grammar }.to_string(), concat!(
+            "derivable: 3 of 5 lines (60.0%)\n",
+            "       2  sentence:0  greeting \", \" name\n",
+            "       1  sentence:1  greeting\n",
+            "       1  greeting:0  \"hello\"\n",
+            "       1  greeting:1  \"hi\"\n",
+            "       1  greeting:2  \"good \" time\n",
+            "       1  time:0  \"morning\"\n",
+            "       0  time:1  \"evening\"\n",
+            "       2  name:0  \"Jo\"\n",
+            "       0  name:1  \"Sam\"\n",
+            "never used: 2 of 9 alternatives (time:1, name:1)\n",
+        ));
+        let empty = cover(&grammar, "sentence", "\n", false);
+        assert_eq!((empty.lines, empty.unused().count()), (0, 9));
+        assert!(Report { coverage: &empty, grammar: &grammar }.to_string().starts_with("derivable: 0 of 0 lines\n"));
+    }
+}
@@ -0,0 +1,89 @@
+/*
+    This module drops repeated sentences from a run's output as they stream
+    out, for when a batch should only hold distinct sentences but needn't
+    have exactly as many as were asked for.
+
+    Sentences are remembered whole, or as 128-bit hashes to bound memory.
+    Two different sentences sharing a hash would drop the second one, but
+    with 128 bits that won't happen in any run that fits in a lifetime.
+*/
+
+use std::collections::HashSet;
+
+// How seen sentences are remembered
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DedupBy {
+    // The whole text
+    Text,
+    // A 128-bit hash of the text
+    Hash,
+}
+
+impl DedupBy {
+    pub fn from_name(name: &str) -> Option<DedupBy> {
+        match name {
+            "text" => Some(DedupBy::Text),
+            "hash" => Some(DedupBy::Hash),
+            _ => None
+        }
+    }
+}
+
+// 128-bit FNV-1a, which is stable between runs and needs no dependency
+pub(crate) fn fnv1a_128(bytes: &[u8]) -> u128 {
+    let mut hash: u128 = 0x6c62272e07bb014262b821756295c58d;
+    for byte in bytes {
+        hash ^= *byte as u128;
+        hash = hash.wrapping_mul(0x0000000001000000000000000000013b);
+    }
+    return hash;
+}
+
+#[derive(Debug)]
+pub struct Dedup {
+    by: DedupBy,
+    texts: HashSet<String>,
+    hashes: HashSet<u128>,
+    // How many repeats were dropped
+    pub suppressed: u64,
+}
+
+impl Dedup {
+    pub fn new(by: DedupBy) -> Self {
+        Dedup { by, texts: HashSet::new(), hashes: HashSet::new(), suppressed: 0 }
+    }
+
+    // Whether the sentence hasn't been seen before, remembering it if not and
+    // counting it as suppressed if it has
+    pub fn first_time(&mut self, sentence: &str) -> bool {
+        let new = match self.by {
+            DedupBy::Text => !self.texts.contains(sentence) && self.texts.insert(sentence.to_string()),
+            DedupBy::Hash => self.hashes.insert(fnv1a_128(sentence.as_bytes())),
+        };
+        if !new {
+            self.suppressed += 1;
+        }
+        return new;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_repeats() {
+        for by in [DedupBy::Text, DedupBy::Hash] {
+            let mut dedup = Dedup::new(by);
+            let kept: Vec<&str> = ["a", "b", "a", "", "b", "", "c"].into_iter().filter(|sentence| dedup.first_time(sentence)).collect();
+            assert_eq!(kept, ["a", "b", "", "c"]);
+            assert_eq!(dedup.suppressed, 3);
+        }
+    }
+
+    #[test]
+    fn stable_hashes() {
+        assert_eq!(fnv1a_128(b""), 0x6c62272e07bb014262b821756295c58d);
+        assert_eq!(fnv1a_128(b"a"), 0xd228cb696f1a8caf78912b704e4a8964);
+    }
+}
@@ -0,0 +1,160 @@
+/*
+    This module checks whether two grammars generate the same sentences, for
+    reviewing refactors like left factoring or inlining rules.
+
+    When neither grammar is recursive and both have few enough derivations,
+    every sentence of each is enumerated and the sets are compared exactly.
+    Grammars with dynamic symbols make too many values to enumerate, so
+    they're always sampled. Otherwise sentences are sampled from each grammar and checked with the
+    recognizer against the other, which can find differences but never prove
+    there are none.
+*/
+
+use std::collections::BTreeSet;
+
+use rand::RngCore;
+
+use crate::analysis::derivations::DerivationCounts;
+use crate::analysis::reachable_rules;
+use crate::analysis::enumerate::{depth_bound, enumerate};
+use crate::analysis::recognize::derives;
+use crate::generator::{generate_from, GenerateError};
+use crate::grammar::{Grammar, SymbolRef};
+
+// Grammars with more derivations than this are sampled rather than
+// enumerated, to bound memory
+pub const ENUMERATION_LIMIT: u64 = 100_000;
+
+pub struct EquivalenceOptions {
+    // How many sentences to sample from each grammar
+    pub samples: u32,
+    // The deepest derivation enumerated. Deeper grammars are sampled.
+    pub max_depth: usize,
+    // How many sentences only one grammar generates to find before stopping
+    pub counterexamples: usize,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Method {
+    // Every sentence was compared, and there were this many altogether
+    Enumerated(usize),
+    // This many samples were taken from each grammar
+    Sampled(u32),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Comparison {
+    pub method: Method,
+    // Sentences the first grammar generates and the second doesn't
+    pub only_first: Vec<String>,
+    // Sentences the second grammar generates and the first doesn't
+    pub only_second: Vec<String>,
+}
+
+impl Comparison {
+    pub fn equivalent(&self) -> bool {
+        self.only_first.is_empty() && self.only_second.is_empty()
+    }
+}
+
+// Compares the sentences first generates from first_start with the ones
+// second generates from second_start
+pub fn compare(first: (&Grammar, &str), second: (&Grammar, &str), options: &EquivalenceOptions, rng: &mut impl RngCore) -> Result<Comparison, GenerateError> {
+    if let (Some(first_sentences), Some(second_sentences)) = (enumerable(first, options), enumerable(second, options)) {
+        let mut only_first: Vec<String> = first_sentences.difference(&second_sentences).cloned().collect();
+        let mut only_second: Vec<String> = second_sentences.difference(&first_sentences).cloned().collect();
+        only_first.truncate(options.counterexamples);
+        only_second.truncate(options.counterexamples);
+        return Ok(Comparison {
+            method: Method::Enumerated(first_sentences.union(&second_sentences).count()),
+            only_first,
+            only_second
+        });
+    }
+
+    let only_first = underivable(first, second, options, rng)?;
+    let only_second = underivable(second, first, options, rng)?;
+    return Ok(Comparison {
+        method: Method::Sampled(options.samples),
+        only_first,
+        only_second
+    });
+}
+
+// Every sentence of a grammar, if it isn't recursive, has no dynamic
+// symbols, its derivations are no deeper than the options allow, and there
+// aren't too many of them
+fn enumerable((grammar, start): (&Grammar, &str), options: &EquivalenceOptions) -> Option<BTreeSet<String>> {
+    let dynamic = reachable_rules(grammar, start).into_iter()
+        .flat_map(|rule| grammar.rules.rewrite(rule).iter().flatten())
+        .any(|symbol| matches!(symbol, SymbolRef::Dynamic(_)));
+    if dynamic {
+        return None;
+    }
+    let depth = depth_bound(grammar, start).filter(|&depth| depth <= options.max_depth)?;
+    if DerivationCounts::new(grammar, depth).count(start, depth) > ENUMERATION_LIMIT.into() {
+        return None;
+    }
+    return Some(enumerate(grammar, start, depth));
+}
+
+// Samples sentences from one grammar, returning those the other can't
+// derive. Each distinct sentence is only recognized once.
+fn underivable((grammar, start): (&Grammar, &str), (other, other_start): (&Grammar, &str), options: &EquivalenceOptions, rng: &mut impl RngCore) -> Result<Vec<String>, GenerateError> {
+    let mut sentences = BTreeSet::new();
+    for _ in 0..options.samples {
+        sentences.insert(generate_from(grammar, start, rng)?);
+    }
+    return Ok(sentences.into_iter()
+        .filter(|sentence| !derives(other, other_start, sentence))
+        .take(options.counterexamples)
+        .collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::generator::Xoshiro256StarStar;
+    use crate::parser::parse_str;
+
+    const OPTIONS: EquivalenceOptions = EquivalenceOptions {
+        samples: 200,
+        max_depth: 20,
+        counterexamples: 10
+    };
+
+    fn compare_str(first: &str, second: &str) -> Comparison {
+        let first = parse_str(first, SourceName::Builtin).unwrap();
+        let second = parse_str(second, SourceName::Builtin).unwrap();
+        let mut rng = Xoshiro256StarStar::new(0);
+        return compare((&first, &first.start_symbol), (&second, &second.start_symbol), &OPTIONS, &mut rng).unwrap();
+    }
+
+    #[test]
+    fn compare_finite_grammars_exactly() {
+        let comparison = compare_str(
+            "greeting = \"good \" \"morning\" | \"good \" \"evening\" | \"hi\"",
+            "greeting = \"good \" time | \"hi\"\ntime = \"morning\" | \"evening\""
+        );
+        assert_eq!(comparison, Comparison { method: Method::Enumerated(3), only_first: vec![], only_second: vec![] });
+
+        let comparison = compare_str("greeting = \"hi\" | \"hello\"", "greeting = \"hi\" | \"hey\"");
+        assert_eq!(comparison.method, Method::Enumerated(3));
+        assert_eq!(comparison.only_first, ["hello"]);
+        assert_eq!(comparison.only_second, ["hey"]);
+        assert!(!comparison.equivalent());
+    }
+
+    #[test]
+    fn sample_recursive_grammars() {
+        let comparison = compare_str("list = \"a\" | \"a\" \",\" list", "list = item | list \",\" item\nitem = \"a\"");
+        assert_eq!(comparison.method, Method::Sampled(200));
+        assert!(comparison.equivalent());
+
+        // A grammar forgetting the single item case
+        let comparison = compare_str("list = \"a\" | \"a\" \",\" list", "list = \"a\" \",\" \"a\" | \"a\" \",\" list");
+        assert_eq!(comparison.only_first, ["a"]);
+        assert!(comparison.only_second.is_empty());
+    }
+}
@@ -1,20 +1,66 @@
-use std::path::PathBuf;
+use std::path::Path;
 use std::fmt::Display;
+use std::sync::Arc;
 
 pub trait ErrorType: Display + PartialEq {}
 
+// Where some grammar text came from
+#[derive(Debug, PartialEq, Clone)]
+pub enum SourceName {
+    // Shared, since every rule's location has its source
+    File(Arc<Path>),
+    Stdin,
+    // Text given on the command line, described by the option it came from
+    Cli(String),
+    // Text built into blabber or constructed by a program
+    Builtin,
+}
+
+impl SourceName {
+    pub fn file(path: impl AsRef<Path>) -> Self {
+        SourceName::File(Arc::from(path.as_ref()))
+    }
+}
+
+impl Display for SourceName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceName::File(path) => write!(f, "{}", path.display()),
+            SourceName::Stdin => write!(f, "<stdin>"),
+            SourceName::Cli(option) => write!(f, "<{}>", option),
+            SourceName::Builtin => write!(f, "<builtin>"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Location {
-    pub file: PathBuf,
-    pub line: usize
+    pub source: SourceName,
+    // The line number starting from 1, or None if it's about the whole source
+    pub line: Option<usize>
+}
+
+impl Location {
+    pub fn line(source: SourceName, line: usize) -> Self {
+        Location {
+            source,
+            line: Some(line)
+        }
+    }
+
+    pub fn whole(source: SourceName) -> Self {
+        Location {
+            source,
+            line: None
+        }
+    }
 }
 
 impl Display for Location {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if self.line == 0 {
-            write!(f, "{}", self.file.display())
-        } else {
-            write!(f, "{}:{}", self.file.display(), self.line)
+        match self.line {
+            Some(line) => write!(f, "{}:{}", self.source, line),
+            None => write!(f, "{} (whole file)", self.source),
         }
     }
 }
@@ -31,4 +77,40 @@ impl<T: ErrorType> Display for Error<T> {
     }
 }
 
-pub type Errors<T> = Vec<Error<T>>;
\ No newline at end of file
+pub type Errors<T> = Vec<Error<T>>;
+
+// Something suspicious which doesn't stop blabber from working
+#[derive(Debug, PartialEq, Clone)]
+pub struct Warning<T: ErrorType> {
+    pub location: Location,
+    pub warning: T
+}
+
+impl<T: ErrorType> Display for Warning<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\x1b[33;49;1m[{}]\x1b[39;49;1m  {}\x1b[0m", self.location, self.warning)
+    }
+}
+
+pub type Warnings<T> = Vec<Warning<T>>;
+
+// Prints a message about something suspicious that doesn't stop blabber
+pub fn warn(message: impl Display) {
+    eprintln!("\x1b[33;49;1m[warning]\x1b[39;49;1m  {}\x1b[0m", message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_locations() {
+        let file = SourceName::file("grammars/english.bnf");
+        assert_eq!(Location::line(file.clone(), 4).to_string(), "grammars/english.bnf:4");
+        assert_eq!(Location::whole(file).to_string(), "grammars/english.bnf (whole file)");
+
+        assert_eq!(Location::line(SourceName::Stdin, 4).to_string(), "<stdin>:4");
+        assert_eq!(Location::line(SourceName::Cli("--define #2".to_string()), 1).to_string(), "<--define #2>:1");
+        assert_eq!(Location::whole(SourceName::Builtin).to_string(), "<builtin> (whole file)");
+    }
+}
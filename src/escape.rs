@@ -0,0 +1,130 @@
+/*
+    This module escapes generated sentences for where they're being sent, so
+    quotes, spaces, and other special characters in them come through intact
+*/
+
+use std::borrow::Cow;
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Escape {
+    // Sentences are output as they are
+    None,
+    // Each sentence is one POSIX shell word in single quotes
+    Shell,
+    // Each sentence is a double quoted C string literal
+    C,
+}
+
+// A sentence contains a nul byte, which neither a shell argument nor a C
+// string can hold
+#[derive(Debug, PartialEq)]
+pub struct NulError;
+
+impl Display for NulError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The sentence contains a nul byte, which can't be escaped")
+    }
+}
+
+impl std::error::Error for NulError {}
+
+impl Escape {
+    pub fn from_name(name: &str) -> Option<Escape> {
+        match name {
+            "none" => Some(Escape::None),
+            "shell" => Some(Escape::Shell),
+            "c" => Some(Escape::C),
+            _ => None
+        }
+    }
+
+    pub fn apply(self, text: &str) -> Result<Cow<'_, str>, NulError> {
+        if self != Escape::None && text.contains('\0') {
+            return Err(NulError);
+        }
+        return Ok(match self {
+            Escape::None => Cow::Borrowed(text),
+            // Nothing is special inside single quotes, so the only thing to
+            // escape is a single quote, by ending the quotes around it
+            Escape::Shell => Cow::Owned(format!("'{}'", text.replace('\'', "'\\''"))),
+            Escape::C => Cow::Owned(c_string(text)),
+        });
+    }
+}
+
+// Control characters are written in octal, since hex escapes in C take every
+// hex digit after them and would swallow following text
+fn c_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\{:03o}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    return escaped;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NASTY: [&str; 8] = [
+        "",
+        "plain words",
+        "it's",
+        "''",
+        "\"double\" and `back` quotes",
+        "$HOME; rm -rf * && echo $(whoami) | cat > /dev/null",
+        "back\\slash\\",
+        "two\nlines\r\n\ttabbed\u{7}\u{7f} ünïcødé",
+    ];
+
+    #[test]
+    fn escape_for_shells() {
+        let escaped = NASTY.map(|text| Escape::Shell.apply(text).unwrap().into_owned());
+        assert_eq!(escaped, [
+            "''",
+            "'plain words'",
+            "'it'\\''s'",
+            "''\\'''\\'''",
+            "'\"double\" and `back` quotes'",
+            "'$HOME; rm -rf * && echo $(whoami) | cat > /dev/null'",
+            "'back\\slash\\'",
+            "'two\nlines\r\n\ttabbed\u{7}\u{7f} ünïcødé'",
+        ]);
+    }
+
+    #[test]
+    fn escape_for_c() {
+        let escaped = NASTY.map(|text| Escape::C.apply(text).unwrap().into_owned());
+        assert_eq!(escaped, [
+            "\"\"",
+            "\"plain words\"",
+            "\"it's\"",
+            "\"''\"",
+            "\"\\\"double\\\" and `back` quotes\"",
+            "\"$HOME; rm -rf * && echo $(whoami) | cat > /dev/null\"",
+            "\"back\\\\slash\\\\\"",
+            "\"two\\nlines\\r\\n\\ttabbed\\007\\177 ünïcødé\"",
+        ]);
+    }
+
+    #[test]
+    fn refuse_nul_bytes() {
+        assert_eq!(Escape::None.apply("a\0b").unwrap(), "a\0b");
+        assert_eq!(Escape::Shell.apply("a\0b"), Err(NulError));
+        assert_eq!(Escape::C.apply("\0"), Err(NulError));
+        for text in NASTY {
+            assert_eq!(Escape::None.apply(text).unwrap(), text);
+        }
+    }
+}
@@ -0,0 +1,206 @@
+/*
+    A C interface, so programs in other languages can generate sentences.
+
+    Grammars are opaque pointers, and strings cross the boundary as UTF-8,
+    nul terminated C strings. Functions which can fail return null and leave
+    a message for blabber_last_error, which is kept per thread. Panics are
+    caught before they reach the caller and reported the same way. The header
+    for this is include/blabber.h, generated with cbindgen.
+*/
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, UnwindSafe};
+use std::path::PathBuf;
+use std::ptr;
+
+use crate::error_handling::SourceName;
+use crate::generator::{generate_from, Xoshiro256StarStar};
+use crate::grammar::Grammar;
+use crate::parser::{parse_file, parse_str};
+use crate::Error;
+
+/// A parsed grammar. Free it with blabber_grammar_free.
+pub struct BlabberGrammar(Grammar);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // Messages can't contain nul bytes in C, so they're cut off there
+    let message = CString::new(message).unwrap_or_else(|error| {
+        let end = error.nul_position();
+        CString::new(&error.into_vec()[..end]).expect("there's no nul before the first one")
+    });
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+// Runs body, turning its error or panic into a null pointer and the last
+// error for this thread
+fn catch<T>(body: impl FnOnce() -> Result<*mut T, String> + UnwindSafe) -> *mut T {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+    match catch_unwind(body) {
+        Ok(Ok(pointer)) => pointer,
+        Ok(Err(message)) => {
+            set_last_error(message);
+            ptr::null_mut()
+        }
+        Err(panic) => {
+            let reason = panic.downcast_ref::<&str>().map(|reason| reason.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown reason".to_string());
+            set_last_error(format!("blabber panicked: {}", reason));
+            ptr::null_mut()
+        }
+    }
+}
+
+// Reads a C string argument, which must be valid UTF-8
+unsafe fn read_str<'a>(text: *const c_char, argument: &str) -> Result<&'a str, String> {
+    if text.is_null() {
+        return Err(format!("`{}` is null", argument));
+    }
+    CStr::from_ptr(text).to_str().map_err(|_| format!("`{}` isn't valid UTF-8", argument))
+}
+
+fn boxed_grammar(grammar: Result<Grammar, Vec<crate::parser::CompileError>>) -> Result<*mut BlabberGrammar, String> {
+    match grammar {
+        Ok(grammar) => Ok(Box::into_raw(Box::new(BlabberGrammar(grammar)))),
+        Err(errors) => Err(Error::from(errors).to_string()),
+    }
+}
+
+/// Parses and verifies the grammar file at path. Returns null if it has
+/// errors, which blabber_last_error describes.
+///
+/// # Safety
+/// path must be null or a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn blabber_parse_file(path: *const c_char) -> *mut BlabberGrammar {
+    catch(|| {
+        let path = read_str(path, "path")?;
+        boxed_grammar(parse_file(&PathBuf::from(path)))
+    })
+}
+
+/// Parses and verifies a grammar held in a string. Returns null if it has
+/// errors, which blabber_last_error describes.
+///
+/// # Safety
+/// text must be null or a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn blabber_parse_string(text: *const c_char) -> *mut BlabberGrammar {
+    catch(|| {
+        let text = read_str(text, "text")?;
+        boxed_grammar(parse_str(text, SourceName::Builtin))
+    })
+}
+
+/// Generates a sentence from start, or from the grammar's start symbol if
+/// start is null. The same seed always gives the same sentence, like `--seed`
+/// on the command line. Returns null on failure, which blabber_last_error
+/// describes. Free the sentence with blabber_string_free.
+///
+/// # Safety
+/// grammar must come from blabber_parse_file or blabber_parse_string and not
+/// have been freed, and start must be null or a valid nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn blabber_generate(grammar: *const BlabberGrammar, start: *const c_char, seed: u64) -> *mut c_char {
+    catch(|| {
+        let grammar = match grammar.as_ref() {
+            Some(BlabberGrammar(grammar)) => grammar,
+            None => return Err("`grammar` is null".to_string()),
+        };
+        let start = if start.is_null() { &grammar.start_symbol } else { read_str(start, "start")? };
+
+        let sentence = generate_from(grammar, start, &mut Xoshiro256StarStar::new(seed)).map_err(|error| error.to_string())?;
+        // Terminals can contain nul bytes, which C strings can't
+        let sentence = CString::new(sentence).map_err(|_| "The sentence contains a nul byte".to_string())?;
+        Ok(sentence.into_raw())
+    })
+}
+
+/// Describes why the last call to a blabber function on this thread failed,
+/// or returns null if it succeeded. The message belongs to blabber and lasts
+/// until the next call on this thread.
+#[no_mangle]
+pub extern "C" fn blabber_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Frees a grammar. Does nothing if grammar is null.
+///
+/// # Safety
+/// grammar must be null or come from blabber_parse_file or
+/// blabber_parse_string, and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn blabber_grammar_free(grammar: *mut BlabberGrammar) {
+    if !grammar.is_null() {
+        drop(Box::from_raw(grammar));
+    }
+}
+
+/// Frees a sentence. Does nothing if sentence is null.
+///
+/// # Safety
+/// sentence must be null or come from blabber_generate, and not have been
+/// freed already.
+#[no_mangle]
+pub unsafe extern "C" fn blabber_string_free(sentence: *mut c_char) {
+    if !sentence.is_null() {
+        drop(CString::from_raw(sentence));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> Option<String> {
+        let error = blabber_last_error();
+        (!error.is_null()).then(|| unsafe { CStr::from_ptr(error) }.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn generate_through_pointers() {
+        unsafe {
+            let grammar = blabber_parse_file(c"example_data/english.bnf".as_ptr());
+            assert!(!grammar.is_null());
+            assert_eq!(last_error(), None);
+
+            let sentence = blabber_generate(grammar, ptr::null(), 42);
+            assert_eq!(CStr::from_ptr(sentence).to_str(), Ok("green ideas hug furiously"));
+            blabber_string_free(sentence);
+
+            let sentence = blabber_generate(grammar, c"verb".as_ptr(), 0);
+            assert_eq!(CStr::from_ptr(sentence).to_str(), Ok("hug"));
+            blabber_string_free(sentence);
+
+            assert!(blabber_generate(grammar, c"nothing".as_ptr(), 0).is_null());
+            assert_eq!(last_error(), Some("No definition for nonterminal `nothing`".to_string()));
+            blabber_grammar_free(grammar);
+        }
+    }
+
+    #[test]
+    fn report_errors() {
+        unsafe {
+            assert!(blabber_parse_string(c"a = b".as_ptr()).is_null());
+            assert!(last_error().unwrap().contains("Could not find definition for `b`"));
+            assert!(blabber_parse_string(ptr::null()).is_null());
+            assert_eq!(last_error(), Some("`text` is null".to_string()));
+            assert!(blabber_generate(ptr::null(), ptr::null(), 0).is_null());
+            assert_eq!(last_error(), Some("`grammar` is null".to_string()));
+        }
+    }
+
+    #[test]
+    fn catch_panics() {
+        assert!(catch::<()>(|| panic!("oh no")).is_null());
+        assert_eq!(last_error(), Some("blabber panicked: oh no".to_string()));
+    }
+}
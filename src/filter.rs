@@ -0,0 +1,103 @@
+/*
+    This module keeps only the sentences that contain every required text
+    and none of the forbidden ones, by generating again until one does.
+
+    Before generating, required text is checked against the characters the
+    grammar can make, so text it can never output fails at once instead of
+    after every try. How often sentences were accepted is tracked, to warn
+    when the filter throws most of them away.
+*/
+
+use crate::analysis::terminals::producible_chars;
+use crate::generator::GenerateErrorType;
+use crate::grammar::Grammar;
+
+// How many tries a sentence gets before giving up, if not given
+pub const DEFAULT_TRIES: u32 = 1000;
+// Accepting fewer than this share of sentences is warned about
+const LOW_ACCEPTANCE: f64 = 0.1;
+
+#[derive(Debug)]
+pub struct Filter {
+    pub require: Vec<String>,
+    pub forbid: Vec<String>,
+    tried: u64,
+    accepted: u64,
+}
+
+impl Filter {
+    pub fn new(require: Vec<String>, forbid: Vec<String>) -> Self {
+        Filter { require, forbid, tried: 0, accepted: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.require.is_empty() && self.forbid.is_empty()
+    }
+
+    // Whether the sentence has every required text and no forbidden text,
+    // counting it for the acceptance rate
+    pub fn accepts(&mut self, sentence: &str) -> bool {
+        let accepted = self.require.iter().all(|text| sentence.contains(text.as_str()))
+            && !self.forbid.iter().any(|text| sentence.contains(text.as_str()));
+        self.tried += 1;
+        self.accepted += accepted as u64;
+        return accepted;
+    }
+
+    // An error if some required text can't be made from the start symbol
+    pub fn check_possible(&self, grammar: &Grammar, start: &str) -> Result<(), GenerateErrorType> {
+        if self.require.is_empty() {
+            return Ok(());
+        }
+        let chars = producible_chars(grammar, start);
+        for required in &self.require {
+            if let Some(missing) = required.chars().find(|c| !chars.contains(c)) {
+                return Err(GenerateErrorType::RequiredImpossible { start: start.to_string(), required: required.clone(), missing });
+            }
+        }
+        return Ok(());
+    }
+
+    // A warning if few of the sentences tried were accepted
+    pub fn warning(&self) -> Option<String> {
+        if self.tried == 0 {
+            return None;
+        }
+        let rate = self.accepted as f64 / self.tried as f64;
+        return (rate < LOW_ACCEPTANCE).then(|| format!(
+            "Only {} of {} sentences ({:.1}%) met --require and --forbid, so most of the time generating was thrown away",
+            self.accepted, self.tried, rate * 100.0
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn accept_required_and_forbidden() {
+        let mut filter = Filter::new(vec!["Acme".to_string(), "!".to_string()], vec!["darn".to_string()]);
+        assert!(filter.accepts("Buy Acme!"));
+        assert!(!filter.accepts("Buy Acme."));
+        // Matching is case sensitive
+        assert!(filter.accepts("Darn, Acme!"));
+        assert!(!filter.accepts("darn Acme!"));
+        assert_eq!(filter.warning(), None);
+        for _ in 0..30 {
+            filter.accepts("nothing");
+        }
+        assert_eq!(filter.warning().unwrap(), "Only 2 of 34 sentences (5.9%) met --require and --forbid, so most of the time generating was thrown away");
+    }
+
+    #[test]
+    fn fail_fast_on_impossible_text() {
+        let grammar = parse_str("s = \"Ac\" \"me\" | t:upper\nt = \"x\"", SourceName::Builtin).unwrap();
+        assert_eq!(Filter::new(vec!["Acme".to_string(), "X".to_string()], vec![]).check_possible(&grammar, "s"), Ok(()));
+        assert_eq!(Filter::new(vec!["Acne".to_string()], vec![]).check_possible(&grammar, "s"),
+            Err(GenerateErrorType::RequiredImpossible { start: "s".to_string(), required: "Acne".to_string(), missing: 'n' }));
+        assert!(Filter::new(vec!["X".to_string()], vec![]).check_possible(&grammar, "t").is_err());
+    }
+}
@@ -0,0 +1,532 @@
+/*
+    Where the generator's choices come from.
+
+    Every time a rule is expanded, the generator asks a choice provider which
+    alternative to use. Normally that's the random number generator, which can
+    also be weighted toward smaller or larger derivations, or away from
+    alternatives it has already used. With --stable-noise, each choice gets
+    a generator of its own instead, so editing one rule doesn't change the
+    choices made for the others, and with --deterministic there's no
+    randomness at all, just the first alternative every time. The choices
+    can also be recorded into a trace, by observing generation, and
+    replayed from one later, so a sentence can be reproduced exactly without
+    knowing the seed. Dynamic symbols, like dates, are made from draws
+    which are traced and replayed like choices.
+*/
+
+use std::collections::{HashMap, HashSet};
+
+use rand::RngCore;
+use serde_json::Value;
+
+use crate::analysis::min_size::{alternative_min_size, min_sizes};
+use crate::grammar::{Grammar, SymbolRef, Weights};
+use crate::json;
+use super::{choose_cumulative, choose_index, choose_u64, choose_weighted, stream_seed, GenerateErrorType, Xoshiro256StarStar};
+
+// Decides which alternative of a rule to expand
+pub trait ChoiceProvider {
+    // Picks an index below alternatives, which is at least 1
+    fn choose(&mut self, rule: &str, alternatives: usize) -> Result<usize, GenerateErrorType>;
+
+    // Picks an index into weights, for a rule the grammar gives weights.
+    // Providers which don't choose randomly can leave the weights out.
+    fn choose_weighted(&mut self, rule: &str, weights: &Weights) -> Result<usize, GenerateErrorType> {
+        self.choose(rule, weights.len())
+    }
+
+    // Picks a value below values, which is at least 1, for a draw making a
+    // dynamic symbol, named like `@date`. Providers which make choices any
+    // other way can make draws as choices.
+    fn draw(&mut self, symbol: &str, values: u64) -> Result<u64, GenerateErrorType> {
+        let values = usize::try_from(values).unwrap_or(usize::MAX);
+        return self.choose(symbol, values).map(|value| value as u64);
+    }
+
+    // Picks one of the alternatives at allowed, which isn't empty, for a rule
+    // at its `;@ max-depth`. Providers can choose as if the rule only had
+    // those, with the grammar's weights if it has any.
+    fn choose_allowed(&mut self, rule: &str, _alternatives: usize, weights: Option<&Weights>, allowed: &[usize]) -> Result<usize, GenerateErrorType> {
+        let index = match weights {
+            Some(weights) => self.choose_weighted(rule, &Weights::new(allowed.iter().map(|index| weights[*index]).collect()))?,
+            None => self.choose(rule, allowed.len())?,
+        };
+        return Ok(allowed[index]);
+    }
+}
+
+// Makes every choice with a random number generator
+pub struct RandomChoices<'a, R: RngCore>(pub &'a mut R);
+
+impl<R: RngCore> ChoiceProvider for RandomChoices<'_, R> {
+    fn choose(&mut self, _rule: &str, alternatives: usize) -> Result<usize, GenerateErrorType> {
+        Ok(choose_index(self.0, alternatives))
+    }
+
+    fn choose_weighted(&mut self, _rule: &str, weights: &Weights) -> Result<usize, GenerateErrorType> {
+        Ok(choose_cumulative(self.0, weights.cumulative()))
+    }
+
+    fn draw(&mut self, _symbol: &str, values: u64) -> Result<u64, GenerateErrorType> {
+        Ok(choose_u64(self.0, values))
+    }
+}
+
+// Always picks the first alternative, and the first value of every draw,
+// so each start symbol has one canonical sentence
+pub struct FirstChoices;
+
+impl ChoiceProvider for FirstChoices {
+    fn choose(&mut self, _rule: &str, _alternatives: usize) -> Result<usize, GenerateErrorType> {
+        Ok(0)
+    }
+
+    fn draw(&mut self, _symbol: &str, _values: u64) -> Result<u64, GenerateErrorType> {
+        Ok(0)
+    }
+}
+
+// The rules first choices go through from start to come back to one of
+// them, ending with the one they come back to, if they do. Then expanding
+// start with FirstChoices never finishes.
+pub fn first_choice_loop<'a>(grammar: &'a Grammar, start: &'a str) -> Option<Vec<&'a str>> {
+    let first = |rule: &str| grammar.rules.get(rule).and_then(|rewrite| rewrite.get(0));
+    let mut finished = HashSet::new();
+    // The rules being expanded, each with how many symbols of its first
+    // alternative have been followed
+    let mut path: Vec<(&str, usize)> = vec![(start, 0)];
+    while let Some(&(rule, followed)) = path.last() {
+        let Some(symbol) = first(rule).and_then(|alternative| alternative.get(followed)) else {
+            finished.insert(rule);
+            path.pop();
+            continue;
+        };
+        path.last_mut().expect("the rule is on the path").1 += 1;
+        if let SymbolRef::Nonterminal(name, _) = symbol {
+            if let Some(at) = path.iter().position(|(on_path, _)| *on_path == name) {
+                return Some(path[at..].iter().map(|(rule, _)| *rule).chain([name]).collect());
+            }
+            if !finished.contains(name) {
+                path.push((name, 0));
+            }
+        }
+    }
+    return None;
+}
+
+// How often each alternative of each rule has been used, across every
+// sentence in a run, so later choices can favour the others
+#[derive(Debug, Default)]
+pub struct Variety {
+    // From 0, which changes nothing, to 1, which never repeats an alternative
+    // until every other one has been used as often
+    strength: f64,
+    uses: HashMap<String, Vec<u64>>,
+}
+
+impl Variety {
+    pub fn new(strength: f64) -> Self {
+        Variety {
+            strength,
+            uses: HashMap::new()
+        }
+    }
+
+    // How many times each alternative of each rule has been picked, by rule
+    pub fn uses(&self) -> Vec<(&str, &[u64])> {
+        let mut uses: Vec<(&str, &[u64])> = self.uses.iter()
+            .map(|(rule, uses)| (rule.as_str(), uses.as_slice()))
+            .collect();
+        uses.sort();
+        return uses;
+    }
+}
+
+// Biases choices toward alternatives with smaller or larger derivations.
+// Each alternative's weight is the size of its smallest derivation tree to
+// the power of temperature - 1 / temperature, which is 0 at a temperature of
+// 1, negative below it, and positive above it.
+#[derive(Debug)]
+pub struct Temperature {
+    weights: HashMap<String, Vec<f64>>,
+}
+
+impl Temperature {
+    pub fn new(grammar: &Grammar, temperature: f64) -> Self {
+        let exponent = temperature - 1.0 / temperature;
+        let sizes = min_sizes(grammar);
+        let weights = grammar.rules.iter()
+            .map(|(rule, rewrite)| {
+                let alternative_sizes: Vec<Option<u64>> = rewrite.iter()
+                    .map(|alternative| alternative_min_size(alternative, &sizes))
+                    .collect();
+                // Alternatives that never finish are avoided, unless the
+                // rule has nothing else
+                let weights = if alternative_sizes.iter().all(Option::is_none) {
+                    vec![1.0; rewrite.len()]
+                } else {
+                    alternative_sizes.iter()
+                        .map(|size| size.map_or(0.0, |size| (size as f64).powf(exponent)))
+                        .collect()
+                };
+                (rule.to_string(), weights)
+            })
+            .collect();
+        Temperature { weights }
+    }
+}
+
+// Makes every choice with a random number generator, weighting alternatives
+// by a temperature and by variety, when either is given. Their weights
+// multiply together, and with the grammar's.
+pub struct WeightedChoices<'a, R: RngCore> {
+    pub rng: &'a mut R,
+    pub temperature: Option<&'a Temperature>,
+    pub variety: Option<&'a mut Variety>,
+}
+
+impl<R: RngCore> WeightedChoices<'_, R> {
+    fn weights(&self, rule: &str, alternatives: usize, grammar_weights: Option<&[f64]>) -> Vec<f64> {
+        let mut weights = match self.temperature.and_then(|temperature| temperature.weights.get(rule)) {
+            Some(weights) => weights.clone(),
+            None => vec![1.0; alternatives],
+        };
+        if let Some(grammar_weights) = grammar_weights {
+            for (weight, grammar_weight) in weights.iter_mut().zip(grammar_weights) {
+                *weight *= grammar_weight;
+            }
+        }
+        if let Some(Variety { strength, uses }) = self.variety.as_deref() {
+            if let Some(uses) = uses.get(rule) {
+                // Counting from the least used alternative keeps its weight
+                // as it was, so the weights can't all shrink to nothing
+                let least = uses.iter().copied().min().unwrap_or(0);
+                for (weight, used) in weights.iter_mut().zip(uses) {
+                    *weight *= (1.0 - strength).powi((used - least).min(i32::MAX as u64) as i32);
+                }
+            }
+        }
+        if weights.iter().all(|weight| *weight <= 0.0) {
+            weights = grammar_weights.map_or_else(|| vec![1.0; alternatives], <[f64]>::to_vec);
+        }
+        return weights;
+    }
+
+    fn choose_with(&mut self, rule: &str, alternatives: usize, grammar_weights: Option<&[f64]>, allowed: Option<&[usize]>) -> usize {
+        let mut weights = self.weights(rule, alternatives, grammar_weights);
+        // Alternatives a `;@ max-depth` rules out weigh nothing, and the
+        // others weigh the same if they'd all weigh nothing too
+        if let Some(allowed) = allowed {
+            for (index, weight) in weights.iter_mut().enumerate() {
+                if !allowed.contains(&index) {
+                    *weight = 0.0;
+                }
+            }
+            if allowed.iter().all(|index| weights[*index] <= 0.0) {
+                allowed.iter().for_each(|index| weights[*index] = 1.0);
+            }
+        }
+        let index = choose_weighted(self.rng, &weights);
+        if let Some(variety) = &mut self.variety {
+            variety.uses.entry(rule.to_string()).or_insert_with(|| vec![0; alternatives])[index] += 1;
+        }
+        return index;
+    }
+}
+
+impl<R: RngCore> ChoiceProvider for WeightedChoices<'_, R> {
+    fn choose(&mut self, rule: &str, alternatives: usize) -> Result<usize, GenerateErrorType> {
+        Ok(self.choose_with(rule, alternatives, None, None))
+    }
+
+    fn choose_weighted(&mut self, rule: &str, weights: &Weights) -> Result<usize, GenerateErrorType> {
+        Ok(self.choose_with(rule, weights.len(), Some(weights), None))
+    }
+
+    // Variety counts uses of every alternative, so the choice is made among
+    // all of them, with the ones ruled out weighing nothing
+    fn choose_allowed(&mut self, rule: &str, alternatives: usize, weights: Option<&Weights>, allowed: &[usize]) -> Result<usize, GenerateErrorType> {
+        Ok(self.choose_with(rule, alternatives, weights.map(|weights| &weights[..]), Some(allowed)))
+    }
+
+    // Temperature and variety only weight alternatives, so draws are uniform
+    fn draw(&mut self, _symbol: &str, values: u64) -> Result<u64, GenerateErrorType> {
+        Ok(choose_u64(self.rng, values))
+    }
+}
+
+// Makes each choice with a generator seeded from the run's seed, the
+// sentence, the rule, and how many times the rule has been chosen for so far
+// in the sentence, rather than from one stream shared by every choice. Adding
+// an alternative to one rule then only changes what that rule and the rules
+// under it make, instead of every choice after it. The choices are a little
+// less random, since each is the first output of a generator seeded from a
+// hash, and neither temperature nor variety can weight them.
+pub struct StableChoices {
+    seed: u64,
+    sentence: u64,
+    attempt: u64,
+    occurrences: HashMap<String, u64>,
+}
+
+impl StableChoices {
+    // Choices for a try at the sentence of a run, counting both from 1, so
+    // a sentence turned down and tried again gets new choices
+    pub fn new(seed: u64, sentence: u64, attempt: u64) -> Self {
+        StableChoices {
+            seed,
+            sentence,
+            attempt,
+            occurrences: HashMap::new()
+        }
+    }
+
+    fn rng(&mut self, name: &str) -> Xoshiro256StarStar {
+        let occurrence = self.occurrences.entry(name.to_string()).or_insert(0);
+        let seed = stream_seed(self.seed, self.sentence, self.attempt, name, *occurrence);
+        *occurrence += 1;
+        return Xoshiro256StarStar::new(seed);
+    }
+}
+
+impl ChoiceProvider for StableChoices {
+    fn choose(&mut self, rule: &str, alternatives: usize) -> Result<usize, GenerateErrorType> {
+        Ok(choose_index(&mut self.rng(rule), alternatives))
+    }
+
+    fn choose_weighted(&mut self, rule: &str, weights: &Weights) -> Result<usize, GenerateErrorType> {
+        Ok(choose_cumulative(&mut self.rng(rule), weights.cumulative()))
+    }
+
+    fn draw(&mut self, symbol: &str, values: u64) -> Result<u64, GenerateErrorType> {
+        Ok(choose_u64(&mut self.rng(symbol), values))
+    }
+}
+
+// One choice made while generating a sentence
+#[derive(Debug, PartialEq, Clone)]
+pub struct Choice {
+    pub rule: String,
+    pub alternative: usize,
+}
+
+// Everything needed to generate a sentence again: the symbol it started from
+// and each choice made, in order
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trace {
+    pub start: String,
+    pub choices: Vec<Choice>,
+}
+
+impl Trace {
+    pub fn new(start: &str) -> Self {
+        Trace {
+            start: start.to_string(),
+            choices: Vec::new()
+        }
+    }
+
+    // The trace as one line of JSON, like
+    // `{"start":"sentence","choices":[["sentence",0],["noun",2]]}`
+    pub fn to_json(&self) -> String {
+        let choices: Vec<String> = self.choices.iter()
+            .map(|choice| format!("[{},{}]", json::string(&choice.rule), choice.alternative))
+            .collect();
+        format!("{{\"start\":{},\"choices\":[{}]}}", json::string(&self.start), choices.join(","))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, GenerateErrorType> {
+        let malformed = || GenerateErrorType::MalformedTrace(
+            "expected an object like {\"start\":\"sentence\",\"choices\":[[\"sentence\",0]]}".to_string()
+        );
+        let value: Value = serde_json::from_str(text)
+            .map_err(|error| GenerateErrorType::MalformedTrace(error.to_string()))?;
+
+        let start = value.get("start").and_then(Value::as_str).ok_or_else(malformed)?;
+        let choices = value.get("choices").and_then(Value::as_array).ok_or_else(malformed)?
+            .iter()
+            .map(|choice| match choice.as_array().map(Vec::as_slice) {
+                Some([rule, alternative]) => Some(Choice {
+                    rule: rule.as_str()?.to_string(),
+                    alternative: alternative.as_u64()? as usize
+                }),
+                _ => None
+            })
+            .collect::<Option<Vec<Choice>>>()
+            .ok_or_else(malformed)?;
+
+        return Ok(Trace {
+            start: start.to_string(),
+            choices
+        });
+    }
+}
+
+// Makes the choices written down in a trace, in order, checking that each
+// one still fits the grammar
+pub struct ReplayChoices<'a> {
+    choices: &'a [Choice],
+    position: usize,
+}
+
+impl<'a> ReplayChoices<'a> {
+    pub fn new(trace: &'a Trace) -> Self {
+        ReplayChoices {
+            choices: &trace.choices,
+            position: 0
+        }
+    }
+
+    // Checks that the whole trace was used
+    pub fn finish(&self) -> Result<(), GenerateErrorType> {
+        if self.position < self.choices.len() {
+            return Err(GenerateErrorType::UnusedChoices(self.position + 1));
+        }
+        return Ok(());
+    }
+}
+
+impl ChoiceProvider for ReplayChoices<'_> {
+    fn choose(&mut self, rule: &str, alternatives: usize) -> Result<usize, GenerateErrorType> {
+        // Positions are counted from 1 in errors
+        let position = self.position + 1;
+        let choice = self.choices.get(self.position).ok_or(GenerateErrorType::TraceEnded(position))?;
+
+        if choice.rule != rule {
+            return Err(GenerateErrorType::ChoiceForOtherRule {
+                position,
+                traced: choice.rule.clone(),
+                expanding: rule.to_string()
+            });
+        }
+        if choice.alternative >= alternatives {
+            return Err(GenerateErrorType::ChoiceOutOfRange {
+                position,
+                rule: rule.to_string(),
+                alternative: choice.alternative,
+                alternatives
+            });
+        }
+
+        self.position += 1;
+        return Ok(choice.alternative);
+    }
+
+    // The traced choice was made among the allowed alternatives, so it's
+    // one of them unless the grammar changed
+    fn choose_allowed(&mut self, rule: &str, alternatives: usize, _weights: Option<&Weights>, allowed: &[usize]) -> Result<usize, GenerateErrorType> {
+        let alternative = self.choose(rule, alternatives)?;
+        if !allowed.contains(&alternative) {
+            return Err(GenerateErrorType::ChoiceRuledOut { position: self.position, rule: rule.to_string(), alternative });
+        }
+        return Ok(alternative);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_traces() {
+        let trace = Trace {
+            start: "sentence \"quoted\"".to_string(),
+            choices: vec![
+                Choice { rule: "sentence \"quoted\"".to_string(), alternative: 0 },
+                Choice { rule: "noun".to_string(), alternative: 12 },
+            ]
+        };
+        let text = trace.to_json();
+        assert_eq!(text, "{\"start\":\"sentence \\\"quoted\\\"\",\"choices\":[[\"sentence \\\"quoted\\\"\",0],[\"noun\",12]]}");
+        assert_eq!(Trace::from_json(&text), Ok(trace));
+
+        assert!(matches!(Trace::from_json("{\"start\":\"a\"}"), Err(GenerateErrorType::MalformedTrace(_))));
+        assert!(matches!(Trace::from_json("{\"start\":\"a\",\"choices\":[[0,\"a\"]]}"), Err(GenerateErrorType::MalformedTrace(_))));
+        assert!(matches!(Trace::from_json("not json"), Err(GenerateErrorType::MalformedTrace(_))));
+    }
+
+    #[test]
+    fn variety_spreads_choices() {
+        let mut rng = super::super::Xoshiro256StarStar::new(3);
+        let mut variety = Variety::new(1.0);
+        let mut choices = WeightedChoices { rng: &mut rng, temperature: None, variety: Some(&mut variety) };
+        // At full strength, each alternative is used once before any repeats
+        let mut picked: Vec<usize> = (0..4).map(|_| choices.choose("a", 4).unwrap()).collect();
+        picked.sort();
+        assert_eq!(picked, vec![0, 1, 2, 3]);
+        choices.choose("b", 2).unwrap();
+
+        let uses = variety.uses();
+        assert_eq!(uses[0], ("a", &[1, 1, 1, 1][..]));
+        assert_eq!(uses[1].0, "b");
+        assert_eq!(uses[1].1.iter().sum::<u64>(), 1);
+    }
+
+    #[test]
+    fn temperature_weights_by_size() {
+        let grammar = crate::parser::parse_str("a = \"x\" | b b | a \"y\"\nb = \"z\"\nc = c", crate::error_handling::SourceName::Builtin).unwrap();
+        let mut rng = super::super::Xoshiro256StarStar::new(0);
+        // Sizes of 2, 5, and 4, to the power of 0.5 - 2
+        let terse = Temperature::new(&grammar, 0.5);
+        assert_eq!(terse.weights["a"], vec![2f64.powf(-1.5), 5f64.powf(-1.5), 4f64.powf(-1.5)]);
+        let florid = Temperature::new(&grammar, 2.0);
+        assert_eq!(florid.weights["a"], vec![2f64.powf(1.5), 5f64.powf(1.5), 4f64.powf(1.5)]);
+        assert_eq!(Temperature::new(&grammar, 1.0).weights["a"], vec![1.0; 3]);
+        // c never finishes, but it still needs weights
+        assert_eq!(terse.weights["c"], vec![1.0]);
+
+        let mut variety = Variety::new(0.5);
+        variety.uses.insert("a".to_string(), vec![0, 2, 1]);
+        let choices = WeightedChoices { rng: &mut rng, temperature: Some(&florid), variety: Some(&mut variety) };
+        assert_eq!(choices.weights("a", 3, None), vec![2f64.powf(1.5), 5f64.powf(1.5) * 0.25, 4f64.powf(1.5) * 0.5]);
+        // The grammar's own weights are multiplied in
+        assert_eq!(choices.weights("a", 3, Some(&[1.0, 0.0, 2.0])), vec![2f64.powf(1.5), 0.0, 4f64.powf(1.5) * 0.5 * 2.0]);
+    }
+
+    #[test]
+    fn replay_checks_choices() {
+        let trace = Trace {
+            start: "a".to_string(),
+            choices: vec![
+                Choice { rule: "a".to_string(), alternative: 1 },
+                Choice { rule: "b".to_string(), alternative: 3 },
+            ]
+        };
+
+        let mut replay = ReplayChoices::new(&trace);
+        assert_eq!(replay.choose("a", 2), Ok(1));
+        assert_eq!(replay.finish(), Err(GenerateErrorType::UnusedChoices(2)));
+        assert_eq!(replay.choose("c", 5), Err(GenerateErrorType::ChoiceForOtherRule {
+            position: 2,
+            traced: "b".to_string(),
+            expanding: "c".to_string()
+        }));
+        assert_eq!(replay.choose("b", 3), Err(GenerateErrorType::ChoiceOutOfRange {
+            position: 2,
+            rule: "b".to_string(),
+            alternative: 3,
+            alternatives: 3
+        }));
+        assert_eq!(replay.choose("b", 4), Ok(3));
+        assert_eq!(replay.finish(), Ok(()));
+        assert_eq!(replay.choose("a", 2), Err(GenerateErrorType::TraceEnded(3)));
+    }
+
+    #[test]
+    fn find_first_choice_loops() {
+        let grammar = crate::parser::parse_str(concat!(
+            "s = a b | \"x\"\n",
+            "a = \"a\" c | s\n",
+            "b = b \"b\" | \"b\"\n",
+            "c = \"c\" | a\n",
+            "d = e \"d\"\n",
+            "e = \"e\" e:upper\n",
+            "f = g\ng = h \"g\"\nh = f | \"h\"\n",
+        ), crate::error_handling::SourceName::Builtin).unwrap();
+        assert_eq!(first_choice_loop(&grammar, "s"), Some(vec!["b", "b"]));
+        assert_eq!(first_choice_loop(&grammar, "a"), None);
+        assert_eq!(first_choice_loop(&grammar, "d"), Some(vec!["e", "e"]));
+        assert_eq!(first_choice_loop(&grammar, "h"), Some(vec!["h", "f", "g", "h"]));
+        assert_eq!(crate::generator::generate_choosing(&grammar, "a", &mut FirstChoices, None, &mut ()), Ok("ac".to_string()));
+    }
+}
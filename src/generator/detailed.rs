@@ -0,0 +1,122 @@
+/*
+    Generates a sentence along with facts about how it was made, for callers
+    wanting more than the text, like how much work it took.
+
+    The facts are counted along with the rest of the state kept while
+    generating a sentence, so they cost a few counters and a bit for each
+    rule per sentence. The derivation tree is only recorded when it's
+    asked for.
+*/
+
+use std::time::{Duration, Instant};
+
+use crate::grammar::Grammar;
+use super::explain::Recorder;
+use super::{generate_tokens_measured, ChoiceProvider, GenerateError, Invocation, DEFAULT_MAX_DEPTH};
+
+// A sentence and how it was made. Expansions a `;@ distinct` rule threw
+// away count too, since they were work done for the sentence.
+#[derive(Debug, PartialEq)]
+pub struct Generation {
+    pub text: String,
+    // How many rules were expanded, including the start symbol
+    pub expansions: u64,
+    // How many rules deep expansions went, where a start symbol expanding
+    // nothing else is 1
+    pub max_depth: usize,
+    // How many different rules were expanded
+    pub rules_used: usize,
+    pub elapsed: Duration,
+    // Every rule expanded to make the sentence, in order, if it was asked
+    // for
+    pub tree: Option<Vec<Invocation>>,
+}
+
+// Everything in a Generation but the text, the time and the tree, which is
+// counted while generating any sentence since it costs next to nothing
+#[derive(Debug, Default)]
+pub struct Measurements {
+    expansions: u64,
+    max_depth: usize,
+    // A bit for each rule, by index, set once it's expanded
+    rules: Vec<u64>,
+}
+
+impl Measurements {
+    // Counts expanding the rule at index, depth rules deep
+    pub(super) fn expand(&mut self, index: usize, depth: usize) {
+        self.expansions += 1;
+        self.max_depth = self.max_depth.max(depth);
+        let (word, bit) = (index / 64, index % 64);
+        if word >= self.rules.len() {
+            self.rules.resize(word + 1, 0);
+        }
+        self.rules[word] |= 1 << bit;
+    }
+
+    // The generation of text, measured by this, which took elapsed
+    pub fn into_generation(self, text: String, elapsed: Duration) -> Generation {
+        Generation {
+            text,
+            expansions: self.expansions,
+            max_depth: self.max_depth,
+            rules_used: self.rules.iter().map(|word| word.count_ones() as usize).sum(),
+            elapsed,
+            tree: None
+        }
+    }
+}
+
+// Generates a sentence like generate_choosing, along with how it was made,
+// recording the rules expanded to make it if tree is set
+pub fn generate_detailed(grammar: &Grammar, start: &str, choices: &mut impl ChoiceProvider, tree: bool) -> Result<Generation, GenerateError> {
+    let started = Instant::now();
+    let mut recorder = tree.then(Recorder::default);
+    let (tokens, measurements) = generate_tokens_measured(grammar, start, choices, None, &mut recorder, DEFAULT_MAX_DEPTH)?;
+    let elapsed = started.elapsed();
+
+    let (text, tree) = match recorder {
+        Some(recorder) => {
+            let explanation = recorder.explain(&tokens);
+            (explanation.sentence, Some(explanation.invocations))
+        }
+        None => (tokens.iter().map(|token| token.text.as_str()).collect(), None),
+    };
+    return Ok(Generation { tree, ..measurements.into_generation(text, elapsed) });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::generator::{generate_from, RandomChoices, Xoshiro256StarStar};
+    use crate::parser::parse_str;
+
+    #[test]
+    fn measure_generation() {
+        let grammar = parse_str(concat!(
+            "sentence = noun.phrase \" sleeps\"\n",
+            "noun.phrase = adjective \" \" noun.phrase | \"ideas\"\n",
+            "adjective = \"green\" | \"colorless\"\n",
+        ), SourceName::Builtin).unwrap();
+        for seed in 0..20 {
+            let generation = generate_detailed(&grammar, "sentence", &mut RandomChoices(&mut Xoshiro256StarStar::new(seed)), false).unwrap();
+            // The same sentence as without the details
+            assert_eq!(generation.text, generate_from(&grammar, "sentence", &mut Xoshiro256StarStar::new(seed)).unwrap());
+            assert_eq!(generation.tree, None);
+
+            let adjectives = generation.text.matches(' ').count() as u64 - 1;
+            assert_eq!(generation.expansions, 2 + 2 * adjectives);
+            assert_eq!(generation.max_depth, 2 + adjectives as usize);
+            assert_eq!(generation.rules_used, if adjectives == 0 { 2 } else { 3 });
+        }
+
+        let grammar = parse_str("greeting = \"hi \" name:upper\nname = \"jo\"", SourceName::Builtin).unwrap();
+        let generation = generate_detailed(&grammar, "greeting", &mut RandomChoices(&mut Xoshiro256StarStar::new(0)), true).unwrap();
+        assert_eq!(generation.text, "hi JO");
+        assert_eq!(generation.tree, Some(vec![
+            Invocation { rule: "greeting".to_string(), alternative: Some(0), depth: 0, start: 0, end: 5 },
+            Invocation { rule: "name".to_string(), alternative: Some(0), depth: 1, start: 3, end: 5 },
+        ]));
+    }
+}
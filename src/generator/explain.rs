@@ -0,0 +1,186 @@
+/*
+    Explains how a sentence was generated: every rule expanded along the way,
+    which alternative was picked, and which part of the sentence it produced.
+*/
+
+use std::fmt::Display;
+
+use crate::grammar::Grammar;
+use crate::json;
+use super::{generate_tokens_choosing, ChoiceProvider, GenerateError, GeneratedToken, GenerationObserver};
+
+// One expansion of a rule, and the part of the sentence it produced, as byte
+// offsets
+#[derive(Debug, PartialEq)]
+pub struct Invocation {
+    pub rule: String,
+    // None for rules without alternatives, which produce nothing
+    pub alternative: Option<usize>,
+    pub depth: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+// A sentence along with every rule expanded to make it, in the order they
+// were expanded
+#[derive(Debug, PartialEq)]
+pub struct Explanation {
+    pub sentence: String,
+    pub invocations: Vec<Invocation>,
+}
+
+// Records invocations as token indices, since case suffixes applied later can
+// change the length of the text. Every non-empty terminal becomes one token.
+#[derive(Default)]
+pub(super) struct Recorder {
+    invocations: Vec<Invocation>,
+    // Indices of the invocations still being expanded
+    open: Vec<usize>,
+    tokens: usize,
+}
+
+impl GenerationObserver for Recorder {
+    fn on_enter_nonterminal(&mut self, name: &str, depth: usize) {
+        self.open.push(self.invocations.len());
+        self.invocations.push(Invocation {
+            rule: name.to_string(),
+            alternative: None,
+            depth,
+            start: self.tokens,
+            end: self.tokens
+        });
+    }
+
+    fn on_choose_alternative(&mut self, _name: &str, index: usize) {
+        if let Some(open) = self.open.last() {
+            self.invocations[*open].alternative = Some(index);
+        }
+    }
+
+    fn on_terminal(&mut self, text: &str) {
+        if !text.is_empty() {
+            self.tokens += 1;
+        }
+    }
+
+    fn on_exit_nonterminal(&mut self, _name: &str) {
+        if let Some(open) = self.open.pop() {
+            self.invocations[open].end = self.tokens;
+        }
+    }
+
+    // Everything the thrown away expansion invoked is forgotten, including
+    // invocations it left unfinished
+    fn on_retry_nonterminal(&mut self, _name: &str, depth: usize) {
+        while self.open.last().is_some_and(|&open| self.invocations[open].depth > depth) {
+            self.open.pop();
+        }
+        if let Some(&open) = self.open.last() {
+            self.invocations.truncate(open + 1);
+            self.invocations[open].alternative = None;
+            self.tokens = self.invocations[open].start;
+        }
+    }
+}
+
+impl Recorder {
+    // The explanation of the sentence made of tokens, which the recorder
+    // watched being generated
+    pub(super) fn explain(self, tokens: &[GeneratedToken]) -> Explanation {
+        // offsets[i] is where token i starts, with the sentence's length at
+        // the end
+        let mut offsets = vec![0];
+        let mut sentence = String::new();
+        for token in tokens {
+            sentence.push_str(&token.text);
+            offsets.push(sentence.len());
+        }
+        let invocations = self.invocations.into_iter()
+            .map(|invocation| Invocation {
+                start: offsets[invocation.start],
+                end: offsets[invocation.end],
+                ..invocation
+            })
+            .collect();
+        return Explanation { sentence, invocations };
+    }
+}
+
+// Generates a sentence like generate_choosing, explaining how it was made
+pub fn generate_explained(grammar: &Grammar, start: &str, choices: &mut impl ChoiceProvider) -> Result<Explanation, GenerateError> {
+    let mut recorder = Recorder::default();
+    let tokens = generate_tokens_choosing(grammar, start, choices, None, &mut recorder)?;
+    return Ok(recorder.explain(&tokens));
+}
+
+impl Explanation {
+    pub fn to_json(&self) -> String {
+        let invocations: Vec<String> = self.invocations.iter()
+            .map(|invocation| format!(
+                "{{\"rule\":{},\"alternative\":{},\"depth\":{},\"start\":{},\"end\":{},\"text\":{}}}",
+                json::string(&invocation.rule),
+                invocation.alternative.map_or("null".to_string(), |alternative| alternative.to_string()),
+                invocation.depth,
+                invocation.start,
+                invocation.end,
+                json::string(&self.sentence[invocation.start..invocation.end])
+            ))
+            .collect();
+        format!("{{\"sentence\":{},\"invocations\":[{}]}}", json::string(&self.sentence), invocations.join(","))
+    }
+}
+
+// The sentence, then each invocation on its own line, indented by depth
+impl Display for Explanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.sentence)?;
+        writeln!(f)?;
+        for invocation in &self.invocations {
+            let alternative = invocation.alternative.map_or("-".to_string(), |alternative| alternative.to_string());
+            writeln!(
+                f, "{}{} #{} [{}..{}] {}",
+                "  ".repeat(invocation.depth),
+                invocation.rule,
+                alternative,
+                invocation.start,
+                invocation.end,
+                json::string(&self.sentence[invocation.start..invocation.end])
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::generator::{Choice, ReplayChoices, Trace};
+    use crate::parser::parse_str;
+
+    #[test]
+    fn explain_each_rule() {
+        let grammar = parse_str("greeting = \"hi \" name:upper \"!\" | \"\"\nname = \"straße\" | \"jo\"", SourceName::Builtin).unwrap();
+        let trace = Trace {
+            start: "greeting".to_string(),
+            choices: vec![
+                Choice { rule: "greeting".to_string(), alternative: 0 },
+                Choice { rule: "name".to_string(), alternative: 0 },
+            ]
+        };
+        let explanation = generate_explained(&grammar, "greeting", &mut ReplayChoices::new(&trace)).unwrap();
+
+        // Offsets are into the cased text, which is longer
+        assert_eq!(explanation.sentence, "hi STRASSE!");
+        assert_eq!(explanation.invocations, vec![
+            Invocation { rule: "greeting".to_string(), alternative: Some(0), depth: 0, start: 0, end: 11 },
+            Invocation { rule: "name".to_string(), alternative: Some(0), depth: 1, start: 3, end: 10 },
+        ]);
+        assert_eq!(explanation.to_string(), "hi STRASSE!\n\ngreeting #0 [0..11] \"hi STRASSE!\"\n  name #0 [3..10] \"STRASSE\"\n");
+        assert_eq!(
+            explanation.to_json(),
+            "{\"sentence\":\"hi STRASSE!\",\"invocations\":[{\"rule\":\"greeting\",\"alternative\":0,\"depth\":0,\"start\":0,\"end\":11,\"text\":\"hi STRASSE!\"},\
+            {\"rule\":\"name\",\"alternative\":0,\"depth\":1,\"start\":3,\"end\":10,\"text\":\"STRASSE\"}]}"
+        );
+    }
+}
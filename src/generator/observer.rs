@@ -0,0 +1,191 @@
+/*
+    Hooks for watching a sentence being generated.
+
+    Observers are passed by generic parameter, so generating with the no-op
+    observer `()` compiles to the same code as having no hooks at all.
+*/
+
+use std::collections::BTreeMap;
+
+use crate::json;
+use super::{Choice, Trace};
+
+// Called back at each step of generation. Every method does nothing unless
+// it's overridden.
+pub trait GenerationObserver {
+    // A rule is about to be expanded, depth rules deep, with the start at 0
+    fn on_enter_nonterminal(&mut self, _name: &str, _depth: usize) {}
+
+    // The alternative at index was picked for the rule being expanded
+    fn on_choose_alternative(&mut self, _name: &str, _index: usize) {}
+
+    // A value below the number asked for was drawn to make a dynamic
+    // symbol, named like `@date`. The text it makes is then reported as a
+    // terminal.
+    fn on_draw(&mut self, _symbol: &str, _value: u64) {}
+
+    // A terminal was output, with its text as written in the grammar, before
+    // any case suffix is applied. Placeholders from lenient generation are
+    // reported as terminals too.
+    fn on_terminal(&mut self, _text: &str) {}
+
+    // A rule finished expanding
+    fn on_exit_nonterminal(&mut self, _name: &str) {}
+
+    // What the rule being expanded depth rules deep has made so far was
+    // thrown away, and it will be expanded again from the start. Rules
+    // inside it which hadn't finished never will. Choices made for the
+    // thrown away expansion were still made, and aren't taken back.
+    fn on_retry_nonterminal(&mut self, _name: &str, _depth: usize) {}
+}
+
+impl GenerationObserver for () {}
+
+// Observing through a reference, so observers can be combined without giving
+// them up
+impl<O: GenerationObserver + ?Sized> GenerationObserver for &mut O {
+    fn on_enter_nonterminal(&mut self, name: &str, depth: usize) {
+        (**self).on_enter_nonterminal(name, depth);
+    }
+
+    fn on_choose_alternative(&mut self, name: &str, index: usize) {
+        (**self).on_choose_alternative(name, index);
+    }
+
+    fn on_draw(&mut self, symbol: &str, value: u64) {
+        (**self).on_draw(symbol, value);
+    }
+
+    fn on_terminal(&mut self, text: &str) {
+        (**self).on_terminal(text);
+    }
+
+    fn on_exit_nonterminal(&mut self, name: &str) {
+        (**self).on_exit_nonterminal(name);
+    }
+
+    fn on_retry_nonterminal(&mut self, name: &str, depth: usize) {
+        (**self).on_retry_nonterminal(name, depth);
+    }
+}
+
+// Both observers are called back, the first one first
+impl<A: GenerationObserver, B: GenerationObserver> GenerationObserver for (A, B) {
+    fn on_enter_nonterminal(&mut self, name: &str, depth: usize) {
+        self.0.on_enter_nonterminal(name, depth);
+        self.1.on_enter_nonterminal(name, depth);
+    }
+
+    fn on_choose_alternative(&mut self, name: &str, index: usize) {
+        self.0.on_choose_alternative(name, index);
+        self.1.on_choose_alternative(name, index);
+    }
+
+    fn on_draw(&mut self, symbol: &str, value: u64) {
+        self.0.on_draw(symbol, value);
+        self.1.on_draw(symbol, value);
+    }
+
+    fn on_terminal(&mut self, text: &str) {
+        self.0.on_terminal(text);
+        self.1.on_terminal(text);
+    }
+
+    fn on_exit_nonterminal(&mut self, name: &str) {
+        self.0.on_exit_nonterminal(name);
+        self.1.on_exit_nonterminal(name);
+    }
+
+    fn on_retry_nonterminal(&mut self, name: &str, depth: usize) {
+        self.0.on_retry_nonterminal(name, depth);
+        self.1.on_retry_nonterminal(name, depth);
+    }
+}
+
+// An observer that's only sometimes wanted
+impl<O: GenerationObserver> GenerationObserver for Option<O> {
+    fn on_enter_nonterminal(&mut self, name: &str, depth: usize) {
+        if let Some(observer) = self {
+            observer.on_enter_nonterminal(name, depth);
+        }
+    }
+
+    fn on_choose_alternative(&mut self, name: &str, index: usize) {
+        if let Some(observer) = self {
+            observer.on_choose_alternative(name, index);
+        }
+    }
+
+    fn on_draw(&mut self, symbol: &str, value: u64) {
+        if let Some(observer) = self {
+            observer.on_draw(symbol, value);
+        }
+    }
+
+    fn on_terminal(&mut self, text: &str) {
+        if let Some(observer) = self {
+            observer.on_terminal(text);
+        }
+    }
+
+    fn on_exit_nonterminal(&mut self, name: &str) {
+        if let Some(observer) = self {
+            observer.on_exit_nonterminal(name);
+        }
+    }
+
+    fn on_retry_nonterminal(&mut self, name: &str, depth: usize) {
+        if let Some(observer) = self {
+            observer.on_retry_nonterminal(name, depth);
+        }
+    }
+}
+
+// Traces record each choice as it's made, and each draw as a choice
+impl GenerationObserver for Trace {
+    fn on_choose_alternative(&mut self, name: &str, index: usize) {
+        self.choices.push(Choice {
+            rule: name.to_string(),
+            alternative: index
+        });
+    }
+
+    fn on_draw(&mut self, symbol: &str, value: u64) {
+        self.choices.push(Choice {
+            rule: symbol.to_string(),
+            alternative: value as usize
+        });
+    }
+}
+
+// How many times each rule was expanded while generating a sentence,
+// including the start symbol
+#[derive(Debug, Default, PartialEq)]
+pub struct SymbolCounts(pub BTreeMap<String, u64>);
+
+impl SymbolCounts {
+    // The counts as one JSON object, like `{"adjective":3,"noun":1}`
+    pub fn to_json(&self) -> String {
+        let counts: Vec<String> = self.0.iter()
+            .map(|(rule, count)| format!("{}:{}", json::string(rule), count))
+            .collect();
+        format!("{{{}}}", counts.join(","))
+    }
+}
+
+impl GenerationObserver for SymbolCounts {
+    fn on_enter_nonterminal(&mut self, name: &str, _depth: usize) {
+        *self.0.entry(name.to_string()).or_insert(0) += 1;
+    }
+}
+
+// How many rules were expanded while generating a sentence, including the
+// start symbol
+#[derive(Debug, Default, PartialEq)]
+pub struct ExpansionCount(pub u64);
+
+impl GenerationObserver for ExpansionCount {
+    fn on_enter_nonterminal(&mut self, _name: &str, _depth: usize) {
+        self.0 += 1;
+    }
+}
@@ -0,0 +1,216 @@
+/*
+    The random number generator behind seeded generation.
+
+    The same seed has to produce the same output on every platform and in
+    every release of blabber, so neither the generator nor the way its output
+    is turned into choices can come from rand, whose algorithms may change
+    between versions. This is xoshiro256** 1.0 by David Blackman and Sebastiano
+    Vigna, with its state filled by SplitMix64 from the seed, as its authors
+    recommend. Changing anything in this file changes seeded output, which
+    counts as a breaking change.
+*/
+
+use rand::RngCore;
+
+#[derive(Debug, Clone)]
+pub struct Xoshiro256StarStar {
+    state: [u64; 4],
+}
+
+impl Xoshiro256StarStar {
+    pub fn new(seed: u64) -> Self {
+        let mut splitmix_state = seed;
+        let mut state = [0; 4];
+        for word in state.iter_mut() {
+            *word = splitmix64(&mut splitmix_state);
+        }
+        Xoshiro256StarStar { state }
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    return z ^ (z >> 31);
+}
+
+impl RngCore for Xoshiro256StarStar {
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+
+        return result;
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// Picks an index below len, uniformly, from whole 64 bit outputs. Outputs
+// at or above the largest multiple of len are rejected and redrawn, so every
+// index is equally likely, and the accepted output is reduced with a
+// remainder. Every choice between equally likely alternatives is made this
+// way.
+pub fn choose_index(rng: &mut (impl RngCore + ?Sized), len: usize) -> usize {
+    choose_u64(rng, len as u64) as usize
+}
+
+// Picks a value below len the same way as choose_index, for draws of
+// dynamic symbols, which can have more values than fit in a usize
+pub fn choose_u64(rng: &mut (impl RngCore + ?Sized), len: u64) -> u64 {
+    // The number of outputs which can't be used, 2^64 mod len
+    let rejected = len.wrapping_neg() % len;
+    loop {
+        let value = rng.next_u64();
+        if value <= u64::MAX - rejected {
+            return value % len;
+        }
+    }
+}
+
+// Picks an index with probability proportional to its weight, by scaling the
+// top 53 bits of one output, as a float in [0, 1), up to the total weight and
+// finding which weight it lands in. Weights must be finite and not negative,
+// and at least one must be positive.
+pub fn choose_weighted(rng: &mut (impl RngCore + ?Sized), weights: &[f64]) -> usize {
+    let total: f64 = weights.iter().sum();
+    let mut target = (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * total;
+    for (index, weight) in weights.iter().enumerate() {
+        if target < *weight {
+            return index;
+        }
+        target -= weight;
+    }
+    // Rounding can leave the target just past the last weight
+    return weights.iter().rposition(|weight| *weight > 0.0).expect("some weight is positive");
+}
+
+// Picks an index like choose_weighted, given the running totals of the
+// weights, by binary search for the first total past the target
+pub fn choose_cumulative(rng: &mut (impl RngCore + ?Sized), cumulative: &[f64]) -> usize {
+    let total = cumulative.last().copied().unwrap_or(0.0);
+    let target = (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * total;
+    let index = cumulative.partition_point(|sum| *sum <= target);
+    if index < cumulative.len() {
+        return index;
+    }
+    // Rounding can leave the target at the total, so the last alternative
+    // with any weight is picked
+    return cumulative.partition_point(|sum| *sum < total);
+}
+
+// The 64 bit FNV-1a hash of the bytes, which never changes between releases
+pub fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash = (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3);
+    }
+    return hash;
+}
+
+// The seed of the generator making one choice with --stable-noise, from
+// the run's seed, which try at which sentence of the run it is, the rule or
+// dynamic symbol being chosen for, and how many times it's been chosen for
+// before in the sentence. It's 64 bit FNV-1a over each number's little
+// endian bytes and the name's, with a 0xff byte, which UTF-8 never has,
+// ending the name. The hash only needs to give different choices different
+// seeds, since SplitMix64 mixes it when it seeds the generator.
+pub fn stream_seed(seed: u64, sentence: u64, attempt: u64, name: &str, occurrence: u64) -> u64 {
+    let bytes = [seed, sentence, attempt].into_iter().flat_map(u64::to_le_bytes)
+        .chain(name.bytes())
+        .chain([0xff])
+        .chain(occurrence.to_le_bytes());
+    return fnv1a(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_outputs() {
+        // Computed with an independent implementation of the reference code
+        let mut rng = Xoshiro256StarStar::new(0);
+        assert_eq!(rng.next_u64(), 0x99ec5f36cb75f2b4);
+        assert_eq!(rng.next_u64(), 0xbf6e1f784956452a);
+        assert_eq!(rng.next_u64(), 0x1a5f849d4933e6e0);
+
+        let mut rng = Xoshiro256StarStar::new(42);
+        assert_eq!(rng.next_u64(), 0x15780b2e0c2ec716);
+        assert_eq!(rng.next_u64(), 0x6104d9866d113a7e);
+        assert_eq!(rng.next_u64(), 0xae17533239e499a1);
+    }
+
+    #[test]
+    fn seed_streams() {
+        // Computed with an independent implementation of FNV-1a
+        assert_eq!(fnv1a("a".bytes()), 0xaf63dc4c8601ec8c);
+        assert_eq!(stream_seed(0, 0, 0, "", 0), 0xe0bb53fafb6ce78e);
+        let seeds = [stream_seed(1, 1, 1, "noun", 0), stream_seed(1, 1, 1, "noun", 1), stream_seed(1, 1, 1, "nou", 0),
+            stream_seed(1, 2, 1, "noun", 0), stream_seed(1, 1, 2, "noun", 0), stream_seed(2, 1, 1, "noun", 0)];
+        assert!(seeds.iter().enumerate().all(|(index, seed)| !seeds[..index].contains(seed)));
+    }
+
+    #[test]
+    fn choose_indices() {
+        let mut rng = Xoshiro256StarStar::new(7);
+        let indices: Vec<usize> = (0..10).map(|_| choose_index(&mut rng, 3)).collect();
+        assert!(indices.iter().all(|index| *index < 3));
+
+        let mut rng = Xoshiro256StarStar::new(0);
+        assert_eq!(choose_index(&mut rng, 1), 0);
+        // 0xbf6e1f784956452a % 10
+        assert_eq!(choose_index(&mut rng, 10), 2);
+    }
+
+    #[test]
+    fn choose_weighted_indices() {
+        let mut rng = Xoshiro256StarStar::new(0);
+        // 0x99ec5f36cb75f2b4 is about 0.6 of the way through
+        assert_eq!(choose_weighted(&mut rng, &[1.0, 1.0]), 1);
+        // 0xbf6e1f784956452a is about 0.75, which is past 2 of 3
+        assert_eq!(choose_weighted(&mut rng, &[2.0, 0.0, 1.0]), 2);
+        let indices: Vec<usize> = (0..20).map(|_| choose_weighted(&mut rng, &[0.0, 2.0, 0.0])).collect();
+        assert!(indices.iter().all(|index| *index == 1));
+    }
+
+    #[test]
+    fn choose_cumulative_indices() {
+        let mut rng = Xoshiro256StarStar::new(0);
+        assert_eq!(choose_cumulative(&mut rng, &[1.0, 2.0]), 1);
+        assert_eq!(choose_cumulative(&mut rng, &[2.0, 2.0, 3.0]), 2);
+        let indices: Vec<usize> = (0..20).map(|_| choose_cumulative(&mut rng, &[0.0, 2.0, 2.0])).collect();
+        assert!(indices.iter().all(|index| *index == 1));
+
+        // The same picks as choose_weighted from the same outputs
+        let weights = [0.5, 0.0, 3.0, 1.25, 0.0, 2.0];
+        let cumulative = crate::grammar::Weights::new(weights.to_vec()).cumulative().to_vec();
+        let (mut first, mut second) = (Xoshiro256StarStar::new(9), Xoshiro256StarStar::new(9));
+        for _ in 0..1000 {
+            assert_eq!(choose_cumulative(&mut first, &cumulative), choose_weighted(&mut second, &weights));
+        }
+    }
+}
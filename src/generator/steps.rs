@@ -0,0 +1,175 @@
+/*
+    Expands a sentence one nonterminal at a time, for showing how a grammar
+    unfolds rather than only what it makes.
+
+    Each step expands the leftmost nonterminal which hasn't been expanded
+    yet, so the choices are made in the same order as when generating
+    normally, and expanding to the end with the same seed gives the same
+    sentence. `;@ distinct` isn't applied, since a finished expansion can't
+    be checked until the end. A title case suffix capitalizes the first
+    symbol of the alternative picked, so a nonterminal there which expands
+    to nothing takes it with it.
+*/
+
+use std::fmt::Display;
+
+use crate::grammar::{Case, Dynamic, Grammar, SymbolRef};
+use super::{make_dynamic, ChoiceProvider, GenerateError, GenerateErrorType};
+
+#[derive(Debug, PartialEq, Clone)]
+enum FormSymbol {
+    Text(String),
+    // A nonterminal not expanded yet, with the cases to apply to what it
+    // expands to, innermost first
+    Pending(String, Vec<Case>),
+    // A dynamic symbol not made yet, which is made in turn like a
+    // nonterminal, so its draws come in the same order as when generating
+    // normally, with the cases to apply to it
+    Dynamic(Dynamic, Vec<Case>),
+}
+
+impl FormSymbol {
+    fn apply_case(&mut self, case: Case) {
+        match self {
+            FormSymbol::Text(text) => *text = case.apply(text),
+            FormSymbol::Pending(_, cases) | FormSymbol::Dynamic(_, cases) => cases.push(case),
+        }
+    }
+}
+
+// A sentence partway through being generated, with terminals and the
+// nonterminals still to be expanded
+#[derive(Debug, PartialEq, Clone)]
+pub struct SententialForm(Vec<FormSymbol>);
+
+impl SententialForm {
+    pub fn new(start: &str) -> Self {
+        SententialForm(vec![FormSymbol::Pending(start.to_string(), Vec::new())])
+    }
+
+    // Whether every nonterminal has been expanded
+    pub fn is_finished(&self) -> bool {
+        self.0.iter().all(|symbol| matches!(symbol, FormSymbol::Text(_)))
+    }
+
+    // Expands the leftmost nonterminal, or makes the leftmost dynamic
+    // symbol, returning false if there's none to expand. Undefined nonterminals are errors, or with lenient, are left
+    // as they are.
+    pub fn step(&mut self, grammar: &Grammar, choices: &mut impl ChoiceProvider, lenient: bool) -> Result<bool, GenerateErrorType> {
+        let Some(index) = self.0.iter().position(|symbol| match symbol {
+            FormSymbol::Pending(rule, _) => !lenient || grammar.rules.contains_key(rule.as_str()),
+            FormSymbol::Dynamic(..) => true,
+            FormSymbol::Text(_) => false,
+        }) else {
+            return Ok(false);
+        };
+        if let FormSymbol::Dynamic(dynamic, cases) = &self.0[index] {
+            let text = make_dynamic(dynamic, choices, &mut ())?;
+            self.0[index] = FormSymbol::Text(cases.iter().fold(text, |text, case| case.apply(&text)));
+            return Ok(true);
+        }
+        let FormSymbol::Pending(rule, cases) = self.0[index].clone() else {
+            unreachable!("only pending symbols are expanded");
+        };
+        let rewrite = grammar.rules.get(rule.as_str()).ok_or_else(|| GenerateErrorType::UndefinedNonterminal(rule.clone()))?;
+
+        let alternative = match rewrite.len() {
+            0 => None,
+            len => {
+                let index = match grammar.weights.get(rule.as_str()) {
+                    Some(weights) => choices.choose_weighted(&rule, weights)?,
+                    None => choices.choose(&rule, len)?,
+                };
+                rewrite.get(index)
+            }
+        };
+        let mut expansion = Vec::new();
+        for symbol in alternative.into_iter().flatten() {
+            match symbol {
+                SymbolRef::Terminal("") => {}
+                SymbolRef::Terminal(text) => expansion.push(FormSymbol::Text(text.to_string())),
+                SymbolRef::Nonterminal(name, case) => expansion.push(FormSymbol::Pending(name.to_string(), case.into_iter().collect())),
+                SymbolRef::Dynamic(dynamic) => expansion.push(FormSymbol::Dynamic(dynamic.clone(), Vec::new())),
+            }
+        }
+        for case in cases {
+            match (case, expansion.first_mut()) {
+                (Case::Title, Some(first)) => first.apply_case(case),
+                (Case::Title, None) => {}
+                _ => expansion.iter_mut().for_each(|symbol| symbol.apply_case(case)),
+            }
+        }
+        self.0.splice(index..=index, expansion);
+        return Ok(true);
+    }
+}
+
+// Terminals as they are, and nonterminals still to be expanded like `⟨noun⟩`
+impl Display for SententialForm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for symbol in &self.0 {
+            match symbol {
+                FormSymbol::Text(text) => write!(f, "{}", text)?,
+                FormSymbol::Pending(rule, _) => write!(f, "⟨{}⟩", rule)?,
+                FormSymbol::Dynamic(dynamic, _) => write!(f, "⟨@{}⟩", dynamic.name())?,
+            }
+        }
+        return Ok(());
+    }
+}
+
+// Expands start at most steps times, or until it's finished without a limit,
+// calling on_step with the form after each step
+pub fn derive_steps(grammar: &Grammar, start: &str, steps: Option<usize>, choices: &mut impl ChoiceProvider, lenient: bool, mut on_step: impl FnMut(&SententialForm)) -> Result<SententialForm, GenerateError> {
+    let mut form = SententialForm::new(start);
+    let mut taken = 0;
+    while steps.is_none_or(|steps| taken < steps) && form.step(grammar, choices, lenient)? {
+        taken += 1;
+        on_step(&form);
+    }
+    return Ok(form);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::generator::{generate_from, RandomChoices, Xoshiro256StarStar};
+    use crate::parser::{parse_file, parse_str};
+
+    #[test]
+    fn expand_leftmost_first() {
+        let grammar = parse_str("s = \"the \" noun \" \" verb:upper\nnoun = adjective \"ideas\"\nadjective = \"green \"\nverb = \"sleep\" | \"dream\"", SourceName::Builtin).unwrap();
+        let mut rng = Xoshiro256StarStar::new(1);
+        let mut forms = Vec::new();
+        let form = derive_steps(&grammar, "s", Some(3), &mut RandomChoices(&mut rng), false, |form| forms.push(form.to_string())).unwrap();
+        assert_eq!(forms, ["the ⟨noun⟩ ⟨verb⟩", "the ⟨adjective⟩ideas ⟨verb⟩", "the green ideas ⟨verb⟩"]);
+        assert!(!form.is_finished());
+
+        let finished = derive_steps(&grammar, "s", None, &mut RandomChoices(&mut rng), false, |_| {}).unwrap();
+        assert!(finished.is_finished());
+        assert!(["the green ideas SLEEP", "the green ideas DREAM"].contains(&finished.to_string().as_str()));
+    }
+
+    #[test]
+    fn finish_like_generating() {
+        let grammar = parse_file(&PathBuf::from("example_data/english.bnf")).unwrap();
+        for seed in 0..20 {
+            let form = derive_steps(&grammar, "sentence", None, &mut RandomChoices(&mut Xoshiro256StarStar::new(seed)), false, |_| {}).unwrap();
+            assert_eq!(form.to_string(), generate_from(&grammar, "sentence", &mut Xoshiro256StarStar::new(seed)).unwrap());
+        }
+    }
+
+    #[test]
+    fn leave_undefined_rules_when_lenient() {
+        let options = crate::parser::ParseOptions { verify: false, ..Default::default() };
+        let grammar = crate::parser::parse_str_with_options("s = missing:title \" \" word\nword = \"hi\"", SourceName::Builtin, &options).unwrap().0;
+        let mut rng = Xoshiro256StarStar::new(0);
+        let form = derive_steps(&grammar, "s", Some(10), &mut RandomChoices(&mut rng), true, |_| {}).unwrap();
+        assert_eq!(form.to_string(), "⟨missing⟩ hi");
+        let error = derive_steps(&grammar, "s", Some(10), &mut RandomChoices(&mut rng), false, |_| {}).unwrap_err();
+        assert_eq!(error.error, GenerateErrorType::UndefinedNonterminal("missing".to_string()));
+    }
+}
@@ -0,0 +1,171 @@
+use itertools::Itertools;
+use num_bigint::BigUint;
+use num_traits::Zero;
+use rand::RngCore;
+
+use crate::analysis::derivations::DerivationCounts;
+use crate::grammar::*;
+use super::{choose_u64, GenResult, GenerateErrorType};
+
+// Generates a sentence by picking uniformly among every derivation of start
+// that fits in the depth bound of counts. Each alternative is chosen with
+// probability proportional to how many derivations go through it, instead of
+// every alternative being equally likely. Like the other generators, it fails
+// if the derivation picked nests rules more than max_depth deep, which it
+// can't when the depth bound is no more than that.
+pub fn generate_uniform(grammar: &Grammar, counts: &DerivationCounts, start: &str, rng: &mut impl RngCore, max_depth: usize) -> GenResult {
+    let depth = counts.max_depth();
+    if !grammar.rules.contains_key(start) {
+        return Err(GenerateErrorType::UndefinedNonterminal(start.to_string()).into());
+    }
+    if counts.count(start, depth).is_zero() {
+        return Err(GenerateErrorType::NoDerivationWithinDepth(start.to_string(), depth).into());
+    }
+
+    let mut result = String::new();
+    expand(grammar, counts, start, depth, rng, max_depth, &mut result)?;
+    return Ok(result);
+}
+
+// Expands a rule which is known to have a derivation within depth, which is
+// as many rules deep below the depth bound as it's nested
+fn expand(grammar: &Grammar, counts: &DerivationCounts, symbol: &str, depth: usize, rng: &mut impl RngCore, max_depth: usize, result: &mut String) -> Result<(), GenerateErrorType> {
+    if counts.max_depth() - depth >= max_depth {
+        return Err(GenerateErrorType::MaxDepthExceeded(symbol.to_string(), max_depth));
+    }
+    let rewrite = grammar.rules.rewrite(symbol);
+    let mut remaining = random_below(&counts.count(symbol, depth), rng);
+
+    for (index, alternative) in rewrite.iter().enumerate() {
+        let alternative_count = counts.alternative_count(symbol, index, depth);
+        if remaining >= alternative_count {
+            remaining -= alternative_count;
+            continue;
+        }
+
+        for symbol in alternative {
+            match symbol {
+                SymbolRef::Terminal(text) => result.push_str(text),
+                SymbolRef::Nonterminal(name, None) => expand(grammar, counts, name, depth - 1, rng, max_depth, result)?,
+                SymbolRef::Nonterminal(name, Some(case)) => {
+                    let mut expansion = String::new();
+                    expand(grammar, counts, name, depth - 1, rng, max_depth, &mut expansion)?;
+                    result.push_str(&case.apply(&expansion));
+                }
+                // Each dynamic symbol is one derivation, whatever it makes
+                SymbolRef::Dynamic(dynamic) => {
+                    let drawn = dynamic.draws().into_iter().map(|values| choose_u64(rng, values)).collect_vec();
+                    result.push_str(&dynamic.render(&drawn));
+                }
+            }
+        }
+        return Ok(());
+    }
+    unreachable!("the derivation picked is below the count of the rule's");
+}
+
+// Picks a number below bound uniformly, by drawing just enough 64 bit
+// outputs for its bits, least significant first, masking off the extra high
+// bits, and redrawing when the result isn't below bound
+fn random_below(bound: &BigUint, rng: &mut impl RngCore) -> BigUint {
+    let bits = bound.bits();
+    let words = bits.div_ceil(64) as usize;
+    let top_bits = bits - (words as u64 - 1) * 64;
+
+    loop {
+        let mut digits: Vec<u64> = (0..words).map(|_| rng.next_u64()).collect();
+        if let Some(top) = digits.last_mut() {
+            if top_bits < 64 {
+                *top &= (1 << top_bits) - 1;
+            }
+        }
+        let value = BigUint::from_slice(&digits.iter()
+            .flat_map(|digit| [*digit as u32, (*digit >> 32) as u32])
+            .collect::<Vec<u32>>());
+        if &value < bound {
+            return value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::analysis::derivations::finite_depth;
+    use crate::error_handling::SourceName;
+    use crate::generator::{Xoshiro256StarStar, DEFAULT_MAX_DEPTH};
+    use crate::parser::parse_str;
+
+    #[test]
+    fn sample_every_derivation_equally() {
+        // Uniform alternatives would give "short" half the time, but it's only
+        // one of the four derivations
+        let grammar = parse_str(concat!(
+            "sentence = \"short\" | long\n",
+            "long = \"a\" | \"b\" | \"c\"\n",
+        ), SourceName::Builtin).unwrap();
+        let counts = DerivationCounts::new(&grammar, finite_depth(&grammar).unwrap());
+        let mut rng = Xoshiro256StarStar::new(415);
+
+        let mut tally: HashMap<String, u32> = HashMap::new();
+        for _ in 0..40_000 {
+            *tally.entry(generate_uniform(&grammar, &counts, "sentence", &mut rng, DEFAULT_MAX_DEPTH).unwrap()).or_insert(0) += 1;
+        }
+
+        assert_eq!(tally.len(), 4);
+        for count in tally.values() {
+            assert!((9_000..11_000).contains(count), "{:?}", tally);
+        }
+    }
+
+    #[test]
+    fn respect_depth_bound() {
+        let grammar = parse_str("list = \"x\" | \"x\" list\n", SourceName::Builtin).unwrap();
+        let counts = DerivationCounts::new(&grammar, 3);
+        let mut rng = Xoshiro256StarStar::new(0);
+
+        for _ in 0..100 {
+            let generated = generate_uniform(&grammar, &counts, "list", &mut rng, 3).unwrap();
+            assert!(["x", "xx", "xxx"].contains(&generated.as_str()));
+        }
+
+        let counts = DerivationCounts::new(&grammar, 0);
+        assert_eq!(
+            generate_uniform(&grammar, &counts, "list", &mut rng, 3),
+            Err(GenerateErrorType::NoDerivationWithinDepth("list".to_string(), 0).into())
+        );
+    }
+
+    #[test]
+    fn fail_past_max_depth() {
+        // Derivations no deeper than max_depth never nest past it
+        let grammar = parse_str("list = \"x\" | \"x\" list\n", SourceName::Builtin).unwrap();
+        let counts = DerivationCounts::new(&grammar, 600);
+        let mut rng = Xoshiro256StarStar::new(0);
+        for _ in 0..20 {
+            assert!(generate_uniform(&grammar, &counts, "list", &mut rng, 600).is_ok());
+        }
+
+        // A bound deeper than the limit fails the derivations nesting past it
+        let chain = (0..599).map(|index| format!("r{} = r{}\n", index, index + 1)).collect::<String>() + "r599 = \"x\"";
+        let grammar = parse_str(&chain, SourceName::Builtin).unwrap();
+        let counts = DerivationCounts::new(&grammar, finite_depth(&grammar).unwrap());
+        assert_eq!(generate_uniform(&grammar, &counts, "r0", &mut rng, 600), Ok("x".to_string()));
+        assert_eq!(
+            generate_uniform(&grammar, &counts, "r0", &mut rng, DEFAULT_MAX_DEPTH),
+            Err(GenerateErrorType::MaxDepthExceeded("r512".to_string(), DEFAULT_MAX_DEPTH).into())
+        );
+    }
+
+    #[test]
+    fn draw_big_numbers_below_bound() {
+        let mut rng = Xoshiro256StarStar::new(1);
+        let bound = BigUint::from(3u32).pow(100);
+        for _ in 0..100 {
+            assert!(random_below(&bound, &mut rng) < bound);
+        }
+        assert_eq!(random_below(&BigUint::from(1u32), &mut rng), BigUint::from(0u32));
+    }
+}
@@ -0,0 +1,413 @@
+/*
+    Dynamic symbols, which make a value that isn't written out as
+    alternatives, like a date from a range, each time they're generated:
+
+        @date("%Y-%m-%d", 2020-01-01, 2025-12-31)
+        @float(0, 1, 3)
+        @uuid
+
+    Each value is made from draws the generator makes with the run's random
+    number generator, so seeded output stays the same. Nothing depends on the
+    clock, so a date range is always written out. Values are kept as whole
+    numbers, days since 1970-01-01 for dates and steps of the last decimal
+    place for floats, so every value of a range is equally likely and is
+    written exactly.
+*/
+
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
+pub enum Dynamic {
+    // A date from first to last, inclusive, as days since 1970-01-01,
+    // written with a strftime-like format
+    Date { format: String, first: i64, last: i64 },
+    // A number from min to max, inclusive, in steps of the last of places
+    // decimal places, which min and max are counted in
+    Float { min: i64, max: i64, places: u32 },
+    // A random version 4 UUID
+    Uuid,
+}
+
+// The names dynamic symbols are written with, after an `@`
+pub const DYNAMICS: [&str; 3] = ["date", "float", "uuid"];
+
+// The most decimal places a float can have, so its steps fit in 64 bits
+pub const MAX_PLACES: u32 = 9;
+
+// The date format specifiers dates can be written with, each after a `%`
+pub const DATE_SPECIFIERS: [char; 12] = ['Y', 'y', 'm', 'd', 'e', 'j', 'B', 'b', 'A', 'a', 'u', 'F'];
+
+// Dates are written with four digit years, so they're from 0000-01-01 to
+// 9999-12-31, as days since 1970-01-01
+pub const FIRST_DAY: i64 = -719528;
+pub const LAST_DAY: i64 = 2932896;
+
+const MONTHS: [&str; 12] = ["January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December"];
+const WEEKDAYS: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+
+// UUIDs are made from two draws of 61 bits, for the 122 random bits of a
+// version 4 UUID
+const UUID_DRAW: u64 = 1 << 61;
+
+impl Dynamic {
+    // The name it's written with, without the `@`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dynamic::Date { .. } => "date",
+            Dynamic::Float { .. } => "float",
+            Dynamic::Uuid => "uuid",
+        }
+    }
+
+    // Whether every value of the symbol can be made, which the parser checks
+    // piece by piece to say what's wrong
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Dynamic::Date { format, first, last } => FIRST_DAY <= *first && first <= last && *last <= LAST_DAY
+                && format_specifiers(format).all(|specifier| specifier.is_some_and(|c| c == '%' || DATE_SPECIFIERS.contains(&c))),
+            Dynamic::Float { min, max, places } => min <= max && *places <= MAX_PLACES && max.abs_diff(*min) < u64::MAX,
+            Dynamic::Uuid => true,
+        }
+    }
+
+    // How many values each draw making the symbol picks from, in order
+    pub fn draws(&self) -> Vec<u64> {
+        match self {
+            // Ranges are checked to fit when they're parsed
+            Dynamic::Date { first, last, .. } => vec![last.abs_diff(*first) + 1],
+            Dynamic::Float { min, max, .. } => vec![max.abs_diff(*min) + 1],
+            Dynamic::Uuid => vec![UUID_DRAW, UUID_DRAW],
+        }
+    }
+
+    // The text made from values drawn below what draws gives
+    pub fn render(&self, drawn: &[u64]) -> String {
+        match self {
+            Dynamic::Date { format, first, .. } => format_date(format, first + drawn[0] as i64),
+            Dynamic::Float { min, places, .. } => format_fixed(min + drawn[0] as i64, *places),
+            Dynamic::Uuid => {
+                let random = (u128::from(drawn[0]) << 61) | u128::from(drawn[1]);
+                let bits = ((random >> 74) << 80)
+                    | (0x4 << 76)
+                    | (((random >> 62) & 0xfff) << 64)
+                    | (0b10 << 62)
+                    | (random & ((1 << 62) - 1));
+                let hex = format!("{:032x}", bits);
+                format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+            }
+        }
+    }
+
+    // The lengths of the shortest and longest text the symbol can make, in
+    // characters
+    pub fn lengths(&self) -> (usize, usize) {
+        match self {
+            Dynamic::Date { format, .. } => {
+                let mut lengths = (0, 0);
+                let mut chars = format.chars();
+                while let Some(c) = chars.next() {
+                    let (min, max) = match (c, chars.clone().next()) {
+                        ('%', Some(specifier)) => {
+                            chars.next();
+                            match specifier {
+                                'Y' => (4, 4),
+                                'y' | 'm' | 'd' | 'e' => (2, 2),
+                                'j' | 'b' | 'a' => (3, 3),
+                                'u' | '%' => (1, 1),
+                                'F' => (10, 10),
+                                // May, and September
+                                'B' => (3, 9),
+                                // Monday, and Wednesday
+                                'A' => (6, 9),
+                                _ => (1, 1),
+                            }
+                        }
+                        _ => (1, 1),
+                    };
+                    lengths = (lengths.0 + min, lengths.1 + max);
+                }
+                lengths
+            }
+            Dynamic::Float { min, max, places } => {
+                let closest_to_zero = 0.clamp(*min, *max);
+                (format_fixed(closest_to_zero, *places).len(), format_fixed(*min, *places).len().max(format_fixed(*max, *places).len()))
+            }
+            Dynamic::Uuid => (36, 36),
+        }
+    }
+
+    // Every character the symbol could make, and maybe a few it can't
+    pub fn chars(&self) -> Vec<char> {
+        let digits = '0'..='9';
+        match self {
+            Dynamic::Date { format, .. } => {
+                let names = MONTHS.iter().chain(&WEEKDAYS).flat_map(|name| name.chars());
+                let mut chars: Vec<char> = format.chars().chain(digits).chain([' ', '-']).chain(names).collect();
+                chars.sort_unstable();
+                chars.dedup();
+                chars
+            }
+            Dynamic::Float { .. } => digits.chain(['-', '.']).collect(),
+            Dynamic::Uuid => digits.chain('a'..='f').chain(['-']).collect(),
+        }
+    }
+}
+
+impl Dynamic {
+    // Whether text is exactly one of the values the symbol makes, or one
+    // with its case changed if ignore_case
+    pub fn makes(&self, text: &str, ignore_case: bool) -> bool {
+        let same = |made: String| if ignore_case { made.to_lowercase() == text.to_lowercase() } else { made == text };
+        match self {
+            Dynamic::Date { format, first, last } => match parse_date(format, text, ignore_case) {
+                Some(days) => (*first..=*last).contains(&days) && same(format_date(format, days)),
+                // Without the whole date in the format, every date is tried
+                None => (*first..=*last).any(|days| same(format_date(format, days))),
+            },
+            Dynamic::Float { min, max, places } => {
+                let digits = text.strip_prefix('-').unwrap_or(text);
+                let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+                if whole.is_empty() || fraction.len() != *places as usize || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+                    return false;
+                }
+                let Ok(value) = text.replace('.', "").parse::<i64>() else {
+                    return false;
+                };
+                (*min..=*max).contains(&value) && format_fixed(value, *places) == text
+            }
+            Dynamic::Uuid => text.len() == 36 && text.char_indices().all(|(index, c)| {
+                let c = if ignore_case { c.to_ascii_lowercase() } else { c };
+                match index {
+                    8 | 13 | 18 | 23 => c == '-',
+                    14 => c == '4',
+                    19 => matches!(c, '8' | '9' | 'a' | 'b'),
+                    _ => matches!(c, '0'..='9' | 'a'..='f'),
+                }
+            }),
+        }
+    }
+}
+
+// Whether text starts with name, which is ASCII, ignoring case if asked
+fn starts_with_name(text: &str, name: &str, ignore_case: bool) -> bool {
+    text.get(..name.len()).is_some_and(|start| if ignore_case { start.eq_ignore_ascii_case(name) } else { start == name })
+}
+
+// Reads a date written with format back into days since 1970-01-01, when
+// the format has the whole date in it. Checking the date makes text again
+// is left to the caller.
+fn parse_date(format: &str, text: &str, ignore_case: bool) -> Option<i64> {
+    let (mut year, mut month, mut day, mut day_of_year) = (None, None, None, None);
+    let mut text = text;
+    let mut chars = format.chars();
+    // Takes a number of exactly width characters, which may start with spaces
+    let number = |text: &mut &str, width: usize| -> Option<i64> {
+        let taken = text.get(..width)?.trim_start();
+        *text = &text[width..];
+        return taken.parse().ok().filter(|_| taken.chars().all(|c| c.is_ascii_digit()));
+    };
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            // Literal text is checked when the date is made again
+            let mut rest = text.chars();
+            rest.next()?;
+            text = rest.as_str();
+            continue;
+        }
+        let specifier = chars.next()?;
+        match specifier {
+            'Y' => year = Some(number(&mut text, 4)?),
+            'm' => month = Some(number(&mut text, 2)?),
+            'd' | 'e' => day = Some(number(&mut text, 2)?),
+            'j' => day_of_year = Some(number(&mut text, 3)?),
+            'F' => {
+                year = Some(number(&mut text, 4)?);
+                text = text.strip_prefix('-')?;
+                month = Some(number(&mut text, 2)?);
+                text = text.strip_prefix('-')?;
+                day = Some(number(&mut text, 2)?);
+            }
+            'y' | 'u' => {
+                number(&mut text, if specifier == 'y' { 2 } else { 1 })?;
+            }
+            'B' | 'b' => {
+                let index = MONTHS.iter().position(|name| starts_with_name(text, if specifier == 'B' { name } else { &name[..3] }, ignore_case))?;
+                text = &text[if specifier == 'B' { MONTHS[index].len() } else { 3 }..];
+                month = Some(index as i64 + 1);
+            }
+            'A' | 'a' => {
+                let name = WEEKDAYS.iter().find(|name| starts_with_name(text, if specifier == 'A' { name } else { &name[..3] }, ignore_case))?;
+                text = &text[if specifier == 'A' { name.len() } else { 3 }..];
+            }
+            _ => text = text.strip_prefix('%')?,
+        }
+    }
+    if !text.is_empty() {
+        return None;
+    }
+    let year = year?;
+    return match (month, day, day_of_year) {
+        (Some(month @ 1..=12), Some(day), _) if day >= 1 && day <= days_in_month(year, month as u32).into() => Some(days_from_civil(year, month as u32, day as u32)),
+        (_, _, Some(day_of_year @ 1..=366)) => Some(days_from_civil(year, 1, 1) + day_of_year - 1),
+        _ => None,
+    };
+}
+
+// Dynamic symbols are shown the way they're written in grammar files
+// Written the way it's read, with the ends of a float range as short as
+// they can be
+impl Display for Dynamic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let shortest = |value: i64, places: u32| {
+            let text = format_fixed(value, places);
+            if places == 0 { text } else { text.trim_end_matches('0').trim_end_matches('.').to_string() }
+        };
+        match self {
+            Dynamic::Date { format, first, last } => write!(f, "@date(\"{}\", {}, {})", format, iso_date(*first), iso_date(*last)),
+            Dynamic::Float { min, max, places } => write!(f, "@float({}, {}, {})", shortest(*min, *places), shortest(*max, *places), places),
+            Dynamic::Uuid => write!(f, "@uuid"),
+        }
+    }
+}
+
+// The days since 1970-01-01 of a date in the proleptic Gregorian calendar,
+// by Howard Hinnant's days_from_civil
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+
+// The year, month, and day of a number of days since 1970-01-01, undoing
+// days_from_civil
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    return (year, month, day);
+}
+
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// The character after each `%` in a date format, or None for a `%` at the
+// end, with `%%` taken as one specifier
+pub fn format_specifiers(format: &str) -> impl Iterator<Item = Option<char>> + '_ {
+    let mut chars = format.chars();
+    return std::iter::from_fn(move || loop {
+        match chars.next()? {
+            '%' => return Some(chars.next()),
+            _ => continue,
+        }
+    });
+}
+
+fn iso_date(days: i64) -> String {
+    format_date("%F", days)
+}
+
+// Writes a date with a format of text and `%` specifiers, which are checked
+// to be DATE_SPECIFIERS or `%%` when the format is parsed
+fn format_date(format: &str, days: i64) -> String {
+    let (year, month, day) = civil_from_days(days);
+    // 1970-01-01 was a Thursday, and weeks start on Monday
+    let weekday = (days + 3).rem_euclid(7) as usize;
+    let day_of_year = days - days_from_civil(year, 1, 1) + 1;
+    let mut text = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => text += &format!("{:04}", year),
+            Some('y') => text += &format!("{:02}", year.rem_euclid(100)),
+            Some('m') => text += &format!("{:02}", month),
+            Some('d') => text += &format!("{:02}", day),
+            Some('e') => text += &format!("{:>2}", day),
+            Some('j') => text += &format!("{:03}", day_of_year),
+            Some('B') => text += MONTHS[month as usize - 1],
+            Some('b') => text += &MONTHS[month as usize - 1][..3],
+            Some('A') => text += WEEKDAYS[weekday],
+            Some('a') => text += &WEEKDAYS[weekday][..3],
+            Some('u') => text += &(weekday + 1).to_string(),
+            Some('F') => text += &format!("{:04}-{:02}-{:02}", year, month, day),
+            Some(other) => text.push(other),
+            None => {}
+        }
+    }
+    return text;
+}
+
+// Writes a number counted in steps of the last of places decimal places
+fn format_fixed(value: i64, places: u32) -> String {
+    let scale = 10u64.pow(places);
+    let sign = if value < 0 { "-" } else { "" };
+    let whole = value.unsigned_abs() / scale;
+    if places == 0 {
+        return format!("{}{}", sign, whole);
+    }
+    return format!("{}{}.{:0width$}", sign, whole, value.unsigned_abs() % scale, width = places as usize);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_days() {
+        for (date, days) in [((1970, 1, 1), 0), ((2000, 3, 1), 11017), ((1969, 12, 31), -1), ((2024, 2, 29), 19782), ((1, 1, 1), -719162), ((0, 1, 1), FIRST_DAY), ((9999, 12, 31), LAST_DAY)] {
+            assert_eq!(days_from_civil(date.0, date.1, date.2), days);
+            assert_eq!(civil_from_days(days), date);
+        }
+        let date = Dynamic::Date { format: "%A %e %B %Y, day %j (%a %d %b %y, %u) 100%%".to_string(), first: 19782, last: 19782 };
+        assert_eq!(date.render(&[0]), "Thursday 29 February 2024, day 060 (Thu 29 Feb 24, 4) 100%");
+        assert_eq!(date.to_string(), "@date(\"%A %e %B %Y, day %j (%a %d %b %y, %u) 100%%\", 2024-02-29, 2024-02-29)");
+        assert_eq!(date.lengths(), (51, 60));
+        assert!(date.makes("Thursday 29 February 2024, day 060 (Thu 29 Feb 24, 4) 100%", false));
+        assert!(!date.makes("Friday 29 February 2024, day 060 (Thu 29 Feb 24, 4) 100%", false));
+        assert!(date.makes("THURSDAY 29 FEBRUARY 2024, DAY 060 (THU 29 FEB 24, 4) 100%", true));
+        let range = Dynamic::Date { format: "%d/%m".to_string(), first: 19782, last: 19800 };
+        assert!(range.makes("29/02", false) && range.makes("18/03", false) && !range.makes("19/03", false) && !range.makes("29/2", false));
+        assert!(date.is_valid());
+        assert_eq!(format_specifiers("%Y-%%-%q %").collect::<Vec<_>>(), [Some('Y'), Some('%'), Some('q'), None]);
+        assert!(!Dynamic::Date { format: "%q".to_string(), first: 0, last: 0 }.is_valid());
+        assert!(!Dynamic::Date { format: "%Y".to_string(), first: 1, last: 0 }.is_valid());
+    }
+
+    #[test]
+    fn render_values() {
+        let float = Dynamic::Float { min: -1500, max: 250, places: 3 };
+        assert_eq!(float.draws(), [1751]);
+        assert_eq!((float.render(&[0]), float.render(&[1495]), float.render(&[1750])), ("-1.500".to_string(), "-0.005".to_string(), "0.250".to_string()));
+        assert_eq!(float.to_string(), "@float(-1.5, 0.25, 3)");
+        assert_eq!(Dynamic::Float { min: 0, max: 10, places: 0 }.to_string(), "@float(0, 10, 0)");
+        assert_eq!(Dynamic::Float { min: 3, max: 7, places: 0 }.render(&[2]), "5");
+        assert_eq!(float.lengths(), (5, 6));
+        assert!(float.makes("-0.005", false) && float.makes("0.250", false));
+        assert!(!float.makes("0.251", false) && !float.makes("-0.000", false) && !float.makes("0.25", false));
+
+        let uuid = Dynamic::Uuid;
+        assert_eq!(uuid.render(&[0, 0]), "00000000-0000-4000-8000-000000000000");
+        assert_eq!(uuid.render(&[UUID_DRAW - 1, UUID_DRAW - 1]), "ffffffff-ffff-4fff-bfff-ffffffffffff");
+        assert!(uuid.makes(&uuid.render(&[12345, 678910]), false) && !uuid.makes("ffffffff-ffff-5fff-bfff-ffffffffffff", false));
+        assert!(uuid.makes("FFFFFFFF-FFFF-4FFF-BFFF-FFFFFFFFFFFF", true) && !uuid.makes("FFFFFFFF-FFFF-4FFF-BFFF-FFFFFFFFFFFF", false));
+    }
+}
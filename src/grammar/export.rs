@@ -0,0 +1,320 @@
+/*
+    Writes grammars back out as BNF, one rule per line in a canonical form:
+    symbols separated by single spaces, alternatives by ` | ` with their
+    weights before them if the rule is weighted, and each rule's
+    `%deprecated` and annotations as lines directly above it. The lines around rules
+    which aren't rules, like comments, are written where they were if
+    they're given as trivia. Grammars with dynamic symbols start with the
+    `%use dynamics` line they need to be read back. The rules made for
+    brackets are written back as the brackets, inside the rules they came
+    from.
+*/
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use itertools::Itertools;
+
+use super::{AlternativeRef, Bracket, Grammar, RewriteRef, Symbol, SymbolRef};
+
+#[derive(Debug, PartialEq)]
+pub enum ExportError {
+    // The rule has weights which aren't whole numbers of at least 1, which
+    // BNF can't express
+    Weighted(String),
+    // A terminal of the rule has text no quoted terminal can hold
+    Unwritable { rule: String, terminal: String },
+    // The rule has something YAML grammars can't express, like weights
+    NotYaml { rule: String, what: &'static str },
+    // Writing the syntax needs blabber built with this feature
+    NeedsFeature(&'static str),
+}
+
+impl Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Weighted(rule) => write!(f, "`{}` has weights which aren't whole numbers of at least 1, which can't be written as BNF", rule),
+            ExportError::Unwritable { rule, terminal } => write!(f, "`{}` has the terminal {:?}, which can't be written as BNF", rule, terminal),
+            ExportError::NotYaml { rule, what } => write!(f, "`{}` has {}, which can't be written as YAML", rule, what),
+            ExportError::NeedsFeature(feature) => write!(f, "Writing this needs blabber built with the `{}` feature", feature),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+// The lines kept around a rule, like comments, with an empty line standing
+// for a blank line
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleTrivia {
+    // The lines above the rule, and above its annotations
+    pub leading: Vec<String>,
+    // The lines directly below the rule
+    pub trailing: Vec<String>,
+}
+
+// The lines kept around each rule when a grammar is written, and the ones
+// after every rule
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trivia {
+    pub rules: HashMap<String, RuleTrivia>,
+    pub end: Vec<String>,
+}
+
+// Terminals with a quote or a backslash before `n` have to be written raw,
+// and raw terminals can't hold newlines, so terminals with both can't be
+// written
+pub(super) fn writable(text: &str) -> bool {
+    !text.contains('\n') || (!text.contains('"') && !text.contains("\\n"))
+}
+
+// The weights of a rule to write before its alternatives, or None if it
+// isn't weighted. Weights from data files can be fractions or 0, which
+// can't be written.
+pub(super) fn written_weights(grammar: &Grammar, rule: &str) -> Result<Option<Vec<u32>>, ExportError> {
+    return grammar.weights.get(rule).map(|weights| whole_weights(rule, weights)).transpose();
+}
+
+// The weights of rule as whole numbers, if they all are
+fn whole_weights(rule: &str, weights: &[f64]) -> Result<Vec<u32>, ExportError> {
+    return weights.iter()
+        .map(|weight| (weight.fract() == 0.0 && (1.0..=u32::MAX as f64).contains(weight)).then_some(*weight as u32))
+        .collect::<Option<Vec<u32>>>()
+        .ok_or_else(|| ExportError::Weighted(rule.to_string()));
+}
+
+// Adds the lines to the BNF, leaving out a blank line at the start
+fn push_lines(bnf: &mut String, lines: &[String]) {
+    for line in lines {
+        if !(bnf.is_empty() && line.is_empty()) {
+            *bnf += line;
+            bnf.push('\n');
+        }
+    }
+}
+
+// Writes the rules named in order, with the trivia given for a rule, if any,
+// around it. The start symbol should come first, since the first rule is the
+// start symbol when the output is parsed.
+pub fn to_bnf(grammar: &Grammar, order: &[String], trivia: &Trivia) -> Result<String, ExportError> {
+    let mut bnf = String::new();
+    if uses_dynamics(grammar, order) {
+        bnf += "%use dynamics\n";
+    }
+    for rule in order.iter().filter(|rule| Bracket::of_rule(rule).is_none()) {
+        let weights = written_weights(grammar, rule)?;
+        let rule_trivia = trivia.rules.get(rule);
+        push_lines(&mut bnf, rule_trivia.map_or(&[], |rule_trivia| &rule_trivia.leading));
+        if let Some(deprecation) = grammar.deprecations.get(rule.as_str()) {
+            if !writable(&deprecation.message) {
+                return Err(ExportError::Unwritable { rule: rule.clone(), terminal: deprecation.message.clone() });
+            }
+            bnf += &deprecation_to_bnf(rule, &deprecation.message);
+            bnf.push('\n');
+        }
+        for (key, values) in grammar.annotations.get(rule.as_str()).into_iter().flatten() {
+            bnf += &format!(";@ {}\n", std::iter::once(key).chain(values).join(" "));
+        }
+
+        bnf += &format!("{} = {}\n", rule, rewrite_to_bnf(grammar, rule, grammar.rules.rewrite(rule.as_str()), weights.as_deref())?);
+        push_lines(&mut bnf, rule_trivia.map_or(&[], |rule_trivia| &rule_trivia.trailing));
+    }
+    push_lines(&mut bnf, &trivia.end);
+    return Ok(bnf);
+}
+
+// A rule as one line of BNF, without a newline. An empty alternative is
+// written as an empty terminal.
+pub fn rule_to_bnf(rule: &str, rewrite: RewriteRef<'_>) -> String {
+    let alternatives = rewrite.iter()
+        .map(|alternative| if alternative.is_empty() { "\"\"".to_string() } else { alternative.iter().join(" ") })
+        .join(" | ");
+    return format!("{} = {}", rule, alternatives);
+}
+
+// A rule of the grammar as one line of BNF like rule_to_bnf, but with its
+// weights and with the brackets it was written with rather than references
+// to the rules made for them. A rule that can't be written back is written
+// as rule_to_bnf would.
+pub fn written_rule_to_bnf(grammar: &Grammar, rule: &str) -> String {
+    let rewrite = grammar.rules.rewrite(rule);
+    let weights = written_weights(grammar, rule).ok().flatten();
+    return match rewrite_to_bnf(grammar, rule, rewrite, weights.as_deref()) {
+        Ok(alternatives) => format!("{} = {}", rule, alternatives),
+        Err(_) => rule_to_bnf(rule, rewrite),
+    };
+}
+
+// The alternatives of a rewrite of rule as BNF, with references to the
+// rules made for brackets written as the brackets
+fn rewrite_to_bnf<'g>(grammar: &Grammar, rule: &str, rewrite: impl IntoIterator<Item = AlternativeRef<'g>>, weights: Option<&[u32]>) -> Result<String, ExportError> {
+    let mut alternatives = Vec::new();
+    for (index, alternative) in rewrite.into_iter().enumerate() {
+        let mut symbols = alternative.iter().map(|symbol| symbol_to_bnf(grammar, rule, symbol)).collect::<Result<Vec<String>, ExportError>>()?;
+        if symbols.is_empty() {
+            symbols.push("\"\"".to_string());
+        }
+        match weights {
+            Some(weights) => alternatives.push(format!("{} {}", weights[index], symbols.join(" "))),
+            None => alternatives.push(symbols.join(" ")),
+        }
+    }
+    return Ok(alternatives.join(" | "));
+}
+
+// What the rule made for a pair of brackets holds, as they're written
+pub(super) struct Held<'a, 'g> {
+    pub bracket: Bracket,
+    // The rule the brackets are in
+    pub of: &'a str,
+    pub rewrite: Vec<AlternativeRef<'g>>,
+    // The weights to write before its alternatives, if they aren't all 1
+    pub weights: Option<Vec<u32>>,
+}
+
+// What the brackets name was made for hold, or None if it's a reference to
+// any other rule. A group's rule is what it holds, but in square brackets
+// and braces that comes after the empty alternative, and braces'
+// alternatives end by repeating. The empty alternative's weight comes from
+// `;@ repeat`, so only the others are written.
+pub(super) fn held<'a, 'g>(grammar: &'g Grammar, name: &'a str) -> Result<Option<Held<'a, 'g>>, ExportError> {
+    let (Some((of, bracket)), Some(rewrite)) = (Bracket::of_rule(name), grammar.rules.get(name)) else {
+        return Ok(None);
+    };
+    let skip = if bracket == Bracket::Group { 0 } else { 1 };
+    let mut rewrite: Vec<AlternativeRef> = rewrite.iter().skip(skip).collect();
+    if bracket == Bracket::Repeat {
+        rewrite.iter_mut().for_each(|alternative| *alternative = alternative.split_last().map_or(*alternative, |(_, rest)| rest));
+    }
+    let weights = grammar.weights.get(name)
+        .map(|weights| whole_weights(of, &weights[skip..]))
+        .transpose()?
+        .filter(|weights| weights.iter().any(|weight| *weight != 1));
+    return Ok(Some(Held { bracket, of, rewrite, weights }));
+}
+
+// A symbol of rule as BNF
+fn symbol_to_bnf(grammar: &Grammar, rule: &str, symbol: SymbolRef<'_>) -> Result<String, ExportError> {
+    match symbol {
+        SymbolRef::Terminal(text) if !writable(text) => Err(ExportError::Unwritable { rule: rule.to_string(), terminal: text.to_string() }),
+        SymbolRef::Nonterminal(name, None) => match held(grammar, name)? {
+            Some(held) => Ok(format!("{}{}{}", held.bracket.open(), rewrite_to_bnf(grammar, held.of, held.rewrite, held.weights.as_deref())?, held.bracket.close())),
+            None => Ok(symbol.to_string()),
+        },
+        _ => Ok(symbol.to_string()),
+    }
+}
+
+// A `%deprecated` line for the rule, without a newline, leaving out an
+// empty message
+pub fn deprecation_to_bnf(rule: &str, message: &str) -> String {
+    if message.is_empty() {
+        return format!("%deprecated {}", rule);
+    }
+    return format!("%deprecated {} {}", rule, Symbol::Terminal(message.into()));
+}
+
+// Every rule of the grammar, starting with the start symbol and then in the
+// order they were defined
+pub fn definition_order(grammar: &Grammar) -> Vec<String> {
+    let start = grammar.rules.get_key_value(grammar.start_symbol.as_str()).map(|(rule, _)| rule);
+    return start.into_iter()
+        .chain(grammar.rules.keys().filter(|rule| *rule != grammar.start_symbol.as_str()))
+        .map(|rule| rule.to_string())
+        .collect();
+}
+
+// Whether any of the rules named in order has a dynamic symbol, so the BNF
+// written for them needs a `%use dynamics` line
+pub fn uses_dynamics(grammar: &Grammar, order: &[String]) -> bool {
+    order.iter().flat_map(|rule| grammar.rules.rewrite(rule.as_str()).iter().flatten()).any(|symbol| matches!(symbol, SymbolRef::Dynamic(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn write_canonical_bnf() {
+        let text = "s =   t:title \" \"  u |\"\" \n;@ tag a  b\nu = \"x\\ny\"\n\nt = u |u u";
+        let grammar = parse_str(text, SourceName::Builtin).unwrap();
+        let order = definition_order(&grammar);
+        assert_eq!(order, ["s", "u", "t"]);
+
+        let trivia = Trivia {
+            rules: HashMap::from([
+                ("s".to_string(), RuleTrivia { leading: vec![String::new(), "; start".to_string()], trailing: Vec::new() }),
+                ("u".to_string(), RuleTrivia { leading: vec!["; from a.bnf:3".to_string()], trailing: vec!["; below".to_string(), String::new()] }),
+            ]),
+            end: vec!["; the end".to_string()],
+        };
+        let bnf = to_bnf(&grammar, &order, &trivia).unwrap();
+        assert_eq!(bnf, "; start\ns = t:title \" \" u | \"\"\n; from a.bnf:3\n;@ tag a b\nu = \"x\\ny\"\n; below\n\nt = u | u u\n; the end\n");
+        assert_eq!(parse_str(&bnf, SourceName::Builtin).unwrap().rules, grammar.rules);
+    }
+
+    #[test]
+    fn write_raw_terminals() {
+        let terminals = ["<a href=\"x\">", "\\n", "\"#", "a\"#\"##b", "plain\nnewline"];
+        let mut grammar = parse_str("s = \"a\"", SourceName::Builtin).unwrap();
+        grammar.rules.insert("s", &terminals.iter().map(|text| vec![Symbol::Terminal((*text).into())]).collect::<Vec<_>>());
+        let bnf = to_bnf(&grammar, &definition_order(&grammar), &Trivia::default()).unwrap();
+        assert_eq!(bnf, "s = r#\"<a href=\"x\">\"# | r\"\\n\" | r##\"\"#\"## | r###\"a\"#\"##b\"### | \"plain\\nnewline\"\n");
+        assert_eq!(parse_str(&bnf, SourceName::Builtin).unwrap().rules, grammar.rules);
+    }
+
+    #[test]
+    fn write_weights() {
+        let grammar = parse_str("s = 5 \"hi\" t | t | 2 \"\"\nt = \"x\"", SourceName::Builtin).unwrap();
+        let bnf = to_bnf(&grammar, &definition_order(&grammar), &Trivia::default()).unwrap();
+        assert_eq!(bnf, "s = 5 \"hi\" t | 1 t | 2 \"\"\nt = \"x\"\n");
+        let read = parse_str(&bnf, SourceName::Builtin).unwrap();
+        assert_eq!((read.rules, read.weights), (grammar.rules, grammar.weights));
+    }
+
+    #[test]
+    fn write_brackets_back() {
+        let text = "s = \"a\" [t [2 \"b\" | \"c\"]] | [[t] | 1 \"d\"]\n;@ distinct\nt = \"x\" [\"y\" | t]";
+        let grammar = parse_str(text, SourceName::Builtin).unwrap();
+        let bnf = to_bnf(&grammar, &definition_order(&grammar), &Trivia::default()).unwrap();
+        assert_eq!(bnf, "s = \"a\" [t [2 \"b\" | 1 \"c\"]] | [[t] | \"d\"]\n;@ distinct\nt = \"x\" [\"y\" | t]\n");
+        let read = parse_str(&bnf, SourceName::Builtin).unwrap();
+        assert_eq!((read.rules, read.weights), (grammar.rules, grammar.weights));
+    }
+
+    #[test]
+    fn write_repetitions_back() {
+        let text = "s = t {\", \" t [\"!\"]}\n;@ repeat 0.9\nt = \"x\" {3 \"y\" | {\"z\"}}";
+        let grammar = parse_str(text, SourceName::Builtin).unwrap();
+        let bnf = to_bnf(&grammar, &definition_order(&grammar), &Trivia::default()).unwrap();
+        assert_eq!(bnf, "s = t {\", \" t [\"!\"]}\n;@ repeat 0.9\nt = \"x\" {3 \"y\" | 1 {\"z\"}}\n");
+        let read = parse_str(&bnf, SourceName::Builtin).unwrap();
+        assert_eq!((read.rules, read.weights), (grammar.rules, grammar.weights));
+    }
+
+    #[test]
+    fn write_groups_back() {
+        let text = "s = (\"a\" | 2 t) \"b\" ((t) | [\"c\" | t])\nt = \"x\"";
+        let grammar = parse_str(text, SourceName::Builtin).unwrap();
+        let bnf = to_bnf(&grammar, &definition_order(&grammar), &Trivia::default()).unwrap();
+        assert_eq!(bnf, "s = (1 \"a\" | 2 t) \"b\" ((t) | [\"c\" | t])\nt = \"x\"\n");
+        let read = parse_str(&bnf, SourceName::Builtin).unwrap();
+        assert_eq!((read.rules, read.weights), (grammar.rules, grammar.weights));
+    }
+
+    #[test]
+    fn refuse_what_bnf_cannot_hold() {
+        let mut grammar = parse_str("s = \"a\" | \"b\"", SourceName::Builtin).unwrap();
+        grammar.rules.insert("s", &[vec![Symbol::Terminal("a".into())], vec![Symbol::Terminal("say\n\"b\"".into())]]);
+        let order = definition_order(&grammar);
+        assert_eq!(to_bnf(&grammar, &order, &Trivia::default()), Err(ExportError::Unwritable { rule: "s".to_string(), terminal: "say\n\"b\"".to_string() }));
+
+        grammar.rules.insert("s", &[vec![Symbol::Terminal("a".into())], vec![Symbol::Terminal("b".into())]]);
+        grammar.weights.insert("s".into(), vec![1.0, 2.5].into());
+        assert_eq!(to_bnf(&grammar, &order, &Trivia::default()), Err(ExportError::Weighted("s".to_string())));
+        grammar.weights.insert("s".into(), vec![1.0, 0.0].into());
+        assert_eq!(to_bnf(&grammar, &order, &Trivia::default()), Err(ExportError::Weighted("s".to_string())));
+    }
+}
@@ -0,0 +1,204 @@
+/*
+    Left factoring pulls the symbols that several alternatives of a rule
+    start with out into one alternative, which continues with a new rule
+    holding whatever came after them. The grammar generates the same strings
+    afterwards, though not with the same probabilities, since the factored
+    alternatives now share one alternative's chance of being picked. Weighted
+    rules keep their probabilities, since the shared alternative gets the
+    total of the factored ones' weights and the new rule gets their weights.
+*/
+
+use std::fmt::Display;
+
+use itertools::Itertools;
+
+use super::*;
+
+// One common prefix which was factored out of a rule
+#[derive(Debug, PartialEq)]
+pub struct Factoring {
+    pub rule: String,
+    pub prefix: Vec<Symbol>,
+    // How many alternatives started with the prefix
+    pub alternatives: usize,
+    // The rule made for what comes after the prefix
+    pub new_rule: String,
+}
+
+impl Display for Factoring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f, "Factored `{}` out of {} alternatives of `{}` into `{}`",
+            self.prefix.iter().join(" "), self.alternatives, self.rule, self.new_rule
+        )
+    }
+}
+
+impl Grammar {
+    // Left factors every rule, including the rules made while doing so,
+    // until no two alternatives of any rule start with the same symbol. New
+    // rules are named after the rule they came from, like `greeting.suffix1`,
+    // skipping names which are already taken, and rules are handled in order
+    // of name so the result is the same every time.
+    pub fn left_factor(&mut self) -> Vec<Factoring> {
+        let mut factorings = Vec::new();
+        let mut pending: Vec<Name> = self.rules.keys().map(Name::from).sorted().rev().collect();
+
+        while let Some(rule) = pending.pop() {
+            let mut rewrite = self.rules.rewrite(&rule).to_rewrite();
+            let mut weights = self.weights.remove(&rule).map(|weights| weights.to_vec());
+            let mut new_rules = Vec::new();
+
+            while let Some(group) = first_shared_start(&rewrite) {
+                let prefix_len = common_prefix_len(&rewrite, &group);
+                let new_rule: Name = (1..)
+                    .map(|n| format!("{}.suffix{}", rule, n))
+                    .find(|name| **name != *rule && !self.rules.contains_key(name.as_str()) && !new_rules.iter().any(|(taken, _, _): &(Name, _, _)| **taken == **name))
+                    .expect("there are always more names")
+                    .into();
+
+                // Keep the first alternative in the group, cut down to the
+                // prefix, and move the suffixes of every alternative in it
+                let suffixes: Vec<Alternative> = group.iter()
+                    .map(|&index| rewrite[index].split_off(prefix_len))
+                    .collect();
+                // A new rule whose weights are all zero is never reached
+                let suffix_weights = weights.as_mut()
+                    .map(|weights| {
+                        let suffix_weights: Vec<f64> = group.iter().map(|&index| weights[index]).collect();
+                        for &index in group[1..].iter().rev() {
+                            weights.remove(index);
+                        }
+                        weights[group[0]] = suffix_weights.iter().sum();
+                        suffix_weights
+                    })
+                    .filter(|suffix_weights| suffix_weights.iter().any(|weight| *weight > 0.0));
+                for &index in group[1..].iter().rev() {
+                    rewrite.remove(index);
+                }
+                let factored = &mut rewrite[group[0]];
+                factorings.push(Factoring {
+                    rule: rule.to_string(),
+                    prefix: factored.clone(),
+                    alternatives: group.len(),
+                    new_rule: new_rule.to_string(),
+                });
+                factored.push(Symbol::Nonterminal(new_rule.clone(), None));
+                new_rules.push((new_rule, suffixes, suffix_weights));
+            }
+
+            // New rules go right after the rule they came from
+            let location = self.rules.location(&rule);
+            let after = self.rules.get_index_of(&rule).expect("only defined rules are factored") + 1;
+            for (new_rule, suffixes, suffix_weights) in new_rules.into_iter().rev() {
+                if let Some(suffix_weights) = suffix_weights {
+                    self.weights.insert(new_rule.clone(), suffix_weights.into());
+                }
+                self.rules.shift_insert(after, &new_rule, &suffixes);
+                if let Some(location) = &location {
+                    self.rules.set_location(after, location);
+                }
+                pending.push(new_rule);
+            }
+            if let Some(weights) = weights {
+                self.weights.insert(rule.clone(), weights.into());
+            }
+            self.rules.insert(&rule, &rewrite);
+        }
+
+        return factorings;
+    }
+}
+
+// The indices of the first group of alternatives which start with the same
+// symbol, if there is one
+fn first_shared_start(rewrite: &Rewrite) -> Option<Vec<usize>> {
+    for (index, alternative) in rewrite.iter().enumerate() {
+        let Some(first) = alternative.first() else {
+            continue;
+        };
+        let group: Vec<usize> = (index..rewrite.len())
+            .filter(|&other| rewrite[other].first() == Some(first))
+            .collect();
+        if group.len() > 1 {
+            return Some(group);
+        }
+    }
+    return None;
+}
+
+// How many symbols every alternative in the group starts with
+fn common_prefix_len(rewrite: &Rewrite, group: &[usize]) -> usize {
+    let first = &rewrite[group[0]];
+    (0..first.len())
+        .take_while(|&position| group.iter().all(|&index| rewrite[index].get(position) == Some(&first[position])))
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::enumerate::enumerate;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    fn factor(text: &str) -> (Grammar, Grammar, Vec<Factoring>) {
+        let original = parse_str(text, SourceName::Builtin).unwrap();
+        let mut factored = parse_str(text, SourceName::Builtin).unwrap();
+        let factorings = factored.left_factor();
+        return (original, factored, factorings);
+    }
+
+    #[test]
+    fn factor_shared_prefixes() {
+        let (original, factored, factorings) = factor(concat!(
+            "greeting = \"good \" \"morning\" | \"hello\" | \"good \" \"evening\" name | \"good \" \"evening\"\n",
+            "name = \"jo\"\n",
+            "greeting.suffix1 = \"taken\"\n",
+        ));
+
+        let terminal = |text: &str| Symbol::Terminal(text.into());
+        let nonterminal = |name: &str| Symbol::Nonterminal(name.into(), None);
+        assert_eq!(factored.rules.rewrite("greeting"), vec![
+            vec![terminal("good "), nonterminal("greeting.suffix2")],
+            vec![terminal("hello")],
+        ]);
+        assert_eq!(factored.rules.rewrite("greeting.suffix2"), vec![
+            vec![terminal("morning")],
+            vec![terminal("evening"), nonterminal("greeting.suffix2.suffix1")],
+        ]);
+        assert_eq!(factored.rules.rewrite("greeting.suffix2.suffix1"), vec![vec![nonterminal("name")], vec![]]);
+        assert_eq!(factored.rules.rewrite("greeting.suffix1"), original.rules.rewrite("greeting.suffix1"));
+        assert_eq!(factored.location("greeting.suffix2.suffix1"), original.location("greeting"));
+
+        assert_eq!(factorings.iter().map(ToString::to_string).collect::<Vec<String>>(), vec![
+            "Factored `\"good \"` out of 3 alternatives of `greeting` into `greeting.suffix2`",
+            "Factored `\"evening\"` out of 2 alternatives of `greeting.suffix2` into `greeting.suffix2.suffix1`",
+        ]);
+        assert_eq!(enumerate(&factored, "greeting", 10), enumerate(&original, "greeting", 10));
+    }
+
+    #[test]
+    fn leave_distinct_alternatives_alone() {
+        let (original, factored, factorings) = factor("a = \"x\" b | b \"x\" | \"\"\nb = \"y\" | b:upper");
+        assert_eq!(factorings, Vec::new());
+        assert_eq!(factored, original);
+    }
+
+    #[test]
+    fn keep_language_of_recursive_rules() {
+        let english = std::fs::read_to_string("example_data/english.bnf").unwrap();
+        for text in [english.as_str(), "list = item \", \" list | item | item \" and \" item\nitem = \"a\" | \"b\""] {
+            let (original, factored, factorings) = factor(text);
+            assert!(!factorings.is_empty());
+
+            // Each rule here is factored at most once, so an original
+            // expansion becomes at most two nested ones. Twice the depth
+            // covers every original derivation, and no factored derivation
+            // is shallower than the original it stands for.
+            let start = &original.start_symbol;
+            assert!(enumerate(&original, start, 5).is_subset(&enumerate(&factored, start, 10)));
+            assert!(enumerate(&factored, start, 5).is_subset(&enumerate(&original, start, 5)));
+        }
+    }
+}
@@ -0,0 +1,119 @@
+/*
+    Writes reference documentation for a grammar as Markdown, for publishing
+    alongside it.
+
+    A header gives the start symbol and the grammar's size, then a table of
+    contents links to a section for each rule, in the order they were
+    defined. Each section has the rule as BNF, the rules using it and the
+    ones it uses as links to their sections, its annotations, and a few
+    sentences it generates. The sentences are seeded, so the document only
+    changes when the grammar does. The rules made for brackets are written
+    as the brackets in the rules they're in, which count what the brackets
+    use as their own, rather than having sections.
+*/
+
+use itertools::Itertools;
+
+use crate::analysis::{counts, referenced_symbols, terminals::escape};
+use crate::generator::{generate_from, Xoshiro256StarStar};
+use super::{deprecation_to_bnf, written_rule_to_bnf, Bracket, Grammar};
+
+// A link to the section of a rule. Sections are given anchors of their own
+// rather than relying on how headings are turned into anchors, which
+// differs between renderers and loses the dots in names like `noun.phrase`.
+fn link(rule: &str) -> String {
+    return format!("[`{}`](#rule-{})", rule, rule);
+}
+
+// The rules as links, or `none`
+fn links<'a>(rules: impl Iterator<Item = &'a str>) -> String {
+    let links = rules.map(link).join(", ");
+    return if links.is_empty() { "none".to_string() } else { links };
+}
+
+// The rule whose brackets a rule was made for, or the rule itself
+fn owner(rule: &str) -> &str {
+    return Bracket::of_rule(rule).map_or(rule, |(owner, _)| owner);
+}
+
+// The document for the grammar under a title, with example sentences
+// generated from each rule. Each rule's examples start from the seed, so
+// changing one rule doesn't change the examples of rules not using it.
+pub fn to_markdown(grammar: &Grammar, title: &str, examples: usize, seed: u64) -> String {
+    let mut markdown = format!("# {}\n\n", title);
+    if grammar.rules.contains_key(grammar.start_symbol.as_str()) {
+        markdown += &format!("Start symbol: {}\n\n", link(&grammar.start_symbol));
+    }
+    let counts = counts(grammar);
+    markdown += "| Rules | Alternatives | Terminals | Nonterminals |\n| ---: | ---: | ---: | ---: |\n";
+    markdown += &format!("| {} | {} | {} | {} |\n\n", counts.rules, counts.alternatives, counts.terminals, counts.nonterminals);
+
+    let rules = grammar.rules.keys().filter(|rule| Bracket::of_rule(rule).is_none());
+    markdown += "## Contents\n\n";
+    for rule in rules.clone() {
+        markdown += &format!("- {}\n", link(rule));
+    }
+
+    for rule in rules {
+        markdown += &format!("\n## <a id=\"rule-{}\"></a>`{}`\n\n", rule, rule);
+        markdown += &format!("```bnf\n{}\n```\n\n", written_rule_to_bnf(grammar, rule));
+
+        let referenced_by = grammar.rules.iter()
+            .filter(|(_, other)| referenced_symbols(*other).any(|symbol| symbol == rule))
+            .map(|(other, _)| owner(other))
+            .unique();
+        markdown += &format!("- Referenced by: {}\n", links(referenced_by));
+        let references = grammar.rules.iter()
+            .filter(|(other, _)| owner(other) == rule)
+            .flat_map(|(_, rewrite)| referenced_symbols(rewrite))
+            .filter(|symbol| grammar.rules.contains_key(symbol) && Bracket::of_rule(symbol).is_none())
+            .unique();
+        markdown += &format!("- References: {}\n", links(references));
+        if grammar.weights.contains_key(rule) {
+            markdown += "- Weighted: alternatives aren't picked equally often\n";
+        }
+        if let Some(annotations) = grammar.annotations.get(rule) {
+            let mut lines = annotations.iter().map(|(key, values)| format!("`;@ {}`", std::iter::once(key).chain(values).join(" ")));
+            markdown += &format!("- Annotations: {}\n", lines.join(", "));
+        }
+        if let Some(deprecation) = grammar.deprecations.get(rule) {
+            markdown += &format!("- Deprecated: `{}`\n", deprecation_to_bnf(rule, &deprecation.message));
+        }
+
+        if examples > 0 {
+            let mut rng = Xoshiro256StarStar::new(seed);
+            markdown += &format!("\nExamples (`--seed {}`):\n\n```text\n", seed);
+            for _ in 0..examples {
+                match generate_from(grammar, rule, &mut rng) {
+                    Ok(sentence) => markdown += &escape(&sentence),
+                    Err(error) => markdown += &format!("(failed: {})", error.error),
+                }
+                markdown.push('\n');
+            }
+            markdown += "```\n";
+        }
+    }
+    return markdown;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn write_brackets_in_their_rules() {
+        let grammar = parse_str(concat!(
+            "greeting = (\"hi\" | 2 \"hello\") [\" \" name] {\"!\"}\n",
+            "name = \"Al\" | \"Bo\" [\" \" greeting]\n",
+            "farewell = \"bye\"\n",
+        ), SourceName::Builtin).unwrap();
+        let markdown = to_markdown(&grammar, "greetings", 0, 0);
+        assert!(markdown.contains("| 3 | 9 | 8 | 2 |\n"), "{}", markdown);
+        assert!(markdown.contains("- [`greeting`](#rule-greeting)\n- [`name`](#rule-name)\n- [`farewell`](#rule-farewell)\n\n"), "{}", markdown);
+        assert!(!markdown.contains("greeting("), "{}", markdown);
+        assert!(markdown.contains("```bnf\ngreeting = (1 \"hi\" | 2 \"hello\") [\" \" name] {\"!\"}\n```\n\n- Referenced by: [`name`](#rule-name)\n- References: [`name`](#rule-name)\n"), "{}", markdown);
+        assert!(markdown.contains("- Referenced by: [`greeting`](#rule-greeting)\n- References: [`greeting`](#rule-greeting)\n"), "{}", markdown);
+    }
+}
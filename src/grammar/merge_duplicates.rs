@@ -0,0 +1,103 @@
+/*
+    Merging duplicate rules keeps one of each group of rules with the same
+    definition, and has every reference to the others use it instead. The
+    grammar generates the same sentences with the same probabilities
+    afterwards. Merging can make more rules the same, like two rules which
+    each referenced one of the merged rules, so it's repeated until no
+    duplicates are left.
+*/
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use itertools::Itertools;
+
+use super::*;
+use crate::analysis::duplicates::duplicate_rules;
+
+// The rules replaced by one with the same definition
+#[derive(Debug, PartialEq)]
+pub struct Consolidation {
+    pub kept: String,
+    pub dropped: Vec<String>,
+}
+
+impl Display for Consolidation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f, "Merged {} into `{}`, which has the same definition",
+            self.dropped.iter().map(|rule| format!("`{}`", rule)).join(", "), self.kept
+        )
+    }
+}
+
+impl Grammar {
+    // Merges each group of rules with the same definition into its first
+    // rule in definition order, which is the start symbol if it's in the
+    // group, so the start symbol is never dropped
+    pub fn merge_duplicates(&mut self, ignore_order: bool) -> Vec<Consolidation> {
+        let mut consolidations = Vec::new();
+        loop {
+            let groups: Vec<Vec<String>> = duplicate_rules(self, ignore_order).into_iter()
+                .map(|group| group.into_iter().map(str::to_string).collect())
+                .collect();
+            if groups.is_empty() {
+                return consolidations;
+            }
+
+            let mut replacements = HashMap::new();
+            for mut group in groups {
+                let kept = group.remove(0);
+                for dropped in &group {
+                    self.rules.shift_remove(dropped.as_str());
+                    self.weights.remove(dropped.as_str());
+                    self.annotations.remove(dropped.as_str());
+                    self.deprecations.remove(dropped.as_str());
+                    replacements.insert(dropped.clone(), kept.clone());
+                }
+                consolidations.push(Consolidation { kept, dropped: group });
+            }
+            self.rules.redirect(replacements.iter().map(|(dropped, kept)| (dropped.as_str(), kept.as_str())));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::enumerate::enumerate;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn merge_into_the_first_rule() {
+        let text = concat!(
+            "sentence = shade \" \" hue \" \" color:upper | colour\n",
+            "colour = \"red\" | \"blue\"\n",
+            "shade = \"light \" colour\n",
+            "color = \"red\" | \"blue\"\n",
+            "hue = \"light \" color\n",
+        );
+        let original = parse_str(text, SourceName::Builtin).unwrap();
+        let mut merged = parse_str(text, SourceName::Builtin).unwrap();
+        let consolidations = merged.merge_duplicates(false);
+
+        assert_eq!(consolidations.iter().map(ToString::to_string).collect::<Vec<String>>(), [
+            "Merged `color` into `colour`, which has the same definition",
+            "Merged `hue` into `shade`, which has the same definition",
+        ]);
+        assert_eq!(merged.rules.keys().sorted().collect::<Vec<_>>(), ["colour", "sentence", "shade"]);
+        assert_eq!(merged.rules.rewrite("sentence").to_rewrite()[0][4], Symbol::Nonterminal("colour".into(), Some(Case::Upper)));
+        assert_eq!(merged.rules.location("hue"), None);
+        assert_eq!(enumerate(&merged, "sentence", 5), enumerate(&original, "sentence", 5));
+    }
+
+    #[test]
+    fn keep_the_start_symbol() {
+        let mut grammar = parse_str("a = \"x\" | \"y\"\nb = \"y\" | \"x\"\nc = b a", SourceName::Builtin).unwrap();
+        grammar.start_symbol = "b".to_string();
+        assert_eq!(grammar.merge_duplicates(false), []);
+        assert_eq!(grammar.merge_duplicates(true), [Consolidation { kept: "b".to_string(), dropped: vec!["a".to_string()] }]);
+        assert_eq!(grammar.rules.rewrite("c"), vec![vec![Symbol::Nonterminal("b".into(), None), Symbol::Nonterminal("b".into(), None)]]);
+    }
+}
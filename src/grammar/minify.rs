@@ -0,0 +1,244 @@
+/*
+    Minifying writes a grammar as the smallest BNF that generates the same
+    sentences from the same seeds. Rules are renamed to the shortest names
+    going, in definition order, so the start symbol becomes `a` and stays
+    first. Adjacent terminals are joined, and only the spaces the lexer needs
+    to find where a nonterminal ends are kept. Comments are left out, but
+    annotations are kept, since they change what's generated. Brackets are
+    written back as brackets, which are shorter than the rules made for them.
+*/
+
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+
+use super::*;
+use super::export::{definition_order, held, uses_dynamics, writable, written_weights, ExportError};
+use crate::json;
+
+// The name at index in the order a, b, ..., z, aa, ab, ..., zz, aaa, ...
+fn short_name(mut index: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'a' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    name.reverse();
+    return String::from_utf8(name).expect("the names are ASCII");
+}
+
+impl Grammar {
+    // Renames every rule to a short name, in definition order, returning
+    // each old name with its new one in that order. Nonterminals used but
+    // not defined keep their names, so no short name is one of them. The
+    // rules made for brackets are named after their rule's new name, and
+    // aren't returned.
+    pub fn minify_names(&mut self) -> Vec<(String, String)> {
+        let undefined: HashSet<&str> = self.rules.values().flatten().flatten()
+            .filter_map(|symbol| match symbol {
+                SymbolRef::Nonterminal(name, _) if !self.rules.contains_key(name) => Some(name),
+                _ => None
+            })
+            .collect();
+        let names: Vec<(String, String)> = definition_order(self).into_iter()
+            .filter(|rule| Bracket::of_rule(rule).is_none())
+            .zip((0..).map(short_name).filter(|name| !undefined.contains(name.as_str())))
+            .collect();
+        let mut renames: HashMap<String, String> = names.iter().cloned().collect();
+        for rule in self.rules.keys() {
+            if let Some((of, _)) = Bracket::of_rule(rule) {
+                if let Some(new) = renames.get(of) {
+                    renames.insert(rule.to_string(), format!("{}{}", new, &rule[of.len()..]));
+                }
+            }
+        }
+        self.rename_symbols(&renames);
+        return names;
+    }
+}
+
+// The names minify_names gave as a JSON object from old names to new ones
+pub fn names_to_json(names: &[(String, String)]) -> String {
+    let mut pairs = names.iter().map(|(old, new)| format!("{}:{}", json::string(old), json::string(new)));
+    return format!("{{{}}}", pairs.join(","));
+}
+
+// An alternative of rule in as few characters as it can be read back from
+fn minify_alternative(grammar: &Grammar, rule: &str, alternative: AlternativeRef<'_>) -> Result<String, ExportError> {
+    // Adjacent terminals are joined, unless that would make one that can't
+    // be written
+    let mut symbols: Vec<Symbol> = Vec::new();
+    for symbol in alternative {
+        match (symbols.last_mut(), symbol) {
+            (Some(Symbol::Terminal(joined)), SymbolRef::Terminal(text)) if writable(&(joined.to_string() + text)) => *joined = (joined.to_string() + text).into(),
+            (_, SymbolRef::Terminal("")) => {}
+            _ => symbols.push(symbol.to_symbol()),
+        }
+    }
+    if symbols.is_empty() {
+        return Ok("\"\"".to_string());
+    }
+    // A nonterminal or dynamic symbol takes everything up to the next
+    // whitespace or bracket
+    let mut text = String::new();
+    for (index, symbol) in symbols.iter().enumerate() {
+        match symbol {
+            Symbol::Terminal(text) if !writable(text) => return Err(ExportError::Unwritable { rule: rule.to_string(), terminal: text.to_string() }),
+            Symbol::Nonterminal(name, None) => if let Some(held) = held(grammar, name)? {
+                // A `(` straight after a dynamic symbol would start its
+                // arguments
+                let arguments = held.bracket == Bracket::Group && index > 0 && matches!(symbols[index - 1], Symbol::Dynamic(_));
+                if text.ends_with(' ') && !arguments {
+                    text.pop();
+                }
+                text.push(held.bracket.open());
+                text += minify_rewrite(grammar, held.of, held.rewrite, held.weights.as_deref())?.trim_end();
+                text.push(held.bracket.close());
+                continue;
+            },
+            _ => {}
+        }
+        text += &symbol.to_string();
+        if matches!(symbol, Symbol::Nonterminal(..) | Symbol::Dynamic(_)) {
+            text.push(' ');
+        }
+    }
+    return Ok(text);
+}
+
+// The alternatives of a rewrite of rule, minified and split by `|`s, with
+// their weights if they have them
+fn minify_rewrite<'g>(grammar: &Grammar, rule: &str, rewrite: impl IntoIterator<Item = AlternativeRef<'g>>, weights: Option<&[u32]>) -> Result<String, ExportError> {
+    let mut alternatives = Vec::new();
+    for (index, alternative) in rewrite.into_iter().enumerate() {
+        match weights {
+            Some(weights) => alternatives.push(format!("{} {}", weights[index], minify_alternative(grammar, rule, alternative)?)),
+            None => alternatives.push(minify_alternative(grammar, rule, alternative)?),
+        }
+    }
+    return Ok(alternatives.join("|"));
+}
+
+// Writes the rules named in order as minified BNF, with the start symbol
+// first
+pub fn to_minified_bnf(grammar: &Grammar, order: &[String]) -> Result<String, ExportError> {
+    let mut bnf = String::new();
+    if uses_dynamics(grammar, order) {
+        bnf += "%use dynamics\n";
+    }
+    for rule in order.iter().filter(|rule| Bracket::of_rule(rule).is_none()) {
+        let weights = written_weights(grammar, rule)?;
+        for (key, values) in grammar.annotations.get(rule.as_str()).into_iter().flatten() {
+            bnf += &format!(";@{}\n", std::iter::once(key).chain(values).join(" "));
+        }
+
+        let rewrite = grammar.rules.rewrite(rule.as_str());
+        // A rule with one empty alternative needs nothing after the `=`
+        let alternatives = match rewrite.get(0) {
+            Some(alternative) if rewrite.len() == 1 && alternative.iter().all(|symbol| symbol == SymbolRef::Terminal("")) => String::new(),
+            _ => minify_rewrite(grammar, rule, rewrite, weights.as_deref())?,
+        };
+        bnf += &format!("{} ={}", rule, alternatives.trim_end());
+        bnf.push('\n');
+    }
+    return Ok(bnf);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::generator::{generate_from, Xoshiro256StarStar};
+    use crate::parser::{parse_file, parse_str, parse_str_with_options, ParseOptions};
+
+    fn minify(mut grammar: Grammar) -> (String, Vec<(String, String)>) {
+        let names = grammar.minify_names();
+        return (to_minified_bnf(&grammar, &definition_order(&grammar)).unwrap(), names);
+    }
+
+    fn sentences(grammar: &Grammar, seed: u64) -> Vec<String> {
+        let mut rng = Xoshiro256StarStar::new(seed);
+        (0..50).map(|_| generate_from(grammar, &grammar.start_symbol, &mut rng).unwrap()).collect()
+    }
+
+    #[test]
+    fn name_shortly() {
+        let names: Vec<String> = [0, 1, 25, 26, 27, 51, 52, 701, 702].into_iter().map(short_name).collect();
+        assert_eq!(names, ["a", "b", "z", "aa", "ab", "az", "ba", "zz", "aaa"]);
+    }
+
+    #[test]
+    fn minify_grammars() {
+        let text = concat!(
+            "; a comment\n",
+            "sentence =   greeting \" \"  \"there\" name:title | \"\" \"\" |  sentence.end\n",
+            ";@ distinct 5\n",
+            "greeting = 3 \"hi\" | \"hello\"\n",
+            "name = r#\"\"jo\"\"# \"\\n\"\n",
+            "sentence.end =\n",
+        );
+        let grammar = parse_str(text, SourceName::Builtin).unwrap();
+        let (bnf, names) = minify(parse_str(text, SourceName::Builtin).unwrap());
+        assert_eq!(bnf, concat!(
+            "a =b \" there\"c:title |\"\"|d\n",
+            ";@distinct 5\n",
+            "b =3 \"hi\"|1 \"hello\"\n",
+            // A quote and a newline can't be in one terminal
+            "c =r#\"\"jo\"\"#\"\\n\"\n",
+            "d =\n",
+        ));
+        assert_eq!(names_to_json(&names), "{\"sentence\":\"a\",\"greeting\":\"b\",\"name\":\"c\",\"sentence.end\":\"d\"}");
+
+        let reparsed = parse_str(&bnf, SourceName::Builtin).unwrap();
+        assert_eq!(reparsed.annotations["b"]["distinct"], ["5"]);
+        assert_eq!(minify(parse_str(&bnf, SourceName::Builtin).unwrap()).0, bnf);
+        for seed in 0..5 {
+            assert_eq!(sentences(&reparsed, seed), sentences(&grammar, seed));
+        }
+    }
+
+    #[test]
+    fn minify_brackets() {
+        let text = "list = item {\", \" item} [\" \" \"!\"]\n;@ repeat 0.7\nitem = \"x\" | \"y\" {2 \"z\" | item}";
+        let grammar = parse_str(text, SourceName::Builtin).unwrap();
+        let (bnf, names) = minify(parse_str(text, SourceName::Builtin).unwrap());
+        assert_eq!(bnf, "a =b{\", \"b}[\" !\"]\n;@repeat 0.7\nb =\"x\"|\"y\"{2 \"z\"|1 b}\n");
+        assert_eq!(names, [("list".to_string(), "a".to_string()), ("item".to_string(), "b".to_string())]);
+
+        let reparsed = parse_str(&bnf, SourceName::Builtin).unwrap();
+        assert_eq!(minify(parse_str(&bnf, SourceName::Builtin).unwrap()).0, bnf);
+        for seed in 0..5 {
+            assert_eq!(sentences(&reparsed, seed), sentences(&grammar, seed));
+        }
+
+        // A group straight after a dynamic symbol would be its arguments
+        let text = "%use dynamics\ns = t (\"a\" | \"b\") @uuid (\"c\")\nt = \"x\"";
+        let (bnf, _) = minify(parse_str(text, SourceName::Builtin).unwrap());
+        assert_eq!(bnf, "%use dynamics\na =b(\"a\"|\"b\")@uuid (\"c\")\nb =\"x\"\n");
+        assert_eq!(minify(parse_str(&bnf, SourceName::Builtin).unwrap()).0, bnf);
+    }
+
+    #[test]
+    fn keep_the_output_of_examples() {
+        let path = std::path::PathBuf::from("example_data/english.bnf");
+        let grammar = parse_file(&path).unwrap();
+        let (bnf, _) = minify(parse_file(&path).unwrap());
+        let reparsed = parse_str(&bnf, SourceName::Builtin).unwrap();
+        assert_eq!(reparsed.rules.len(), grammar.rules.len());
+        for seed in 0..5 {
+            assert_eq!(sentences(&reparsed, seed), sentences(&grammar, seed));
+        }
+    }
+
+    #[test]
+    fn skip_names_of_undefined_rules() {
+        let unverified = ParseOptions { verify: false, ..Default::default() };
+        let (grammar, _) = parse_str_with_options("s = a t\nt = b", SourceName::Builtin, &unverified).unwrap();
+        let (bnf, names) = minify(grammar);
+        assert_eq!(bnf, "c =a d\nd =b\n");
+        assert_eq!(names, [("s".to_string(), "c".to_string()), ("t".to_string(), "d".to_string())]);
+    }
+}
@@ -2,24 +2,339 @@
     This module is for storing and manipulating grammars
 */
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::ops::Deref;
+use std::sync::Arc;
 
-// The base unit in a grammar rule
-#[derive(Debug, PartialEq)]
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::error_handling::{Location, SourceName};
+
+mod dynamic;
+mod export;
+mod left_factor;
+mod markdown;
+mod merge_duplicates;
+mod minify;
+mod rename;
+mod rules;
+mod tags;
+mod yaml;
+pub use dynamic::{days_from_civil, days_in_month, format_specifiers, Dynamic, DATE_SPECIFIERS, DYNAMICS, FIRST_DAY, LAST_DAY, MAX_PLACES};
+pub use export::{definition_order, deprecation_to_bnf, rule_to_bnf, to_bnf, written_rule_to_bnf, ExportError, RuleTrivia, Trivia};
+pub use left_factor::Factoring;
+pub use markdown::to_markdown;
+pub use merge_duplicates::Consolidation;
+pub use minify::{names_to_json, to_minified_bnf};
+pub use rules::{AlternativeRef, Alternatives, Iter, RewriteRef, Rules, SymbolRef, Symbols};
+pub(crate) use rules::Tables;
+pub use tags::{parse_tag, renumber_tags, tags_of};
+pub use yaml::to_yaml;
+
+// A change of case applied to a nonterminal's expansion, written as a suffix
+// like `name:title`
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub enum Case {
+    Upper,
+    Lower,
+    // Only the first grapheme is uppercased, the rest is left alone
+    Title,
+}
+
+impl Case {
+    pub fn from_suffix(suffix: &str) -> Option<Case> {
+        match suffix {
+            "upper" => Some(Case::Upper),
+            "lower" => Some(Case::Lower),
+            "title" => Some(Case::Title),
+            _ => None
+        }
+    }
+
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Case::Upper => text.to_uppercase(),
+            Case::Lower => text.to_lowercase(),
+            Case::Title => match text.graphemes(true).next() {
+                Some(first) => first.to_uppercase() + &text[first.len()..],
+                None => String::new()
+            },
+        }
+    }
+}
+
+// A kind of bracket grouping symbols in a rewrite, like `[" " middle]`.
+// What a pair of brackets holds is made into a rule of its own, named after
+// the rule it's in and numbered in the order the brackets open, like
+// `name[1]`. Brackets end nonterminals, so no rule written in a grammar can
+// have the same name.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Bracket {
+    // What's inside is left out half the time
+    Optional,
+    // What's inside is repeated any number of times, including none, each
+    // repetition followed by another by a chance given by `;@ repeat`
+    Repeat,
+    // What's inside is always there, with `|`s choosing between its
+    // alternatives rather than the whole alternative's
+    Group,
+}
+
+impl Bracket {
+    pub const ALL: [Bracket; 3] = [Bracket::Optional, Bracket::Repeat, Bracket::Group];
+
+    pub fn open(self) -> char {
+        match self {
+            Bracket::Optional => '[',
+            Bracket::Repeat => '{',
+            Bracket::Group => '(',
+        }
+    }
+
+    pub fn close(self) -> char {
+        match self {
+            Bracket::Optional => ']',
+            Bracket::Repeat => '}',
+            Bracket::Group => ')',
+        }
+    }
+
+    pub fn from_open(c: char) -> Option<Bracket> {
+        Bracket::ALL.into_iter().find(|bracket| bracket.open() == c)
+    }
+
+    pub fn from_close(c: char) -> Option<Bracket> {
+        Bracket::ALL.into_iter().find(|bracket| bracket.close() == c)
+    }
+
+    // Whether c is a bracket of any kind, which nonterminals can't hold
+    pub fn is_bracket(c: char) -> bool {
+        Bracket::ALL.into_iter().any(|bracket| bracket.open() == c || bracket.close() == c)
+    }
+
+    // The name of the rule made for the index-th brackets of rule, counting
+    // from 1
+    pub fn rule_name(self, rule: &str, index: usize) -> String {
+        format!("{}{}{}{}", rule, self.open(), index, self.close())
+    }
+
+    // The rule whose brackets a rule was made for, and their kind, if it
+    // was made for some
+    pub fn of_rule(name: &str) -> Option<(&str, Bracket)> {
+        let bracket = Bracket::from_close(name.chars().last()?)?;
+        let (rule, index) = name[..name.len() - 1].rsplit_once(bracket.open())?;
+        return (!index.is_empty() && index.bytes().all(|byte| byte.is_ascii_digit())).then_some((rule, bracket));
+    }
+}
+
+// The name of a rule, in the symbols of rewrites built in code and as the
+// key of what's kept about some rules, like their weights. The rules
+// themselves keep every name once in their own tables.
+pub type Name = Arc<str>;
+
+// The base unit in a grammar rule, as it's built in code. Grammars keep
+// their rules in tables and lend them out as SymbolRefs instead.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone)]
 pub enum Symbol {
-    Terminal(String),
-    Nonterminal(String),
+    Terminal(Box<str>),
+    // A reference to a rule, with the case to put its expansion in
+    Nonterminal(Name, Option<Case>),
+    // A value made each time it's generated, like a date from a range
+    Dynamic(Box<Dynamic>),
+}
+
+impl Display for Case {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Case::Upper => write!(f, "upper"),
+            Case::Lower => write!(f, "lower"),
+            Case::Title => write!(f, "title"),
+        }
+    }
+}
+
+// How many `#`s a terminal needs to be written raw, if it has text a quoted
+// terminal can't hold: a quote, or a backslash before `n`, which would be
+// read as a newline. Raw terminals can't hold newlines, so terminals with
+// one are never raw.
+fn raw_hashes(text: &str) -> Option<usize> {
+    if text.contains('\n') || (!text.contains('"') && !text.contains("\\n")) {
+        return None;
+    }
+    return (0..).find(|&hashes| !text.contains(&format!("\"{}", "#".repeat(hashes))));
+}
+
+impl Symbol {
+    // The symbol as grammars lend theirs out, borrowing its text
+    pub fn view(&self) -> SymbolRef<'_> {
+        match self {
+            Symbol::Terminal(text) => SymbolRef::Terminal(text),
+            Symbol::Nonterminal(name, case) => SymbolRef::Nonterminal(name, *case),
+            Symbol::Dynamic(dynamic) => SymbolRef::Dynamic(dynamic),
+        }
+    }
+}
+
+impl Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.view().fmt(f)
+    }
 }
 
-// The symbols in a single alternative
+// Applies the case, if any, to an expansion
+pub fn apply_case(case: Option<Case>, text: String) -> String {
+    match case {
+        Some(case) => case.apply(&text),
+        None => text
+    }
+}
+
+// The symbols in a single alternative, as it's built in code
 pub type Alternative = Vec<Symbol>;
 
-// The alternatives of a rewrite rule
+// The alternatives of a rewrite rule, as it's built in code
 pub type Rewrite = Vec<Alternative>;
 
-#[derive(Debug, PartialEq)]
+// Metadata given to a rule by `;@ KEY VALUES...` comments directly above it,
+// as each key's values in order. A key written more than once has the values
+// from every line.
+pub type Annotations = BTreeMap<String, Vec<String>>;
+
+#[derive(Debug)]
 pub struct Grammar {
     pub start_symbol: String,
-    pub rules: HashMap<String, Rewrite>,
+    // Each rule's alternatives and where it was defined
+    pub rules: Rules,
+    // The annotations of each rule which has any
+    pub annotations: HashMap<Name, Annotations>,
+    // The weights of rules which don't pick their alternatives equally often
+    pub weights: HashMap<Name, Weights>,
+    // The rules deprecated by `%deprecated`, which still work but are warned
+    // about wherever they're used
+    pub deprecations: HashMap<Name, Deprecation>,
+}
+
+// Why a rule is deprecated, and where the `%deprecated` saying so was
+#[derive(Debug, PartialEq, Clone)]
+pub struct Deprecation {
+    // What to use instead, or anything else said about it, which can be empty
+    pub message: String,
+    pub location: Location,
+}
+
+// Where rules added by a program, rather than parsed, are said to be from
+static CONSTRUCTED: Location = Location { source: SourceName::Builtin, line: None };
+
+impl Grammar {
+    // A grammar of one rule, which is its start symbol, for building grammars
+    // in code
+    pub fn new(start_symbol: &str, rewrite: Rewrite) -> Self {
+        let mut grammar = Grammar {
+            start_symbol: start_symbol.to_string(),
+            rules: Rules::new(),
+            annotations: HashMap::new(),
+            weights: HashMap::new(),
+            deprecations: HashMap::new(),
+        };
+        grammar.insert_rule(start_symbol, rewrite);
+        return grammar;
+    }
+
+    // Adds a rule, or replaces one along with its weights and annotations,
+    // located at the whole of `<builtin>`
+    pub fn insert_rule(&mut self, rule: &str, rewrite: Rewrite) {
+        let index = self.rules.insert(rule, &rewrite);
+        self.rules.set_location(index, &CONSTRUCTED);
+        self.weights.remove(rule);
+        self.annotations.remove(rule);
+    }
+
+    // Where a rule was defined. Rules added to the rules without a
+    // location are at the whole of `<builtin>`, like inserted ones.
+    pub fn location(&self, rule: &str) -> Location {
+        self.rules.location(rule).unwrap_or_else(|| CONSTRUCTED.clone())
+    }
+}
+
+// Grammars are the same if their rules are defined in the same places too
+impl PartialEq for Grammar {
+    fn eq(&self, other: &Self) -> bool {
+        self.start_symbol == other.start_symbol
+            && self.rules == other.rules
+            && self.rules.keys().all(|rule| self.rules.location(rule) == other.rules.location(rule))
+            && self.annotations == other.annotations
+            && self.weights == other.weights
+            && self.deprecations == other.deprecations
+    }
+}
+
+// How likely each alternative of a rule is to be picked, relative to the
+// others. Every weight is finite and not negative, and at least one is
+// positive. Their running totals are kept too, so an alternative can be
+// picked by binary search however many there are.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Weights {
+    weights: Vec<f64>,
+    cumulative: Vec<f64>,
 }
 
+impl Weights {
+    pub fn new(weights: Vec<f64>) -> Self {
+        let mut total = 0.0;
+        let cumulative = weights.iter().map(|weight| {
+            total += weight;
+            total
+        }).collect();
+        Weights { weights, cumulative }
+    }
+
+    // The total of each weight and the ones before it
+    pub fn cumulative(&self) -> &[f64] {
+        &self.cumulative
+    }
+}
+
+impl Deref for Weights {
+    type Target = [f64];
+
+    fn deref(&self) -> &[f64] {
+        &self.weights
+    }
+}
+
+impl From<Vec<f64>> for Weights {
+    fn from(weights: Vec<f64>) -> Self {
+        Weights::new(weights)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn change_case() {
+        assert_eq!(Case::Upper.apply("straße"), "STRASSE");
+        assert_eq!(Case::Lower.apply("ÀB"), "àb");
+        assert_eq!(Case::Title.apply("élan vital"), "Élan vital");
+        assert_eq!(Case::Title.apply("mIxed"), "MIxed");
+        // A letter with a combining accent is one grapheme
+        assert_eq!(Case::Title.apply("e\u{301}clair"), "E\u{301}clair");
+        assert_eq!(Case::Title.apply(""), "");
+    }
+
+    #[test]
+    fn build_grammars_in_code() {
+        let mut grammar = Grammar::new("s", vec![vec![Symbol::Nonterminal("word".into(), None)]]);
+        grammar.insert_rule("word", vec![vec![Symbol::Terminal("hi".into())]]);
+        assert_eq!(definition_order(&grammar), ["s", "word"]);
+        assert_eq!(grammar.location("word").to_string(), "<builtin> (whole file)");
+        assert_eq!(crate::parser::verify_grammar(&grammar), Vec::new());
+
+        grammar.rules.insert("other", &[vec![Symbol::Nonterminal("missing".into(), None)]]);
+        let errors = crate::parser::verify_grammar(&grammar);
+        assert_eq!(errors[0].location, grammar.location("other"));
+    }
+}
@@ -0,0 +1,61 @@
+/*
+    Renaming rules changes their names in the rules, their locations,
+    annotations, weights, and deprecations, every reference to them, and the start symbol
+    all at once, so no reference is left using an old name.
+*/
+
+use std::collections::HashMap;
+
+use super::*;
+
+// Moves each value whose key is renamed to its new key
+fn rename_keys<V>(map: &mut HashMap<Name, V>, names: &HashMap<&str, Name>) {
+    let renamed: Vec<(Name, V)> = names.keys()
+        .filter_map(|old| map.remove_entry(*old))
+        .collect();
+    for (old, value) in renamed {
+        map.insert(names[&*old].clone(), value);
+    }
+}
+
+impl Grammar {
+    // Renames every rule in names to the name it maps to. Every name changes
+    // at the same time, so two rules can swap names, but a new name mustn't
+    // be one of the rules that isn't renamed.
+    pub fn rename_symbols(&mut self, names: &HashMap<String, String>) {
+        // Each new name is made once, so the maps keyed by rule share it
+        let names: HashMap<&str, Name> = names.iter()
+            .map(|(old, new)| (old.as_str(), Name::from(new.as_str())))
+            .collect();
+        self.rules.rename(names.iter().map(|(old, new)| (*old, &**new)));
+        rename_keys(&mut self.annotations, &names);
+        rename_keys(&mut self.weights, &names);
+        rename_keys(&mut self.deprecations, &names);
+        if let Some(new) = names.get(self.start_symbol.as_str()) {
+            self.start_symbol = new.to_string();
+        }
+    }
+
+    pub fn rename_symbol(&mut self, old: &str, new: &str) {
+        self.rename_symbols(&HashMap::from([(old.to_string(), new.to_string())]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn rename_everywhere_at_once() {
+        let mut grammar = parse_str("s = a b:upper | b\n;@ distinct\na = \"x\" a | \"\"\nb = \"y\"", SourceName::Builtin).unwrap();
+        grammar.rename_symbols(&HashMap::from([("a".to_string(), "b".to_string()), ("b".to_string(), "a".to_string())]));
+        grammar.rename_symbol("s", "start");
+        let renamed = parse_str("start = b a:upper | a\n;@ distinct\nb = \"x\" b | \"\"\na = \"y\"", SourceName::Builtin).unwrap();
+        assert_eq!(grammar.rules, renamed.rules);
+        assert_eq!(grammar.annotations, renamed.annotations);
+        assert_eq!(grammar.start_symbol, "start");
+        assert_eq!(grammar.location("b").line, Some(3));
+    }
+}
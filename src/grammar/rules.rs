@@ -0,0 +1,998 @@
+/*
+    A grammar's rules, kept as a few tables of numbers rather than a list of
+    symbols for every alternative, since machine-made grammars have millions
+    of them.
+
+    Every string, name or terminal, is kept once in one run of text, and
+    referred to by its index. Each rule is a run of numbers in one list
+    shared by every rule: how many alternatives it has, where each of them
+    ends among its symbols, and then the symbols, each with its kind in the
+    top bits and the index of its string or dynamic symbol in the rest.
+
+    The rules are read through views borrowing from the tables, RewriteRef,
+    AlternativeRef and SymbolRef, and added from Rewrites built in code or
+    from another grammar's views. Compiled grammars keep the same tables, so
+    loading one checks them and takes them as they are, rather than adding
+    its rules one at a time.
+*/
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
+
+use hashbrown::HashTable;
+
+use crate::error_handling::{Location, SourceName};
+use crate::generator::fnv1a;
+use super::{raw_hashes, Alternative, Case, Dynamic, Rewrite, Symbol};
+
+// A symbol is stored as its kind, shifted into the top bits, and the index
+// of its string or dynamic symbol in the rest. Nonterminals' kinds are their
+// case's index in CASES plus one.
+const KIND_SHIFT: u32 = 29;
+const INDEX_MASK: u32 = (1 << KIND_SHIFT) - 1;
+const TERMINAL: u32 = 0;
+const DYNAMIC: u32 = 5;
+const CASES: [Option<Case>; 4] = [None, Some(Case::Upper), Some(Case::Lower), Some(Case::Title)];
+
+// Stands for a string no rule is named, and a rule without a source
+const NONE: u32 = u32::MAX;
+
+// How much more room to make in a list of len items with room for capacity
+// to add more, if it needs any. Lists grow by an eighth at a time rather
+// than doubling, so a large grammar isn't left with nearly as much room to
+// spare as it uses.
+fn more_room(len: usize, capacity: usize, more: usize) -> Option<usize> {
+    (capacity - len < more).then(|| more.max(len / 8))
+}
+
+fn make_room<T>(list: &mut Vec<T>, more: usize) {
+    if let Some(room) = more_room(list.len(), list.capacity(), more) {
+        list.reserve_exact(room);
+    }
+}
+
+// The index-th of the strings ending at ends in text
+fn slice<'a>(text: &'a str, ends: &[u32], index: u32) -> &'a str {
+    let start = match index {
+        0 => 0,
+        index => ends[index as usize - 1] as usize,
+    };
+    return &text[start..ends[index as usize] as usize];
+}
+
+// The top half of the hash of a string's text, which is what's kept of it
+fn string_hash(text: &str) -> u32 {
+    (fnv1a(text.bytes()) >> 32) as u32
+}
+
+// A kept hash spread over the 64 bits the table finds strings by
+fn spread(hash: u32) -> u64 {
+    u64::from(hash) << 32 | u64::from(hash)
+}
+
+// Strings kept once each, numbered in the order they were added. The names
+// are always found by their text, but terminals loaded from a compiled
+// grammar aren't looked up, so one can be kept again if it's added again.
+#[derive(Clone, Default)]
+struct Strings {
+    text: String,
+    ends: Vec<u32>,
+    // The index of each string which can be found, along with its hash, so
+    // the table can grow without reading every string again
+    table: HashTable<(u32, u32)>,
+}
+
+impl Strings {
+    fn get(&self, index: u32) -> &str {
+        slice(&self.text, &self.ends, index)
+    }
+
+    fn find(&self, text: &str) -> Option<u32> {
+        self.find_hashed(text, string_hash(text))
+    }
+
+    fn find_hashed(&self, text: &str, hash: u32) -> Option<u32> {
+        self.table.find(spread(hash), |&(index, kept)| kept == hash && self.get(index) == text).map(|&(index, _)| index)
+    }
+
+    // Adds a string without making it findable
+    fn push(&mut self, text: &str) -> u32 {
+        let index = u32::try_from(self.ends.len()).expect("grammars have fewer than 2^32 strings");
+        if let Some(room) = more_room(self.text.len(), self.text.capacity(), text.len()) {
+            self.text.reserve_exact(room);
+        }
+        self.text.push_str(text);
+        make_room(&mut self.ends, 1);
+        self.ends.push(u32::try_from(self.text.len()).expect("grammars have less than 4GB of text"));
+        return index;
+    }
+
+    // Makes a string added with push findable, given its string_hash
+    fn make_findable(&mut self, index: u32, hash: u32) {
+        self.table.insert_unique(spread(hash), (index, hash), |&(_, hash)| spread(hash));
+    }
+
+    // The index of a string, adding it if it isn't kept yet
+    fn intern(&mut self, text: &str) -> u32 {
+        let hash = string_hash(text);
+        if let Some(index) = self.find_hashed(text, hash) {
+            return index;
+        }
+        let index = self.push(text);
+        self.make_findable(index, hash);
+        return index;
+    }
+}
+
+// A rule, as the index of its name, where its numbers start, and its
+// location as the index of its source, or NONE, and its line, with 0 for the
+// whole source
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Entry {
+    name: u32,
+    start: u32,
+    source: u32,
+    line: u32,
+}
+
+// The rules as compiled grammars keep them: the strings as one run of text
+// and where each ends, the strings naming rules or referred to by
+// nonterminals, by index and with their hashes, each rule as its name, where
+// its numbers start, and its source and line, every rule's numbers one after
+// another, the dynamic symbols, and the sources of the rules' locations
+pub struct Tables {
+    pub text: String,
+    pub ends: Vec<u32>,
+    pub names: Vec<[u32; 2]>,
+    pub rules: Vec<[u32; 4]>,
+    pub codes: Vec<u32>,
+    pub dynamics: Vec<Dynamic>,
+    pub sources: Vec<SourceName>,
+}
+
+// Each rule's alternatives, in the order the rules were defined, which is
+// the order they're exported and listed in, along with where they were
+// defined
+#[derive(Clone, Default)]
+pub struct Rules {
+    strings: Strings,
+    rules: Vec<Entry>,
+    // For each string, the index of the rule it names, or NONE. Strings
+    // added after the last rule's name may be left out.
+    rule_of: Vec<u32>,
+    // Every rule's numbers, one after another
+    codes: Vec<u32>,
+    // How many of the codes were a rule's before it was replaced or removed
+    unused: usize,
+    dynamics: Vec<Dynamic>,
+    sources: Vec<SourceName>,
+}
+
+impl Rules {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // The index of the rule named by the string at index, if there is one
+    fn rule_named(&self, name: u32) -> Option<usize> {
+        self.rule_of.get(name as usize).filter(|rule| **rule != NONE).map(|rule| *rule as usize)
+    }
+
+    pub fn get_index_of(&self, name: &str) -> Option<usize> {
+        self.rule_named(self.strings.find(name)?)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get_index_of(name).is_some()
+    }
+
+    pub fn get(&self, name: &str) -> Option<RewriteRef<'_>> {
+        self.get_index(self.get_index_of(name)?).map(|(_, rewrite)| rewrite)
+    }
+
+    // A rule's index, along with the grammar's copy of its name
+    pub fn get_full(&self, name: &str) -> Option<(usize, &str, RewriteRef<'_>)> {
+        let index = self.get_index_of(name)?;
+        let (name, rewrite) = self.get_index(index)?;
+        return Some((index, name, rewrite));
+    }
+
+    pub fn get_key_value(&self, name: &str) -> Option<(&str, RewriteRef<'_>)> {
+        self.get_index(self.get_index_of(name)?)
+    }
+
+    pub fn get_index(&self, index: usize) -> Option<(&str, RewriteRef<'_>)> {
+        let entry = self.rules.get(index)?;
+        return Some((self.strings.get(entry.name), self.rewrite_at(entry.start)));
+    }
+
+    // A rule's alternatives, which must be defined
+    pub fn rewrite(&self, name: &str) -> RewriteRef<'_> {
+        self.get(name).unwrap_or_else(|| panic!("`{}` isn't a rule", name))
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { rules: self, entries: self.rules.iter() }
+    }
+
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &str> + ExactSizeIterator + Clone {
+        self.iter().map(|(name, _)| name)
+    }
+
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = RewriteRef<'_>> + ExactSizeIterator + Clone {
+        self.iter().map(|(_, rewrite)| rewrite)
+    }
+
+    // Where a rule was defined, if it was and has a location
+    pub fn location(&self, name: &str) -> Option<Location> {
+        let entry = self.rules[self.get_index_of(name)?];
+        let source = self.sources.get(entry.source as usize)?.clone();
+        return Some(Location { source, line: (entry.line != 0).then_some(entry.line as usize) });
+    }
+
+    // Gives the rule at index a location
+    pub fn set_location(&mut self, index: usize, location: &Location) {
+        let source = match self.sources.iter().rposition(|source| *source == location.source) {
+            Some(source) => source,
+            None => {
+                self.sources.push(location.source.clone());
+                self.sources.len() - 1
+            }
+        };
+        let entry = &mut self.rules[index];
+        entry.source = source as u32;
+        entry.line = location.line.map_or(0, |line| u32::try_from(line).expect("grammars have fewer than 2^32 lines"));
+    }
+
+    fn rewrite_at(&self, start: u32) -> RewriteRef<'_> {
+        let start = start as usize;
+        let alternatives = self.codes[start] as usize;
+        let symbols = match alternatives {
+            0 => 0,
+            alternatives => self.codes[start + alternatives] as usize,
+        };
+        return RewriteRef { rules: self, codes: &self.codes[start..start + 1 + alternatives + symbols] };
+    }
+
+    fn symbol(&self, code: u32) -> SymbolRef<'_> {
+        let index = code & INDEX_MASK;
+        return match code >> KIND_SHIFT {
+            TERMINAL => SymbolRef::Terminal(self.strings.get(index)),
+            DYNAMIC => SymbolRef::Dynamic(&self.dynamics[index as usize]),
+            kind => SymbolRef::Nonterminal(self.strings.get(index), CASES[kind as usize - 1]),
+        };
+    }
+
+    fn code(&mut self, symbol: SymbolRef) -> u32 {
+        let (kind, index) = match symbol {
+            SymbolRef::Terminal(text) => (TERMINAL, self.strings.intern(text)),
+            SymbolRef::Nonterminal(name, case) => {
+                let kind = CASES.iter().position(|each| *each == case).expect("every case has a kind") as u32 + 1;
+                (kind, self.strings.intern(name))
+            }
+            SymbolRef::Dynamic(dynamic) => {
+                self.dynamics.push(dynamic.clone());
+                (DYNAMIC, self.dynamics.len() as u32 - 1)
+            }
+        };
+        assert!(index <= INDEX_MASK, "grammars have fewer than 2^29 strings");
+        return kind << KIND_SHIFT | index;
+    }
+
+    // Adds a rewrite's numbers after the others, returning where they start
+    fn push_rewrite<'s, R>(&mut self, rewrite: R) -> u32
+    where
+        R: IntoIterator<IntoIter: ExactSizeIterator, Item: IntoIterator<Item: Into<SymbolRef<'s>>>>,
+    {
+        let alternatives = rewrite.into_iter();
+        let start = self.codes.len();
+        make_room(&mut self.codes, 1 + alternatives.len());
+        self.codes.push(alternatives.len() as u32);
+        self.codes.resize(start + 1 + alternatives.len(), 0);
+        let symbols = self.codes.len();
+        for (index, alternative) in alternatives.enumerate() {
+            for symbol in alternative {
+                let code = self.code(symbol.into());
+                make_room(&mut self.codes, 1);
+                self.codes.push(code);
+            }
+            self.codes[start + 1 + index] = (self.codes.len() - symbols) as u32;
+        }
+        return u32::try_from(start).expect("grammars have fewer than 2^32 symbols");
+    }
+
+    // Adds a rule after the others, or replaces one's alternatives where it
+    // is, keeping its location. Returns its index. The rewrite can be a
+    // Rewrite built in code, or another grammar's RewriteRef.
+    pub fn insert<'s, R>(&mut self, name: &str, rewrite: R) -> usize
+    where
+        R: IntoIterator<IntoIter: ExactSizeIterator, Item: IntoIterator<Item: Into<SymbolRef<'s>>>>,
+    {
+        let start = self.push_rewrite(rewrite);
+        let name = self.strings.intern(name);
+        if let Some(index) = self.rule_named(name) {
+            let earlier = std::mem::replace(&mut self.rules[index].start, start);
+            self.unused += self.rewrite_at(earlier).codes.len();
+            self.compact_if_wasteful();
+            return index;
+        }
+        if self.rule_of.len() <= name as usize {
+            let more = name as usize + 1 - self.rule_of.len();
+            make_room(&mut self.rule_of, more);
+            self.rule_of.resize(name as usize + 1, NONE);
+        }
+        self.rule_of[name as usize] = self.rules.len() as u32;
+        make_room(&mut self.rules, 1);
+        self.rules.push(Entry { name, start, source: NONE, line: 0 });
+        return self.rules.len() - 1;
+    }
+
+    // Adds a rule at index, or moves one there and replaces its
+    // alternatives, keeping its location
+    pub fn shift_insert<'s, R>(&mut self, index: usize, name: &str, rewrite: R)
+    where
+        R: IntoIterator<IntoIter: ExactSizeIterator, Item: IntoIterator<Item: Into<SymbolRef<'s>>>>,
+    {
+        let from = self.insert(name, rewrite);
+        let entry = self.rules.remove(from);
+        self.rules.insert(index.min(self.rules.len()), entry);
+        self.renumber(index.min(from));
+    }
+
+    // Gives the rules from the one at index on the indices they're at now
+    fn renumber(&mut self, from: usize) {
+        for (index, entry) in self.rules.iter().enumerate().skip(from) {
+            self.rule_of[entry.name as usize] = index as u32;
+        }
+    }
+
+    // Removes a rule, moving the ones after it back, and returns whether
+    // there was one
+    pub fn shift_remove(&mut self, name: &str) -> bool {
+        let Some(index) = self.get_index_of(name) else {
+            return false;
+        };
+        self.retain_from(index, |rules, entry| rules.strings.get(entry.name) != name);
+        return true;
+    }
+
+    // Keeps only the rules keep returns true for, in the same order
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, RewriteRef) -> bool) {
+        self.retain_from(0, |rules, entry| keep(rules.strings.get(entry.name), rules.rewrite_at(entry.start)));
+    }
+
+    // Keeps the rules from the one at index from on which keep returns true
+    // for, and every rule before it
+    fn retain_from(&mut self, from: usize, mut keep: impl FnMut(&Rules, &Entry) -> bool) {
+        let kept = self.rules[from..].iter().map(|entry| keep(self, entry)).collect::<Vec<_>>();
+        for (entry, _) in self.rules[from..].iter().zip(&kept).filter(|(_, kept)| !**kept) {
+            self.unused += self.rewrite_at(entry.start).codes.len();
+        }
+        for entry in &self.rules[from..] {
+            self.rule_of[entry.name as usize] = NONE;
+        }
+        let mut kept = std::iter::repeat_n(true, from).chain(kept);
+        self.rules.retain(|_| kept.next().expect("every rule is decided on"));
+        self.renumber(from);
+        self.compact_if_wasteful();
+    }
+
+    // Has every nonterminal referring to a rule in names refer to the rule it
+    // maps to instead, without renaming any rule
+    pub fn redirect<'a>(&mut self, names: impl IntoIterator<Item = (&'a str, &'a str)>) {
+        let mut redirected = HashMap::new();
+        for (from, to) in names {
+            if let Some(from) = self.strings.find(from) {
+                redirected.insert(from, self.strings.intern(to));
+            }
+        }
+        for code in &mut self.codes {
+            let kind = *code >> KIND_SHIFT;
+            if kind != TERMINAL && kind != DYNAMIC {
+                if let Some(to) = redirected.get(&(*code & INDEX_MASK)) {
+                    *code = kind << KIND_SHIFT | to;
+                }
+            }
+        }
+    }
+
+    // Renames each rule in names to the name it maps to, along with every
+    // reference to it. Every name changes at the same time, so two rules can
+    // swap names, but a new name mustn't be one of the rules that isn't
+    // renamed.
+    pub fn rename<'a>(&mut self, names: impl IntoIterator<Item = (&'a str, &'a str)> + Clone) {
+        let renamed = names.clone().into_iter()
+            .filter_map(|(from, to)| Some((self.get_index_of(from)?, to)))
+            .collect::<Vec<_>>();
+        for (index, _) in &renamed {
+            self.rule_of[self.rules[*index].name as usize] = NONE;
+        }
+        for (index, to) in renamed {
+            let name = self.strings.intern(to);
+            self.rules[index].name = name;
+            if self.rule_of.len() <= name as usize {
+                self.rule_of.resize(name as usize + 1, NONE);
+            }
+            self.rule_of[name as usize] = index as u32;
+        }
+        self.redirect(names);
+    }
+
+    // Moves the rules' numbers together once more than half of them are
+    // left over from rules since replaced or removed
+    fn compact_if_wasteful(&mut self) {
+        if self.unused <= self.codes.len() / 2 {
+            return;
+        }
+        let mut codes = Vec::with_capacity(self.codes.len() - self.unused);
+        for index in 0..self.rules.len() {
+            let rewrite = self.rewrite_at(self.rules[index].start).codes;
+            let start = codes.len() as u32;
+            codes.extend_from_slice(rewrite);
+            self.rules[index].start = start;
+        }
+        self.codes = codes;
+        self.unused = 0;
+    }
+
+    // The strings naming the rules an entry's nonterminals refer to
+    fn referenced(&self, entry: &Entry) -> impl Iterator<Item = u32> + '_ {
+        let rewrite = self.rewrite_at(entry.start).codes;
+        return rewrite[1 + rewrite[0] as usize..].iter()
+            .filter(|code| *code >> KIND_SHIFT != TERMINAL && *code >> KIND_SHIFT != DYNAMIC)
+            .map(|code| code & INDEX_MASK);
+    }
+
+    // Whether a nonterminal refers to a rule that isn't defined, so the
+    // rules need checking for them
+    pub(crate) fn has_undefined(&self) -> bool {
+        self.rules.iter().any(|entry| self.referenced(entry).any(|name| self.rule_named(name).is_none()))
+    }
+
+    // Whether each rule, by index, can be expanded from the rule named start,
+    // which reaches itself. Rules are followed by their indices rather than
+    // their names, so even the largest grammars are walked quickly.
+    pub fn reachable(&self, start: &str) -> Vec<bool> {
+        let mut reached = vec![false; self.rules.len()];
+        let mut pending: Vec<usize> = self.get_index_of(start).into_iter().collect();
+        while let Some(index) = pending.pop() {
+            if std::mem::replace(&mut reached[index], true) {
+                continue;
+            }
+            let referenced = self.referenced(&self.rules[index]).filter_map(|name| self.rule_named(name));
+            pending.extend(referenced.filter(|rule| !reached[*rule]));
+        }
+        return reached;
+    }
+
+    // The rules' tables, with their numbers moved together in the rules'
+    // order, so the same rules always give the same tables
+    pub(crate) fn tables(&self) -> Tables {
+        let mut named = vec![false; self.strings.ends.len()];
+        let mut codes = Vec::with_capacity(self.codes.len() - self.unused);
+        let mut rules = Vec::with_capacity(self.rules.len());
+        for entry in &self.rules {
+            named[entry.name as usize] = true;
+            for name in self.referenced(entry) {
+                named[name as usize] = true;
+            }
+            rules.push([entry.name, codes.len() as u32, entry.source, entry.line]);
+            codes.extend_from_slice(self.rewrite_at(entry.start).codes);
+        }
+        let names = (0..named.len() as u32)
+            .filter(|index| named[*index as usize])
+            .map(|index| [index, string_hash(self.strings.get(index))])
+            .collect();
+        return Tables {
+            text: self.strings.text.clone(),
+            ends: self.strings.ends.clone(),
+            names,
+            rules,
+            codes,
+            dynamics: self.dynamics.clone(),
+            sources: self.sources.clone(),
+        };
+    }
+
+    // Rules taken as they are from the tables of a compiled grammar, which
+    // are checked rather than rebuilt: each string has to end after the one
+    // before it, each rule's numbers have to follow the one before's and
+    // refer to strings and dynamic symbols there are, and no two rules can
+    // share a name. Only the names are made findable, by the hashes they
+    // come with, so loading hashes no strings at all.
+    pub(crate) fn from_tables(tables: Tables) -> Result<Self, &'static str> {
+        const MISCOUNTED: &str = "its rules don't add up to its symbols";
+        const OUT_OF_RANGE: &str = "an index is out of range";
+        let Tables { text, ends, names, rules, codes, dynamics, sources } = tables;
+        let mut start = 0;
+        for &end in &ends {
+            if end < start || !text.is_char_boundary(end as usize) {
+                return Err("its strings are out of order");
+            }
+            start = end;
+        }
+        if start as usize != text.len() {
+            return Err("its strings are out of order");
+        }
+        // In order, so none is made findable twice
+        if names.windows(2).any(|pair| pair[0][0] >= pair[1][0]) || names.last().is_some_and(|[index, _]| *index as usize >= ends.len()) {
+            return Err("its names are out of order");
+        }
+
+        let mut rule_of = vec![NONE; ends.len()];
+        let mut next = 0;
+        for (index, [name, start, source, _]) in rules.iter().enumerate() {
+            if *start as usize != next {
+                return Err(MISCOUNTED);
+            }
+            if *source != NONE && *source as usize >= sources.len() {
+                return Err(OUT_OF_RANGE);
+            }
+            match rule_of.get_mut(*name as usize) {
+                Some(rule) if *rule == NONE => *rule = index as u32,
+                Some(_) => return Err("a rule is defined twice"),
+                None => return Err(OUT_OF_RANGE),
+            }
+
+            let alternatives = *codes.get(next).ok_or(MISCOUNTED)? as usize;
+            let symbols_at = next + 1 + alternatives;
+            let alternative_ends = codes.get(next + 1..symbols_at).ok_or(MISCOUNTED)?;
+            if alternative_ends.windows(2).any(|pair| pair[0] > pair[1]) {
+                return Err(MISCOUNTED);
+            }
+            let symbols = alternative_ends.last().map_or(0, |end| *end as usize);
+            for code in codes.get(symbols_at..symbols_at + symbols).ok_or(MISCOUNTED)? {
+                let index = (code & INDEX_MASK) as usize;
+                let in_range = match code >> KIND_SHIFT {
+                    DYNAMIC => index < dynamics.len(),
+                    kind if kind as usize <= CASES.len() => index < ends.len(),
+                    _ => return Err("a symbol has an unknown kind"),
+                };
+                if !in_range {
+                    return Err(OUT_OF_RANGE);
+                }
+            }
+            next = symbols_at + symbols;
+        }
+        if next != codes.len() {
+            return Err(MISCOUNTED);
+        }
+
+        let mut strings = Strings { text, ends, table: HashTable::with_capacity(names.len()) };
+        for [index, hash] in names {
+            strings.make_findable(index, hash);
+        }
+        let rules = rules.into_iter().map(|[name, start, source, line]| Entry { name, start, source, line }).collect();
+        return Ok(Rules { strings, rules, rule_of, codes, unused: 0, dynamics, sources });
+    }
+}
+
+// Rules are the same if they have the same names and alternatives, in any
+// order and wherever they were defined
+impl PartialEq for Rules {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(name, rewrite)| other.get(name) == Some(rewrite))
+    }
+}
+
+impl Debug for Rules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+// The rules in order, with their names
+#[derive(Clone)]
+pub struct Iter<'g> {
+    rules: &'g Rules,
+    entries: std::slice::Iter<'g, Entry>,
+}
+
+impl<'g> Iterator for Iter<'g> {
+    type Item = (&'g str, RewriteRef<'g>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        return Some((self.rules.strings.get(entry.name), self.rules.rewrite_at(entry.start)));
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next_back()?;
+        return Some((self.rules.strings.get(entry.name), self.rules.rewrite_at(entry.start)));
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl<'g> IntoIterator for &'g Rules {
+    type Item = (&'g str, RewriteRef<'g>);
+    type IntoIter = Iter<'g>;
+
+    fn into_iter(self) -> Iter<'g> {
+        self.iter()
+    }
+}
+
+// A symbol of a rule, borrowing its text from the grammar
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub enum SymbolRef<'g> {
+    Terminal(&'g str),
+    Nonterminal(&'g str, Option<Case>),
+    Dynamic(&'g Dynamic),
+}
+
+impl SymbolRef<'_> {
+    pub fn to_symbol(self) -> Symbol {
+        match self {
+            SymbolRef::Terminal(text) => Symbol::Terminal(text.into()),
+            SymbolRef::Nonterminal(name, case) => Symbol::Nonterminal(name.into(), case),
+            SymbolRef::Dynamic(dynamic) => Symbol::Dynamic(Box::new(dynamic.clone())),
+        }
+    }
+}
+
+impl<'s> From<&'s Symbol> for SymbolRef<'s> {
+    fn from(symbol: &'s Symbol) -> Self {
+        symbol.view()
+    }
+}
+
+impl PartialEq<Symbol> for SymbolRef<'_> {
+    fn eq(&self, other: &Symbol) -> bool {
+        *self == other.view()
+    }
+}
+
+// Symbols are shown the way they're written in grammar files, with
+// terminals written raw, like `r#"say "hi""#`, when they have to be
+impl Display for SymbolRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SymbolRef::Terminal(text) => match raw_hashes(text) {
+                Some(hashes) => write!(f, "r{0}\"{1}\"{0}", "#".repeat(hashes), text),
+                None => write!(f, "\"{}\"", text.replace('\n', "\\n")),
+            },
+            SymbolRef::Nonterminal(name, None) => write!(f, "{}", name),
+            SymbolRef::Nonterminal(name, Some(case)) => write!(f, "{}:{}", name, case),
+            SymbolRef::Dynamic(dynamic) => write!(f, "{}", dynamic),
+        }
+    }
+}
+
+// The alternatives of a rule, as the numbers saying how many there are and
+// where each ends, followed by all of their symbols
+#[derive(Clone, Copy)]
+pub struct RewriteRef<'g> {
+    rules: &'g Rules,
+    codes: &'g [u32],
+}
+
+impl<'g> RewriteRef<'g> {
+    pub fn len(&self) -> usize {
+        self.codes[0] as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<AlternativeRef<'g>> {
+        let ends = &self.codes[1..1 + self.len()];
+        let end = *ends.get(index)? as usize;
+        let start = match index {
+            0 => 0,
+            index => ends[index - 1] as usize,
+        };
+        let symbols = &self.codes[1 + self.len()..];
+        return Some(AlternativeRef { rules: self.rules, codes: &symbols[start..end] });
+    }
+
+    pub fn iter(&self) -> Alternatives<'g> {
+        Alternatives { rewrite: *self, indices: 0..self.len() }
+    }
+
+    pub fn to_rewrite(&self) -> Rewrite {
+        self.iter().map(|alternative| alternative.to_alternative()).collect()
+    }
+}
+
+impl<'g> IntoIterator for RewriteRef<'g> {
+    type Item = AlternativeRef<'g>;
+    type IntoIter = Alternatives<'g>;
+
+    fn into_iter(self) -> Alternatives<'g> {
+        self.iter()
+    }
+}
+
+impl PartialEq for RewriteRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(alternative, other)| alternative == other)
+    }
+}
+
+impl PartialEq<Rewrite> for RewriteRef<'_> {
+    fn eq(&self, other: &Rewrite) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(alternative, other)| alternative == *other)
+    }
+}
+
+impl Debug for RewriteRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Clone)]
+pub struct Alternatives<'g> {
+    rewrite: RewriteRef<'g>,
+    indices: std::ops::Range<usize>,
+}
+
+impl<'g> Iterator for Alternatives<'g> {
+    type Item = AlternativeRef<'g>;
+
+    fn next(&mut self) -> Option<AlternativeRef<'g>> {
+        self.rewrite.get(self.indices.next()?)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Alternatives<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.rewrite.get(self.indices.next_back()?)
+    }
+}
+
+impl ExactSizeIterator for Alternatives<'_> {}
+
+// The symbols of one alternative
+#[derive(Clone, Copy)]
+pub struct AlternativeRef<'g> {
+    rules: &'g Rules,
+    codes: &'g [u32],
+}
+
+impl<'g> AlternativeRef<'g> {
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<SymbolRef<'g>> {
+        self.codes.get(index).map(|code| self.rules.symbol(*code))
+    }
+
+    pub fn first(&self) -> Option<SymbolRef<'g>> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<SymbolRef<'g>> {
+        self.codes.last().map(|code| self.rules.symbol(*code))
+    }
+
+    pub fn iter(&self) -> Symbols<'g> {
+        Symbols { rules: self.rules, codes: self.codes.iter() }
+    }
+
+    // The first symbol and the ones after it, if there are any
+    pub fn split_first(&self) -> Option<(SymbolRef<'g>, AlternativeRef<'g>)> {
+        let (first, rest) = self.codes.split_first()?;
+        return Some((self.rules.symbol(*first), AlternativeRef { rules: self.rules, codes: rest }));
+    }
+
+    // The last symbol and the ones before it, if there are any
+    pub fn split_last(&self) -> Option<(SymbolRef<'g>, AlternativeRef<'g>)> {
+        let (last, rest) = self.codes.split_last()?;
+        return Some((self.rules.symbol(*last), AlternativeRef { rules: self.rules, codes: rest }));
+    }
+
+    pub fn to_alternative(&self) -> Alternative {
+        self.iter().map(SymbolRef::to_symbol).collect()
+    }
+}
+
+impl<'g> IntoIterator for AlternativeRef<'g> {
+    type Item = SymbolRef<'g>;
+    type IntoIter = Symbols<'g>;
+
+    fn into_iter(self) -> Symbols<'g> {
+        self.iter()
+    }
+}
+
+impl PartialEq for AlternativeRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for AlternativeRef<'_> {}
+
+impl std::hash::Hash for AlternativeRef<'_> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        state.write_usize(self.len());
+        self.iter().for_each(|symbol| symbol.hash(state));
+    }
+}
+
+// Alternatives are ordered by their symbols, like the lists they're built
+// from
+impl PartialOrd for AlternativeRef<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for AlternativeRef<'_> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl PartialEq<Alternative> for AlternativeRef<'_> {
+    fn eq(&self, other: &Alternative) -> bool {
+        self.len() == other.len() && self.iter().zip(other).all(|(symbol, other)| symbol == *other)
+    }
+}
+
+impl Debug for AlternativeRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Clone)]
+pub struct Symbols<'g> {
+    rules: &'g Rules,
+    codes: std::slice::Iter<'g, u32>,
+}
+
+impl<'g> Iterator for Symbols<'g> {
+    type Item = SymbolRef<'g>;
+
+    fn next(&mut self) -> Option<SymbolRef<'g>> {
+        self.codes.next().map(|code| self.rules.symbol(*code))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.codes.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Symbols<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.codes.next_back().map(|code| self.rules.symbol(*code))
+    }
+}
+
+impl ExactSizeIterator for Symbols<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terminal(text: &str) -> Symbol {
+        Symbol::Terminal(text.into())
+    }
+
+    fn nonterminal(name: &str) -> Symbol {
+        Symbol::Nonterminal(name.into(), None)
+    }
+
+    #[test]
+    fn share_strings() {
+        let mut rules = Rules::new();
+        rules.insert("s", &[vec![nonterminal("word"), terminal(" "), nonterminal("word")]]);
+        rules.insert("word", &[vec![terminal("hi")], vec![terminal(" ")], vec![]]);
+        assert_eq!(rules.strings.ends.len(), 4);
+        assert_eq!(rules.keys().collect::<Vec<_>>(), ["s", "word"]);
+        assert_eq!(rules.rewrite("word"), vec![vec![terminal("hi")], vec![terminal(" ")], vec![]]);
+        assert_eq!(rules.rewrite("word").get(2).unwrap().len(), 0);
+        assert_eq!(rules.rewrite("s").get(0).unwrap().get(2), Some(SymbolRef::Nonterminal("word", None)));
+        assert_eq!(rules.get_index_of("word"), Some(1));
+        assert!(!rules.contains_key("hi"));
+        assert_eq!(rules.location("s"), None);
+    }
+
+    #[test]
+    fn replace_and_remove_rules() {
+        let mut rules = Rules::new();
+        rules.insert("a", &[vec![terminal("1")]]);
+        let b = rules.insert("b", &[vec![nonterminal("a")]]);
+        rules.set_location(b, &Location::line(SourceName::Builtin, 3));
+        rules.insert("c", &[vec![nonterminal("b")]]);
+        for times in 0..10 {
+            rules.insert("b", &[vec![terminal(&times.to_string())], vec![nonterminal("c")]]);
+        }
+        assert_eq!(rules.keys().collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert_eq!(rules.rewrite("b"), vec![vec![terminal("9")], vec![nonterminal("c")]]);
+        assert_eq!(rules.location("b"), Some(Location::line(SourceName::Builtin, 3)));
+        // Replaced rules' numbers are let go of once they're most of them
+        assert!(rules.codes.len() < 40, "{}", rules.codes.len());
+
+        assert!(rules.shift_remove("a"));
+        assert!(!rules.shift_remove("a"));
+        assert_eq!(rules.get_full("c").map(|(index, name, _)| (index, name)), Some((1, "c")));
+        rules.shift_insert(0, "a", &[vec![terminal("2")]]);
+        assert_eq!(rules.keys().collect::<Vec<_>>(), ["a", "b", "c"]);
+        rules.retain(|name, _| name != "b");
+        assert_eq!(rules.keys().collect::<Vec<_>>(), ["a", "c"]);
+        assert_eq!(rules.get_index_of("c"), Some(1));
+        assert_eq!(rules.get("b"), None);
+    }
+
+    #[test]
+    fn rename_rules() {
+        let mut rules = Rules::new();
+        rules.insert("a", &[vec![nonterminal("b"), Symbol::Nonterminal("a".into(), Some(Case::Upper))]]);
+        rules.insert("b", &[vec![terminal("b")]]);
+        rules.rename([("a", "b"), ("b", "a")]);
+        assert_eq!(rules.keys().collect::<Vec<_>>(), ["b", "a"]);
+        assert_eq!(rules.rewrite("b"), vec![vec![nonterminal("a"), Symbol::Nonterminal("b".into(), Some(Case::Upper))]]);
+        // Terminals keep their text
+        assert_eq!(rules.rewrite("a"), vec![vec![terminal("b")]]);
+
+        rules.redirect([("a", "c")]);
+        assert_eq!(rules.rewrite("b").get(0).unwrap().first(), Some(SymbolRef::Nonterminal("c", None)));
+        assert!(rules.contains_key("a"));
+    }
+
+    #[test]
+    fn copy_between_rules() {
+        let mut rules = Rules::new();
+        rules.insert("s", &[vec![Symbol::Dynamic(Box::new(Dynamic::Uuid)), terminal("x")]]);
+        let mut copy = Rules::new();
+        copy.insert("t", rules.rewrite("s"));
+        assert_eq!(copy.rewrite("t"), rules.rewrite("s"));
+        assert_eq!(format!("{:?}", copy), "{\"t\": [[Dynamic(Uuid), Terminal(\"x\")]]}");
+    }
+
+    #[test]
+    fn load_tables() {
+        let mut rules = Rules::new();
+        rules.insert("s", &[vec![nonterminal("t"), terminal("x")], vec![]]);
+        let t = rules.insert("t", &[vec![terminal("y")]]);
+        rules.set_location(t, &Location::line(SourceName::Builtin, 2));
+        rules.insert("t", &[vec![nonterminal("undefined")]]);
+        let loaded = Rules::from_tables(rules.tables()).unwrap();
+        assert_eq!(loaded, rules);
+        assert_eq!(loaded.location("t"), Some(Location::line(SourceName::Builtin, 2)));
+        // Replaced rules' numbers are left out
+        assert_eq!(loaded.codes.len(), 8);
+        // Names which aren't defined can be found, so defining one later
+        // gives the nonterminals referring to it a rule
+        assert_eq!(loaded.strings.find("undefined"), rules.strings.find("undefined"));
+        assert_eq!(loaded.strings.find("x"), None);
+
+        let mut twice = rules.tables();
+        twice.rules[1][0] = twice.rules[0][0];
+        assert_eq!(Rules::from_tables(twice).err(), Some("a rule is defined twice"));
+        let mut short = rules.tables();
+        short.codes.pop();
+        assert_eq!(Rules::from_tables(short).err(), Some("its rules don't add up to its symbols"));
+        let mut unknown = rules.tables();
+        *unknown.codes.last_mut().unwrap() = 7 << KIND_SHIFT;
+        assert_eq!(Rules::from_tables(unknown).err(), Some("a symbol has an unknown kind"));
+    }
+}
@@ -0,0 +1,126 @@
+/*
+    Tags sort alternatives into groups, like the registers of a grammar
+    mixing formal and casual sentences, so some groups can be left out when
+    generating without keeping a copy of the grammar for each.
+
+    They're `;@ tag` annotations: `;@ tag formal` tags every alternative of
+    the rule below it, and `;@ tag formal=0,2` only the alternatives at those
+    indices, counting from 0.
+*/
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+
+use super::{Annotations, Grammar};
+
+// Reads a value of a `;@ tag` annotation as the tag and the indices it's
+// limited to, if it is. Tags are made of letters, digits, `-` and `_`.
+pub fn parse_tag(value: &str) -> Option<(&str, Option<Vec<usize>>)> {
+    let (tag, indices) = match value.split_once('=') {
+        Some((tag, indices)) => (tag, Some(indices)),
+        None => (value, None),
+    };
+    if tag.is_empty() || !tag.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+    let indices = match indices {
+        Some(indices) => Some(indices.split(',').map(|index| index.parse().ok()).collect::<Option<Vec<usize>>>()?),
+        None => None,
+    };
+    return Some((tag, indices));
+}
+
+// The tags of the alternative at index of a rule with these annotations,
+// in the order they're given, without repeats
+pub fn tags_of(annotations: Option<&Annotations>, index: usize) -> Vec<&str> {
+    let mut tags = Vec::new();
+    for value in annotations.and_then(|annotations| annotations.get("tag")).into_iter().flatten() {
+        if let Some((tag, indices)) = parse_tag(value) {
+            if indices.is_none_or(|indices| indices.contains(&index)) && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+    }
+    return tags;
+}
+
+// Renumbers the tags of a rule which had alternatives removed, where kept
+// says which of the alternatives it had are left. Tags only on removed
+// alternatives are dropped.
+pub fn renumber_tags(annotations: &mut Annotations, kept: &[bool]) {
+    let Some(values) = annotations.get_mut("tag") else {
+        return;
+    };
+    let renumbered = kept.iter()
+        .scan(0, |next, keep| {
+            let index = keep.then_some(*next);
+            *next += usize::from(*keep);
+            return Some(index);
+        })
+        .collect_vec();
+    *values = values.iter().filter_map(|value| match parse_tag(value) {
+        Some((tag, Some(indices))) => {
+            let indices = indices.iter().filter_map(|index| renumbered.get(*index).copied().flatten()).collect_vec();
+            (!indices.is_empty()).then(|| format!("{}={}", tag, indices.iter().join(",")))
+        }
+        _ => Some(value.clone()),
+    }).collect();
+    if values.is_empty() {
+        annotations.remove("tag");
+    }
+}
+
+impl Grammar {
+    pub fn alternative_tags(&self, rule: &str, index: usize) -> Vec<&str> {
+        return tags_of(self.annotations.get(rule), index);
+    }
+
+    // How many alternatives have each tag
+    pub fn tag_counts(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for (rule, rewrite) in &self.rules {
+            for index in 0..rewrite.len() {
+                for tag in self.alternative_tags(rule, index) {
+                    *counts.entry(tag).or_insert(0) += 1;
+                }
+            }
+        }
+        return counts;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn read_tags() {
+        assert_eq!(parse_tag("formal"), Some(("formal", None)));
+        assert_eq!(parse_tag("not-safe_2=0,3"), Some(("not-safe_2", Some(vec![0, 3]))));
+        for bad in ["", "=1", "formal=", "formal=1,", "formal=-1", "a.b", "a=b"] {
+            assert_eq!(parse_tag(bad), None, "{}", bad);
+        }
+
+        let mut grammar = parse_str(concat!(
+            ";@ tag formal=0,2\n",
+            ";@ tag casual=1 polite formal=2\n",
+            "greeting = \"good day\" | \"hi\" | \"hello\"\n",
+            "name = \"Jo\"\n",
+        ), SourceName::Builtin).unwrap();
+        assert_eq!(grammar.alternative_tags("greeting", 2), ["formal", "polite"]);
+        assert_eq!(grammar.alternative_tags("greeting", 1), ["casual", "polite"]);
+        assert!(grammar.alternative_tags("name", 0).is_empty());
+        assert_eq!(grammar.tag_counts(), BTreeMap::from([("casual", 1), ("formal", 2), ("polite", 3)]));
+
+        let annotations = grammar.annotations.get_mut("greeting").unwrap();
+        renumber_tags(annotations, &[false, true, true]);
+        assert_eq!(annotations["tag"], ["formal=1", "casual=0", "polite", "formal=1"]);
+        renumber_tags(annotations, &[true, false]);
+        assert_eq!(annotations["tag"], ["casual=0", "polite"]);
+        renumber_tags(annotations, &[false]);
+        assert_eq!(annotations["tag"], ["polite"]);
+    }
+}
@@ -0,0 +1,105 @@
+/*
+    Writes grammars back out as YAML, in the shape the parser reads: a
+    `start` key and a `rules` map from each rule to its list of alternatives.
+    Terminals are strings and nonterminals are `{nt: NAME}` maps.
+*/
+
+use super::{ExportError, Grammar};
+#[cfg(feature = "yaml")]
+use {
+    serde_yaml::{Mapping, Value},
+    super::{Bracket, SymbolRef},
+};
+
+#[cfg(feature = "yaml")]
+fn symbol_to_yaml(symbol: SymbolRef) -> Value {
+    match symbol {
+        SymbolRef::Terminal(text) => Value::String(text.to_string()),
+        SymbolRef::Nonterminal(name, case) => {
+            let mut map = Mapping::new();
+            map.insert("nt".into(), name.to_string().into());
+            if let Some(case) = case {
+                map.insert("case".into(), case.to_string().into());
+            }
+            Value::Mapping(map)
+        }
+        SymbolRef::Dynamic(_) => unreachable!("rules with dynamic symbols are refused before they're written"),
+    }
+}
+
+// Writes the rules named in order. Weights, annotations, brackets and dynamic
+// symbols have no place in the YAML shape, so rules with them are refused
+// rather than written without.
+#[cfg(feature = "yaml")]
+pub fn to_yaml(grammar: &Grammar, order: &[String]) -> Result<String, ExportError> {
+    let mut rules = Mapping::new();
+    for rule in order {
+        if let Some((of, _)) = Bracket::of_rule(rule) {
+            return Err(ExportError::NotYaml { rule: of.to_string(), what: "brackets" });
+        }
+        if grammar.weights.contains_key(rule.as_str()) {
+            return Err(ExportError::NotYaml { rule: rule.clone(), what: "weights" });
+        }
+        if grammar.annotations.contains_key(rule.as_str()) {
+            return Err(ExportError::NotYaml { rule: rule.clone(), what: "annotations" });
+        }
+        if grammar.rules.rewrite(rule.as_str()).iter().flatten().any(|symbol| matches!(symbol, SymbolRef::Dynamic(_))) {
+            return Err(ExportError::NotYaml { rule: rule.clone(), what: "dynamic symbols" });
+        }
+        let alternatives = grammar.rules.rewrite(rule.as_str()).iter()
+            .map(|alternative| Value::Sequence(alternative.iter().map(symbol_to_yaml).collect()))
+            .collect();
+        rules.insert(rule.as_str().into(), Value::Sequence(alternatives));
+    }
+
+    let mut document = Mapping::new();
+    document.insert("start".into(), grammar.start_symbol.as_str().into());
+    document.insert("rules".into(), Value::Mapping(rules));
+    return Ok(serde_yaml::to_string(&document).expect("YAML values can always be written"));
+}
+
+#[cfg(not(feature = "yaml"))]
+pub fn to_yaml(_grammar: &Grammar, _order: &[String]) -> Result<String, ExportError> {
+    return Err(ExportError::NeedsFeature("yaml"));
+}
+
+#[cfg(all(test, feature = "yaml"))]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::grammar::definition_order;
+    use crate::parser::{parse_str, parse_str_with_options, ParseOptions, Syntax};
+
+    #[test]
+    fn round_trip_through_yaml() {
+        let grammar = parse_str("s = n:title \" sleep\" | \"\"\nn = \"ideas\" | n \" and \" n | \"yes\" | \"1\"", SourceName::Builtin).unwrap();
+        let yaml = to_yaml(&grammar, &definition_order(&grammar)).unwrap();
+        assert_eq!(yaml, concat!(
+            "start: s\n",
+            "rules:\n",
+            "  s:\n",
+            "  - - nt: n\n",
+            "      case: title\n",
+            "    - ' sleep'\n",
+            "  - - ''\n",
+            "  n:\n",
+            "  - - ideas\n",
+            "  - - nt: n\n",
+            "    - ' and '\n",
+            "    - nt: n\n",
+            "  - - yes\n",
+            "  - - '1'\n",
+        ));
+        let options = ParseOptions { syntax: Some(Syntax::Yaml), ..Default::default() };
+        let (read, _) = parse_str_with_options(&yaml, SourceName::Builtin, &options).unwrap();
+        assert_eq!(read.rules, grammar.rules);
+        assert_eq!(definition_order(&read), ["s", "n"]);
+    }
+
+    #[test]
+    fn refuse_what_yaml_cannot_hold() {
+        let grammar = parse_str(";@ tag a\ns = \"a\"", SourceName::Builtin).unwrap();
+        let error = to_yaml(&grammar, &definition_order(&grammar)).unwrap_err();
+        assert_eq!(error.to_string(), "`s` has annotations, which can't be written as YAML");
+    }
+}
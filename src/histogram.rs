@@ -0,0 +1,169 @@
+/*
+    This module tallies how often each distinct expansion of a symbol occurs
+*/
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::generator::{GenResult, GenerateError};
+use crate::json;
+
+pub struct Histogram {
+    counts: HashMap<String, u64>,
+    // The most distinct strings that will be counted, to bound memory
+    max_distinct: usize,
+    // Every sample added, counted or not
+    total: u64,
+    // Samples which were new strings after the cap was reached
+    uncounted: u64,
+}
+
+impl Histogram {
+    pub fn new(max_distinct: usize) -> Self {
+        Histogram {
+            counts: HashMap::new(),
+            max_distinct,
+            total: 0,
+            uncounted: 0,
+        }
+    }
+
+    pub fn add(&mut self, sample: String) {
+        self.total += 1;
+        if let Some(count) = self.counts.get_mut(&sample) {
+            *count += 1;
+        } else if self.counts.len() < self.max_distinct {
+            self.counts.insert(sample, 1);
+        } else {
+            self.uncounted += 1;
+        }
+    }
+
+    // The counted strings, most frequent first. Ties are ordered by the
+    // strings themselves so the output is stable between runs
+    pub fn sorted(&self) -> Vec<(&str, u64)> {
+        let mut entries: Vec<(&str, u64)> = self.counts
+            .iter()
+            .map(|(sample, count)| (sample.as_str(), *count))
+            .collect();
+        entries.sort_by(|(a_sample, a_count), (b_sample, b_count)| {
+            b_count.cmp(a_count).then_with(|| a_sample.cmp(b_sample))
+        });
+        return entries;
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.total as f64
+        }
+    }
+
+    pub fn write_table(&self, top: usize, out: &mut impl Write) -> io::Result<()> {
+        let entries = self.sorted();
+        let shown = &entries[..top.min(entries.len())];
+        let count_width = shown.iter()
+            .map(|(_, count)| count.to_string().len())
+            .max()
+            .unwrap_or(1);
+
+        for (sample, count) in shown {
+            writeln!(out, "{:>width$}  {:>6.2}%  {:?}", count, self.percent(*count), sample, width = count_width)?;
+        }
+
+        let rest = &entries[shown.len()..];
+        if !rest.is_empty() {
+            let rest_count: u64 = rest.iter().map(|(_, count)| count).sum();
+            writeln!(out, "... {} more distinct strings ({} samples, {:.2}%)", rest.len(), rest_count, self.percent(rest_count))?;
+        }
+        if self.uncounted > 0 {
+            writeln!(out, "... {} samples not counted after reaching {} distinct strings ({:.2}%)", self.uncounted, self.max_distinct, self.percent(self.uncounted))?;
+        }
+        writeln!(out, "{} samples, {} distinct strings counted", self.total, entries.len())
+    }
+
+    pub fn write_json(&self, top: usize, out: &mut impl Write) -> io::Result<()> {
+        let entries = self.sorted();
+        let shown = &entries[..top.min(entries.len())];
+        let rest = &entries[shown.len()..];
+        let rest_count: u64 = rest.iter().map(|(_, count)| count).sum();
+
+        let top_json: Vec<String> = shown.iter()
+            .map(|(sample, count)| format!(
+                "{{\"text\":{},\"count\":{},\"percent\":{}}}",
+                json::string(sample), count, self.percent(*count)
+            ))
+            .collect();
+
+        writeln!(
+            out,
+            "{{\"total\":{},\"distinct\":{},\"top\":[{}],\"rest\":{{\"distinct\":{},\"count\":{}}},\"uncounted\":{}}}",
+            self.total, entries.len(), top_json.join(","), rest.len(), rest_count, self.uncounted
+        )
+    }
+}
+
+// Calls the generator the given amount of times, tallying the results
+pub fn histogram(mut generate: impl FnMut() -> GenResult, amount: u32, max_distinct: usize) -> Result<Histogram, GenerateError> {
+    let mut histogram = Histogram::new(max_distinct);
+    for _ in 0..amount {
+        histogram.add(generate()?);
+    }
+    return Ok(histogram);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram_of(samples: &[&str], max_distinct: usize) -> Histogram {
+        let mut histogram = Histogram::new(max_distinct);
+        for sample in samples {
+            histogram.add(sample.to_string());
+        }
+        return histogram;
+    }
+
+    #[test]
+    fn sort_by_count_then_text() {
+        let histogram = histogram_of(&["b", "a", "c", "c", "b", "c"], 10);
+        assert_eq!(histogram.sorted(), vec![("c", 3), ("b", 2), ("a", 1)]);
+
+        let histogram = histogram_of(&["b", "a"], 10);
+        assert_eq!(histogram.sorted(), vec![("a", 1), ("b", 1)]);
+    }
+
+    #[test]
+    fn cap_distinct_strings() {
+        let histogram = histogram_of(&["a", "b", "c", "a", "c"], 2);
+        assert_eq!(histogram.sorted(), vec![("a", 2), ("b", 1)]);
+        assert_eq!(histogram.total, 5);
+        assert_eq!(histogram.uncounted, 2);
+    }
+
+    #[test]
+    fn table_output() {
+        let histogram = histogram_of(&["a", "b", "a", "c"], 10);
+        let mut out = Vec::new();
+        histogram.write_table(1, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), concat!(
+            "2   50.00%  \"a\"\n",
+            "... 2 more distinct strings (2 samples, 50.00%)\n",
+            "4 samples, 3 distinct strings counted\n"
+        ));
+    }
+
+    #[test]
+    fn json_output() {
+        let histogram = histogram_of(&["a\n", "b", "a\n", "c"], 10);
+        let mut out = Vec::new();
+        histogram.write_json(1, &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"total\":4,\"distinct\":3,\"top\":[{\"text\":\"a\\n\",\"count\":2,\"percent\":50}],\"rest\":{\"distinct\":2,\"count\":2},\"uncounted\":0}\n"
+        );
+    }
+}
@@ -0,0 +1,42 @@
+/*
+    Stops long runs cleanly on Ctrl-C.
+
+    Once watching starts, the first Ctrl-C only sets a flag, which generation
+    loops check between sentences so they can stop after the current one,
+    flush what they've written, and report as usual before blabber exits with
+    EXIT_CODE. A second Ctrl-C exits immediately.
+*/
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+use blabber::error_handling;
+
+// What shells use for a process stopped by SIGINT
+pub const EXIT_CODE: i32 = 128 + 2;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static WATCH: Once = Once::new();
+
+// Starts catching Ctrl-C instead of letting it kill blabber. This should
+// only be done right before a loop which checks interrupted, since nothing
+// else stops on the first Ctrl-C.
+pub fn watch() {
+    WATCH.call_once(|| {
+        let handler = || {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(EXIT_CODE);
+            }
+            // ctrlc runs this on its own thread, so it's safe to print
+            eprintln!("stopping after the current sentence; press Ctrl-C again to stop now");
+        };
+        if let Err(error) = ctrlc::set_handler(handler) {
+            error_handling::warn(format!("Ctrl-C will stop blabber without finishing up: {}", error));
+        }
+    });
+}
+
+// Whether Ctrl-C has been pressed since watching started
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,159 @@
+/*
+    This module reads jobs files, which list several batches of sentences to
+    generate from one grammar.
+
+    Each line is a start symbol, how many sentences to generate from it, and
+    optionally a file to write them to, separated by tabs. Blank lines and
+    lines starting with `#` are skipped. With --key-field, one more field
+    holds a key the job's sentences are seeded from.
+*/
+
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use crate::error_handling::{Error, ErrorType, Location, SourceName};
+use crate::grammar::Grammar;
+use crate::seeds::split_key;
+
+// One batch of sentences to generate
+#[derive(Debug, PartialEq)]
+pub struct Job {
+    pub start: String,
+    pub count: u32,
+    // Where to write the sentences, or None for stdout
+    pub output: Option<PathBuf>,
+    // What to seed the sentences from instead of the run's seed
+    pub key: Option<String>,
+    // The line of the jobs file it came from
+    pub location: Location,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum JobErrorType {
+    // A line has a start symbol but no count
+    MissingCount,
+    // The count isn't a whole number
+    InvalidCount(String),
+    // A line has more than three fields, not counting its key
+    TooManyFields(usize),
+    // A line has fewer fields than --key-field says its key is in
+    MissingKey { field: usize, fields: usize },
+    // The start symbol isn't defined in the grammar
+    UndefinedStart(String),
+    // Generating or writing the job's sentences failed
+    Failed(String),
+}
+
+impl ErrorType for JobErrorType {}
+
+impl Display for JobErrorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobErrorType::MissingCount => write!(f, "Expected a tab and a count after the start symbol"),
+            JobErrorType::InvalidCount(count) => write!(f, "`{}` isn't a valid count", count),
+            JobErrorType::TooManyFields(fields) => write!(f, "Expected at most 3 tab separated fields, found {}", fields),
+            JobErrorType::MissingKey { field, fields } => write!(f, "Expected a key in tab separated field {}, but the line has {} fields", field, fields),
+            JobErrorType::UndefinedStart(start) => write!(f, "No definition for start symbol `{}`", start),
+            JobErrorType::Failed(reason) => write!(f, "The job failed: {}", reason),
+        }
+    }
+}
+
+pub type JobError = Error<JobErrorType>;
+
+fn parse_job(line: &str, key_field: Option<usize>, location: Location) -> Result<Job, JobError> {
+    let error = |error| Error { location: location.clone(), error };
+    let (key, line) = match key_field {
+        Some(field) => match split_key(line, field) {
+            Ok((key, rest)) => (Some(key.to_string()), rest),
+            Err(fields) => return Err(error(JobErrorType::MissingKey { field, fields })),
+        },
+        None => (None, line.to_string()),
+    };
+    let fields: Vec<&str> = line.split('\t').collect();
+    let (start, count, output) = match fields.as_slice() {
+        [_] => return Err(error(JobErrorType::MissingCount)),
+        [start, count] => (start, count, None),
+        [start, count, output] => (start, count, Some(output.trim()).filter(|output| !output.is_empty())),
+        _ => return Err(error(JobErrorType::TooManyFields(fields.len()))),
+    };
+    let count = count.trim().parse().map_err(|_| error(JobErrorType::InvalidCount(count.trim().to_string())))?;
+
+    return Ok(Job {
+        start: start.trim().to_string(),
+        count,
+        output: output.map(PathBuf::from),
+        key,
+        location
+    });
+}
+
+// Reads every job in a jobs file, using source for the error locations and
+// taking each line's key from key_field if it's given, and returns every
+// error found if any line is wrong
+pub fn parse_jobs(text: &str, source: SourceName, key_field: Option<usize>) -> Result<Vec<Job>, Vec<JobError>> {
+    let mut jobs = Vec::new();
+    let mut errors = Vec::new();
+    for (num, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_job(line, key_field, Location::line(source.clone(), num + 1)) {
+            Ok(job) => jobs.push(job),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok(jobs);
+}
+
+// Finds every job starting from a symbol the grammar doesn't define
+pub fn check_starts(jobs: &[Job], grammar: &Grammar) -> Vec<JobError> {
+    jobs.iter()
+        .filter(|job| !grammar.rules.contains_key(job.start.as_str()))
+        .map(|job| Error {
+            location: job.location.clone(),
+            error: JobErrorType::UndefinedStart(job.start.clone())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn read_jobs() {
+        let jobs = parse_jobs("# names first\nname\t10000\tnames.txt\n\nsentence\t3\n", SourceName::Builtin, None).unwrap();
+        assert_eq!(jobs, vec![
+            Job { start: "name".to_string(), count: 10000, output: Some(PathBuf::from("names.txt")), key: None, location: Location::line(SourceName::Builtin, 2) },
+            Job { start: "sentence".to_string(), count: 3, output: None, key: None, location: Location::line(SourceName::Builtin, 4) },
+        ]);
+
+        let errors = parse_jobs("name\nname\tmany\nname\t1\ta\tb\nname\t1\t\n", SourceName::Builtin, None).unwrap_err();
+        let errors: Vec<(Option<usize>, JobErrorType)> = errors.into_iter().map(|error| (error.location.line, error.error)).collect();
+        assert_eq!(errors, vec![
+            (Some(1), JobErrorType::MissingCount),
+            (Some(2), JobErrorType::InvalidCount("many".to_string())),
+            (Some(3), JobErrorType::TooManyFields(4)),
+        ]);
+
+        let jobs = parse_jobs("user-4812\tname\t2\nuser-4813\tsentence\t1\tout.txt\n", SourceName::Builtin, Some(1)).unwrap();
+        assert_eq!((jobs[0].key.as_deref(), jobs[0].start.as_str(), jobs[0].count), (Some("user-4812"), "name", 2));
+        assert_eq!((jobs[1].key.as_deref(), jobs[1].output.as_deref()), (Some("user-4813"), Some(PathBuf::from("out.txt").as_path())));
+        let errors = parse_jobs("name\t1\n", SourceName::Builtin, Some(3)).unwrap_err();
+        assert_eq!(errors[0].error, JobErrorType::MissingKey { field: 3, fields: 2 });
+    }
+
+    #[test]
+    fn check_start_symbols() {
+        let grammar = parse_str("name = \"Ann\"", SourceName::Builtin).unwrap();
+        let jobs = parse_jobs("name\t1\nplace\t2\n", SourceName::Builtin, None).unwrap();
+        let errors = check_starts(&jobs, &grammar);
+        assert_eq!(errors, vec![Error { location: Location::line(SourceName::Builtin, 2), error: JobErrorType::UndefinedStart("place".to_string()) }]);
+    }
+}
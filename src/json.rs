@@ -0,0 +1,35 @@
+/*
+    This module has helpers for writing JSON output
+*/
+
+// Quotes and escapes a string so it can be used as a JSON string literal
+pub fn string(text: &str) -> String {
+    let mut result = String::with_capacity(text.len() + 2);
+    result.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_strings() {
+        assert_eq!(string("alpha"), "\"alpha\"");
+        assert_eq!(string("say \"hi\"\n"), "\"say \\\"hi\\\"\\n\"");
+        assert_eq!(string("back\\slash\t"), "\"back\\\\slash\\t\"");
+        assert_eq!(string("\u{1}"), "\"\\u0001\"");
+    }
+}
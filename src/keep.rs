@@ -0,0 +1,154 @@
+/*
+    This module keeps the best few sentences of a run by some measure, for
+    finding stress-test inputs among many candidates.
+
+    Only the K best records are held, in a heap with the worst of them on
+    top, so memory doesn't grow with the number of candidates. Records that
+    measure the same are ranked by when they were generated, earliest first,
+    so seeded runs always keep the same ones.
+*/
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+// What makes a sentence better than another
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum KeepBy {
+    // More characters
+    Longest,
+    // Fewer characters
+    Shortest,
+    // More words, separated by whitespace
+    MostWords,
+    // More rules expanded to generate it
+    MostExpansions,
+}
+
+impl KeepBy {
+    pub fn from_name(name: &str) -> Option<KeepBy> {
+        match name {
+            "longest" => Some(KeepBy::Longest),
+            "shortest" => Some(KeepBy::Shortest),
+            "most-words" => Some(KeepBy::MostWords),
+            "most-expansions" => Some(KeepBy::MostExpansions),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeepBy::Longest => "longest",
+            KeepBy::Shortest => "shortest",
+            KeepBy::MostWords => "most-words",
+            KeepBy::MostExpansions => "most-expansions",
+        }
+    }
+
+    // The value of the measure for a sentence that took expansions rules to
+    // generate
+    pub fn measure(self, sentence: &str, expansions: u64) -> u64 {
+        match self {
+            KeepBy::Longest | KeepBy::Shortest => sentence.chars().count() as u64,
+            KeepBy::MostWords => sentence.split_whitespace().count() as u64,
+            KeepBy::MostExpansions => expansions,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Candidate {
+    value: u64,
+    // How many candidates came before it
+    index: u64,
+    record: String,
+    by: KeepBy,
+}
+
+// Worse candidates are greater, so the heap has the worst on top
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let by_value = match self.by {
+            KeepBy::Shortest => self.value.cmp(&other.value),
+            _ => other.value.cmp(&self.value),
+        };
+        return by_value.then(self.index.cmp(&other.index));
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+#[derive(Debug)]
+pub struct Best {
+    by: KeepBy,
+    keep: usize,
+    heap: BinaryHeap<Candidate>,
+    seen: u64,
+}
+
+impl Best {
+    pub fn new(by: KeepBy, keep: usize) -> Self {
+        Best { by, keep, heap: BinaryHeap::with_capacity(keep + 1), seen: 0 }
+    }
+
+    pub fn by(&self) -> KeepBy {
+        self.by
+    }
+
+    // Offers a record measuring value, which is held if it's among the best
+    // so far
+    pub fn offer(&mut self, value: u64, record: String) {
+        let candidate = Candidate { value, index: self.seen, record, by: self.by };
+        self.seen += 1;
+        if self.heap.len() < self.keep {
+            self.heap.push(candidate);
+        } else if self.heap.peek().is_some_and(|worst| candidate < *worst) {
+            self.heap.pop();
+            self.heap.push(candidate);
+        }
+    }
+
+    // The records kept and what they measured, best first
+    pub fn into_best(self) -> impl Iterator<Item = (u64, String)> {
+        self.heap.into_sorted_vec().into_iter().map(|candidate| (candidate.value, candidate.record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keep(by: KeepBy, keep: usize, sentences: &[&str]) -> Vec<String> {
+        let mut best = Best::new(by, keep);
+        for sentence in sentences {
+            best.offer(by.measure(sentence, 0), sentence.to_string());
+        }
+        return best.into_best().map(|(_, record)| record).collect();
+    }
+
+    #[test]
+    fn keep_the_best() {
+        let sentences = ["bb", "a", "dddd", "cc", "e e e", "ff"];
+        assert_eq!(keep(KeepBy::Longest, 3, &sentences), ["e e e", "dddd", "bb"]);
+        assert_eq!(keep(KeepBy::Shortest, 3, &sentences), ["a", "bb", "cc"]);
+        assert_eq!(keep(KeepBy::MostWords, 2, &sentences), ["e e e", "bb"]);
+        assert_eq!(keep(KeepBy::Longest, 10, &sentences).len(), 6);
+        assert!(keep(KeepBy::Longest, 0, &sentences).is_empty());
+
+        let mut best = Best::new(KeepBy::MostExpansions, 1);
+        best.offer(KeepBy::MostExpansions.measure("short", 9), "short".to_string());
+        best.offer(KeepBy::MostExpansions.measure("much longer", 4), "much longer".to_string());
+        assert_eq!(best.into_best().collect::<Vec<_>>(), [(9, "short".to_string())]);
+    }
+}
@@ -0,0 +1,154 @@
+#![allow(clippy::needless_return)]
+
+/*
+    Blabber generates random sentences from context-free grammars
+*/
+
+pub mod analysis;
+pub mod assertions;
+pub mod codegen;
+pub mod color;
+pub mod compiled;
+pub mod corpus;
+pub mod coverage;
+pub mod dedup;
+pub mod error_handling;
+pub mod equivalence;
+pub mod escape;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod filter;
+pub mod generator;
+pub mod grammar;
+pub mod histogram;
+pub mod jobs;
+pub mod json;
+pub mod keep;
+pub mod limit;
+pub mod lint;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod merge;
+pub mod normalize;
+pub mod parser;
+pub mod profile;
+pub mod repeats;
+pub mod scaffold;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod seeds;
+#[cfg(feature = "serve")]
+pub mod serve;
+pub mod snapshot;
+pub mod sorting;
+pub mod summary;
+pub mod timing;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use std::fmt::Display;
+
+pub use generator::GenResult;
+pub use grammar::Grammar;
+
+use generator::{GenerateError, GenerateErrorType};
+use parser::CompileError;
+
+// Any error blabber can produce, so applications can use `?` across parsing,
+// generation, and io. The specific functions like parser::parse_file keep
+// their own error types, which convert into this with From.
+#[derive(Debug)]
+pub enum Error {
+    // The grammar could not be parsed, with every problem that was found
+    Parse(Vec<CompileError>),
+    // A sentence could not be generated
+    Generate(GenerateError),
+    // Reading input or writing output failed
+    Io(std::io::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(errors) => {
+                let mut first = true;
+                for error in errors {
+                    if !first {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", error)?;
+                    first = false;
+                }
+                Ok(())
+            }
+            Error::Generate(error) => write!(f, "{}", error),
+            Error::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<Vec<CompileError>> for Error {
+    fn from(errors: Vec<CompileError>) -> Self {
+        Error::Parse(errors)
+    }
+}
+
+impl From<GenerateError> for Error {
+    fn from(error: GenerateError) -> Self {
+        Error::Generate(error)
+    }
+}
+
+impl From<GenerateErrorType> for Error {
+    fn from(error: GenerateErrorType) -> Self {
+        Error::Generate(error.into())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn parse_then_generate(path: &str, start: &str) -> Result<String, Error> {
+        let grammar = parser::parse_file(&PathBuf::from(path))?;
+        let sentence = generator::generate_with_override(&grammar, start)?;
+        return Ok(sentence);
+    }
+
+    #[test]
+    fn convert_with_question_mark() {
+        assert_eq!(parse_then_generate("example_data/english.bnf", "noun").unwrap(), "ideas");
+
+        match parse_then_generate("example_data/malformed.bnf", "noun") {
+            Err(Error::Parse(errors)) => assert_eq!(errors.len(), 2),
+            other => panic!("expected parse errors, got {:?}", other),
+        }
+        match parse_then_generate("example_data/english.bnf", "pronoun") {
+            Err(Error::Generate(error)) => assert_eq!(error.error, GenerateErrorType::UndefinedNonterminal("pronoun".to_string())),
+            other => panic!("expected a generation error, got {:?}", other),
+        }
+        match parse_then_generate("example_data/missing.bnf", "noun") {
+            Err(Error::Parse(errors)) => assert!(matches!(errors[0].error, parser::CompileErrorType::FileError(_))),
+            other => panic!("expected a file error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+/*
+    Caps how much output a run writes, for runs of millions of sentences
+    redirected to a file, which can otherwise fill a disk.
+
+    Bytes are counted as they're written, after escaping, labels, colors and
+    anything else added to a sentence, so the count is what ends up in the
+    file. The cap is checked at the end of each record rather than in the
+    middle of one, so the record that reaches it is still written whole, and
+    the output can go over the cap by at most one record.
+*/
+
+use std::io::{self, Write};
+
+// What blabber exits with when --limit-is-error and the limit stopped it
+pub const EXIT_CODE: i32 = 3;
+
+// A writer which counts the bytes and records written through it
+pub struct Limited<W: Write> {
+    inner: W,
+    limit: Option<u64>,
+    written: u64,
+    records: u64,
+}
+
+impl<W: Write> Limited<W> {
+    // Limits writing to inner to limit bytes, or counts without a limit
+    pub fn new(inner: W, limit: Option<u64>) -> Self {
+        Limited { inner, limit, written: 0, records: 0 }
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    // How many records have been finished
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    // Whether the limit has been reached, so nothing more should be written
+    pub fn reached(&self) -> bool {
+        self.limit.is_some_and(|limit| self.written >= limit)
+    }
+
+    // Finishes a record, returning whether the limit has been reached
+    pub fn end_record(&mut self) -> bool {
+        self.records += 1;
+        return self.reached();
+    }
+}
+
+impl<W: Write> Write for Limited<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        return Ok(written);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Reads a number of bytes, with an optional suffix like `K`, `M`, `G` or
+// `T` for powers of 1024, which can be lowercase and end in `B` or `iB`
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let invalid = || format!("expected a number of bytes like 4096, 512M or 2G, found `{}`", text);
+    let trimmed = text.trim();
+    let digits = trimmed.find(|c: char| !c.is_ascii_digit()).unwrap_or(trimmed.len());
+    let (number, suffix) = trimmed.split_at(digits);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let suffix = suffix.trim_start().to_ascii_uppercase();
+    let power = match suffix.trim_end_matches("IB").trim_end_matches('B') {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => return Err(invalid()),
+    };
+    if suffix.ends_with("IB") && power == 0 {
+        return Err(invalid());
+    }
+    let bytes = number.checked_mul(1024u64.pow(power)).ok_or_else(|| format!("`{}` is too many bytes", text))?;
+    if bytes == 0 {
+        return Err("the limit must be at least 1 byte".to_string());
+    }
+    return Ok(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("512M"), Ok(512 * 1024 * 1024));
+        assert_eq!(parse_size("2G"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("3kb"), Ok(3 * 1024));
+        assert_eq!(parse_size("1 TiB"), Ok(1024u64.pow(4)));
+        assert_eq!(parse_size("10B"), Ok(10));
+        for bad in ["", "M", "1.5G", "2X", "-1", "3iB", "1GG"] {
+            assert!(parse_size(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(parse_size("0K"), Err("the limit must be at least 1 byte".to_string()));
+        assert_eq!(parse_size("99999999999T"), Err("`99999999999T` is too many bytes".to_string()));
+    }
+
+    #[test]
+    fn stop_after_the_record_reaching_the_limit() {
+        let mut out = Limited::new(Vec::new(), Some(10));
+        let mut records = ["first\n", "second\n", "third\n"].into_iter();
+        for record in records.by_ref() {
+            out.write_all(record.as_bytes()).unwrap();
+            if out.end_record() {
+                break;
+            }
+        }
+        assert_eq!(records.next(), Some("third\n"));
+        assert_eq!((out.written(), out.records()), (13, 2));
+        assert_eq!(out.inner, b"first\nsecond\n");
+
+        let mut unlimited = Limited::new(Vec::new(), None);
+        write!(unlimited, "{}", "x".repeat(100)).unwrap();
+        assert!(!unlimited.end_record());
+    }
+}
@@ -0,0 +1,165 @@
+/*
+    Checks every word a grammar's terminals could put in a sentence is in a
+    dictionary of approved words, so text outside an agreed vocabulary can't
+    slip into a grammar unnoticed.
+
+    Terminals are split into words at whitespace and punctuation, keeping
+    apostrophes and hyphens inside a word, so "don't" and "well-known" are
+    one word each, like --no-repeat-window splits them. Runs of punctuation
+    and words made only of digits are left unchecked unless asked for.
+    Dynamic symbols make their text while generating, so they aren't
+    checked.
+*/
+
+use std::collections::HashSet;
+use std::fmt::Display;
+
+use crate::error_handling::{ErrorType, Location};
+use crate::grammar::{definition_order, Grammar, SymbolRef};
+
+// The approved words, one per line of the dictionary file
+#[derive(Debug)]
+pub struct Dictionary {
+    words: HashSet<String>,
+    case_sensitive: bool,
+}
+
+impl Dictionary {
+    // Reads the words from text, one per line, leaving out blank lines.
+    // Unless case_sensitive, `Paris` in a terminal is allowed by `paris` in
+    // the dictionary and the other way around.
+    pub fn parse(text: &str, case_sensitive: bool) -> Self {
+        let words = text.lines()
+            .map(str::trim)
+            .filter(|word| !word.is_empty())
+            .map(|word| if case_sensitive { word.to_string() } else { word.to_lowercase() })
+            .collect();
+        return Dictionary { words, case_sensitive };
+    }
+
+    pub fn contains(&self, word: &str) -> bool {
+        if self.case_sensitive {
+            return self.words.contains(word);
+        }
+        return self.words.contains(&word.to_lowercase());
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LintOptions {
+    // Whether runs of punctuation, like `!` or `...`, are checked as words
+    pub check_punctuation: bool,
+    // Whether words made only of digits, like `42`, are checked
+    pub check_numbers: bool,
+}
+
+// A word of a rule's terminals that isn't in the dictionary
+#[derive(Debug, PartialEq, Clone)]
+pub struct UnknownWord {
+    pub rule: String,
+    pub word: String,
+}
+
+impl ErrorType for UnknownWord {}
+
+impl Display for UnknownWord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` uses the word `{}`, which isn't in the dictionary", self.rule, self.word)
+    }
+}
+
+// Apostrophes and hyphens join the letters either side of them into a word
+fn joins(c: char) -> bool {
+    c == '\'' || c == '’' || c == '-'
+}
+
+// The words and runs of punctuation in text, with whether each is a word
+fn tokens(text: &str) -> Vec<(&str, bool)> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let is_word = |index: usize| {
+        let c = chars[index].1;
+        c.is_alphanumeric() || (joins(c)
+            && index > 0 && chars[index - 1].1.is_alphanumeric()
+            && chars.get(index + 1).is_some_and(|(_, next)| next.is_alphanumeric()))
+    };
+
+    let mut tokens = Vec::new();
+    let mut start: Option<(usize, bool)> = None;
+    for index in 0..chars.len() {
+        let kind = (!chars[index].1.is_whitespace()).then(|| is_word(index));
+        match (start, kind) {
+            (Some((_, word)), Some(kind)) if word == kind => continue,
+            (Some((from, word)), _) => tokens.push((&text[chars[from].0..chars[index].0], word)),
+            (None, _) => (),
+        }
+        start = kind.map(|kind| (index, kind));
+    }
+    if let Some((from, word)) = start {
+        tokens.push((&text[chars[from].0..], word));
+    }
+    return tokens;
+}
+
+// Every word of each rule's terminals missing from the dictionary, in the
+// order the rules were defined, with where each rule is. A word missing
+// more than once from the same rule is only given once.
+pub fn unknown_words(grammar: &Grammar, dictionary: &Dictionary, options: &LintOptions) -> Vec<(Location, UnknownWord)> {
+    let mut unknown = Vec::new();
+    for rule in definition_order(grammar) {
+        let mut seen = HashSet::new();
+        let terminals = grammar.rules.rewrite(rule.as_str()).iter()
+            .flatten()
+            .filter_map(|symbol| match symbol {
+                SymbolRef::Terminal(text) => Some(text),
+                _ => None
+            });
+        for terminal in terminals {
+            for (token, word) in tokens(terminal) {
+                let checked = if word {
+                    options.check_numbers || !token.chars().all(|c| c.is_ascii_digit())
+                } else {
+                    options.check_punctuation
+                };
+                if checked && !dictionary.contains(token) && seen.insert(token) {
+                    unknown.push((grammar.location(&rule).clone(), UnknownWord { rule: rule.clone(), word: token.to_string() }));
+                }
+            }
+        }
+    }
+    return unknown;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::parse_str;
+
+    #[test]
+    fn find_unknown_words() {
+        assert_eq!(tokens("  don't 'quote' well-known -- 42!"), [
+            ("don't", true), ("'", false), ("quote", true), ("'", false), ("well-known", true), ("--", false), ("42", true), ("!", false),
+        ]);
+
+        let grammar = parse_str(concat!(
+            "sentence = greeting \", \" name \"!\" | \"Hello, wrold.\"\n",
+            "greeting = \"Hello\" | \"hi there\" | \"Hello 3 times\"\n",
+            "name = \"Jo\" | \"Sam\" | \"jo\"\n",
+        ), SourceName::Builtin).unwrap();
+        let dictionary = Dictionary::parse("hello\n\n  hi \nthere\njo\n", false);
+        let words = |options: &LintOptions| unknown_words(&grammar, &dictionary, options).into_iter()
+            .map(|(location, unknown)| (location.line.unwrap(), unknown.word))
+            .collect::<Vec<_>>();
+        assert_eq!(words(&LintOptions::default()), [(1, "wrold".to_string()), (2, "times".to_string()), (3, "Sam".to_string())]);
+        assert_eq!(words(&LintOptions { check_punctuation: true, check_numbers: true }), [
+            (1, ",".to_string()), (1, "!".to_string()), (1, "wrold".to_string()), (1, ".".to_string()),
+            (2, "3".to_string()), (2, "times".to_string()),
+            (3, "Sam".to_string()),
+        ]);
+
+        // Case matters if asked to
+        let dictionary = Dictionary::parse("Hello\nhi\nthere\ntimes\nSam\nJo\nwrold\n", true);
+        let unknown = unknown_words(&grammar, &dictionary, &LintOptions::default());
+        assert_eq!(unknown.into_iter().map(|(_, unknown)| unknown.to_string()).collect::<Vec<_>>(), ["`name` uses the word `jo`, which isn't in the dictionary"]);
+    }
+}
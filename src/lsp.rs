@@ -0,0 +1,412 @@
+/*
+    A language server for grammar files, so editors can show problems as
+    they're typed, jump from a nonterminal to its rule, complete rule names,
+    and show a rule's alternatives on hover.
+
+    It speaks JSON-RPC over stdin and stdout, with each message preceded by
+    a `Content-Length` header. Documents are sent whole on every change, and
+    parsed with the error tolerant parser so the rules that are fine still
+    work while others have errors. Each document keeps a parse cache, so
+    only the lines an edit changed are lexed again.
+*/
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use itertools::Itertools;
+use serde_json::{json, Value};
+
+use crate::error_handling::{Location, SourceName};
+use crate::grammar::{written_rule_to_bnf, Bracket, Grammar};
+use crate::parser::lexer::{lex_line_spanned, Token};
+use crate::parser::{CompileErrorType, CompileWarningType, ParseCache};
+
+// JSON-RPC's code for a method the server doesn't have
+const METHOD_NOT_FOUND: i64 = -32601;
+
+// An open document and what was parsed from it
+struct Document {
+    text: String,
+    grammar: Grammar,
+    cache: ParseCache,
+}
+
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    // Whether the client asked the server to shut down
+    shut_down: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server::default()
+    }
+
+    // Handles one message from the client, returning the messages to send
+    // back, and whether the client asked the server to exit
+    pub fn handle(&mut self, message: &Value) -> (Vec<Value>, bool) {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let mut replies = Vec::new();
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    // The whole document is sent on every change
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "completionProvider": {},
+                    "hoverProvider": true
+                },
+                "serverInfo": { "name": "blabber", "version": env!("CARGO_PKG_VERSION") }
+            })),
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                replies.push(self.update(document["uri"].as_str().unwrap_or(""), document["text"].as_str().unwrap_or("")));
+                None
+            }
+            "textDocument/didChange" => {
+                // With full syncing, the last change has the whole text
+                if let Some(text) = params["contentChanges"].as_array().and_then(|changes| changes.last()).and_then(|change| change["text"].as_str()) {
+                    replies.push(self.update(params["textDocument"]["uri"].as_str().unwrap_or(""), text));
+                }
+                None
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+                self.documents.remove(uri);
+                replies.push(diagnostics_notification(uri, Vec::new()));
+                None
+            }
+            "textDocument/definition" => Some(self.definition(params).unwrap_or(Value::Null)),
+            "textDocument/completion" => Some(self.completion(params)),
+            "textDocument/hover" => Some(self.hover(params).unwrap_or(Value::Null)),
+            "shutdown" => {
+                self.shut_down = true;
+                Some(Value::Null)
+            }
+            "exit" => return (replies, true),
+            _ => None,
+        };
+
+        // Only requests have ids, and every request gets a response
+        if let Some(id) = message.get("id") {
+            replies.push(match result {
+                Some(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                None => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": METHOD_NOT_FOUND, "message": format!("Unknown method `{}`", method) } }),
+            });
+        }
+        return (replies, false);
+    }
+
+    // Parses a document's new text, returning the diagnostics to publish
+    fn update(&mut self, uri: &str, text: &str) -> Value {
+        let mut cache = self.documents.remove(uri).map_or_else(ParseCache::new, |document| document.cache);
+        let (grammar, errors, warnings) = cache.parse_str_partial(text, source_name(uri));
+        let lines: Vec<&str> = text.lines().collect();
+        // Diagnostics cover the whole line, or from an unclosed quote to its end
+        let diagnostic = |location: &Location, col: usize, severity: u8, message: String| {
+            let line = location.line.map_or(0, |line| line - 1);
+            let start = lines.get(line).map_or(0, |text| utf16_column(text, col));
+            let length = lines.get(line).map_or(0, |text| utf16_column(text, text.chars().count()));
+            json!({
+                "range": { "start": { "line": line, "character": start }, "end": { "line": line, "character": length } },
+                "severity": severity,
+                "source": "blabber",
+                "message": message
+            })
+        };
+        let diagnostics = errors.iter()
+            .map(|error| match error.error {
+                CompileErrorType::UnmatchedQuote { col, .. } => diagnostic(&error.location, col - 1, 1, error.error.to_string()),
+                _ => diagnostic(&error.location, 0, 1, error.error.to_string()),
+            })
+            .chain(warnings.iter().map(|warning| {
+                let mut diagnostic = diagnostic(&warning.location, 0, 2, warning.warning.to_string());
+                diagnostic["code"] = json!(warning.warning.code());
+                // Editors can strike through what's deprecated
+                if let CompileWarningType::Deprecated { .. } = warning.warning {
+                    diagnostic["tags"] = json!([2]);
+                }
+                diagnostic
+            }))
+            .collect();
+
+        self.documents.insert(uri.to_string(), Document { text: text.to_string(), grammar, cache });
+        return diagnostics_notification(uri, diagnostics);
+    }
+
+    // The document a request is about, and the nonterminal at the position
+    // it gives, if there is one
+    fn nonterminal_at<'a>(&'a self, params: &'a Value) -> Option<(&'a str, &'a Document, &'a str)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let line = document.text.lines().nth(params["position"]["line"].as_u64()? as usize)?;
+        if line.starts_with(';') {
+            return None;
+        }
+        let column = char_column(line, params["position"]["character"].as_u64()? as usize) + 1;
+        let tokens = lex_line_spanned(line).ok()?;
+        return tokens.into_iter().find_map(|(token, span)| match token {
+            Token::Nonterminal(name, _) if (span.col..=span.col + span.len).contains(&column) => Some((uri, document, name)),
+            _ => None,
+        });
+    }
+
+    // Where the rule named at the position is defined
+    fn definition(&self, params: &Value) -> Option<Value> {
+        let (uri, document, name) = self.nonterminal_at(params)?;
+        let line = document.grammar.rules.location(name)?.line? - 1;
+        let text = document.text.lines().nth(line)?;
+        // The rule's name is the first thing on its line
+        let indent = text.chars().take_while(|c| c.is_whitespace()).count();
+        let start = utf16_column(text, indent);
+        let end = start + name.encode_utf16().count();
+        return Some(json!({
+            "uri": uri,
+            "range": { "start": { "line": line, "character": start }, "end": { "line": line, "character": end } }
+        }));
+    }
+
+    // Every rule the document defines
+    fn completion(&self, params: &Value) -> Value {
+        let Some(document) = params["textDocument"]["uri"].as_str().and_then(|uri| self.documents.get(uri)) else {
+            return json!([]);
+        };
+        let items = document.grammar.rules.iter()
+            .filter(|(name, _)| Bracket::of_rule(name).is_none())
+            .sorted_by_key(|(name, _)| *name)
+            .map(|(name, rewrite)| json!({
+                "label": name,
+                // Variable
+                "kind": 6,
+                "detail": format!("{} alternatives", rewrite.len())
+            }))
+            .collect::<Vec<_>>();
+        return json!(items);
+    }
+
+    // The rule named at the position, written as BNF
+    fn hover(&self, params: &Value) -> Option<Value> {
+        let (_, document, name) = self.nonterminal_at(params)?;
+        document.grammar.rules.get(name)?;
+        return Some(json!({
+            "contents": { "kind": "markdown", "value": format!("```bnf\n{}\n```", written_rule_to_bnf(&document.grammar, name)) }
+        }));
+    }
+}
+
+fn diagnostics_notification(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics }
+    })
+}
+
+// Documents saved as files are named by their path, so errors and data
+// files are found relative to it
+fn source_name(uri: &str) -> SourceName {
+    match uri.strip_prefix("file://") {
+        Some(path) => SourceName::file(percent_decode(path)),
+        None => SourceName::Cli(uri.to_string()),
+    }
+}
+
+fn percent_decode(text: &str) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        let hex = after.get(..2).and_then(|hex| std::str::from_utf8(hex).ok()).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, hex) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &after[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = after;
+            }
+        }
+    }
+    return String::from_utf8_lossy(&bytes).into_owned();
+}
+
+// Positions count UTF-16 code units, while the lexer counts characters
+fn utf16_column(line: &str, chars: usize) -> usize {
+    line.chars().take(chars).map(char::len_utf16).sum()
+}
+
+// The number of characters before a position counted in UTF-16 code units
+fn char_column(line: &str, utf16: usize) -> usize {
+    let mut units = 0;
+    return line.chars().take_while(|c| {
+        units += c.len_utf16();
+        units <= utf16
+    }).count();
+}
+
+// Reads one message, or None at the end of the input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "A message has no Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    return serde_json::from_slice(&body).map(Some).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error));
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    return output.flush();
+}
+
+// Answers messages from input until the client says to exit or the input
+// ends, returning whether the client shut the server down first
+pub fn run(mut input: impl BufRead, mut output: impl Write) -> io::Result<bool> {
+    let mut server = Server::new();
+    while let Some(message) = read_message(&mut input)? {
+        let (replies, exit) = server.handle(&message);
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+        if exit {
+            break;
+        }
+    }
+    return Ok(server.shut_down);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///grammars/my%20words.bnf";
+    const TEXT: &str = "sentence = noun \" \" verb\nnoun = \"ideas\" | \"cats\"\nverb = \"sleep\" | adverb\n  ü = \"x\" | ü";
+
+    fn open(server: &mut Server, text: &str) -> Value {
+        let message = json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": { "uri": URI, "text": text } } });
+        let (mut replies, _) = server.handle(&message);
+        return replies.remove(0);
+    }
+
+    fn request(server: &mut Server, method: &str, line: u64, character: u64) -> Value {
+        let message = json!({ "jsonrpc": "2.0", "id": 7, "method": method, "params": {
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character }
+        } });
+        let (mut replies, _) = server.handle(&message);
+        assert_eq!(replies[0]["id"], 7);
+        return replies.remove(0)["result"].take();
+    }
+
+    #[test]
+    fn publish_diagnostics() {
+        let mut server = Server::new();
+        let published = open(&mut server, TEXT);
+        assert_eq!(published["params"]["uri"], URI);
+        let diagnostics = published["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["message"], "Could not find definition for `adverb`");
+        assert_eq!(diagnostics[0]["range"], json!({ "start": { "line": 2, "character": 0 }, "end": { "line": 2, "character": 23 } }));
+
+        let change = json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": "sentence = \"hi\"" }]
+        } });
+        let (replies, _) = server.handle(&change);
+        assert_eq!(replies[0]["params"]["diagnostics"], json!([]));
+        assert_eq!(server.documents[URI].cache.lexed(), 5);
+
+        // An unclosed quote is marked from where it opens
+        let change = json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+            "textDocument": { "uri": URI, "version": 3 },
+            "contentChanges": [{ "text": "sentence = \"hi\" | \"bye" }]
+        } });
+        let (replies, _) = server.handle(&change);
+        assert_eq!(replies[0]["params"]["diagnostics"][0]["range"], json!({ "start": { "line": 0, "character": 18 }, "end": { "line": 0, "character": 22 } }));
+
+        // Warnings say what kind they are
+        let published = open(&mut server, "s = old |\n%deprecated old \"use new\"\nold = \"x\"");
+        let diagnostics = &published["params"]["diagnostics"];
+        assert_eq!(diagnostics[0]["code"], "empty-alternative");
+        assert_eq!((&diagnostics[1]["code"], &diagnostics[1]["tags"]), (&json!("deprecated"), &json!([2])));
+        assert_eq!(diagnostics[1]["message"], "`old` is deprecated: use new");
+        assert_eq!(source_name(URI), SourceName::file("/grammars/my words.bnf"));
+    }
+
+    #[test]
+    fn navigate_rules() {
+        let mut server = Server::new();
+        open(&mut server, TEXT);
+
+        // `verb` in the first rule
+        let definition = request(&mut server, "textDocument/definition", 0, 22);
+        assert_eq!(definition["range"], json!({ "start": { "line": 2, "character": 0 }, "end": { "line": 2, "character": 4 } }));
+        let definition = request(&mut server, "textDocument/definition", 3, 13);
+        assert_eq!(definition["range"], json!({ "start": { "line": 3, "character": 2 }, "end": { "line": 3, "character": 3 } }));
+        // A terminal, and an undefined rule
+        assert_eq!(request(&mut server, "textDocument/definition", 1, 9), Value::Null);
+        assert_eq!(request(&mut server, "textDocument/definition", 2, 20), Value::Null);
+
+        let hover = request(&mut server, "textDocument/hover", 0, 12);
+        assert_eq!(hover["contents"]["value"], "```bnf\nnoun = \"ideas\" | \"cats\"\n```");
+
+        let completion = request(&mut server, "textDocument/completion", 0, 0);
+        let labels = completion.as_array().unwrap().iter().map(|item| item["label"].as_str().unwrap()).collect::<Vec<_>>();
+        assert_eq!(labels, ["noun", "sentence", "verb", "ü"]);
+    }
+
+    #[test]
+    fn speak_json_rpc() {
+        let messages = [
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "workspace/symbol", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "shutdown" }),
+        ];
+        let mut input = Vec::new();
+        for message in &messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        assert!(run(input.as_slice(), &mut output).unwrap());
+
+        let mut output = output.as_slice();
+        let mut replies = Vec::new();
+        while let Some(reply) = read_message(&mut output).unwrap() {
+            replies.push(reply);
+        }
+        // Nothing after exit is answered
+        assert_eq!(replies.len(), 3);
+        assert_eq!(replies[0]["result"]["capabilities"]["textDocumentSync"], 1);
+        assert_eq!(replies[1]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(replies[2], json!({ "jsonrpc": "2.0", "id": 3, "result": null }));
+
+        // Input ending without a shutdown
+        assert!(!run(&b""[..], Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn convert_columns() {
+        assert_eq!(utf16_column("a😀b", 2), 3);
+        assert_eq!(char_column("a😀b", 3), 2);
+        assert_eq!(char_column("a😀b", 2), 1);
+    }
+}
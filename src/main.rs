@@ -1,10 +1,22 @@
-use clap::Parser;
+#![allow(clippy::needless_return)]
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use blabber::*;
+use itertools::Itertools;
+use rand::{thread_rng, Rng, RngCore};
 
-mod grammar;
-mod parser;
-mod generator;
 mod cli;
-mod error_handling;
+mod config;
+mod interrupt;
+mod pick;
 
 fn create_generation_closure(grammar: grammar::Grammar, start: Option<String>) -> Box<dyn Fn() -> generator::GenResult> {
     match start {
@@ -13,25 +25,1622 @@ fn create_generation_closure(grammar: grammar::Grammar, start: Option<String>) -
     }
 }
 
-fn main() {
-    let args = cli::Cli::parse();
-    let grammar_res = parser::parse_file(&args.file);
-    if let Err(errors) = grammar_res {
-        for error in errors {
+// Every alternative to leave out, from --exclude, --exclude-containing,
+// --only-tags and --skip-tags
+fn exclusions(exclude: &[parser::Exclusion], containing: &[String], only_tags: &[String], skip_tags: &[String]) -> Vec<parser::Exclusion> {
+    let containing = containing.iter().cloned().map(parser::Exclusion::Containing);
+    let only_tags = (!only_tags.is_empty()).then(|| parser::Exclusion::OnlyTags(only_tags.to_vec()));
+    let skip_tags = skip_tags.iter().cloned().map(parser::Exclusion::SkipTag);
+    return exclude.iter().cloned().chain(containing).chain(only_tags).chain(skip_tags).collect();
+}
+
+// Parses the grammar file, printing any warnings about it
+fn parse_grammar(file: &PathBuf, options: &parser::ParseOptions) -> Result<grammar::Grammar, Error> {
+    let (grammar, warnings) = parser::parse_file_with_options(file, options)?;
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+    return Ok(grammar);
+}
+
+fn run_histogram(args: cli::HistogramArgs) -> Result<(), Error> {
+    let grammar = parse_grammar(&args.file, &parser::ParseOptions::default())?;
+    let amount = args.amount.unwrap_or(1000);
+    let max_distinct = args.max_distinct.unwrap_or(100_000);
+
+    let histogram = if args.variety.is_some() || args.temperature.is_some() {
+        let start = args.start.unwrap_or_else(|| grammar.start_symbol.clone());
+        let mut rng = generator::Xoshiro256StarStar::new(thread_rng().gen());
+        let temperature = args.temperature.map(|temperature| generator::Temperature::new(&grammar, temperature));
+        let mut variety = args.variety.map(generator::Variety::new);
+        histogram::histogram(|| {
+            let mut choices = generator::WeightedChoices { rng: &mut rng, temperature: temperature.as_ref(), variety: variety.as_mut() };
+            generator::generate_choosing(&grammar, &start, &mut choices, None, &mut ())
+        }, amount, max_distinct)?
+    } else {
+        histogram::histogram(create_generation_closure(grammar, args.start), amount, max_distinct)?
+    };
+
+    let top = args.top.unwrap_or(20);
+    let mut stdout = std::io::stdout().lock();
+    if args.json {
+        histogram.write_json(top, &mut stdout)?;
+    } else {
+        histogram.write_table(top, &mut stdout)?;
+    }
+    return Ok(());
+}
+
+fn run_stats(args: cli::StatsArgs) -> Result<(), Error> {
+    let mut grammar = parse_grammar(&args.file, &parser::ParseOptions::default())?;
+    parser::exclude_alternatives(&mut grammar, &exclusions(&args.exclude, &args.exclude_containing, &args.only_tags, &args.skip_tags))?;
+    let mut stdout = std::io::stdout().lock();
+
+    if args.expected_lengths {
+        let expectations = analysis::expected_length::expected_lengths(&grammar);
+        for (symbol, expectation) in analysis::expected_length::sorted_descending(&expectations) {
+            writeln!(stdout, "{:>12}  {}", expectation.to_string(), symbol)?;
+        }
+        return Ok(());
+    }
+
+    let counts = analysis::counts(&grammar);
+    writeln!(stdout, "start symbol: {}", grammar.start_symbol)?;
+    writeln!(stdout, "rules: {}", counts.rules)?;
+    writeln!(stdout, "alternatives: {}", counts.alternatives)?;
+    writeln!(stdout, "terminals: {}", counts.terminals)?;
+    writeln!(stdout, "nonterminals: {}", counts.nonterminals)?;
+    for (tag, alternatives) in grammar.tag_counts() {
+        writeln!(stdout, "tag {}: {} alternatives", tag, alternatives)?;
+    }
+    return Ok(());
+}
+
+// How the includes of URLs are handled, keeping fetched ones in the default
+// cache directory
+fn remote_includes(allow: bool, offline: bool) -> parser::RemoteIncludes {
+    match (allow, offline) {
+        (false, _) => parser::RemoteIncludes::Denied,
+        (true, false) => parser::RemoteIncludes::Fetch(parser::default_cache_dir()),
+        (true, true) => parser::RemoteIncludes::Offline(parser::default_cache_dir()),
+    }
+}
+
+// Returns whether the grammar passed every check
+fn run_check(args: cli::CheckArgs) -> Result<bool, Error> {
+    let options = parser::ParseOptions {
+        warning_levels: args.warning_levels(),
+        remote: remote_includes(args.allow_remote_includes, args.offline),
+        max_include_depth: args.max_include_depth,
+        warn_unreachable: true,
+        ..Default::default()
+    };
+    let (grammar, warnings) = parser::parse_file_with_options(&args.file, &options)?;
+    let mut passed = true;
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+
+    let deny_recursion = args.deny.iter().any(|finding| finding == "recursion");
+
+    if args.cycles || deny_recursion {
+        let mut stdout = std::io::stdout().lock();
+        for cycle in analysis::cycles::cycles(&grammar) {
+            if deny_recursion {
+                eprintln!("{}", error_handling::Error {
+                    location: grammar.location(cycle.members[0]).clone(),
+                    error: analysis::cycles::RecursionError(cycle.to_string())
+                });
+                passed = false;
+            }
+            if args.cycles {
+                writeln!(stdout, "cycle: {}", cycle)?;
+                for member in &cycle.members {
+                    writeln!(stdout, "  {} ({})", member, grammar.location(member))?;
+                }
+            }
+        }
+    }
+
+    let deny_duplicates = args.deny.iter().any(|finding| finding == "duplicates");
+    if args.duplicates || deny_duplicates {
+        let mut stdout = std::io::stdout().lock();
+        for group in analysis::duplicates::duplicate_rules(&grammar, args.ignore_alternative_order) {
+            if deny_duplicates {
+                eprintln!("{}", error_handling::Error {
+                    location: grammar.location(group[1]).clone(),
+                    error: analysis::duplicates::DuplicateError(group.join(", "))
+                });
+                passed = false;
+            }
+            if args.duplicates {
+                writeln!(stdout, "duplicates: {} (references to the others could use `{}`)", group.join(", "), group[0])?;
+                for member in &group {
+                    writeln!(stdout, "  {} ({})", member, grammar.location(member))?;
+                }
+            }
+        }
+    }
+
+    return Ok(passed);
+}
+
+// Checks each `;!` assertion in the grammar, printing whether it passed.
+// Assertions which can't be read count as failures.
+fn run_test(args: cli::TestArgs) -> Result<bool, Error> {
+    let (text, source) = if args.file.as_os_str() == "-" {
+        (std::io::read_to_string(std::io::stdin().lock())?, error_handling::SourceName::Stdin)
+    } else {
+        (std::fs::read_to_string(&args.file)?, error_handling::SourceName::file(&args.file))
+    };
+    let (grammar, warnings) = parser::parse_str_with_options(&text, source.clone(), &parser::ParseOptions::default())?;
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+
+    let (assertions, errors) = assertions::parse_assertions(&text, source);
+    for error in &errors {
+        eprintln!("{}", error);
+    }
+    let mut stdout = std::io::stdout().lock();
+    let mut failed = errors.len();
+    for assertion in &assertions {
+        match assertion.check(&grammar) {
+            Ok(()) => writeln!(stdout, "pass  {}  {}", assertion.location, assertion.check)?,
+            Err(error) => {
+                writeln!(stdout, "FAIL  {}  {}: {}", assertion.location, assertion.check, error.error)?;
+                failed += 1;
+            }
+        }
+    }
+    writeln!(stdout, "{} passed, {} failed", assertions.len() + errors.len() - failed, failed)?;
+    return Ok(failed == 0);
+}
+
+// Generates the sentences a seeded run of amount sentences from start would,
+// with --stable-noise if stable_noise
+fn generate_seeded(grammar: &grammar::Grammar, start: &str, seed: u64, amount: usize, stable_noise: bool) -> Result<Vec<String>, Error> {
+    let mut rng = generator::Xoshiro256StarStar::new(seed);
+    let sentences = (1..=amount as u64)
+        .map(|sentence| match stable_noise {
+            true => generator::generate_choosing(grammar, start, &mut generator::StableChoices::new(seed, sentence, 1), None, &mut ()),
+            false => generator::generate_from(grammar, start, &mut rng),
+        })
+        .collect::<Result<Vec<String>, generator::GenerateError>>()?;
+    return Ok(sentences);
+}
+
+// Writes a new snapshot with --write, or compares the grammar's output against
+// one with --check, returning whether it matched or was updated
+fn run_snapshot(args: cli::SnapshotArgs) -> Result<bool, Error> {
+    let grammar = parse_grammar(&args.file, &parser::ParseOptions::default())?;
+    let start = |recorded: Option<&str>| match (&args.start, recorded) {
+        (Some(start), _) if start == "auto" => default_start(&grammar, true),
+        (Some(start), _) => start.clone(),
+        (None, Some(recorded)) => recorded.to_string(),
+        (None, None) => default_start(&grammar, false),
+    };
+
+    let Some(path) = &args.check else {
+        let path = args.write.as_ref().expect("clap requires --write without --check");
+        let seed = args.seed.expect("clap requires --seed with --write");
+        let start = start(None);
+        let sentences = generate_seeded(&grammar, &start, seed, args.amount.unwrap_or(1) as usize, args.stable_noise)?;
+        std::fs::write(path, snapshot::Snapshot::new(seed, start, args.stable_noise, sentences).to_text())?;
+        return Ok(true);
+    };
+
+    let recorded = match snapshot::Snapshot::parse(&std::fs::read_to_string(path)?, error_handling::SourceName::file(path)) {
+        Ok(recorded) => recorded,
+        Err(error) => {
             eprintln!("{}", error);
+            return Ok(false);
         }
-        std::process::exit(1);
+    };
+    if recorded.version != snapshot::VERSION {
+        error_handling::warn(format!("{} was made by blabber {}, and seeded output can change between versions; this is {}", path.display(), recorded.version, snapshot::VERSION));
+    }
+    let seed = args.seed.unwrap_or(recorded.seed);
+    let start = start(Some(&recorded.start));
+    let amount = args.amount.map_or(recorded.sentences.len(), |amount| amount as usize);
+    let stable_noise = args.stable_noise || recorded.stable_noise;
+    let sentences = generate_seeded(&grammar, &start, seed, amount, stable_noise)?;
+
+    let mut stdout = std::io::stdout().lock();
+    let differences = snapshot::differences(&recorded.sentences, &sentences);
+    for difference in &differences {
+        write!(stdout, "{}", difference)?;
+    }
+    let settings_changed = seed != recorded.seed || start != recorded.start || stable_noise != recorded.stable_noise;
+    if differences.is_empty() && !settings_changed {
+        writeln!(stdout, "{} matches ({} sentences)", path.display(), sentences.len())?;
+        return Ok(true);
+    }
+    if args.update {
+        let changed = differences.len();
+        std::fs::write(path, snapshot::Snapshot::new(seed, start, stable_noise, sentences).to_text())?;
+        writeln!(stdout, "updated {} ({} sentences changed)", path.display(), changed)?;
+        return Ok(true);
+    }
+    if settings_changed {
+        writeln!(stdout, "the seed, start symbol or noise differs from the snapshot's")?;
+    }
+    writeln!(stdout, "{} differs ({} of {} sentences changed); rerun with --update to accept", path.display(), differences.len(), recorded.sentences.len().max(sentences.len()))?;
+    return Ok(false);
+}
+
+// Prints the tokens on each line of the grammar, with where they are. Lines
+// that fail to lex show the error, and comments are shown as one token.
+fn run_lex(args: cli::LexArgs) -> Result<(), Error> {
+    let reader: Box<dyn BufRead> = if args.file.as_os_str() == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(&args.file)?))
+    };
+    let json = args.format == "json";
+    let mut stdout = std::io::stdout().lock();
+
+    for (num, line_res) in reader.lines().enumerate() {
+        let line = line_res?;
+        let num = num + 1;
+        if line.is_empty() {
+            continue;
+        }
+
+        let lexed = if line.starts_with(';') {
+            let span = parser::lexer::Span { col: 1, len: line.chars().count() };
+            Ok(vec![("comment", line.clone(), None, span)])
+        } else {
+            parser::lexer::lex_line_spanned(&line).map(|tokens| tokens.into_iter()
+                .map(|(token, span)| (token.kind(), token.text().to_string(), token.case(), span))
+                .collect())
+        };
+
+        match (lexed, json) {
+            (Ok(tokens), true) => {
+                let tokens_json: Vec<String> = tokens.iter()
+                    .map(|(kind, text, case, span)| parser::lexer::span_json(kind, text, *case, span))
+                    .collect();
+                writeln!(stdout, "{{\"line\":{},\"tokens\":[{}]}}", num, tokens_json.join(","))?;
+            }
+            (Ok(tokens), false) => {
+                for (kind, text, case, span) in tokens {
+                    let suffix = case.map(|case| format!(":{}", case)).unwrap_or_default();
+                    writeln!(stdout, "{}:{}+{}\t{}\t{:?}{}", num, span.col, span.len, kind, text, suffix)?;
+                }
+            }
+            (Err(error), true) => writeln!(stdout, "{{\"line\":{},\"error\":{}}}", num, json::string(&error.to_string()))?,
+            (Err(error), false) => writeln!(stdout, "{}\terror\t{}", num, error)?,
+        }
+    }
+    return Ok(());
+}
+
+// A generated sentence and how it was made, with how often each rule was
+// expanded for it if --count-symbols was given
+type Generated = (Result<generator::Generation, generator::GenerateError>, Option<generator::SymbolCounts>);
+
+// Generates one sentence for each start symbol read from stdin, as they are
+// read, so blabber can be used as a filter. With --key-field, each line also
+// has a key to seed its sentence from. Unless lenient, unknown symbols are
+// reported as errors. Returns whether every line was generated successfully,
+// stopping early if out reaches its limit.
+fn run_stdin_starts(grammar: &grammar::Grammar, generate: &mut impl FnMut(&str, Option<u64>) -> Result<Generated, Error>, out: &mut limit::Limited<impl Write>, args: &cli::GenerateArgs) -> Result<bool, Error> {
+    let mut succeeded = true;
+
+    for (num, line_res) in std::io::stdin().lines().enumerate() {
+        let mut line = line_res?;
+        let mut own_seed = None;
+        if let Some(field) = args.key_field {
+            match seeds::split_key(&line, field as usize) {
+                Ok((key, rest)) => {
+                    let seed = seeds::key_seed(key);
+                    if args.verbose {
+                        eprintln!("`{}` gives seed {}", key, seed);
+                    }
+                    own_seed = Some(seed);
+                    line = rest;
+                }
+                Err(fields) => {
+                    let location = error_handling::Location::line(error_handling::SourceName::Stdin, num + 1);
+                    eprintln!("{}", error_handling::Error { location, error: seeds::SeedErrorType::MissingKey { field: field as usize, fields } });
+                    succeeded = false;
+                    if !args.keep_going {
+                        break;
+                    }
+                    continue;
+                }
+            }
+        }
+        let start = line.trim();
+
+        let (generated_res, symbol_counts) = if args.lenient || grammar.rules.contains_key(start) {
+            generate(start, own_seed)?
+        } else {
+            (Err(generator::GenerateErrorType::UndefinedNonterminal(start.to_string()).into()), None)
+        };
+        match generated_res {
+            Ok(generation) => {
+                write_sentence(out, start, &generation.text, symbol_counts.as_ref(), args)?;
+                out.flush()?;
+                if out.end_record() {
+                    break;
+                }
+            }
+            Err(error) => {
+                let location = error_handling::Location::line(error_handling::SourceName::Stdin, num + 1);
+                let error = error_handling::Error { location, error };
+                eprintln!("{}", error);
+                print_partial(&error.error, args);
+                succeeded = false;
+                if !args.keep_going {
+                    break;
+                }
+            }
+        }
+    }
+
+    return Ok(succeeded);
+}
+
+// Writes a generated sentence as a line of output, escaped with --escape and
+// after its start symbol and a tab with --label, then the counts of the rules
+// expanded for it if there are any
+fn write_sentence(out: &mut dyn Write, start: &str, sentence: &str, counts: Option<&generator::SymbolCounts>, args: &cli::GenerateArgs) -> Result<(), Error> {
+    let escape = escape::Escape::from_name(&args.escape).expect("clap only allows known escapes");
+    let sentence = escape.apply(sentence).map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+    if args.label {
+        writeln!(out, "{}\t{}", start, sentence)?;
+    } else {
+        writeln!(out, "{}", sentence)?;
+    }
+    if let Some(counts) = counts {
+        writeln!(out, "# counts {}", counts.to_json())?;
+    }
+    return Ok(());
+}
+
+// Lists the undefined symbols that lenient generation replaced, most
+// frequent first
+fn warn_missing(missing: &generator::MissingCounts) {
+    if missing.is_empty() {
+        return;
+    }
+    let mut counts: Vec<(&String, &u64)> = missing.iter().collect();
+    counts.sort_by(|(a_symbol, a_count), (b_symbol, b_count)| b_count.cmp(a_count).then_with(|| a_symbol.cmp(b_symbol)));
+    let listed: Vec<String> = counts.iter()
+        .map(|(symbol, count)| format!("`{}` ({}×)", symbol, count))
+        .collect();
+    error_handling::warn(format!("Placeholders were generated for undefined symbols: {}", listed.join(", ")));
+}
+
+// The start symbol to use when none is given, or with `--start auto`. Warns
+// when the first rule doesn't look like the start, and with `auto` uses the
+// one rule nothing else references if there is exactly one.
+fn default_start(grammar: &grammar::Grammar, auto: bool) -> String {
+    let warning = analysis::start::check_start(grammar);
+    if let (true, Some(analysis::start::StartWarningType::Inferred { inferred, .. })) = (auto, &warning) {
+        return inferred.clone();
+    }
+    if let Some(warning) = warning {
+        eprintln!("{}", error_handling::Warning {
+            location: grammar.location(&grammar.start_symbol).clone(),
+            warning
+        });
+    }
+    return grammar.start_symbol.clone();
+}
+
+// The symbols each sentence may start from, with one picked per sentence.
+// `--start random` means every rule but the ones made for brackets, sorted
+// so seeded runs are repeatable.
+fn start_candidates(grammar: &grammar::Grammar, args: &cli::GenerateArgs) -> Result<Vec<String>, Error> {
+    let candidates = match (&args.start, &args.start_any) {
+        (Some(start), _) if start == "random" => grammar.rules.keys().filter(|rule| grammar::Bracket::of_rule(rule).is_none()).map(|rule| rule.to_string()).sorted().collect(),
+        (Some(start), _) if start == "auto" => vec![default_start(grammar, true)],
+        (Some(start), _) => vec![start.clone()],
+        (None, Some(starts)) => starts.clone(),
+        (None, None) => vec![default_start(grammar, false)],
+    };
+
+    if !args.lenient {
+        if let Some(undefined) = candidates.iter().find(|start| !grammar.rules.contains_key(start.as_str())) {
+            return Err(generator::GenerateErrorType::UndefinedNonterminal(undefined.clone()).into());
+        }
+    }
+    return Ok(candidates);
+}
+
+// Generates like generator::generate_choosing, along with how the sentence
+// was made, failing if rules nest more than max_depth deep. With a painter,
+// it also colors the sentence by the rules that made it into the string
+// given with it.
+fn generate_painted(grammar: &grammar::Grammar, start: &str, choices: &mut impl generator::ChoiceProvider, missing: Option<&mut generator::MissingCounts>,
+    observer: &mut impl generator::GenerationObserver, painting: Option<(&mut color::Painter, &mut Option<String>)>, max_depth: usize) -> Result<(String, generator::Measurements), generator::GenerateError> {
+    let (tokens, measurements) = generator::generate_tokens_measured(grammar, start, choices, missing, observer, max_depth)?;
+    if let Some((painter, painted)) = painting {
+        *painted = Some(painter.paint(&tokens));
+    }
+    return Ok((tokens.iter().map(|token| token.text.as_str()).collect(), measurements));
+}
+
+// Expands each sentence a step at a time for --steps, printing what it got
+// to, or with --steps-trace, what it was after every step, starting from the
+// start symbol, with a blank line between sentences
+fn run_steps(grammar: &grammar::Grammar, args: &cli::GenerateArgs, rng: &mut generator::Xoshiro256StarStar) -> Result<(), Error> {
+    let starts = start_candidates(grammar, args)?;
+    let mut stdout = std::io::stdout().lock();
+    for index in 0..args.amount.unwrap_or(1) {
+        let start = match starts.len() {
+            1 => &starts[0],
+            len => &starts[generator::choose_index(rng, len)],
+        };
+        if args.steps_trace {
+            if index > 0 {
+                writeln!(stdout)?;
+            }
+            writeln!(stdout, "{}", generator::SententialForm::new(start))?;
+        }
+        let mut written = Ok(());
+        let form = generator::derive_steps(grammar, start, args.steps, &mut generator::RandomChoices(&mut *rng), args.lenient, |form| {
+            if args.steps_trace && written.is_ok() {
+                written = writeln!(stdout, "{}", form);
+            }
+        })?;
+        written?;
+        if !args.steps_trace {
+            writeln!(stdout, "{}", form)?;
+        }
+    }
+    return Ok(());
+}
+
+// Generates one sentence for each trace in the file, making exactly the
+// choices it lists. Returns whether every trace could be replayed, stopping
+// at the first one that can't.
+fn run_choices_in(grammar: &grammar::Grammar, path: &PathBuf, args: &cli::GenerateArgs, missing: &mut generator::MissingCounts) -> Result<bool, Error> {
+    let mut stdout = std::io::stdout().lock();
+
+    for (num, line_res) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line_res?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let replayed = generator::Trace::from_json(&line).map_err(generator::GenerateError::from).and_then(|trace| {
+            if !args.lenient && !grammar.rules.contains_key(trace.start.as_str()) {
+                return Err(generator::GenerateErrorType::UndefinedNonterminal(trace.start).into());
+            }
+            let mut replay = generator::ReplayChoices::new(&trace);
+            let generated = generator::generate_choosing(grammar, &trace.start, &mut replay, args.lenient.then_some(&mut *missing), &mut ())?;
+            replay.finish()?;
+            Ok((trace.start.clone(), generated))
+        });
+        match replayed {
+            Ok((start, generated)) => write_sentence(&mut stdout, &start, &generated, None, args)?,
+            Err(error) => {
+                let location = error_handling::Location::line(error_handling::SourceName::file(path), num + 1);
+                let error = error_handling::Error { location, error };
+                eprintln!("{}", error);
+                print_partial(&error.error, args);
+                return Ok(false);
+            }
+        }
+    }
+
+    return Ok(true);
+}
+
+// Generates the sentences of one job, writing them to its file, or to out
+// after a `# job:` header unless they're labelled. Returns the undefined
+// symbols counted in lenient mode.
+fn run_job(grammar: &grammar::Grammar, job: &jobs::Job, seed: u64, counts: Option<&analysis::derivations::DerivationCounts>, args: &cli::GenerateArgs, out: &mut dyn Write) -> Result<generator::MissingCounts, Error> {
+    let mut file = match &job.output {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    let out: &mut dyn Write = match &mut file {
+        Some(file) => file,
+        None => {
+            if !args.label {
+                writeln!(out, "# job: {}\t{}", job.start, job.count)?;
+            }
+            out
+        }
+    };
+
+    let mut rng = generator::Xoshiro256StarStar::new(seed);
+    let mut missing = generator::MissingCounts::new();
+    let max_depth = args.max_depth.unwrap_or(generator::DEFAULT_MAX_DEPTH);
+    for _ in 0..job.count {
+        if interrupt::interrupted() {
+            break;
+        }
+        let generated = match counts {
+            Some(counts) => generator::generate_uniform(grammar, counts, &job.start, &mut rng, max_depth),
+            None => generate_painted(grammar, &job.start, &mut generator::RandomChoices(&mut rng), args.lenient.then_some(&mut missing), &mut (), None, max_depth)
+                .map(|(sentence, _)| sentence),
+        }?;
+        write_sentence(out, &job.start, &generated, None, args)?;
+    }
+    out.flush()?;
+    return Ok(missing);
+}
+
+// Generates every job in a jobs file from the one grammar. Each job gets its
+// own seed, drawn from rng in order, so running jobs in parallel with --jobs
+// gives the same output as running them one at a time. Parallel jobs writing
+// to stdout are buffered and printed in order. Returns how many sentences
+// were generated, or None if a job failed.
+fn run_jobs(grammar: &grammar::Grammar, path: &PathBuf, args: &cli::GenerateArgs, rng: &mut generator::Xoshiro256StarStar, counts: Option<&analysis::derivations::DerivationCounts>, missing: &mut generator::MissingCounts) -> Result<Option<u64>, Error> {
+    let text = std::fs::read_to_string(path)?;
+    let jobs = match jobs::parse_jobs(&text, error_handling::SourceName::file(path), args.key_field.map(|field| field as usize)) {
+        Ok(jobs) => jobs,
+        Err(errors) => {
+            errors.iter().for_each(|error| eprintln!("{}", error));
+            return Ok(None);
+        }
+    };
+    let errors = if args.lenient { Vec::new() } else { jobs::check_starts(&jobs, grammar) };
+    if !errors.is_empty() {
+        errors.iter().for_each(|error| eprintln!("{}", error));
+        return Ok(None);
+    }
+    // Keyed jobs still draw a seed, so adding a key to one job leaves the
+    // others' sentences alone
+    let seeds: Vec<u64> = jobs.iter()
+        .map(|job| {
+            let drawn = rng.next_u64();
+            let Some(key) = &job.key else {
+                return drawn;
+            };
+            let seed = seeds::key_seed(key);
+            if args.verbose {
+                eprintln!("{}: `{}` gives seed {}", job.location, key, seed);
+            }
+            return seed;
+        })
+        .collect();
+
+    // Reports how a job went, returning whether it succeeded
+    let mut report = |job: &jobs::Job, result: Result<generator::MissingCounts, Error>, took: std::time::Duration| {
+        match result {
+            Ok(job_missing) => {
+                for (symbol, count) in job_missing {
+                    *missing.entry(symbol).or_insert(0) += count;
+                }
+                eprintln!("{} ({} × {}) took {}", job.location, job.start, job.count, timing::humanize(took));
+                true
+            }
+            Err(error) => {
+                eprintln!("{}", error_handling::Error { location: job.location.clone(), error: jobs::JobErrorType::Failed(error.to_string()) });
+                false
+            }
+        }
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    let threads = (args.jobs as usize).min(jobs.len());
+    interrupt::watch();
+    if threads <= 1 {
+        for (job, seed) in jobs.iter().zip(&seeds) {
+            if interrupt::interrupted() {
+                break;
+            }
+            let started = Instant::now();
+            let result = run_job(grammar, job, *seed, counts, args, &mut stdout);
+            if !report(job, result, started.elapsed()) {
+                return Ok(None);
+            }
+        }
+    } else {
+        let next = AtomicUsize::new(0);
+        let finished: Vec<Mutex<Option<_>>> = jobs.iter().map(|_| Mutex::new(None)).collect();
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(job) = jobs.get(index).filter(|_| !interrupt::interrupted()) else {
+                        break;
+                    };
+                    let started = Instant::now();
+                    let mut buffer = Vec::new();
+                    let result = run_job(grammar, job, seeds[index], counts, args, &mut buffer);
+                    *finished[index].lock().expect("jobs don't panic while holding the lock") = Some((result, buffer, started.elapsed()));
+                });
+            }
+        });
+
+        let mut succeeded = true;
+        for (job, finished) in jobs.iter().zip(finished) {
+            // Jobs not started before an interruption have no result
+            let Some((result, buffer, took)) = finished.into_inner().expect("jobs don't panic while holding the lock") else {
+                continue;
+            };
+            stdout.write_all(&buffer)?;
+            succeeded &= report(job, result, took);
+        }
+        if !succeeded {
+            return Ok(None);
+        }
+    }
+    return Ok(Some(jobs.iter().map(|job| job.count as u64).sum()));
+}
+
+// Lists the terminals or words of the grammar for --list-terminals or
+// --words
+fn run_list_terminals(grammar: &grammar::Grammar, args: &cli::GenerateArgs) -> Result<(), Error> {
+    let reachable = if args.reachable {
+        let mut reachable = HashSet::new();
+        for start in start_candidates(grammar, args)? {
+            reachable.extend(analysis::reachable_rules(grammar, &start));
+        }
+        Some(reachable)
+    } else {
+        None
+    };
+
+    let terminals = analysis::terminals::terminals(grammar, reachable.as_ref());
+    let listed = if args.words { analysis::terminals::words(&terminals) } else { terminals };
+
+    let mut stdout = std::io::stdout().lock();
+    for (text, count) in listed {
+        if args.counts {
+            writeln!(stdout, "{}\t{}", count, analysis::terminals::escape(text))?;
+        } else {
+            writeln!(stdout, "{}", analysis::terminals::escape(text))?;
+        }
+    }
+    return Ok(());
+}
+
+fn run_list_tags(grammar: &grammar::Grammar, args: &cli::GenerateArgs) -> Result<(), Error> {
+    let mut stdout = std::io::stdout().lock();
+    for (tag, count) in grammar.tag_counts() {
+        if args.counts {
+            writeln!(stdout, "{}\t{}", count, tag)?;
+        } else {
+            writeln!(stdout, "{}", tag)?;
+        }
+    }
+    return Ok(());
+}
+
+// Lets the user generate a sentence by hand, asking on stderr, then prints it
+// and its trace on stdout. Nothing is printed if stdin ends first.
+fn run_pick(args: cli::PickArgs) -> Result<(), Error> {
+    let grammar = parse_grammar(&args.file, &parser::ParseOptions::default())?;
+    let start = args.start.unwrap_or_else(|| grammar.start_symbol.clone());
+    if !grammar.rules.contains_key(start.as_str()) {
+        return Err(generator::GenerateErrorType::UndefinedNonterminal(start).into());
+    }
+    let rng = generator::Xoshiro256StarStar::new(args.seed.unwrap_or_else(|| thread_rng().gen()));
+    let mut picker = pick::Picker::new(&grammar, std::io::stdin().lock(), std::io::stderr(), rng);
+    if let Some((sentence, trace)) = picker.pick(&start)? {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", sentence)?;
+        writeln!(stdout, "{}", trace.to_json())?;
+    }
+    return Ok(());
+}
+
+fn run_compile(args: cli::CompileArgs) -> Result<(), Error> {
+    let options = parser::ParseOptions {
+        warning_levels: args.warning_levels(),
+        remote: remote_includes(args.allow_remote_includes, args.offline),
+        max_include_depth: args.max_include_depth,
+        ..Default::default()
+    };
+    let grammar = parse_grammar(&args.file, &options)?;
+    std::fs::write(&args.output, compiled::to_bytes(&grammar))?;
+    return Ok(());
+}
+
+// Writes the grammar as a Rust module, to the output file if there is one
+fn run_codegen(args: cli::CodegenArgs) -> Result<(), Error> {
+    let grammar = parse_grammar(&args.file, &parser::ParseOptions::default())?;
+    let module = codegen::codegen(&grammar)?;
+    match args.output {
+        Some(output) => std::fs::write(output, module)?,
+        None => std::io::stdout().lock().write_all(module.as_bytes())?,
+    }
+    return Ok(());
+}
+
+// Compares the sentences of two grammars, returning whether they're the same
+fn run_equiv(args: cli::EquivArgs) -> Result<bool, Error> {
+    let first = parse_grammar(&args.first, &parser::ParseOptions::default())?;
+    let second = parse_grammar(&args.second, &parser::ParseOptions::default())?;
+    let first_start = args.start.clone().unwrap_or_else(|| first.start_symbol.clone());
+    let second_start = args.start.clone().unwrap_or_else(|| second.start_symbol.clone());
+    for (grammar, start) in [(&first, &first_start), (&second, &second_start)] {
+        if !grammar.rules.contains_key(start.as_str()) {
+            return Err(generator::GenerateErrorType::UndefinedNonterminal(start.clone()).into());
+        }
+    }
+
+    let options = equivalence::EquivalenceOptions {
+        samples: args.samples,
+        max_depth: args.max_depth,
+        counterexamples: args.counterexamples
+    };
+    let mut rng = generator::Xoshiro256StarStar::new(args.seed.unwrap_or_else(|| thread_rng().gen()));
+    let comparison = equivalence::compare((&first, &first_start), (&second, &second_start), &options, &mut rng)?;
+
+    let mut stdout = std::io::stdout().lock();
+    for (sentences, file) in [(&comparison.only_first, &args.first), (&comparison.only_second, &args.second)] {
+        for sentence in sentences {
+            writeln!(stdout, "only in {}: {}", file.display(), grammar::Symbol::Terminal(sentence.as_str().into()))?;
+        }
+    }
+    match (comparison.method, comparison.equivalent()) {
+        (equivalence::Method::Enumerated(sentences), true) => writeln!(stdout, "equivalent: both generate the same {} sentences", sentences)?,
+        (equivalence::Method::Enumerated(_), false) => writeln!(stdout, "not equivalent")?,
+        (equivalence::Method::Sampled(samples), true) => writeln!(stdout, "no differences found in {} samples from each grammar", samples)?,
+        (equivalence::Method::Sampled(_), false) => writeln!(stdout, "not equivalent (found by sampling)")?,
+    }
+    return Ok(comparison.equivalent());
+}
+
+fn run_cover(args: cli::CoverArgs) -> Result<(), Error> {
+    let grammar = parse_grammar(&args.file, &parser::ParseOptions::default())?;
+    let start = match args.start {
+        Some(start) if start == "auto" => default_start(&grammar, true),
+        Some(start) => start,
+        None => default_start(&grammar, false),
+    };
+    if !grammar.rules.contains_key(start.as_str()) {
+        return Err(generator::GenerateErrorType::UndefinedNonterminal(start).into());
+    }
+    let corpus = if args.corpus.as_os_str() == "-" {
+        std::io::read_to_string(std::io::stdin().lock())?
+    } else {
+        std::fs::read_to_string(&args.corpus)?
+    };
+
+    let coverage = coverage::cover(&grammar, &start, &corpus, args.unmatched);
+    let mut stdout = std::io::stdout().lock();
+    write!(stdout, "{}", coverage::Report { coverage: &coverage, grammar: &grammar })?;
+    if args.unmatched {
+        for underivable in &coverage.underivable {
+            writeln!(stdout, "{}", underivable)?;
+        }
+    }
+    return Ok(());
+}
+
+// Reports each word in the grammar's terminals missing from the dictionary,
+// returning whether there were none
+fn run_lint(args: cli::LintArgs) -> Result<bool, Error> {
+    let grammar = parse_grammar(&args.file, &parser::ParseOptions::default())?;
+    let dictionary = lint::Dictionary::parse(&std::fs::read_to_string(&args.dictionary)?, args.case_sensitive);
+    let options = lint::LintOptions { check_punctuation: args.check_punctuation, check_numbers: args.check_numbers };
+    let unknown = lint::unknown_words(&grammar, &dictionary, &options);
+
+    for (location, error) in &unknown {
+        eprintln!("{}", error_handling::Error { location: location.clone(), error: error.clone() });
+    }
+    if let Some(path) = &args.unknown_words_out {
+        let mut out = BufWriter::new(File::create(path)?);
+        for (location, unknown) in &unknown {
+            writeln!(out, "{}\t{}\t{}", unknown.word, unknown.rule, location)?;
+        }
+        out.flush()?;
+    }
+    return Ok(unknown.is_empty());
+}
+
+// Merges grammar files into one, written to the output file if there is one
+fn run_merge(args: cli::MergeArgs) -> Result<(), Error> {
+    // Files can use rules from the others, so they're verified once merged
+    let unverified = parser::ParseOptions { verify: false, ..Default::default() };
+    let grammars = args.files.iter().map(|file| parse_grammar(file, &unverified)).collect::<Result<Vec<_>, _>>()?;
+    let options = merge::MergeOptions {
+        on_conflict: merge::ConflictPolicy::from_name(&args.on_conflict).expect("clap only accepts known policies"),
+        prune: args.prune
+    };
+    let merged = merge::merge(grammars, &options)?;
+    let bnf = merged.to_bnf().map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+    // The merged file has to stand on its own, so it's checked before it's written
+    parser::parse_str(&bnf, error_handling::SourceName::Cli("merged".to_string()))?;
+
+    match args.output {
+        Some(output) => std::fs::write(output, bnf)?,
+        None => std::io::stdout().lock().write_all(bnf.as_bytes())?,
+    }
+    return Ok(());
+}
+
+// Writes a grammar out again, as canonical BNF with the start symbol first
+// and the comments kept, or minified. Included rules are left to the files
+// they're from, with the `%include`s kept like comments.
+fn run_fmt(args: cli::FmtArgs) -> Result<(), Error> {
+    let (mut grammar, warnings, mut trivia) = parser::parse_file_with_trivia(&args.file, &parser::ParseOptions::default())?;
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+    let source = if args.file.as_os_str() == "-" { error_handling::SourceName::Stdin } else { error_handling::SourceName::file(&args.file) };
+    let syntax = parser::Syntax::from_name(&args.to).expect("clap only accepts known syntaxes");
+    let formatted = if args.minify {
+        let names = grammar.minify_names();
+        if let Some(map) = &args.map {
+            std::fs::write(map, grammar::names_to_json(&names) + "\n")?;
+        }
+        grammar::to_minified_bnf(&grammar, &grammar::definition_order(&grammar))
+    } else if syntax == parser::Syntax::Yaml {
+        grammar::to_yaml(&grammar, &grammar::definition_order(&grammar))
+    } else {
+        let mut order = grammar::definition_order(&grammar);
+        order.retain(|rule| grammar.location(rule).source == source);
+        // `%deprecated`s are written above their rules, so the file's own
+        // ones of included rules go at the end instead
+        grammar.deprecations.retain(|_, deprecation| deprecation.location.source == source);
+        let elsewhere = grammar.deprecations.iter()
+            .filter(|(rule, _)| !order.iter().any(|ordered| ordered[..] == rule[..]))
+            .sorted_by_key(|(_, deprecation)| deprecation.location.line)
+            .map(|(rule, deprecation)| grammar::deprecation_to_bnf(rule, &deprecation.message))
+            .collect_vec();
+        trivia.end.splice(0..0, elsewhere);
+        grammar::to_bnf(&grammar, &order, &trivia)
+    }.map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+    // Parsed as the same file, so its includes are found
+    let options = parser::ParseOptions { syntax: Some(syntax), ..Default::default() };
+    parser::parse_str_with_options(&formatted, source, &options)?;
+
+    match args.output {
+        Some(output) => std::fs::write(output, formatted)?,
+        None => std::io::stdout().lock().write_all(formatted.as_bytes())?,
+    }
+    return Ok(());
+}
+
+// Writes documentation of every rule of a grammar, titled with the file's
+// name
+fn run_export(args: cli::ExportArgs) -> Result<(), Error> {
+    let grammar = parse_grammar(&args.file, &parser::ParseOptions::default())?;
+    let title = match args.file.file_stem() {
+        Some(stem) if args.file.as_os_str() != "-" => stem.to_string_lossy().into_owned(),
+        _ => "Grammar".to_string(),
+    };
+    let markdown = grammar::to_markdown(&grammar, &title, args.examples, args.seed);
+    match args.output {
+        Some(output) => std::fs::write(output, markdown)?,
+        None => std::io::stdout().lock().write_all(markdown.as_bytes())?,
+    }
+    return Ok(());
+}
+
+fn run_new(args: cli::NewArgs) -> Result<(), Error> {
+    let template = scaffold::Template::from_name(&args.template).expect("clap only accepts known templates");
+    let grammar_path = scaffold::create_project(&args.dir, template)?;
+    println!("Created {}, try `blabber {}`", args.dir.display(), grammar_path.display());
+    return Ok(());
+}
+
+// Generates a sentence from a seed again, the same way a run with that seed
+// did, and prints which rule produced each part
+fn run_explain(args: cli::ExplainArgs) -> Result<(), Error> {
+    let grammar = parse_grammar(&args.file, &parser::ParseOptions::default())?;
+    let start = match args.start {
+        Some(start) if start == "auto" => default_start(&grammar, true),
+        Some(start) => start,
+        None => default_start(&grammar, false),
+    };
+
+    let explanation = if args.stable_noise {
+        generator::generate_explained(&grammar, &start, &mut generator::StableChoices::new(args.seed, args.sentence.into(), 1))?
+    } else {
+        let mut rng = generator::Xoshiro256StarStar::new(args.seed);
+        // Generate the sentences before it, to get the generator to where it was
+        for _ in 1..args.sentence {
+            generator::generate_from(&grammar, &start, &mut rng)?;
+        }
+        generator::generate_explained(&grammar, &start, &mut generator::RandomChoices(&mut rng))?
+    };
+
+    if args.format == "json" {
+        println!("{}", explanation.to_json());
+    } else {
+        print!("{}", explanation);
+    }
+    return Ok(());
+}
+
+// Where generating from start failed: the start rule, or the whole grammar
+// if it isn't defined, which --lenient allows
+fn failure_location(grammar: &Grammar, start: &str) -> error_handling::Location {
+    grammar.rules.location(start)
+        .unwrap_or_else(|| error_handling::Location::whole(grammar.location(&grammar.start_symbol).source))
+}
+
+// Shows what had been generated when generation failed, with --verbose
+fn print_partial(error: &generator::GenerateError, args: &cli::GenerateArgs) {
+    if let (true, Some(partial)) = (args.verbose, &error.partial) {
+        eprintln!("partial output:\n{}", partial.preview(args.partial_limit));
+    }
+}
+
+// Prints how long each phase took with `--time`
+fn report_timings(timings: Option<timing::Timings>, args: &cli::GenerateArgs) {
+    match (timings, args.time.as_deref()) {
+        (Some(timings), Some("json")) => eprintln!("{}", timings.to_json()),
+        (Some(timings), _) => eprintln!("{}", timings),
+        (None, _) => {}
+    }
+}
+
+// Writes the records held until the end of a run: the best ones with --keep,
+// followed by what they measured with --verbose, sorted with --sort
+fn write_held(best: Option<keep::Best>, mut sorted: Option<sorting::SortedOutput>, out: &mut dyn Write, args: &cli::GenerateArgs) -> Result<(), Error> {
+    if let Some(best) = best {
+        let by = best.by();
+        for (value, mut record) in best.into_best() {
+            if args.verbose {
+                record += &format!("# {} {}\n", by.name(), value);
+            }
+            match &mut sorted {
+                Some(sorted) => sorted.push(record),
+                None => out.write_all(record.as_bytes())?,
+            }
+        }
+    }
+    if let Some(sorted) = sorted {
+        sorted.write_to(out)?;
+    }
+    return Ok(());
+}
+
+// Parses the grammar to generate from, printing any warnings about it, then
+// fills in its --param values and leaves out the excluded alternatives
+fn load_generate_grammar(args: &cli::GenerateArgs, timings: &mut Option<timing::Timings>) -> Result<grammar::Grammar, Error> {
+    let options = parser::ParseOptions {
+        verify: !args.lenient && !args.no_verify,
+        warning_levels: args.warning_levels(),
+        syntax: args.syntax.as_deref().map(|name| parser::Syntax::from_name(name).expect("clap only accepts known syntaxes")),
+        remote: remote_includes(args.allow_remote_includes, args.offline),
+        max_include_depth: args.max_include_depth,
+        warn_unreachable: false
+    };
+    let (mut grammar, warnings) = match (&args.grammar, &args.file, timings) {
+        (Some(text), _, Some(timings)) => parser::parse_arg_timed(text, &options, timings)?,
+        (Some(text), _, None) => parser::parse_arg_with_options(text, &options)?,
+        (None, Some(file), Some(timings)) => parser::parse_file_timed(file, &options, timings)?,
+        (None, Some(file), None) => parser::parse_file_with_options(file, &options)?,
+        (None, None, _) => unreachable!("clap requires a file or --grammar"),
+    };
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+    if !args.param.is_empty() {
+        parser::substitute_params(&mut grammar, &args.param.iter().cloned().collect())?;
+    }
+    parser::exclude_alternatives(&mut grammar, &exclusions(&args.exclude, &args.exclude_containing, &args.only_tags, &args.skip_tags))?;
+    return Ok(grammar);
+}
+
+// Runs the passes given with --optimize, describing what they changed with
+// --verbose
+fn optimize_grammar(grammar: &mut grammar::Grammar, args: &cli::GenerateArgs, timings: &mut Option<timing::Timings>) {
+    if args.optimize.iter().any(|pass| pass == "merge-duplicates") {
+        let consolidations = timing::maybe_time(timings, "optimizing", || grammar.merge_duplicates(args.ignore_alternative_order));
+        if args.verbose {
+            for consolidation in consolidations {
+                eprintln!("{}", consolidation);
+            }
+        }
+    }
+    if args.optimize.iter().any(|pass| pass == "left-factor") {
+        let factorings = timing::maybe_time(timings, "optimizing", || grammar.left_factor());
+        if args.verbose {
+            for factoring in factorings {
+                eprintln!("{}", factoring);
+            }
+        }
+    }
+}
+
+// The seed of the run: --seed, one derived from --seed-from, or a random one
+fn run_seed(args: &cli::GenerateArgs) -> u64 {
+    match (args.seed, &args.seed_from) {
+        (Some(seed), _) => seed,
+        (None, Some(key)) => {
+            let seed = seeds::key_seed(key);
+            if args.verbose {
+                eprintln!("`{}` gives seed {}", key, seed);
+            }
+            seed
+        }
+        (None, None) => thread_rng().gen(),
+    }
+}
+
+// The number of derivations of each size for --uniform-sentences, bounded by
+// --max-depth or else the grammar's own depth
+fn uniform_counts<'a>(grammar: &'a grammar::Grammar, args: &cli::GenerateArgs, timings: &mut Option<timing::Timings>) -> Result<Option<analysis::derivations::DerivationCounts<'a>>, Error> {
+    if !args.uniform_sentences {
+        return Ok(None);
+    }
+    let depth = args.max_depth
+        .or_else(|| analysis::derivations::finite_depth(grammar))
+        .ok_or(generator::GenerateErrorType::DepthBoundRequired)?;
+    return Ok(Some(timing::maybe_time(timings, "counting", || analysis::derivations::DerivationCounts::new(grammar, depth))));
+}
+
+// The seed of each sentence with --seeds-file, or None without it. Seeds
+// which can't be read are reported, giving Err.
+fn read_seeds(args: &cli::GenerateArgs) -> Result<Result<Option<Vec<u64>>, ()>, Error> {
+    let Some(path) = &args.seeds_file else {
+        return Ok(Ok(None));
+    };
+    return match seeds::parse_seeds(&std::fs::read_to_string(path)?, error_handling::SourceName::file(path)) {
+        Ok(seeds) => Ok(Ok(Some(seeds))),
+        Err(errors) => {
+            errors.iter().for_each(|error| eprintln!("{}", error));
+            Ok(Err(()))
+        }
+    };
+}
+
+// What's watched while one sentence is generated: its choices for
+// --choices-out, how often each rule was expanded for --count-symbols, and
+// its colored text for --color-output
+#[derive(Default)]
+struct Watched {
+    trace: Option<generator::Trace>,
+    symbol_counts: Option<generator::SymbolCounts>,
+    painted: Option<String>,
+}
+
+// Generates the sentences of a run one at a time, keeping what's measured
+// of them to report once they're all done
+struct Sentences<'a> {
+    grammar: &'a grammar::Grammar,
+    args: &'a cli::GenerateArgs,
+    // Kept for saying which sentence of the run was empty
+    seed: u64,
+    // Rules nesting past the limit fail the sentence, which with
+    // --uniform-sentences only happens when the grammar sets the depth bound
+    // instead of --max-depth
+    max_depth: usize,
+    derivation_counts: Option<analysis::derivations::DerivationCounts<'a>>,
+    temperature: Option<generator::Temperature>,
+    variety: Option<generator::Variety>,
+    filter: filter::Filter,
+    repeats: Option<repeats::RepeatWindow>,
+    normalization: normalize::Normalization,
+    choices_out: Option<BufWriter<File>>,
+    summary: Option<summary::Summary>,
+    profile: Option<profile::Profile>,
+    painter: Option<color::Painter>,
+    missing: generator::MissingCounts,
+    // How many sentences were generated, however many tries each took
+    count: u64,
+}
+
+impl<'a> Sentences<'a> {
+    fn new(grammar: &'a grammar::Grammar, args: &'a cli::GenerateArgs, seed: u64, derivation_counts: Option<analysis::derivations::DerivationCounts<'a>>) -> Result<Self, Error> {
+        let choices_out = match &args.choices_out {
+            Some(path) => Some(BufWriter::new(File::create(path)?)),
+            None => None,
+        };
+        return Ok(Sentences {
+            grammar,
+            args,
+            seed,
+            max_depth: args.max_depth.unwrap_or(generator::DEFAULT_MAX_DEPTH),
+            derivation_counts,
+            temperature: args.temperature.map(|temperature| generator::Temperature::new(grammar, temperature)),
+            variety: args.variety.map(generator::Variety::new),
+            filter: filter::Filter::new(args.require.clone(), args.forbid.clone()),
+            repeats: args.no_repeat_window.map(|window| repeats::RepeatWindow::new(window as usize, args.repeat_allow.clone()
+                .unwrap_or_else(|| repeats::DEFAULT_ALLOWED.iter().map(ToString::to_string).collect()))),
+            normalization: normalize::Normalization::from_name(&args.normalize).expect("clap only allows known forms"),
+            choices_out,
+            summary: args.summary.then(summary::Summary::new),
+            profile: args.profile.then(profile::Profile::new),
+            // Colors are only for people reading a terminal
+            painter: (args.color_output && std::io::stdout().is_terminal()).then(color::Painter::new),
+            missing: generator::MissingCounts::new(),
+            count: 0,
+        });
+    }
+
+    // Fails if no start symbol can make the text --require asks for, unless
+    // lenient placeholders might make it
+    fn check_possible(&self, starts: &[String]) -> Result<(), Error> {
+        if self.args.lenient || self.args.require.is_empty() {
+            return Ok(());
+        }
+        let impossible: Vec<_> = starts.iter().filter_map(|start| self.filter.check_possible(self.grammar, start).err()).collect();
+        if impossible.len() == starts.len() {
+            return Err(impossible.into_iter().next().expect("there's a start symbol").into());
+        }
+        return Ok(());
+    }
+
+    // Generates a sentence that meets --require and --forbid, writing down
+    // its choices with --choices-out and adding it to the --summary. The
+    // outer result is for failing to write the choices. A sentence given its
+    // own seed, from --seeds-file or a key, is the first of that seed.
+    fn generate(&mut self, start: &str, rng: &mut generator::Xoshiro256StarStar, own_seed: Option<u64>) -> Result<Generated, Error> {
+        self.count += 1;
+        let sentence = match own_seed {
+            Some(own_seed) => (own_seed, 1),
+            None => (self.seed, self.count),
+        };
+        let mut tries = 0;
+        loop {
+            tries += 1;
+            let started = Instant::now();
+            // Counted again for each sentence
+            let mut watched = Watched {
+                trace: self.choices_out.is_some().then(|| generator::Trace::new(start)),
+                symbol_counts: self.args.count_symbols.then(generator::SymbolCounts::default),
+                painted: None,
+            };
+            let generated = self.draw(start, rng, sentence, tries, &mut watched);
+            let mut generated = generated.map(|(sentence, measurements)| measurements.into_generation(sentence, started.elapsed()));
+            // Normalized first, so everything after sees the same text
+            self.normalize(&mut generated, &mut watched.painted);
+            // Sentences --require, --forbid and --no-repeat-window turn down
+            // are generated again, with nothing about them written down
+            if generated.as_ref().is_ok_and(|generation| self.rejects(&generation.text)) {
+                if tries < self.args.max_tries {
+                    continue;
+                }
+                generated = Err(generator::GenerateErrorType::Rejected(tries).into());
+            }
+            if let Ok(generator::Generation { text, .. }) = &generated {
+                let empty = text.is_empty();
+                if (self.args.fail_on_empty && empty) || (self.args.fail_on_blank && text.trim().is_empty()) {
+                    generated = Err(generator::GenerateErrorType::EmptySentence { blank: !empty, sentence: sentence.1, seed: sentence.0 }.into());
+                }
+            }
+            if let (Some(out), Some(trace), Ok(_)) = (&mut self.choices_out, &watched.trace, &generated) {
+                writeln!(out, "{}", trace.to_json())?;
+            }
+            if let (Some(summary), Ok(generation)) = (&mut self.summary, &generated) {
+                summary.add(&generation.text);
+            }
+            // Only what's written is colored, not what was checked
+            if let (Some(painted), Ok(generation)) = (watched.painted, &mut generated) {
+                generation.text = painted;
+            }
+            return Ok((generated, watched.symbol_counts));
+        }
+    }
+
+    // Generates a sentence from start with the choices the options call for.
+    // sentence is the seed and number of the sentence within it, for
+    // --stable-noise, and tries counts its attempts from 1.
+    fn draw(&mut self, start: &str, rng: &mut generator::Xoshiro256StarStar, sentence: (u64, u64), tries: u32, watched: &mut Watched)
+        -> Result<(String, generator::Measurements), generator::GenerateError> {
+        let grammar = self.grammar;
+        let max_depth = self.max_depth;
+        let missing = self.args.lenient.then_some(&mut self.missing);
+        let mut observer = ((&mut watched.trace, &mut self.profile), &mut watched.symbol_counts);
+        let painting = self.painter.as_mut().map(|painter| (painter, &mut watched.painted));
+        if let Some(counts) = &self.derivation_counts {
+            return generator::generate_uniform(grammar, counts, start, rng, max_depth).map(|sentence| (sentence, generator::Measurements::default()));
+        }
+        if self.temperature.is_some() || self.variety.is_some() {
+            // Weighted choices draw differently, so they're only used when
+            // needed, keeping other seeded output the same
+            let mut choices = generator::WeightedChoices { rng, temperature: self.temperature.as_ref(), variety: self.variety.as_mut() };
+            return generate_painted(grammar, start, &mut choices, missing, &mut observer, painting, max_depth);
+        }
+        if self.args.deterministic {
+            if let Some(path) = generator::first_choice_loop(grammar, start) {
+                return Err(generator::GenerateErrorType::FirstChoiceLoop {
+                    start: start.to_string(),
+                    path: path.into_iter().map(String::from).collect(),
+                }.into());
+            }
+            return generate_painted(grammar, start, &mut generator::FirstChoices, missing, &mut observer, painting, max_depth);
+        }
+        if self.args.stable_noise {
+            let mut choices = generator::StableChoices::new(sentence.0, sentence.1, u64::from(tries));
+            return generate_painted(grammar, start, &mut choices, missing, &mut observer, painting, max_depth);
+        }
+        return generate_painted(grammar, start, &mut generator::RandomChoices(rng), missing, &mut observer, painting, max_depth);
+    }
+
+    // Puts a sentence, and its colored text, in the --normalize form
+    fn normalize(&self, generated: &mut Result<generator::Generation, generator::GenerateError>, painted: &mut Option<String>) {
+        if self.normalization == normalize::Normalization::None {
+            return;
+        }
+        if let Ok(generation) = generated {
+            if let Cow::Owned(normalized) = self.normalization.apply(&generation.text) {
+                generation.text = normalized;
+            }
+        }
+        if let Some(painted) = painted {
+            if let Cow::Owned(normalized) = self.normalization.apply(painted) {
+                *painted = normalized;
+            }
+        }
+    }
+
+    // Whether --require, --forbid or --no-repeat-window turn a sentence down
+    fn rejects(&mut self, sentence: &str) -> bool {
+        return (!self.filter.is_empty() && !self.filter.accepts(sentence))
+            || self.repeats.as_mut().is_some_and(|repeats| !repeats.accepts(sentence));
+    }
+
+    // Reports what was measured of the sentences once they're all generated,
+    // with the --summary, --profile and color legend, then warns about the
+    // filters and undefined symbols
+    fn report(mut self) -> Result<(), Error> {
+        if let Some(out) = &mut self.choices_out {
+            out.flush()?;
+        }
+        if let (Some(variety), true) = (&self.variety, self.args.verbose) {
+            for (rule, uses) in variety.uses() {
+                eprintln!("`{}` used its alternatives {} times", rule, uses.iter().join(", "));
+            }
+        }
+        if let (Some(summary), Some(repeats)) = (&mut self.summary, &self.repeats) {
+            summary.set_repeated_words(repeats.rejected, repeats.tried);
+        }
+        match (&self.summary, self.args.summary_format.as_str()) {
+            (Some(summary), "json") => eprintln!("{}", summary.to_json()),
+            (Some(summary), _) => eprintln!("{}", summary),
+            (None, _) => {}
+        }
+        if let Some(profile) = &self.profile {
+            eprintln!("{}", profile);
+        }
+        if let Some(painter) = &self.painter {
+            eprintln!("{}", painter.legend());
+        }
+        if let Some(warning) = self.filter.warning() {
+            error_handling::warn(warning);
+        }
+        warn_missing(&self.missing);
+        return Ok(());
     }
-    let grammar = grammar_res.unwrap();
+}
+
+// Where the sentences of a batch go, and what's been kept of them: files in
+// --out-dir, records held for --keep or --sort, or else straight out
+struct Batch {
+    corpus: Option<corpus::Corpus>,
+    sorted: Option<sorting::SortedOutput>,
+    best: Option<keep::Best>,
+    dedup: Option<dedup::Dedup>,
+    // Failures, counted by kind
+    failures: BTreeMap<&'static str, u64>,
+    // How many sentences were tried, which is fewer than asked for if
+    // interrupted or stopped by a failure
+    attempted: u64,
+}
 
-    let generate = create_generation_closure(grammar, args.start);
+impl Batch {
+    fn new(args: &cli::GenerateArgs, amount: u32) -> Result<Self, Error> {
+        let corpus = match &args.out_dir {
+            Some(dir) => {
+                let name_by = if args.name_by == "hash" { corpus::NameBy::Hash } else { corpus::NameBy::Index };
+                Some(corpus::Corpus::create(dir, name_by, amount, args.no_clobber)?)
+            }
+            None => None,
+        };
+        let keep_by = args.by.as_deref().map(|by| keep::KeepBy::from_name(by).expect("clap only accepts known measures"));
+        return Ok(Batch {
+            corpus,
+            sorted: args.sort.as_deref()
+                .map(|order| sorting::SortedOutput::new(sorting::SortOrder::from_name(order).expect("clap only accepts known orders"), u64::from(amount))),
+            best: args.keep.zip(keep_by).map(|(keep, by)| keep::Best::new(by, keep)),
+            dedup: args.dedup.as_deref()
+                .map(|by| dedup::Dedup::new(dedup::DedupBy::from_name(by).expect("clap only accepts known ways to dedup"))),
+            failures: BTreeMap::new(),
+            attempted: 0,
+        });
+    }
 
-    for _ in 0..args.amount.unwrap_or(1) {
-        let generated_res = generate();
-        if let Err(error) = generated_res {
+    // Counts a failed sentence, showing it if it's the first of its kind
+    fn fail(&mut self, error: error_handling::Error<generator::GenerateError>, args: &cli::GenerateArgs) {
+        let seen = self.failures.entry(error.error.error.kind()).or_insert(0);
+        if *seen == 0 {
             eprintln!("{}", error);
-            std::process::exit(1);
+            print_partial(&error.error, args);
+        }
+        *seen += 1;
+    }
+
+    // Writes the index-th sentence of the batch where it goes, returning
+    // whether the batch should stop
+    fn write(&mut self, index: u32, start: &str, generation: generator::Generation, symbol_counts: Option<&generator::SymbolCounts>, args: &cli::GenerateArgs,
+        out: &mut limit::Limited<impl Write>) -> Result<bool, Error> {
+        if self.dedup.as_mut().is_some_and(|dedup| !dedup.first_time(&generation.text)) {
+            return Ok(false);
+        }
+        if let Some(corpus) = &mut self.corpus {
+            if let Err(error) = corpus.write(index, &generation.text) {
+                eprintln!("{}", error);
+                return Ok(args.fail_fast);
+            }
+        } else if self.best.is_some() || self.sorted.is_some() {
+            let mut record = Vec::new();
+            write_sentence(&mut record, start, &generation.text, symbol_counts, args)?;
+            let record = String::from_utf8(record).expect("sentences are UTF-8");
+            match (&mut self.best, &mut self.sorted) {
+                (Some(best), _) => best.offer(best.by().measure(&generation.text, generation.expansions), record),
+                (None, Some(sorted)) => sorted.push(record),
+                (None, None) => unreachable!("records are only made to be held"),
+            }
+        } else {
+            write_sentence(out, start, &generation.text, symbol_counts, args)?;
+            return Ok(out.end_record());
+        }
+        return Ok(false);
+    }
+
+    // Writes what was held until the end and reports how the batch went,
+    // returning whether it succeeded
+    fn finish(self, amount: u32, summary: Option<&mut summary::Summary>, args: &cli::GenerateArgs, out: &mut limit::Limited<impl Write>) -> Result<bool, Error> {
+        // What was generated is still written after a failure, as it would
+        // have been without --keep or --sort
+        write_held(self.best, self.sorted, out, args)?;
+        out.flush()?;
+        let failed: u64 = self.failures.values().sum();
+        if failed > 0 && args.keep_going {
+            let kinds = self.failures.iter().map(|(kind, count)| format!("{}× {}", count, kind)).join(", ");
+            eprintln!("generated {}/{}; {} failed: {}", self.attempted - failed, amount, failed, kinds);
+        }
+        if let (Some(summary), Some(dedup)) = (summary, &self.dedup) {
+            summary.set_duplicates(dedup.suppressed);
+        }
+        let written = match self.corpus {
+            Some(corpus) => {
+                eprintln!("{}", corpus.summary());
+                corpus.failed == 0
+            }
+            None => true
+        };
+        return Ok(written && failed <= args.allow_failures.unwrap_or(0));
+    }
+}
+
+// Generates the sentences of a batch, from --amount, --seeds-file or each
+// start symbol with --deterministic. Without --keep-going, the first failure
+// stops it. Returns whether it succeeded.
+fn run_batch(sentences: &mut Sentences, rng: &mut generator::Xoshiro256StarStar, seeds: Option<&[u64]>, out: &mut limit::Limited<impl Write>) -> Result<bool, Error> {
+    let (grammar, args) = (sentences.grammar, sentences.args);
+    if seeds.is_some() && args.amount.is_some() {
+        error_handling::warn("`--amount` is ignored with `--seeds-file`, which generates one sentence per seed");
+    }
+    let starts = start_candidates(grammar, args)?;
+    if args.deterministic && args.amount.is_some_and(|amount| amount > 1) {
+        error_handling::warn("`--amount` is ignored with `--deterministic`, which generates one sentence per start symbol, since more would all be the same");
+    }
+    let amount = match seeds {
+        _ if args.deterministic => starts.len() as u32,
+        Some(seeds) => seeds.len() as u32,
+        None => args.amount.unwrap_or(1),
+    };
+    let mut batch = Batch::new(args, amount)?;
+    interrupt::watch();
+    for index in 0..amount {
+        if interrupt::interrupted() {
+            break;
+        }
+        batch.attempted += 1;
+        let own_seed = seeds.map(|seeds| seeds[index as usize]);
+        if let Some(own_seed) = own_seed {
+            *rng = generator::Xoshiro256StarStar::new(own_seed);
+        }
+        // Only draw from the generator when there's a choice, so seeded
+        // output with one start symbol doesn't depend on this
+        let start = match starts.len() {
+            _ if args.deterministic => &starts[index as usize],
+            1 => &starts[0],
+            len => &starts[generator::choose_index(rng, len)],
+        };
+        let (generated, symbol_counts) = sentences.generate(start, rng, own_seed)?;
+        match generated {
+            Ok(generation) => {
+                if batch.write(index, start, generation, symbol_counts.as_ref(), args, out)? {
+                    break;
+                }
+            }
+            Err(error) => {
+                batch.fail(error_handling::Error { location: failure_location(grammar, start), error }, args);
+                if !args.keep_going {
+                    break;
+                }
+            }
+        }
+    }
+    return batch.finish(amount, sentences.summary.as_mut(), args, out);
+}
+
+// Returns whether every sentence was generated successfully
+fn run_generate(args: cli::GenerateArgs) -> Result<bool, Error> {
+    let mut timings = args.time.is_some().then(timing::Timings::new);
+    let mut grammar = load_generate_grammar(&args, &mut timings)?;
+    if args.list_tags {
+        run_list_tags(&grammar, &args)?;
+        report_timings(timings, &args);
+        return Ok(true);
+    }
+    optimize_grammar(&mut grammar, &args, &mut timings);
+    if args.list_terminals || args.words {
+        run_list_terminals(&grammar, &args)?;
+        report_timings(timings, &args);
+        return Ok(true);
+    }
+
+    let seed = run_seed(&args);
+    let mut rng = generator::Xoshiro256StarStar::new(seed);
+    if args.steps.is_some() || args.steps_trace {
+        run_steps(&grammar, &args, &mut rng)?;
+        report_timings(timings, &args);
+        return Ok(true);
+    }
+    let derivation_counts = uniform_counts(&grammar, &args, &mut timings)?;
+    if let Some(path) = &args.jobs_file {
+        let mut missing = generator::MissingCounts::new();
+        let generating = Instant::now();
+        let sentences = run_jobs(&grammar, path, &args, &mut rng, derivation_counts.as_ref(), &mut missing)?;
+        if let (Some(timings), Some(sentences)) = (&mut timings, sentences) {
+            timings.add_generating(generating.elapsed(), sentences);
+        }
+        warn_missing(&missing);
+        report_timings(timings, &args);
+        return Ok(sentences.is_some());
+    }
+    if let Some(path) = &args.choices_in {
+        let mut missing = generator::MissingCounts::new();
+        let succeeded = timing::maybe_time(&mut timings, "generating", || run_choices_in(&grammar, path, &args, &mut missing))?;
+        warn_missing(&missing);
+        report_timings(timings, &args);
+        return Ok(succeeded);
+    }
+
+    let mut sentences = Sentences::new(&grammar, &args, seed, derivation_counts)?;
+    if !args.stdin_starts {
+        sentences.check_possible(&start_candidates(&grammar, &args)?)?;
+    }
+    // With --seeds-file, each sentence is generated from its own seed, so
+    // it's the first sentence --seed would give
+    let Ok(seeds) = read_seeds(&args)? else {
+        return Ok(false);
+    };
+    if args.fail_on_empty || args.fail_on_blank {
+        for start in start_candidates(&grammar, &args)? {
+            if analysis::min_size::can_be_empty(&grammar, &start) {
+                error_handling::warn(format!("`{}` can expand to nothing, so empty sentences come from the grammar itself rather than bad luck", start));
+            }
+        }
+    }
+
+    let generating = Instant::now();
+    // Sentences are written through this, so --limit-bytes counts what's
+    // actually written
+    let mut stdout = limit::Limited::new(std::io::stdout().lock(), args.limit_bytes);
+    let succeeded = if args.stdin_starts {
+        if args.amount.is_some() {
+            error_handling::warn("`--amount` is ignored with `--stdin-starts`, which generates one sentence per line");
         }
-        println!("{}", generated_res.unwrap());
+        run_stdin_starts(&grammar, &mut |start, own_seed| {
+            if let Some(own_seed) = own_seed {
+                rng = generator::Xoshiro256StarStar::new(own_seed);
+            }
+            sentences.generate(start, &mut rng, own_seed)
+        }, &mut stdout, &args)?
+    } else {
+        run_batch(&mut sentences, &mut rng, seeds.as_deref(), &mut stdout)?
+    };
+    let limited = stdout.reached();
+    if limited {
+        stdout.flush()?;
+        let written = if stdout.records() == 1 { "sentence" } else { "sentences" };
+        eprintln!("stopped at the --limit-bytes limit after writing {} {} ({} bytes)", stdout.records(), written, stdout.written());
+    }
+    if let Some(timings) = &mut timings {
+        timings.add_generating(generating.elapsed(), sentences.count);
+    }
+    sentences.report()?;
+    report_timings(timings, &args);
+    if limited && args.limit_is_error {
+        std::process::exit(limit::EXIT_CODE);
+    }
+    return Ok(succeeded);
+}
+
+fn run() -> Result<(), Error> {
+    let args = config::parse_args()?;
+    match args.command {
+        Some(cli::Command::Histogram(histogram_args)) => run_histogram(histogram_args),
+        Some(cli::Command::Stats(stats_args)) => run_stats(stats_args),
+        Some(cli::Command::Lex(lex_args)) => run_lex(lex_args),
+        Some(cli::Command::Pick(pick_args)) => run_pick(pick_args),
+        Some(cli::Command::Compile(compile_args)) => run_compile(compile_args),
+        Some(cli::Command::Codegen(codegen_args)) => run_codegen(codegen_args),
+        Some(cli::Command::Explain(explain_args)) => run_explain(explain_args),
+        Some(cli::Command::Cover(cover_args)) => run_cover(cover_args),
+        Some(cli::Command::Merge(merge_args)) => run_merge(merge_args),
+        Some(cli::Command::Fmt(fmt_args)) => run_fmt(fmt_args),
+        Some(cli::Command::Export(export_args)) => run_export(export_args),
+        Some(cli::Command::New(new_args)) => run_new(new_args),
+        #[cfg(feature = "serve")]
+        Some(cli::Command::Serve(serve_args)) => {
+            let grammar = parse_grammar(&serve_args.file, &parser::ParseOptions::default())?;
+            let options = serve::ServeOptions {
+                port: serve_args.port,
+                threads: serve_args.threads,
+                reload: serve_args.reload
+            };
+            Ok(serve::serve(serve_args.file, grammar, options)?)
+        }
+        #[cfg(feature = "lsp")]
+        Some(cli::Command::Lsp) => {
+            // Exiting without being shut down first is a failure, as LSP says
+            if !lsp::run(std::io::stdin().lock(), std::io::stdout().lock())? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        #[cfg(feature = "tui")]
+        Some(cli::Command::Tui(tui_args)) => {
+            let options = tui::TuiOptions {
+                samples: tui_args.amount,
+                seed: tui_args.seed,
+                every: tui_args.every.map(std::time::Duration::from_secs),
+            };
+            for sentence in tui::run(tui_args.file, options)? {
+                println!("{}", sentence);
+            }
+            Ok(())
+        }
+        Some(cli::Command::Test(test_args)) => {
+            if !run_test(test_args)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(cli::Command::Snapshot(snapshot_args)) => {
+            if !run_snapshot(snapshot_args)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(cli::Command::Equiv(equiv_args)) => {
+            if !run_equiv(equiv_args)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(cli::Command::Lint(lint_args)) => {
+            if !run_lint(lint_args)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Some(cli::Command::Check(check_args)) => {
+            if !run_check(check_args)? {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        None => {
+            let generate_args = args.generate.expect("clap requires the generate arguments without a subcommand");
+            let succeeded = run_generate(generate_args)?;
+            if interrupt::interrupted() {
+                std::process::exit(interrupt::EXIT_CODE);
+            }
+            if !succeeded {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("{}", error);
+        std::process::exit(1);
     }
 }
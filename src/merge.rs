@@ -0,0 +1,245 @@
+/*
+    This module combines several grammars into one, for shipping a grammar
+    split across files as a single file.
+
+    Rules are taken from each grammar in turn, and a rule defined by more
+    than one is resolved by the conflict policy. The start symbol is the
+    first grammar's. Each rule remembers every definition it came from, so
+    the merged file can say where its rules were written.
+*/
+
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+
+use crate::analysis::reachable_rules;
+use crate::error_handling::Location;
+use crate::grammar::{definition_order, to_bnf, Annotations, ExportError, Grammar, Rewrite, RuleTrivia, Trivia};
+use crate::parser::{verify_grammar, CompileError, CompileErrorType, CompileErrors};
+
+// What to do with a rule defined by more than one grammar
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConflictPolicy {
+    // It's an error
+    Error,
+    // The first definition is kept
+    First,
+    // The last definition is kept
+    Last,
+    // The alternatives of every definition are kept, leaving out repeats
+    Combine,
+}
+
+impl ConflictPolicy {
+    pub fn from_name(name: &str) -> Option<ConflictPolicy> {
+        match name {
+            "error" => Some(ConflictPolicy::Error),
+            "first" => Some(ConflictPolicy::First),
+            "last" => Some(ConflictPolicy::Last),
+            "combine" => Some(ConflictPolicy::Combine),
+            _ => None
+        }
+    }
+}
+
+pub struct MergeOptions {
+    pub on_conflict: ConflictPolicy,
+    // Whether to leave out rules the start symbol can't reach
+    pub prune: bool,
+}
+
+#[derive(Debug)]
+pub struct Merged {
+    pub grammar: Grammar,
+    // The rules in the order they're written, starting with the start symbol
+    pub order: Vec<String>,
+    // Where each rule's kept definitions were
+    pub sources: HashMap<String, Vec<Location>>,
+}
+
+impl Merged {
+    // The merged grammar as BNF, with a comment above each rule saying where
+    // it was defined
+    pub fn to_bnf(&self) -> Result<String, ExportError> {
+        let rules = self.sources.iter()
+            .map(|(rule, sources)| (rule.clone(), RuleTrivia { leading: vec![format!("; from {}", sources.iter().join(", "))], trailing: Vec::new() }))
+            .collect();
+        return to_bnf(&self.grammar, &self.order, &Trivia { rules, end: Vec::new() });
+    }
+
+    fn add(&mut self, rule: &str, rewrite: Rewrite, weights: Option<Vec<f64>>, annotations: Option<Annotations>, location: Location) {
+        let index = self.grammar.rules.insert(rule, &rewrite);
+        self.grammar.rules.set_location(index, &location);
+        if let Some(weights) = weights {
+            self.grammar.weights.insert(rule.to_string().into(), weights.into());
+        }
+        if let Some(annotations) = annotations {
+            self.grammar.annotations.insert(rule.to_string().into(), annotations);
+        }
+        self.sources.insert(rule.to_string(), vec![location]);
+    }
+
+    // Adds the alternatives the rule doesn't have yet. An alternative it
+    // already has gains the other's weight, if either is weighted, with
+    // unweighted alternatives weighing 1.
+    fn combine(&mut self, rule: &str, rewrite: Rewrite, weights: Option<Vec<f64>>, annotations: Option<Annotations>, location: Location) {
+        let mut alternatives = self.grammar.rules.rewrite(rule).to_rewrite();
+        let mut combined_weights = match (self.grammar.weights.remove(rule), &weights) {
+            (Some(existing), _) => Some(existing.to_vec()),
+            (None, Some(_)) => Some(vec![1.0; alternatives.len()]),
+            (None, None) => None,
+        };
+        for (index, alternative) in rewrite.into_iter().enumerate() {
+            let weight = weights.as_ref().map_or(1.0, |weights| weights[index]);
+            match alternatives.iter().position(|existing| *existing == alternative) {
+                Some(existing) => if let Some(combined) = &mut combined_weights {
+                    combined[existing] += weight;
+                },
+                None => {
+                    alternatives.push(alternative);
+                    if let Some(combined) = &mut combined_weights {
+                        combined.push(weight);
+                    }
+                }
+            }
+        }
+        self.grammar.rules.insert(rule, &alternatives);
+        if let Some(combined) = combined_weights {
+            self.grammar.weights.insert(rule.to_string().into(), combined.into());
+        }
+        for (key, values) in annotations.into_iter().flatten() {
+            self.grammar.annotations.entry(rule.to_string().into()).or_default().entry(key).or_default().extend(values);
+        }
+        self.sources.get_mut(rule).unwrap().push(location);
+    }
+}
+
+// Merges grammars parsed without verifying them, since one can use rules
+// another defines, and verifies the result. Conflicts are errors located at
+// the later definition. There must be at least one grammar.
+pub fn merge(grammars: Vec<Grammar>, options: &MergeOptions) -> Result<Merged, CompileErrors> {
+    let mut grammars = grammars.into_iter();
+    let first = grammars.next().expect("there's a grammar to merge");
+    let mut merged = Merged {
+        order: definition_order(&first),
+        sources: first.rules.keys().filter_map(|rule| Some((rule.to_string(), vec![first.rules.location(rule)?]))).collect(),
+        grammar: first,
+    };
+
+    let mut errors = Vec::new();
+    for grammar in grammars {
+        for rule in definition_order(&grammar) {
+            let location = grammar.location(&rule);
+            let rewrite = grammar.rules.rewrite(rule.as_str()).to_rewrite();
+            let weights = grammar.weights.get(rule.as_str()).map(|weights| weights.to_vec());
+            let annotations = grammar.annotations.get(rule.as_str()).cloned();
+            if !merged.grammar.rules.contains_key(rule.as_str()) {
+                merged.add(&rule, rewrite, weights, annotations, location);
+                merged.order.push(rule);
+                continue;
+            }
+
+            match options.on_conflict {
+                ConflictPolicy::Error => errors.push(CompileError {
+                    location,
+                    error: CompileErrorType::ConflictingRule { rule: rule.clone(), first: Box::new(merged.grammar.location(&rule)) }
+                }),
+                ConflictPolicy::First => {}
+                ConflictPolicy::Last => {
+                    merged.grammar.weights.remove(rule.as_str());
+                    merged.grammar.annotations.remove(rule.as_str());
+                    merged.sources.remove(&rule);
+                    merged.add(&rule, rewrite, weights, annotations, location);
+                }
+                ConflictPolicy::Combine => merged.combine(&rule, rewrite, weights, annotations, location),
+            }
+        }
+        // A later `%deprecated` of a rule replaces an earlier one, like it
+        // does within a grammar
+        merged.grammar.deprecations.extend(grammar.deprecations);
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    if options.prune {
+        let reachable: HashSet<String> = reachable_rules(&merged.grammar, &merged.grammar.start_symbol).into_iter().map(str::to_string).collect();
+        merged.order.retain(|rule| reachable.contains(rule));
+        merged.grammar.rules.retain(|rule, _| reachable.contains(rule));
+        merged.grammar.annotations.retain(|rule, _| reachable.contains(&**rule));
+        merged.grammar.weights.retain(|rule, _| reachable.contains(&**rule));
+        merged.grammar.deprecations.retain(|rule, _| reachable.contains(&**rule));
+        merged.sources.retain(|rule, _| reachable.contains(rule));
+    }
+
+    let mut undefined = verify_grammar(&merged.grammar);
+    if !undefined.is_empty() {
+        undefined.sort_by_key(|error| (error.location.source.to_string(), error.location.line));
+        return Err(undefined);
+    }
+    return Ok(merged);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::parser::{parse_str, parse_str_with_options, ParseOptions};
+
+    const BASE: &str = "sentence = greeting \" \" name\ngreeting = \"hi\" | \"hello\"\nunused = \"x\"";
+    const EXTRA: &str = ";@ tag friendly\ngreeting = \"hey\" | \"hi\"\nname = \"jo\"";
+
+    fn merge_str(on_conflict: ConflictPolicy, prune: bool) -> Result<Merged, CompileErrors> {
+        let unverified = ParseOptions { verify: false, ..Default::default() };
+        let grammars = [("base.bnf", BASE), ("extra.bnf", EXTRA)]
+            .map(|(name, text)| parse_str_with_options(text, SourceName::file(name), &unverified).unwrap().0);
+        return merge(grammars.into(), &MergeOptions { on_conflict, prune });
+    }
+
+    fn alternatives(merged: &Merged, rule: &str) -> Vec<String> {
+        merged.grammar.rules.rewrite(rule).iter().map(|alternative| alternative.iter().join(" ")).collect()
+    }
+
+    #[test]
+    fn resolve_conflicts() {
+        let errors = merge_str(ConflictPolicy::Error, false).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].location.to_string(), "extra.bnf:2");
+        assert_eq!(errors[0].error.to_string(), "`greeting` is already defined at base.bnf:2 (pass --on-conflict to choose how to merge them)");
+
+        let first = merge_str(ConflictPolicy::First, false).unwrap();
+        assert_eq!(alternatives(&first, "greeting"), ["\"hi\"", "\"hello\""]);
+        assert_eq!(first.grammar.annotations.get("greeting"), None);
+        let last = merge_str(ConflictPolicy::Last, false).unwrap();
+        assert_eq!(alternatives(&last, "greeting"), ["\"hey\"", "\"hi\""]);
+        assert_eq!(last.sources["greeting"], [Location::line(SourceName::file("extra.bnf"), 2)]);
+        let combined = merge_str(ConflictPolicy::Combine, false).unwrap();
+        assert_eq!(alternatives(&combined, "greeting"), ["\"hi\"", "\"hello\"", "\"hey\""]);
+        assert_eq!(combined.order, ["sentence", "greeting", "unused", "name"]);
+    }
+
+    #[test]
+    fn write_merged_grammar() {
+        let merged = merge_str(ConflictPolicy::Combine, true).unwrap();
+        let bnf = merged.to_bnf().unwrap();
+        assert_eq!(bnf, concat!(
+            "; from base.bnf:1\n",
+            "sentence = greeting \" \" name\n",
+            "; from base.bnf:2, extra.bnf:2\n",
+            ";@ tag friendly\n",
+            "greeting = \"hi\" | \"hello\" | \"hey\"\n",
+            "; from extra.bnf:3\n",
+            "name = \"jo\"\n",
+        ));
+        assert_eq!(parse_str(&bnf, SourceName::Builtin).unwrap().rules, merged.grammar.rules);
+    }
+
+    #[test]
+    fn verify_the_merged_grammar() {
+        let unverified = ParseOptions { verify: false, ..Default::default() };
+        let grammar = parse_str_with_options(BASE, SourceName::file("base.bnf"), &unverified).unwrap().0;
+        let errors = merge(vec![grammar], &MergeOptions { on_conflict: ConflictPolicy::Error, prune: false }).unwrap_err();
+        assert_eq!(errors[0].error, CompileErrorType::UndefinedNonterminal("name".to_string()));
+        assert_eq!(errors[0].location.to_string(), "base.bnf:1");
+    }
+}
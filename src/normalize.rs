@@ -0,0 +1,68 @@
+/*
+    This module puts generated sentences in a Unicode normalization form, so
+    text from word lists that write accents differently, like é as one
+    codepoint or as e and a combining acute, comes out the same and compares
+    equal when deduplicated.
+*/
+
+use std::borrow::Cow;
+
+use unicode_normalization::{is_nfc, is_nfd, UnicodeNormalization};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Normalization {
+    // Sentences are left as they were generated
+    None,
+    // Composed, with é as one codepoint where there is one
+    Nfc,
+    // Decomposed, with é as e and a combining acute
+    Nfd,
+}
+
+impl Normalization {
+    pub fn from_name(name: &str) -> Option<Normalization> {
+        match name {
+            "none" => Some(Normalization::None),
+            "nfc" => Some(Normalization::Nfc),
+            "nfd" => Some(Normalization::Nfd),
+            _ => None
+        }
+    }
+
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        return match self {
+            Normalization::None => Cow::Borrowed(text),
+            Normalization::Nfc if is_nfc(text) => Cow::Borrowed(text),
+            Normalization::Nfd if is_nfd(text) => Cow::Borrowed(text),
+            Normalization::Nfc => Cow::Owned(text.nfc().collect()),
+            Normalization::Nfd => Cow::Owned(text.nfd().collect()),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dedup::{Dedup, DedupBy};
+
+    #[test]
+    fn normalize_sentences() {
+        let composed = "caf\u{e9} cr\u{e8}me";
+        let decomposed = "cafe\u{301} cre\u{300}me";
+        assert_eq!(Normalization::Nfc.apply(decomposed), composed);
+        assert_eq!(Normalization::Nfd.apply(composed), decomposed);
+        assert_eq!(Normalization::Nfc.apply("cafe\u{301} cr\u{e8}me"), composed);
+        assert!(matches!(Normalization::Nfc.apply(composed), Cow::Borrowed(_)));
+        assert_eq!(Normalization::None.apply(decomposed), decomposed);
+
+        // Both spellings are one sentence once normalized
+        for normalization in [Normalization::Nfc, Normalization::Nfd] {
+            let mut dedup = Dedup::new(DedupBy::Text);
+            assert!(dedup.first_time(&normalization.apply(composed)));
+            assert!(!dedup.first_time(&normalization.apply(decomposed)));
+        }
+        let mut dedup = Dedup::new(DedupBy::Hash);
+        assert!(dedup.first_time(composed));
+        assert!(dedup.first_time(decomposed));
+    }
+}
@@ -0,0 +1,176 @@
+/*
+    Brackets group symbols inside a rewrite, like `[" " middle]`, so small
+    variations don't need a rule of their own written out for them.
+
+    What a pair of brackets holds is parsed like a rewrite, alternatives and
+    weights included, and made into a rule named after the rule it's in,
+    like `name[1]`, which the brackets are replaced by a reference to. So
+    generating, analysing and checking grammars treat brackets like any
+    other rule, and choice traces and explanations show them by those names.
+    The lexer checks brackets are balanced, so everything here can count on
+    every bracket being closed by one of the same kind.
+*/
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::error_handling::Location;
+use crate::grammar::{Annotations, Bracket};
+use super::lexer::Token;
+use super::{ParsedRewrite, ParsedSymbol, Rule};
+
+// An alternative of a rewrite, or of what brackets hold, as it was lexed
+#[derive(Debug, PartialEq)]
+pub struct LexedAlternative {
+    // Where its tokens are, after its weight if it has one
+    pub tokens: Range<usize>,
+    // How many symbols it has at its own level, with brackets counting as
+    // one symbol
+    pub symbols: usize,
+    // Whether one of those symbols is an empty terminal
+    pub empty_terminal: bool,
+}
+
+impl LexedAlternative {
+    fn at(start: usize) -> Self {
+        LexedAlternative { tokens: start..start, symbols: 0, empty_terminal: false }
+    }
+
+    // A level's alternatives, starting with the one at start, with room for
+    // the few most have
+    fn level(start: usize) -> Vec<Self> {
+        let mut alternatives = Vec::with_capacity(4);
+        alternatives.push(LexedAlternative::at(start));
+        return alternatives;
+    }
+}
+
+// The alternatives of a rewrite's tokens, and of what each pair of brackets
+// in it holds, in the order the brackets open after the rewrite's own.
+// Brackets are found with a stack rather than by recursing, so they can
+// nest as deep as they like.
+pub fn alternatives_by_level(tokens: &[Token]) -> Vec<Vec<LexedAlternative>> {
+    let mut levels = vec![LexedAlternative::level(0)];
+    // The levels inside the brackets open so far, innermost last, which are
+    // inside the rewrite's own level
+    let mut open = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        let level = open.last().copied().unwrap_or(0);
+        let alternative = levels[level].last_mut().expect("every level has an alternative");
+        match token {
+            Token::Weight(_) => alternative.tokens.start = index + 1,
+            Token::Or => {
+                alternative.tokens.end = index;
+                levels[level].push(LexedAlternative::at(index + 1));
+            }
+            Token::Open(_) => {
+                alternative.symbols += 1;
+                levels.push(LexedAlternative::level(index + 1));
+                open.push(levels.len() - 1);
+            }
+            Token::Close(_) => {
+                alternative.tokens.end = index;
+                open.pop();
+            }
+            token => {
+                alternative.symbols += 1;
+                alternative.empty_terminal |= matches!(token, Token::Terminal(text) if text.is_empty());
+            }
+        }
+    }
+    if let Some(alternative) = levels[0].last_mut() {
+        alternative.tokens.end = tokens.len();
+    }
+    return levels;
+}
+
+// The rules made for the brackets in a rule's rewrite, in the order the
+// brackets open
+pub struct Helpers<'r, 'a> {
+    rule: &'r str,
+    location: &'r Location,
+    // Filled in as the brackets close, so brackets inside others come after
+    // them
+    rules: Vec<Option<Rule<'a>>>,
+}
+
+impl<'r, 'a> Helpers<'r, 'a> {
+    pub fn new(rule: &'r str, location: &'r Location) -> Self {
+        Helpers { rule, location, rules: Vec::new() }
+    }
+
+    // Makes room for the rule of brackets that just opened, returning
+    // where it goes
+    pub fn open(&mut self) -> usize {
+        self.rules.push(None);
+        return self.rules.len() - 1;
+    }
+
+    // Makes the rule for the brackets at index, which hold rewrite, and
+    // returns the symbol standing in for them. A group's rule is just what
+    // it holds. Square brackets and braces start with an empty alternative,
+    // picked half the time, with what they hold sharing the other half by
+    // weight. Braces go on to expand their rule again after each
+    // alternative, so they repeat until the empty one is picked.
+    pub fn close(&mut self, index: usize, bracket: Bracket, mut rewrite: ParsedRewrite<'a>, mut weights: Option<Vec<f64>>) -> ParsedSymbol<'a> {
+        let name = bracket.rule_name(self.rule, index + 1);
+        if bracket == Bracket::Repeat {
+            let mut repeated = ParsedRewrite::with_capacity(rewrite.len(), rewrite.symbols.len() + rewrite.len());
+            for alternative in rewrite.alternatives() {
+                alternative.iter().for_each(|symbol| repeated.push(symbol.clone()));
+                repeated.push(ParsedSymbol::Nonterminal(Cow::Owned(name.clone()), None));
+                repeated.end_alternative();
+            }
+            rewrite = repeated;
+        }
+        if bracket != Bracket::Group {
+            weights = weights.or_else(|| (rewrite.len() > 1).then(|| vec![1.0; rewrite.len()]));
+            // The empty alternative ends before any symbol
+            rewrite.ends.insert(0, 0);
+            weights = weights.map(|weights| std::iter::once(weights.iter().sum()).chain(weights).collect());
+        }
+        self.rules[index] = Some(Rule {
+            symbol: Cow::Owned(name.clone()),
+            rewrite,
+            location: self.location.clone(),
+            annotations: Annotations::new(),
+            weights,
+            helpers: Vec::new()
+        });
+        return ParsedSymbol::Nonterminal(Cow::Owned(name), None);
+    }
+
+    pub fn into_rules(self) -> Vec<Rule<'a>> {
+        self.rules.into_iter().map(|rule| rule.expect("every bracket is closed")).collect()
+    }
+}
+
+// Weighs the empty alternative of the rule made for braces so each
+// repetition is followed by another by chance, keeping the weights of what
+// the braces hold. Rules made for other brackets are left alone.
+pub fn set_repeat_chance(helper: &mut Rule, chance: f64) {
+    if !matches!(Bracket::of_rule(&helper.symbol), Some((_, Bracket::Repeat))) {
+        return;
+    }
+    let weights = helper.weights.get_or_insert_with(|| vec![1.0; helper.rewrite.len()]);
+    let held: f64 = weights[1..].iter().sum();
+    weights[0] = held * (1.0 - chance) / chance;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::lexer::lex_line;
+
+    #[test]
+    fn find_alternatives_by_level() {
+        let tokens = lex_line("\"a\" [b | 2 \"\" [d]] | e").unwrap();
+        let spans = |level: &[LexedAlternative]| level.iter().map(|alternative| (alternative.tokens.clone(), alternative.symbols, alternative.empty_terminal)).collect::<Vec<_>>();
+        let levels = alternatives_by_level(&tokens);
+        assert_eq!(levels.len(), 3);
+        assert_eq!(spans(&levels[0]), [(0..10, 2, false), (11..12, 1, false)]);
+        assert_eq!(spans(&levels[1]), [(2..3, 1, false), (5..9, 2, true)]);
+        assert_eq!(spans(&levels[2]), [(7..8, 1, false)]);
+        assert_eq!(spans(&alternatives_by_level(&[])[0]), [(0..0, 0, false)]);
+    }
+}
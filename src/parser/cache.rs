@@ -0,0 +1,133 @@
+/*
+    Caches what each line of a grammar parsed to, so tools which parse the
+    same grammar again after every edit, like the language server, only lex
+    the lines which changed.
+
+    Lines are looked up by their text, so a line moved by lines added above
+    it is still found, and is given its new location. Only lines which
+    parsed are kept: lines with errors are parsed again, and so are rules
+    defined by directives, since their data files can change without the
+    line changing. Annotations aren't lexed, so they're read again every
+    time. The ruleset is built and verified again from every rule each time.
+*/
+
+use std::collections::HashMap;
+
+use super::*;
+
+#[derive(Default)]
+pub struct ParseCache {
+    // The lines of the last grammar parsed, by their text
+    lines: HashMap<String, (Rule<'static>, CompileWarnings)>,
+    // How many lines have been lexed, rather than found in the cache
+    lexed: usize,
+}
+
+// A cached line's rule and warnings, moved to where the line is now
+fn relocated((rule, warnings): &(Rule<'static>, CompileWarnings), location: &Location) -> (Rule<'static>, CompileWarnings) {
+    let warnings = warnings.iter()
+        .map(|warning| CompileWarning { location: location.clone(), ..warning.clone() })
+        .collect();
+    let mut rule = Rule { location: location.clone(), ..rule.clone() };
+    for helper in &mut rule.helpers {
+        helper.location = location.clone();
+    }
+    return (rule, warnings);
+}
+
+impl ParseCache {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // How many lines have been lexed since the cache was made
+    pub fn lexed(&self) -> usize {
+        self.lexed
+    }
+
+    // Parses each line like parse_lex_line, using the lines cached from the
+    // last grammar parsed and keeping only the ones from this one
+    fn parse_with<T>(&mut self, parse_lines: impl FnOnce(&mut LineParser) -> T) -> T {
+        let mut last = std::mem::take(&mut self.lines);
+        let lines = &mut self.lines;
+        let lexed = &mut self.lexed;
+        return parse_lines(&mut |line: &str, location: Location| {
+            if let Some(parsed) = last.remove(line).or_else(|| lines.get(line).cloned()) {
+                let parsed = relocated(&parsed, &location);
+                lines.insert(line.to_string(), parsed.clone());
+                return Ok(parsed);
+            }
+            *lexed += 1;
+            let (rule, warnings) = parse_lex_line(line, location)?;
+            let parsed = (rule.into_owned(), warnings);
+            let directive = line.split_once('=').is_some_and(|(_, rewrite)| is_directive(rewrite));
+            if !directive {
+                lines.insert(line.to_string(), parsed.clone());
+            }
+            return Ok(parsed);
+        });
+    }
+
+    // Parses a grammar held in a string like parse_str_with_options. YAML
+    // grammars are parsed whole, without the cache.
+    pub fn parse_str_with_options(&mut self, text: &str, source: SourceName, options: &ParseOptions) -> FileResult<(Grammar, CompileWarnings)> {
+        if options.syntax == Some(Syntax::Yaml) {
+            return yaml::parse_yaml(text, &source, options);
+        }
+        return self.parse_with(|parse| parse_lines(str_line_nums(text), &source, options, parse));
+    }
+
+    // Parses a grammar held in a string like parse_str_partial
+    pub fn parse_str_partial(&mut self, text: &str, source: SourceName) -> (Grammar, CompileErrors, CompileWarnings) {
+        return self.parse_with(|parse| parse_lines_partial(str_line_nums(text), &source, &ParseOptions::default(), parse));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A grammar of a chain of rules, each on its own line
+    fn chain(rules: usize) -> Vec<String> {
+        let mut lines: Vec<String> = (0..rules - 1).map(|i| format!("r{} = \"word {}\" | r{}", i, i, i + 1)).collect();
+        lines.push(format!("r{} = \"last\"", rules - 1));
+        return lines;
+    }
+
+    #[test]
+    fn lex_only_changed_lines() {
+        let mut lines = chain(20_000);
+        let mut cache = ParseCache::new();
+        let (grammar, _) = cache.parse_str_with_options(&lines.join("\n"), SourceName::Builtin, &ParseOptions::default()).unwrap();
+        assert_eq!(cache.lexed(), 20_000);
+        assert_eq!(grammar.rules.len(), 20_000);
+
+        lines[500] = "r500 = \"edited\" | r501".to_string();
+        let (edited, _) = cache.parse_str_with_options(&lines.join("\n"), SourceName::Builtin, &ParseOptions::default()).unwrap();
+        assert_eq!(cache.lexed(), 20_001);
+        assert_eq!(edited.rules.rewrite("r500").to_rewrite()[0], vec![Symbol::Terminal("edited".into())]);
+        assert_eq!(edited, parse_str(&lines.join("\n"), SourceName::Builtin).unwrap());
+
+        // Unchanged lines moved down keep being found, at their new lines
+        lines.insert(0, "; a comment".to_string());
+        lines[3] = "r2 = \"word 2\" | r2_x".to_string();
+        let (grammar, errors, _) = cache.parse_str_partial(&lines.join("\n"), SourceName::Builtin);
+        assert_eq!(cache.lexed(), 20_002);
+        assert_eq!(grammar.location("r9").line, Some(11));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].location.line, Some(4));
+        assert_eq!(cache.lines.len(), 20_000);
+    }
+
+    #[test]
+    fn parse_broken_lines_again() {
+        let mut cache = ParseCache::new();
+        let text = "s = a | \"b\nlist = @csv(missing.csv)\na = \"a\" \"\"";
+        for _ in 0..2 {
+            let (_, errors, warnings) = cache.parse_str_partial(text, SourceName::Builtin);
+            assert_eq!(errors.len(), 2);
+            assert_eq!(warnings.len(), 1);
+        }
+        assert_eq!(cache.lexed(), 5);
+    }
+}
@@ -0,0 +1,84 @@
+/*
+    `%deprecated NAME "MESSAGE"` lines, which mark a rule as on its way out
+    while it keeps working, so grammars can rename or replace rules without
+    breaking the ones that include them all at once.
+
+    Every reference to a deprecated rule gets a warning at the referencing
+    rule, with the message, which should say what to use instead. A rule's
+    references to itself don't, since they go away with it. Deprecating a
+    rule that isn't defined is an error, like referencing one.
+*/
+
+use itertools::Itertools;
+
+use crate::grammar::{Deprecation, Grammar, SymbolRef};
+use super::lexer::{self, Token};
+use super::{CompileError, CompileErrorType, CompileErrors, CompileWarning, CompileWarningType, CompileWarnings, Result};
+
+pub fn is_deprecation(line: &str) -> bool {
+    line.strip_prefix("%deprecated").is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+// The rule a `%deprecated` line names, and its message, which is empty if
+// it has none
+pub fn parse_deprecation(line: &str) -> Result<(String, String)> {
+    let rest = line["%deprecated".len()..].trim();
+    let malformed = || CompileErrorType::MalformedDeprecation(rest.to_string());
+    return match lexer::lex_line(rest).map_err(|_| malformed())?.as_slice() {
+        [Token::Nonterminal(rule, None)] => Ok((rule.to_string(), String::new())),
+        [Token::Nonterminal(rule, None), Token::Terminal(message)] => Ok((rule.to_string(), message.to_string())),
+        _ => Err(malformed()),
+    };
+}
+
+// The deprecations of rules the grammar doesn't define, in the order they
+// were written
+pub fn undefined_deprecations(grammar: &Grammar) -> CompileErrors {
+    grammar.deprecations.iter()
+        .filter(|(rule, _)| !grammar.rules.contains_key(rule))
+        .sorted_by_key(|(_, Deprecation { location, .. })| (location.source.to_string(), location.line))
+        .map(|(rule, Deprecation { location, .. })| CompileError {
+            location: location.clone(),
+            error: CompileErrorType::UndefinedDeprecation(rule.to_string())
+        })
+        .collect()
+}
+
+// A warning for each reference to a deprecated rule, at the rule making it,
+// in the order the rules were defined
+pub fn deprecated_references(grammar: &Grammar) -> CompileWarnings {
+    if grammar.deprecations.is_empty() {
+        return Vec::new();
+    }
+    let mut warnings = Vec::new();
+    for (rule, rewrite) in &grammar.rules {
+        for symbol in rewrite.iter().flatten() {
+            let SymbolRef::Nonterminal(name, _) = symbol else {
+                continue;
+            };
+            if let Some(deprecation) = grammar.deprecations.get(name).filter(|_| name != rule) {
+                warnings.push(CompileWarning {
+                    location: grammar.location(rule).clone(),
+                    warning: CompileWarningType::Deprecated { rule: name.to_string(), message: deprecation.message.clone() }
+                });
+            }
+        }
+    }
+    return warnings;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_deprecations() {
+        assert!(is_deprecation("%deprecated old") && is_deprecation("%deprecated") && !is_deprecation("%deprecatedold"));
+        assert_eq!(parse_deprecation("%deprecated  old.name  \"use new.name\" "), Ok(("old.name".to_string(), "use new.name".to_string())));
+        assert_eq!(parse_deprecation("%deprecated old r#\"say \"new\"\"#"), Ok(("old".to_string(), "say \"new\"".to_string())));
+        assert_eq!(parse_deprecation("%deprecated old"), Ok(("old".to_string(), String::new())));
+        for bad in ["%deprecated", "%deprecated \"old\"", "%deprecated old:upper", "%deprecated old new", "%deprecated old \"a\" \"b\"", "%deprecated old \"a"] {
+            assert!(matches!(parse_deprecation(bad), Err(CompileErrorType::MalformedDeprecation(_))), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+/*
+    A rule can take its alternatives from a data file instead of having them
+    written out, with a directive in place of its rewrite, like
+
+        colors = @csv("palette.csv", column = "name", weight = "freq")
+        first.name = @weighted_file("names.tsv")
+
+    Relative paths are found from the directory of the grammar file.
+*/
+
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+
+use crate::error_handling::SourceName;
+use crate::grammar::{Rewrite, Symbol};
+use super::dynamic::starts_with_dynamic;
+use super::{CompileErrorType, CompileWarningType, Result};
+
+// A problem with the data a directive reads
+#[derive(Debug)]
+pub enum DataErrorType {
+    Unreadable(std::io::Error),
+    // The header has no column with the name, and these are the ones it has
+    MissingColumn { column: String, found: Vec<String> },
+    // A weight isn't a number, or is negative or infinite
+    BadWeight { line: u64, weight: String },
+    // A line has no tab separating a terminal from its weight
+    MissingWeight { line: u64 },
+    // A row has a different number of fields than the header
+    RaggedRow { line: u64, expected: u64, found: u64 },
+    // Every weight is zero, so there's nothing to pick
+    NoPositiveWeight,
+    // The file isn't valid for some other reason, at a line if it's known
+    Malformed { line: Option<u64>, reason: String },
+}
+
+// A data file error, with the file it was in
+#[derive(Debug)]
+pub struct DataError {
+    pub path: PathBuf,
+    pub error: DataErrorType,
+}
+
+impl Display for DataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = self.path.display();
+        match &self.error {
+            DataErrorType::Unreadable(error) => write!(f, "Could not read {}: {}", path, error),
+            DataErrorType::MissingColumn { column, found } if found.is_empty() => write!(f, "{} has no column `{}` (it has no header row)", path, column),
+            DataErrorType::MissingColumn { column, found } => write!(f, "{} has no column `{}` (its columns are {})", path, column,
+                found.iter().map(|column| format!("`{}`", column)).join(", ")),
+            DataErrorType::BadWeight { line, weight } => write!(f, "{} line {}: weight `{}` isn't a number of at least 0", path, line, weight),
+            DataErrorType::MissingWeight { line } => write!(f, "{} line {}: expected a terminal, a tab, and a weight", path, line),
+            DataErrorType::RaggedRow { line, expected, found } => write!(f, "{} line {}: expected {} fields like the header, found {}", path, line, expected, found),
+            DataErrorType::NoPositiveWeight => write!(f, "{} has no row with a weight above 0", path),
+            DataErrorType::Malformed { line: Some(line), reason } => write!(f, "{} line {}: {}", path, line, reason),
+            DataErrorType::Malformed { line: None, reason } => write!(f, "{}: {}", path, reason),
+        }
+    }
+}
+
+// A parsed `@name("file", key = "value", ...)`
+#[derive(Debug, PartialEq)]
+struct Directive {
+    name: String,
+    path: String,
+    options: Vec<(String, String)>,
+}
+
+impl Directive {
+    // The value of an option, checking every option given is one of known
+    fn option(&self, key: &str, known: &[&str]) -> Result<Option<&str>> {
+        if let Some((unknown, _)) = self.options.iter().find(|(key, _)| !known.contains(&key.as_str())) {
+            return Err(malformed(format!("`@{}` has no option `{}`", self.name, unknown)));
+        }
+        return Ok(self.options.iter().find(|(given, _)| given == key).map(|(_, value)| value.as_str()));
+    }
+}
+
+fn malformed(reason: String) -> CompileErrorType {
+    CompileErrorType::MalformedDirective(reason)
+}
+
+// Whether the text after a rule's `=` is a directive, rather than
+// alternatives starting with a dynamic symbol like `@uuid`
+pub fn is_directive(rewrite: &str) -> bool {
+    let rewrite = rewrite.trim_start();
+    return rewrite.starts_with('@') && !starts_with_dynamic(rewrite);
+}
+
+// Reads a quoted string from the start of text, returning it and the rest
+fn quoted(text: &str) -> Option<(String, &str)> {
+    let (string, rest) = text.strip_prefix('"')?.split_once('"')?;
+    return Some((string.to_string(), rest.trim_start()));
+}
+
+fn parse_directive(text: &str) -> Result<Directive> {
+    let text = text.trim().strip_prefix('@').expect("directives start with `@`");
+    let Some((name, arguments)) = text.split_once('(') else {
+        return Err(malformed(format!("Expected `(` after `@{}`", text)));
+    };
+    let name = name.trim_end().to_string();
+    let Some(arguments) = arguments.trim_end().strip_suffix(')') else {
+        return Err(malformed(format!("Expected `@{}(...)` to end with `)`", name)));
+    };
+    let Some((path, mut rest)) = quoted(arguments.trim_start()) else {
+        return Err(malformed(format!("Expected `@{}` to start with a quoted file name", name)));
+    };
+
+    let mut options = Vec::new();
+    while let Some(after_comma) = rest.strip_prefix(',') {
+        let (key, value) = after_comma.split_once('=')
+            .ok_or_else(|| malformed(format!("Expected `KEY = \"VALUE\"` after the file name of `@{}`", name)))?;
+        let key = key.trim().to_string();
+        let (value, after_value) = quoted(value.trim_start())
+            .ok_or_else(|| malformed(format!("Expected a quoted value for `{}`", key)))?;
+        options.push((key, value));
+        rest = after_value;
+    }
+    if !rest.is_empty() {
+        return Err(malformed(format!("Unexpected `{}` in `@{}`", rest, name)));
+    }
+    return Ok(Directive { name, path, options });
+}
+
+// Where a data file named in source is
+fn data_path(source: &SourceName, path: &str) -> PathBuf {
+    match source {
+        SourceName::File(grammar) => grammar.parent().unwrap_or(Path::new("")).join(path),
+        _ => PathBuf::from(path),
+    }
+}
+
+// Reads the alternatives, and their weights if the directive gives any, of a
+// rule defined by a directive in source, along with any warnings about the
+// data
+pub fn expand_directive(text: &str, source: &SourceName) -> Result<(Rewrite, Option<Vec<f64>>, Vec<CompileWarningType>)> {
+    let directive = parse_directive(text)?;
+    let path = data_path(source, &directive.path);
+    let mut warnings = Vec::new();
+    let (terminals, weights) = match directive.name.as_str() {
+        "csv" => {
+            let column = directive.option("column", &["column", "weight"])?
+                .ok_or_else(|| malformed("`@csv` needs a `column = \"NAME\"` to take terminals from".to_string()))?;
+            let weight = directive.option("weight", &["column", "weight"])?;
+            read_csv(&path, column, weight)
+        }
+        "weighted_file" => {
+            // It takes no options, so any given is an error
+            directive.option("", &[])?;
+            read_weighted_file(&path).map(|list| {
+                if !list.skipped.is_empty() {
+                    warnings.push(CompileWarningType::SkippedZeroWeights { path: path.clone(), lines: list.skipped });
+                }
+                (list.terminals, Some(list.weights))
+            })
+        }
+        _ => return Err(CompileErrorType::UnknownDirective(directive.name)),
+    }.map_err(|error| CompileErrorType::DataFile(DataError { path, error }))?;
+
+    let rewrite = terminals.into_iter().map(|terminal| vec![Symbol::Terminal(terminal.into())]).collect();
+    return Ok((rewrite, weights, warnings));
+}
+
+// The entries of a weighted file
+struct WeightedList {
+    terminals: Vec<String>,
+    weights: Vec<f64>,
+    // The lines left out for weighing 0
+    skipped: Vec<u64>,
+}
+
+// Reads a file of lines holding a terminal, a tab, and a weight. Blank lines
+// are skipped, and the weight is after the last tab, so a terminal can hold
+// tabs of its own.
+fn read_weighted_file(path: &Path) -> std::result::Result<WeightedList, DataErrorType> {
+    let text = std::fs::read_to_string(path).map_err(|error| match error.kind() {
+        std::io::ErrorKind::InvalidData => DataErrorType::Malformed { line: None, reason: "it isn't UTF-8".to_string() },
+        _ => DataErrorType::Unreadable(error),
+    })?;
+
+    let mut terminals = Vec::new();
+    let mut weights = Vec::new();
+    let mut skipped = Vec::new();
+    for (line, entry) in (1..).zip(text.lines()) {
+        if entry.trim().is_empty() {
+            continue;
+        }
+        let Some((terminal, weight)) = entry.rsplit_once('\t').filter(|(_, weight)| !weight.trim().is_empty()) else {
+            return Err(DataErrorType::MissingWeight { line });
+        };
+        match weight.trim().parse::<f64>() {
+            Ok(0.0) => skipped.push(line),
+            Ok(value) if value.is_finite() && value > 0.0 => {
+                terminals.push(terminal.to_string());
+                weights.push(value);
+            }
+            _ => return Err(DataErrorType::BadWeight { line, weight: weight.trim().to_string() }),
+        }
+    }
+    if terminals.is_empty() {
+        return Err(DataErrorType::NoPositiveWeight);
+    }
+    return Ok(WeightedList { terminals, weights, skipped });
+}
+
+// Reads the terminals from a column of a CSV file with a header row, and the
+// weights from another column if one is named
+#[cfg(feature = "csv")]
+fn read_csv(path: &Path, column: &str, weight: Option<&str>) -> std::result::Result<(Vec<String>, Option<Vec<f64>>), DataErrorType> {
+    let convert = |error: csv::Error| match error.kind() {
+        csv::ErrorKind::Io(error) => DataErrorType::Unreadable(std::io::Error::new(error.kind(), error.to_string())),
+        csv::ErrorKind::UnequalLengths { pos, expected_len, len } => DataErrorType::RaggedRow {
+            line: pos.as_ref().map_or(0, |pos| pos.line()),
+            expected: *expected_len,
+            found: *len
+        },
+        csv::ErrorKind::Utf8 { pos, .. } => DataErrorType::Malformed { line: pos.as_ref().map(|pos| pos.line()), reason: "it isn't UTF-8".to_string() },
+        _ => DataErrorType::Malformed { line: None, reason: error.to_string() },
+    };
+    let mut reader = csv::Reader::from_path(path).map_err(convert)?;
+    let headers = reader.headers().map_err(convert)?.clone();
+    let find = |name: &str| headers.iter().position(|header| header == name).ok_or_else(|| DataErrorType::MissingColumn {
+        column: name.to_string(),
+        found: headers.iter().map(str::to_string).collect()
+    });
+    let column = find(column)?;
+    let weight = weight.map(find).transpose()?;
+
+    let mut terminals = Vec::new();
+    let mut weights = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(convert)?;
+        terminals.push(record[column].to_string());
+        if let Some(weight) = weight {
+            let text = record[weight].trim();
+            match text.parse::<f64>() {
+                Ok(value) if value.is_finite() && value >= 0.0 => weights.push(value),
+                _ => return Err(DataErrorType::BadWeight {
+                    line: record.position().map_or(0, |position| position.line()),
+                    weight: text.to_string()
+                }),
+            }
+        }
+    }
+    if weight.is_some() && !terminals.is_empty() && !weights.iter().any(|weight| *weight > 0.0) {
+        return Err(DataErrorType::NoPositiveWeight);
+    }
+    return Ok((terminals, weight.map(|_| weights)));
+}
+
+#[cfg(not(feature = "csv"))]
+fn read_csv(_path: &Path, _column: &str, _weight: Option<&str>) -> std::result::Result<(Vec<String>, Option<Vec<f64>>), DataErrorType> {
+    return Err(DataErrorType::Malformed { line: None, reason: "reading CSV files needs blabber built with the `csv` feature".to_string() });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_directives() {
+        assert_eq!(parse_directive(" @csv(\"palette.csv\", column = \"name\",weight=\"freq\") "), Ok(Directive {
+            name: "csv".to_string(),
+            path: "palette.csv".to_string(),
+            options: vec![("column".to_string(), "name".to_string()), ("weight".to_string(), "freq".to_string())]
+        }));
+        let reason = |text: &str| match parse_directive(text) {
+            Err(CompileErrorType::MalformedDirective(reason)) => reason,
+            other => panic!("expected a malformed directive, got {:?}", other),
+        };
+        assert_eq!(reason("@csv"), "Expected `(` after `@csv`");
+        assert_eq!(reason("@csv(\"a.csv\""), "Expected `@csv(...)` to end with `)`");
+        assert_eq!(reason("@csv(a.csv)"), "Expected `@csv` to start with a quoted file name");
+        assert_eq!(reason("@csv(\"a.csv\", column)"), "Expected `KEY = \"VALUE\"` after the file name of `@csv`");
+        assert_eq!(reason("@csv(\"a.csv\" \"b\")"), "Unexpected `\"b\"` in `@csv`");
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn read_csv_columns() {
+        let grammar = crate::parser::parse_file(&PathBuf::from("example_data/palette.bnf")).unwrap();
+        let terminals = grammar.rules.rewrite("colors").iter().map(|alternative| alternative.first().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(terminals, ["\"red\"", "\"light, dusty blue\"", "\"midnight\\nblack\""]);
+        assert_eq!(*grammar.weights["colors"], [5.0, 1.5, 0.0]);
+        assert!(!grammar.weights.contains_key("sentence"));
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn refuse_bad_csv_files() {
+        let dir = std::env::temp_dir().join(format!("blabber-csv-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let error = |csv: &str, options: &str| {
+            std::fs::write(dir.join("words.csv"), csv).unwrap();
+            let grammar = format!("sentence = \"hi\"\nwords = @csv(\"words.csv\"{})", options);
+            let errors = crate::parser::parse_str(&grammar, SourceName::file(dir.join("words.bnf"))).unwrap_err();
+            assert_eq!(errors[0].location.line, Some(2));
+            return errors[0].error.to_string().replace(&dir.join("words.csv").display().to_string(), "words.csv");
+        };
+
+        assert_eq!(error("word,freq\nhi,1\n", ", column = \"name\""), "words.csv has no column `name` (its columns are `word`, `freq`)");
+        assert_eq!(error("word,freq\nhi,1\n\"a\nb\",lots\n", ", column = \"word\", weight = \"freq\""),
+            "words.csv line 3: weight `lots` isn't a number of at least 0");
+        assert_eq!(error("word,freq\nhi,1\nhey\n", ", column = \"word\""), "words.csv line 3: expected 2 fields like the header, found 1");
+        assert_eq!(error("word,freq\nhi,0\n", ", column = \"word\", weight = \"freq\""), "words.csv has no row with a weight above 0");
+        assert_eq!(error("word\nhi\n", ""), "`@csv` needs a `column = \"NAME\"` to take terminals from");
+        assert_eq!(error("word\nhi\n", ", column = \"word\", sheet = \"1\""), "`@csv` has no option `sheet`");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_weighted_files() {
+        let (grammar, warnings) = crate::parser::parse_file_with_options(&PathBuf::from("example_data/names.bnf"), &Default::default()).unwrap();
+        let terminals = grammar.rules.rewrite("first.name").iter().map(|alternative| alternative.first().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(terminals, ["\"Ada\"", "\"Grace\"", "\"Margaret Hamilton\""]);
+        assert_eq!(*grammar.weights["first.name"], [12.0, 7.5, 3.0]);
+        assert_eq!(grammar.weights["first.name"].cumulative(), [12.0, 19.5, 22.5]);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].location.line, Some(3));
+        assert_eq!(warnings[0].warning.to_string(), "Skipped 1 entry weighing 0 in example_data/names.tsv (line 4)");
+    }
+
+    #[test]
+    fn refuse_bad_weighted_files() {
+        let dir = std::env::temp_dir().join(format!("blabber-weighted-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let error = |tsv: &str, options: &str| {
+            std::fs::write(dir.join("words.tsv"), tsv).unwrap();
+            let grammar = format!("sentence = \"hi\"\nwords = @weighted_file(\"words.tsv\"{})", options);
+            let errors = crate::parser::parse_str(&grammar, SourceName::file(dir.join("words.bnf"))).unwrap_err();
+            assert_eq!(errors[0].location.line, Some(2));
+            return errors[0].error.to_string().replace(&dir.join("words.tsv").display().to_string(), "words.tsv");
+        };
+
+        assert_eq!(error("hi\t1\n\nhey\n", ""), "words.tsv line 3: expected a terminal, a tab, and a weight");
+        assert_eq!(error("hi\t1\nhey\t\n", ""), "words.tsv line 2: expected a terminal, a tab, and a weight");
+        assert_eq!(error("hi\t1\nhey\tlots\n", ""), "words.tsv line 2: weight `lots` isn't a number of at least 0");
+        assert_eq!(error("hi\t-1\n", ""), "words.tsv line 1: weight `-1` isn't a number of at least 0");
+        assert_eq!(error("hi\t0\n", ""), "words.tsv has no row with a weight above 0");
+        assert_eq!(error("hi\t1\n", ", column = \"a\""), "`@weighted_file` has no option `column`");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_large_weighted_files() {
+        let dir = std::env::temp_dir().join(format!("blabber-large-weighted-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tsv = (0..100_000).map(|index| format!("word {}\t{}\n", index, index % 7)).collect::<String>();
+        std::fs::write(dir.join("words.tsv"), tsv).unwrap();
+
+        let start = std::time::Instant::now();
+        let (grammar, warnings) = crate::parser::parse_str_with_options("words = @weighted_file(\"words.tsv\")",
+            SourceName::file(dir.join("words.bnf")), &Default::default()).unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(grammar.rules.rewrite("words").len(), 85_714);
+        assert_eq!(warnings[0].warning.to_string().split(" in ").next(), Some("Skipped 14286 entries weighing 0"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn find_data_files_next_to_grammars() {
+        assert_eq!(data_path(&SourceName::file("grammars/colors.bnf"), "palette.csv"), PathBuf::from("grammars/palette.csv"));
+        assert_eq!(data_path(&SourceName::file("colors.bnf"), "palette.csv"), PathBuf::from("palette.csv"));
+        assert_eq!(data_path(&SourceName::Stdin, "data/palette.csv"), PathBuf::from("data/palette.csv"));
+    }
+}
@@ -0,0 +1,197 @@
+/*
+    Reads dynamic symbols, like `@date("%Y-%m-%d", 2020-01-01, 2025-12-31)`,
+    and the `%use dynamics` line a file needs to use them, so a grammar
+    written before they existed can't start making random values by accident.
+
+    Everything about a dynamic symbol is checked where it's written, so a bad
+    format or a range that ends before it starts is an error at the rule
+    using it, rather than a surprise while generating.
+*/
+
+use itertools::Itertools;
+
+use crate::grammar::{days_from_civil, days_in_month, format_specifiers, Bracket, Dynamic, DATE_SPECIFIERS, DYNAMICS, MAX_PLACES};
+use super::{CompileErrorType, Result};
+
+// How each dynamic symbol is written, for errors
+const USAGES: [(&str, &str); 3] = [
+    ("date", "`@date(\"FORMAT\", FIRST, LAST)` with dates like 2020-01-01"),
+    ("float", "`@float(MIN, MAX, PLACES)`"),
+    ("uuid", "`@uuid`"),
+];
+
+fn malformed(reason: String) -> CompileErrorType {
+    CompileErrorType::MalformedDynamic(reason)
+}
+
+// Whether text starts with a dynamic symbol: an `@` and the name of one,
+// followed by its arguments, a case suffix, or the end of the symbol, which
+// a bracket ends too
+pub fn starts_with_dynamic(text: &str) -> bool {
+    let Some(text) = text.strip_prefix('@') else {
+        return false;
+    };
+    return DYNAMICS.iter().any(|name| text.strip_prefix(name)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(':') || rest.starts_with(|c: char| c.is_whitespace() || Bracket::is_bracket(c))));
+}
+
+pub fn is_use(line: &str) -> bool {
+    line.strip_prefix("%use").is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+// Checks a `%use` line names a feature blabber has. The only one is
+// `dynamics`.
+pub fn parse_use(line: &str) -> Result<()> {
+    match line["%use".len()..].trim() {
+        "dynamics" => Ok(()),
+        feature => Err(CompileErrorType::UnknownFeature(feature.to_string())),
+    }
+}
+
+// Parses the text of a dynamic symbol, as the lexer took it
+pub fn parse_dynamic(text: &str) -> Result<Dynamic> {
+    let (name, arguments) = match text.split_once('(') {
+        Some((name, arguments)) => (name, Some(arguments.strip_suffix(')').expect("the lexer takes dynamic symbols up to their `)`"))),
+        None => (text, None),
+    };
+    return match (name, arguments) {
+        ("@uuid", None) => Ok(Dynamic::Uuid),
+        ("@date", Some(arguments)) => parse_date(arguments),
+        ("@float", Some(arguments)) => parse_float(arguments),
+        _ => {
+            let usage = USAGES.iter().find(|(usage_name, _)| name[1..].starts_with(usage_name)).map_or("", |(_, usage)| usage);
+            Err(malformed(format!("Expected {}, found `{}`", usage, text)))
+        }
+    };
+}
+
+// Reads a quoted string from the start of text, returning it and the rest
+fn quoted(text: &str) -> Option<(String, &str)> {
+    let (string, rest) = text.strip_prefix('"')?.split_once('"')?;
+    return Some((string.to_string(), rest.trim_start()));
+}
+
+fn parse_date(arguments: &str) -> Result<Dynamic> {
+    let usage = || malformed(format!("Expected {}, found `@date({})`", USAGES[0].1, arguments));
+    let (format, rest) = quoted(arguments.trim_start()).ok_or_else(usage)?;
+    let dates = rest.strip_prefix(',').ok_or_else(usage)?.split(',').map(str::trim).collect_vec();
+    let [first_text, last_text] = dates[..] else {
+        return Err(usage());
+    };
+
+    for specifier in format_specifiers(&format) {
+        match specifier {
+            None => return Err(malformed(format!("The `@date` format `{}` ends with a `%` (write `%%` for a literal one)", format))),
+            Some(c) if c != '%' && !DATE_SPECIFIERS.contains(&c) => return Err(malformed(format!(
+                "`%{}` isn't a date format `@date` knows (it knows {} and %%)", c, DATE_SPECIFIERS.iter().map(|c| format!("%{}", c)).join(" ")))),
+            Some(_) => {}
+        }
+    }
+    let first = parse_iso_date(first_text)?;
+    let last = parse_iso_date(last_text)?;
+    if first > last {
+        return Err(malformed(format!("`@date` ends before it starts: {} is before {}", last_text, first_text)));
+    }
+    return Ok(Dynamic::Date { format, first, last });
+}
+
+// Reads a date written like 2020-01-01, as days since 1970-01-01
+fn parse_iso_date(text: &str) -> Result<i64> {
+    let invalid = || malformed(format!("`{}` isn't a date like 2020-01-01", text));
+    let parts = text.split('-').collect_vec();
+    let [year, month, day] = parts[..] else {
+        return Err(invalid());
+    };
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 || !text.chars().all(|c| c.is_ascii_digit() || c == '-') {
+        return Err(invalid());
+    }
+    let (year, month, day): (i64, u32, u32) = (year.parse().map_err(|_| invalid())?, month.parse().map_err(|_| invalid())?, day.parse().map_err(|_| invalid())?);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return Err(invalid());
+    }
+    return Ok(days_from_civil(year, month, day));
+}
+
+fn parse_float(arguments: &str) -> Result<Dynamic> {
+    let parts = arguments.split(',').map(str::trim).collect_vec();
+    let [min_text, max_text, places_text] = parts[..] else {
+        return Err(malformed(format!("Expected {}, found `@float({})`", USAGES[1].1, arguments)));
+    };
+    let places: u32 = places_text.parse()
+        .map_err(|_| malformed(format!("Expected a number of decimal places for `@float`, found `{}`", places_text)))?;
+    if places > MAX_PLACES {
+        return Err(malformed(format!("`@float` can have at most {} decimal places, not {}", MAX_PLACES, places)));
+    }
+    let min = parse_fixed(min_text, places)?;
+    let max = parse_fixed(max_text, places)?;
+    if min > max {
+        return Err(malformed(format!("`@float` ends before it starts: {} is below {}", max_text, min_text)));
+    }
+    if max.abs_diff(min) == u64::MAX {
+        return Err(malformed(format!("`@float({})` has too many values to pick from", arguments)));
+    }
+    return Ok(Dynamic::Float { min, max, places });
+}
+
+// Reads a decimal number like -1.25, counted in steps of the last of places
+// decimal places
+fn parse_fixed(text: &str, places: u32) -> Result<i64> {
+    let invalid = || malformed(format!("`{}` isn't a number like 1 or -0.25", text));
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) || digits.ends_with('.') {
+        return Err(invalid());
+    }
+    if fraction.len() > places as usize {
+        return Err(malformed(format!("`{}` has more decimal places than the {} `@float` makes", text, places)));
+    }
+    let too_large = || malformed(format!("`{}` is too large for `@float`", text));
+    let scaled = format!("{}{:0<width$}", whole, fraction, width = places as usize);
+    let value: i64 = scaled.parse().map_err(|_| too_large())?;
+    return Ok(if negative { -value } else { value });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_dynamics() {
+        assert_eq!(parse_dynamic("@date(\"%Y-%m-%d\", 2020-01-01, 2025-12-31)"), Ok(Dynamic::Date {
+            format: "%Y-%m-%d".to_string(),
+            first: days_from_civil(2020, 1, 1),
+            last: days_from_civil(2025, 12, 31)
+        }));
+        assert_eq!(parse_dynamic("@date( \"(%d)\" ,2024-02-29,2024-02-29 )").unwrap().to_string(), "@date(\"(%d)\", 2024-02-29, 2024-02-29)");
+        assert_eq!(parse_dynamic("@float(0, 1, 3)"), Ok(Dynamic::Float { min: 0, max: 1000, places: 3 }));
+        assert_eq!(parse_dynamic("@float(-2.5, 0.25, 2)"), Ok(Dynamic::Float { min: -250, max: 25, places: 2 }));
+        assert_eq!(parse_dynamic("@uuid"), Ok(Dynamic::Uuid));
+        assert!(starts_with_dynamic("@uuid | x") && starts_with_dynamic("@date(") && !starts_with_dynamic("@dates") && !starts_with_dynamic("@csv(\"a\")"));
+        assert!(is_use("%use dynamics") && !is_use("%user"));
+        assert_eq!(parse_use("%use  dynamics "), Ok(()));
+        assert_eq!(parse_use("%use dynamite"), Err(CompileErrorType::UnknownFeature(String::new())));
+    }
+
+    #[test]
+    fn refuse_bad_dynamics() {
+        let reason = |text: &str| parse_dynamic(text).unwrap_err().to_string();
+        assert_eq!(reason("@date(\"%Y %q\", 2020-01-01, 2020-12-31)"),
+            "`%q` isn't a date format `@date` knows (it knows %Y %y %m %d %e %j %B %b %A %a %u %F and %%)");
+        assert_eq!(reason("@date(\"100%\", 2020-01-01, 2020-12-31)"), "The `@date` format `100%` ends with a `%` (write `%%` for a literal one)");
+        assert_eq!(reason("@date(\"%F\", 2025-12-31, 2020-01-01)"), "`@date` ends before it starts: 2020-01-01 is before 2025-12-31");
+        assert_eq!(reason("@date(\"%F\", 2023-02-29, 2024-01-01)"), "`2023-02-29` isn't a date like 2020-01-01");
+        assert_eq!(reason("@date(2020-01-01, 2020-12-31)"),
+            "Expected `@date(\"FORMAT\", FIRST, LAST)` with dates like 2020-01-01, found `@date(2020-01-01, 2020-12-31)`");
+        assert_eq!(reason("@float(1, 0, 3)"), "`@float` ends before it starts: 0 is below 1");
+        assert_eq!(reason("@float(0, 0.12345, 3)"), "`0.12345` has more decimal places than the 3 `@float` makes");
+        assert_eq!(reason("@float(0, 1, 10)"), "`@float` can have at most 9 decimal places, not 10");
+        assert_eq!(reason("@float(0, 99999999999, 9)"), "`99999999999` is too large for `@float`");
+        assert_eq!(reason("@uuid()"), "Expected `@uuid`, found `@uuid()`");
+        for bad in ["@float(0, 1)", "@float(a, 1, 0)", "@float(1., 2, 1)", "@float(.5, 1, 1)", "@date", "@uuid:upper", "@date(\"%F\", 2020-1-1, 2020-01-02)"] {
+            assert!(matches!(parse_dynamic(bad), Err(CompileErrorType::MalformedDynamic(_))), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt::Display;
+
+use itertools::Itertools;
+
+use crate::error_handling::{Location, SourceName};
+use crate::grammar::{renumber_tags, tags_of, AlternativeRef, Rewrite, Symbol, SymbolRef};
+use super::{CompileError, CompileErrorType, FileResult, Grammar};
+
+// An alternative to leave out of a grammar, for trying it without some
+// branches
+#[derive(Debug, PartialEq, Clone)]
+pub enum Exclusion {
+    // The alternative of the rule at this index, counting from 0
+    Alternative(String, usize),
+    // Every alternative with a terminal containing this text
+    Containing(String),
+    // Every tagged alternative without one of these tags. Untagged
+    // alternatives are kept.
+    OnlyTags(Vec<String>),
+    // Every alternative with this tag
+    SkipTag(String),
+}
+
+// Exclusions are shown as the option giving them
+impl Display for Exclusion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Exclusion::Alternative(rule, index) => write!(f, "--exclude {}:{}", rule, index),
+            Exclusion::Containing(text) => write!(f, "--exclude-containing {}", Symbol::Terminal(text.as_str().into())),
+            Exclusion::OnlyTags(tags) => write!(f, "--only-tags {}", tags.join(",")),
+            Exclusion::SkipTag(tag) => write!(f, "--skip-tags {}", tag),
+        }
+    }
+}
+
+impl Exclusion {
+    fn excludes(&self, rule: &str, index: usize, alternative: AlternativeRef<'_>, tags: &[&str]) -> bool {
+        match self {
+            Exclusion::Alternative(excluded, excluded_index) => excluded == rule && *excluded_index == index,
+            Exclusion::Containing(text) => alternative.iter()
+                .any(|symbol| matches!(symbol, SymbolRef::Terminal(terminal) if terminal.contains(text.as_str()))),
+            Exclusion::OnlyTags(only) => !tags.is_empty() && !tags.iter().any(|tag| only.iter().any(|only| only == tag)),
+            Exclusion::SkipTag(skipped) => tags.contains(&skipped.as_str()),
+        }
+    }
+}
+
+// Reads `RULE:INDEX` for --exclude, splitting at the last `:` since rule
+// names can contain one
+pub fn parse_exclusion(text: &str) -> Result<Exclusion, String> {
+    match text.rsplit_once(':').map(|(rule, index)| (rule, index.parse())) {
+        Some((rule, Ok(index))) if !rule.is_empty() => Ok(Exclusion::Alternative(rule.to_string(), index)),
+        _ => Err(format!("expected RULE:INDEX, found `{}`", text)),
+    }
+}
+
+// Removes every excluded alternative from the grammar. Indices count the
+// alternatives as they're written, before any are removed. A rule with no
+// alternatives left is an error, as is an index a rule doesn't have or a
+// tag no alternative has. Index-limited tags are renumbered for the
+// alternatives left.
+pub fn exclude_alternatives(grammar: &mut Grammar, exclusions: &[Exclusion]) -> FileResult<()> {
+    let mut errors = Vec::new();
+    let known = grammar.tag_counts().into_keys().map(str::to_string).collect_vec();
+    for exclusion in exclusions {
+        let (option, tags) = match exclusion {
+            Exclusion::OnlyTags(tags) => ("only-tags", &tags[..]),
+            Exclusion::SkipTag(tag) => ("skip-tags", std::slice::from_ref(tag)),
+            _ => ("", &[][..]),
+        };
+        for tag in tags.iter().filter(|tag| !known.contains(tag)) {
+            errors.push(CompileError {
+                location: Location::whole(SourceName::Cli(option.to_string())),
+                error: CompileErrorType::UnknownTag { tag: tag.clone(), known: known.clone() }
+            });
+        }
+        if let Exclusion::Alternative(rule, index) = exclusion {
+            match grammar.rules.get(rule.as_str()) {
+                Some(rewrite) if *index >= rewrite.len() => errors.push(CompileError {
+                    location: grammar.location(rule),
+                    error: CompileErrorType::ExcludedOutOfRange { rule: rule.clone(), index: *index, alternatives: rewrite.len() }
+                }),
+                Some(_) => {}
+                None => errors.push(CompileError {
+                    location: Location::whole(SourceName::Cli("exclude".to_string())),
+                    error: CompileErrorType::UndefinedNonterminal(rule.clone())
+                }),
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // The rules which had alternatives removed, and which were kept
+    let mut reduced = Vec::new();
+    for (rule, rewrite) in grammar.rules.iter() {
+        let before = rewrite.len();
+        let annotations = grammar.annotations.get(rule);
+        // Which exclusions removed the rule's alternatives, in the order
+        // they were given
+        let mut responsible = Vec::new();
+        let mut kept = Vec::with_capacity(before);
+        for (index, alternative) in rewrite.iter().enumerate() {
+            let mut keep = true;
+            let tags = tags_of(annotations, index);
+            for exclusion in exclusions.iter().filter(|exclusion| exclusion.excludes(rule, index, alternative, &tags)) {
+                keep = false;
+                if !responsible.contains(exclusion) {
+                    responsible.push(exclusion.clone());
+                }
+            }
+            kept.push(keep);
+        }
+        let left = kept.iter().filter(|keep| **keep).count();
+        // A weighted rule with only weights of zero left has nothing to pick
+        let mut nothing_to_pick = false;
+        if let Some(weights) = grammar.weights.get_mut(rule) {
+            let mut kept = kept.iter();
+            let mut kept_weights = weights.to_vec();
+            kept_weights.retain(|_| *kept.next().unwrap());
+            nothing_to_pick = !kept_weights.iter().any(|weight| *weight > 0.0);
+            *weights = kept_weights.into();
+        }
+        if before > 0 && (left == 0 || nothing_to_pick) {
+            responsible.sort_by_key(|exclusion| exclusions.iter().position(|given| given == exclusion));
+            errors.push(CompileError {
+                location: grammar.rules.location(rule).expect("parsed rules have locations"),
+                error: CompileErrorType::ExcludedEverything { rule: rule.to_string(), exclusions: responsible }
+            });
+        }
+        if left < before {
+            let rewrite: Rewrite = rewrite.iter().zip(&kept).filter(|(_, keep)| **keep).map(|(alternative, _)| alternative.to_alternative()).collect();
+            reduced.push((rule.to_string(), rewrite, kept));
+        }
+    }
+
+    if !errors.is_empty() {
+        errors.sort_by_key(|error| error.location.line);
+        return Err(errors);
+    }
+    for (rule, rewrite, kept) in reduced {
+        grammar.rules.insert(&rule, &rewrite);
+        if let Some(annotations) = grammar.annotations.get_mut(rule.as_str()) {
+            renumber_tags(annotations, &kept);
+            if annotations.is_empty() {
+                grammar.annotations.remove(rule.as_str());
+            }
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_str;
+
+    const GRAMMAR: &str = concat!(
+        "sentence = noun \" \" verb\n",
+        "noun = \"ideas\" | \"dogs\" | \"cats\"\n",
+        "verb = \"sleep furiously\" | \"run\" | \"sleep\"\n",
+    );
+
+    #[test]
+    fn read_exclusions() {
+        assert_eq!(parse_exclusion("noun:2"), Ok(Exclusion::Alternative("noun".to_string(), 2)));
+        assert_eq!(parse_exclusion("odd:name:0"), Ok(Exclusion::Alternative("odd:name".to_string(), 0)));
+        assert!(parse_exclusion("noun").is_err());
+        assert!(parse_exclusion(":1").is_err());
+        assert!(parse_exclusion("noun:-1").is_err());
+    }
+
+    #[test]
+    fn exclude_from_grammar() {
+        let mut grammar = parse_str(GRAMMAR, SourceName::Builtin).unwrap();
+        let exclusions = [Exclusion::Alternative("noun".to_string(), 2), Exclusion::Containing("furious".to_string())];
+        exclude_alternatives(&mut grammar, &exclusions).unwrap();
+        let terminals = |rule: &str| grammar.rules.rewrite(rule).iter().map(|alternative| alternative.first().unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(terminals("noun"), ["\"ideas\"", "\"dogs\""]);
+        assert_eq!(terminals("verb"), ["\"run\"", "\"sleep\""]);
+    }
+
+    #[test]
+    fn refuse_bad_exclusions() {
+        let mut grammar = parse_str(GRAMMAR, SourceName::Builtin).unwrap();
+        let errors = exclude_alternatives(&mut grammar, &[Exclusion::Alternative("noun".to_string(), 3), Exclusion::Alternative("adverb".to_string(), 0)]).unwrap_err();
+        assert_eq!(errors[0].error.to_string(), "`noun` has no alternative 3 to exclude (it has 3, counting from 0)");
+        assert_eq!(errors[0].location.line, Some(2));
+        assert_eq!(errors[1].error, CompileErrorType::UndefinedNonterminal("adverb".to_string()));
+
+        let exclusions = [Exclusion::Containing("sleep".to_string()), Exclusion::Alternative("verb".to_string(), 1)];
+        let errors = exclude_alternatives(&mut grammar, &exclusions).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error.to_string(), "`verb` has no alternatives left after --exclude-containing \"sleep\", --exclude verb:1");
+    }
+
+    #[test]
+    fn exclude_by_tag() {
+        let text = concat!(
+            "sentence = greeting \", \" name\n",
+            ";@ tag formal=0,1 casual=2\n",
+            "greeting = \"good day\" | \"hello\" | \"hey\"\n",
+            ";@ tag casual nsfw=1\n",
+            "name = \"pal\" | \"you\"\n",
+        );
+        let only = |tags: &[&str]| Exclusion::OnlyTags(tags.iter().map(ToString::to_string).collect());
+        let mut grammar = parse_str(text, SourceName::Builtin).unwrap();
+        exclude_alternatives(&mut grammar, &[only(&["casual"]), Exclusion::SkipTag("nsfw".to_string())]).unwrap();
+        assert_eq!(grammar.rules.rewrite("greeting"), vec![vec![Symbol::Terminal("hey".into())]]);
+        assert_eq!(grammar.rules.rewrite("name"), vec![vec![Symbol::Terminal("pal".into())]]);
+        assert_eq!(grammar.rules.rewrite("sentence").len(), 1);
+        assert_eq!(grammar.annotations["greeting"]["tag"], ["casual=0"]);
+        assert_eq!(grammar.annotations["name"]["tag"], ["casual"]);
+
+        let mut grammar = parse_str(text, SourceName::Builtin).unwrap();
+        let errors = exclude_alternatives(&mut grammar, &[only(&["formal", "polite"]), Exclusion::SkipTag("nsfw".to_string())]).unwrap_err();
+        assert_eq!(errors.iter().map(|error| error.error.to_string()).collect_vec(), [
+            "No alternative is tagged `polite` (the tags are `casual`, `formal`, `nsfw`)",
+        ]);
+        assert_eq!(errors[0].location.source, SourceName::Cli("only-tags".to_string()));
+        let errors = exclude_alternatives(&mut grammar, &[only(&["formal"]), Exclusion::SkipTag("nsfw".to_string())]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error.to_string(), "`name` has no alternatives left after --only-tags formal, --skip-tags nsfw");
+        assert_eq!(errors[0].location.line, Some(5));
+
+        let mut untagged = parse_str(GRAMMAR, SourceName::Builtin).unwrap();
+        let errors = exclude_alternatives(&mut untagged, &[Exclusion::SkipTag("nsfw".to_string())]).unwrap_err();
+        assert_eq!(errors[0].error.to_string(), "No alternative is tagged `nsfw` (the grammar has no tags)");
+    }
+}
@@ -0,0 +1,291 @@
+/*
+    `%include "PATH"` lines, which parse the rules of another grammar file
+    into the one including it, for sharing vocabularies between grammars.
+    Paths are relative to the file with the `%include`, and the included
+    rules keep their own file in their locations.
+
+    Each file is only included once, however many files include it or
+    whichever path they use, so shared vocabularies can be included
+    wherever they're needed. A file including one it's being included
+    from is an error naming the cycle, and so are includes nested deeper
+    than the maximum, which stops long chains.
+
+    A URL is only fetched with `--allow-remote-includes`, in a blabber built
+    with the `remote` feature. What's fetched is kept in a cache directory,
+    with its ETag, and only fetched again if the server says it changed.
+    Offline, only the cache is used.
+*/
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::dedup::fnv1a_128;
+use super::{CompileErrorType, ParseOptions, Result, SourceName};
+
+// How `%include`s of URLs are handled
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum RemoteIncludes {
+    // They're errors
+    #[default]
+    Denied,
+    // They're fetched, keeping what was fetched in the directory
+    Fetch(PathBuf),
+    // Only what was kept in the directory is used
+    Offline(PathBuf),
+}
+
+// Where fetched includes are kept unless told otherwise
+pub fn default_cache_dir() -> PathBuf {
+    let cache = std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    return cache.join("blabber").join("includes");
+}
+
+pub fn is_include(line: &str) -> bool {
+    line.strip_prefix("%include").is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+// The path or URL a `%include` line names
+fn include_target(line: &str) -> Result<&str> {
+    let quoted = line["%include".len()..].trim();
+    return quoted.strip_prefix('"')
+        .and_then(|target| target.strip_suffix('"'))
+        .filter(|target| !target.is_empty() && !target.contains('"'))
+        .ok_or_else(|| CompileErrorType::MalformedInclude(quoted.to_string()));
+}
+
+fn is_url(target: &str) -> bool {
+    target.starts_with("http://") || target.starts_with("https://")
+}
+
+// Where the target is, relative to the file including it. Relative targets
+// in a fetched file are relative to its URL.
+fn resolve(target: &str, source: &SourceName) -> String {
+    if is_url(target) {
+        return target.to_string();
+    }
+    match source {
+        SourceName::File(path) => match path.to_str().filter(|path| is_url(path)) {
+            Some(url) => {
+                // Absolute paths are from the URL's host, and relative ones
+                // from the URL's directory
+                let host = url.find("//").expect("URLs have slashes") + 2;
+                let end = if target.starts_with('/') {
+                    url[host..].find('/').map_or(url.len(), |slash| host + slash)
+                } else {
+                    url.rfind('/').filter(|&slash| slash >= host).map_or(url.len(), |slash| slash + 1)
+                };
+                let separator = if target.starts_with('/') || url[..end].ends_with('/') { "" } else { "/" };
+                format!("{}{}{}", &url[..end], separator, target)
+            }
+            None => path.parent().unwrap_or(Path::new("")).join(target).to_string_lossy().into_owned(),
+        },
+        _ => target.to_string(),
+    }
+}
+
+// What identifies a file however it's named, so it's only included once
+fn include_key(target: &str) -> String {
+    if is_url(target) {
+        return target.to_string();
+    }
+    return std::fs::canonicalize(target).map_or_else(|_| target.to_string(), |path| path.to_string_lossy().into_owned());
+}
+
+// The files being included, from the one being parsed down, and every file
+// included so far
+pub struct Includes {
+    // The key and name of each file being included
+    stack: Vec<(String, String)>,
+    seen: HashSet<String>,
+    // Whether the source being parsed is on the stack
+    from_file: bool,
+}
+
+impl Includes {
+    // Starts from the source being parsed, which only files can include
+    pub fn new(source: &SourceName) -> Self {
+        let mut includes = Includes { stack: Vec::new(), seen: HashSet::new(), from_file: false };
+        if let SourceName::File(path) = source {
+            let name = path.to_string_lossy().into_owned();
+            let key = include_key(&name);
+            includes.seen.insert(key.clone());
+            includes.stack.push((key, name));
+            includes.from_file = true;
+        }
+        return includes;
+    }
+
+    // Reads what a `%include` line in source names, along with the source
+    // its rules are from, which for a URL is the URL. Returns None if it's
+    // been included already. Unless it's None, leave must be called once
+    // its rules are parsed.
+    pub fn enter(&mut self, line: &str, source: &SourceName, options: &ParseOptions) -> Result<Option<(String, SourceName)>> {
+        let target = resolve(include_target(line)?, source);
+        let key = include_key(&target);
+        if let Some(start) = self.stack.iter().position(|(included, _)| *included == key) {
+            let mut cycle: Vec<String> = self.stack[start..].iter().map(|(_, name)| name.clone()).collect();
+            cycle.push(target);
+            return Err(CompileErrorType::IncludeCycle(cycle));
+        }
+        if self.seen.contains(&key) {
+            return Ok(None);
+        }
+        let depth = self.stack.len() + 1 - usize::from(self.from_file);
+        if depth > options.max_include_depth {
+            return Err(CompileErrorType::IncludesTooDeep(options.max_include_depth));
+        }
+
+        let text = if is_url(&target) {
+            fetch_cached(&target, &options.remote)
+        } else {
+            std::fs::read_to_string(&target).map_err(|error| error.to_string())
+        };
+        return match text {
+            Ok(text) => {
+                self.seen.insert(key.clone());
+                self.stack.push((key, target.clone()));
+                Ok(Some((text, SourceName::file(&target))))
+            }
+            Err(reason) => Err(CompileErrorType::Include { target, reason }),
+        };
+    }
+
+    // Finishes the file entered last
+    pub fn leave(&mut self) {
+        self.stack.pop();
+    }
+}
+
+// Where a URL's text and ETag are kept in the cache
+fn cache_paths(dir: &Path, url: &str) -> (PathBuf, PathBuf) {
+    let key = format!("{:032x}", fnv1a_128(url.as_bytes()));
+    return (dir.join(format!("{}.bnf", key)), dir.join(format!("{}.etag", key)));
+}
+
+fn fetch_cached(url: &str, remote: &RemoteIncludes) -> std::result::Result<String, String> {
+    match remote {
+        RemoteIncludes::Denied => Err("including URLs needs --allow-remote-includes".to_string()),
+        RemoteIncludes::Offline(dir) => std::fs::read_to_string(cache_paths(dir, url).0)
+            .map_err(|_| "it hasn't been fetched before, and --offline only uses what has".to_string()),
+        RemoteIncludes::Fetch(dir) => fetch(url, dir),
+    }
+}
+
+// Fetches the URL, unless the server says the cached copy is still current
+#[cfg(feature = "remote")]
+fn fetch(url: &str, dir: &Path) -> std::result::Result<String, String> {
+    let (text_path, etag_path) = cache_paths(dir, url);
+    let cached = std::fs::read_to_string(&text_path).ok();
+    let etag = cached.as_ref().and_then(|_| std::fs::read_to_string(&etag_path).ok());
+    let mut request = ureq::get(url);
+    if let Some(etag) = &etag {
+        request = request.header("If-None-Match", etag.as_str());
+    }
+    let mut response = request.call().map_err(|error| error.to_string())?;
+    if let (304, Some(cached)) = (response.status().as_u16(), cached) {
+        return Ok(cached);
+    }
+    let text = response.body_mut().read_to_string().map_err(|error| error.to_string())?;
+
+    // A cache that can't be written only costs fetching it again next time
+    let _ = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&text_path, &text));
+    match response.headers().get("ETag").and_then(|etag| etag.to_str().ok()) {
+        Some(etag) => {
+            let _ = std::fs::write(&etag_path, etag);
+        }
+        None => {
+            let _ = std::fs::remove_file(&etag_path);
+        }
+    }
+    return Ok(text);
+}
+
+#[cfg(not(feature = "remote"))]
+fn fetch(_url: &str, _dir: &Path) -> std::result::Result<String, String> {
+    Err("fetching needs blabber built with the `remote` feature".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads an include in stdin
+    fn read(line: &str, remote: RemoteIncludes) -> Result<(String, SourceName)> {
+        let options = ParseOptions { remote, ..Default::default() };
+        return Includes::new(&SourceName::Stdin).enter(line, &SourceName::Stdin, &options).map(|read| read.expect("nothing was included before"));
+    }
+
+    #[test]
+    fn resolve_targets() {
+        let file = SourceName::file("grammars/english.bnf");
+        assert_eq!(resolve("names.bnf", &file), "grammars/names.bnf");
+        assert_eq!(resolve("/shared/names.bnf", &file), "/shared/names.bnf");
+        assert_eq!(resolve("https://example.com/a.bnf", &file), "https://example.com/a.bnf");
+        let fetched = SourceName::file("https://example.com/vocab/a.bnf");
+        assert_eq!(resolve("b.bnf", &fetched), "https://example.com/vocab/b.bnf");
+        assert_eq!(resolve("/b.bnf", &fetched), "https://example.com/b.bnf");
+        assert_eq!(resolve("b.bnf", &SourceName::file("https://example.com")), "https://example.com/b.bnf");
+        assert_eq!(resolve("names.bnf", &SourceName::Stdin), "names.bnf");
+
+        assert!(is_include("%include \"a.bnf\"") && is_include("%include") && !is_include("%included"));
+        assert_eq!(include_target("%include  \"a b.bnf\" "), Ok("a b.bnf"));
+        assert_eq!(include_target("%include a.bnf"), Err(CompileErrorType::MalformedInclude("a.bnf".to_string())));
+    }
+
+    #[test]
+    fn use_the_cache_offline() {
+        let dir = std::env::temp_dir().join(format!("blabber-include-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let url = "https://example.com/names.bnf";
+        let line = format!("%include \"{}\"", url);
+        let denied = read(&line, RemoteIncludes::Denied).unwrap_err();
+        assert_eq!(denied.to_string(), "Couldn't include `https://example.com/names.bnf`: including URLs needs --allow-remote-includes");
+        assert!(read(&line, RemoteIncludes::Offline(dir.clone())).is_err());
+
+        std::fs::write(cache_paths(&dir, url).0, "name = \"Ada\"").unwrap();
+        let (text, source) = read(&line, RemoteIncludes::Offline(dir.clone())).unwrap();
+        assert_eq!(text, "name = \"Ada\"");
+        assert_eq!(source.to_string(), url);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn fetch_again_only_when_changed() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/names.bnf", listener.local_addr().unwrap());
+        // Answers two requests, the second only if it asks about the ETag
+        let server = std::thread::spawn(move || {
+            for response in ["200 OK\r\nETag: \"v1\"\r\nContent-Length: 12\r\n\r\nname = \"Ada\"", "304 Not Modified\r\n\r\n"] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&stream);
+                let mut headers = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    headers.push(line.to_lowercase());
+                }
+                let revalidating = headers.iter().any(|header| header.starts_with("if-none-match: \"v1\""));
+                let response = if response.starts_with("304") && !revalidating { "500 Unexpected\r\nContent-Length: 0\r\n\r\n" } else { response };
+                (&stream).write_all(format!("HTTP/1.1 {}", response).as_bytes()).unwrap();
+            }
+        });
+
+        let dir = std::env::temp_dir().join(format!("blabber-include-fetch-{}", std::process::id()));
+        let line = format!("%include \"{}\"", url);
+        for _ in 0..2 {
+            let (text, _) = read(&line, RemoteIncludes::Fetch(dir.clone())).unwrap();
+            assert_eq!(text, "name = \"Ada\"");
+        }
+        server.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+/*
+    Decides what happens to each kind of warning about a grammar: whether
+    it's left out, warned about, or turned into an error. Kinds of warning
+    are named by their codes, like `deprecated`, or all at once by
+    `warnings`, and given a level with `-A`, `-W` and `-D`.
+
+    A level given for a warning's own name beats one given for `warnings`,
+    so `-D warnings -A deprecated` fails on everything but deprecations.
+    When a name is given more than one level, the strictest wins, with
+    denying stricter than warning and warning stricter than allowing.
+    Warnings nothing gives a level are warned about.
+*/
+
+use itertools::Itertools;
+
+use super::WARNING_CODES;
+
+// The name standing for every kind of warning
+pub const ALL_WARNINGS: &str = "warnings";
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    // The short flag giving a warning this level
+    pub fn flag(self) -> &'static str {
+        match self {
+            Level::Allow => "-A",
+            Level::Warn => "-W",
+            Level::Deny => "-D",
+        }
+    }
+}
+
+// How a warning with a level was given it
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Decision {
+    pub level: Level,
+    // Whether the level was given for `warnings` rather than the warning's
+    // own name
+    pub implied: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct WarningLevels {
+    // Each name given a level, with the strictest level given it
+    levels: Vec<(String, Level)>,
+}
+
+impl WarningLevels {
+    // Every warning turned into an error, like --deny-warnings
+    pub fn deny_all() -> Self {
+        let mut levels = WarningLevels::default();
+        levels.set(ALL_WARNINGS, Level::Deny);
+        return levels;
+    }
+
+    // Gives the warnings named a level, unless they were already given a
+    // stricter one. Names are checked with warning_name first.
+    pub fn set(&mut self, name: &str, level: Level) {
+        match self.levels.iter_mut().find(|(given, _)| given == name) {
+            Some((_, given)) => *given = level.max(*given),
+            None => self.levels.push((name.to_string(), level)),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<Level> {
+        self.levels.iter().find(|(given, _)| given == name).map(|(_, level)| *level)
+    }
+
+    // The level of the warning with code, or None if nothing gave it one
+    pub fn decide(&self, code: &str) -> Option<Decision> {
+        if let Some(level) = self.get(code) {
+            return Some(Decision { level, implied: false });
+        }
+        return self.get(ALL_WARNINGS).map(|level| Decision { level, implied: true });
+    }
+}
+
+// Checks name is a warning's code or `warnings`, explaining what names
+// there are if not
+pub fn warning_name(name: &str) -> Result<String, String> {
+    if name == ALL_WARNINGS || WARNING_CODES.contains(&name) {
+        return Ok(name.to_string());
+    }
+    return Err(format!("unknown warning `{}` (known warnings are {}, and `{}` for all of them)",
+        name, WARNING_CODES.iter().map(|code| format!("`{}`", code)).join(", "), ALL_WARNINGS));
+}
+
+// How a denied warning came to be an error, for the end of its message,
+// like "`-D deprecated` implied by `-D warnings`"
+pub fn denial_note(code: &str, implied: bool) -> String {
+    if implied {
+        return format!("`-D {}` implied by `-D {}`", code, ALL_WARNINGS);
+    }
+    return format!("requested with `-D {}`", code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decide_levels() {
+        let mut levels = WarningLevels::default();
+        assert_eq!(levels.decide("deprecated"), None);
+
+        levels.set(ALL_WARNINGS, Level::Deny);
+        levels.set("deprecated", Level::Allow);
+        levels.set("empty-terminal", Level::Warn);
+        levels.set("empty-terminal", Level::Allow);
+        assert_eq!(levels.decide("deprecated"), Some(Decision { level: Level::Allow, implied: false }));
+        assert_eq!(levels.decide("empty-terminal"), Some(Decision { level: Level::Warn, implied: false }));
+        assert_eq!(levels.decide("empty-alternative"), Some(Decision { level: Level::Deny, implied: true }));
+        assert_eq!(WarningLevels::deny_all().decide("deprecated"), Some(Decision { level: Level::Deny, implied: true }));
+
+        assert_eq!(warning_name("deprecated"), Ok("deprecated".to_string()));
+        assert_eq!(warning_name("unreachable-rule"), Ok("unreachable-rule".to_string()));
+        assert_eq!(warning_name(ALL_WARNINGS), Ok(ALL_WARNINGS.to_string()));
+        let error = warning_name("unreachable").unwrap_err();
+        assert!(error.starts_with("unknown warning `unreachable` (known warnings are `empty-alternative`, "), "{}", error);
+        assert_eq!(denial_note("deprecated", true), "`-D deprecated` implied by `-D warnings`");
+    }
+}
@@ -1,59 +1,290 @@
-use itertools::{Itertools, PeekingNext};
+use std::borrow::Cow;
 
+use crate::grammar::{Bracket, Case};
+use crate::json;
+use super::dynamic::starts_with_dynamic;
 use super::{CompileErrorType, Result};
 
+// A token of a line, borrowing its text from the line where it can, since
+// large grammars have millions of them
 #[derive(PartialEq, Debug)]
-pub enum Token {
+pub enum Token<'a> {
     Equals,
     Or,
-    Nonterminal(String),
-    Terminal(String)
+    Nonterminal(&'a str, Option<Case>),
+    // Only terminals with a `\n` in them have text of their own
+    Terminal(Cow<'a, str>),
+    // A dynamic symbol like `@uuid` or `@float(0, 1, 2)`, as written
+    Dynamic(&'a str),
+    // How likely the alternative it starts is to be picked, like the `5` of
+    // `5 "hi"`
+    Weight(u32),
+    // The brackets around symbols grouped together, like `[" " middle]`
+    Open(Bracket),
+    Close(Bracket),
 }
 
-pub fn lex_terminal(line: &mut impl PeekingNext<Item = char>) -> Result<Token> {
-    line.next(); // Consume open quote
-    let token_text: String = line.peeking_take_while(|&c| c != '\"').collect();
+// Lexes a terminal starting with its open quote, at col in its line,
+// returning it and how many bytes of text it took
+pub fn lex_terminal(text: &str, col: usize) -> Result<(Token<'_>, usize)> {
+    let quoted = &text[1..];
+    // Whether there's a backslash, which few terminals have, is found on the
+    // way to the closing quote, so the rest can be borrowed as they are
+    let mut backslash = false;
+    let Some(end) = quoted.bytes().position(|byte| {
+        backslash |= byte == b'\\';
+        byte == b'"'
+    }) else {
+        return Err(CompileErrorType::UnmatchedQuote { col, consumed: quoted.chars().count(), quotes: 1 });
+    };
 
-    // Check if there is a close quote and consume it if there is
-    if line.next() != Some('\"') {
-        return Err(CompileErrorType::UnmatchedQuote);
-    }
+    let token_text = &quoted[..end];
+    let token = if backslash { Cow::Owned(token_text.replace("\\n", "\n")) } else { Cow::Borrowed(token_text) };
+    return Ok((Token::Terminal(token), end + 2));
+}
 
-    Ok(Token::Terminal(token_text.replace("\\n", "\n")))
+// How many `#`s a raw terminal at the start of text has, like 2 for
+// `r##"...`, or None if text doesn't start with one
+pub fn raw_hashes(text: &str) -> Option<usize> {
+    let after_r = text.strip_prefix('r')?;
+    let hashes = after_r.len() - after_r.trim_start_matches('#').len();
+    return after_r[hashes..].starts_with('"').then_some(hashes);
 }
 
-pub fn lex_nonterminal(line: &mut impl Iterator<Item = char>) -> Result<Token> {
-    Ok(Token::Nonterminal(line.take_while(|c| !c.is_whitespace()).collect()))
+// Lexes a raw terminal at the start of text with the given number of `#`s,
+// taking everything up to the first `"` followed by as many `#`s as it is,
+// without reading `\n` as a newline
+pub fn lex_raw_terminal(text: &str, hashes: usize) -> Result<(Token<'_>, usize)> {
+    let closing = format!("\"{}", "#".repeat(hashes));
+    // After the `r`, the `#`s, and the open quote
+    let start = hashes + 2;
+    return match text[start..].find(&closing) {
+        Some(end) => Ok((Token::Terminal(Cow::Borrowed(&text[start..start + end])), start + end + closing.len())),
+        None => Err(CompileErrorType::UnterminatedRawTerminal(closing)),
+    };
 }
 
-pub fn lex_line(line: &str) -> Result<Vec<Token>> {
-    let mut tokens = Vec::new();
-
-    let mut line_chars = line.chars().peekable();
-
-    while let Some(c) = line_chars.peek() {
-        if *c == '=' {
-            line_chars.next();
-            tokens.push(Token::Equals);
-        } else if *c == '|' {
-            line_chars.next();
-            tokens.push(Token::Or);
-        } else if *c == '\"' {
-            tokens.push(lex_terminal(&mut line_chars)?);
-        } else if !c.is_whitespace() {
-            tokens.push(lex_nonterminal(&mut line_chars)?);
+// Lexes the nonterminal at the start of text along with its case suffix,
+// like `name:title`, if it has one. Brackets end it, like whitespace. Most
+// of a line is nonterminals, so they're looked through a byte at a time,
+// only decoding the characters which aren't ASCII.
+pub fn lex_nonterminal(text: &str) -> Result<(Token<'_>, usize)> {
+    let mut end = text.len();
+    let mut colon = None;
+    for (at, byte) in text.bytes().enumerate() {
+        let ends = if byte.is_ascii() {
+            char::from(byte).is_whitespace() || Bracket::is_bracket(char::from(byte))
         } else {
-            line_chars.next();
+            text.is_char_boundary(at) && text[at..].starts_with(char::is_whitespace)
+        };
+        if ends {
+            end = at;
+            break;
+        }
+        if byte == b':' && colon.is_none() {
+            colon = Some(at);
+        }
+    }
+
+    let token = match colon {
+        None => Token::Nonterminal(&text[..end], None),
+        Some(colon) => match Case::from_suffix(&text[colon + 1..end]) {
+            Some(case) => Token::Nonterminal(&text[..colon], Some(case)),
+            None => return Err(CompileErrorType::UnknownCase(text[colon + 1..end].to_string()))
+        }
+    };
+    return Ok((token, end));
+}
+
+// Lexes the whole number starting an alternative as its weight, which has
+// to be at least 1
+pub fn lex_weight(text: &str) -> Result<Token<'_>> {
+    match text.parse::<u32>() {
+        Ok(weight) if weight > 0 => Ok(Token::Weight(weight)),
+        _ => Err(CompileErrorType::BadWeight(text.to_string())),
+    }
+}
+
+// Lexes the dynamic symbol at the start of text, taking its parentheses and
+// everything between them, which can be in quotes, whole. What's inside is
+// checked when it's parsed.
+pub fn lex_dynamic(text: &str) -> Result<(Token<'_>, usize)> {
+    // A `(` straight after the name starts its arguments, rather than a
+    // group
+    let name_end = text.find(|c: char| c.is_whitespace() || Bracket::is_bracket(c)).unwrap_or(text.len());
+    if !text[name_end..].starts_with('(') {
+        return Ok((Token::Dynamic(&text[..name_end]), name_end));
+    }
+    let mut quoted = false;
+    for (index, c) in text.char_indices().skip(name_end + 1) {
+        match c {
+            '"' => quoted = !quoted,
+            ')' if !quoted => return Ok((Token::Dynamic(&text[..=index]), index + 1)),
+            _ => {}
+        }
+    }
+    return Err(CompileErrorType::MalformedDynamic(format!("Expected `{}` to end with `)`", text)));
+}
+
+impl Token<'_> {
+    // What sort of token this is, for showing tokens to people and tools
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Token::Equals => "equals",
+            Token::Or => "or",
+            Token::Nonterminal(..) => "nonterminal",
+            Token::Terminal(_) => "terminal",
+            Token::Dynamic(_) => "dynamic",
+            Token::Weight(_) => "weight",
+            Token::Open(_) => "open-bracket",
+            Token::Close(_) => "close-bracket",
+        }
+    }
+
+    // The token's text, which for terminals is what's between the quotes,
+    // for nonterminals leaves out the case suffix, and for dynamic symbols
+    // is all of it
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            Token::Equals => Cow::Borrowed("="),
+            Token::Or => Cow::Borrowed("|"),
+            Token::Nonterminal(text, _) | Token::Dynamic(text) => Cow::Borrowed(text),
+            Token::Terminal(text) => Cow::Borrowed(text),
+            Token::Weight(weight) => Cow::Owned(weight.to_string()),
+            Token::Open(bracket) => Cow::Owned(bracket.open().to_string()),
+            Token::Close(bracket) => Cow::Owned(bracket.close().to_string()),
+        }
+    }
+
+    pub fn case(&self) -> Option<Case> {
+        match self {
+            Token::Nonterminal(_, case) => *case,
+            _ => None
+        }
+    }
+}
+
+// Where a token was in its line, in characters, with columns counted from 1
+#[derive(PartialEq, Debug)]
+pub struct Span {
+    pub col: usize,
+    pub len: usize,
+}
+
+pub fn lex_line(line: &str) -> Result<Vec<Token<'_>>> {
+    lex_tokens(line, |_| {})
+}
+
+// Lexes a line like lex_line, also returning where each token was
+pub fn lex_line_spanned(line: &str) -> Result<Vec<(Token<'_>, Span)>> {
+    let mut spans = Vec::new();
+    let tokens = lex_tokens(line, |span| spans.push(span))?;
+    return Ok(tokens.into_iter().zip(spans).collect());
+}
+
+// Lexes a line, handing where each token was to spanned as it goes. Tokens
+// are sliced out of the line, rather than built up a character at a time,
+// since large grammars have millions of them.
+fn lex_tokens<'a>(line: &'a str, mut spanned: impl FnMut(Span)) -> Result<Vec<Token<'a>>> {
+    // With the space after them, tokens are rarely shorter than a few bytes
+    let mut tokens = Vec::with_capacity(line.len() / 4);
+    // The brackets open so far and their columns, checked as they close.
+    // A bracket closed by the wrong kind is only an error once the rest of
+    // the line has lexed.
+    let mut open = Vec::new();
+    let mut unmatched = None;
+
+    // Where the rest of the line starts, in bytes and as a column, which
+    // are the same for all of an ASCII line
+    let mut at = 0;
+    let mut col = 1;
+    let ascii = line.is_ascii();
+    while let Some(c) = line[at..].chars().next() {
+        // Most of a line between tokens is spaces, which are skipped first
+        if c.is_whitespace() {
+            at += c.len_utf8();
+            col += 1;
+            continue;
+        }
+        let rest = &line[at..];
+        let (token, taken) = match c {
+            '=' => (Token::Equals, 1),
+            '|' => (Token::Or, 1),
+            '"' => lex_terminal(rest, col).map_err(|error| match error {
+                CompileErrorType::UnmatchedQuote { col, consumed, .. } => CompileErrorType::UnmatchedQuote { col, consumed, quotes: line.matches('"').count() },
+                error => error,
+            })?,
+            // Case suffixes only go directly after nonterminals
+            ':' => return Err(CompileErrorType::MisplacedCase),
+            c => if let Some(bracket) = Bracket::from_open(c) {
+                (Token::Open(bracket), 1)
+            } else if let Some(bracket) = Bracket::from_close(c) {
+                (Token::Close(bracket), 1)
+            } else if let Some(hashes) = raw_hashes(rest) {
+                lex_raw_terminal(rest, hashes)?
+            } else if starts_with_dynamic(rest) {
+                lex_dynamic(rest)?
+            } else {
+                lex_nonterminal(rest)?
+            },
+        };
+
+        match token {
+            Token::Open(bracket) => open.push((bracket, col)),
+            Token::Close(close) if unmatched.is_none() => unmatched = match open.pop() {
+                Some((bracket, _)) if bracket == close => None,
+                Some((bracket, open_col)) => Some(CompileErrorType::MismatchedBracket { open: bracket, col: open_col, close, close_col: col }),
+                None => Some(CompileErrorType::UnopenedBracket { bracket: close, col }),
+            },
+            _ => {}
+        }
+        let len = if ascii { taken } else { rest[..taken].chars().count() };
+        spanned(Span { col, len });
+        tokens.push(token);
+        at += taken;
+        col += len;
+    }
+    if let Some(error) = unmatched {
+        return Err(error);
+    }
+    if let Some((bracket, col)) = open.pop() {
+        return Err(CompileErrorType::UnclosedBracket { bracket, col });
+    }
+
+    // A whole number starting an alternative is its weight, as long as
+    // symbols follow it, so a rule named like `7` can still be an
+    // alternative of its own
+    for index in 1..tokens.len().saturating_sub(1) {
+        let starts_alternative = matches!(tokens[index - 1], Token::Equals | Token::Or | Token::Open(_))
+            && !matches!(tokens[index + 1], Token::Or | Token::Close(_));
+        if let (true, Token::Nonterminal(name, None)) = (starts_alternative, &tokens[index]) {
+            let name = *name;
+            if name.bytes().all(|byte| byte.is_ascii_digit()) {
+                tokens[index] = lex_weight(name)?;
+            }
         }
     }
 
     return Ok(tokens);
 }
 
+// A span of a line as JSON, like
+// `{"kind":"nonterminal","text":"noun","col":1,"len":4}`, with a `case` field
+// if there's a case
+pub fn span_json(kind: &str, text: &str, case: Option<Case>, span: &Span) -> String {
+    let case_json = case.map(|case| format!(",\"case\":\"{}\"", case)).unwrap_or_default();
+    format!(
+        "{{\"kind\":{},\"text\":{}{},\"col\":{},\"len\":{}}}",
+        json::string(kind), json::string(text), case_json, span.col, span.len
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use std::iter::zip;
 
+    use itertools::Itertools;
+
     use super::*;
 
     #[test]
@@ -65,15 +296,15 @@ mod tests {
         ];
         // (result from the function, rest of the iterator)
         let answers = vec![
-            (Token::Terminal("alpha".to_string()), " bravo charlie"),
-            (Token::Terminal("delta".to_string()), ""),
-            (Token::Terminal("january".to_string()), "\"february\"\"march\"")
+            (Token::Terminal("alpha".into()), " bravo charlie"),
+            (Token::Terminal("delta".into()), ""),
+            (Token::Terminal("january".into()), "\"february\"\"march\"")
         ];
 
         for (line, (answer_token, answer_rest)) in zip(lines, answers) {
-            let mut chars = line.chars().peekable();
-            assert_eq!(lex_terminal(&mut chars).unwrap(), answer_token);
-            assert_eq!(chars.collect::<String>(), answer_rest);
+            let (token, taken) = lex_terminal(line, 1).unwrap();
+            assert_eq!(token, answer_token);
+            assert_eq!(&line[taken..], answer_rest);
         }
     }
 
@@ -85,11 +316,49 @@ mod tests {
         ];
 
         for line in lines {
-            let mut chars = line.chars().peekable();
-            chars.next();
+            assert!(matches!(lex_terminal(line, 1).unwrap_err(), CompileErrorType::UnmatchedQuote { col: 1, .. }));
+        }
+    }
 
-            assert_eq!(lex_terminal(&mut chars).unwrap_err(), CompileErrorType::UnmatchedQuote);
+    #[test]
+    fn locate_unmatched_quotes() {
+        // (line, column of the unclosed quote, characters taken after it)
+        let lines = [
+            (r#"a = "x" "y"#, 9, 1),
+            (r#"a = "x" | "y z"#, 11, 3),
+            (r#"a = "never closed at all"#, 5, 19),
+            (r#"a = "one" | "two" | "three"#, 21, 5),
+            (r#"a = "one" "two" "three"#, 17, 5),
+            (r#"a = "ünï" "cödé"#, 11, 4),
+        ];
+        for (line, col, consumed) in lines {
+            match lex_line(line).unwrap_err() {
+                CompileErrorType::UnmatchedQuote { col: found_col, consumed: found_consumed, quotes } => {
+                    assert_eq!((found_col, found_consumed, quotes), (col, consumed, line.matches('"').count()), "{}", line);
+                }
+                error => panic!("expected an unmatched quote in {}, found {:?}", line, error),
+            }
         }
+
+        assert_eq!(lex_line(r#"a = "one" | "two" | "three"#).unwrap_err().to_string(),
+            "Unmatched quote: the quote opened at column 21 was never closed; consumed 5 characters to end of line (the line has 5 quotes, so one before column 21 may be missing or doubled)");
+        assert_eq!(lex_line(r#"a = "x"#).unwrap_err().to_string(),
+            "Unmatched quote: the quote opened at column 5 was never closed; consumed 1 character to end of line");
+    }
+
+    #[test]
+    fn lex_raw_terminals() {
+        assert_eq!(lex_line("a = r#\"<p class=\"x\">\"# r\"\\n\" r##\"\"#\"## ray").unwrap(), vec![
+            Token::Nonterminal("a", None),
+            Token::Equals,
+            Token::Terminal("<p class=\"x\">".into()),
+            Token::Terminal("\\n".into()),
+            Token::Terminal("\"#".into()),
+            Token::Nonterminal("ray", None),
+        ]);
+        assert_eq!(lex_line("a = r#\"x\"## b").unwrap()[2..], [Token::Terminal("x".into()), Token::Nonterminal("#", None), Token::Nonterminal("b", None)]);
+        assert_eq!(lex_line("a = r#\"x\"").unwrap_err().to_string(), "Unterminated raw terminal (expected it to end with `\"#`)");
+        assert_eq!(lex_line("r#a = \"x\"").unwrap()[0], Token::Nonterminal("r#a", None));
     }
 
     #[test]
@@ -101,15 +370,16 @@ mod tests {
         ];
         // (result from the function, rest of the iterator)
         let answers = vec![
-            (Token::Nonterminal("alpha".to_string()), "bravo charlie"),
-            (Token::Nonterminal("delta".to_string()), ""),
-            (Token::Nonterminal("january".to_string()), "february march")
+            (Token::Nonterminal("alpha", None), "bravo charlie"),
+            (Token::Nonterminal("delta", None), ""),
+            (Token::Nonterminal("january", None), "february march")
         ];
 
+        // The whitespace after a nonterminal isn't taken
         for (line, (answer_token, answer_rest)) in zip(lines, answers) {
-            let mut chars = line.chars();
-            assert_eq!(lex_nonterminal(&mut chars).unwrap(), answer_token);
-            assert_eq!(chars.collect::<String>(), answer_rest);
+            let (token, taken) = lex_nonterminal(line).unwrap();
+            assert_eq!(token, answer_token);
+            assert_eq!(line[taken..].trim_start(), answer_rest);
         }
     }
 
@@ -121,20 +391,20 @@ mod tests {
         ];
         let answers = vec![
             vec![
-                Token::Nonterminal("personal.part".to_string()),
+                Token::Nonterminal("personal.part", None),
                 Token::Equals,
-                Token::Nonterminal("first.name".to_string()),
+                Token::Nonterminal("first.name", None),
                 Token::Or,
-                Token::Nonterminal("initial".to_string()),
-                Token::Terminal(".".to_string())
+                Token::Nonterminal("initial", None),
+                Token::Terminal(".".into())
             ],
             vec![
-                Token::Nonterminal("opt.apt.num".to_string()),
+                Token::Nonterminal("opt.apt.num", None),
                 Token::Equals,
-                Token::Terminal("Apt".to_string()),
-                Token::Nonterminal("apt.num".to_string()),
+                Token::Terminal("Apt".into()),
+                Token::Nonterminal("apt.num", None),
                 Token::Or,
-                Token::Terminal("".to_string())
+                Token::Terminal("".into())
             ]
         ];
 
@@ -142,4 +412,124 @@ mod tests {
             assert_eq!(lex_line(line).unwrap(), answer)
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn lex_case_suffixes() {
+        assert_eq!(lex_line("Name = name:title word:upper word:lower").unwrap(), vec![
+            Token::Nonterminal("Name", None),
+            Token::Equals,
+            Token::Nonterminal("name", Some(Case::Title)),
+            Token::Nonterminal("word", Some(Case::Upper)),
+            Token::Nonterminal("word", Some(Case::Lower))
+        ]);
+        assert_eq!(lex_line("a = word:shout").unwrap_err(), CompileErrorType::UnknownCase("shout".to_string()));
+        assert_eq!(lex_line("a = \"word\":upper").unwrap_err(), CompileErrorType::MisplacedCase);
+        assert_eq!(lex_line("a = word :upper").unwrap_err(), CompileErrorType::MisplacedCase);
+    }
+
+    #[test]
+    fn lex_weights() {
+        assert_eq!(lex_line("a = 5 \"hi\" | 12 b 3 | 4").unwrap(), vec![
+            Token::Nonterminal("a", None),
+            Token::Equals,
+            Token::Weight(5),
+            Token::Terminal("hi".into()),
+            Token::Or,
+            Token::Weight(12),
+            Token::Nonterminal("b", None),
+            Token::Nonterminal("3", None),
+            Token::Or,
+            Token::Nonterminal("4", None),
+        ]);
+        assert_eq!(lex_line("a = 0 \"x\"").unwrap_err(), CompileErrorType::BadWeight("0".to_string()));
+        assert_eq!(lex_line("a = 5:upper \"x\"").unwrap()[2], Token::Nonterminal("5", Some(Case::Upper)));
+
+        let (weight, span) = &lex_line_spanned("a = 25 b").unwrap()[2];
+        assert_eq!(span_json(weight.kind(), &weight.text(), weight.case(), span), "{\"kind\":\"weight\",\"text\":\"25\",\"col\":5,\"len\":2}");
+    }
+
+    #[test]
+    fn lex_brackets() {
+        assert_eq!(lex_line("a = b[\"x\" [2 c]]").unwrap(), vec![
+            Token::Nonterminal("a", None),
+            Token::Equals,
+            Token::Nonterminal("b", None),
+            Token::Open(Bracket::Optional),
+            Token::Terminal("x".into()),
+            Token::Open(Bracket::Optional),
+            Token::Weight(2),
+            Token::Nonterminal("c", None),
+            Token::Close(Bracket::Optional),
+            Token::Close(Bracket::Optional),
+        ]);
+        // A number alone in brackets is a rule
+        assert_eq!(lex_line("a = [7]").unwrap()[3], Token::Nonterminal("7", None));
+
+        assert_eq!(lex_line("a = [b [c]").unwrap_err().to_string(), "The `[` at column 5 is never closed (expected a `]` after what it holds)");
+        assert_eq!(lex_line("a = b] [c]").unwrap_err().to_string(), "The `]` at column 6 has no `[` before it to close");
+        assert_eq!(lex_line("a = \"[\" r\"]\"").unwrap().len(), 4);
+
+        assert_eq!(lex_line("a = b{\", \" b}").unwrap()[3..], [
+            Token::Open(Bracket::Repeat),
+            Token::Terminal(", ".into()),
+            Token::Nonterminal("b", None),
+            Token::Close(Bracket::Repeat),
+        ]);
+        assert_eq!(lex_line("a = {b [c} d]").unwrap_err().to_string(), "The `[` at column 8 is closed by the `}` at column 10 (expected `]`)");
+    }
+
+    #[test]
+    fn lex_groups() {
+        let terminal = |text: &'static str| Token::Terminal(text.into());
+        assert_eq!(lex_line("a = \"x\"((\"y\"|b)\"z\")\"w\"").unwrap()[2..], [
+            terminal("x"),
+            Token::Open(Bracket::Group),
+            Token::Open(Bracket::Group),
+            terminal("y"),
+            Token::Or,
+            Token::Nonterminal("b", None),
+            Token::Close(Bracket::Group),
+            terminal("z"),
+            Token::Close(Bracket::Group),
+            terminal("w"),
+        ]);
+        assert_eq!(lex_line("a = (2 r#\"(\"# | \")\")").unwrap()[3..5], [Token::Weight(2), terminal("(")]);
+
+        // A `(` straight after a dynamic symbol starts its arguments
+        assert_eq!(lex_line("a = @float(0, 1, 2)").unwrap()[2], Token::Dynamic("@float(0, 1, 2)"));
+        assert_eq!(lex_line("a = (@uuid) @uuid (\"x\")").unwrap()[2..6], [
+            Token::Open(Bracket::Group),
+            Token::Dynamic("@uuid"),
+            Token::Close(Bracket::Group),
+            Token::Dynamic("@uuid"),
+        ]);
+
+        assert_eq!(lex_line("a = ((\"x\") | \"y\"").unwrap_err().to_string(), "The `(` at column 5 is never closed (expected a `)` after what it holds)");
+        assert_eq!(lex_line("a = (\"x\"]").unwrap_err(), CompileErrorType::MismatchedBracket { open: Bracket::Group, col: 5, close: Bracket::Optional, close_col: 9 });
+    }
+
+    #[test]
+    fn lex_token_spans() {
+        let spans = |line: &str| lex_line_spanned(line).unwrap().into_iter().map(|(_, span)| (span.col, span.len)).collect_vec();
+
+        assert_eq!(spans("noun = \"idea\" |  word:upper"), vec![(1, 4), (6, 1), (8, 6), (15, 1), (18, 10)]);
+        assert_eq!(spans("  é = \"ü\"\"\" x"), vec![(3, 1), (5, 1), (7, 3), (10, 2), (13, 1)]);
+        // Quotes inside a nonterminal are part of it
+        assert_eq!(spans("a\"b\" c"), vec![(1, 4), (6, 1)]);
+        assert_eq!(spans("x = r#\"é\"# y"), vec![(1, 1), (3, 1), (5, 6), (12, 1)]);
+        assert_eq!(spans(""), vec![]);
+    }
+
+    #[test]
+    fn serialize_tokens() {
+        let tokens = lex_line_spanned("Name = name:title \"a\\nb\"").unwrap();
+        let serialized = tokens.iter().map(|(token, span)| span_json(token.kind(), &token.text(), token.case(), span)).collect_vec();
+
+        assert_eq!(serialized, vec![
+            "{\"kind\":\"nonterminal\",\"text\":\"Name\",\"col\":1,\"len\":4}",
+            "{\"kind\":\"equals\",\"text\":\"=\",\"col\":6,\"len\":1}",
+            "{\"kind\":\"nonterminal\",\"text\":\"name\",\"case\":\"title\",\"col\":8,\"len\":10}",
+            "{\"kind\":\"terminal\",\"text\":\"a\\nb\",\"col\":19,\"len\":6}",
+        ]);
+    }
+}
@@ -2,21 +2,50 @@
     This module parses BNF files
 */
 
-mod lexer;
+mod brackets;
+mod cache;
+mod deprecated;
+mod directive;
+mod dynamic;
+mod exclude;
+mod include;
+pub mod lexer;
+mod levels;
+mod params;
+mod ruleset;
+mod trivia;
 mod verifier;
+mod yaml;
 
-use std::collections::HashMap;
+use std::borrow::Cow;
 use std::fmt::Display;
 use std::fs::File;
 use std::io::BufRead;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use crate::analysis::rules_reaching;
+use crate::compiled::{self, CompiledErrorType};
 use crate::grammar::*;
 use crate::error_handling::*;
+use crate::timing::Timings;
 use itertools::Itertools;
 use lexer::*;
+use brackets::{alternatives_by_level, set_repeat_chance, Helpers, LexedAlternative};
+use deprecated::{deprecated_references, is_deprecation, parse_deprecation, undefined_deprecations};
+use directive::{expand_directive, is_directive};
+use dynamic::{is_use, parse_dynamic, parse_use};
+pub use cache::ParseCache;
+pub use directive::{DataError, DataErrorType};
+pub use exclude::{exclude_alternatives, parse_exclusion, Exclusion};
+pub use include::{default_cache_dir, RemoteIncludes};
+pub use levels::{denial_note, warning_name, Decision, Level, WarningLevels, ALL_WARNINGS};
+use include::{is_include, Includes};
+use ruleset::Ruleset;
+pub use params::substitute_params;
+pub use trivia::{parse_file_with_trivia, read_trivia};
+pub use verifier::verify_grammar;
 use verifier::verify_rules;
-use verifier::IntermediateRuleset;
+pub use yaml::Syntax;
 
 #[derive(Debug)]
 pub enum CompileErrorType {
@@ -26,8 +55,11 @@ pub enum CompileErrorType {
     UnexpectedEquals,
     // The user starts a rule line with something other than a nonterminal
     MissingNonterminal,
-    // There is an unclosed quote
-    UnmatchedQuote,
+    // The quote at this column of the line is never closed, after taking
+    // this many characters, on a line with this many quotes
+    UnmatchedQuote { col: usize, consumed: usize, quotes: usize },
+    // A raw terminal has no closing delimiter, which is this
+    UnterminatedRawTerminal(String),
     // An undefined token was used
     UndefinedNonterminal(String),
     // Somehow a full rewrite was parsed as a base alternative
@@ -38,6 +70,83 @@ pub enum CompileErrorType {
     UnexpectedBlankLine,
     // There was an issue with reading a file
     FileError(std::io::Error),
+    // A terminal has a `{` or `}` which isn't part of a parameter or escape
+    UnmatchedBrace,
+    // A terminal uses parameters which weren't given
+    MissingParameters(Vec<String>),
+    // A warning was found while its kind is treated as an error, because
+    // of a level given for every warning if implied
+    DeniedWarning { warning: CompileWarningType, implied: bool },
+    // A nonterminal has a case suffix other than `upper`, `lower`, or `title`
+    UnknownCase(String),
+    // A case suffix follows something other than a referenced nonterminal
+    MisplacedCase,
+    // A `;@` comment has nothing after it
+    EmptyAnnotation,
+    // A `;@` comment isn't directly above a rule or another `;@` comment
+    DetachedAnnotation,
+    // A compiled grammar couldn't be loaded
+    Compiled(CompiledErrorType),
+    // An excluded alternative is past the end of its rule
+    ExcludedOutOfRange { rule: String, index: usize, alternatives: usize },
+    // Exclusions removed every alternative of a rule
+    ExcludedEverything { rule: String, exclusions: Vec<Exclusion> },
+    // A directive like `@csv(...)` isn't written correctly, and why
+    MalformedDirective(String),
+    // A rule is defined by a directive blabber doesn't have
+    UnknownDirective(String),
+    // The data file a directive reads has a problem
+    DataFile(DataError),
+    // Grammars being merged both define a rule, and where the first did
+    ConflictingRule { rule: String, first: Box<Location> },
+    // The source has no rules, only blank lines and comments if anything
+    EmptyGrammar,
+    // A `;@ distinct` has something other than one count of tries
+    BadDistinct(String),
+    // A `;@ max-depth` has something other than one depth
+    BadMaxDepth(String),
+    // A `;@ repeat` has something other than one chance between 0 and 1
+    BadRepeat(String),
+    // A value of a `;@ tag` isn't a tag, or a tag with the indices it's on
+    BadTag(String),
+    // A `;@ tag` is on an index past the end of its rule
+    TaggedOutOfRange { rule: String, tag: String, index: usize, alternatives: usize },
+    // --only-tags or --skip-tags names a tag no alternative has, and the
+    // tags alternatives do have
+    UnknownTag { tag: String, known: Vec<String> },
+    // A YAML grammar has something other than what was expected at a path
+    // like `rules.noun[1][0]`, and what it found instead
+    YamlSchema { path: String, expected: &'static str, found: &'static str },
+    // A YAML grammar can't be read, and why
+    Yaml(String),
+    // An `%include` isn't followed by a quoted path or URL, but this
+    MalformedInclude(String),
+    // The path or URL an `%include` names couldn't be read, and why
+    Include { target: String, reason: String },
+    // A file includes one it's being included from, through these files
+    IncludeCycle(Vec<String>),
+    // Includes are nested deeper than this
+    IncludesTooDeep(usize),
+    // A `%deprecated` isn't followed by a rule name and maybe a quoted
+    // message, but this
+    MalformedDeprecation(String),
+    // A `%deprecated` names a rule which isn't defined
+    UndefinedDeprecation(String),
+    // A dynamic symbol like `@date(...)` isn't written correctly, and why
+    MalformedDynamic(String),
+    // A `%use` names something other than a feature blabber has
+    UnknownFeature(String),
+    // A dynamic symbol, like `@date`, is used in a file without
+    // `%use dynamics`
+    DynamicsNotEnabled(String),
+    // An alternative's weight is 0 or too large to count
+    BadWeight(String),
+    // The bracket at this column is never closed
+    UnclosedBracket { bracket: Bracket, col: usize },
+    // The closing bracket at this column has nothing to close
+    UnopenedBracket { bracket: Bracket, col: usize },
+    // The bracket at col is closed by a different kind at close_col
+    MismatchedBracket { open: Bracket, col: usize, close: Bracket, close_col: usize },
 }
 
 impl ErrorType for CompileErrorType {}
@@ -59,11 +168,59 @@ impl Display for CompileErrorType {
             CompileErrorType::MissingEquals => write!(f, "Expected `=` after nonterminal"),
             CompileErrorType::UnexpectedEquals => write!(f, "Unexpected `=` encountered"),
             CompileErrorType::MissingNonterminal => write!(f, "Tried to define something other than a nonterminal"),
-            CompileErrorType::UnmatchedQuote => write!(f, "Unmatched quotes"),
+            CompileErrorType::UnmatchedQuote { col, consumed, quotes } => {
+                let characters = if *consumed == 1 { "character" } else { "characters" };
+                write!(f, "Unmatched quote: the quote opened at column {} was never closed; consumed {} {} to end of line", col, consumed, characters)?;
+                if *quotes > 1 {
+                    write!(f, " (the line has {} quotes, so one before column {} may be missing or doubled)", quotes, col)?;
+                }
+                Ok(())
+            }
+            CompileErrorType::UnterminatedRawTerminal(closing) => write!(f, "Unterminated raw terminal (expected it to end with `{}`)", closing),
             CompileErrorType::UndefinedNonterminal(nonterminal) => write!(f, "Could not find definition for `{}`", nonterminal),
             CompileErrorType::UnsplitRewrite => write!(f, "Rewrite was not fully split (this is a problem with blabber, not the grammar)"),
             CompileErrorType::UnexpectedBlankLine => write!(f, "Blank line encountered in rule parser (this is a problem with blabber, not the grammar)"),
             CompileErrorType::FileError(e) => write!(f, "File error: {}", e),
+            CompileErrorType::UnmatchedBrace => write!(f, "Unmatched brace in parameter (write `{{{{` or `}}}}` for a literal brace)"),
+            CompileErrorType::MissingParameters(names) => write!(f, "Missing parameters: {}", names.iter().map(|name| format!("`{}`", name)).join(", ")),
+            CompileErrorType::DeniedWarning { warning, implied } => write!(f, "Denied warning: {}\n  = note: {}", warning, denial_note(warning.code(), *implied)),
+            CompileErrorType::UnknownCase(suffix) => write!(f, "Unknown case `:{}` (expected `:upper`, `:lower`, or `:title`)", suffix),
+            CompileErrorType::MisplacedCase => write!(f, "Case suffixes can only directly follow a referenced nonterminal"),
+            CompileErrorType::EmptyAnnotation => write!(f, "Expected an annotation key after `;@`"),
+            CompileErrorType::DetachedAnnotation => write!(f, "Annotations must be directly above the rule they're for"),
+            CompileErrorType::Compiled(error) => write!(f, "{}", error),
+            CompileErrorType::ExcludedOutOfRange { rule, index, alternatives } => write!(f, "`{}` has no alternative {} to exclude (it has {}, counting from 0)", rule, index, alternatives),
+            CompileErrorType::ExcludedEverything { rule, exclusions } => write!(f, "`{}` has no alternatives left after {}", rule, exclusions.iter().join(", ")),
+            CompileErrorType::MalformedDirective(reason) => write!(f, "{}", reason),
+            CompileErrorType::UnknownDirective(name) => write!(f, "Unknown directive `@{}` (known directives are {})", name,
+                DIRECTIVES.iter().map(|name| format!("`@{}`", name)).join(", ")),
+            CompileErrorType::DataFile(error) => write!(f, "{}", error),
+            CompileErrorType::EmptyGrammar => write!(f, "The grammar has no rules (a rule looks like `sentence = \"hello \" name`)"),
+            CompileErrorType::BadDistinct(values) => write!(f, "Expected `;@ distinct` to be followed by nothing or a number of tries of at least 1, found `{}`", values),
+            CompileErrorType::BadMaxDepth(values) => write!(f, "Expected `;@ max-depth` to be followed by how many expansions of the rule can be inside each other, at least 1, found `{}`", values),
+            CompileErrorType::BadRepeat(values) => write!(f, "Expected `;@ repeat` to be followed by the chance each repetition in braces is followed by another, more than 0 and less than 1, found `{}`", values),
+            CompileErrorType::BadTag(value) => write!(f, "Expected `;@ tag` to be followed by tags like `formal`, or `formal=0,2` for only some alternatives, found `{}`", value),
+            CompileErrorType::TaggedOutOfRange { rule, tag, index, alternatives } => write!(f, "`{}` has no alternative {} to tag `{}` (it has {}, counting from 0)", rule, index, tag, alternatives),
+            CompileErrorType::UnknownTag { tag, known } if known.is_empty() => write!(f, "No alternative is tagged `{}` (the grammar has no tags)", tag),
+            CompileErrorType::UnknownTag { tag, known } => write!(f, "No alternative is tagged `{}` (the tags are {})", tag, known.iter().map(|tag| format!("`{}`", tag)).join(", ")),
+            CompileErrorType::YamlSchema { path, expected, found } if path.is_empty() => write!(f, "Expected {} at the top level, found {}", expected, found),
+            CompileErrorType::YamlSchema { path, expected, found } => write!(f, "`{}`: expected {}, found {}", path, expected, found),
+            CompileErrorType::Yaml(reason) => write!(f, "{}", reason),
+            CompileErrorType::MalformedInclude(found) => write!(f, "Expected `%include` to be followed by a quoted path or URL, found `{}`", found),
+            CompileErrorType::Include { target, reason } => write!(f, "Couldn't include `{}`: {}", target, reason),
+            CompileErrorType::IncludeCycle(files) => write!(f, "Includes form a cycle: {}", files.join(" → ")),
+            CompileErrorType::IncludesTooDeep(depth) => write!(f, "Includes are nested more than {} deep (raise it with --max-include-depth)", depth),
+            CompileErrorType::MalformedDeprecation(found) => write!(f, "Expected `%deprecated` to be followed by a rule name and optionally a quoted message, found `{}`", found),
+            CompileErrorType::UndefinedDeprecation(rule) => write!(f, "Could not find definition for deprecated `{}`", rule),
+            CompileErrorType::MalformedDynamic(reason) => write!(f, "{}", reason),
+            CompileErrorType::UnknownFeature(feature) => write!(f, "Unknown feature `{}` after `%use` (the only feature is `dynamics`)", feature),
+            CompileErrorType::DynamicsNotEnabled(name) => write!(f, "`@{}` is a dynamic symbol, which needs a `%use dynamics` line in the file using it", name),
+            CompileErrorType::BadWeight(weight) => write!(f, "Expected an alternative's weight to be a whole number from 1 to {}, found `{}` (leave out alternatives which should never be picked)", u32::MAX, weight),
+            CompileErrorType::UnclosedBracket { bracket, col } => write!(f, "The `{}` at column {} is never closed (expected a `{}` after what it holds)", bracket.open(), col, bracket.close()),
+            CompileErrorType::UnopenedBracket { bracket, col } => write!(f, "The `{}` at column {} has no `{}` before it to close", bracket.close(), col, bracket.open()),
+            CompileErrorType::MismatchedBracket { open, col, close, close_col } => write!(f, "The `{}` at column {} is closed by the `{}` at column {} (expected `{}`)",
+                open.open(), col, close.close(), close_col, open.close()),
+            CompileErrorType::ConflictingRule { rule, first } => write!(f, "`{}` is already defined at {} (pass --on-conflict to choose how to merge them)", rule, first),
         }
     }
 }
@@ -71,12 +228,96 @@ impl Display for CompileErrorType {
 pub type CompileError = Error<CompileErrorType>;
 pub type CompileErrors = Errors<CompileErrorType>;
 
-fn io_error(error: std::io::Error, file: PathBuf) -> CompileError {
+// Where a stray `|` was found in a rewrite
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PipePosition {
+    Leading,
+    Trailing,
+    Doubled,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CompileWarningType {
+    // A stray `|` made an empty alternative, which is usually a typo
+    EmptyAlternative(PipePosition),
+    // An empty terminal sits among other symbols in this alternative, where
+    // it does nothing and is usually a typo or left over from templating
+    EmptyTerminal(String),
+    // A `;@` comment has a key nothing uses, which may be a typo
+    UnknownAnnotation(String),
+    // A data file had entries weighing 0, on these lines, which were left out
+    SkippedZeroWeights { path: PathBuf, lines: Vec<u64> },
+    // A rule deprecated by `%deprecated`, with this message, is used
+    Deprecated { rule: String, message: String },
+    // A terminal isn't in Unicode normalization form NFC, so it may not
+    // match the same text from elsewhere written composed
+    UnnormalizedTerminal(String),
+    // A rule with a `;@ max-depth` can't expand inside itself, so the cap
+    // never applies
+    UselessMaxDepth(String),
+    // A rule can't be expanded when generating from the start symbol, so it
+    // may be left over or missing a reference
+    UnreachableRule(String),
+}
+
+impl ErrorType for CompileWarningType {}
+
+// The code of every kind of warning
+pub const WARNING_CODES: [&str; 8] = ["empty-alternative", "empty-terminal", "unknown-annotation", "skipped-zero-weights", "deprecated",
+    "unnormalized-terminal", "useless-max-depth", "unreachable-rule"];
+
+impl CompileWarningType {
+    // A name for the kind of warning, for tools
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompileWarningType::EmptyAlternative(_) => "empty-alternative",
+            CompileWarningType::EmptyTerminal(_) => "empty-terminal",
+            CompileWarningType::UnknownAnnotation(_) => "unknown-annotation",
+            CompileWarningType::SkippedZeroWeights { .. } => "skipped-zero-weights",
+            CompileWarningType::Deprecated { .. } => "deprecated",
+            CompileWarningType::UnnormalizedTerminal(_) => "unnormalized-terminal",
+            CompileWarningType::UselessMaxDepth(_) => "useless-max-depth",
+            CompileWarningType::UnreachableRule(_) => "unreachable-rule",
+        }
+    }
+}
+
+impl Display for CompileWarningType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileWarningType::EmptyAlternative(position) => {
+                let description = match position {
+                    PipePosition::Leading => "A leading `|`",
+                    PipePosition::Trailing => "A trailing `|`",
+                    PipePosition::Doubled => "A doubled `||`",
+                };
+                write!(f, "{} creates an empty alternative (write `\"\"` if it should be empty)", description)
+            }
+            CompileWarningType::EmptyTerminal(alternative) => write!(f, "The alternative `{}` has an empty terminal, which adds nothing (write `\"\"` alone for an empty alternative)", alternative),
+            CompileWarningType::UnknownAnnotation(key) => write!(f, "Unknown annotation `{}` (known annotations are {})", key,
+                ANNOTATION_KEYS.iter().map(|key| format!("`{}`", key)).join(", ")),
+            CompileWarningType::SkippedZeroWeights { path, lines } => {
+                let shown = lines.iter().take(5).join(", ");
+                let more = if lines.len() > 5 { format!(" and {} more", lines.len() - 5) } else { String::new() };
+                let (entries, line) = if lines.len() == 1 { ("entry", "line") } else { ("entries", "lines") };
+                write!(f, "Skipped {} {} weighing 0 in {} ({} {}{})", lines.len(), entries, path.display(), line, shown, more)
+            }
+            CompileWarningType::Deprecated { rule, message } if message.is_empty() => write!(f, "`{}` is deprecated", rule),
+            CompileWarningType::Deprecated { rule, message } => write!(f, "`{}` is deprecated: {}", rule, message),
+            CompileWarningType::UnnormalizedTerminal(text) => write!(f, "The terminal `{}` isn't in Unicode normalization form NFC (`--normalize nfc` makes sentences consistent)",
+                Symbol::Terminal(text.as_str().into())),
+            CompileWarningType::UselessMaxDepth(rule) => write!(f, "`{}` has a `;@ max-depth`, but it can't expand inside itself, so the cap does nothing", rule),
+            CompileWarningType::UnreachableRule(rule) => write!(f, "`{}` can't be reached from the start symbol, so it's never used", rule),
+        }
+    }
+}
+
+pub type CompileWarning = Warning<CompileWarningType>;
+pub type CompileWarnings = Warnings<CompileWarningType>;
+
+fn io_error(error: std::io::Error, source: SourceName) -> CompileError {
     CompileError {
-        location: Location {
-            file,
-            line: 0
-        },
+        location: Location::whole(source),
         error: CompileErrorType::FileError(error)
     }
 }
@@ -85,159 +326,940 @@ pub type Result<T> = std::result::Result<T, CompileErrorType>;
 pub type LineResult<T> = std::result::Result<T, CompileError>;
 pub type FileResult<T> = std::result::Result<T, CompileErrors>;
 
-#[derive(PartialEq, Debug)]
-struct Rule {
-    symbol: String,
-    rewrite: Rewrite,
-    location: Location
+// Settings for how grammars are parsed
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    // Whether to check that every referenced nonterminal is defined
+    pub verify: bool,
+    // Which kinds of warning are left out or turned into errors
+    pub warning_levels: WarningLevels,
+    // The syntax the grammar is written in, or None to go by the file's
+    // extension, with BNF for anything that isn't a file
+    pub syntax: Option<Syntax>,
+    // Whether `%include`s of URLs are fetched, and where they're kept
+    pub remote: RemoteIncludes,
+    // How deeply `%include`s can be nested
+    pub max_include_depth: usize,
+    // Whether to warn about rules the start symbol can't reach. Only
+    // `blabber check` does, since generating can start from any rule.
+    pub warn_unreachable: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            verify: true,
+            warning_levels: WarningLevels::default(),
+            syntax: None,
+            remote: RemoteIncludes::Denied,
+            max_include_depth: DEFAULT_MAX_INCLUDE_DEPTH,
+            warn_unreachable: false
+        }
+    }
+}
+
+// How deeply `%include`s can be nested unless told otherwise
+pub const DEFAULT_MAX_INCLUDE_DEPTH: usize = 16;
+
+// The annotation keys which mean something to blabber
+pub const ANNOTATION_KEYS: [&str; 5] = ["distinct", "max-depth", "repeat", "tag", "weight"];
+
+// The directives a rule can be defined with instead of a rewrite
+pub const DIRECTIVES: [&str; 2] = ["csv", "weighted_file"];
+
+// A rule as it's parsed from a line, with its text still in the line where
+// it can be, until it's added to the grammar
+#[derive(PartialEq, Debug, Clone)]
+struct Rule<'a> {
+    symbol: Cow<'a, str>,
+    rewrite: ParsedRewrite<'a>,
+    location: Location,
+    annotations: Annotations,
+    // The alternatives' weights, if they aren't all picked equally often
+    weights: Option<Vec<f64>>,
+    // The rules made for the brackets in its rewrite
+    helpers: Vec<Rule<'a>>,
+}
+
+impl Rule<'_> {
+    // The rule with its own copy of its text, for keeping after the line
+    fn into_owned(self) -> Rule<'static> {
+        Rule {
+            symbol: Cow::Owned(self.symbol.into_owned()),
+            rewrite: self.rewrite.into_owned(),
+            location: self.location,
+            annotations: self.annotations,
+            weights: self.weights,
+            helpers: self.helpers.into_iter().map(Rule::into_owned).collect(),
+        }
+    }
+}
+
+// A symbol of a rule being parsed. Terminals and nonterminals borrow their
+// text from the line, unless a terminal had escapes to replace.
+#[derive(PartialEq, Debug, Clone)]
+enum ParsedSymbol<'a> {
+    Terminal(Cow<'a, str>),
+    Nonterminal(Cow<'a, str>, Option<Case>),
+    Dynamic(Box<Dynamic>),
+}
+
+impl ParsedSymbol<'_> {
+    fn into_owned(self) -> ParsedSymbol<'static> {
+        match self {
+            ParsedSymbol::Terminal(text) => ParsedSymbol::Terminal(Cow::Owned(text.into_owned())),
+            ParsedSymbol::Nonterminal(name, case) => ParsedSymbol::Nonterminal(Cow::Owned(name.into_owned()), case),
+            ParsedSymbol::Dynamic(dynamic) => ParsedSymbol::Dynamic(dynamic),
+        }
+    }
+}
+
+impl From<Symbol> for ParsedSymbol<'_> {
+    fn from(symbol: Symbol) -> Self {
+        match symbol {
+            Symbol::Terminal(text) => ParsedSymbol::Terminal(Cow::Owned(text.into())),
+            Symbol::Nonterminal(name, case) => ParsedSymbol::Nonterminal(Cow::Owned(name.to_string()), case),
+            Symbol::Dynamic(dynamic) => ParsedSymbol::Dynamic(dynamic),
+        }
+    }
+}
+
+impl<'s> From<&'s ParsedSymbol<'_>> for SymbolRef<'s> {
+    fn from(symbol: &'s ParsedSymbol<'_>) -> Self {
+        match symbol {
+            ParsedSymbol::Terminal(text) => SymbolRef::Terminal(text),
+            ParsedSymbol::Nonterminal(name, case) => SymbolRef::Nonterminal(name, *case),
+            ParsedSymbol::Dynamic(dynamic) => SymbolRef::Dynamic(dynamic),
+        }
+    }
+}
+
+// The alternatives of a rule being parsed, as every symbol of them in one
+// list and where each alternative ends in it, so a rule takes the same few
+// lists however many alternatives it has
+#[derive(PartialEq, Debug, Clone, Default)]
+struct ParsedRewrite<'a> {
+    symbols: Vec<ParsedSymbol<'a>>,
+    ends: Vec<usize>,
+}
+
+impl<'a> ParsedRewrite<'a> {
+    fn with_capacity(alternatives: usize, symbols: usize) -> Self {
+        ParsedRewrite { symbols: Vec::with_capacity(symbols), ends: Vec::with_capacity(alternatives) }
+    }
+
+    fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    // Adds a symbol to the alternative after the last one ended
+    fn push(&mut self, symbol: ParsedSymbol<'a>) {
+        self.symbols.push(symbol);
+    }
+
+    // Ends the alternative the symbols since the last one make
+    fn end_alternative(&mut self) {
+        self.ends.push(self.symbols.len());
+    }
+
+    fn alternatives(&self) -> impl ExactSizeIterator<Item = &[ParsedSymbol<'a>]> + Clone {
+        (0..self.ends.len()).map(|index| {
+            let start = if index == 0 { 0 } else { self.ends[index - 1] };
+            &self.symbols[start..self.ends[index]]
+        })
+    }
+
+    fn into_owned(self) -> ParsedRewrite<'static> {
+        ParsedRewrite { symbols: self.symbols.into_iter().map(ParsedSymbol::into_owned).collect(), ends: self.ends }
+    }
+}
+
+// Rewrites built in code, like the ones read from a data file
+impl From<Rewrite> for ParsedRewrite<'_> {
+    fn from(rewrite: Rewrite) -> Self {
+        let mut parsed = ParsedRewrite::with_capacity(rewrite.len(), rewrite.iter().map(Vec::len).sum());
+        for alternative in rewrite {
+            parsed.symbols.extend(alternative.into_iter().map(ParsedSymbol::from));
+            parsed.end_alternative();
+        }
+        return parsed;
+    }
 }
 
 fn parse_alternative(tokens: &[Token]) -> Result<Alternative> {
     tokens.iter().map(|t| match t {
         Token::Equals => Err(CompileErrorType::UnexpectedEquals),
         Token::Or => Err(CompileErrorType::UnsplitRewrite),
-        Token::Nonterminal(s) => Ok(Symbol::Nonterminal(s.clone())),
-        Token::Terminal(s) => Ok(Symbol::Terminal(s.clone()))
+        Token::Weight(weight) => Err(CompileErrorType::BadWeight(weight.to_string())),
+        Token::Open(_) | Token::Close(_) => Err(CompileErrorType::UnsplitRewrite),
+        Token::Nonterminal(s, case) => Ok(Symbol::Nonterminal((*s).into(), *case)),
+        Token::Terminal(s) => Ok(Symbol::Terminal(s.as_ref().into())),
+        Token::Dynamic(text) => Ok(Symbol::Dynamic(Box::new(parse_dynamic(text)?))),
     }).collect()
 }
 
-fn parse_rewrite(tokens: &[Token]) -> Result<Rewrite> {
-    tokens.split(|t| *t == Token::Or).map(parse_alternative).collect()
+// Parses a symbol, keeping the text the token borrows from the line
+fn parse_symbol(token: Token) -> Result<ParsedSymbol> {
+    match token {
+        Token::Equals => Err(CompileErrorType::UnexpectedEquals),
+        Token::Or => Err(CompileErrorType::UnsplitRewrite),
+        // Weights and brackets are parsed along with the rewrite
+        Token::Weight(weight) => Err(CompileErrorType::BadWeight(weight.to_string())),
+        Token::Open(_) | Token::Close(_) => Err(CompileErrorType::UnsplitRewrite),
+        Token::Nonterminal(s, case) => Ok(ParsedSymbol::Nonterminal(Cow::Borrowed(s), case)),
+        Token::Terminal(s) => Ok(ParsedSymbol::Terminal(s)),
+        Token::Dynamic(text) => Ok(ParsedSymbol::Dynamic(Box::new(parse_dynamic(text)?))),
+    }
+}
+
+// A rewrite being parsed, either the rule's own or what a pair of brackets
+// holds
+struct ParsingRewrite<'a> {
+    // Where the rule made for its brackets goes in the helpers, and their
+    // kind, unless it's the rule's own rewrite
+    brackets: Option<(usize, Bracket)>,
+    // The alternatives parsed, and the symbols of the one being parsed
+    rewrite: ParsedRewrite<'a>,
+    // The weights of the alternatives parsed, once one of them has one
+    weights: Option<Vec<f64>>,
+    // The weight of the alternative being parsed
+    weight: f64,
+}
+
+impl<'a> ParsingRewrite<'a> {
+    // A level with room for the symbols of its alternatives, as
+    // alternatives_by_level found them
+    fn new(brackets: Option<(usize, Bracket)>, alternatives: &[LexedAlternative]) -> Self {
+        let symbols = alternatives.iter().map(|alternative| alternative.symbols).sum();
+        ParsingRewrite {
+            brackets,
+            rewrite: ParsedRewrite::with_capacity(alternatives.len(), symbols),
+            weights: None,
+            weight: 1.0,
+        }
+    }
+
+    // Ends the alternative being parsed
+    fn next_alternative(&mut self) {
+        self.rewrite.end_alternative();
+        if let Some(weights) = &mut self.weights {
+            weights.push(std::mem::replace(&mut self.weight, 1.0));
+        }
+    }
+
+    // Weighs the alternative being parsed, with the ones before it weighing
+    // 1
+    fn weigh(&mut self, weight: u32) {
+        let parsed = self.rewrite.len();
+        self.weights.get_or_insert_with(|| vec![1.0; parsed]);
+        self.weight = weight as f64;
+    }
+
+    fn finish(mut self) -> (ParsedRewrite<'a>, Option<Vec<f64>>) {
+        self.rewrite.end_alternative();
+        if let Some(weights) = &mut self.weights {
+            weights.push(self.weight);
+        }
+        return (self.rewrite, self.weights);
+    }
+}
+
+// Moves the tokens into the rewrite they make, with their text still
+// borrowed from the line, into one list of symbols sized for all of them
+// from levels, the alternatives_by_level of the tokens. Returns the
+// alternatives' weights too if any has one, with the others weighing 1.
+// What brackets hold is parsed the same way into a rule kept in helpers,
+// keeping the brackets open on a stack rather than recursing, so they can
+// nest as deep as they like.
+fn parse_rewrite<'a>(tokens: std::vec::IntoIter<Token<'a>>, levels: &[Vec<LexedAlternative>], helpers: &mut Helpers<'_, 'a>) -> Result<(ParsedRewrite<'a>, Option<Vec<f64>>)> {
+    let mut own = ParsingRewrite::new(None, &levels[0]);
+    // The brackets open inside it, innermost last. Levels are numbered in
+    // the order their brackets open.
+    let mut open: Vec<ParsingRewrite<'a>> = Vec::new();
+    let mut opened = 0;
+    for token in tokens {
+        let level = open.last_mut().unwrap_or(&mut own);
+        match token {
+            // The lexer only makes weights at the start of alternatives
+            Token::Weight(weight) => level.weigh(weight),
+            Token::Or => level.next_alternative(),
+            Token::Open(bracket) => {
+                opened += 1;
+                open.push(ParsingRewrite::new(Some((helpers.open(), bracket)), &levels[opened]));
+            }
+            Token::Close(_) => {
+                let closed = open.pop().expect("the lexer checks brackets are balanced");
+                let (index, bracket) = closed.brackets.expect("the lexer checks brackets are balanced");
+                let (rewrite, weights) = closed.finish();
+                let level = open.last_mut().unwrap_or(&mut own);
+                level.rewrite.push(helpers.close(index, bracket, rewrite, weights));
+            }
+            token => level.rewrite.push(parse_symbol(token)?),
+        }
+    }
+    return Ok(own.finish());
+}
+
+// An alternative as it's written, for warnings about it
+fn describe_alternative(tokens: &[Token]) -> String {
+    let mut text = String::new();
+    for (index, token) in tokens.iter().enumerate() {
+        if index > 0 && !matches!(tokens[index - 1], Token::Open(_)) && !matches!(token, Token::Close(_)) {
+            text.push(' ');
+        }
+        match parse_alternative(std::slice::from_ref(token)) {
+            Ok(symbols) => text += &symbols.iter().join(" "),
+            Err(_) => text += &token.text(),
+        }
+    }
+    return text;
+}
+
+// Finds the `|`s which split off empty alternatives, and the alternatives
+// with an empty terminal among other symbols. A rewrite with no `|` at all
+// can be empty on purpose, as can an alternative of just `""`.
+fn rewrite_warnings(tokens: &[Token], levels: &[Vec<LexedAlternative>]) -> Vec<CompileWarningType> {
+    let mut warnings = alternative_warnings(tokens, levels);
+    for token in tokens {
+        if let Token::Terminal(text) = token {
+            // ASCII is always in NFC, and checking for it is much quicker
+            if !text.is_ascii() && !unicode_normalization::is_nfc(text) {
+                warnings.push(CompileWarningType::UnnormalizedTerminal(text.to_string()));
+            }
+        }
+    }
+    return warnings;
 }
 
-fn parse_line(tokens: &[Token], location: Location) -> Result<Rule> {
+fn parse_line(tokens: Vec<Token>, location: Location) -> Result<Rule> {
+    let levels = alternatives_by_level(tokens.get(2..).unwrap_or_default());
+    return parse_line_by_levels(tokens, &levels, location);
+}
+
+// Parses a line like parse_line, given the alternatives_by_level of its
+// rewrite's tokens, so finding them for warnings needn't be done twice
+fn parse_line_by_levels<'a>(tokens: Vec<Token<'a>>, levels: &[Vec<LexedAlternative>], location: Location) -> Result<Rule<'a>> {
+    let mut tokens = tokens.into_iter();
     // Try to get the token the rule is for. The match returns a result which
     // is then unwrapped with the ? operator
-    let symbol = match tokens.get(0) {
-        Some(Token::Nonterminal(s)) => Ok(s.clone()),
+    let symbol = match tokens.next() {
+        Some(Token::Nonterminal(s, None)) => Ok(s),
+        Some(Token::Nonterminal(_, Some(_))) => Err(CompileErrorType::MisplacedCase),
         Some(_) => Err(CompileErrorType::MissingNonterminal),
         None => Err(CompileErrorType::UnexpectedBlankLine)
     }?;
 
-    if tokens.get(1) != Some(&Token::Equals) {
+    if tokens.next() != Some(Token::Equals) {
         return Err(CompileErrorType::MissingEquals)
     }
 
-    let rewrite = parse_rewrite(&tokens[2..])?;
+    let mut helpers = Helpers::new(symbol, &location);
+    let (rewrite, weights) = parse_rewrite(tokens, levels, &mut helpers)?;
+    let helpers = helpers.into_rules();
 
     return Ok(Rule {
-        symbol,
+        symbol: Cow::Borrowed(symbol),
         rewrite,
-        location
+        location,
+        annotations: Annotations::new(),
+        weights,
+        helpers
     });
 }
 
-fn parse_lex_line(line: &str, location: Location) -> LineResult<Rule> {
-    lexer::lex_line(line)
-        .and_then(|lexed_line| parse_line(&lexed_line, location.clone()))
-        .map_err(|error| CompileError { location: location, error })
+// Parses one rule line. Lines are parsed through this, so a cache can stand
+// in for lexing lines it has seen before.
+type LineParser<'a> = dyn for<'l> FnMut(&'l str, Location) -> LineResult<(Rule<'l>, CompileWarnings)> + 'a;
+
+fn parse_lex_line(line: &str, location: Location) -> LineResult<(Rule<'_>, CompileWarnings)> {
+    // A rule defined by a directive takes its alternatives from a data file
+    if let Some((head, rewrite)) = line.split_once('=').filter(|(_, rewrite)| is_directive(rewrite)) {
+        let error_at = |error| CompileError { location: location.clone(), error };
+        let head = &line[..head.len() + 1];
+        let tokens = lexer::lex_line(head).map_err(error_at)?;
+        let rule = parse_line(tokens, location.clone()).map_err(error_at)?;
+        let (rewrite, weights, warnings) = expand_directive(rewrite, &location.source).map_err(error_at)?;
+        let warnings = warnings.into_iter().map(|warning| CompileWarning { location: location.clone(), warning }).collect();
+        return Ok((Rule { rewrite: rewrite.into(), weights, ..rule }, warnings));
+    }
+
+    let tokens = lexer::lex_line(line).map_err(|error| CompileError { location: location.clone(), error })?;
+    // The rewrite starts after the symbol and equals, which parse_line
+    // checks, and takes the tokens
+    let rewrite = tokens.get(2..).unwrap_or_default();
+    let levels = alternatives_by_level(rewrite);
+    let warnings = rewrite_warnings(rewrite, &levels)
+        .into_iter()
+        .map(|warning| CompileWarning { location: location.clone(), warning })
+        .collect();
+    let rule = parse_line_by_levels(tokens, &levels, location.clone()).map_err(|error| CompileError { location: location.clone(), error })?;
+
+    return Ok((rule, warnings));
+}
+
+// The warnings about the alternatives of a rewrite, and of what each pair of
+// brackets in it holds, in the order they're written, given their levels
+// from alternatives_by_level
+fn alternative_warnings(tokens: &[Token], levels: &[Vec<LexedAlternative>]) -> Vec<CompileWarningType> {
+    // Each warning with where what it's about starts
+    let mut warnings = Vec::new();
+    for alternatives in levels {
+        let last = alternatives.len() - 1;
+        if last > 0 {
+            warnings.extend(alternatives.iter()
+                .enumerate()
+                .filter(|(_, alternative)| alternative.symbols == 0)
+                .map(|(index, _)| (alternatives[0].tokens.start, CompileWarningType::EmptyAlternative(match index {
+                    0 => PipePosition::Leading,
+                    i if i == last => PipePosition::Trailing,
+                    _ => PipePosition::Doubled,
+                }))));
+        }
+        for alternative in alternatives.iter().filter(|alternative| alternative.symbols > 1 && alternative.empty_terminal) {
+            warnings.push((alternative.tokens.start, CompileWarningType::EmptyTerminal(describe_alternative(&tokens[alternative.tokens.clone()]))));
+        }
+    }
+    warnings.sort_by_key(|(start, _)| *start);
+    return warnings.into_iter().map(|(_, warning)| warning).collect();
 }
 
-fn is_rule_line(line: &String) -> bool {
-    !line.is_empty() && !line.starts_with(';')
+// Lines of only whitespace are blank too
+fn is_rule_line(line: &str) -> bool {
+    !line.trim().is_empty() && !line.starts_with(';')
 }
 
-// Returns an iterator over the lines of a file, with the io errors wrapped
-// in CompileError and enumerated
-fn file_line_nums<'a>(file: File, path: &'a PathBuf) -> impl Iterator<Item = (usize, LineResult<String>)> + 'a {
-    std::io::BufReader::new(file)
-        .lines()
-        .map(move |line| line.map_err(|e| io_error(e, path.clone())))
-        .enumerate()
-        .filter(|(_, line)| line.as_ref().is_ok_and(is_rule_line) || line.is_err())
-        .map(|(num, line)| (num + 1, line))
+// Numbered lines of grammar text, lent out one at a time so every line of a
+// file can be read into the same buffer
+trait Lines {
+    fn next_line(&mut self) -> Option<(usize, LineResult<&str>)>;
 }
 
-// Generates a rule hashmap from a vector of rules
-fn ruleset_from_rules(rules: Vec<Rule>) -> FileResult<HashMap<String, Rewrite>> {
-    let rule_count = rules.len();
+struct ReaderLines<'a, R> {
+    reader: R,
+    source: &'a SourceName,
+    line: String,
+    num: usize,
+}
+
+// The lines of a reader, with the io errors wrapped in CompileError and
+// numbered from 1
+fn reader_line_nums<R: BufRead>(reader: R, source: &SourceName) -> ReaderLines<'_, R> {
+    ReaderLines { reader, source, line: String::new(), num: 0 }
+}
 
-    // Construct test hashmap
-    let mut test_ruleset = IntermediateRuleset::with_capacity(rule_count);
-    for rule in rules {
-        test_ruleset.insert(rule.symbol, (rule.rewrite, rule.location));
+impl<R: BufRead> Lines for ReaderLines<'_, R> {
+    fn next_line(&mut self) -> Option<(usize, LineResult<&str>)> {
+        self.line.clear();
+        self.num += 1;
+        return match self.reader.read_line(&mut self.line) {
+            Ok(0) => None,
+            Ok(_) => {
+                // Line endings are left out, like BufRead::lines does
+                let ending = if self.line.ends_with("\r\n") { 2 } else { usize::from(self.line.ends_with('\n')) };
+                self.line.truncate(self.line.len() - ending);
+                Some((self.num, Ok(&self.line)))
+            }
+            Err(error) => Some((self.num, Err(io_error(error, self.source.clone())))),
+        };
     }
+}
 
-    verify_rules(&test_ruleset)?;
+struct StrLines<'a>(std::iter::Enumerate<std::str::Lines<'a>>);
+
+// The lines of a string, numbered from 1
+fn str_line_nums(text: &str) -> StrLines<'_> {
+    StrLines(text.lines().enumerate())
+}
 
-    let mut ruleset = HashMap::<String, Rewrite>::with_capacity(rule_count);
-    for (symbol, (rewrite, _)) in test_ruleset.drain() {
-        ruleset.insert(symbol, rewrite);
+impl Lines for StrLines<'_> {
+    fn next_line(&mut self) -> Option<(usize, LineResult<&str>)> {
+        self.0.next().map(|(num, line)| (num + 1, Ok(line)))
     }
+}
+
+// A file which couldn't be opened, which has nothing but the error
+struct Unreadable(Option<CompileError>);
 
-    return Ok(ruleset);
+impl Lines for Unreadable {
+    fn next_line(&mut self) -> Option<(usize, LineResult<&str>)> {
+        self.0.take().map(|error| (0, Err(error)))
+    }
 }
 
-fn grammar_from_rules(rule_list: Vec<Rule>) -> FileResult<Grammar> {
-    let start_symbol = if rule_list.len() > 0 {
-        rule_list[0].symbol.clone()
-    } else {
-        String::new()
-    };
+// Leaves out the warnings the options allow, and turns the ones they deny
+// into errors
+fn apply_levels(warnings: &mut CompileWarnings, options: &ParseOptions) -> CompileErrors {
+    let mut errors = Vec::new();
+    warnings.retain(|warning| match options.warning_levels.decide(warning.warning.code()) {
+        Some(Decision { level: Level::Allow, .. }) => false,
+        Some(Decision { level: Level::Deny, implied }) => {
+            errors.push(CompileError {
+                location: warning.location.clone(),
+                error: CompileErrorType::DeniedWarning { warning: warning.warning.clone(), implied }
+            });
+            false
+        }
+        Some(Decision { level: Level::Warn, .. }) | None => true,
+    });
+    return errors;
+}
 
-    let rules = ruleset_from_rules(rule_list)?;
+// Makes the grammar of a ruleset. A source without any rules is an error,
+// since there's nothing to start generating from. The start symbol is the
+// source's own first rule, rather than one it included. Returns the
+// warnings about references to deprecated rules, which can only be found
+// once every rule is known.
+fn grammar_from_ruleset(ruleset: Ruleset, deprecations: Vec<(String, Deprecation)>, source: &SourceName, options: &ParseOptions) -> (Grammar, CompileErrors, CompileWarnings) {
+    let (grammar, mut errors) = ruleset.into_grammar(deprecations, options);
+    if grammar.rules.is_empty() {
+        errors.push(CompileError { location: Location::whole(source.clone()), error: CompileErrorType::EmptyGrammar });
+    }
+    if options.verify {
+        errors.extend(undefined_deprecations(&grammar));
+    }
+    let mut warnings = deprecated_references(&grammar);
+    warnings.extend(useless_max_depths(&grammar));
+    if options.warn_unreachable {
+        warnings.extend(unreachable_rules(&grammar));
+    }
+    errors.extend(apply_levels(&mut warnings, options));
+    return (grammar, errors, warnings);
+}
 
-    return Ok(Grammar {
-        start_symbol,
-        rules
-    })
+// Whether the values of a `;@ distinct` are nothing or a number of tries
+fn valid_distinct(values: &[String]) -> bool {
+    match values {
+        [] => true,
+        [tries] => tries.parse::<u32>().is_ok_and(|tries| tries > 0),
+        _ => false,
+    }
 }
 
+// Whether the values of a `;@ max-depth` are one depth
+fn valid_max_depth(values: &[String]) -> bool {
+    return matches!(values, [depth] if depth.parse::<usize>().is_ok_and(|depth| depth > 0));
+}
+
+// The chance of a `;@ repeat`, if its values are one number between 0 and
+// 1, leaving both out since braces would then never repeat or never stop
+fn repeat_chance(values: &[String]) -> Option<f64> {
+    match values {
+        [chance] => chance.parse::<f64>().ok().filter(|chance| *chance > 0.0 && *chance < 1.0),
+        _ => None,
+    }
+}
+
+// A warning for each rule with a `;@ max-depth` that isn't recursive, in
+// the order the rules were defined
+fn useless_max_depths(grammar: &Grammar) -> CompileWarnings {
+    return grammar.rules.keys()
+        .filter(|rule| grammar.annotations.get(*rule).is_some_and(|annotations| annotations.contains_key("max-depth")))
+        .filter(|rule| !rules_reaching(&grammar.rules, rule).contains(&rule[..]))
+        .map(|rule| CompileWarning { location: grammar.location(rule), warning: CompileWarningType::UselessMaxDepth(rule.to_string()) })
+        .collect();
+}
+
+// A warning for each rule the start symbol can't reach, in the order the
+// rules were defined. The rules made for brackets are left out, since the
+// rules they're in are warned about instead.
+fn unreachable_rules(grammar: &Grammar) -> CompileWarnings {
+    return grammar.rules.keys()
+        .zip(grammar.rules.reachable(&grammar.start_symbol))
+        .filter(|(rule, reached)| !reached && Bracket::of_rule(rule).is_none())
+        .map(|(rule, _)| rule)
+        .map(|rule| CompileWarning { location: grammar.location(rule), warning: CompileWarningType::UnreachableRule(rule.to_string()) })
+        .collect();
+}
+
+// The `;@ tag`s of a rule on indices it doesn't have
+fn tags_out_of_range(rule: &Rule, annotations: &Annotations) -> Vec<CompileError> {
+    let alternatives = rule.rewrite.len();
+    return annotations.get("tag").into_iter().flatten()
+        .filter_map(|value| parse_tag(value))
+        .flat_map(|(tag, indices)| indices.into_iter().flatten().filter(|index| *index >= alternatives).map(move |index| (tag, index)))
+        .map(|(tag, index)| CompileError {
+            location: rule.location.clone(),
+            error: CompileErrorType::TaggedOutOfRange { rule: rule.symbol.to_string(), tag: tag.to_string(), index, alternatives }
+        })
+        .collect();
+}
+
+// Parses each numbered line into a rule with parse, using source for the
+// error locations, and adds the rules from the lines without errors to
+// rules. Returns the `%deprecated` rules, along with the errors and warnings
+// found. The rules of `%include`d files come where they're included, and
+// their problems after this source's.
+fn parse_rule_lines(lines: &mut impl Lines, source: &SourceName, options: &ParseOptions, parse: &mut LineParser, includes: &mut Includes, rules: &mut Ruleset)
+    -> (Vec<(String, Deprecation)>, CompileErrors, CompileWarnings) {
+    let mut deprecations = Vec::new();
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    // The `;@` lines read since the last rule, and where the first was
+    let mut annotations = Annotations::new();
+    let mut annotated: Option<Location> = None;
+    let mut included_errors = Vec::new();
+    // Whether the source has `%use dynamics`, and the first dynamic symbol
+    // of each rule using one, which needs it
+    let mut dynamics = false;
+    let mut dynamic_uses = Vec::new();
+    while let Some((num, line_res)) = lines.next_line() {
+        let location = Location::line(source.clone(), num);
+        let line = match line_res {
+            Ok(line) => line,
+            Err(error) => {
+                errors.push(error);
+                continue;
+            }
+        };
+
+        if let Some(annotation) = line.strip_prefix(";@") {
+            let mut words = annotation.split_whitespace().map(str::to_string);
+            match words.next() {
+                Some(key) => {
+                    if !ANNOTATION_KEYS.contains(&key.as_str()) {
+                        warnings.push(CompileWarning { location: location.clone(), warning: CompileWarningType::UnknownAnnotation(key.clone()) });
+                    }
+                    let values: Vec<String> = words.collect();
+                    if key == "distinct" && !valid_distinct(&values) {
+                        errors.push(CompileError { location: location.clone(), error: CompileErrorType::BadDistinct(values.join(" ")) });
+                    }
+                    if key == "max-depth" && !valid_max_depth(&values) {
+                        errors.push(CompileError { location: location.clone(), error: CompileErrorType::BadMaxDepth(values.join(" ")) });
+                    }
+                    if key == "repeat" && repeat_chance(&values).is_none() {
+                        errors.push(CompileError { location: location.clone(), error: CompileErrorType::BadRepeat(values.join(" ")) });
+                    }
+                    if key == "tag" {
+                        if values.is_empty() {
+                            errors.push(CompileError { location: location.clone(), error: CompileErrorType::BadTag(String::new()) });
+                        }
+                        for value in values.iter().filter(|value| parse_tag(value).is_none()) {
+                            errors.push(CompileError { location: location.clone(), error: CompileErrorType::BadTag(value.clone()) });
+                        }
+                    }
+                    annotations.entry(key).or_default().extend(values);
+                }
+                None => errors.push(CompileError { location: location.clone(), error: CompileErrorType::EmptyAnnotation }),
+            }
+            annotated.get_or_insert(location);
+            continue;
+        }
+        if !is_rule_line(line) || is_include(line) || is_deprecation(line) || is_use(line) {
+            // Anything else between annotations and a rule detaches them
+            if let Some(location) = annotated.take() {
+                errors.push(CompileError { location, error: CompileErrorType::DetachedAnnotation });
+                annotations.clear();
+            }
+            if is_include(line) {
+                match includes.enter(line, source, options) {
+                    Ok(Some((text, included))) => {
+                        let (included_deprecations, errors, included_warnings) = parse_rule_lines(&mut str_line_nums(&text), &included, options, parse, includes, rules);
+                        includes.leave();
+                        deprecations.extend(included_deprecations);
+                        included_errors.extend(errors);
+                        warnings.extend(included_warnings);
+                    }
+                    Ok(None) => {}
+                    Err(error) => errors.push(CompileError { location, error }),
+                }
+            } else if is_deprecation(line) {
+                match parse_deprecation(line) {
+                    Ok((rule, message)) => deprecations.push((rule, Deprecation { message, location })),
+                    Err(error) => errors.push(CompileError { location, error }),
+                }
+            } else if is_use(line) {
+                match parse_use(line) {
+                    Ok(()) => dynamics = true,
+                    Err(error) => errors.push(CompileError { location, error }),
+                }
+            }
+            continue;
+        }
+
+        annotated = None;
+        let rule_annotations = std::mem::take(&mut annotations);
+        let parsed = parse(line, location);
+        match parsed {
+            Ok((mut rule, line_warnings)) => {
+                let mut helpers = std::mem::take(&mut rule.helpers);
+                if let Some(chance) = rule_annotations.get("repeat").and_then(|values| repeat_chance(values)) {
+                    helpers.iter_mut().for_each(|helper| set_repeat_chance(helper, chance));
+                }
+                let symbols = || rule.rewrite.symbols.iter().chain(helpers.iter().flat_map(|helper| &helper.rewrite.symbols));
+                if let Some(ParsedSymbol::Dynamic(dynamic)) = symbols().find(|symbol| matches!(symbol, ParsedSymbol::Dynamic(_))) {
+                    dynamic_uses.push(CompileError { location: rule.location.clone(), error: CompileErrorType::DynamicsNotEnabled(dynamic.name().to_string()) });
+                }
+                errors.extend(tags_out_of_range(&rule, &rule_annotations));
+                rules.define(Rule { annotations: rule_annotations, ..rule });
+                helpers.into_iter().for_each(|helper| rules.define(helper));
+                warnings.extend(line_warnings);
+            }
+            Err(error) => errors.push(error),
+        }
+    }
+    if let Some(location) = annotated {
+        errors.push(CompileError { location, error: CompileErrorType::DetachedAnnotation });
+    }
+
+    if !dynamics {
+        errors.extend(dynamic_uses);
+    }
+    errors.extend(apply_levels(&mut warnings, options));
+    // Detached annotations and dynamic symbols without `%use dynamics` are
+    // only found after the lines below them
+    errors.sort_by_key(|error| error.location.line);
+    errors.extend(included_errors);
+    return (deprecations, errors, warnings);
+}
+
+// Parses numbered lines into a grammar, leaving out lines with errors, and
+// returns every problem found alongside it, sorted by line
+fn parse_lines_partial(mut lines: impl Lines, source: &SourceName, options: &ParseOptions, parse: &mut LineParser) -> (Grammar, CompileErrors, CompileWarnings) {
+    let mut rules = Ruleset::new(source);
+    let (deprecations, mut errors, mut warnings) = parse_rule_lines(&mut lines, source, options, parse, &mut Includes::new(source), &mut rules);
+    let (grammar, mut undefined, deprecated) = grammar_from_ruleset(rules, deprecations, source, options);
+    warnings.extend(deprecated);
+    // A grammar whose only rules have errors isn't empty
+    if !errors.is_empty() {
+        undefined.retain(|error| error.error != CompileErrorType::EmptyGrammar);
+    }
+    errors.extend(undefined);
+    // The sort is stable, so errors on the same line keep their order, and
+    // errors in included files stay after this source's
+    errors.sort_by_key(|error| (error.location.source != *source, error.location.line));
+    return (grammar, errors, warnings);
+}
+
+// Parses numbered lines into a grammar, failing if there's any error.
+// Undefined symbols are only looked for once every line has parsed, since a
+// broken line would leave its rule undefined. Returns the grammar along with
+// any warnings about it.
+fn parse_lines(mut lines: impl Lines, source: &SourceName, options: &ParseOptions, parse: &mut LineParser) -> FileResult<(Grammar, CompileWarnings)> {
+    let mut rules = Ruleset::new(source);
+    let (deprecations, errors, mut warnings) = parse_rule_lines(&mut lines, source, options, parse, &mut Includes::new(source), &mut rules);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let (grammar, undefined, deprecated) = grammar_from_ruleset(rules, deprecations, source, options);
+    if !undefined.is_empty() {
+        return Err(undefined);
+    }
+    warnings.extend(deprecated);
+    return Ok((grammar, warnings));
+}
+
+// Parses and verifies a grammar file, returning every problem found. A path
+// of `-` reads the grammar from stdin. Warnings are ignored; use
+// parse_file_with_options to get them. The errors convert into crate::Error,
+// so `?` works in functions returning it.
 pub fn parse_file(path: &PathBuf) -> FileResult<Grammar> {
-    let file = File::open(path).map_err(|e| vec![io_error(e, path.clone())])?;
-    let lines = file_line_nums(file, path);
-
-    let parsed_lines = lines.map(|(num, line_res)| {
-        line_res.and_then(|line| parse_lex_line(&line, Location {
-            file: path.clone(),
-            line: num
-        }))
-    });
+    parse_file_with_options(path, &ParseOptions::default()).map(|(grammar, _)| grammar)
+}
 
-    let (rules, errors): (Vec<_>, Vec<_>) = parsed_lines.partition(LineResult::is_ok);
-    if errors.len() > 0 {
-        return Err(errors.into_iter().map(LineResult::unwrap_err).collect_vec());
+// Loads a grammar written by `blabber compile`. It has no warnings, since
+// they were reported when it was compiled.
+fn load_compiled(bytes: &[u8], source: SourceName) -> FileResult<(Grammar, CompileWarnings)> {
+    match compiled::from_bytes(bytes) {
+        Ok(grammar) => Ok((grammar, Vec::new())),
+        Err(error) => Err(vec![CompileError { location: Location::whole(source), error: CompileErrorType::Compiled(error) }]),
     }
-    let rules_unwrapped = rules.into_iter().map(LineResult::unwrap).collect_vec();
+}
 
-    return grammar_from_rules(rules_unwrapped);
+// Reads a whole grammar file, or stdin for a path of `-`
+fn read_source(path: &Path) -> std::io::Result<String> {
+    if path.as_os_str() == "-" {
+        return std::io::read_to_string(std::io::stdin().lock());
+    }
+    return std::fs::read_to_string(path);
+}
+
+// The syntax a file is parsed as, which the options can override
+fn file_syntax(path: &Path, options: &ParseOptions) -> Syntax {
+    options.syntax.unwrap_or_else(|| Syntax::of_path(path))
+}
+
+// Parses a grammar file, returning the grammar along with any warnings.
+// Files ending in `.blb` are loaded as compiled grammars instead, and files
+// ending in `.yaml` or `.yml` are read as YAML unless the options say
+// otherwise.
+pub fn parse_file_with_options(path: &PathBuf, options: &ParseOptions) -> FileResult<(Grammar, CompileWarnings)> {
+    if compiled::is_compiled(path) {
+        let source = SourceName::file(path);
+        let bytes = std::fs::read(path).map_err(|e| vec![io_error(e, source.clone())])?;
+        return load_compiled(&bytes, source);
+    }
+    if file_syntax(path, options) == Syntax::Yaml {
+        let source = if path.as_os_str() == "-" { SourceName::Stdin } else { SourceName::file(path) };
+        let text = read_source(path).map_err(|e| vec![io_error(e, source.clone())])?;
+        return yaml::parse_yaml(&text, &source, options);
+    }
+    if path.as_os_str() == "-" {
+        let source = SourceName::Stdin;
+        return parse_lines(reader_line_nums(std::io::stdin().lock(), &source), &source, options, &mut parse_lex_line);
+    }
+
+    let source = SourceName::file(path);
+    let file = File::open(path).map_err(|e| vec![io_error(e, source.clone())])?;
+    return parse_lines(reader_line_nums(std::io::BufReader::new(file), &source), &source, options, &mut parse_lex_line);
+}
+
+// Parses a grammar file like parse_file_with_options, timing reading the
+// file, parsing it, and verifying it separately. The whole file is read
+// before parsing starts, rather than line by line, so the phases don't
+// overlap.
+pub fn parse_file_timed(path: &PathBuf, options: &ParseOptions, timings: &mut Timings) -> FileResult<(Grammar, CompileWarnings)> {
+    let source = if path.as_os_str() == "-" { SourceName::Stdin } else { SourceName::file(path) };
+    if compiled::is_compiled(path) {
+        let bytes = timings.time("reading", || std::fs::read(path)).map_err(|error| vec![io_error(error, source.clone())])?;
+        return timings.time("loading", || load_compiled(&bytes, source));
+    }
+    let text = timings.time("reading", || read_source(path)).map_err(|error| vec![io_error(error, source.clone())])?;
+
+    let options = ParseOptions { syntax: Some(file_syntax(path, options)), ..options.clone() };
+    return parse_str_timed(&text, source, &options, timings);
+}
+
+// Parses grammar text, timing parsing and verifying separately
+fn parse_str_timed(text: &str, source: SourceName, options: &ParseOptions, timings: &mut Timings) -> FileResult<(Grammar, CompileWarnings)> {
+    let unverified = ParseOptions { verify: false, ..options.clone() };
+    let (grammar, warnings) = timings.time("parsing", || parse_str_with_options(text, source, &unverified))?;
+    if options.verify {
+        let undefined = timings.time("verifying", || verify_grammar(&grammar));
+        if !undefined.is_empty() {
+            return Err(undefined);
+        }
+    }
+    return Ok((grammar, warnings));
+}
+
+// Parses and verifies as much of a grammar file as possible, for tools which
+// need to work with files that have errors. Lines with errors are left out,
+// rules using undefined symbols are kept, and every error is returned along
+// with the grammar instead of failing. If the file can't be read, the grammar
+// is empty.
+pub fn parse_file_partial(path: &PathBuf) -> (Grammar, CompileErrors, CompileWarnings) {
+    let options = ParseOptions::default();
+    if path.as_os_str() == "-" {
+        let source = SourceName::Stdin;
+        return parse_lines_partial(reader_line_nums(std::io::stdin().lock(), &source), &source, &options, &mut parse_lex_line);
+    }
+
+    let source = SourceName::file(path);
+    match File::open(path) {
+        Ok(file) => parse_lines_partial(reader_line_nums(std::io::BufReader::new(file), &source), &source, &options, &mut parse_lex_line),
+        Err(error) => parse_lines_partial(Unreadable(Some(io_error(error, source.clone()))), &source, &options, &mut parse_lex_line),
+    }
+}
+
+// Parses a grammar held in a string like parse_file_partial
+pub fn parse_str_partial(text: &str, source: SourceName) -> (Grammar, CompileErrors, CompileWarnings) {
+    let lines = str_line_nums(text);
+    return parse_lines_partial(lines, &source, &ParseOptions::default(), &mut parse_lex_line);
+}
+
+// Parses a grammar held in a string, as if it were the contents of the given
+// source
+pub fn parse_str(text: &str, source: SourceName) -> FileResult<Grammar> {
+    parse_str_with_options(text, source, &ParseOptions::default()).map(|(grammar, _)| grammar)
+}
+
+// Parses a grammar held in a string, as BNF unless the options say it's
+// written in another syntax
+pub fn parse_str_with_options(text: &str, source: SourceName, options: &ParseOptions) -> FileResult<(Grammar, CompileWarnings)> {
+    if options.syntax == Some(Syntax::Yaml) {
+        return yaml::parse_yaml(text, &source, options);
+    }
+    let lines = str_line_nums(text);
+    return parse_lines(lines, &source, options, &mut parse_lex_line);
+}
+
+// Splits a grammar written in one command line argument into lines, at each
+// `;` or `\n` outside quotes
+fn split_arg_rules(text: &str) -> String {
+    let mut lines = String::with_capacity(text.len());
+    let mut quoted = false;
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        // Raw terminals start where a symbol could, and are copied whole
+        let at_symbol = index == 0 || text[..index].ends_with(|c: char| c.is_whitespace() || "=|;".contains(c));
+        if let Some(hashes) = lexer::raw_hashes(&text[index..]).filter(|_| !quoted && at_symbol) {
+            let closing = format!("\"{}", "#".repeat(hashes));
+            let end = text[index + hashes + 2..].find(&closing).map_or(text.len(), |end| index + hashes + 2 + end + closing.len());
+            lines.push_str(&text[index..end]);
+            while chars.next_if(|(next, _)| *next < end).is_some() {}
+            continue;
+        }
+        match c {
+            '\"' => {
+                quoted = !quoted;
+                lines.push(c);
+            }
+            ';' if !quoted => lines.push('\n'),
+            '\\' if !quoted && chars.peek().map(|(_, c)| *c) == Some('n') => {
+                chars.next();
+                lines.push('\n');
+            }
+            _ => lines.push(c),
+        }
+    }
+    return lines;
+}
+
+// The lines of a grammar given with --grammar. YAML is taken as it is,
+// since it has its own use for `;` and `\n`.
+fn arg_lines(text: &str, options: &ParseOptions) -> String {
+    match options.syntax {
+        Some(Syntax::Yaml) => text.to_string(),
+        _ => split_arg_rules(text),
+    }
+}
+
+// Parses a grammar given with --grammar, where rules are separated by `;` or
+// `\n`. Errors are located at `<arg>:RULE`, counting rules from 1.
+pub fn parse_arg_with_options(text: &str, options: &ParseOptions) -> FileResult<(Grammar, CompileWarnings)> {
+    return parse_str_with_options(&arg_lines(text, options), SourceName::Cli("arg".to_string()), options);
+}
+
+// Parses a grammar given on the command line like parse_arg_with_options,
+// timing parsing and verifying separately
+pub fn parse_arg_timed(text: &str, options: &ParseOptions, timings: &mut Timings) -> FileResult<(Grammar, CompileWarnings)> {
+    return parse_str_timed(&arg_lines(text, options), SourceName::Cli("arg".to_string()), options, timings);
 }
 
 #[cfg(test)]
 mod tests {
     use std::iter::zip;
 
+
     use super::*;
 
     impl Location {
-        pub fn new() -> Self {
-            Location {
-                file: PathBuf::new(),
-                line: 0
-            }
+        fn new() -> Self {
+            Location::whole(SourceName::Builtin)
         }
     }
 
     fn s_nonterminal(text: &str) -> Symbol {
-        Symbol::Nonterminal(text.to_string())
+        Symbol::Nonterminal(text.into(), None)
     }
 
     fn s_terminal(text: &str) -> Symbol {
-        Symbol::Terminal(text.to_string())
+        Symbol::Terminal(text.into())
     }
 
     #[test]
     fn parse_normal_alternative() {
         let lines = vec![
             vec![
-                Token::Nonterminal("personal.part".to_string()),
-                Token::Nonterminal("last.name".to_string()),
-                Token::Nonterminal("opt.suffix.name".to_string()),
-                Token::Terminal("\\n".to_string())
+                Token::Nonterminal("personal.part", None),
+                Token::Nonterminal("last.name", None),
+                Token::Nonterminal("opt.suffix.name", None),
+                Token::Terminal("\\n".into())
             ],
             vec![
-                Token::Nonterminal("town.name".to_string()),
-                Token::Terminal(",".to_string())
+                Token::Nonterminal("town.name", None),
+                Token::Terminal(",".into())
             ]
         ];
         let answers = vec![
@@ -264,68 +1286,263 @@ mod tests {
         assert_eq!(parse_alternative(&[Token::Or]), Err(CompileErrorType::UnsplitRewrite));
     }
 
+    #[test]
+    fn warn_about_stray_pipes() {
+        let warnings_for = |line: &str| {
+            let tokens = lexer::lex_line(line).unwrap();
+            rewrite_warnings(&tokens[2..], &alternatives_by_level(&tokens[2..]))
+        };
+        let empty = CompileWarningType::EmptyAlternative;
+
+        assert_eq!(warnings_for("adjective = \"green\" | \"colorless\""), vec![]);
+        assert_eq!(warnings_for("adjective = \"green\" | \"\""), vec![]);
+        assert_eq!(warnings_for("adjective ="), vec![]);
+        assert_eq!(warnings_for("adjective = \"green\" | \"colorless\" |"), vec![empty(PipePosition::Trailing)]);
+        assert_eq!(warnings_for("adjective = | \"green\" | \"colorless\""), vec![empty(PipePosition::Leading)]);
+        assert_eq!(warnings_for("adjective = \"green\" || \"colorless\""), vec![empty(PipePosition::Doubled)]);
+        assert_eq!(warnings_for("adjective = | \"green\" | | \"colorless\" |"), vec![
+            empty(PipePosition::Leading),
+            empty(PipePosition::Doubled),
+            empty(PipePosition::Trailing)
+        ]);
+        assert_eq!(warnings_for("adjective = |"), vec![empty(PipePosition::Leading), empty(PipePosition::Trailing)]);
+    }
+
+    #[test]
+    fn warn_about_empty_terminals_among_symbols() {
+        let warnings_for = |line: &str| {
+            let tokens = lexer::lex_line(line).unwrap();
+            rewrite_warnings(&tokens[2..], &alternatives_by_level(&tokens[2..]))
+        };
+        let empty = |alternative: &str| CompileWarningType::EmptyTerminal(alternative.to_string());
+
+        assert_eq!(warnings_for("suffix = \"s\" | \"\""), vec![]);
+        assert_eq!(warnings_for("suffix = \"\""), vec![]);
+        assert_eq!(warnings_for("a = \"x\" \"\" \"y\" | b"), vec![empty("\"x\" \"\" \"y\"")]);
+        assert_eq!(warnings_for("a = \"\" r\"\" | \"\" b:upper |"), vec![
+            CompileWarningType::EmptyAlternative(PipePosition::Trailing),
+            empty("\"\" \"\""),
+            empty("\"\" b:upper"),
+        ]);
+        assert_eq!(empty("\"x\" \"\"").to_string(), "The alternative `\"x\" \"\"` has an empty terminal, which adds nothing (write `\"\"` alone for an empty alternative)");
+    }
+
+    #[test]
+    fn warn_about_unnormalized_terminals() {
+        let warnings_for = |line: &str| {
+            let tokens = lexer::lex_line(line).unwrap();
+            rewrite_warnings(&tokens[2..], &alternatives_by_level(&tokens[2..]))
+        };
+        assert_eq!(warnings_for("food = \"caf\u{e9}\" | r\"cr\u{e8}me\" | \"plain\""), vec![]);
+        let warnings = warnings_for("food = \"caf\u{e9}\" | \"cre\u{300}me\" \"br\u{fb}le\u{301}e\"");
+        assert_eq!(warnings, vec![
+            CompileWarningType::UnnormalizedTerminal("cre\u{300}me".to_string()),
+            CompileWarningType::UnnormalizedTerminal("br\u{fb}le\u{301}e".to_string()),
+        ]);
+        assert_eq!(warnings[0].code(), "unnormalized-terminal");
+        assert_eq!(warnings[0].to_string(), "The terminal `\"cre\u{300}me\"` isn't in Unicode normalization form NFC (`--normalize nfc` makes sentences consistent)");
+    }
+
+    #[test]
+    fn locate_warnings() {
+        let text = "sentence = adjective \"ideas\"\nadjective = \"green\" | \"colorless\" |";
+        let source = SourceName::Builtin;
+
+        let (grammar, warnings) = parse_str_with_options(text, source.clone(), &ParseOptions::default()).unwrap();
+        assert_eq!(grammar.rules.rewrite("adjective").len(), 3);
+        assert_eq!(warnings, vec![CompileWarning {
+            location: Location::line(source.clone(), 2),
+            warning: CompileWarningType::EmptyAlternative(PipePosition::Trailing)
+        }]);
+
+        let options = ParseOptions { warning_levels: WarningLevels::deny_all(), ..Default::default() };
+        let errors = parse_str_with_options(text, source.clone(), &options).unwrap_err();
+        assert_eq!(errors, vec![CompileError {
+            location: Location::line(source.clone(), 2),
+            error: CompileErrorType::DeniedWarning { warning: CompileWarningType::EmptyAlternative(PipePosition::Trailing), implied: true }
+        }]);
+        assert!(errors[0].error.to_string().ends_with("\n  = note: `-D empty-alternative` implied by `-D warnings`"));
+
+        // Allowed warnings are left out, and a warning's own level beats the
+        // one for every warning
+        let mut warning_levels = WarningLevels::deny_all();
+        warning_levels.set("empty-alternative", Level::Allow);
+        let options = ParseOptions { warning_levels, ..Default::default() };
+        assert_eq!(parse_str_with_options(text, source, &options).unwrap().1, vec![]);
+    }
+
     #[test]
     fn parse_normal_line() {
         let text = "personal.part = first.name | initial \".\"";
         let lexed = lexer::lex_line(text).unwrap();
-        let location = Location {
-            file: PathBuf::new(),
-            line: 0
-        };
+        let location = Location::whole(SourceName::Builtin);
 
         let answer = Rule {
-            symbol: "personal.part".to_string(),
-            rewrite: vec![
+            symbol: "personal.part".into(),
+            rewrite: ParsedRewrite::from(vec![
                 vec![s_nonterminal("first.name")],
                 vec![
                     s_nonterminal("initial"),
                     s_terminal(".")
                 ]
-            ],
-            location: location.clone()
+            ]),
+            location: location.clone(),
+            annotations: Annotations::new(),
+            weights: None,
+            helpers: Vec::new()
         };
 
-        assert_eq!(parse_line(&lexed[..], location), Ok(answer));
+        assert_eq!(parse_line(lexed, location), Ok(answer));
     }
 
     #[test]
     fn parse_malformed_line() {
         // Blank
-        assert_eq!(parse_line(&[], Location::new()), Err(CompileErrorType::UnexpectedBlankLine));
+        assert_eq!(parse_line(Vec::new(), Location::new()), Err(CompileErrorType::UnexpectedBlankLine));
 
         // Missing equals
         assert_eq!(parse_line(
-            &lexer::lex_line("alpha bravo charlie").unwrap()[..],
+            lexer::lex_line("alpha bravo charlie").unwrap(),
             Location::new()
         ), Err(CompileErrorType::MissingEquals));
 
         // Improper definition
         assert_eq!(parse_line(
-            &lexer::lex_line("\"alpha\" = bravo charlie").unwrap()[..],
+            lexer::lex_line("\"alpha\" = bravo charlie").unwrap(),
             Location::new()
         ), Err(CompileErrorType::MissingNonterminal));
         assert_eq!(parse_line(
-            &lexer::lex_line("| = alpha bravo charlie").unwrap()[..],
+            lexer::lex_line("| = alpha bravo charlie").unwrap(),
             Location::new()
         ), Err(CompileErrorType::MissingNonterminal));
         assert_eq!(parse_line(
-            &lexer::lex_line("= alpha bravo charlie").unwrap()[..],
+            lexer::lex_line("= alpha bravo charlie").unwrap(),
             Location::new()
         ), Err(CompileErrorType::MissingNonterminal));
     }
 
+    #[test]
+    fn parse_weighted_alternatives() {
+        let grammar = parse_str("greeting = 5 \"hi\" | \"hey\" | 2 name \"!\"\nname = \"jo\"\n7 = \"x\"\nlucky = 7", SourceName::Builtin).unwrap();
+        assert_eq!(grammar.rules.rewrite("greeting"), vec![vec![s_terminal("hi")], vec![s_terminal("hey")], vec![s_nonterminal("name"), s_terminal("!")]]);
+        assert_eq!(*grammar.weights["greeting"], [5.0, 1.0, 2.0]);
+        // A number alone is a rule, like before
+        assert_eq!(grammar.rules.rewrite("lucky"), vec![vec![s_nonterminal("7")]]);
+        assert!(!grammar.weights.contains_key("name") && !grammar.weights.contains_key("lucky"));
+
+        let errors = parse_str("greeting = \"hi\"\nname = 0 \"jo\" | 1 \"sam\"", SourceName::Builtin).unwrap_err();
+        assert_eq!(errors[0].location.line, Some(2));
+        assert_eq!(errors[0].error.to_string(), "Expected an alternative's weight to be a whole number from 1 to 4294967295, found `0` (leave out alternatives which should never be picked)");
+        let errors = parse_str("name = 99999999999 \"jo\"", SourceName::Builtin).unwrap_err();
+        assert_eq!(errors[0].error, CompileErrorType::BadWeight("99999999999".to_string()));
+
+        // A weight isn't a symbol, so `5 ""` is an empty alternative
+        let warnings_for = |line: &str| {
+            let tokens = lexer::lex_line(line).unwrap();
+            rewrite_warnings(&tokens[2..], &alternatives_by_level(&tokens[2..]))
+        };
+        assert_eq!(warnings_for("suffix = 5 \"\" | 1 \"s\""), vec![]);
+    }
+
+    #[test]
+    fn parse_optional_groups() {
+        let grammar = parse_str("greeting = \"hi\" [\" \" [adjective \" \"] name] | [3 \"yo\" | name]\nadjective = \"dear\"\nname = \"jo\"", SourceName::Builtin).unwrap();
+        assert_eq!(grammar.rules.keys().collect_vec(), ["greeting", "greeting[1]", "greeting[2]", "greeting[3]", "adjective", "name"]);
+        assert_eq!(grammar.rules.rewrite("greeting"), vec![vec![s_terminal("hi"), s_nonterminal("greeting[1]")], vec![s_nonterminal("greeting[3]")]]);
+        assert_eq!(grammar.rules.rewrite("greeting[1]"), vec![vec![], vec![s_terminal(" "), s_nonterminal("greeting[2]"), s_nonterminal("name")]]);
+        assert_eq!(grammar.rules.rewrite("greeting[2]"), vec![vec![], vec![s_nonterminal("adjective"), s_terminal(" ")]]);
+        // Left out half the time, with the alternatives sharing the rest
+        assert!(!grammar.weights.contains_key("greeting") && !grammar.weights.contains_key("greeting[1]"));
+        assert_eq!(*grammar.weights["greeting[3]"], [4.0, 3.0, 1.0]);
+        assert_eq!(grammar.location("greeting[2]").line, Some(1));
+
+        // Defining a rule again replaces the rules of its brackets too
+        let grammar = parse_str("a = [\"x\"] [\"y\"]\na = [\"z\"]", SourceName::Builtin).unwrap();
+        assert_eq!(grammar.rules.keys().collect_vec(), ["a", "a[1]"]);
+        assert_eq!(grammar.rules.rewrite("a[1]"), vec![vec![], vec![s_terminal("z")]]);
+
+        // Warnings look inside brackets, which count as one symbol
+        let warnings_for = |line: &str| {
+            let tokens = lexer::lex_line(line).unwrap();
+            rewrite_warnings(&tokens[2..], &alternatives_by_level(&tokens[2..]))
+        };
+        assert_eq!(warnings_for("a = [\"x\" | \"y\"] \"\""), vec![CompileWarningType::EmptyTerminal("[\"x\" | \"y\"] \"\"".to_string())]);
+        assert_eq!(warnings_for("a = \"x\" [\"y\" | | [\"\" b]]"), vec![
+            CompileWarningType::EmptyAlternative(PipePosition::Doubled),
+            CompileWarningType::EmptyTerminal("\"\" b".to_string()),
+        ]);
+        assert_eq!(warnings_for("a = [\"\"] | [\"x\"]"), vec![]);
+
+        let errors = parse_str("a = \"x\"\nb = [[\"y\"]", SourceName::Builtin).unwrap_err();
+        assert_eq!((errors[0].location.line, &errors[0].error), (Some(2), &CompileErrorType::UnclosedBracket { bracket: Bracket::Optional, col: 5 }));
+    }
+
+    #[test]
+    fn parse_repetitions() {
+        let grammar = parse_str("list = item {\", \" item} [\".\"]\nitem = \"x\" {2 \"y\" | \"z\"}", SourceName::Builtin).unwrap();
+        assert_eq!(grammar.rules.keys().collect_vec(), ["list", "list{1}", "list[2]", "item", "item{1}"]);
+        // Braces repeat by expanding their rule again
+        assert_eq!(grammar.rules.rewrite("list{1}"), vec![vec![], vec![s_terminal(", "), s_nonterminal("item"), s_nonterminal("list{1}")]]);
+        assert_eq!(grammar.rules.rewrite("item{1}").to_rewrite()[2], vec![s_terminal("z"), s_nonterminal("item{1}")]);
+        assert!(!grammar.weights.contains_key("list{1}"));
+        assert_eq!(*grammar.weights["item{1}"], [3.0, 2.0, 1.0]);
+        // The rules made for brackets are defined like any other
+        assert_eq!(verify_grammar(&grammar), vec![]);
+        let errors = parse_str("list = \"x\" {\", \" item}", SourceName::Builtin).unwrap_err();
+        assert_eq!(errors.iter().map(|error| error.error.to_string()).collect_vec(), [CompileErrorType::UndefinedNonterminal("item".to_string()).to_string()]);
+
+        // `;@ repeat` sets how likely each repetition is to be followed by
+        // another, only for braces
+        let grammar = parse_str(";@ repeat 0.75\nlist = \"x\" {\"y\"} [\"z\"] {2 \"a\" | \"b\"}", SourceName::Builtin).unwrap();
+        assert_eq!(*grammar.weights["list{1}"], [1.0 / 3.0, 1.0]);
+        assert!(!grammar.weights.contains_key("list[2]"));
+        assert_eq!(*grammar.weights["list{3}"], [1.0, 2.0, 1.0]);
+
+        for chance in ["0", "1", "0.5 0.5", "half", ""] {
+            let errors = parse_str(&format!(";@ repeat {}\nlist = {{\"x\"}}", chance), SourceName::Builtin).unwrap_err();
+            assert_eq!(errors[0].error, CompileErrorType::BadRepeat(chance.to_string()));
+        }
+    }
+
+    #[test]
+    fn parse_groups() {
+        let grammar = parse_str("greeting = (\"hi\" | 3 \"hello\") \" there\"(\"!\" | ((\"?\") | \".\")) | \"yo\"", SourceName::Builtin).unwrap();
+        assert_eq!(grammar.rules.keys().collect_vec(), ["greeting", "greeting(1)", "greeting(2)", "greeting(3)", "greeting(4)"]);
+        // The `|`s in a group only choose within it
+        assert_eq!(grammar.rules.rewrite("greeting"), vec![
+            vec![s_nonterminal("greeting(1)"), s_terminal(" there"), s_nonterminal("greeting(2)")],
+            vec![s_terminal("yo")],
+        ]);
+        assert_eq!(grammar.rules.rewrite("greeting(1)"), vec![vec![s_terminal("hi")], vec![s_terminal("hello")]]);
+        assert_eq!(grammar.rules.rewrite("greeting(2)"), vec![vec![s_terminal("!")], vec![s_nonterminal("greeting(3)")]]);
+        assert_eq!(grammar.rules.rewrite("greeting(3)"), vec![vec![s_nonterminal("greeting(4)")], vec![s_terminal(".")]]);
+        assert_eq!(grammar.rules.rewrite("greeting(4)"), vec![vec![s_terminal("?")]]);
+        assert_eq!(*grammar.weights["greeting(1)"], [1.0, 3.0]);
+        assert!(!grammar.weights.contains_key("greeting") && !grammar.weights.contains_key("greeting(2)"));
+
+        // Groups nest as deep as they're written
+        let nested = format!("a = {}\"x\"{}", "(".repeat(10_000), ")".repeat(10_000));
+        let grammar = parse_str(&nested, SourceName::Builtin).unwrap();
+        assert_eq!(grammar.rules.len(), 10_001);
+        assert_eq!(grammar.rules.rewrite("a(9999)"), vec![vec![s_nonterminal("a(10000)")]]);
+        assert_eq!(grammar.rules.rewrite("a(10000)"), vec![vec![s_terminal("x")]]);
+
+        let errors = parse_str("a = \"x\"\nb = (\"y\" | (\"z\")", SourceName::Builtin).unwrap_err();
+        assert_eq!((errors[0].location.line, &errors[0].error), (Some(2), &CompileErrorType::UnclosedBracket { bracket: Bracket::Group, col: 5 }));
+    }
+
     #[test]
     fn parse_normal_file() {
         let example_path = PathBuf::from("example_data/english.bnf");
         let example_parsed = parse_file(&example_path).unwrap();
         
-        let mut rules = HashMap::new();
-        rules.insert("sentence".to_string(), vec![vec![
+        let mut rules = Rules::new();
+        rules.insert("sentence", &[vec![
             s_nonterminal("noun.phrase"),
             s_terminal(" "),
             s_nonterminal("verb.phrase")
         ]]);
-        rules.insert("noun.phrase".to_string(), vec![
+        rules.insert("noun.phrase", &[
             vec![
                 s_nonterminal("adjective.phrase"),
                 s_terminal(" "),
@@ -333,8 +1550,8 @@ mod tests {
             ],
             vec![s_nonterminal("noun")]
         ]);
-        rules.insert("noun".to_string(), vec![vec![s_terminal("ideas")]]);
-        rules.insert("adjective.phrase".to_string(), vec![
+        rules.insert("noun", &[vec![s_terminal("ideas")]]);
+        rules.insert("adjective.phrase", &[
             vec![
                 s_nonterminal("adjective"),
                 s_terminal(", "),
@@ -342,11 +1559,11 @@ mod tests {
             ],
             vec![s_nonterminal("adjective")]
         ]);
-        rules.insert("adjective".to_string(), vec![
+        rules.insert("adjective", &[
             vec![s_terminal("colorless")],
             vec![s_terminal("green")]
         ]);
-        rules.insert("verb.phrase".to_string(), vec![
+        rules.insert("verb.phrase", &[
             vec![
                 s_nonterminal("verb"),
                 s_terminal(" "),
@@ -360,8 +1577,8 @@ mod tests {
                 s_nonterminal("noun.phrase")
             ]
         ]);
-        rules.insert("verb".to_string(), vec![vec![s_terminal("hug")]]);
-        rules.insert("adverb.phrase".to_string(), vec![
+        rules.insert("verb", &[vec![s_terminal("hug")]]);
+        rules.insert("adverb.phrase", &[
             vec![
                 s_nonterminal("adverb"),
                 s_terminal(", "),
@@ -369,12 +1586,42 @@ mod tests {
             ],
             vec![s_nonterminal("adverb")]
         ]);
-        rules.insert("adverb".to_string(), vec![vec![s_terminal("furiously")]]);
+        rules.insert("adverb", &[vec![s_terminal("furiously")]]);
+
+        assert_eq!(example_parsed.start_symbol, "sentence");
+        assert_eq!(example_parsed.rules, rules);
+        // Rules are kept in the order the file defines them
+        assert!(example_parsed.rules.keys().eq(rules.keys()));
+        assert!(example_parsed.rules.keys().all(|rule| example_parsed.rules.location(rule).is_some()));
+        assert_eq!(example_parsed.location("noun.phrase"), Location::line(SourceName::file(&example_path), 4));
+        assert_eq!(example_parsed.location("adverb"), Location::line(SourceName::file(&example_path), 18));
+    }
+
+    #[test]
+    fn refuse_empty_files() {
+        for name in ["empty", "comments_only", "whitespace_only"] {
+            let path = PathBuf::from(format!("example_data/{}.bnf", name));
+            assert_eq!(parse_file(&path).unwrap_err(), vec![CompileError {
+                location: Location::whole(SourceName::file(&path)),
+                error: CompileErrorType::EmptyGrammar
+            }]);
+        }
+        assert_eq!(parse_arg_with_options("", &ParseOptions::default()).unwrap_err()[0].error, CompileErrorType::EmptyGrammar);
+
+        // Only when there are no rules at all, not when they all have errors
+        let (_, errors, _) = parse_str_partial("= \"x\"", SourceName::Builtin);
+        assert_eq!(errors.iter().map(|error| &error.error).collect_vec(), [&CompileErrorType::MissingNonterminal]);
+    }
+
+    #[test]
+    fn parse_without_verifying() {
+        let example_path = PathBuf::from("example_data/postal_address.bnf");
+        assert!(parse_file(&example_path).is_err());
 
-        assert_eq!(example_parsed, Grammar {
-            start_symbol: "sentence".to_string(),
-            rules
-        });
+        let options = ParseOptions { verify: false, ..Default::default() };
+        let (example_parsed, _) = parse_file_with_options(&example_path, &options).unwrap();
+        assert_eq!(example_parsed.start_symbol, "postal.address");
+        assert_eq!(example_parsed.rules.len(), 7);
     }
 
     #[test]
@@ -384,19 +1631,299 @@ mod tests {
 
         assert_eq!(example_parsed, vec![
             CompileError {
-                location: Location {
-                    file: example_path.clone(),
-                    line: 3
-                },
+                location: Location::line(SourceName::file(&example_path), 3),
                 error: CompileErrorType::MissingNonterminal
             },
             CompileError {
-                location: Location {
-                    file: example_path,
-                    line: 7
-                },
+                location: Location::line(SourceName::file(&example_path), 7),
                 error: CompileErrorType::UnexpectedEquals
             }
         ]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_malformed_file_partially() {
+        let example_path = PathBuf::from("example_data/malformed.bnf");
+        let (grammar, errors, warnings) = parse_file_partial(&example_path);
+
+        // Every line but the two broken ones is kept, even with undefined
+        // symbols in it
+        assert_eq!(grammar.start_symbol, "postal.address");
+        assert_eq!(grammar.rules.keys().sorted().collect_vec(), vec![
+            "name.part", "opt.suffix.part", "postal.address", "street.address", "zip.part"
+        ]);
+        assert_eq!(grammar.location("zip.part"), Location::line(SourceName::file(&example_path), 5));
+        assert_eq!(warnings, vec![]);
+
+        let lines = errors.iter().map(|error| error.location.line.unwrap()).collect_vec();
+        assert_eq!(lines, vec![2, 2, 2, 3, 4, 4, 4, 5, 5, 5, 6, 7]);
+        assert_eq!(errors[3].error, CompileErrorType::MissingNonterminal);
+        assert_eq!(errors[4].error, CompileErrorType::UndefinedNonterminal("house.num".to_string()));
+        assert_eq!(errors[11].error, CompileErrorType::UnexpectedEquals);
+
+        let (grammar, errors, _) = parse_file_partial(&PathBuf::from("example_data/missing.bnf"));
+        assert!(grammar.rules.is_empty());
+        assert_eq!(errors.iter().map(|error| &error.error).collect_vec(), vec![
+            &CompileErrorType::FileError(std::io::ErrorKind::NotFound.into())
+        ]);
+    }
+
+    #[test]
+    fn parse_grammar_argument() {
+        let (grammar, _) = parse_arg_with_options(r#"greeting = "hello" | "hi;\n" name ; name = "you"\nother = "x""#, &ParseOptions::default()).unwrap();
+        assert_eq!(grammar.start_symbol, "greeting");
+        assert_eq!(grammar.rules.len(), 3);
+        // Separators inside quotes are left alone, with `\n` still a newline
+        assert_eq!(grammar.rules.rewrite("greeting").to_rewrite()[1][0], Symbol::Terminal("hi;\n".into()));
+
+        // Raw terminals are copied whole, even with quotes inside
+        let (grammar, _) = parse_arg_with_options(r##"a = r#"say "x;y""# b;b = r"\n""##, &ParseOptions::default()).unwrap();
+        assert_eq!(grammar.rules.rewrite("a").to_rewrite()[0][0], Symbol::Terminal("say \"x;y\"".into()));
+        assert_eq!(grammar.rules.rewrite("b").to_rewrite()[0][0], Symbol::Terminal("\\n".into()));
+
+        let errors = parse_arg_with_options("a = b; = c", &ParseOptions::default()).unwrap_err();
+        assert_eq!(errors.iter().map(|error| error.location.to_string()).collect_vec(), vec!["<arg>:2"]);
+    }
+
+    #[test]
+    fn attach_annotations() {
+        let text = concat!(
+            ";@ tag formal\n",
+            ";@ weight hello=5 hi=1\n",
+            ";@   tag   polite\n",
+            "greeting = \"hello\" | \"hi\"\n",
+            ";@ colour blue\n",
+            "name = \"Ann\"\n",
+            "plain = \"x\"\n",
+        );
+        let (grammar, warnings) = parse_str_with_options(text, SourceName::Builtin, &ParseOptions::default()).unwrap();
+        assert_eq!(grammar.annotations["greeting"], Annotations::from([
+            ("tag".to_string(), vec!["formal".to_string(), "polite".to_string()]),
+            ("weight".to_string(), vec!["hello=5".to_string(), "hi=1".to_string()]),
+        ]));
+        assert_eq!(grammar.annotations["name"]["colour"], vec!["blue"]);
+        assert!(!grammar.annotations.contains_key("plain"));
+        assert_eq!(warnings, vec![CompileWarning {
+            location: Location::line(SourceName::Builtin, 5),
+            warning: CompileWarningType::UnknownAnnotation("colour".to_string())
+        }]);
+    }
+
+    #[test]
+    fn detached_annotations() {
+        let errors_in = |text: &str| {
+            let (_, errors, _) = parse_str_partial(text, SourceName::Builtin);
+            errors.into_iter().map(|error| (error.location.line.unwrap(), error.error)).collect_vec()
+        };
+        // A blank line or comment between them detaches annotations from the
+        // rule, and the rule is left without them
+        let text = ";@ tag a\n\nrule = \"x\"\n;@ tag b\n; a comment\nother = \"y\"\n";
+        assert_eq!(errors_in(text), vec![(1, CompileErrorType::DetachedAnnotation), (4, CompileErrorType::DetachedAnnotation)]);
+        assert!(parse_str_partial(text, SourceName::Builtin).0.annotations.is_empty());
+        // So does the end of the file
+        assert_eq!(errors_in("rule = \"x\"\n;@ tag a\n;@ tag b"), vec![(2, CompileErrorType::DetachedAnnotation)]);
+        assert_eq!(errors_in(";@\nrule = \"x\"\n"), vec![(1, CompileErrorType::EmptyAnnotation)]);
+        // A rule with an error still takes its annotations
+        assert_eq!(errors_in(";@ tag a\n= \"x\"\nrule = \"x\"\n"), vec![(2, CompileErrorType::MissingNonterminal)]);
+    }
+
+    #[test]
+    fn check_distinct_annotations() {
+        for good in [";@ distinct\nrule = \"x\"", ";@ distinct 20\nrule = \"x\""] {
+            assert!(parse_str(good, SourceName::Builtin).is_ok());
+        }
+        for (bad, values) in [(";@ distinct 0\nrule = \"x\"", "0"), (";@ distinct many\nrule = \"x\"", "many"), (";@ distinct 1 2\nrule = \"x\"", "1 2")] {
+            let errors = parse_str(bad, SourceName::Builtin).unwrap_err();
+            assert_eq!(errors[0].location.line, Some(1));
+            assert_eq!(errors[0].error.to_string(), format!("Expected `;@ distinct` to be followed by nothing or a number of tries of at least 1, found `{}`", values));
+        }
+    }
+
+    #[test]
+    fn check_tag_annotations() {
+        assert!(parse_str(";@ tag formal casual=1\n;@ tag polite=0,1\nrule = \"x\" | \"y\"", SourceName::Builtin).is_ok());
+        let errors = parse_str(";@ tag\na = \"x\"\n;@ tag for.mal casual=\nb = \"x\"\n;@ tag casual=0,2\nc = \"x\" | \"y\"", SourceName::Builtin).unwrap_err();
+        assert_eq!(errors.iter().map(|error| (error.location.line.unwrap(), error.error.to_string())).collect_vec(), vec![
+            (1, "Expected `;@ tag` to be followed by tags like `formal`, or `formal=0,2` for only some alternatives, found ``".to_string()),
+            (3, "Expected `;@ tag` to be followed by tags like `formal`, or `formal=0,2` for only some alternatives, found `for.mal`".to_string()),
+            (3, "Expected `;@ tag` to be followed by tags like `formal`, or `formal=0,2` for only some alternatives, found `casual=`".to_string()),
+            (6, "`c` has no alternative 2 to tag `casual` (it has 2, counting from 0)".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn check_max_depth_annotations() {
+        let options = ParseOptions::default();
+        let (_, warnings) = parse_str_with_options(concat!(
+            ";@ max-depth 2\n",
+            "clause = \"that \" clause | \"it rained\"\n",
+            ";@ max-depth 1\n",
+            "noun = \"dog\" | adjective noun\n",
+            "adjective = \"big\"\n",
+            ";@ max-depth 1\n",
+            "sentence = clause\n",
+        ), SourceName::Builtin, &options).unwrap();
+        assert_eq!(warnings.iter().map(|warning| (warning.location.line.unwrap(), warning.warning.to_string())).collect_vec(), vec![
+            (7, "`sentence` has a `;@ max-depth`, but it can't expand inside itself, so the cap does nothing".to_string()),
+        ]);
+        assert_eq!(warnings[0].warning.code(), "useless-max-depth");
+
+        let errors = parse_str(";@ max-depth\na = \"x\"\n;@ max-depth 0\nb = \"x\"\n;@ max-depth 1 2\nc = \"x\"", SourceName::Builtin).unwrap_err();
+        assert_eq!(errors.iter().map(|error| error.location.line.unwrap()).collect_vec(), vec![1, 3, 5]);
+        assert_eq!(errors[1].error.to_string(), "Expected `;@ max-depth` to be followed by how many expansions of the rule can be inside each other, at least 1, found `0`");
+    }
+
+    #[test]
+    fn include_other_files() {
+        let dir = std::env::temp_dir().join(format!("blabber-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("shared")).unwrap();
+        std::fs::write(dir.join("shared/names.bnf"), "name = \"Ada\" | title\n%include \"titles.bnf\"").unwrap();
+        std::fs::write(dir.join("shared/titles.bnf"), "title = \"Dr\"\nbroken = \"").unwrap();
+        let path = dir.join("greeting.bnf");
+        std::fs::write(&path, "%include \"shared/names.bnf\"\ngreeting = \"hello \" name\n%include \"missing.bnf\"\n%include names.bnf").unwrap();
+
+        // Included rules come from their own files, and the start symbol is
+        // still the including file's first rule
+        let (grammar, errors, _) = parse_file_partial(&path);
+        assert_eq!(grammar.start_symbol, "greeting");
+        assert_eq!(grammar.rules.keys().collect_vec(), vec!["name", "title", "greeting"]);
+        assert_eq!(grammar.location("title"), Location::line(SourceName::file(dir.join("shared/titles.bnf")), 1));
+        let errors = errors.iter().map(|error| (error.location.to_string(), error.error.to_string())).collect_vec();
+        assert_eq!(errors, vec![
+            (format!("{}:3", path.display()), format!("Couldn't include `{}`: No such file or directory (os error 2)", dir.join("missing.bnf").display())),
+            (format!("{}:4", path.display()), "Expected `%include` to be followed by a quoted path or URL, found `names.bnf`".to_string()),
+            (format!("{}:2", dir.join("shared/titles.bnf").display()), "Unmatched quote: the quote opened at column 10 was never closed; consumed 0 characters to end of line".to_string()),
+        ]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_each_file_once() {
+        let dir = std::env::temp_dir().join(format!("blabber-include-once-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("shared")).unwrap();
+        std::fs::write(dir.join("shared/words.bnf"), "word = \"hi\"").unwrap();
+        std::fs::write(dir.join("shared/names.bnf"), "%include \"words.bnf\"\nname = word").unwrap();
+        std::fs::write(dir.join("a.bnf"), "%include \"b.bnf\"\nnoun = \"x\"").unwrap();
+        std::fs::write(dir.join("b.bnf"), "%include \"shared/../a.bnf\"\nverb = \"y\"").unwrap();
+        let path = dir.join("main.bnf");
+        std::fs::write(&path, "s = name word\n%include \"shared/names.bnf\"\n%include \"shared/../shared/words.bnf\"\n%include \"shared/words.bnf\"\n%include \"a.bnf\"").unwrap();
+
+        // Files included again by another path are left out, without
+        // defining their rules twice
+        let (grammar, errors, _) = parse_file_partial(&path);
+        assert_eq!(grammar.rules.keys().collect_vec(), vec!["s", "word", "name", "verb", "noun"]);
+        let errors = errors.iter().map(|error| (error.location.to_string(), error.error.to_string())).collect_vec();
+        assert_eq!(errors, vec![(
+            format!("{}:1", dir.join("b.bnf").display()),
+            format!("Includes form a cycle: {} → {} → {}", dir.join("a.bnf").display(), dir.join("b.bnf").display(), dir.join("shared/../a.bnf").display()),
+        )]);
+
+        // Including itself is a cycle too
+        std::fs::write(&path, "s = \"x\"\n%include \"main.bnf\"").unwrap();
+        let errors = parse_file(&path).unwrap_err();
+        assert_eq!(errors[0].location, Location::line(SourceName::file(&path), 2));
+        assert_eq!(errors[0].error, CompileErrorType::IncludeCycle(Vec::new()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn limit_include_depth() {
+        let dir = std::env::temp_dir().join(format!("blabber-include-depth-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for i in 0..5 {
+            std::fs::write(dir.join(format!("{}.bnf", i)), format!("r{} = \"x\"\n%include \"{}.bnf\"", i, i + 1)).unwrap();
+        }
+        std::fs::write(dir.join("5.bnf"), "r5 = \"x\"").unwrap();
+
+        let path = dir.join("0.bnf");
+        assert_eq!(parse_file(&path).unwrap().rules.len(), 6);
+        let options = ParseOptions { max_include_depth: 4, ..Default::default() };
+        let errors = parse_file_with_options(&path, &options).unwrap_err();
+        assert_eq!(errors.iter().map(|error| (error.location.to_string(), error.error.to_string())).collect_vec(), vec![
+            (format!("{}:2", dir.join("4.bnf").display()), "Includes are nested more than 4 deep (raise it with --max-include-depth)".to_string()),
+        ]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn warn_about_unreachable_rules() {
+        let text = "sentence = greeting name\ngreeting = \"hi \" | \"hello \"\nname = \"Ann\"\nfarewell = \"bye \" name\n_spare = [\"x\"]";
+        let (_, warnings) = parse_str_with_options(text, SourceName::Builtin, &ParseOptions::default()).unwrap();
+        assert_eq!(warnings, vec![]);
+
+        let options = ParseOptions { warn_unreachable: true, ..Default::default() };
+        let (_, warnings) = parse_str_with_options(text, SourceName::Builtin, &options).unwrap();
+        assert_eq!(warnings.iter().map(|warning| (warning.location.line.unwrap(), warning.warning.to_string())).collect_vec(), vec![
+            (4, "`farewell` can't be reached from the start symbol, so it's never used".to_string()),
+            (5, "`_spare` can't be reached from the start symbol, so it's never used".to_string()),
+        ]);
+        assert_eq!(warnings[0].warning.code(), "unreachable-rule");
+
+        let mut options = ParseOptions { warn_unreachable: true, ..Default::default() };
+        options.warning_levels.set("unreachable-rule", Level::Deny);
+        let errors = parse_str_with_options(text, SourceName::Builtin, &options).unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].error.to_string(), "Denied warning: `farewell` can't be reached from the start symbol, so it's never used\n  = note: requested with `-D unreachable-rule`");
+
+        let mut options = ParseOptions { warn_unreachable: true, ..Default::default() };
+        options.warning_levels.set("unreachable-rule", Level::Allow);
+        let (_, warnings) = parse_str_with_options(text, SourceName::Builtin, &options).unwrap();
+        assert_eq!(warnings, vec![]);
+    }
+
+    #[test]
+    fn warn_about_deprecated_rules() {
+        let text = "s = old \" \" old:upper | new\n%deprecated old \"use `new` instead\"\nold = \"x\" | old\nnew = old";
+        let (grammar, warnings) = parse_str_with_options(text, SourceName::Builtin, &ParseOptions::default()).unwrap();
+        assert_eq!(grammar.deprecations["old"], Deprecation { message: "use `new` instead".to_string(), location: Location::line(SourceName::Builtin, 2) });
+        // Each reference is warned about at its rule, except old's own
+        let warnings = warnings.iter().map(|warning| (warning.location.line, warning.warning.to_string())).collect_vec();
+        assert_eq!(warnings, vec![
+            (Some(1), "`old` is deprecated: use `new` instead".to_string()),
+            (Some(1), "`old` is deprecated: use `new` instead".to_string()),
+            (Some(4), "`old` is deprecated: use `new` instead".to_string()),
+        ]);
+        let options = ParseOptions { warning_levels: WarningLevels::deny_all(), ..Default::default() };
+        let errors = parse_str_with_options(text, SourceName::Builtin, &options).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(&errors[0].error, CompileErrorType::DeniedWarning { warning: CompileWarningType::Deprecated { .. }, .. }));
+
+        // It survives being written out again
+        let bnf = to_bnf(&grammar, &definition_order(&grammar), &Trivia::default()).unwrap();
+        assert_eq!(bnf, "s = old \" \" old:upper | new\n%deprecated old \"use `new` instead\"\nold = \"x\" | old\nnew = old\n");
+
+        // Deprecating what isn't there is an error, unless it may be defined
+        // elsewhere
+        let (_, errors, _) = parse_str_partial("s = \"x\"\n%deprecated gone\n%deprecated s extra", SourceName::Builtin);
+        assert_eq!(errors.iter().map(|error| error.error.to_string()).collect_vec(), vec![
+            "Could not find definition for deprecated `gone`",
+            "Expected `%deprecated` to be followed by a rule name and optionally a quoted message, found `s extra`",
+        ]);
+        let unverified = ParseOptions { verify: false, ..Default::default() };
+        let (grammar, _) = parse_str_with_options("s = \"x\"\n%deprecated gone", SourceName::Builtin, &unverified).unwrap();
+        assert_eq!(verify_grammar(&grammar)[0].location.line, Some(2));
+    }
+
+    #[test]
+    fn opt_in_to_dynamics() {
+        let text = "%use dynamics\ns = \"id \" @uuid | @float(0, 1, 3) t\nt = @date(\"%F\", 2020-01-01, 2020-12-31)";
+        let grammar = parse_str(text, SourceName::Builtin).unwrap();
+        assert_eq!(grammar.rules.rewrite("s").to_rewrite()[0], vec![s_terminal("id "), Symbol::Dynamic(Box::new(Dynamic::Uuid))]);
+        assert_eq!(grammar.rules.rewrite("s").to_rewrite()[1][0], Symbol::Dynamic(Box::new(Dynamic::Float { min: 0, max: 1000, places: 3 })));
+        // It survives being written out again
+        let bnf = to_bnf(&grammar, &definition_order(&grammar), &Trivia::default()).unwrap();
+        assert_eq!(bnf, format!("{}\n", text));
+
+        // Without `%use dynamics` each rule using one is an error, at the
+        // rule, and so is a bad one or an unknown feature either way
+        let (_, errors, _) = parse_str_partial("s = @uuid @uuid\nt = \"x\"\nu = @float(2, 1, 0)", SourceName::Builtin);
+        assert_eq!(errors.iter().map(|error| (error.location.line, error.error.to_string())).collect_vec(), vec![
+            (Some(1), "`@uuid` is a dynamic symbol, which needs a `%use dynamics` line in the file using it".to_string()),
+            (Some(3), "`@float` ends before it starts: 1 is below 2".to_string()),
+        ]);
+        let (_, errors, _) = parse_str_partial("%use dynamite\ns = \"x\"", SourceName::Builtin);
+        assert_eq!(errors.iter().map(|error| (error.location.line, error.error.to_string())).collect_vec(), vec![
+            (Some(1), "Unknown feature `dynamite` after `%use` (the only feature is `dynamics`)".to_string()),
+        ]);
+    }
+}
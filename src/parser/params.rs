@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+
+use crate::grammar::{Rewrite, Symbol, SymbolRef};
+use super::{CompileError, CompileErrorType, FileResult, Grammar, Result};
+
+// Replaces every `{name}` in a terminal with the value of the parameter of
+// that name. `{{` and `}}` stand for literal braces. Values are inserted as
+// they are, so braces in them are never expanded. The names of parameters
+// that weren't given are added to missing.
+fn substitute_terminal(text: &str, params: &HashMap<String, String>, missing: &mut Vec<String>) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                result.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                result.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some('{') | None => return Err(CompileErrorType::UnmatchedBrace),
+                        Some(c) => name.push(c),
+                    }
+                }
+                match params.get(&name) {
+                    Some(value) => result.push_str(value),
+                    None => missing.push(name),
+                }
+            }
+            '}' => return Err(CompileErrorType::UnmatchedBrace),
+            c => result.push(c),
+        }
+    }
+
+    return Ok(result);
+}
+
+// Substitutes parameters into every terminal of the grammar. This happens
+// once after parsing, so generation isn't slowed down by it. Every rule using
+// parameters that weren't given gets an error listing them.
+pub fn substitute_params(grammar: &mut Grammar, params: &HashMap<String, String>) -> FileResult<()> {
+    let mut errors = Vec::new();
+    let mut substituted = Vec::new();
+
+    for (rule, rewrite) in grammar.rules.iter() {
+        let mut missing = Vec::new();
+        let mut rule_error = None;
+
+        let mut changed = false;
+        let alternatives: Rewrite = rewrite.iter()
+            .map(|alternative| alternative.iter().map(|symbol| match symbol {
+                SymbolRef::Terminal(text) => match substitute_terminal(text, params, &mut missing) {
+                    Ok(substituted) => {
+                        changed |= substituted != text;
+                        Symbol::Terminal(substituted.into())
+                    }
+                    Err(error) => {
+                        rule_error = Some(error);
+                        symbol.to_symbol()
+                    }
+                },
+                _ => symbol.to_symbol(),
+            }).collect())
+            .collect();
+        if changed {
+            substituted.push((rule.to_string(), alternatives));
+        }
+
+        if !missing.is_empty() {
+            missing.sort();
+            missing.dedup();
+            rule_error.get_or_insert(CompileErrorType::MissingParameters(missing));
+        }
+        if let Some(error) = rule_error {
+            errors.push(CompileError {
+                location: grammar.location(rule),
+                error
+            });
+        }
+    }
+
+    if !errors.is_empty() {
+        errors.sort_by_key(|error| error.location.line);
+        return Err(errors);
+    }
+    for (rule, rewrite) in substituted {
+        grammar.rules.insert(&rule, &rewrite);
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::{Location, SourceName};
+    use crate::parser::parse_str;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn substitute_terminals() {
+        let params = params(&[("company", "Acme"), ("year", "2025"), ("braces", "{year}")]);
+        let substitute = |text: &str| {
+            let mut missing = Vec::new();
+            let result = substitute_terminal(text, &params, &mut missing);
+            (result, missing)
+        };
+
+        assert_eq!(substitute("© {company} {year}"), (Ok("© Acme 2025".to_string()), vec![]));
+        assert_eq!(substitute("{{company}} {{{company}}}"), (Ok("{company} {Acme}".to_string()), vec![]));
+        assert_eq!(substitute("{braces}"), (Ok("{year}".to_string()), vec![]));
+        assert_eq!(substitute("{name} and {title}").1, vec!["name".to_string(), "title".to_string()]);
+        assert_eq!(substitute("{company").0, Err(CompileErrorType::UnmatchedBrace));
+        assert_eq!(substitute("company}").0, Err(CompileErrorType::UnmatchedBrace));
+    }
+
+    #[test]
+    fn substitute_grammar() {
+        let mut grammar = parse_str(concat!(
+            "sentence = \"Welcome to {company}\" | year\n",
+            "year = \"Since {year}\"\n",
+        ), SourceName::Builtin).unwrap();
+
+        substitute_params(&mut grammar, &params(&[("company", "Acme"), ("year", "1999")])).unwrap();
+        assert_eq!(grammar.rules.rewrite("sentence").to_rewrite()[0], vec![Symbol::Terminal("Welcome to Acme".into())]);
+        assert_eq!(grammar.rules.rewrite("year").to_rewrite()[0], vec![Symbol::Terminal("Since 1999".into())]);
+    }
+
+    #[test]
+    fn report_missing_params() {
+        let mut grammar = parse_str(concat!(
+            "sentence = \"{greeting}, {name}\" | \"{name}\" year\n",
+            "year = \"Since {year}\"\n",
+        ), SourceName::Builtin).unwrap();
+
+        let errors = substitute_params(&mut grammar, &params(&[("year", "1999")])).unwrap_err();
+        assert_eq!(errors, vec![CompileError {
+            location: Location::line(SourceName::Builtin, 1),
+            error: CompileErrorType::MissingParameters(vec!["greeting".to_string(), "name".to_string()])
+        }]);
+        assert_eq!(errors[0].error.to_string(), "Missing parameters: `greeting`, `name`");
+    }
+}
@@ -0,0 +1,84 @@
+/*
+    A grammar's rules as its lines are parsed. Each rule is added straight
+    into the grammar's tables, where every name and terminal is kept once
+    however often it's used, so the text the rules were parsed from only
+    needs to last until they're added. Since a nonterminal only refers to a
+    rule by name, a rule can be used before it's defined, and once every line
+    is parsed, the grammar only has to be checked for undefined symbols if
+    some are left.
+*/
+
+use std::collections::HashMap;
+
+use super::*;
+
+pub(super) struct Ruleset {
+    // The source being parsed, whose own first rule is the start symbol
+    source: SourceName,
+    rules: Rules,
+    annotations: HashMap<Name, Annotations>,
+    weights: HashMap<Name, Weights>,
+    // The first rule of the source, and the first rule of all, which are
+    // different when it starts with an `%include`
+    start: Option<String>,
+    first: Option<String>,
+}
+
+impl Ruleset {
+    pub fn new(source: &SourceName) -> Self {
+        Ruleset {
+            source: source.clone(),
+            rules: Rules::new(),
+            annotations: HashMap::new(),
+            weights: HashMap::new(),
+            start: None,
+            first: None,
+        }
+    }
+
+    // Adds a rule. A rule defined again replaces the earlier one's
+    // annotations and weights too, and is ordered where it was last defined,
+    // with the rules for its earlier brackets gone and its new ones after it.
+    pub fn define(&mut self, rule: Rule) {
+        if self.start.is_none() && rule.location.source == self.source {
+            self.start = Some(rule.symbol.to_string());
+        }
+        self.first.get_or_insert_with(|| rule.symbol.to_string());
+
+        if self.rules.shift_remove(&rule.symbol) {
+            self.annotations.remove(&*rule.symbol);
+            self.weights.remove(&*rule.symbol);
+            // Nothing should use the rules of the earlier brackets, but if
+            // something does, it's found undefined
+            if Bracket::of_rule(&rule.symbol).is_none() {
+                self.rules.retain(|name, _| Bracket::of_rule(name).is_none_or(|(of, _)| of != &*rule.symbol));
+            }
+        }
+        let index = self.rules.insert(&rule.symbol, rule.rewrite.alternatives());
+        self.rules.set_location(index, &rule.location);
+        if !rule.annotations.is_empty() {
+            self.annotations.insert(Name::from(&*rule.symbol), rule.annotations);
+        }
+        if let Some(weights) = rule.weights {
+            self.weights.insert(Name::from(&*rule.symbol), weights.into());
+        }
+    }
+
+    // The grammar of the rules, with the given deprecations, and the
+    // undefined symbols found if the options say to look for them
+    pub fn into_grammar(self, deprecations: Vec<(String, Deprecation)>, options: &ParseOptions) -> (Grammar, CompileErrors) {
+        let errors = if options.verify && self.rules.has_undefined() {
+            verify_rules(&self.rules)
+        } else {
+            Vec::new()
+        };
+        let grammar = Grammar {
+            start_symbol: self.start.or(self.first).unwrap_or_default(),
+            rules: self.rules,
+            annotations: self.annotations,
+            weights: self.weights,
+            deprecations: deprecations.into_iter().map(|(rule, deprecation)| (rule.into(), deprecation)).collect()
+        };
+        return (grammar, errors);
+    }
+}
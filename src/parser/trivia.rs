@@ -0,0 +1,146 @@
+/*
+    Reads the lines of a BNF grammar which aren't rules, like comments, so
+    `blabber fmt` can write them back where they were.
+
+    Comments directly above a rule are kept above it, and comments directly
+    below a rule are kept below it if a blank line or the end of the file
+    comes after them. Blank lines are kept, with several in a row kept as
+    one, so rules stay grouped into sections. `%include` lines are kept like
+    comments. Annotations, `%deprecated` and `%use` lines aren't trivia,
+    since they're part of the grammar.
+*/
+
+use super::*;
+
+// The name a rule line defines
+fn rule_name(line: &str) -> String {
+    line.split_once('=').map_or(line, |(name, _)| name).trim().to_string()
+}
+
+// Splits the lines between two rules into the ones below the first, which
+// are the ones before a blank line, and the ones above the second
+fn split_pending(pending: Vec<String>) -> (Vec<String>, Vec<String>) {
+    match pending.iter().position(String::is_empty) {
+        Some(blank) => {
+            let mut trailing = pending;
+            let leading = trailing.split_off(blank);
+            (trailing, leading)
+        }
+        None => (Vec::new(), pending),
+    }
+}
+
+pub fn read_trivia(text: &str) -> Trivia {
+    let mut trivia = Trivia::default();
+    // The lines since the last rule, and that rule
+    let mut pending: Vec<String> = Vec::new();
+    let mut last: Option<String> = None;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            if pending.last().is_none_or(|last| !last.is_empty()) {
+                pending.push(String::new());
+            }
+        } else if line.starts_with(";@") || is_deprecation(line) || is_use(line) {
+            continue;
+        } else if !is_rule_line(line) || is_include(line) {
+            pending.push(line.to_string());
+        } else {
+            let name = rule_name(line);
+            let leading = match &last {
+                Some(last) => {
+                    let (trailing, leading) = split_pending(std::mem::take(&mut pending));
+                    trivia.rules.entry(last.clone()).or_default().trailing.extend(trailing);
+                    leading
+                }
+                None => std::mem::take(&mut pending),
+            };
+            trivia.rules.entry(name.clone()).or_default().leading.extend(leading);
+            last = Some(name);
+        }
+    }
+
+    // Lines after the last rule are below it up to a blank line
+    if pending.last().is_some_and(String::is_empty) {
+        pending.pop();
+    }
+    match last {
+        Some(last) => {
+            let blank = pending.iter().position(String::is_empty).unwrap_or(pending.len());
+            trivia.end = pending.split_off(blank);
+            trivia.rules.entry(last).or_default().trailing.extend(pending);
+        }
+        None => trivia.end = pending,
+    }
+    return trivia;
+}
+
+// Parses a grammar file like parse_file_with_options, along with the trivia
+// around its rules. Compiled and YAML grammars have no trivia.
+pub fn parse_file_with_trivia(path: &PathBuf, options: &ParseOptions) -> FileResult<(Grammar, CompileWarnings, Trivia)> {
+    if compiled::is_compiled(path) || file_syntax(path, options) == Syntax::Yaml {
+        return parse_file_with_options(path, options).map(|(grammar, warnings)| (grammar, warnings, Trivia::default()));
+    }
+    let source = if path.as_os_str() == "-" { SourceName::Stdin } else { SourceName::file(path) };
+    let text = read_source(path).map_err(|error| vec![io_error(error, source.clone())])?;
+    let options = ParseOptions { syntax: Some(Syntax::Bnf), ..options.clone() };
+    let (grammar, warnings) = parse_str_with_options(&text, source, &options)?;
+    return Ok((grammar, warnings, read_trivia(&text)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_comments_where_they_were() {
+        let text = concat!(
+            "; A grammar\n",
+            "\n",
+            "; The start\n",
+            "s = noun \" \" verb\n",
+            "; more verbs later\n",
+            "\n\n",
+            "; -- Words --\n",
+            "\n",
+            ";@ tag word\n",
+            "noun = \"cat\"   \n",
+            "%include \"verbs.bnf\"\n",
+            "verb = \"sat\"\n",
+            "; below verb\n",
+            "\n",
+            "; the end\n",
+            "\n",
+        );
+        let trivia = read_trivia(text);
+        let lines = |lines: &[&str]| lines.iter().map(|line| line.to_string()).collect::<Vec<_>>();
+        assert_eq!(trivia.rules["s"], RuleTrivia { leading: lines(&["; A grammar", "", "; The start"]), trailing: lines(&["; more verbs later"]) });
+        assert_eq!(trivia.rules["noun"], RuleTrivia { leading: lines(&["", "; -- Words --", ""]), trailing: Vec::new() });
+        assert_eq!(trivia.rules["verb"], RuleTrivia { leading: lines(&["%include \"verbs.bnf\""]), trailing: lines(&["; below verb"]) });
+        assert_eq!(trivia.end, lines(&["", "; the end"]));
+    }
+
+    #[test]
+    fn format_commented_grammars_idempotently() {
+        let format = |text: &str| {
+            let grammar = parse_str(text, SourceName::Builtin).unwrap();
+            return to_bnf(&grammar, &definition_order(&grammar), &read_trivia(text)).unwrap();
+        };
+        for path in ["example_data/english.bnf", "example_data/postal_address_filled_in.bnf"] {
+            let text = std::fs::read_to_string(path).unwrap();
+            let formatted = format(&text);
+            assert_eq!(format(&formatted), formatted);
+            for comment in text.lines().filter(|line| line.starts_with(';')) {
+                assert!(formatted.lines().any(|line| line == comment.trim_end()), "{} lost {:?}", path, comment);
+            }
+        }
+
+        // A rule defined again keeps the comments of both definitions
+        let text = "; first s\ns = \"a\"\n\n; second s\ns   =   \"b\"\n; after\n\n\n; end\n";
+        assert_eq!(format(text), "; first s\n\n; second s\ns = \"b\"\n; after\n\n; end\n");
+
+        // `%deprecated` is written directly above its rule's annotations
+        let text = "s = old\n%deprecated old \"use s\"\n; old\n;@ tag x\nold = \"x\"\n";
+        assert_eq!(format(text), "s = old\n; old\n%deprecated old \"use s\"\n;@ tag x\nold = \"x\"\n");
+    }
+}
@@ -1,51 +1,63 @@
-use std::collections::HashMap;
-
-use crate::grammar::Symbol::Nonterminal;
+use crate::grammar::SymbolRef::Nonterminal;
 use super::CompileErrorType::UndefinedNonterminal;
-use super::{Alternative, CompileError, CompileErrors, FileResult, Location, Rewrite};
-
-pub type IntermediateRuleset = HashMap<String, (Rewrite, Location)>;
+use crate::grammar::{AlternativeRef, Grammar, RewriteRef, Rules};
+use super::deprecated::undefined_deprecations;
+use super::{CompileError, CompileErrors, Location};
 
-fn get_alternative_undefined_symbols(alternative: &Alternative, location: &Location, rules: &IntermediateRuleset) -> CompileErrors {
+fn get_alternative_undefined_symbols(alternative: AlternativeRef<'_>, location: &Location, is_defined: &impl Fn(&str) -> bool) -> CompileErrors {
     // Filter out everything but nonterminals and unwrap the text from the
     // nonterminals. Then filter out all the undefined nonterminals.
     alternative.iter()
         .filter_map(|symbol| match symbol {
-            Nonterminal(symbol) => Some(symbol),
+            Nonterminal(symbol, _) => Some(symbol),
             _ => None
         })
-        .filter(|symbol| !rules.contains_key(*symbol))
+        .filter(|symbol| !is_defined(symbol))
         .map(|symbol_text| CompileError {
             location: location.to_owned(),
-            error: UndefinedNonterminal(symbol_text.to_owned())
+            error: UndefinedNonterminal(symbol_text.to_string())
         })
         .collect()
 }
 
-fn get_rewrite_undefined_symbols(rewrite: &Rewrite, location: &Location, rules: &IntermediateRuleset) -> CompileErrors {
+fn get_rewrite_undefined_symbols(rewrite: RewriteRef<'_>, location: &Location, is_defined: &impl Fn(&str) -> bool) -> CompileErrors {
     // Get the undefined nonterminals in each alternative, while flattening
     // into all the undefined nonterminals in the rewrite
     rewrite.iter()
-        .flat_map(|alternative| get_alternative_undefined_symbols(alternative, location, rules))
+        .flat_map(|alternative| get_alternative_undefined_symbols(alternative, location, is_defined))
         .collect()
 }
 
-fn get_undefined_symbols(rules: &IntermediateRuleset) -> CompileErrors {
+fn get_undefined_symbols(rules: &Rules) -> CompileErrors {
+    let is_defined = |symbol: &str| rules.contains_key(symbol);
     // Get the undefined nonterminals in each rewrite, while flattening
-    // into all the undefined nonterminals in the hashmap
+    // into all the undefined nonterminals in the rules. Only rules using
+    // one have their locations looked up.
     rules.iter()
-        .flat_map(|(_, (rewrite, location))| get_rewrite_undefined_symbols(rewrite, location, rules))
+        .filter(|(_, rewrite)| rewrite.iter().flatten().any(|symbol| matches!(symbol, Nonterminal(symbol, _) if !is_defined(symbol))))
+        .flat_map(|(symbol, rewrite)| {
+            let location = rules.location(symbol).expect("parsed rules have locations");
+            get_rewrite_undefined_symbols(rewrite, &location, &is_defined)
+        })
         .collect()
 }
 
-pub fn verify_rules(rules: &IntermediateRuleset) -> FileResult<()> {
+// Returns every problem found in the rules, located with where each rule
+// was defined
+pub fn verify_rules(rules: &Rules) -> CompileErrors {
     let mut errors = Vec::new();
 
-    errors.extend(get_undefined_symbols(&rules).into_iter());
+    errors.extend(get_undefined_symbols(rules));
 
-    if errors.len() > 0 {
-        Err(errors)
-    } else {
-        Ok(())
-    }
-}
\ No newline at end of file
+    return errors;
+}
+
+// Returns every problem found in a grammar that was put together without
+// verifying it
+pub fn verify_grammar(grammar: &Grammar) -> CompileErrors {
+    let is_defined = |symbol: &str| grammar.rules.contains_key(symbol);
+    grammar.rules.iter()
+        .flat_map(|(symbol, rewrite)| get_rewrite_undefined_symbols(rewrite, &grammar.location(symbol), &is_defined))
+        .chain(undefined_deprecations(grammar))
+        .collect()
+}
@@ -0,0 +1,316 @@
+/*
+    Grammars can be written as YAML, to keep them alongside other YAML
+    configuration, like
+
+        start: sentence
+        rules:
+          sentence: [[{nt: noun}, " sleep furiously"]]
+          noun: [["ideas"], [{nt: noun}, " and ", {nt: noun}]]
+
+    Each rule is a list of alternatives, and each alternative a list of
+    symbols. Strings are terminals and `{nt: NAME}` maps are nonterminals,
+    with an optional `case: upper|lower|title`. Without `start`, the first
+    rule is the start symbol. Other keys at the top level are left alone, so
+    a grammar can share a file with other settings.
+
+    Reading YAML needs blabber built with the `yaml` feature.
+*/
+
+use std::path::Path;
+
+use crate::error_handling::{Location, SourceName};
+use super::{CompileError, CompileErrorType, CompileWarnings, FileResult, Grammar, ParseOptions};
+#[cfg(feature = "yaml")]
+use {
+    serde_yaml::{Mapping, Value},
+    std::borrow::Cow,
+    crate::grammar::{Alternative, Annotations, Case, Name, Symbol},
+    super::lexer::{self, Token},
+    super::{grammar_from_ruleset, Rule, Ruleset},
+};
+
+// The syntaxes a grammar can be written in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Syntax {
+    Bnf,
+    Yaml,
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Option<Syntax> {
+        match name {
+            "bnf" => Some(Syntax::Bnf),
+            "yaml" => Some(Syntax::Yaml),
+            _ => None
+        }
+    }
+
+    // The syntax of a file going by its extension, which is BNF unless it's
+    // `.yaml` or `.yml`
+    pub fn of_path(path: &Path) -> Syntax {
+        match path.extension() {
+            Some(extension) if extension == "yaml" || extension == "yml" => Syntax::Yaml,
+            _ => Syntax::Bnf,
+        }
+    }
+}
+
+// What sort of value a YAML value is, for schema errors
+#[cfg(feature = "yaml")]
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "nothing",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Sequence(_) => "a list",
+        Value::Mapping(_) => "a map",
+        Value::Tagged(_) => "a tagged value",
+    }
+}
+
+#[cfg(feature = "yaml")]
+fn schema(path: String, expected: &'static str, found: &Value) -> CompileErrorType {
+    CompileErrorType::YamlSchema { path, expected, found: kind(found) }
+}
+
+// Whether BNF could refer to a rule by the name, so every YAML grammar can
+// be written as BNF too
+#[cfg(feature = "yaml")]
+fn valid_name(name: &str) -> bool {
+    matches!(lexer::lex_line(name).as_deref(), Ok([Token::Nonterminal(lexed, None)]) if *lexed == name)
+}
+
+// The line a top level key or a rule is written on: the first line starting
+// with its name and a colon, after the `rules:` line for rules. serde_yaml
+// doesn't keep where values were, so this is a guess, and rules written
+// inline aren't found at all.
+#[cfg(feature = "yaml")]
+fn key_line(text: &str, name: &str, in_rules: bool) -> Option<usize> {
+    let mut lines = text.lines().enumerate();
+    if in_rules {
+        lines.find(|(_, line)| line.starts_with("rules:"))?;
+    }
+    let names = [name.to_string(), format!("\"{}\"", name), format!("'{}'", name)];
+    return lines
+        .find(|(_, line)| {
+            let line = line.trim_start();
+            names.iter().any(|name| line.strip_prefix(name.as_str()).is_some_and(|rest| rest.trim_start().starts_with(':')))
+        })
+        .map(|(num, _)| num + 1);
+}
+
+#[cfg(feature = "yaml")]
+fn parse_symbol(value: &Value, path: String) -> super::Result<Symbol> {
+    let map = match value {
+        Value::String(text) => return Ok(Symbol::Terminal(text.as_str().into())),
+        Value::Mapping(map) => map,
+        other => return Err(schema(path, "a string or an `{nt: NAME}` map", other)),
+    };
+    for key in map.keys() {
+        if key.as_str().is_none_or(|key| key != "nt" && key != "case") {
+            return Err(schema(path, "only the keys `nt` and `case`", key));
+        }
+    }
+    let name = match map.get("nt") {
+        Some(Value::String(name)) if valid_name(name) => Name::from(name.as_str()),
+        Some(name @ Value::String(_)) => return Err(schema(path + ".nt", "a rule name without spaces, quotes, `=`, `|`, or `:`", name)),
+        Some(other) => return Err(schema(path + ".nt", "a rule name", other)),
+        None => return Err(schema(path, "a map with `nt`", value)),
+    };
+    let case = match map.get("case") {
+        None => None,
+        Some(Value::String(suffix)) => Some(Case::from_suffix(suffix).ok_or_else(|| CompileErrorType::UnknownCase(suffix.clone()))?),
+        Some(other) => return Err(schema(path + ".case", "`upper`, `lower`, or `title`", other)),
+    };
+    return Ok(Symbol::Nonterminal(name, case));
+}
+
+#[cfg(feature = "yaml")]
+fn parse_rewrite(value: &Value, path: &str) -> super::Result<Vec<Alternative>> {
+    let Value::Sequence(alternatives) = value else {
+        return Err(schema(path.to_string(), "a list of alternatives", value));
+    };
+    let mut rewrite = Vec::with_capacity(alternatives.len());
+    for (index, alternative) in alternatives.iter().enumerate() {
+        let Value::Sequence(symbols) = alternative else {
+            return Err(schema(format!("{}[{}]", path, index), "a list of symbols", alternative));
+        };
+        let alternative = symbols.iter().enumerate()
+            .map(|(symbol_index, symbol)| parse_symbol(symbol, format!("{}[{}][{}]", path, index, symbol_index)))
+            .collect::<super::Result<_>>()?;
+        rewrite.push(alternative);
+    }
+    return Ok(rewrite);
+}
+
+// The start symbol a YAML grammar gives, if any, and where it was
+#[cfg(feature = "yaml")]
+type Start = Option<(String, Location)>;
+
+// Reads the rules of a YAML grammar into rules, returning its start symbol
+#[cfg(feature = "yaml")]
+fn read_document(text: &str, source: &SourceName, document: Mapping, rules: &mut Ruleset) -> FileResult<Start> {
+    let locate = |name: &str, in_rules: bool| match key_line(text, name, in_rules) {
+        Some(line) => Location::line(source.clone(), line),
+        None => Location::whole(source.clone()),
+    };
+    let start = match document.get("start") {
+        None => None,
+        Some(Value::String(start)) => Some((start.clone(), locate("start", false))),
+        Some(other) => return Err(vec![CompileError { location: locate("start", false), error: schema("start".to_string(), "a rule name", other) }]),
+    };
+    let written = match document.get("rules") {
+        Some(Value::Mapping(written)) => written,
+        Some(other) => return Err(vec![CompileError { location: locate("rules", false), error: schema("rules".to_string(), "a map from rule names to alternatives", other) }]),
+        None => return Err(vec![CompileError {
+            location: Location::whole(source.clone()),
+            error: CompileErrorType::YamlSchema { path: String::new(), expected: "a map with `rules`", found: "a map without it" }
+        }]),
+    };
+
+    let mut errors = Vec::new();
+    for (name, value) in written {
+        let name = match name {
+            Value::String(name) if valid_name(name) => name,
+            Value::String(name) => {
+                errors.push(CompileError {
+                    location: locate(name, true),
+                    error: schema(format!("rules.{}", name), "a rule name without spaces, quotes, `=`, `|`, or `:`", &Value::String(name.clone()))
+                });
+                continue;
+            }
+            other => {
+                errors.push(CompileError { location: locate("rules", false), error: schema("rules".to_string(), "rule names as keys", other) });
+                continue;
+            }
+        };
+        let location = locate(name, true);
+        match parse_rewrite(value, &format!("rules.{}", name)) {
+            Ok(rewrite) => {
+                let symbol = Cow::Borrowed(name.as_str());
+                rules.define(Rule { symbol, rewrite: rewrite.into(), location, annotations: Annotations::new(), weights: None, helpers: Vec::new() });
+            }
+            Err(error) => errors.push(CompileError { location, error }),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok(start);
+}
+
+// Parses and, if the options say to, verifies a YAML grammar. There are no
+// warnings, since the ones BNF has are about how it's written.
+#[cfg(feature = "yaml")]
+pub fn parse_yaml(text: &str, source: &SourceName, options: &ParseOptions) -> FileResult<(Grammar, CompileWarnings)> {
+    let document = match serde_yaml::from_str(text) {
+        Ok(Value::Mapping(document)) => document,
+        Ok(other) => return Err(vec![CompileError { location: Location::whole(source.clone()), error: schema(String::new(), "a map with `rules`", &other) }]),
+        Err(error) => return Err(vec![CompileError {
+            location: match error.location() {
+                Some(location) => Location::line(source.clone(), location.line()),
+                None => Location::whole(source.clone()),
+            },
+            error: CompileErrorType::Yaml(format!("Invalid YAML: {}", error))
+        }]),
+    };
+    let mut rules = Ruleset::new(source);
+    let start = read_document(text, source, document, &mut rules)?;
+
+    // YAML grammars have no `%deprecated`, so no warnings about it either
+    let (mut grammar, mut errors, _) = grammar_from_ruleset(rules, Vec::new(), source, options);
+    if let Some((start, location)) = start {
+        if options.verify && !grammar.rules.is_empty() && !grammar.rules.contains_key(start.as_str()) {
+            errors.push(CompileError { location, error: CompileErrorType::UndefinedNonterminal(start.clone()) });
+        }
+        grammar.start_symbol = start;
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok((grammar, Vec::new()));
+}
+
+#[cfg(not(feature = "yaml"))]
+pub fn parse_yaml(_text: &str, source: &SourceName, _options: &ParseOptions) -> FileResult<(Grammar, CompileWarnings)> {
+    return Err(vec![CompileError {
+        location: Location::whole(source.clone()),
+        error: CompileErrorType::Yaml("reading YAML grammars needs blabber built with the `yaml` feature".to_string())
+    }]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "yaml")]
+    use crate::parser::parse_str;
+
+    #[cfg(feature = "yaml")]
+    const GRAMMAR: &str = concat!(
+        "# settings for something else\n",
+        "version: 2\n",
+        "start: sentence\n",
+        "rules:\n",
+        "  noun: [[\"ideas\"], [{nt: noun}, \" and \", {nt: noun}]]\n",
+        "  sentence:\n",
+        "    - [{nt: noun, case: title}, \" sleep\"]\n",
+        "    - [\"\"]\n",
+    );
+
+    #[cfg(feature = "yaml")]
+    fn parse(text: &str) -> FileResult<(Grammar, CompileWarnings)> {
+        parse_yaml(text, &SourceName::file("grammar.yaml"), &ParseOptions::default())
+    }
+
+    #[cfg(feature = "yaml")]
+    fn first_error(text: &str) -> String {
+        let errors = parse(text).unwrap_err();
+        return format!("{}: {}", errors[0].location, errors[0].error);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn read_yaml_grammars() {
+        let (grammar, _) = parse(GRAMMAR).unwrap();
+        let bnf = parse_str("sentence = noun:title \" sleep\" | \"\"\nnoun = \"ideas\" | noun \" and \" noun", SourceName::Builtin).unwrap();
+        assert_eq!(grammar.rules, bnf.rules);
+        assert_eq!(grammar.start_symbol, "sentence");
+        assert_eq!(grammar.location("noun").line, Some(5));
+        assert_eq!(grammar.location("sentence").line, Some(6));
+
+        let (grammar, _) = parse("rules: {b: [[\"x\"]], a: [[{nt: b}]]}").unwrap();
+        assert_eq!(grammar.start_symbol, "b");
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn quote_the_yaml_path() {
+        assert_eq!(first_error("rules:\n  noun: [[\"ideas\"], [1]]"), "grammar.yaml:2: `rules.noun[1][0]`: expected a string or an `{nt: NAME}` map, found a number");
+        assert_eq!(first_error("rules:\n  noun: [\"ideas\"]"), "grammar.yaml:2: `rules.noun[0]`: expected a list of symbols, found a string");
+        assert_eq!(first_error("rules:\n  noun: [[{nt: noun, case: big}]]"), "grammar.yaml:2: Unknown case `:big` (expected `:upper`, `:lower`, or `:title`)");
+        assert_eq!(first_error("rules:\n  noun: [[{nt: a b}]]"), "grammar.yaml:2: `rules.noun[0][0].nt`: expected a rule name without spaces, quotes, `=`, `|`, or `:`, found a string");
+        assert_eq!(first_error("rules:\n  noun: [[{name: noun}]]"), "grammar.yaml:2: `rules.noun[0][0]`: expected only the keys `nt` and `case`, found a string");
+        assert_eq!(first_error("start: [a]\nrules: {}"), "grammar.yaml:1: `start`: expected a rule name, found a list");
+        assert_eq!(first_error("- a"), "grammar.yaml (whole file): Expected a map with `rules` at the top level, found a list");
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn refuse_broken_grammars() {
+        assert_eq!(first_error("start: s\nrules:\n  noun: [[{nt: verb}]]"), "grammar.yaml:3: Could not find definition for `verb`");
+        assert_eq!(first_error("start: s\nrules:\n  noun: [[\"x\"]]"), "grammar.yaml:1: Could not find definition for `s`");
+        assert_eq!(parse("rules: {}").unwrap_err()[0].error, CompileErrorType::EmptyGrammar);
+        let error = &parse("rules:\n  noun: [[\"x\"]\n").unwrap_err()[0];
+        assert!(matches!(error.error, CompileErrorType::Yaml(_)));
+        assert_eq!(error.location.line, Some(3));
+    }
+
+    #[test]
+    fn recognize_yaml_files() {
+        assert_eq!(Syntax::of_path(Path::new("a/grammar.yaml")), Syntax::Yaml);
+        assert_eq!(Syntax::of_path(Path::new("grammar.yml")), Syntax::Yaml);
+        assert_eq!(Syntax::of_path(Path::new("grammar.bnf")), Syntax::Bnf);
+        assert_eq!(Syntax::of_path(Path::new("-")), Syntax::Bnf);
+    }
+}
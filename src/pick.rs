@@ -0,0 +1,239 @@
+/*
+    Generates a sentence with the user making each choice, for crafting
+    examples by hand.
+
+    Whenever a rule with more than one alternative is expanded, its
+    alternatives are shown and the user picks one, presses enter to let the
+    random number generator make every choice from there on, or types `b` to
+    go back to the choice before. Going back starts the sentence again,
+    replaying every choice made before the one being undone.
+*/
+
+use std::io::{self, BufRead, Write};
+
+use blabber::generator::{self, choose_cumulative, choose_index, choose_u64, Choice, ChoiceProvider, GenerateErrorType, Trace, Xoshiro256StarStar};
+use blabber::grammar::{Grammar, Weights};
+use blabber::Error;
+use itertools::Itertools;
+
+// Why the picker stopped the generator
+enum Stop {
+    // The user went back a choice
+    Back,
+    // The input ended before the sentence did
+    EndOfInput,
+    Io(io::Error),
+}
+
+pub struct Picker<'a, R: BufRead, W: Write> {
+    grammar: &'a Grammar,
+    input: R,
+    // Where the alternatives and questions are shown
+    prompts: W,
+    rng: Xoshiro256StarStar,
+    // Every choice made so far, in the order the generator asked for them
+    choices: Vec<Choice>,
+    // Which of the choices the user made, as indices into choices
+    asked: Vec<usize>,
+    // Whether the user handed the rest of the choices to the rng
+    random: bool,
+    // How many choices the current attempt at the sentence has made
+    position: usize,
+    stop: Option<Stop>,
+}
+
+impl<'a, R: BufRead, W: Write> Picker<'a, R, W> {
+    pub fn new(grammar: &'a Grammar, input: R, prompts: W, rng: Xoshiro256StarStar) -> Self {
+        Picker {
+            grammar,
+            input,
+            prompts,
+            rng,
+            choices: Vec::new(),
+            asked: Vec::new(),
+            random: false,
+            position: 0,
+            stop: None
+        }
+    }
+
+    // Generates a sentence from start with the user's choices, returning it
+    // and the trace of every choice made, or None if the input ended first
+    pub fn pick(&mut self, start: &str) -> Result<Option<(String, Trace)>, Error> {
+        let grammar = self.grammar;
+        loop {
+            self.position = 0;
+            let mut trace = Trace::new(start);
+            let error = match generator::generate_choosing(grammar, start, self, None, &mut trace) {
+                Ok(sentence) => return Ok(Some((sentence, trace))),
+                Err(error) => error,
+            };
+            match self.stop.take() {
+                Some(Stop::Back) => continue,
+                Some(Stop::EndOfInput) => return Ok(None),
+                Some(Stop::Io(error)) => return Err(error.into()),
+                None => return Err(error.into()),
+            }
+        }
+    }
+
+    // Shows the alternatives of rule and asks for one until the user gives
+    // a usable answer
+    fn ask(&mut self, rule: &str) -> io::Result<Option<usize>> {
+        let alternatives = self.grammar.rules.rewrite(rule);
+        writeln!(self.prompts, "{}:", rule)?;
+        for (index, alternative) in alternatives.iter().enumerate() {
+            let symbols = if alternative.is_empty() { "\"\"".to_string() } else { alternative.iter().join(" ") };
+            writeln!(self.prompts, "{:>4}  {}", index, symbols)?;
+        }
+
+        loop {
+            write!(self.prompts, "choice (0-{}, enter for random from here on, b to go back): ", alternatives.len() - 1)?;
+            self.prompts.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                self.stop = Some(Stop::EndOfInput);
+                return Ok(None);
+            }
+            match line.trim() {
+                "" => {
+                    self.random = true;
+                    return Ok(Some(match self.grammar.weights.get(rule) {
+                        Some(weights) => choose_cumulative(&mut self.rng, weights.cumulative()),
+                        None => choose_index(&mut self.rng, alternatives.len()),
+                    }));
+                }
+                "b" => match self.asked.pop() {
+                    Some(last) => {
+                        self.choices.truncate(last);
+                        self.stop = Some(Stop::Back);
+                        return Ok(None);
+                    }
+                    None => writeln!(self.prompts, "there's no choice to go back to")?,
+                },
+                answer => match answer.parse::<usize>() {
+                    Ok(index) if index < alternatives.len() => {
+                        self.asked.push(self.choices.len());
+                        return Ok(Some(index));
+                    }
+                    _ => writeln!(self.prompts, "`{}` isn't one of the alternatives", answer)?,
+                },
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Picker<'_, R, W> {
+    // Makes a choice, using the grammar's weights for the rule once the user
+    // hands the choices to the rng
+    fn choose_with(&mut self, rule: &str, alternatives: usize, weights: Option<&Weights>) -> Result<usize, GenerateErrorType> {
+        // Choices from before going back are made again without asking
+        if let Some(choice) = self.choices.get(self.position) {
+            self.position += 1;
+            return Ok(choice.alternative);
+        }
+
+        let alternative = if alternatives == 1 {
+            0
+        } else if self.random {
+            match weights {
+                Some(weights) => choose_cumulative(&mut self.rng, weights.cumulative()),
+                None => choose_index(&mut self.rng, alternatives),
+            }
+        } else {
+            match self.ask(rule) {
+                Ok(Some(alternative)) => alternative,
+                Ok(None) => return Err(GenerateErrorType::Stopped),
+                Err(error) => {
+                    self.stop = Some(Stop::Io(error));
+                    return Err(GenerateErrorType::Stopped);
+                }
+            }
+        };
+        self.choices.push(Choice {
+            rule: rule.to_string(),
+            alternative
+        });
+        self.position += 1;
+        return Ok(alternative);
+    }
+}
+
+impl<R: BufRead, W: Write> ChoiceProvider for Picker<'_, R, W> {
+    fn choose(&mut self, rule: &str, alternatives: usize) -> Result<usize, GenerateErrorType> {
+        self.choose_with(rule, alternatives, None)
+    }
+
+    fn choose_weighted(&mut self, rule: &str, weights: &Weights) -> Result<usize, GenerateErrorType> {
+        self.choose_with(rule, weights.len(), Some(weights))
+    }
+
+    // Dynamic symbols, like dates, aren't worth asking about, so they're
+    // always drawn by the rng, and drawn the same again after going back
+    fn draw(&mut self, symbol: &str, values: u64) -> Result<u64, GenerateErrorType> {
+        if let Some(choice) = self.choices.get(self.position) {
+            self.position += 1;
+            return Ok(choice.alternative as u64);
+        }
+        let value = choose_u64(&mut self.rng, values);
+        self.choices.push(Choice {
+            rule: symbol.to_string(),
+            alternative: value as usize
+        });
+        self.position += 1;
+        return Ok(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use blabber::parser::parse_file;
+
+    fn pick(input: &str) -> (Option<(String, Trace)>, String) {
+        let grammar = parse_file(&PathBuf::from("example_data/english.bnf")).unwrap();
+        let mut prompts = Vec::new();
+        let picked = Picker::new(&grammar, input.as_bytes(), &mut prompts, Xoshiro256StarStar::new(0)).pick("sentence").unwrap();
+        return (picked, String::from_utf8(prompts).unwrap());
+    }
+
+    #[test]
+    fn pick_each_choice() {
+        // noun.phrase, then verb.phrase
+        let (picked, prompts) = pick("1\n0\n");
+        let (sentence, trace) = picked.unwrap();
+        assert_eq!(sentence, "ideas hug furiously");
+        assert_eq!(trace.to_json(), concat!(
+            "{\"start\":\"sentence\",\"choices\":[[\"sentence\",0],[\"noun.phrase\",1],[\"noun\",0],",
+            "[\"verb.phrase\",0],[\"verb\",0],[\"adverb\",0]]}"
+        ));
+        assert!(prompts.starts_with("noun.phrase:\n   0  adjective.phrase \" \" noun\n   1  noun\nchoice (0-1, "));
+    }
+
+    #[test]
+    fn go_back_and_choose_randomly() {
+        // Going back from verb.phrase to noun.phrase, then leaving the rest
+        // to the rng at adjective.phrase after a bad answer
+        let (picked, prompts) = pick("1\nb\n0\n3\n\n");
+        let (sentence, trace) = picked.unwrap();
+        assert_eq!(trace.choices[1], Choice { rule: "noun.phrase".to_string(), alternative: 0 });
+        assert!(prompts.contains("`3` isn't one of the alternatives"));
+        assert_eq!(prompts.matches("noun.phrase:").count(), 2);
+
+        // The trace generates the same sentence again
+        let grammar = parse_file(&PathBuf::from("example_data/english.bnf")).unwrap();
+        let mut replay = generator::ReplayChoices::new(&trace);
+        assert_eq!(generator::generate_choosing(&grammar, "sentence", &mut replay, None, &mut ()).unwrap(), sentence);
+        replay.finish().unwrap();
+
+        let (_, prompts) = pick("b\n");
+        assert!(prompts.contains("there's no choice to go back to"));
+    }
+
+    #[test]
+    fn stop_at_end_of_input() {
+        assert_eq!(pick("1\n").0, None);
+    }
+}
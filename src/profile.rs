@@ -0,0 +1,169 @@
+/*
+    This module profiles generation rule by rule, for finding out which rules
+    make a slow grammar slow.
+
+    Each rule's time is measured from entering it to leaving it. Its self
+    time leaves out the rules it expanded, and its total time doesn't count
+    a recursive rule again while it's already being expanded. Bytes are the
+    text of the rule's own terminals, before any case suffix.
+*/
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use itertools::Itertools;
+
+use crate::generator::GenerationObserver;
+use crate::timing::humanize;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RuleProfile {
+    pub expansions: u64,
+    pub self_time: Duration,
+    pub total_time: Duration,
+    pub bytes: u64,
+}
+
+// A rule being expanded, and how long the rules it expanded took
+struct Frame {
+    rule: String,
+    entered: Instant,
+    children: Duration,
+}
+
+// Gathers each rule's profile over every sentence of a run
+#[derive(Default)]
+pub struct Profile {
+    rules: HashMap<String, RuleProfile>,
+    stack: Vec<Frame>,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Leaves the innermost rule, adding the time since it was entered
+    fn close(&mut self, now: Instant) {
+        let frame = self.stack.pop().expect("a rule is being expanded");
+        let elapsed = now - frame.entered;
+        let recursive = self.stack.iter().any(|outer| outer.rule == frame.rule);
+        let rule = self.rules.entry(frame.rule).or_default();
+        rule.self_time += elapsed.saturating_sub(frame.children);
+        if !recursive {
+            rule.total_time += elapsed;
+        }
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children += elapsed;
+        }
+    }
+
+    // Leaves the rules deeper than depth. Expansions which never finished,
+    // because they were retried or generation failed, still took time.
+    fn close_below(&mut self, depth: usize) {
+        let now = Instant::now();
+        while self.stack.len() > depth {
+            self.close(now);
+        }
+    }
+
+    // Each rule's profile, the most self time first
+    pub fn rules(&self) -> Vec<(&str, &RuleProfile)> {
+        return self.rules.iter()
+            .map(|(rule, profile)| (rule.as_str(), profile))
+            .sorted_by(|(a_rule, a), (b_rule, b)| b.self_time.cmp(&a.self_time).then_with(|| a_rule.cmp(b_rule)))
+            .collect();
+    }
+}
+
+impl GenerationObserver for Profile {
+    fn on_enter_nonterminal(&mut self, name: &str, depth: usize) {
+        // A new sentence starts at depth 0, closing what a failed one left
+        self.close_below(depth);
+        self.rules.entry(name.to_string()).or_default().expansions += 1;
+        self.stack.push(Frame { rule: name.to_string(), entered: Instant::now(), children: Duration::ZERO });
+    }
+
+    fn on_terminal(&mut self, text: &str) {
+        if let Some(frame) = self.stack.last() {
+            self.rules.get_mut(&frame.rule).expect("entered rules are profiled").bytes += text.len() as u64;
+        }
+    }
+
+    fn on_exit_nonterminal(&mut self, _name: &str) {
+        self.close(Instant::now());
+    }
+
+    fn on_retry_nonterminal(&mut self, _name: &str, depth: usize) {
+        self.close_below(depth + 1);
+    }
+}
+
+// A table with a row for each rule, the most self time first
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rules = self.rules();
+        let width = rules.iter().map(|(rule, _)| rule.chars().count()).chain([4]).max().unwrap_or(4);
+        let all_self: Duration = rules.iter().map(|(_, profile)| profile.self_time).sum();
+        write!(f, "{:<width$}  {:>10}  {:>8}  {:>6}  {:>8}  {:>10}", "rule", "expansions", "self", "self %", "total", "bytes")?;
+        for (rule, profile) in rules {
+            let share = if all_self.is_zero() { 0.0 } else { profile.self_time.as_secs_f64() / all_self.as_secs_f64() * 100.0 };
+            write!(f, "\n{:<width$}  {:>10}  {:>8}  {:>5.1}%  {:>8}  {:>10}", rule, profile.expansions,
+                humanize(profile.self_time), share, humanize(profile.total_time), profile.bytes)?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_handling::SourceName;
+    use crate::generator::{generate_choosing, RandomChoices, Xoshiro256StarStar};
+    use crate::parser::parse_str;
+
+    #[test]
+    fn count_each_rule() {
+        let grammar = parse_str("s = word \" \" word:upper\nword = \"ab\" | \"abc\" | s", SourceName::Builtin).unwrap();
+        let mut rng = Xoshiro256StarStar::new(3);
+        let mut profile = Profile::new();
+        let mut sentence_bytes = 0;
+        for _ in 0..20 {
+            let sentence = generate_choosing(&grammar, "s", &mut RandomChoices(&mut rng), None, &mut profile).unwrap();
+            sentence_bytes += sentence.len() as u64;
+        }
+        assert!(profile.stack.is_empty());
+        let s = &profile.rules["s"];
+        let word = &profile.rules["word"];
+        assert_eq!(word.expansions, s.expansions * 2);
+        assert_eq!(s.bytes + word.bytes, sentence_bytes);
+        assert!(s.self_time <= s.total_time && word.self_time <= word.total_time);
+    }
+
+    #[test]
+    fn close_unfinished_expansions() {
+        let mut profile = Profile::new();
+        profile.on_enter_nonterminal("s", 0);
+        profile.on_enter_nonterminal("team", 1);
+        profile.on_enter_nonterminal("member", 2);
+        profile.on_retry_nonterminal("team", 1);
+        assert_eq!(profile.stack.len(), 2);
+        profile.on_enter_nonterminal("s", 0);
+        assert_eq!(profile.stack.len(), 1);
+        assert_eq!(profile.rules["s"].expansions, 2);
+        assert_eq!(profile.rules["member"].expansions, 1);
+    }
+
+    #[test]
+    fn show_a_table() {
+        let mut profile = Profile::new();
+        profile.rules.insert("s".to_string(), RuleProfile { expansions: 10, self_time: Duration::from_millis(1), total_time: Duration::from_millis(4), bytes: 20 });
+        profile.rules.insert("word.list".to_string(), RuleProfile { expansions: 20, self_time: Duration::from_millis(3), total_time: Duration::from_millis(3), bytes: 95 });
+        assert_eq!(profile.to_string(), concat!(
+            "rule       expansions      self  self %     total       bytes\n",
+            "word.list          20     3.0ms   75.0%     3.0ms          95\n",
+            "s                  10     1.0ms   25.0%     4.0ms          20",
+        ));
+    }
+}
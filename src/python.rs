@@ -0,0 +1,99 @@
+/*
+    A Python module, so notebooks can generate sentences without running
+    blabber as a program. Build it with `maturin develop` or
+    `maturin build --release`, which read pyproject.toml.
+
+    Parse errors are raised as `BlabberError`, with an `errors` attribute
+    listing each as a `(file, line, message)` tuple, where `line` is None for
+    errors about the whole file. Failing to generate raises it too, with no
+    errors listed. Bulk generation lets go of the GIL, so Python threads can
+    generate at the same time.
+*/
+
+use std::path::PathBuf;
+
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use rand::{thread_rng, Rng};
+
+use crate::error_handling::SourceName;
+use crate::generator::{generate_from, GenerateError, Xoshiro256StarStar};
+use crate::grammar::{definition_order, Grammar};
+use crate::parser::{parse_file, parse_str, CompileError};
+
+pyo3::create_exception!(blabber, BlabberError, PyException);
+
+fn parse_error(errors: Vec<CompileError>) -> PyErr {
+    let message = errors.iter()
+        .map(|error| format!("{}: {}", error.location, error.error))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let listed: Vec<(String, Option<usize>, String)> = errors.into_iter()
+        .map(|error| (error.location.source.to_string(), error.location.line, error.error.to_string()))
+        .collect();
+    return raise(message, listed);
+}
+
+fn generate_error(error: GenerateError) -> PyErr {
+    return raise(error.to_string(), Vec::new());
+}
+
+fn raise(message: String, errors: Vec<(String, Option<usize>, String)>) -> PyErr {
+    let raised = BlabberError::new_err(message);
+    Python::attach(|py| raised.value(py).setattr("errors", errors).map(|_| raised))
+        .unwrap_or_else(|error| error)
+}
+
+#[pyclass(name = "Grammar", module = "blabber", frozen)]
+pub struct PyGrammar(Grammar);
+
+impl PyGrammar {
+    fn generate_seeded(&self, n: usize, start: Option<&str>, seed: Option<u64>) -> Result<Vec<String>, GenerateError> {
+        let start = start.unwrap_or(&self.0.start_symbol);
+        let mut rng = Xoshiro256StarStar::new(seed.unwrap_or_else(|| thread_rng().gen()));
+        return (0..n).map(|_| generate_from(&self.0, start, &mut rng)).collect();
+    }
+}
+
+#[pymethods]
+impl PyGrammar {
+    // Parses and verifies the grammar file at path
+    #[staticmethod]
+    fn parse_file(path: PathBuf) -> PyResult<Self> {
+        parse_file(&path).map(PyGrammar).map_err(parse_error)
+    }
+
+    // Parses and verifies a grammar held in a string
+    #[staticmethod]
+    fn parse_str(text: &str) -> PyResult<Self> {
+        parse_str(text, SourceName::Builtin).map(PyGrammar).map_err(parse_error)
+    }
+
+    // Generates a sentence from start, or the start symbol, giving what
+    // `--seed` does with a seed
+    #[pyo3(signature = (start = None, seed = None))]
+    fn generate(&self, start: Option<&str>, seed: Option<u64>) -> PyResult<String> {
+        let mut sentences = self.generate_seeded(1, start, seed).map_err(generate_error)?;
+        return Ok(sentences.remove(0));
+    }
+
+    // Generates n sentences one after another from the same seed, like
+    // `--seed SEED --amount N`, without holding the GIL
+    #[pyo3(signature = (n, start = None, seed = None))]
+    fn generate_many(&self, py: Python<'_>, n: usize, start: Option<&str>, seed: Option<u64>) -> PyResult<Vec<String>> {
+        py.detach(|| self.generate_seeded(n, start, seed)).map_err(generate_error)
+    }
+
+    // The rules, starting with the start symbol and then in the order they
+    // were defined
+    #[getter]
+    fn symbols(&self) -> Vec<String> {
+        definition_order(&self.0)
+    }
+}
+
+#[pymodule(name = "blabber")]
+mod module {
+    #[pymodule_export]
+    use super::{BlabberError, PyGrammar};
+}
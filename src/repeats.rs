@@ -0,0 +1,93 @@
+/*
+    This module turns down sentences that use a word again too soon after
+    using it, like "the big big dog", which gives away that they were
+    generated. Such sentences are generated again under the same tries as
+    --require and --forbid.
+
+    Words are split at whitespace and punctuation and compared ignoring
+    case. Hyphenated words and short common words are allowed to repeat,
+    since "the" twice in a few words is just English.
+*/
+
+use std::collections::HashMap;
+
+// The words allowed to repeat when --repeat-allow isn't given
+pub const DEFAULT_ALLOWED: [&str; 16] = ["a", "an", "and", "as", "at", "by", "for", "in", "is", "it", "of", "on", "or", "the", "to", "with"];
+
+#[derive(Debug)]
+pub struct RepeatWindow {
+    // How many words in a row can't have one twice
+    window: usize,
+    allowed: Vec<String>,
+    pub tried: u64,
+    pub rejected: u64,
+}
+
+// The words of a sentence, lowercased. Apostrophes and hyphens inside a word
+// are kept, so "don't" and "well-known" are one word each.
+fn words(sentence: &str) -> impl Iterator<Item = String> + '_ {
+    sentence.split(|c: char| !(c.is_alphanumeric() || c == '\'' || c == '-'))
+        .map(|word| word.trim_matches(|c| c == '\'' || c == '-'))
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+impl RepeatWindow {
+    pub fn new(window: usize, allowed: Vec<String>) -> Self {
+        let allowed = allowed.iter().map(|word| word.to_lowercase()).collect();
+        RepeatWindow { window, allowed, tried: 0, rejected: 0 }
+    }
+
+    // The first word used again within the window, if there is one
+    pub fn repeated(&self, sentence: &str) -> Option<String> {
+        let mut last_seen: HashMap<String, usize> = HashMap::new();
+        for (position, word) in words(sentence).enumerate() {
+            if word.contains('-') || self.allowed.contains(&word) {
+                continue;
+            }
+            if let Some(seen) = last_seen.insert(word.clone(), position) {
+                if position - seen < self.window {
+                    return Some(word);
+                }
+            }
+        }
+        return None;
+    }
+
+    // Whether no word repeats within the window, counting the sentence for
+    // the rejection rate
+    pub fn accepts(&mut self, sentence: &str) -> bool {
+        let accepted = self.repeated(sentence).is_none();
+        self.tried += 1;
+        self.rejected += u64::from(!accepted);
+        return accepted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject_close_repeats() {
+        let defaults = || DEFAULT_ALLOWED.iter().map(ToString::to_string).collect();
+        let mut window = RepeatWindow::new(3, defaults());
+        assert_eq!(window.repeated("the big big dog"), Some("big".to_string()));
+        assert_eq!(window.repeated("Big dog, big... dog!"), Some("big".to_string()));
+        assert_eq!(window.repeated("big red old big dog"), None);
+        assert_eq!(window.repeated("the dog and the cat and the bird"), None);
+        assert_eq!(window.repeated("a well-known, well-known fact"), None);
+        assert_eq!(window.repeated("don't don't"), Some("don't".to_string()));
+        assert_eq!(window.repeated(""), None);
+
+        assert!(window.accepts("a red dog"));
+        assert!(!window.accepts("red red dog"));
+        assert!(window.accepts("red and blue and red"));
+        assert_eq!((window.tried, window.rejected), (3, 1));
+
+        let window = RepeatWindow::new(2, vec!["Big".to_string()]);
+        assert_eq!(window.repeated("the big big dog"), None);
+        assert_eq!(window.repeated("the the dog"), Some("the".to_string()));
+        assert_eq!(window.repeated("dog the dog"), None);
+    }
+}
@@ -0,0 +1,129 @@
+/*
+    Creates a directory for a new grammar, so starting one doesn't mean
+    copying an example and its setup from somewhere else.
+
+    The directory gets a starter grammar, whose comments explain how to run
+    it and the mistakes new grammars usually make, a blabber.toml, any data
+    files the grammar reads, and a snapshot of the grammar's seeded output
+    to check changes against. The starters are built into the binary, from
+    src/templates.
+*/
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::generator::{generate_from, Xoshiro256StarStar};
+use crate::parser::parse_file;
+use crate::snapshot::Snapshot;
+use crate::Error;
+
+const CONFIG: &str = include_str!("templates/blabber.toml");
+// Written in place of `{{name}}` in the starters
+const NAME: &str = "{{name}}";
+const SNAPSHOT_SEED: u64 = 1;
+const SNAPSHOT_AMOUNT: usize = 5;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Template {
+    // A short story, with recursion, optional parts and a case suffix
+    Story,
+    // Log lines, with dynamic symbols, weights and a weighted list
+    Data,
+    // Three rules and nothing else
+    Minimal,
+}
+
+impl Template {
+    pub fn from_name(name: &str) -> Option<Template> {
+        match name {
+            "story" => Some(Template::Story),
+            "data" => Some(Template::Data),
+            "minimal" => Some(Template::Minimal),
+            _ => None
+        }
+    }
+
+    fn grammar(self) -> &'static str {
+        match self {
+            Template::Story => include_str!("templates/story.bnf"),
+            Template::Data => include_str!("templates/data.bnf"),
+            Template::Minimal => include_str!("templates/minimal.bnf"),
+        }
+    }
+
+    // The files the grammar reads, by name
+    fn data_files(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Template::Data => &[("users.tsv", include_str!("templates/users.tsv"))],
+            Template::Story | Template::Minimal => &[],
+        }
+    }
+}
+
+// Creates dir with a project from the template, named after the directory,
+// and returns the path of its grammar. The directory can already exist if
+// it's empty, but nothing is written into one with anything in it.
+pub fn create_project(dir: &Path, template: Template) -> Result<PathBuf, Error> {
+    if dir.read_dir().is_ok_and(|mut entries| entries.next().is_some()) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists and isn't empty", dir.display())).into());
+    }
+    let name = match dir.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} doesn't end in a name for the grammar", dir.display())).into()),
+    };
+
+    std::fs::create_dir_all(dir.join("snapshots"))?;
+    let grammar_path = dir.join(format!("{}.bnf", name));
+    std::fs::write(&grammar_path, template.grammar().replace(NAME, &name))?;
+    std::fs::write(dir.join("blabber.toml"), CONFIG)?;
+    for (file, contents) in template.data_files() {
+        std::fs::write(dir.join(file), contents)?;
+    }
+
+    // Made from the grammar as written, so checking it passes until the
+    // grammar changes
+    let grammar = parse_file(&grammar_path)?;
+    let mut rng = Xoshiro256StarStar::new(SNAPSHOT_SEED);
+    let sentences = (0..SNAPSHOT_AMOUNT)
+        .map(|_| generate_from(&grammar, &grammar.start_symbol, &mut rng))
+        .collect::<Result<Vec<String>, _>>()?;
+    let snapshot = Snapshot::new(SNAPSHOT_SEED, grammar.start_symbol.clone(), false, sentences);
+    std::fs::write(dir.join("snapshots").join(format!("{}.txt", name)), snapshot.to_text())?;
+    return Ok(grammar_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assertions::parse_assertions;
+    use crate::error_handling::SourceName;
+
+    #[test]
+    fn create_projects() {
+        let root = std::env::temp_dir().join(format!("blabber-new-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for template in [Template::Story, Template::Data, Template::Minimal] {
+            let dir = root.join(format!("my-{:?}", template).to_lowercase());
+            let grammar_path = create_project(&dir, template).unwrap();
+            assert_eq!(grammar_path, dir.join(format!("my-{:?}.bnf", template).to_lowercase()));
+
+            let text = std::fs::read_to_string(&grammar_path).unwrap();
+            assert!(!text.contains(NAME) && text.contains(&format!("snapshots/my-{:?}.txt", template).to_lowercase()));
+            let (grammar, warnings) = crate::parser::parse_file_with_options(&grammar_path, &Default::default()).unwrap();
+            assert!(warnings.is_empty(), "{:?}", warnings);
+            let (assertions, errors) = parse_assertions(&text, SourceName::Builtin);
+            assert!(errors.is_empty());
+            assert!(assertions.iter().all(|assertion| assertion.check(&grammar).is_ok()));
+
+            // Refused now there's something there, without touching it
+            std::fs::write(&grammar_path, "changed = \"by hand\"").unwrap();
+            assert!(matches!(create_project(&dir, template), Err(Error::Io(error)) if error.kind() == io::ErrorKind::AlreadyExists));
+            assert_eq!(std::fs::read_to_string(&grammar_path).unwrap(), "changed = \"by hand\"");
+        }
+
+        // An empty directory is fine
+        std::fs::create_dir(root.join("empty")).unwrap();
+        create_project(&root.join("empty"), Template::Minimal).unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
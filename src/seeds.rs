@@ -0,0 +1,118 @@
+/*
+    This module reads seeds files, which list seeds to generate one sentence
+    each from, for reproducing interesting cases one by one.
+
+    Each line is a seed, a whole number that fits in 64 bits. Blank lines are
+    skipped, and `#` starts a comment running to the end of the line.
+
+    Seeds can also come from keys, like the IDs of records a run generates a
+    sentence for each of, so each sentence only depends on its key.
+*/
+
+use std::fmt::Display;
+
+use crate::error_handling::{Error, ErrorType, Location, SourceName};
+use crate::generator::fnv1a;
+
+#[derive(Debug, PartialEq)]
+pub enum SeedErrorType {
+    // The line isn't a whole number from 0 to 2^64 - 1
+    InvalidSeed(String),
+    // A line given keys by --key-field has fewer fields than that
+    MissingKey { field: usize, fields: usize },
+}
+
+impl ErrorType for SeedErrorType {}
+
+impl Display for SeedErrorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SeedErrorType::InvalidSeed(seed) => write!(f, "`{}` isn't a valid seed (expected a whole number from 0 to {})", seed, u64::MAX),
+            SeedErrorType::MissingKey { field, fields } => write!(f, "Expected a key in tab separated field {}, but the line has {} fields", field, fields),
+        }
+    }
+}
+
+pub type SeedError = Error<SeedErrorType>;
+
+// Reads every seed in a seeds file, in order, using source for the error
+// locations, and returns every error found if any line is wrong
+pub fn parse_seeds(text: &str, source: SourceName) -> Result<Vec<u64>, Vec<SeedError>> {
+    let mut seeds = Vec::new();
+    let mut errors = Vec::new();
+    for (num, line) in text.lines().enumerate() {
+        let seed = line.split('#').next().unwrap_or("").trim();
+        if seed.is_empty() {
+            continue;
+        }
+        match seed.parse() {
+            Ok(seed) => seeds.push(seed),
+            Err(_) => errors.push(Error {
+                location: Location::line(source.clone(), num + 1),
+                error: SeedErrorType::InvalidSeed(seed.to_string())
+            }),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    return Ok(seeds);
+}
+
+// The seed for a key, which is the 64 bit FNV-1a hash of its UTF-8 bytes,
+// so a key gives the same sentences on every platform and release
+pub fn key_seed(key: &str) -> u64 {
+    return fnv1a(key.bytes());
+}
+
+// Splits the tab separated field of a line holding its key, counting from 1,
+// from the line without that field, or gives how many fields there are if
+// it has too few
+pub fn split_key(line: &str, field: usize) -> Result<(&str, String), usize> {
+    let mut fields: Vec<&str> = line.split('\t').collect();
+    if field == 0 || field > fields.len() {
+        return Err(fields.len());
+    }
+    let key = fields.remove(field - 1);
+    return Ok((key, fields.join("\t")));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{generate_from, Xoshiro256StarStar};
+
+    #[test]
+    fn read_seeds() {
+        let seeds = parse_seeds("# found by the fuzzer\n42\n\n7 # crashed once\n18446744073709551615\n", SourceName::Builtin).unwrap();
+        assert_eq!(seeds, [42, 7, u64::MAX]);
+
+        let errors = parse_seeds("1\n-1\nseven\n18446744073709551616\n", SourceName::file("seeds.txt")).unwrap_err();
+        let errors: Vec<String> = errors.iter().map(|error| format!("{}: {}", error.location, error.error)).collect();
+        assert_eq!(errors, [
+            "seeds.txt:2: `-1` isn't a valid seed (expected a whole number from 0 to 18446744073709551615)",
+            "seeds.txt:3: `seven` isn't a valid seed (expected a whole number from 0 to 18446744073709551615)",
+            "seeds.txt:4: `18446744073709551616` isn't a valid seed (expected a whole number from 0 to 18446744073709551615)",
+        ]);
+    }
+
+    #[test]
+    fn seed_from_keys() {
+        // Pinned, since changing them changes every keyed sentence
+        assert_eq!(key_seed("user-4812"), 17591767524397225150);
+        assert_eq!(key_seed("user-4813"), 17591768623908853361);
+        assert_eq!(key_seed(""), 0xcbf29ce484222325);
+        assert_eq!(key_seed("café"), 5253592154431032713);
+        let grammar = crate::parser::parse_file(&std::path::PathBuf::from("example_data/english.bnf")).unwrap();
+        let sentence = |key| generate_from(&grammar, "sentence", &mut Xoshiro256StarStar::new(key_seed(key))).unwrap();
+        assert_eq!(sentence("user-4812"), "ideas hug furiously");
+        assert_eq!(sentence("user-4813"), "ideas hug furiously");
+        assert_eq!(sentence("café"), "ideas furiously hug colorless ideas");
+        assert_eq!(sentence(""), "green, colorless, colorless ideas furiously hug ideas");
+
+        assert_eq!(split_key("user-4812\tgreeting", 1), Ok(("user-4812", "greeting".to_string())));
+        assert_eq!(split_key("greeting\t3\tuser-4812\tout.txt", 3), Ok(("user-4812", "greeting\t3\tout.txt".to_string())));
+        assert_eq!(split_key("greeting", 2), Err(1));
+    }
+}
@@ -0,0 +1,232 @@
+/*
+    A small HTTP server for generating sentences, so prototypes can get them
+    from a local endpoint. Requests are handled by a few threads sharing the
+    parsed grammar.
+*/
+
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use itertools::Itertools;
+use rand::{thread_rng, Rng};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::error_handling::warn;
+use crate::generator::{generate_from, Xoshiro256StarStar};
+use crate::grammar::Grammar;
+use crate::json;
+use crate::parser::parse_file;
+
+// The most sentences one request can ask for
+const MAX_AMOUNT: u32 = 10_000;
+
+pub struct ServeOptions {
+    pub port: u16,
+    pub threads: usize,
+    // Whether to parse the grammar again when the file changes
+    pub reload: bool,
+}
+
+// The grammar being served, and when its file was last modified
+struct Served {
+    grammar: Arc<Grammar>,
+    modified: Option<SystemTime>,
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Serves the grammar until the process is stopped. Only returns if the
+// server couldn't start.
+pub fn serve(path: PathBuf, grammar: Grammar, options: ServeOptions) -> std::io::Result<()> {
+    let server = Arc::new(Server::http(("127.0.0.1", options.port)).map_err(std::io::Error::other)?);
+    let served = Arc::new(RwLock::new(Served {
+        modified: modified(&path),
+        grammar: Arc::new(grammar),
+    }));
+    eprintln!("Serving {} on http://127.0.0.1:{}", path.display(), options.port);
+
+    let workers: Vec<_> = (0..options.threads.max(1)).map(|_| {
+        let server = Arc::clone(&server);
+        let served = Arc::clone(&served);
+        let path = path.clone();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                if options.reload {
+                    reload_if_changed(&path, &served);
+                }
+                let grammar = Arc::clone(&served.read().expect("no thread panics holding the lock").grammar);
+
+                let (status, body) = match request.method() {
+                    Method::Get => respond(&grammar, request.url()),
+                    _ => (405, error_json("Only GET requests are supported")),
+                };
+                let content_type = Header::from_bytes("Content-Type", "application/json").expect("the header is valid");
+                let response = Response::from_string(body).with_status_code(status).with_header(content_type);
+                // The client hanging up isn't the server's problem
+                let _ = request.respond(response);
+            }
+        })
+    }).collect();
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+    return Ok(());
+}
+
+// Parses the grammar again if its file was modified since it was last
+// parsed. If the new version has errors, they're printed and the old
+// grammar is kept.
+fn reload_if_changed(path: &PathBuf, served: &RwLock<Served>) {
+    let modified = modified(path);
+    if modified == served.read().expect("no thread panics holding the lock").modified {
+        return;
+    }
+
+    let mut served = served.write().expect("no thread panics holding the lock");
+    // Another thread may have reloaded it while this one waited
+    if modified == served.modified {
+        return;
+    }
+    served.modified = modified;
+    match parse_file(path) {
+        Ok(grammar) => {
+            served.grammar = Arc::new(grammar);
+            eprintln!("Reloaded {}", path.display());
+        }
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            warn("Keeping the previous version of the grammar");
+        }
+    }
+}
+
+fn error_json(message: &str) -> String {
+    format!("{{\"error\":{}}}", json::string(message))
+}
+
+// The status code and JSON body answering a GET request for url
+pub fn respond(grammar: &Grammar, url: &str) -> (u16, String) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params = match parse_query(query) {
+        Ok(params) => params,
+        Err(message) => return (400, error_json(&message)),
+    };
+    let param = |name: &str| params.iter().rev().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+
+    match path {
+        "/health" => (200, "{\"status\":\"ok\"}".to_string()),
+        "/symbols" => {
+            let symbols = grammar.rules.keys().sorted().map(json::string).join(",");
+            (200, format!("{{\"start\":{},\"symbols\":[{}]}}", json::string(&grammar.start_symbol), symbols))
+        }
+        "/generate" => {
+            let start = param("start").unwrap_or(&grammar.start_symbol);
+            let amount = match param("n").map(str::parse::<u32>) {
+                None => 1,
+                Some(Ok(amount)) if amount <= MAX_AMOUNT => amount,
+                _ => return (400, error_json(&format!("`n` must be a number from 0 to {}", MAX_AMOUNT))),
+            };
+            let seed = match param("seed").map(str::parse::<u64>) {
+                None => thread_rng().gen(),
+                Some(Ok(seed)) => seed,
+                Some(Err(_)) => return (400, error_json("`seed` must be a number from 0 to 2^64 - 1")),
+            };
+
+            let mut rng = Xoshiro256StarStar::new(seed);
+            let sentences: Result<Vec<String>, _> = (0..amount)
+                .map(|_| generate_from(grammar, start, &mut rng).map(|sentence| json::string(&sentence)))
+                .collect();
+            match sentences {
+                Ok(sentences) => (200, format!("{{\"sentences\":[{}]}}", sentences.join(","))),
+                Err(error) => (400, error_json(&error.to_string())),
+            }
+        }
+        _ => (404, error_json(&format!("No endpoint at `{}`", path))),
+    }
+}
+
+// Splits a query string into its decoded names and values
+fn parse_query(query: &str) -> Result<Vec<(String, String)>, String> {
+    query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(name)?, percent_decode(value)?))
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.bytes();
+    while let Some(byte) = rest.next() {
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex: Vec<u8> = rest.by_ref().take(2).collect();
+                let decoded = std::str::from_utf8(&hex).ok()
+                    .filter(|hex| hex.len() == 2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| format!("Invalid percent escape in `{}`", text))?;
+                bytes.push(decoded);
+            }
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| format!("`{}` isn't UTF-8 once decoded", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn english() -> Grammar {
+        parse_file(&PathBuf::from("example_data/english.bnf")).unwrap()
+    }
+
+    #[test]
+    fn generate_like_the_command_line() {
+        let grammar = english();
+        assert_eq!(respond(&grammar, "/generate?seed=42&n=2"), (200, "{\"sentences\":[\"green ideas hug furiously\",\"ideas furiously hug ideas\"]}".to_string()));
+        assert_eq!(respond(&grammar, "/generate?start=adjective&seed=0&n=0"), (200, "{\"sentences\":[]}".to_string()));
+        assert_eq!(respond(&grammar, "/generate?start=verb"), (200, "{\"sentences\":[\"hug\"]}".to_string()));
+    }
+
+    #[test]
+    fn reject_bad_requests() {
+        let grammar = english();
+        assert_eq!(respond(&grammar, "/generate?start=no%20such"), (400, "{\"error\":\"No definition for nonterminal `no such`\"}".to_string()));
+        assert_eq!(respond(&grammar, "/generate?n=lots").0, 400);
+        assert_eq!(respond(&grammar, "/generate?n=10001").0, 400);
+        assert_eq!(respond(&grammar, "/generate?seed=-1").0, 400);
+        assert_eq!(respond(&grammar, "/generate?start=%zz").0, 400);
+        assert_eq!(respond(&grammar, "/elsewhere").0, 404);
+    }
+
+    #[test]
+    fn list_symbols() {
+        let grammar = english();
+        let (status, body) = respond(&grammar, "/symbols");
+        assert_eq!(status, 200);
+        assert!(body.starts_with("{\"start\":\"sentence\",\"symbols\":[\"adjective\",\"adjective.phrase\","));
+        assert_eq!(respond(&grammar, "/health"), (200, "{\"status\":\"ok\"}".to_string()));
+    }
+
+    #[test]
+    fn decode_queries() {
+        assert_eq!(parse_query("start=noun.phrase&n=5&&flag"), Ok(vec![
+            ("start".to_string(), "noun.phrase".to_string()),
+            ("n".to_string(), "5".to_string()),
+            ("flag".to_string(), String::new()),
+        ]));
+        assert_eq!(percent_decode("a+b%2Cc%C3%A9"), Ok("a b,cé".to_string()));
+        assert!(percent_decode("%4").is_err());
+        assert!(percent_decode("%ff").is_err());
+    }
+}
@@ -0,0 +1,219 @@
+/*
+    This module reads and writes snapshots, which record the sentences a
+    seeded run generated so later runs can be compared against them.
+
+    A snapshot starts with a header of `# key: value` lines giving the blabber
+    version, seed, start symbol, and amount it was made with, and
+    `# noise: stable` if it was made with --stable-noise, followed by one
+    sentence per line, escaped so each fits on one.
+*/
+
+use std::fmt::Display;
+
+use crate::analysis::terminals::{escape, unescape};
+use crate::error_handling::{Error, ErrorType, Location, SourceName};
+
+const MAGIC: &str = "# blabber snapshot";
+
+// The version of blabber making snapshots, which is recorded in them since
+// seeded output can change between versions
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    pub version: String,
+    pub seed: u64,
+    pub start: String,
+    // Whether each choice was seeded from its rule, with --stable-noise
+    pub stable_noise: bool,
+    pub sentences: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SnapshotErrorType {
+    // The file doesn't start with `# blabber snapshot`
+    NotASnapshot,
+    // A header line isn't `# key: value` with a known key and valid value
+    InvalidHeader(String),
+    // The header is missing a key
+    MissingHeader(&'static str),
+    // A sentence has an escape that escaping can't produce
+    InvalidEscape,
+    // There aren't as many sentences as the header says
+    WrongAmount { expected: usize, found: usize },
+}
+
+impl ErrorType for SnapshotErrorType {}
+
+impl Display for SnapshotErrorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotErrorType::NotASnapshot => write!(f, "Not a snapshot (expected it to start with `{}`)", MAGIC),
+            SnapshotErrorType::InvalidHeader(line) => write!(f, "Invalid header line `{}`", line),
+            SnapshotErrorType::MissingHeader(key) => write!(f, "The header has no `{}`", key),
+            SnapshotErrorType::InvalidEscape => write!(f, "Invalid escape in sentence"),
+            SnapshotErrorType::WrongAmount { expected, found } => write!(f, "The header says there are {} sentences, but there are {}", expected, found),
+        }
+    }
+}
+
+pub type SnapshotError = Error<SnapshotErrorType>;
+
+impl Snapshot {
+    pub fn new(seed: u64, start: String, stable_noise: bool, sentences: Vec<String>) -> Self {
+        Snapshot {
+            version: VERSION.to_string(),
+            seed,
+            start,
+            stable_noise,
+            sentences
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n# version: {}\n# seed: {}\n# start: {}\n", MAGIC, self.version, self.seed, self.start);
+        // Left out otherwise, so snapshots from before it existed still match
+        if self.stable_noise {
+            text.push_str("# noise: stable\n");
+        }
+        text += &format!("# amount: {}\n", self.sentences.len());
+        for sentence in &self.sentences {
+            text.push_str(&escape(sentence));
+            text.push('\n');
+        }
+        return text;
+    }
+
+    // Reads a snapshot written by to_text, using source for the error
+    // locations
+    pub fn parse(text: &str, source: SourceName) -> Result<Snapshot, SnapshotError> {
+        let error = |line: usize, error| Error { location: Location::line(source.clone(), line), error };
+        let mut lines = text.lines().enumerate().map(|(num, line)| (num + 1, line)).peekable();
+        if lines.next().map(|(_, line)| line) != Some(MAGIC) {
+            return Err(error(1, SnapshotErrorType::NotASnapshot));
+        }
+
+        let (mut version, mut seed, mut start, mut amount) = (None, None, None, None);
+        let mut stable_noise = false;
+        while let Some((num, line)) = lines.next_if(|(_, line)| line.starts_with('#')) {
+            let invalid = || error(num, SnapshotErrorType::InvalidHeader(line.to_string()));
+            let (key, value) = line[1..].split_once(':').ok_or_else(invalid)?;
+            let value = value.trim();
+            match key.trim() {
+                "version" => version = Some(value.to_string()),
+                "seed" => seed = Some(value.parse::<u64>().map_err(|_| invalid())?),
+                "start" => start = Some(value.to_string()),
+                "amount" => amount = Some(value.parse::<usize>().map_err(|_| invalid())?),
+                "noise" => stable_noise = match value {
+                    "stable" => true,
+                    "sequential" => false,
+                    _ => return Err(invalid()),
+                },
+                _ => return Err(invalid()),
+            }
+        }
+        let missing = |key| Error { location: Location::whole(source.clone()), error: SnapshotErrorType::MissingHeader(key) };
+        let version = version.ok_or_else(|| missing("version"))?;
+        let seed = seed.ok_or_else(|| missing("seed"))?;
+        let start = start.ok_or_else(|| missing("start"))?;
+        let amount = amount.ok_or_else(|| missing("amount"))?;
+
+        let sentences = lines
+            .map(|(num, line)| unescape(line).ok_or_else(|| error(num, SnapshotErrorType::InvalidEscape)))
+            .collect::<Result<Vec<String>, SnapshotError>>()?;
+        if sentences.len() != amount {
+            return Err(Error {
+                location: Location::whole(source),
+                error: SnapshotErrorType::WrongAmount { expected: amount, found: sentences.len() }
+            });
+        }
+
+        return Ok(Snapshot {
+            version,
+            seed,
+            start,
+            stable_noise,
+            sentences
+        });
+    }
+}
+
+// A sentence which differs between two snapshots, counting from 1. A side is
+// None when that snapshot has fewer sentences.
+#[derive(Debug, PartialEq)]
+pub struct Difference<'a> {
+    pub sentence: usize,
+    pub expected: Option<&'a str>,
+    pub actual: Option<&'a str>,
+}
+
+// Compares two lists of sentences position by position, since each sentence
+// of a seeded run depends on the ones before it
+pub fn differences<'a>(expected: &'a [String], actual: &'a [String]) -> Vec<Difference<'a>> {
+    (0..expected.len().max(actual.len()))
+        .map(|index| Difference {
+            sentence: index + 1,
+            expected: expected.get(index).map(String::as_str),
+            actual: actual.get(index).map(String::as_str)
+        })
+        .filter(|difference| difference.expected != difference.actual)
+        .collect()
+}
+
+// Shown as the sentence number, then the expected sentence after `-` and the
+// actual one after `+`, escaped like they're written
+impl Display for Difference<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "sentence {}:", self.sentence)?;
+        if let Some(expected) = self.expected {
+            writeln!(f, "- {}", escape(expected))?;
+        }
+        if let Some(actual) = self.actual {
+            writeln!(f, "+ {}", escape(actual))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_read_snapshots() {
+        let snapshot = Snapshot::new(7, "greeting".to_string(), false, vec!["hello".to_string(), "two\nlines".to_string()]);
+        let text = snapshot.to_text();
+        assert!(text.ends_with("# seed: 7\n# start: greeting\n# amount: 2\nhello\ntwo\\nlines\n"));
+        assert_eq!(Snapshot::parse(&text, SourceName::Builtin).unwrap(), snapshot);
+        let stable = Snapshot::new(7, "greeting".to_string(), true, vec!["hello".to_string()]);
+        assert!(stable.to_text().ends_with("# start: greeting\n# noise: stable\n# amount: 1\nhello\n"));
+        assert_eq!(Snapshot::parse(&stable.to_text(), SourceName::Builtin).unwrap(), stable);
+
+        let errors = [
+            "hello\n",
+            "# blabber snapshot\n# version: 1\n# seed: many\n",
+            "# blabber snapshot\n# version: 1\n# noise: loud\n",
+            "# blabber snapshot\n# version: 1\n# seed: 1\n# amount: 0\n",
+            "# blabber snapshot\n# version: 1\n# seed: 1\n# start: s\n# amount: 2\nhi\n",
+        ].map(|text| Snapshot::parse(text, SourceName::Builtin).unwrap_err().error);
+        assert_eq!(errors, [
+            SnapshotErrorType::NotASnapshot,
+            SnapshotErrorType::InvalidHeader("# seed: many".to_string()),
+            SnapshotErrorType::InvalidHeader("# noise: loud".to_string()),
+            SnapshotErrorType::MissingHeader("start"),
+            SnapshotErrorType::WrongAmount { expected: 2, found: 1 },
+        ]);
+    }
+
+    #[test]
+    fn diff_sentences() {
+        let expected = ["a", "b", "c"].map(String::from);
+        let actual = ["a", "B"].map(String::from);
+        let found = differences(&expected, &actual);
+        assert_eq!(found, vec![
+            Difference { sentence: 2, expected: Some("b"), actual: Some("B") },
+            Difference { sentence: 3, expected: Some("c"), actual: None },
+        ]);
+        assert_eq!(found[0].to_string(), "sentence 2:\n- b\n+ B\n");
+    }
+}
@@ -0,0 +1,154 @@
+/*
+    This module holds a run's output until the end so it can be written in
+    sorted order, for fixture files that diff cleanly between runs.
+
+    Each record is everything written for one sentence, so a `# counts` line
+    stays with its sentence, and records are sorted by their first line.
+    Sorting is stable, so records which compare equal stay in the order they
+    were generated.
+*/
+
+use std::cmp::Ordering;
+use std::io::Write;
+
+use crate::error_handling::warn;
+
+// Outputs bigger than this are warned about, since they're held in memory
+const LARGE_OUTPUT_BYTES: u64 = 1 << 30;
+// How many records are seen before estimating the size of the whole output
+const ESTIMATE_AFTER: usize = 1000;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SortOrder {
+    // By the bytes of the text
+    Bytes,
+    // Ignoring case, with runs of digits compared as numbers, so `item 9`
+    // comes before `item 10`
+    Natural,
+}
+
+impl SortOrder {
+    pub fn from_name(name: &str) -> Option<SortOrder> {
+        match name {
+            "bytes" => Some(SortOrder::Bytes),
+            "natural" => Some(SortOrder::Natural),
+            _ => None
+        }
+    }
+
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            SortOrder::Bytes => a.cmp(b),
+            SortOrder::Natural => natural_cmp(a, b),
+        }
+    }
+}
+
+// Splits text into runs of ASCII digits and runs of everything else
+fn runs(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let digits = rest.starts_with(|c: char| c.is_ascii_digit());
+        let end = rest.find(|c: char| c.is_ascii_digit() != digits).unwrap_or(rest.len());
+        let (run, after) = rest.split_at(end);
+        rest = after;
+        return (!run.is_empty()).then_some(run);
+    })
+}
+
+// Compares runs of digits by their value, and the rest case insensitively.
+// Text that only differs in case or leading zeros is then compared by bytes,
+// so no two different texts are equal.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_runs = runs(a);
+    let mut b_runs = runs(b);
+    loop {
+        let ordering = match (a_runs.next(), b_runs.next()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_run), Some(b_run)) if a_run.starts_with(|c: char| c.is_ascii_digit()) && b_run.starts_with(|c: char| c.is_ascii_digit()) => {
+                let a_value = a_run.trim_start_matches('0');
+                let b_value = b_run.trim_start_matches('0');
+                a_value.len().cmp(&b_value.len()).then_with(|| a_value.cmp(b_value))
+            }
+            (Some(a_run), Some(b_run)) => a_run.chars().flat_map(char::to_lowercase).cmp(b_run.chars().flat_map(char::to_lowercase)),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+fn first_line(record: &str) -> &str {
+    record.split('\n').next().unwrap_or("")
+}
+
+pub struct SortedOutput {
+    order: SortOrder,
+    records: Vec<String>,
+    // How many records the run is expected to make, for the size estimate
+    expected: u64,
+    bytes: u64,
+    warned: bool,
+}
+
+impl SortedOutput {
+    pub fn new(order: SortOrder, expected: u64) -> Self {
+        SortedOutput { order, records: Vec::new(), expected, bytes: 0, warned: false }
+    }
+
+    // Holds a record until the end, warning once if the whole output looks
+    // like it will be large
+    pub fn push(&mut self, record: String) {
+        self.bytes += record.len() as u64;
+        self.records.push(record);
+        if self.warned {
+            return;
+        }
+        let estimate = if self.records.len() == ESTIMATE_AFTER {
+            self.bytes / ESTIMATE_AFTER as u64 * self.expected
+        } else {
+            self.bytes
+        };
+        if estimate > LARGE_OUTPUT_BYTES {
+            warn(format!("`--sort` holds every sentence until the end, which looks like it will take about {} MiB", estimate >> 20));
+            self.warned = true;
+        }
+    }
+
+    // Writes the records sorted by their first lines
+    pub fn write_to(mut self, out: &mut dyn Write) -> std::io::Result<()> {
+        let order = self.order;
+        self.records.sort_by(|a, b| order.compare(first_line(a), first_line(b)));
+        for record in self.records {
+            out.write_all(record.as_bytes())?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_naturally() {
+        let mut texts = vec!["item 10", "Item 9", "item 9", "item 09", "apple", "item", "item 9b", "Zebra"];
+        texts.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(texts, ["apple", "item", "Item 9", "item 09", "item 9", "item 9b", "item 10", "Zebra"]);
+        assert_eq!(runs("ab12c3").collect::<Vec<_>>(), ["ab", "12", "c", "3"]);
+        assert_eq!(natural_cmp("x99999999999999999999999", "x100000000000000000000000"), Ordering::Less);
+    }
+
+    #[test]
+    fn sort_whole_records_stably() {
+        let mut sorted = SortedOutput::new(SortOrder::Bytes, 4);
+        for record in ["b\n# counts {\"x\":1}\n", "a\n# counts {\"x\":2}\n", "b\n# counts {\"x\":3}\n", "a\n# counts {\"x\":4}\n"] {
+            sorted.push(record.to_string());
+        }
+        let mut out = Vec::new();
+        sorted.write_to(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a\n# counts {\"x\":2}\na\n# counts {\"x\":4}\nb\n# counts {\"x\":1}\nb\n# counts {\"x\":3}\n");
+    }
+}
@@ -0,0 +1,268 @@
+/*
+    This module summarizes the lengths of generated sentences as they're
+    made, without keeping the sentences, for checking what a batch looks like.
+
+    Quantiles are exact until more than RESERVOIR_SIZE sentences have been
+    seen, after which they're estimated from a uniform sample of that many
+    lengths. The histogram buckets are powers of two, so they're always exact.
+*/
+
+use std::fmt::Display;
+
+use crate::generator::{choose_index, Xoshiro256StarStar};
+
+// How many lengths are kept for the quantiles
+const RESERVOIR_SIZE: usize = 10_000;
+// Widest bar in the histogram, in characters
+const BAR_WIDTH: u64 = 40;
+
+// The lengths of the sentences in one unit
+#[derive(Debug, Default)]
+struct Lengths {
+    min: Option<u64>,
+    max: u64,
+    total: u64,
+    // Every length until the reservoir is full, then a sample of them
+    sample: Vec<u64>,
+}
+
+impl Lengths {
+    fn add(&mut self, length: u64) {
+        self.min = Some(self.min.map_or(length, |min| min.min(length)));
+        self.max = self.max.max(length);
+        self.total += length;
+    }
+
+    fn mean(&self, count: u64) -> f64 {
+        self.total as f64 / count as f64
+    }
+
+    // The nearest rank quantile of the sample
+    fn quantile(&self, quantile: f64) -> u64 {
+        let mut sorted = self.sample.clone();
+        sorted.sort_unstable();
+        let rank = (quantile * sorted.len() as f64).ceil() as usize;
+        return sorted[rank.clamp(1, sorted.len()) - 1];
+    }
+
+    fn to_json(&self, count: u64) -> String {
+        format!("{{\"min\":{},\"mean\":{},\"median\":{},\"p95\":{},\"max\":{}}}",
+            self.min.unwrap_or(0), self.mean(count), self.quantile(0.5), self.quantile(0.95), self.max)
+    }
+}
+
+#[derive(Debug)]
+pub struct Summary {
+    count: u64,
+    empty: u64,
+    chars: Lengths,
+    bytes: Lengths,
+    // How many sentences were in each power of two of characters, where
+    // bucket 0 is empty sentences and bucket i holds 2^(i-1) to 2^i - 1
+    buckets: Vec<u64>,
+    // How many repeats --dedup left out, if it was given
+    duplicates: Option<u64>,
+    // How many of the sentences tried --no-repeat-window turned down, and
+    // how many it tried, if it was given
+    repeated_words: Option<(u64, u64)>,
+    // Picks which lengths are kept once the reservoir is full, seeded so
+    // summaries don't change between runs
+    rng: Xoshiro256StarStar,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Summary::new()
+    }
+}
+
+impl Summary {
+    pub fn new() -> Self {
+        Summary {
+            count: 0,
+            empty: 0,
+            chars: Lengths::default(),
+            bytes: Lengths::default(),
+            buckets: Vec::new(),
+            duplicates: None,
+            repeated_words: None,
+            rng: Xoshiro256StarStar::new(0)
+        }
+    }
+
+    pub fn add(&mut self, sentence: &str) {
+        self.count += 1;
+        let chars = sentence.chars().count() as u64;
+        if chars == 0 {
+            self.empty += 1;
+        }
+
+        let bytes = sentence.len() as u64;
+        self.chars.add(chars);
+        self.bytes.add(bytes);
+
+        // Once the reservoir is full, each new length replaces a random kept
+        // one with the chance that keeps the sample uniform
+        if self.chars.sample.len() < RESERVOIR_SIZE {
+            self.chars.sample.push(chars);
+            self.bytes.sample.push(bytes);
+        } else {
+            let index = choose_index(&mut self.rng, self.count as usize);
+            if index < RESERVOIR_SIZE {
+                self.chars.sample[index] = chars;
+                self.bytes.sample[index] = bytes;
+            }
+        }
+
+        let bucket = (u64::BITS - chars.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+    }
+
+    // Counts sentences left out as repeats, which are still summarized
+    pub fn set_duplicates(&mut self, duplicates: u64) {
+        self.duplicates = Some(duplicates);
+    }
+
+    // Counts sentences turned down for repeating a word, which aren't
+    // summarized, out of every sentence tried
+    pub fn set_repeated_words(&mut self, rejected: u64, tried: u64) {
+        self.repeated_words = Some((rejected, tried));
+    }
+
+    // Whether the quantiles are estimated rather than exact
+    pub fn estimated(&self) -> bool {
+        self.count > RESERVOIR_SIZE as u64
+    }
+
+    // The smallest and largest length in each bucket, with its count,
+    // starting from the first bucket with any sentences
+    fn histogram(&self) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+        self.buckets.iter()
+            .enumerate()
+            .skip_while(|(_, count)| **count == 0)
+            .map(|(bucket, count)| match bucket {
+                0 => (0, 0, *count),
+                bucket => (1 << (bucket - 1), (1 << bucket) - 1, *count),
+            })
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut counts = self.duplicates.map_or(String::new(), |duplicates| format!(",\"duplicates\":{}", duplicates));
+        if let Some((rejected, tried)) = self.repeated_words {
+            counts.push_str(&format!(",\"repeated_words\":{{\"rejected\":{},\"tried\":{}}}", rejected, tried));
+        }
+        if self.count == 0 {
+            return format!("{{\"count\":0,\"empty\":0{}}}", counts);
+        }
+        let histogram: Vec<String> = self.histogram()
+            .map(|(min, max, count)| format!("{{\"min\":{},\"max\":{},\"count\":{}}}", min, max, count))
+            .collect();
+        format!("{{\"count\":{},\"empty\":{}{},\"estimated\":{},\"chars\":{},\"bytes\":{},\"histogram\":[{}]}}",
+            self.count, self.empty, counts, self.estimated(), self.chars.to_json(self.count), self.bytes.to_json(self.count), histogram.join(","))
+    }
+}
+
+// A table of the lengths, then a histogram of the lengths in characters
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let plural = if self.count == 1 { "" } else { "s" };
+        write!(f, "{} sentence{}, {} empty", self.count, plural, self.empty)?;
+        if let Some(duplicates) = self.duplicates {
+            write!(f, ", {} repeat{} left out", duplicates, if duplicates == 1 { "" } else { "s" })?;
+        }
+        if let Some((rejected, tried)) = self.repeated_words {
+            let rate = if tried == 0 { 0.0 } else { 100.0 * rejected as f64 / tried as f64 };
+            write!(f, ", {} of {} tried ({:.1}%) turned down for repeating a word", rejected, tried, rate)?;
+        }
+        if self.count == 0 {
+            return Ok(());
+        }
+        if self.estimated() {
+            write!(f, " (median and p95 estimated from {} of them)", RESERVOIR_SIZE)?;
+        }
+        writeln!(f)?;
+
+        writeln!(f, "{:<8}{:>8}{:>10}{:>8}{:>8}{:>8}", "length", "min", "mean", "median", "p95", "max")?;
+        for (unit, lengths) in [("chars", &self.chars), ("bytes", &self.bytes)] {
+            writeln!(f, "{:<8}{:>8}{:>10.1}{:>8}{:>8}{:>8}", unit, lengths.min.unwrap_or(0), lengths.mean(self.count),
+                lengths.quantile(0.5), lengths.quantile(0.95), lengths.max)?;
+        }
+
+        let most = self.buckets.iter().copied().max().unwrap_or(0);
+        write!(f, "chars")?;
+        for (min, max, count) in self.histogram() {
+            // Any sentences at all get at least one mark
+            let width = (count * BAR_WIDTH).div_ceil(most) as usize;
+            let range = if min == max { min.to_string() } else { format!("{}-{}", min, max) };
+            write!(f, "\n{:>13}  {:<width$}  {}", range, "#".repeat(width), count, width = BAR_WIDTH as usize)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarize_lengths() {
+        let mut summary = Summary::new();
+        for sentence in ["", "a", "ab", "abc", "héllo", "", "a sentence of 20 chr"] {
+            summary.add(sentence);
+        }
+        assert!(!summary.estimated());
+        assert_eq!(summary.to_string(), concat!(
+            "7 sentences, 2 empty\n",
+            "length       min      mean  median     p95     max\n",
+            "chars          0       4.4       2      20      20\n",
+            "bytes          0       4.6       2      20      20\n",
+            "chars\n",
+            "            0  ########################################  2\n",
+            "            1  ####################                      1\n",
+            "          2-3  ########################################  2\n",
+            "          4-7  ####################                      1\n",
+            "         8-15                                            0\n",
+            "        16-31  ####################                      1",
+        ));
+        assert_eq!(summary.to_json(), concat!(
+            "{\"count\":7,\"empty\":2,\"estimated\":false,",
+            "\"chars\":{\"min\":0,\"mean\":4.428571428571429,\"median\":2,\"p95\":20,\"max\":20},",
+            "\"bytes\":{\"min\":0,\"mean\":4.571428571428571,\"median\":2,\"p95\":20,\"max\":20},",
+            "\"histogram\":[{\"min\":0,\"max\":0,\"count\":2},{\"min\":1,\"max\":1,\"count\":1},{\"min\":2,\"max\":3,\"count\":2},",
+            "{\"min\":4,\"max\":7,\"count\":1},{\"min\":8,\"max\":15,\"count\":0},{\"min\":16,\"max\":31,\"count\":1}]}",
+        ));
+        assert_eq!(Summary::new().to_string(), "0 sentences, 0 empty");
+
+        let mut deduplicated = Summary::new();
+        deduplicated.set_duplicates(1);
+        assert_eq!(deduplicated.to_string(), "0 sentences, 0 empty, 1 repeat left out");
+        assert_eq!(deduplicated.to_json(), "{\"count\":0,\"empty\":0,\"duplicates\":1}");
+        deduplicated.set_repeated_words(3, 12);
+        assert_eq!(deduplicated.to_string(), "0 sentences, 0 empty, 1 repeat left out, 3 of 12 tried (25.0%) turned down for repeating a word");
+        assert_eq!(deduplicated.to_json(), "{\"count\":0,\"empty\":0,\"duplicates\":1,\"repeated_words\":{\"rejected\":3,\"tried\":12}}");
+
+        // Buckets shorter than every sentence are left out
+        let mut long = Summary::new();
+        long.add("eight ch");
+        assert!(long.to_string().ends_with("chars\n         8-15  ########################################  1"));
+    }
+
+    #[test]
+    fn estimate_quantiles_of_many() {
+        let mut summary = Summary::new();
+        let sentences: Vec<String> = (0..100).map(|length| "x".repeat(length)).collect();
+        for index in 0..50_000 {
+            summary.add(&sentences[index % 100]);
+        }
+        assert!(summary.estimated());
+        assert_eq!(summary.chars.sample.len(), RESERVOIR_SIZE);
+        // The exact values are 0, 49.5, 49, 94, and 99
+        assert_eq!((summary.chars.min, summary.chars.max), (Some(0), 99));
+        assert_eq!(summary.chars.mean(summary.count), 49.5);
+        assert!(summary.chars.quantile(0.5).abs_diff(49) <= 2);
+        assert!(summary.chars.quantile(0.95).abs_diff(94) <= 2);
+    }
+}
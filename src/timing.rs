@@ -0,0 +1,128 @@
+/*
+    This module times the phases of a run, for finding out where a slow run
+    spends its time
+*/
+
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+// How long each phase took, in the order they ran
+#[derive(Debug, Default)]
+pub struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+    // How many sentences the generating phase made, for the mean
+    sentences: u64,
+    started: Option<Instant>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Timings {
+            started: Some(Instant::now()),
+            ..Default::default()
+        }
+    }
+
+    // Runs body, adding how long it took to phase
+    pub fn time<T>(&mut self, phase: &'static str, body: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = body();
+        self.add(phase, start.elapsed());
+        return result;
+    }
+
+    pub fn add(&mut self, phase: &'static str, duration: Duration) {
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((phase, duration)),
+        }
+    }
+
+    // Records the generating phase, which made the given number of sentences
+    pub fn add_generating(&mut self, duration: Duration, sentences: u64) {
+        self.add("generating", duration);
+        self.sentences += sentences;
+    }
+
+    fn mean_sentence(&self) -> Option<Duration> {
+        let (_, generating) = self.phases.iter().find(|(name, _)| *name == "generating")?;
+        let sentences = u32::try_from(self.sentences).ok().filter(|sentences| *sentences > 0)?;
+        return Some(*generating / sentences);
+    }
+
+    fn total(&self) -> Duration {
+        self.started.map_or(Duration::ZERO, |started| started.elapsed())
+    }
+
+    // The durations in seconds, as one JSON object
+    pub fn to_json(&self) -> String {
+        let mut fields: Vec<String> = self.phases.iter()
+            .map(|(phase, duration)| format!("\"{}\":{}", phase, duration.as_secs_f64()))
+            .collect();
+        if let Some(mean) = self.mean_sentence() {
+            fields.push(format!("\"sentences\":{}", self.sentences));
+            fields.push(format!("\"per_sentence\":{}", mean.as_secs_f64()));
+        }
+        fields.push(format!("\"total\":{}", self.total().as_secs_f64()));
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+// Runs body, timing it as phase if there are timings to add it to
+pub fn maybe_time<T>(timings: &mut Option<Timings>, phase: &'static str, body: impl FnOnce() -> T) -> T {
+    match timings {
+        Some(timings) => timings.time(phase, body),
+        None => body(),
+    }
+}
+
+// A duration in the most readable unit, like `850µs`, `12.3ms`, or `4.56s`
+pub fn humanize(duration: Duration) -> String {
+    let micros = duration.as_secs_f64() * 1_000_000.0;
+    if micros < 1000.0 {
+        format!("{:.0}µs", micros)
+    } else if micros < 1_000_000.0 {
+        format!("{:.1}ms", micros / 1000.0)
+    } else {
+        format!("{:.2}s", micros / 1_000_000.0)
+    }
+}
+
+// One phase per line, then the total
+impl Display for Timings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (phase, duration) in &self.phases {
+            write!(f, "{:<12}{}", phase, humanize(*duration))?;
+            if let (&"generating", Some(mean)) = (phase, self.mean_sentence()) {
+                let plural = if self.sentences == 1 { "" } else { "s" };
+                write!(f, " ({} per sentence, {} sentence{})", humanize(mean), self.sentences, plural)?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "{:<12}{}", "total", humanize(self.total()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn humanize_durations() {
+        assert_eq!(humanize(Duration::from_nanos(850_400)), "850µs");
+        assert_eq!(humanize(Duration::from_micros(12_345)), "12.3ms");
+        assert_eq!(humanize(Duration::from_millis(4_560)), "4.56s");
+    }
+
+    #[test]
+    fn report_phases() {
+        let mut timings = Timings::default();
+        timings.add("reading", Duration::from_micros(10));
+        timings.add("parsing", Duration::from_millis(2));
+        timings.add("reading", Duration::from_micros(5));
+        timings.add_generating(Duration::from_millis(30), 10);
+
+        assert_eq!(timings.to_string(), "reading     15µs\nparsing     2.0ms\ngenerating  30.0ms (3.0ms per sentence, 10 sentences)\ntotal       0µs");
+        assert_eq!(timings.to_json(), "{\"reading\":0.000015,\"parsing\":0.002,\"generating\":0.03,\"sentences\":10,\"per_sentence\":0.003,\"total\":0}");
+    }
+}
@@ -0,0 +1,269 @@
+/*
+    A terminal UI for writing grammars, with the rules down the left, fresh
+    sentences from the selected rule on the right, and the problems found
+    in the file along the bottom.
+
+    The file is parsed leniently, like the language server does, so the
+    rules which are fine keep generating while others have errors, and it's
+    parsed again whenever it changes. Sentences are generated from one seed
+    at a time, so a fixed seed gives what `--seed SEED --amount N` would.
+    Sentences copied with the number keys are printed when the UI closes.
+*/
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+use rand::{thread_rng, Rng};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::error_handling::SourceName;
+use crate::generator::{generate_from, Xoshiro256StarStar};
+use crate::grammar::{definition_order, written_rule_to_bnf, Bracket, Grammar};
+use crate::parser::ParseCache;
+
+// How often the file is checked for changes
+const POLL: Duration = Duration::from_millis(250);
+// The most sentences shown at once
+const MAX_SAMPLES: usize = 50;
+
+pub struct TuiOptions {
+    // How many sentences to show
+    pub samples: usize,
+    // The seed to fix sentences to, if any
+    pub seed: Option<u64>,
+    // How often to generate new sentences without a key being pressed
+    pub every: Option<Duration>,
+}
+
+fn modified(path: &PathBuf) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// What the UI shows, apart from how it's laid out
+pub struct App {
+    path: PathBuf,
+    cache: ParseCache,
+    modified: Option<SystemTime>,
+    grammar: Grammar,
+    // The rules in definition order, and which is selected
+    rules: Vec<String>,
+    selected: usize,
+    // The problems from the last time the file was read
+    diagnostics: Vec<String>,
+    count: usize,
+    seed: u64,
+    fixed: bool,
+    samples: Vec<String>,
+    // Sentences copied to print on exit
+    copied: Vec<String>,
+}
+
+impl App {
+    pub fn new(path: PathBuf, options: &TuiOptions) -> Self {
+        let mut app = App {
+            path,
+            cache: ParseCache::new(),
+            modified: None,
+            grammar: Grammar::new("", Vec::new()),
+            rules: Vec::new(),
+            selected: 0,
+            diagnostics: Vec::new(),
+            count: options.samples.clamp(1, MAX_SAMPLES),
+            seed: options.seed.unwrap_or_else(|| thread_rng().gen()),
+            fixed: options.seed.is_some(),
+            samples: Vec::new(),
+            copied: Vec::new(),
+        };
+        app.reload();
+        app.generate();
+        return app;
+    }
+
+    // Parses the file again, keeping the selected rule selected if it's
+    // still there. If the file can't be read, the last grammar is kept.
+    fn reload(&mut self) {
+        self.modified = modified(&self.path);
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(error) => {
+                self.diagnostics = vec![format!("{}: {}", self.path.display(), error)];
+                return;
+            }
+        };
+        let (grammar, errors, warnings) = self.cache.parse_str_partial(&text, SourceName::file(&self.path));
+        self.diagnostics = errors.iter().map(|error| format!("error: {}: {}", error.location, error.error))
+            .chain(warnings.iter().map(|warning| format!("warning: {}: {}", warning.location, warning.warning)))
+            .collect();
+
+        let selected = self.rules.get(self.selected).cloned();
+        self.rules = definition_order(&grammar).into_iter().filter(|rule| Bracket::of_rule(rule).is_none()).collect();
+        self.selected = selected.and_then(|rule| self.rules.iter().position(|name| *name == rule)).unwrap_or(0);
+        self.grammar = grammar;
+    }
+
+    // Parses the file again and generates new sentences if it changed
+    pub fn reload_if_changed(&mut self) -> bool {
+        if modified(&self.path) == self.modified {
+            return false;
+        }
+        self.reload();
+        self.generate();
+        return true;
+    }
+
+    // Generates the sentences of the selected rule, from a new seed unless
+    // it's fixed
+    pub fn generate(&mut self) {
+        if !self.fixed {
+            self.seed = thread_rng().gen();
+        }
+        let Some(rule) = self.rules.get(self.selected) else {
+            self.samples.clear();
+            return;
+        };
+        let mut rng = Xoshiro256StarStar::new(self.seed);
+        self.samples = (0..self.count)
+            .map(|_| generate_from(&self.grammar, rule, &mut rng).unwrap_or_else(|error| format!("error: {}", error)))
+            .collect();
+    }
+
+    // Acts on a key, returning whether to quit
+    pub fn handle_key(&mut self, key: KeyCode) -> bool {
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => return true,
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.selected = self.selected.saturating_sub(1);
+                self.generate();
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.selected = (self.selected + 1).min(self.rules.len().saturating_sub(1));
+                self.generate();
+            }
+            KeyCode::Enter | KeyCode::Char(' ') | KeyCode::Char('r') => self.generate(),
+            KeyCode::Char('+') | KeyCode::Char('=') => {
+                self.count = (self.count + 1).min(MAX_SAMPLES);
+                self.generate();
+            }
+            KeyCode::Char('-') => {
+                self.count = self.count.saturating_sub(1).max(1);
+                self.generate();
+            }
+            KeyCode::Char('s') => self.fixed = !self.fixed,
+            KeyCode::Char(digit @ '1'..='9') => {
+                let index = digit as usize - '1' as usize;
+                if let Some(sample) = self.samples.get(index) {
+                    self.copied.push(sample.clone());
+                }
+            }
+            _ => {}
+        }
+        return false;
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, diagnostics, help] = Layout::vertical([Constraint::Min(5), Constraint::Length(8), Constraint::Length(1)]).areas(frame.area());
+        let [rules, samples] = Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)]).areas(main);
+
+        let items: Vec<ListItem> = self.rules.iter()
+            .map(|rule| ListItem::new(written_rule_to_bnf(&self.grammar, rule)))
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(format!(" {} ", self.path.display())))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, rules, &mut ListState::default().with_selected(Some(self.selected)));
+
+        let title = match self.rules.get(self.selected) {
+            Some(rule) => format!(" {} from `{}`, seed {}{} ", self.count, rule, self.seed, if self.fixed { " (fixed)" } else { "" }),
+            None => " No rules ".to_string(),
+        };
+        let lines: Vec<Line> = self.samples.iter().enumerate()
+            .map(|(index, sample)| Line::from(format!("{:>2}  {}", index + 1, sample)))
+            .collect();
+        frame.render_widget(Paragraph::new(lines).wrap(Wrap { trim: false }).block(Block::bordered().title(title)), samples);
+
+        let (problems, style) = match self.diagnostics.len() {
+            0 => (vec![Line::from("No problems")], Style::new().fg(Color::Green)),
+            _ => (self.diagnostics.iter().map(|line| Line::from(line.as_str())).collect(), Style::new().fg(Color::Yellow)),
+        };
+        frame.render_widget(Paragraph::new(problems).style(style).wrap(Wrap { trim: false }).block(Block::bordered().title(" Problems ")), diagnostics);
+
+        let copied = if self.copied.is_empty() { String::new() } else { format!(", {} copied", self.copied.len()) };
+        let keys = format!("↑↓ rule  r new  +/- amount  s fix seed  1-9 copy{}  q quit", copied);
+        frame.render_widget(Paragraph::new(keys).style(Style::new().add_modifier(Modifier::DIM)), help);
+    }
+}
+
+fn run_app(terminal: &mut DefaultTerminal, app: &mut App, every: Option<Duration>) -> std::io::Result<()> {
+    let mut generated = Instant::now();
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if event::poll(POLL)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && app.handle_key(key.code) {
+                    return Ok(());
+                }
+                generated = Instant::now();
+            }
+        }
+        if app.reload_if_changed() {
+            generated = Instant::now();
+        }
+        if every.is_some_and(|every| generated.elapsed() >= every) {
+            app.generate();
+            generated = Instant::now();
+        }
+    }
+}
+
+// Runs the UI until it's quit, returning the sentences copied
+pub fn run(path: PathBuf, options: TuiOptions) -> std::io::Result<Vec<String>> {
+    let mut app = App::new(path, &options);
+    let mut terminal = ratatui::init();
+    let result = run_app(&mut terminal, &mut app, options.every);
+    ratatui::restore();
+    return result.map(|_| app.copied);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn browse_a_grammar() {
+        let dir = std::env::temp_dir().join(format!("blabber-tui-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("grammar.bnf");
+        std::fs::write(&path, "s = greeting \" world\"\ngreeting = \"hello\" | \"hi\"").unwrap();
+
+        let mut app = App::new(path.clone(), &TuiOptions { samples: 3, seed: Some(5), every: None });
+        assert_eq!(app.rules, ["s", "greeting"]);
+        assert_eq!(app.samples.len(), 3);
+        assert!(app.samples.iter().all(|sample| sample.ends_with(" world")));
+        let first = app.samples.clone();
+        app.handle_key(KeyCode::Char('r'));
+        assert_eq!(app.samples, first);
+
+        app.handle_key(KeyCode::Down);
+        app.handle_key(KeyCode::Char('-'));
+        assert_eq!(app.samples.len(), 2);
+        assert!(app.samples.iter().all(|sample| sample == "hello" || sample == "hi"));
+        app.handle_key(KeyCode::Char('2'));
+        app.handle_key(KeyCode::Char('9'));
+        assert_eq!(app.copied, [app.samples[1].clone()]);
+
+        // Edits show up, keeping the selected rule selected
+        std::fs::write(&path, "s = greeting\nextra = \"x\"\ngreeting = \"hey\" | missing\nbroken = \"").unwrap();
+        app.modified = None;
+        assert!(app.reload_if_changed());
+        assert_eq!(app.rules, ["s", "extra", "greeting"]);
+        assert_eq!(app.selected, 2);
+        assert_eq!(app.diagnostics.len(), 2);
+        assert!(app.handle_key(KeyCode::Char('q')));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
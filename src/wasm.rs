@@ -0,0 +1,72 @@
+/*
+    JavaScript bindings, for running blabber in the browser. Build them with
+    `wasm-pack build --features wasm`.
+
+    Grammars are parsed from strings and generate with their own seeded
+    generator, so nothing here reads files or asks the system for
+    randomness. Failures are thrown as JavaScript `Error`s, and parse errors
+    carry an `errors` array of `{line, message}` objects too, with `line`
+    null for errors about the whole grammar.
+*/
+
+use js_sys::{Array, Math, Object, Reflect};
+use wasm_bindgen::prelude::*;
+
+use crate::error_handling::SourceName;
+use crate::generator::{generate_from, Xoshiro256StarStar};
+use crate::grammar::{definition_order, Grammar};
+use crate::parser::{parse_str, CompileError};
+
+// Sets a property the objects were just made with, which can't fail
+fn set(object: &Object, key: &str, value: JsValue) {
+    Reflect::set(object, &key.into(), &value).expect("setting a property of a plain object works");
+}
+
+fn parse_error(errors: Vec<CompileError>) -> JsValue {
+    let list = Array::new();
+    for error in &errors {
+        let object = Object::new();
+        set(&object, "line", error.location.line.map_or(JsValue::NULL, |line| JsValue::from(line as u32)));
+        set(&object, "message", error.error.to_string().into());
+        list.push(&object);
+    }
+    let message = errors.iter()
+        .map(|error| format!("{}: {}", error.location, error.error))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let thrown = js_sys::Error::new(&message);
+    set(&thrown, "errors", list.into());
+    return thrown.into();
+}
+
+// A seed from the page's random numbers, for when none is given
+fn random_seed() -> u64 {
+    let half = || (Math::random() * f64::from(u32::MAX)) as u64;
+    return half() << 32 | half();
+}
+
+#[wasm_bindgen(js_name = Grammar)]
+pub struct JsGrammar(Grammar);
+
+// Parses and verifies a grammar, throwing its errors if it has any
+#[wasm_bindgen]
+pub fn parse(source: &str) -> Result<JsGrammar, JsValue> {
+    return parse_str(source, SourceName::Builtin).map(JsGrammar).map_err(parse_error);
+}
+
+#[wasm_bindgen(js_class = Grammar)]
+impl JsGrammar {
+    // Generates a sentence from start, or the start symbol, with the seed
+    // giving the same sentence as `--seed` does, or a random one
+    pub fn generate(&self, start: Option<String>, seed: Option<u64>) -> Result<String, JsValue> {
+        let start = start.as_deref().unwrap_or(&self.0.start_symbol);
+        let mut rng = Xoshiro256StarStar::new(seed.unwrap_or_else(random_seed));
+        return generate_from(&self.0, start, &mut rng).map_err(|error| js_sys::Error::new(&error.to_string()).into());
+    }
+
+    // The rules, starting with the start symbol and then in the order they
+    // were defined
+    pub fn symbols(&self) -> Vec<String> {
+        return definition_order(&self.0);
+    }
+}
@@ -0,0 +1,55 @@
+// Runs the blabber binary, checking how it reports problems to the user
+
+use std::process::Command;
+
+#[test]
+fn report_max_depth_at_the_start_rule() {
+    let output = Command::new(env!("CARGO_BIN_EXE_blabber"))
+        .args(["--max-depth", "3", "tests/cli/left_recursive.bnf"])
+        .output()
+        .expect("blabber runs");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    // Shown like any other error, in red with where it's from
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), concat!(
+        "\x1b[31;49;1m[tests/cli/left_recursive.bnf:2]\x1b[39;49;1m  ",
+        "Expanding `expr` would nest rules more than 3 deep, so the grammar may recurse without end (pass --max-depth to allow more)",
+        "\x1b[0m\n"
+    ));
+}
+
+#[test]
+fn deny_or_allow_unreachable_rules() {
+    let check = |level: &str| Command::new(env!("CARGO_BIN_EXE_blabber"))
+        .args(["check", level, "unreachable-rule", "tests/cli/unreachable.bnf"])
+        .output()
+        .expect("blabber runs");
+
+    let denied = check("-D");
+    assert_eq!(denied.status.code(), Some(1));
+    assert_eq!(String::from_utf8(denied.stderr).unwrap(), concat!(
+        "\x1b[31;49;1m[tests/cli/unreachable.bnf:3]\x1b[39;49;1m  ",
+        "Denied warning: `farewell` can't be reached from the start symbol, so it's never used\n",
+        "  = note: requested with `-D unreachable-rule`",
+        "\x1b[0m\n"
+    ));
+
+    let allowed = check("-A");
+    assert_eq!(allowed.status.code(), Some(0));
+    assert!(allowed.stderr.is_empty());
+}
+
+#[test]
+fn summarize_after_a_failing_sentence() {
+    // The third sentence of seed 3 is empty, which stops the batch
+    let output = Command::new(env!("CARGO_BIN_EXE_blabber"))
+        .args(["--fail-on-empty", "--summary", "--seed", "3", "-n", "5", "tests/cli/sometimes_empty.bnf"])
+        .output()
+        .expect("blabber runs");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "yes\nyes\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let failure = stderr.find("Sentence 3 of `--seed 3` was empty").expect("the failure is shown");
+    let summary = stderr.find("2 sentences, 0 empty\n").expect("the summary is still printed");
+    assert!(failure < summary, "{}", stderr);
+}
@@ -0,0 +1,97 @@
+// Builds modules written by `blabber codegen` in a crate of their own and
+// checks they compile without warnings and generate what the interpreter
+// does from the same seeds
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use blabber::codegen::codegen;
+use blabber::parser::parse_file;
+
+const SEEDS: u64 = 500;
+
+// The crate's library holds the modules, denying warnings, and its binary
+// compares them against blabber
+fn write_crate(dir: &Path) {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(dir.join("Cargo.toml"), format!(concat!(
+        "[package]\nname = \"codegen-check\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n",
+        "[dependencies]\nblabber = {{ path = {:?} }}\nrand = \"0.8.5\"\n\n",
+        "[workspace]\n",
+    ), manifest_dir)).unwrap();
+    // The versions blabber's own tests were resolved with, so the crate
+    // doesn't need the registry
+    let lock = Path::new(manifest_dir).join("Cargo.lock");
+    if lock.exists() {
+        std::fs::copy(lock, dir.join("Cargo.lock")).unwrap();
+    }
+
+    let grammar = parse_file(&PathBuf::from("tests/codegen/flavor_text.bnf")).unwrap();
+    std::fs::write(dir.join("src/flavor_text.rs"), codegen(&grammar).unwrap()).unwrap();
+    let plain = blabber::parser::parse_str("greeting = \"hi\" | \"hello\"", blabber::error_handling::SourceName::Builtin).unwrap();
+    std::fs::write(dir.join("src/plain.rs"), codegen(&plain).unwrap()).unwrap();
+    std::fs::write(dir.join("src/weighted.rs"), codegen(&weighted()).unwrap()).unwrap();
+    std::fs::write(dir.join("src/lib.rs"), "#![deny(warnings)]\n\npub mod flavor_text;\npub mod plain;\npub mod weighted;\n").unwrap();
+
+    std::fs::write(dir.join("src/main.rs"), format!(r#"
+use blabber::generator::{{generate_from, Xoshiro256StarStar}};
+use codegen_check::{{flavor_text, plain, weighted}};
+
+fn check(grammar: &blabber::grammar::Grammar, start: &str, generated: fn(&mut Xoshiro256StarStar) -> String) {{
+    for seed in 0..{seeds} {{
+        let expected = generate_from(grammar, start, &mut Xoshiro256StarStar::new(seed)).unwrap();
+        assert_eq!(generated(&mut Xoshiro256StarStar::new(seed)), expected, "`{{}}` with seed {{}}", start, seed);
+    }}
+}}
+
+fn main() {{
+    let grammar = blabber::parser::parse_file(&"{manifest_dir}/tests/codegen/flavor_text.bnf".into()).unwrap();
+    check(&grammar, "line", |rng| flavor_text::generate(rng));
+    check(&grammar, "remark", |rng| flavor_text::remark(rng));
+    check(&grammar, "title", |rng| flavor_text::title(rng));
+    check(&grammar, "2nd.remark", |rng| flavor_text::rule_2nd_remark(rng));
+    check(&grammar, "loop", |rng| flavor_text::loop_(rng));
+
+    let plain_grammar = blabber::parser::parse_str("greeting = \"hi\" | \"hello\"", blabber::error_handling::SourceName::Builtin).unwrap();
+    check(&plain_grammar, "greeting", |rng| plain::generate(rng));
+    check(&plain_grammar, "greeting", |rng| plain::greeting(rng));
+
+    let mut weighted_grammar = blabber::parser::parse_str({weighted_text:?}, blabber::error_handling::SourceName::Builtin).unwrap();
+    weighted_grammar.weights.insert("greeting".into(), vec!{weights:?}.into());
+    check(&weighted_grammar, "sentence", |rng| weighted::generate(rng));
+
+    // Each sentence draws from the generator where the last one left off
+    let mut rng = Xoshiro256StarStar::new(7);
+    let mut expected_rng = Xoshiro256StarStar::new(7);
+    for _ in 0..{seeds} {{
+        assert_eq!(flavor_text::generate(&mut rng), generate_from(&grammar, "line", &mut expected_rng).unwrap());
+    }}
+    println!("ok");
+}}
+"#, seeds = SEEDS, manifest_dir = manifest_dir.replace('\\', "/"), weighted_text = WEIGHTED, weights = WEIGHTS)).unwrap();
+}
+
+// A grammar with one rule weighted, which only code can make without the csv
+// feature
+const WEIGHTED: &str = "sentence = greeting \" \" name\ngreeting = \"hi\" | \"hello\" | \"hey\"\nname = \"jo\" | \"sam\"";
+const WEIGHTS: [f64; 3] = [5.0, 0.0, 0.25];
+
+fn weighted() -> blabber::grammar::Grammar {
+    let mut grammar = blabber::parser::parse_str(WEIGHTED, blabber::error_handling::SourceName::Builtin).unwrap();
+    grammar.weights.insert("greeting".into(), WEIGHTS.to_vec().into());
+    grammar
+}
+
+#[test]
+fn match_the_interpreter() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("codegen");
+    write_crate(&dir);
+    let output = Command::new(env!("CARGO"))
+        .args(["run", "--quiet", "--manifest-path"])
+        .arg(dir.join("Cargo.toml"))
+        .output()
+        .expect("cargo runs");
+    assert!(output.status.success(), "the generated modules failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
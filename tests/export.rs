@@ -0,0 +1,15 @@
+// Checks `blabber export --format markdown` still writes the document kept
+// for the english example, so changes to it are seen in review
+
+use std::path::PathBuf;
+
+use blabber::grammar::to_markdown;
+use blabber::parser::parse_file;
+
+#[test]
+fn export_english_markdown() {
+    let grammar = parse_file(&PathBuf::from("example_data/english.bnf")).unwrap();
+    let expected = std::fs::read_to_string("tests/export/english.md").unwrap();
+    assert!(to_markdown(&grammar, "english", 3, 0) == expected,
+        "the document changed; if that's intended, write it again with `cargo run -- export example_data/english.bnf -o tests/export/english.md`");
+}
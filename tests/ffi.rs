@@ -0,0 +1,76 @@
+// Compiles the C program in tests/ffi against the cdylib and runs it, to
+// check that the header and the library link together
+
+#![cfg(feature = "ffi")]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// The host's target triple, as rustc reports it
+fn host_triple() -> String {
+    let output = Command::new(std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+        .arg("-vV")
+        .output()
+        .expect("rustc runs");
+    String::from_utf8(output.stdout).unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix("host: ").map(str::to_string))
+        .expect("rustc reports its host")
+}
+
+// Builds the cdylib with the ffi feature and returns the directory holding
+// it. It gets its own target directory, since the one these tests run from
+// can hold a library built without the feature: cdylibs aren't named by
+// their features, so builds with different features overwrite each other.
+fn library_dir(out_dir: &Path) -> PathBuf {
+    let target_dir = out_dir.join("ffi");
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--lib", "--features", "ffi", "--target-dir"])
+        .arg(&target_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .expect("cargo runs");
+    assert!(status.success(), "building the cdylib failed");
+    target_dir.join("debug")
+}
+
+#[test]
+fn link_from_c() {
+    let out_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let host = host_triple();
+    let compiler = cc::Build::new()
+        .cargo_metadata(false)
+        .opt_level(0)
+        .target(&host)
+        .host(&host)
+        .out_dir(&out_dir)
+        .get_compiler();
+
+    let library_dir = library_dir(&out_dir);
+    let exe = out_dir.join(format!("flavor_text{}", std::env::consts::EXE_SUFFIX));
+    let mut command = compiler.to_command();
+    command.arg("tests/ffi/flavor_text.c").arg("-Iinclude");
+    if compiler.is_like_msvc() {
+        command.arg(format!("/Fe{}", exe.display())).arg(library_dir.join("blabber.dll.lib"));
+    } else {
+        command.arg("-o").arg(&exe)
+            .arg(format!("-L{}", library_dir.display()))
+            .arg("-lblabber")
+            .arg(format!("-Wl,-rpath,{}", library_dir.display()));
+    }
+    let status = command.status().expect("the C compiler runs");
+    assert!(status.success(), "compiling the C program failed");
+
+    // Cargo puts its own library directories on the loader's search path,
+    // which is searched before the rpath, and Windows only uses the path
+    let loader_path = match std::env::consts::OS {
+        "windows" => "PATH",
+        "macos" => "DYLD_LIBRARY_PATH",
+        _ => "LD_LIBRARY_PATH",
+    };
+    let paths = std::env::var_os(loader_path).unwrap_or_default();
+    let paths = std::env::join_paths(std::iter::once(library_dir).chain(std::env::split_paths(&paths))).unwrap();
+    let output = Command::new(&exe).env(loader_path, paths).output().expect("the C program runs");
+    assert!(output.status.success(), "the C program failed: {}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
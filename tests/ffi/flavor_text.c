@@ -0,0 +1,35 @@
+/* Checks that a C program can link against blabber and use its interface */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "blabber.h"
+
+int main(void) {
+    BlabberGrammar *grammar = blabber_parse_string("flavor = \"a \" thing:upper\nthing = \"sword\"");
+    if (grammar == NULL) {
+        fprintf(stderr, "parsing failed: %s\n", blabber_last_error());
+        return 1;
+    }
+
+    char *sentence = blabber_generate(grammar, NULL, 42);
+    if (sentence == NULL || strcmp(sentence, "a SWORD") != 0) {
+        fprintf(stderr, "unexpected sentence: %s\n", sentence ? sentence : blabber_last_error());
+        return 1;
+    }
+    blabber_string_free(sentence);
+
+    if (blabber_generate(grammar, "missing", 0) != NULL || blabber_last_error() == NULL) {
+        fprintf(stderr, "generating from an undefined symbol didn't fail\n");
+        return 1;
+    }
+    blabber_grammar_free(grammar);
+
+    if (blabber_parse_file("no/such/file.bnf") != NULL) {
+        fprintf(stderr, "parsing a missing file didn't fail\n");
+        return 1;
+    }
+
+    puts("ok");
+    return 0;
+}
@@ -0,0 +1,40 @@
+// Runs the JavaScript bindings in a browser or node, with
+// `wasm-pack test --node -- --features wasm`
+
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+
+use js_sys::{Array, Reflect};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+use blabber::wasm::parse;
+
+const GRAMMAR: &str = "sentence = \"the \" noun\nnoun = \"cat\" | \"dog\"";
+
+fn property(value: &JsValue, key: &str) -> JsValue {
+    Reflect::get(value, &key.into()).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn generate_from_seeds() {
+    let grammar = parse(GRAMMAR).unwrap();
+    let sentence = grammar.generate(None, Some(7)).unwrap();
+    assert!(sentence == "the cat" || sentence == "the dog");
+    assert_eq!(grammar.generate(None, Some(7)).unwrap(), sentence);
+    assert_eq!(grammar.generate(Some("noun".to_string()), Some(7)).unwrap().len(), 3);
+    assert!(grammar.generate(Some("verb".to_string()), None).is_err());
+    assert_eq!(grammar.symbols(), ["sentence", "noun"]);
+}
+
+#[wasm_bindgen_test]
+fn throw_located_errors() {
+    let error = parse("sentence = \"the \" noun\nnoun = \"cat").err().unwrap();
+    let errors = Array::from(&property(&error, "errors"));
+    assert_eq!(errors.length(), 1);
+    assert_eq!(property(&errors.get(0), "line").as_f64(), Some(2.0));
+    assert_eq!(property(&errors.get(0), "message").as_string().unwrap(), "Unmatched quote: the quote opened at column 8 was never closed; consumed 3 characters to end of line");
+    assert_eq!(property(&error, "message").as_string().unwrap(), "<builtin>:2: Unmatched quote: the quote opened at column 8 was never closed; consumed 3 characters to end of line");
+
+    let error = parse("; nothing here").err().unwrap();
+    assert!(property(&Array::from(&property(&error, "errors")).get(0), "line").is_null());
+}